@@ -1,9 +1,10 @@
 use std::hint::black_box;
 
 use bonsai_trie::{
+    compute_root,
     databases::HashMapDb,
     id::{BasicId, BasicIdBuilder},
-    BonsaiStorage, BonsaiStorageConfig,
+    BitVec, BonsaiStorage, BonsaiStorageConfig,
 };
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use rand::{prelude::*, thread_rng};
@@ -243,6 +244,29 @@ fn multiple_contracts(c: &mut Criterion) {
     });
 }
 
+fn block_commitment(c: &mut Criterion) {
+    c.bench_function("block commitment", move |b| {
+        let mut rng = thread_rng();
+        // Like a transaction trie: 64 bit transaction indices as keys.
+        let leaves: Vec<_> = (0u64..1000)
+            .map(|i| {
+                (
+                    BitVec::from_vec(i.to_be_bytes().to_vec()),
+                    Felt::from(rng.gen::<u64>()),
+                )
+            })
+            .collect();
+
+        b.iter_batched(
+            || leaves.clone(),
+            |leaves| {
+                black_box(compute_root::<Pedersen>(leaves));
+            },
+            BatchSize::LargeInput,
+        );
+    });
+}
+
 fn pedersen_hash(c: &mut Criterion) {
     c.bench_function("pedersen hash", move |b| {
         let felt0 =
@@ -274,6 +298,6 @@ fn poseidon_hash(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default(); // .with_profiler(flamegraph::FlamegraphProfiler::new(100));
-    targets = storage, one_update, five_updates, pedersen_hash, poseidon_hash, drop_storage, storage_with_insert, multiple_contracts
+    targets = storage, one_update, five_updates, pedersen_hash, poseidon_hash, drop_storage, storage_with_insert, multiple_contracts, block_commitment
 }
 criterion_main!(benches);
@@ -1,25 +1,156 @@
-use crate::{id::Id, ByteVec, Vec};
+use crate::{
+    changes,
+    id::Id,
+    trie::{
+        inline::inline_trie_key,
+        path::PathKey,
+        tree::{bytes_to_bitvec, leaf_count_key},
+        trie_db::{MetaKeyType, TrieKey, TrieKeyType},
+    },
+    BitSlice, BitVec, ByteVec, Path, Vec,
+};
 #[cfg(feature = "std")]
 use std::error::Error;
 
 /// Key in the database of the different elements that can be stored in the database.
-#[derive(Debug, Hash, PartialEq, Eq)]
+///
+/// Each variant is a column, whose keys are laid out as follows, see [`DatabaseKey::parse`]:
+/// - [`DatabaseKey::Trie`]: the trie identifier, then the path of the node prefixed by its length
+///   in bits as a byte, see [`DatabaseKey::trie_node_bytes`]. The length `0xff` holds the number
+///   of leaves of the trie instead, and `0xff 0x00` the record of a trie stored without its
+///   nodes, see [`crate::BonsaiStorageConfig::max_inline_leaves`].
+/// - [`DatabaseKey::Flat`]: the trie identifier, then the leaf key prefixed by its length in bits
+///   as a byte, see [`DatabaseKey::leaf_bytes`].
+/// - [`DatabaseKey::TrieLog`]: the commit ID, then `0x01`, the length of the trie identifier as a
+///   SCALE compact integer and the identifier for the changes of a trie, or `0x00` for the other
+///   changes. Then comes the changed key, without the identifier of the trie, its column (`0` for
+///   the trie nodes, `1` for the leaves, `2` for the metadata) and `0x00` for its new value or
+///   `0x01` for its old value.
+/// - [`DatabaseKey::Meta`]: a byte telling the kind of metadata, `0x00` for the metadata of the
+///   user, see [`DatabaseKey::user_meta_bytes`], then the key.
+///
+/// The identifiers are not length-prefixed in the trie and flat keys, so the keys of a trie can
+/// be the same as the keys of another trie whose identifier it prefixes. This can't happen if all
+/// the identifiers have the same length. With the `key_checks` feature, the debug builds panic on
+/// commits changing tries whose identifiers prefix each other.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum DatabaseKey<'a> {
     Trie(&'a [u8]),
     Flat(&'a [u8]),
     TrieLog(&'a [u8]),
+    /// Auxiliary data of the user, see [`crate::BonsaiStorage::put_meta`].
+    Meta(&'a [u8]),
 }
 
-impl DatabaseKey<'_> {
-    pub fn as_slice(&self) -> &[u8] {
+/// A database key split in its parts, see [`DatabaseKey::parse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParsedKey<'a> {
+    /// Node of the trie `identifier` at `path`.
+    TrieNode { identifier: &'a [u8], path: Path },
+    /// Number of leaves of the trie `identifier`.
+    LeafCount { identifier: &'a [u8] },
+    /// Record of the trie `identifier` stored inline, see
+    /// [`crate::BonsaiStorageConfig::max_inline_leaves`].
+    InlineTrie { identifier: &'a [u8] },
+    /// Leaf of the trie `identifier`.
+    Leaf { identifier: &'a [u8], key: BitVec },
+    /// Entry of the trie log of the commit `id`, recording the new or the old value of `key`.
+    /// The keys of the changes of the trie `identifier` don't start with the identifier, unless
+    /// they are metadata.
+    TrieLog {
+        id: &'a [u8],
+        identifier: Option<&'a [u8]>,
+        key: DatabaseKey<'a>,
+        new_value: bool,
+    },
+    /// Metadata of the kind `kind`, `0` being the metadata of the user.
+    Meta { kind: u8, key: &'a [u8] },
+}
+
+impl<'a> DatabaseKey<'a> {
+    pub fn as_slice(&self) -> &'a [u8] {
         match self {
             DatabaseKey::Trie(slice) => slice,
             DatabaseKey::Flat(slice) => slice,
             DatabaseKey::TrieLog(slice) => slice,
+            DatabaseKey::Meta(slice) => slice,
+        }
+    }
+
+    /// Bytes of the [`DatabaseKey::Trie`] key of the node at `path` in the trie `identifier`.
+    pub fn trie_node_bytes(identifier: &[u8], path: &BitSlice) -> ByteVec {
+        TrieKey::new(identifier, TrieKeyType::Trie, &PathKey::node(path))
+            .as_slice()
+            .into()
+    }
+
+    /// Bytes of the [`DatabaseKey::Flat`] key of the leaf `key` in the trie `identifier`.
+    pub fn leaf_bytes(identifier: &[u8], key: &BitSlice) -> ByteVec {
+        TrieKey::new(identifier, TrieKeyType::Flat, &PathKey::leaf(key))
+            .as_slice()
+            .into()
+    }
+
+    /// Bytes of the [`DatabaseKey::Meta`] key of the metadata `key` of the user, see
+    /// [`crate::BonsaiStorage::put_meta`].
+    pub fn user_meta_bytes(key: &[u8]) -> ByteVec {
+        TrieKey::new_meta(MetaKeyType::User, key).as_slice().into()
+    }
+
+    /// Split the key in its parts, for the tries whose identifiers are `identifier_len` bytes
+    /// long and the commit IDs whose [`Id::to_bytes`] are `id_len` bytes long. `None` if the key
+    /// can't have been written by the storage.
+    pub fn parse(&self, identifier_len: usize, id_len: usize) -> Option<ParsedKey<'a>> {
+        match *self {
+            DatabaseKey::Trie(bytes) => {
+                let (identifier, path) = bytes.split_at_checked(identifier_len)?;
+                if bytes == leaf_count_key(identifier).as_slice() {
+                    return Some(ParsedKey::LeafCount { identifier });
+                }
+                if bytes == inline_trie_key(identifier).as_slice() {
+                    return Some(ParsedKey::InlineTrie { identifier });
+                }
+                let path = Path::from_bytes(path).ok().filter(|path| {
+                    DatabaseKey::trie_node_bytes(identifier, &path.0).as_slice() == bytes
+                })?;
+                Some(ParsedKey::TrieNode { identifier, path })
+            }
+            DatabaseKey::Flat(bytes) => {
+                let (identifier, key) = bytes.split_at_checked(identifier_len)?;
+                let (len, bits) = key.split_first()?;
+                if bits.len() != (*len as usize).div_ceil(8) {
+                    return None;
+                }
+                let key = bytes_to_bitvec(key);
+                (DatabaseKey::leaf_bytes(identifier, &key).as_slice() == bytes)
+                    .then_some(ParsedKey::Leaf { identifier, key })
+            }
+            DatabaseKey::TrieLog(bytes) => changes::parse_trie_log_key(bytes, id_len),
+            DatabaseKey::Meta(bytes) => {
+                let (kind, key) = bytes.split_first()?;
+                Some(ParsedKey::Meta { kind: *kind, key })
+            }
         }
     }
 }
 
+/// Panics if the identifier of a trie is a prefix of the identifier of another one, as their keys
+/// would be mixed, see [`DatabaseKey`].
+#[cfg(feature = "key_checks")]
+pub(crate) fn debug_assert_prefix_free<'a>(identifiers: impl IntoIterator<Item = &'a [u8]>) {
+    let mut identifiers: Vec<&[u8]> = identifiers.into_iter().collect();
+    identifiers.sort_unstable();
+    // Sorted, an identifier is followed by the ones it prefixes.
+    for pair in identifiers.windows(2) {
+        debug_assert!(
+            !pair[1].starts_with(pair[0]),
+            "The trie identifier {:?} is a prefix of the trie identifier {:?}, their keys collide",
+            pair[0],
+            pair[1]
+        );
+    }
+}
+
 #[cfg(feature = "std")]
 pub trait DBError: Error + Send + Sync {}
 
@@ -40,6 +171,12 @@ pub trait BonsaiDatabase: core::fmt::Debug {
     /// Returns the value of the key if it exists
     fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError>;
 
+    /// Returns the values of the keys, in the same order. Databases that can batch reads should
+    /// override this, the default implementation calls `get` for each key.
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
     #[allow(clippy::type_complexity)]
     /// Returns all values with keys that start with the given prefix
     fn get_by_prefix(
@@ -47,6 +184,20 @@ pub trait BonsaiDatabase: core::fmt::Debug {
         prefix: &DatabaseKey,
     ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError>;
 
+    /// Returns the keys that start with the given prefix, in the same order as `get_by_prefix`.
+    /// Databases that can list keys without reading their values should override this, the
+    /// default implementation calls `get_by_prefix`.
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        Ok(self
+            .get_by_prefix(prefix)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
     /// Returns true if the key exists
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError>;
 
@@ -59,6 +210,21 @@ pub trait BonsaiDatabase: core::fmt::Debug {
         batch: Option<&mut Self::Batch>,
     ) -> Result<Option<ByteVec>, Self::DatabaseError>;
 
+    /// Insert the key-value pairs in order, returns the old value of each key as `insert` does.
+    /// If a batch is provided, the changes will be written in the batch instead of the database.
+    /// Databases that can batch writes should override this, the default implementation calls
+    /// `insert` for each pair.
+    fn insert_many(
+        &mut self,
+        items: &[(DatabaseKey, &[u8])],
+        mut batch: Option<&mut Self::Batch>,
+    ) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        items
+            .iter()
+            .map(|(key, value)| self.insert(key, value, batch.as_deref_mut()))
+            .collect()
+    }
+
     /// Remove a key-value pair, returns the old value if it existed.
     /// If a batch is provided, the change will be written in the batch instead of the database.
     fn remove(
@@ -73,6 +239,11 @@ pub trait BonsaiDatabase: core::fmt::Debug {
     /// Write batch of changes directly in the database
     fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError>;
 
+    /// Reclaim the space left by removed keys, if the database needs to. Does nothing by default.
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        Ok(())
+    }
+
     /// Functions available in tests to display the whole database key/values
     #[cfg(test)]
     fn dump_database(&self);
@@ -90,6 +261,21 @@ pub trait BonsaiPersistentDatabase<ID: Id> {
     /// This function returns a snapshot id that can be used to create a transaction
     fn snapshot(&mut self, id: ID);
 
+    /// Ids of the saved snapshots, in increasing order
+    ///
+    /// The default implementation returns none, in which case the storage cannot list the
+    /// snapshots, limit their number, or remove the ones of reverted commits.
+    fn snapshots(&self) -> Vec<ID> {
+        Vec::new()
+    }
+
+    /// Remove the snapshot `id`, returns whether it existed
+    ///
+    /// The default implementation removes nothing and returns `false`.
+    fn remove_snapshot(&mut self, _id: ID) -> bool {
+        false
+    }
+
     /// Create a transaction based on the given snapshot id
     fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)>;
 
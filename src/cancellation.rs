@@ -0,0 +1,37 @@
+//! Cooperative cancellation of long operations, see [`CancellationToken`].
+
+use crate::{bonsai_database::DBError, Arc, BonsaiStorageError};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Stops the operations it is given to, such as [`crate::BonsaiStorage::commit_cancellable`],
+/// once [`CancellationToken::cancel`] is called from any thread. They fail with
+/// [`BonsaiStorageError::Cancelled`] and leave the storage as it was before them.
+///
+/// Clones share the same state, so that a clone can be kept to cancel the operation.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations given this token or one of its clones, now and from now on.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Fail with [`BonsaiStorageError::Cancelled`] if `cancel` is cancelled.
+pub(crate) fn check_cancelled<E: DBError>(
+    cancel: Option<&CancellationToken>,
+) -> Result<(), BonsaiStorageError<E>> {
+    match cancel {
+        Some(cancel) if cancel.is_cancelled() => Err(BonsaiStorageError::Cancelled),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{BitVec, ByteVec, String};
+use starknet_types_core::felt::Felt;
+
+/// A leaf changed by a commit, see [`ChangeSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafChange {
+    pub identifier: ByteVec,
+    pub key: BitVec,
+    /// `None` if the leaf did not exist.
+    pub old_value: Option<Felt>,
+    /// `None` if the leaf was removed.
+    pub new_value: Option<Felt>,
+}
+
+/// Receives the leaf changes of the commits of a storage, to stream them to an external system
+/// without reading the trie logs back. See [`crate::BonsaiStorage::set_change_sink`].
+pub trait ChangeSink<ChangeID>: Send + Sync {
+    /// Called with the changes of commit `id` before they are written to the database. Returning
+    /// an error, for instance because the downstream queue is full, refuses them: what happens
+    /// then depends on the [`ChangeSinkPolicy`].
+    ///
+    /// The commit can still fail after the changes were accepted, in which case the same id may
+    /// be sent again.
+    fn send(&self, id: ChangeID, changes: &[LeafChange]) -> Result<(), String>;
+}
+
+/// What a commit does when its [`ChangeSink`] refuses its changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangeSinkPolicy {
+    /// The commit fails with [`crate::BonsaiStorageError::ChangeSink`] and its changes stay
+    /// uncommitted, so that it can be retried.
+    #[default]
+    FailCommit,
+    /// The changes are kept in memory and sent again, in order, on the next commits or with
+    /// [`crate::BonsaiStorage::flush_change_sink`]. Commits fail as above once `max_commits`
+    /// commits are waiting.
+    Buffer { max_commits: usize },
+}
@@ -1,6 +1,14 @@
-use crate::{hash_map::Entry, id::Id, trie::TrieKey, ByteVec, HashMap, Vec};
+use crate::{
+    bonsai_database::{DBError, ParsedKey},
+    hash_map::Entry,
+    id::Id,
+    trie::{trie_db::TrieKeyType, TrieKey},
+    BonsaiStorageError, ByteVec, DatabaseKey, EncodeExt, HashMap, Vec,
+};
 use core::iter;
+use parity_scale_codec::{Compact, Decode};
 use serde::{Deserialize, Serialize};
+use starknet_types_core::felt::Felt;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Change {
@@ -8,16 +16,32 @@ pub struct Change {
     pub new_value: Option<ByteVec>,
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(feature = "bench", derive(Clone))]
-pub struct ChangeBatch(pub(crate) HashMap<TrieKey, Change>);
+/// Database changes made by a commit, trie nodes included, as recorded in its trie log. See
+/// [`crate::BonsaiStorage::get_change_batch`] and [`crate::BonsaiStorage::apply_change_batch`].
+#[derive(Debug, Default, Clone)]
+pub struct ChangeBatch(
+    pub(crate) HashMap<TrieKey, Change>,
+    /// Trie of the changes which belong to one, by key, see [`ChangeBatch::serialize`].
+    pub(crate) HashMap<TrieKey, ByteVec>,
+);
 
 const KEY_SEPARATOR: u8 = 0x00;
+/// Separates the commit ID from the identifier of the trie of a framed entry.
+const IDENTIFIER_SEPARATOR: u8 = 0x01;
 const NEW_VALUE: u8 = 0x00;
 const OLD_VALUE: u8 = 0x01;
 
 impl ChangeBatch {
-    pub fn insert_in_place(&mut self, key: TrieKey, change: Change) {
+    /// Number of database entries changed.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn insert_in_place(&mut self, key: TrieKey, change: Change) {
         match self.0.entry(key) {
             Entry::Occupied(mut entry) => {
                 let e = entry.get_mut();
@@ -32,11 +56,18 @@ impl ChangeBatch {
         }
     }
 
+    /// Key-value pairs of the trie log of commit `id`, which can be sent over the network and read
+    /// back with [`ChangeBatch::deserialize`].
+    ///
+    /// The entries of the changes to a trie are framed by its identifier: they all start with
+    /// [`trie_log_prefix`], so that they can be read without the ones of the other tries. The
+    /// other entries, such as the metadata, follow the commit ID directly.
     pub fn serialize<ID: Id>(&self, id: &ID) -> Vec<(ByteVec, &[u8])> {
         self.0
             .iter()
             .flat_map(|(change_key, change)| {
                 let mut changes = Vec::new();
+                let identifier = self.1.get(change_key).map(ByteVec::as_slice);
 
                 if let Some(old_value) = &change.old_value {
                     if let Some(new_value) = &change.new_value {
@@ -44,12 +75,12 @@ impl ChangeBatch {
                             return changes;
                         }
                     }
-                    let key = key_old_value(id, change_key);
+                    let key = key_old_value(id, identifier, change_key);
                     changes.push((key, old_value.as_slice()));
                 }
 
                 if let Some(new_value) = &change.new_value {
-                    let key = key_new_value(id, change_key);
+                    let key = key_new_value(id, identifier, change_key);
                     changes.push((key, new_value.as_slice()));
                 }
                 changes
@@ -57,72 +88,191 @@ impl ChangeBatch {
             .collect()
     }
 
-    pub fn deserialize<ID: Id>(id: &ID, changes: Vec<(ByteVec, ByteVec)>) -> Self {
+    /// Read back the key-value pairs of a trie log, see [`ChangeBatch::serialize`].
+    ///
+    /// Fails with [`BonsaiStorageError::InvalidTrieLogKey`] if a key is not a trie log key of
+    /// commit `id`.
+    pub fn deserialize<ID: Id, E: DBError>(
+        id: &ID,
+        changes: Vec<(ByteVec, ByteVec)>,
+    ) -> Result<Self, BonsaiStorageError<E>> {
         let id = id.to_bytes();
-        let mut change_batch = ChangeBatch(HashMap::new());
-        let mut current_change = Change::default();
-        let mut last_key = None;
+        let mut change_batch = ChangeBatch::default();
+        // The keys are not prefix-free: the entries of a key may be separated by the ones of a
+        // longer key, such as a metadata key starting with the path of a trie node.
         for (key, value) in changes {
-            if key.len() < id.len() + 3 {
-                panic!("Invalid key format");
+            let invalid_key = || BonsaiStorageError::InvalidTrieLogKey { key: key.clone() };
+            let Some(ParsedKey::TrieLog {
+                id: key_id,
+                identifier,
+                key: change_key,
+                new_value,
+            }) = parse_trie_log_key(&key, id.len())
+            else {
+                return Err(invalid_key());
+            };
+            if key_id != &id[..] {
+                return Err(invalid_key());
             }
-            // following unwraps and indices are safe because of the check above
-            let mut key = key.to_vec();
-            let change_type = key.pop().unwrap();
-            let key_type = key.pop().unwrap();
-            let change_key = TrieKey::from_variant_and_bytes(key_type, key[id.len() + 1..].into());
-            if let Some(last_key) = last_key {
-                if last_key != change_key {
-                    change_batch.insert_in_place(last_key, current_change);
-                    current_change = Change::default();
+            let key_type = match change_key {
+                DatabaseKey::Trie(_) => TrieKeyType::Trie,
+                DatabaseKey::Flat(_) => TrieKeyType::Flat,
+                DatabaseKey::Meta(_) => TrieKeyType::Meta,
+                DatabaseKey::TrieLog(_) => return Err(invalid_key()),
+            };
+            let change_key = match identifier {
+                // The identifier is only left out of the keys it starts.
+                Some(identifier) if !matches!(change_key, DatabaseKey::Meta(_)) => {
+                    TrieKey::new(identifier, key_type, change_key.as_slice())
                 }
+                _ => TrieKey::new(&[], key_type, change_key.as_slice()),
+            };
+            if let Some(identifier) = identifier {
+                change_batch.1.insert(change_key.clone(), identifier.into());
             }
-            match change_type {
-                NEW_VALUE => current_change.new_value = Some(value),
-                OLD_VALUE => current_change.old_value = Some(value),
-                _ => panic!("Invalid change type"),
-            }
-            last_key = Some(change_key.clone());
-        }
-        if let Some(last_key) = last_key {
-            if current_change.new_value.is_some() || current_change.old_value.is_some() {
-                change_batch.insert_in_place(last_key, current_change);
+            let change = change_batch.0.entry(change_key).or_default();
+            if new_value {
+                change.new_value = Some(value);
+            } else {
+                change.old_value = Some(value);
             }
         }
-        change_batch
+        Ok(change_batch)
     }
 }
 
-pub fn key_old_value<ID: Id>(id: &ID, key: &TrieKey) -> ByteVec {
+/// Split a trie log key written by [`trie_log_key`], for commit IDs of `id_len` bytes.
+pub(crate) fn parse_trie_log_key(key: &[u8], id_len: usize) -> Option<ParsedKey<'_>> {
+    let (id, rest) = key.split_at_checked(id_len)?;
+    let (separator, mut rest) = rest.split_first()?;
+    let identifier = match *separator {
+        KEY_SEPARATOR => None,
+        IDENTIFIER_SEPARATOR => {
+            let len = Compact::<u32>::decode(&mut rest).ok()?;
+            let (identifier, trie_key) = rest.split_at_checked(len.0 as usize)?;
+            rest = trie_key;
+            Some(identifier)
+        }
+        _ => return None,
+    };
+    let [trie_key @ .., key_type, change_type] = rest else {
+        return None;
+    };
+    let key = match *key_type {
+        x if x == TrieKeyType::Trie as u8 => DatabaseKey::Trie(trie_key),
+        x if x == TrieKeyType::Flat as u8 => DatabaseKey::Flat(trie_key),
+        x if x == TrieKeyType::Meta as u8 => DatabaseKey::Meta(trie_key),
+        _ => return None,
+    };
+    let new_value = match *change_type {
+        NEW_VALUE => true,
+        OLD_VALUE => false,
+        _ => return None,
+    };
+    Some(ParsedKey::TrieLog {
+        id,
+        identifier,
+        key,
+        new_value,
+    })
+}
+
+/// Start of the keys of the trie log entries of commit `id` which are not framed by a trie. The
+/// trie logs written before the entries were framed only have these.
+pub(crate) fn unframed_trie_log_prefix<ID: Id>(id: &ID) -> ByteVec {
+    let mut prefix = id.to_bytes();
+    prefix.push(KEY_SEPARATOR);
+    prefix
+}
+
+/// Start of the keys of the trie log entries of commit `id` framed by the trie `identifier`.
+pub(crate) fn trie_log_prefix<ID: Id>(id: &ID, identifier: &[u8]) -> ByteVec {
     id.to_bytes()
         .into_iter()
-        .chain(iter::once(KEY_SEPARATOR))
-        .chain(key.as_slice().iter().copied())
-        .chain(iter::once(key.into()))
-        .chain(iter::once(OLD_VALUE))
+        .chain(iter::once(IDENTIFIER_SEPARATOR))
+        .chain(Compact(identifier.len() as u32).encode_bytevec())
+        .chain(identifier.iter().copied())
         .collect()
 }
 
-pub fn key_new_value<ID: Id>(id: &ID, key: &TrieKey) -> ByteVec {
-    id.to_bytes()
+fn trie_log_key<ID: Id>(
+    id: &ID,
+    identifier: Option<&[u8]>,
+    key: &TrieKey,
+    change_type: u8,
+) -> ByteVec {
+    let (prefix, key_bytes) = match identifier {
+        Some(identifier) => {
+            let key_bytes = match key {
+                TrieKey::Meta(_) => key.as_slice(),
+                _ => key
+                    .as_slice()
+                    .strip_prefix(identifier)
+                    .expect("The keys of a trie start with its identifier"),
+            };
+            (trie_log_prefix(id, identifier), key_bytes)
+        }
+        None => (unframed_trie_log_prefix(id), key.as_slice()),
+    };
+    prefix
         .into_iter()
-        .chain(iter::once(KEY_SEPARATOR))
-        .chain(key.as_slice().iter().copied())
+        .chain(key_bytes.iter().copied())
         .chain(iter::once(key.into()))
-        .chain(iter::once(NEW_VALUE))
+        .chain(iter::once(change_type))
         .collect()
 }
 
+pub fn key_old_value<ID: Id>(id: &ID, identifier: Option<&[u8]>, key: &TrieKey) -> ByteVec {
+    trie_log_key(id, identifier, key, OLD_VALUE)
+}
+
+pub fn key_new_value<ID: Id>(id: &ID, identifier: Option<&[u8]>, key: &TrieKey) -> ByteVec {
+    trie_log_key(id, identifier, key, NEW_VALUE)
+}
+
 #[cfg_attr(feature = "bench", derive(Clone))]
 #[derive(Debug)]
 pub struct ChangeStore {
     pub current_changes: ChangeBatch,
+    /// Number of trie log entries of the current changes and their size without the commit ID, by
+    /// trie identifier, see [`crate::BonsaiStorage::disk_usage`].
+    pub log_usage: HashMap<ByteVec, (u64, u64)>,
+    /// Root hash of the tries changed by the current changes, before and after them, by trie
+    /// identifier, see [`crate::BonsaiStorage::root_hash_at`].
+    pub root_hashes: HashMap<ByteVec, (Felt, Felt)>,
 }
 
 impl ChangeStore {
     pub fn new() -> Self {
         Self {
-            current_changes: ChangeBatch(HashMap::new()),
+            current_changes: ChangeBatch::default(),
+            log_usage: HashMap::new(),
+            root_hashes: HashMap::new(),
         }
     }
+
+    pub(crate) fn clear(&mut self) {
+        self.current_changes.0.clear();
+        self.current_changes.1.clear();
+        self.log_usage.clear();
+        self.root_hashes.clear();
+    }
+}
+
+/// Number of trie log entries recording `change` at `key` of the trie `identifier` and their size
+/// without the commit ID, as written by [`ChangeBatch::serialize`].
+pub(crate) fn trie_log_usage(identifier: &[u8], key: &TrieKey, change: &Change) -> (u64, u64) {
+    if change.old_value == change.new_value {
+        return (0, 0);
+    }
+    [&change.old_value, &change.new_value]
+        .into_iter()
+        .flatten()
+        .fold((0, 0), |(entries, bytes), value| {
+            // Separator, identifier length, key starting with the identifier, key type, change type
+            // and value.
+            let identifier_len = Compact(identifier.len() as u32).encode_bytevec().len();
+            let size = 1 + identifier_len + key.as_slice().len() + 2 + value.len();
+            (entries + 1, bytes + size as u64)
+        })
 }
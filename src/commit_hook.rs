@@ -0,0 +1,18 @@
+use crate::{ByteVec, HashMap, String};
+use starknet_types_core::felt::Felt;
+
+/// A commit about to be written, given to the hooks registered with
+/// [`crate::BonsaiStorage::add_commit_hook`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingCommit<ChangeID> {
+    pub id: ChangeID,
+    /// Root hash of each trie with uncommitted changes, once they are committed.
+    pub root_hashes: HashMap<ByteVec, Felt>,
+    /// Number of leaves inserted, updated or removed by the commit.
+    pub changes: usize,
+}
+
+/// Validates a commit before it is written. Returning an error aborts the commit, see
+/// [`crate::BonsaiStorage::add_commit_hook`].
+pub type CommitHook<ChangeID> =
+    dyn Fn(&PendingCommit<ChangeID>) -> Result<(), String> + Send + Sync;
@@ -0,0 +1,87 @@
+//! Commits kept readable while a [`CommitGuard`] is alive, see
+//! [`crate::BonsaiStorage::pin_commit`].
+
+use crate::{id::Id, Arc, BTreeMap, Vec};
+use std::sync::{Mutex, PoisonError};
+
+/// What a pinned commit needs to be read.
+#[derive(Debug, Clone, Copy)]
+struct Pin {
+    /// Number of guards of the commit.
+    guards: usize,
+    /// Oldest commit whose trie log is needed.
+    trie_logs_from: u64,
+    /// Snapshot a transactional state at the commit is created from.
+    snapshot: Option<u64>,
+}
+
+/// Commits pinned through a storage, shared with their guards, which unpin them when dropped.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CommitPins(Arc<Mutex<BTreeMap<u64, Pin>>>);
+
+impl CommitPins {
+    /// Pin commit `id`, keeping the trie logs from `trie_logs_from` and the snapshot at `snapshot`.
+    pub(crate) fn pin<ID: Id>(
+        &self,
+        id: ID,
+        trie_logs_from: u64,
+        snapshot: Option<ID>,
+    ) -> CommitGuard<ID> {
+        let mut pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let pin = pins.entry(id.as_u64()).or_insert(Pin {
+            guards: 0,
+            trie_logs_from,
+            snapshot: snapshot.map(|id| id.as_u64()),
+        });
+        pin.guards += 1;
+        CommitGuard {
+            id,
+            pins: self.clone(),
+        }
+    }
+
+    /// Oldest commit whose trie log is needed by a pinned commit.
+    pub(crate) fn trie_logs_from(&self) -> Option<u64> {
+        let pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        pins.values().map(|pin| pin.trie_logs_from).min()
+    }
+
+    /// Snapshots needed by the pinned commits.
+    pub(crate) fn snapshots(&self) -> Vec<u64> {
+        let pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        pins.values().filter_map(|pin| pin.snapshot).collect()
+    }
+
+    fn unpin(&self, id: u64) {
+        let mut pins = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pin) = pins.get_mut(&id) {
+            pin.guards -= 1;
+            if pin.guards == 0 {
+                pins.remove(&id);
+            }
+        }
+    }
+}
+
+/// Keeps a commit readable while it is alive, created with
+/// [`crate::BonsaiStorage::pin_commit`]. Dropping it lets the following commits prune what the
+/// commit needs.
+#[must_use = "the commit is unpinned when the guard is dropped"]
+#[derive(Debug)]
+pub struct CommitGuard<ID: Id> {
+    id: ID,
+    pins: CommitPins,
+}
+
+impl<ID: Id> CommitGuard<ID> {
+    /// The pinned commit.
+    pub fn id(&self) -> ID {
+        self.id
+    }
+}
+
+impl<ID: Id> Drop for CommitGuard<ID> {
+    fn drop(&mut self) {
+        self.pins.unpin(self.id.as_u64());
+    }
+}
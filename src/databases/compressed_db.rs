@@ -0,0 +1,494 @@
+#[cfg(feature = "zstd")]
+use std::{collections::BTreeMap, sync::Arc};
+use std::{error::Error as StdError, fmt};
+
+#[cfg(feature = "zstd")]
+use zstd::{
+    bulk::{Compressor, Decompressor},
+    dict::{DecoderDictionary, EncoderDictionary},
+};
+
+#[cfg(feature = "zstd")]
+use crate::trie::trie_db::MetaKeyType;
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    ByteVec, Vec,
+};
+
+/// First byte of a stored value, telling how it was compressed.
+const UNCOMPRESSED: u8 = 0;
+const ZSTD: u8 = 1;
+const ZSTD_DICTIONARY: u8 = 2;
+const LZ4: u8 = 3;
+
+#[derive(Debug)]
+pub enum CompressedDbError<E> {
+    Database(E),
+    /// A stored value could not be decompressed, or was compressed with a disabled feature.
+    Decompression,
+    /// A stored value was compressed with a dictionary which is not in the database.
+    UnknownDictionary(u32),
+    /// Training a dictionary failed, see [`CompressedDb::train_dictionary`].
+    DictionaryTraining(String),
+}
+
+impl<E: fmt::Display> fmt::Display for CompressedDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(err) => write!(f, "Database error: {}", err),
+            Self::Decompression => write!(f, "Decompression failed"),
+            Self::UnknownDictionary(id) => write!(f, "Unknown compression dictionary {}", id),
+            Self::DictionaryTraining(err) => write!(f, "Dictionary training failed: {}", err),
+        }
+    }
+}
+
+impl<E: DBError> StdError for CompressedDbError<E> {}
+
+impl<E: DBError> DBError for CompressedDbError<E> {}
+
+/// Compression of the values of a column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Uses the last dictionary trained for the column, if any, see
+    /// [`CompressedDb::train_dictionary`].
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// Configuration for the compressed database. The metadata is never compressed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressedDbConfig {
+    pub trie: Compression,
+    pub flat: Compression,
+    pub trie_log: Compression,
+}
+
+impl CompressedDbConfig {
+    fn column(&self, column: Column) -> Compression {
+        match column {
+            Column::Trie => self.trie,
+            Column::Flat => self.flat,
+            Column::TrieLog => self.trie_log,
+        }
+    }
+}
+
+/// Column of a compressed value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Trie = 0,
+    Flat = 1,
+    TrieLog = 2,
+}
+
+impl Column {
+    fn of(key: &DatabaseKey) -> Option<Self> {
+        match key {
+            DatabaseKey::Trie(_) => Some(Column::Trie),
+            DatabaseKey::Flat(_) => Some(Column::Flat),
+            DatabaseKey::TrieLog(_) => Some(Column::TrieLog),
+            DatabaseKey::Meta(_) => None,
+        }
+    }
+}
+
+/// Dictionaries of a [`CompressedDb`], shared with its transactions.
+#[cfg(feature = "zstd")]
+#[derive(Default)]
+struct Dictionaries {
+    /// All the dictionaries, to decompress the values.
+    decoders: BTreeMap<u32, DecoderDictionary<'static>>,
+    /// Last dictionary of each column, to compress the values.
+    encoders: [Option<(u32, EncoderDictionary<'static>)>; 3],
+}
+
+/// Database compressing the values written to the underlying database, with a byte telling
+/// how each value was compressed so that the compression of a column can be changed.
+///
+/// Trie nodes are small and similar, zstd compresses them much better with a dictionary trained
+/// over a sample of them, see [`CompressedDb::train_dictionary`]. The dictionaries are stored in
+/// the metadata of the underlying database.
+///
+/// All the values of the trie, flat and trie log columns must have been written through the
+/// compressed database: it can't wrap a database created without it.
+pub struct CompressedDb<DB> {
+    db: DB,
+    config: CompressedDbConfig,
+    #[cfg(feature = "zstd")]
+    dictionaries: Arc<Dictionaries>,
+}
+
+impl<DB: fmt::Debug> fmt::Debug for CompressedDb<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("CompressedDb");
+        debug.field("db", &self.db).field("config", &self.config);
+        #[cfg(feature = "zstd")]
+        debug.field(
+            "dictionaries",
+            &self.dictionaries.decoders.keys().collect::<Vec<_>>(),
+        );
+        debug.finish()
+    }
+}
+
+impl<DB> CompressedDb<DB> {
+    pub fn inner(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+
+    fn compress(&self, key: &DatabaseKey, value: &[u8]) -> ByteVec {
+        let Some(column) = Column::of(key) else {
+            return value.into();
+        };
+        let compressed = match self.config.column(column) {
+            Compression::None => None,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level } => self.compress_zstd(column, level, value),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let mut compressed = vec![LZ4];
+                compressed.extend(lz4_flex::compress_prepend_size(value));
+                Some(compressed)
+            }
+        };
+        match compressed {
+            Some(compressed) if compressed.len() <= value.len() => compressed.into(),
+            // Not worth it, which is frequent for the leaf values.
+            _ => {
+                let mut stored = ByteVec::with_capacity(value.len() + 1);
+                stored.push(UNCOMPRESSED);
+                stored.extend_from_slice(value);
+                stored
+            }
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compress_zstd(&self, column: Column, level: i32, value: &[u8]) -> Option<Vec<u8>> {
+        let mut compressed = Vec::new();
+        let frame = match &self.dictionaries.encoders[column as usize] {
+            Some((id, dictionary)) => {
+                compressed.push(ZSTD_DICTIONARY);
+                compressed.extend_from_slice(&id.to_be_bytes());
+                Compressor::with_prepared_dictionary(dictionary)
+                    .and_then(|mut compressor| compressor.compress(value))
+            }
+            None => {
+                compressed.push(ZSTD);
+                zstd::bulk::compress(value, level)
+            }
+        };
+        compressed.extend_from_slice(&u32::try_from(value.len()).ok()?.to_be_bytes());
+        compressed.extend(frame.ok()?);
+        Some(compressed)
+    }
+
+    fn decompress<E>(
+        &self,
+        key: &DatabaseKey,
+        stored: &[u8],
+    ) -> Result<ByteVec, CompressedDbError<E>> {
+        if Column::of(key).is_none() {
+            return Ok(stored.into());
+        }
+        let [format, compressed @ ..] = stored else {
+            return Err(CompressedDbError::Decompression);
+        };
+        match *format {
+            UNCOMPRESSED => Ok(compressed.into()),
+            #[cfg(feature = "zstd")]
+            ZSTD => {
+                let (len, frame) = split_u32(compressed)?;
+                zstd::bulk::decompress(frame, len as usize)
+                    .map(Into::into)
+                    .map_err(|_| CompressedDbError::Decompression)
+            }
+            #[cfg(feature = "zstd")]
+            ZSTD_DICTIONARY => {
+                let (id, compressed) = split_u32(compressed)?;
+                let (len, frame) = split_u32(compressed)?;
+                let dictionary = self
+                    .dictionaries
+                    .decoders
+                    .get(&id)
+                    .ok_or(CompressedDbError::UnknownDictionary(id))?;
+                Decompressor::with_prepared_dictionary(dictionary)
+                    .and_then(|mut decompressor| decompressor.decompress(frame, len as usize))
+                    .map(Into::into)
+                    .map_err(|_| CompressedDbError::Decompression)
+            }
+            #[cfg(feature = "lz4")]
+            LZ4 => lz4_flex::decompress_size_prepended(compressed)
+                .map(Into::into)
+                .map_err(|_| CompressedDbError::Decompression),
+            _ => Err(CompressedDbError::Decompression),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn split_u32<E>(bytes: &[u8]) -> Result<(u32, &[u8]), CompressedDbError<E>> {
+    let Some((int, rest)) = bytes.split_first_chunk::<4>() else {
+        return Err(CompressedDbError::Decompression);
+    };
+    Ok((u32::from_be_bytes(*int), rest))
+}
+
+#[cfg(feature = "zstd")]
+fn dictionary_key(id: u32) -> [u8; 5] {
+    let mut key = [MetaKeyType::CompressionDictionary as u8; 5];
+    key[1..].copy_from_slice(&id.to_be_bytes());
+    key
+}
+
+impl<DB: BonsaiDatabase> CompressedDb<DB> {
+    /// Creates a compressed view of `db`, loading the dictionaries it stores
+    pub fn new(
+        db: DB,
+        config: CompressedDbConfig,
+    ) -> Result<Self, CompressedDbError<DB::DatabaseError>> {
+        #[cfg(feature = "zstd")]
+        let dictionaries = Arc::new(Self::load_dictionaries(&db, &config)?);
+        Ok(Self {
+            db,
+            config,
+            #[cfg(feature = "zstd")]
+            dictionaries,
+        })
+    }
+
+    #[cfg(feature = "zstd")]
+    fn load_dictionaries(
+        db: &DB,
+        config: &CompressedDbConfig,
+    ) -> Result<Dictionaries, CompressedDbError<DB::DatabaseError>> {
+        let mut dictionaries = Dictionaries::default();
+        let stored = db
+            .get_by_prefix(&DatabaseKey::Meta(&[
+                MetaKeyType::CompressionDictionary as u8
+            ]))
+            .map_err(CompressedDbError::Database)?;
+        // In increasing id order: the last dictionary of each column is used to compress.
+        for (key, value) in stored {
+            let (_, id) = key.split_first().ok_or(CompressedDbError::Decompression)?;
+            let (id, _) = split_u32(id)?;
+            let (column, data) = value
+                .split_first()
+                .ok_or(CompressedDbError::Decompression)?;
+            let column = match *column {
+                0 => Column::Trie,
+                1 => Column::Flat,
+                2 => Column::TrieLog,
+                _ => return Err(CompressedDbError::Decompression),
+            };
+            dictionaries
+                .decoders
+                .insert(id, DecoderDictionary::copy(data));
+            if let Compression::Zstd { level } = config.column(column) {
+                dictionaries.encoders[column as usize] =
+                    Some((id, EncoderDictionary::copy(data, level)));
+            }
+        }
+        Ok(dictionaries)
+    }
+
+    /// Trains a zstd dictionary over at most `max_samples` values of the keys starting with
+    /// `prefix`, of at most `max_size` bytes, and uses it for the next writes of the column if it
+    /// is compressed with zstd. Returns the id of the dictionary, `None` if there are no values
+    /// to sample or `prefix` is a metadata key.
+    ///
+    /// The values written before keep their compression.
+    #[cfg(feature = "zstd")]
+    pub fn train_dictionary(
+        &mut self,
+        prefix: &DatabaseKey,
+        max_samples: usize,
+        max_size: usize,
+    ) -> Result<Option<u32>, CompressedDbError<DB::DatabaseError>> {
+        let Some(column) = Column::of(prefix) else {
+            return Ok(None);
+        };
+        let entries = self
+            .db
+            .get_by_prefix(prefix)
+            .map_err(CompressedDbError::Database)?;
+        if entries.is_empty() || max_samples == 0 {
+            return Ok(None);
+        }
+        let step = entries.len().div_ceil(max_samples);
+        let samples = entries
+            .iter()
+            .step_by(step)
+            .map(|(_, value)| self.decompress(prefix, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let data = zstd::dict::from_samples(&samples, max_size)
+            .map_err(|err| CompressedDbError::DictionaryTraining(err.to_string()))?;
+
+        let id = self
+            .dictionaries
+            .decoders
+            .last_key_value()
+            .map_or(0, |(id, _)| id + 1);
+        let mut value = vec![column as u8];
+        value.extend_from_slice(&data);
+        self.db
+            .insert(&DatabaseKey::Meta(&dictionary_key(id)), &value, None)
+            .map_err(CompressedDbError::Database)?;
+        self.dictionaries = Arc::new(Self::load_dictionaries(&self.db, &self.config)?);
+        Ok(Some(id))
+    }
+}
+
+impl<DB: BonsaiDatabase> BonsaiDatabase for CompressedDb<DB> {
+    type Batch = DB::Batch;
+    type DatabaseError = CompressedDbError<DB::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.db.create_batch()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.db
+            .get(key)
+            .map_err(CompressedDbError::Database)?
+            .map(|value| self.decompress(key, &value))
+            .transpose()
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        let values = self
+            .db
+            .get_many(keys)
+            .map_err(CompressedDbError::Database)?;
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| value.map(|value| self.decompress(key, &value)).transpose())
+            .collect()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        self.db
+            .get_by_prefix(prefix)
+            .map_err(CompressedDbError::Database)?
+            .into_iter()
+            .map(|(key, value)| Ok((key, self.decompress(prefix, &value)?)))
+            .collect()
+    }
+
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        self.db
+            .get_keys_by_prefix(prefix)
+            .map_err(CompressedDbError::Database)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        self.db.contains(key).map_err(CompressedDbError::Database)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        let stored = self.compress(key, value);
+        self.db
+            .insert(key, &stored, batch)
+            .map_err(CompressedDbError::Database)?
+            .map(|old_value| self.decompress(key, &old_value))
+            .transpose()
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.db
+            .remove(key, batch)
+            .map_err(CompressedDbError::Database)?
+            .map(|old_value| self.decompress(key, &old_value))
+            .transpose()
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        self.db
+            .remove_by_prefix(prefix)
+            .map_err(CompressedDbError::Database)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.db
+            .write_batch(batch)
+            .map_err(CompressedDbError::Database)
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.db.compact().map_err(CompressedDbError::Database)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+impl<ID, DB> BonsaiPersistentDatabase<ID> for CompressedDb<DB>
+where
+    ID: Id,
+    DB: BonsaiPersistentDatabase<ID>,
+{
+    type Transaction<'a>
+        = CompressedDb<DB::Transaction<'a>>
+    where
+        Self: 'a;
+    type DatabaseError = CompressedDbError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        self.db.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.db.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.db.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let (snapshot_id, db) = self.db.transaction(id)?;
+        let transaction = CompressedDb {
+            db,
+            config: self.config,
+            #[cfg(feature = "zstd")]
+            dictionaries: Arc::clone(&self.dictionaries),
+        };
+        Some((snapshot_id, transaction))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.db
+            .merge(transaction.db)
+            .map_err(CompressedDbError::Database)
+    }
+}
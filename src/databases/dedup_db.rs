@@ -0,0 +1,411 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    trie::{
+        merkle_node::Node,
+        trie_db::{MetaKeyType, TrieKey},
+    },
+    BonsaiDatabase, ByteVec, EncodeExt, HashMap, Vec,
+};
+use core::{fmt, fmt::Display};
+use parity_scale_codec::Decode;
+
+/// First byte of a reference to a shared node. No node encoding starts with it, and the other
+/// values of the trie column, the leaf counts, are shorter than a reference.
+const REFERENCE_TAG: u8 = 0xFF;
+/// Length of the id of a shared node: its height then its hash.
+const NODE_ID_LEN: usize = 8 + 32;
+
+/// Error of a [`DedupDb`].
+#[derive(Debug)]
+pub enum DedupDbError<E> {
+    Database(E),
+    /// The shared node referenced at this key of the trie column is missing.
+    MissingNode(ByteVec),
+    /// The shared node at this key of the metadata column can't be decoded.
+    InvalidNode(ByteVec),
+}
+
+#[cfg(feature = "std")]
+impl<E: DBError> std::error::Error for DedupDbError<E> {}
+
+impl<E: Display> Display for DedupDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DedupDbError::Database(err) => write!(f, "Database error: {}", err),
+            DedupDbError::MissingNode(key) => {
+                write!(
+                    f,
+                    "Shared node referenced at {:?} is missing",
+                    key.as_slice()
+                )
+            }
+            DedupDbError::InvalidNode(key) => {
+                write!(f, "Shared node at {:?} can't be decoded", key.as_slice())
+            }
+        }
+    }
+}
+
+impl<E: DBError> DBError for DedupDbError<E> {}
+
+/// Writes of a [`DedupDb`] batch. The values written to the trie column and the shared nodes are
+/// kept as well, so that the next writes to the batch see the reference counts it updated.
+#[derive(Debug, Default)]
+pub struct DedupDbBatch<Batch> {
+    batch: Batch,
+    /// Values stored at the trie keys, `None` marks a removed key.
+    references: HashMap<ByteVec, Option<ByteVec>>,
+    /// Shared nodes by metadata key, `None` marks a removed node.
+    nodes: HashMap<ByteVec, Option<ByteVec>>,
+}
+
+/// Database storing the trie nodes once, however many tries have them: the nodes are stored in
+/// the metadata column by height and hash, with the number of references to them, and the trie
+/// column only keeps references. Identical subtries, such as the storages of contracts with the
+/// same layout, share their nodes.
+///
+/// A node is removed along with its last reference, when it is not in any trie anymore: removing
+/// the nodes of a trie, by pruning or reverting it, only frees the nodes that no other trie has.
+/// The other values are stored as they are, so this can wrap a database written without it, its
+/// nodes being shared as they are rewritten.
+///
+/// The nodes are read through their reference, which costs a second read, and the writes read the
+/// reference counts.
+#[derive(Debug)]
+pub struct DedupDb<DB> {
+    db: DB,
+}
+
+impl<DB> DedupDb<DB> {
+    pub fn new(db: DB) -> Self {
+        Self { db }
+    }
+
+    pub fn inner(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+}
+
+/// Metadata key of the shared node with the id `node_id`.
+fn node_key(node_id: &[u8]) -> ByteVec {
+    TrieKey::new_meta(MetaKeyType::SharedNode, node_id)
+        .as_slice()
+        .into()
+}
+
+/// Id of the shared node stored as `value` in the trie column, `None` if it is not a node.
+fn node_id(value: &[u8]) -> Option<ByteVec> {
+    let (node, _) = Node::decode_versioned(value).ok()?;
+    let (height, hash) = match &node {
+        Node::Binary(binary) => (binary.height, binary.hash?),
+        Node::Edge(edge) => (edge.height, edge.hash?),
+    };
+    let mut id = ByteVec::with_capacity(NODE_ID_LEN);
+    id.extend_from_slice(&u64::from(height).to_be_bytes());
+    id.extend_from_slice(&hash.to_bytes_be());
+    Some(id)
+}
+
+/// Id of the shared node referenced by `stored`, a value of the trie column, `None` if it is
+/// stored as it is.
+fn referenced(stored: &[u8]) -> Option<&[u8]> {
+    match stored.split_first() {
+        Some((&REFERENCE_TAG, node_id)) if node_id.len() == NODE_ID_LEN => Some(node_id),
+        _ => None,
+    }
+}
+
+impl<DB: BonsaiDatabase> DedupDb<DB> {
+    /// Value stored in the underlying database at the trie key `key`, with the writes of `batch`.
+    fn stored(
+        &self,
+        key: &[u8],
+        batch: Option<&DedupDbBatch<DB::Batch>>,
+    ) -> Result<Option<ByteVec>, DedupDbError<DB::DatabaseError>> {
+        if let Some(value) = batch.and_then(|batch| batch.references.get(key)) {
+            return Ok(value.clone());
+        }
+        self.db
+            .get(&DatabaseKey::Trie(key))
+            .map_err(DedupDbError::Database)
+    }
+
+    /// Reference count and encoding of the shared node `node_id`, with the writes of `batch`.
+    fn shared(
+        &self,
+        node_id: &[u8],
+        batch: Option<&DedupDbBatch<DB::Batch>>,
+    ) -> Result<Option<(u64, ByteVec)>, DedupDbError<DB::DatabaseError>> {
+        let key = node_key(node_id);
+        let value = match batch.and_then(|batch| batch.nodes.get(&key)) {
+            Some(value) => value.clone(),
+            None => self
+                .db
+                .get(&DatabaseKey::Meta(&key))
+                .map_err(DedupDbError::Database)?,
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let mut input = value.as_slice();
+        let count = u64::decode(&mut input).map_err(|_| DedupDbError::InvalidNode(key))?;
+        Ok(Some((count, input.into())))
+    }
+
+    /// Value at the trie key `key`, whose stored value is `stored`.
+    fn resolve(
+        &self,
+        key: &[u8],
+        stored: ByteVec,
+        batch: Option<&DedupDbBatch<DB::Batch>>,
+    ) -> Result<ByteVec, DedupDbError<DB::DatabaseError>> {
+        let Some(node_id) = referenced(&stored) else {
+            return Ok(stored);
+        };
+        let (_, node) = self
+            .shared(node_id, batch)?
+            .ok_or_else(|| DedupDbError::MissingNode(key.into()))?;
+        Ok(node)
+    }
+
+    /// Store the shared node `node_id` with `count` references, removing it when there are none.
+    fn write_shared(
+        &mut self,
+        node_id: &[u8],
+        count: u64,
+        node: &[u8],
+        batch: Option<&mut DedupDbBatch<DB::Batch>>,
+    ) -> Result<(), DedupDbError<DB::DatabaseError>> {
+        let key = node_key(node_id);
+        let value = (count != 0).then(|| {
+            let mut value = count.encode_bytevec();
+            value.extend_from_slice(node);
+            value
+        });
+        let (inner_batch, nodes) = match batch {
+            Some(batch) => (Some(&mut batch.batch), Some(&mut batch.nodes)),
+            None => (None, None),
+        };
+        let db_key = DatabaseKey::Meta(&key);
+        match &value {
+            Some(value) => self.db.insert(&db_key, value, inner_batch),
+            None => self.db.remove(&db_key, inner_batch),
+        }
+        .map_err(DedupDbError::Database)?;
+        if let Some(nodes) = nodes {
+            nodes.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Replace the value at the trie key `key` with `value`, or remove it, and return the previous
+    /// one. Nodes are stored as references to shared nodes.
+    fn replace(
+        &mut self,
+        key: &[u8],
+        value: Option<&[u8]>,
+        mut batch: Option<&mut DedupDbBatch<DB::Batch>>,
+    ) -> Result<Option<ByteVec>, DedupDbError<DB::DatabaseError>> {
+        let previous = self.stored(key, batch.as_deref())?;
+        let old_value = previous
+            .clone()
+            .map(|stored| self.resolve(key, stored, batch.as_deref()))
+            .transpose()?;
+        // The new reference is taken before the previous one is released, so that rewriting a
+        // node doesn't remove it.
+        let stored = match value {
+            Some(value) => Some(match node_id(value) {
+                Some(node_id) => {
+                    let count = self
+                        .shared(&node_id, batch.as_deref())?
+                        .map_or(0, |(count, _)| count);
+                    self.write_shared(&node_id, count + 1, value, batch.as_deref_mut())?;
+                    let mut reference = ByteVec::with_capacity(1 + NODE_ID_LEN);
+                    reference.push(REFERENCE_TAG);
+                    reference.extend_from_slice(&node_id);
+                    reference
+                }
+                None => value.into(),
+            }),
+            None => None,
+        };
+        if let Some(node_id) = previous.as_deref().and_then(referenced) {
+            if let Some((count, node)) = self.shared(node_id, batch.as_deref())? {
+                self.write_shared(
+                    node_id,
+                    count.saturating_sub(1),
+                    &node,
+                    batch.as_deref_mut(),
+                )?;
+            }
+        }
+        let db_key = DatabaseKey::Trie(key);
+        let inner_batch = batch.as_deref_mut().map(|batch| &mut batch.batch);
+        match &stored {
+            Some(stored) => self.db.insert(&db_key, stored, inner_batch),
+            None => self.db.remove(&db_key, inner_batch),
+        }
+        .map_err(DedupDbError::Database)?;
+        if let Some(batch) = batch {
+            batch.references.insert(key.into(), stored);
+        }
+        Ok(old_value)
+    }
+}
+
+impl<DB: BonsaiDatabase> BonsaiDatabase for DedupDb<DB> {
+    type Batch = DedupDbBatch<DB::Batch>;
+    type DatabaseError = DedupDbError<DB::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        DedupDbBatch {
+            batch: self.db.create_batch(),
+            references: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        let DatabaseKey::Trie(key) = key else {
+            return self.db.get(key).map_err(DedupDbError::Database);
+        };
+        self.stored(key, None)?
+            .map(|stored| self.resolve(key, stored, None))
+            .transpose()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        let entries = self
+            .db
+            .get_by_prefix(prefix)
+            .map_err(DedupDbError::Database)?;
+        match prefix {
+            DatabaseKey::Trie(_) => entries
+                .into_iter()
+                .map(|(key, stored)| {
+                    let value = self.resolve(&key, stored, None)?;
+                    Ok((key, value))
+                })
+                .collect(),
+            // The shared nodes are an implementation detail of this database.
+            DatabaseKey::Meta(&[]) => Ok(entries
+                .into_iter()
+                .filter(|(key, _)| key.first() != Some(&(MetaKeyType::SharedNode as u8)))
+                .collect()),
+            _ => Ok(entries),
+        }
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        self.db.contains(key).map_err(DedupDbError::Database)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        match key {
+            DatabaseKey::Trie(key) => self.replace(key, Some(value), batch),
+            _ => self
+                .db
+                .insert(key, value, batch.map(|batch| &mut batch.batch))
+                .map_err(DedupDbError::Database),
+        }
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        match key {
+            DatabaseKey::Trie(key) => self.replace(key, None, batch),
+            _ => self
+                .db
+                .remove(key, batch.map(|batch| &mut batch.batch))
+                .map_err(DedupDbError::Database),
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        if !matches!(prefix, DatabaseKey::Trie(_)) {
+            return self
+                .db
+                .remove_by_prefix(prefix)
+                .map_err(DedupDbError::Database);
+        }
+        // The references are released one by one.
+        let mut batch = self.create_batch();
+        let entries = self
+            .db
+            .get_by_prefix(prefix)
+            .map_err(DedupDbError::Database)?;
+        for (key, _) in entries {
+            self.replace(&key, None, Some(&mut batch))?;
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.db
+            .write_batch(batch.batch)
+            .map_err(DedupDbError::Database)
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.db.compact().map_err(DedupDbError::Database)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+impl<ID, DB> BonsaiPersistentDatabase<ID> for DedupDb<DB>
+where
+    ID: Id,
+    DB: BonsaiPersistentDatabase<ID>,
+{
+    type Transaction<'a>
+        = DedupDb<DB::Transaction<'a>>
+    where
+        Self: 'a;
+    type DatabaseError = DedupDbError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        self.db.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.db.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.db.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let (snapshot_id, db) = self.db.transaction(id)?;
+        Some((snapshot_id, DedupDb { db }))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.db
+            .merge(transaction.db)
+            .map_err(DedupDbError::Database)
+    }
+}
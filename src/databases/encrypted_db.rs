@@ -0,0 +1,570 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    ByteVec, Vec,
+};
+
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Bytes added to an encrypted value, or to the encrypted part of a key.
+const OVERHEAD: usize = KEY_ID_LEN + NONCE_LEN + TAG_LEN;
+
+const KEY_HASH_CONTEXT: &str = "bonsai-trie encrypted database keys";
+
+#[derive(Debug)]
+pub enum EncryptedDbError<E> {
+    Database(E),
+    /// A value or a key could not be decrypted: it was modified, or written with another key.
+    Decryption,
+    /// The encryption key with this id was removed, see [`EncryptedDb::remove_key`].
+    UnknownKey(u32),
+}
+
+impl<E: fmt::Display> fmt::Display for EncryptedDbError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(err) => write!(f, "Database error: {}", err),
+            Self::Decryption => write!(f, "Decryption failed"),
+            Self::UnknownKey(key_id) => write!(f, "Unknown encryption key {}", key_id),
+        }
+    }
+}
+
+impl<E: DBError> StdError for EncryptedDbError<E> {}
+
+impl<E: DBError> DBError for EncryptedDbError<E> {}
+
+/// Encryption of the database keys by an [`EncryptedDb`], which keeps their first bytes in clear
+/// so that the prefix reads of the storage can be served by the underlying database.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEncryption {
+    /// Length of the trie identifiers, kept in clear in the trie and flat keys. Prefix reads
+    /// with a longer prefix read all the keys of the identifier.
+    pub identifier_len: usize,
+    /// Length of the commit ids, see [`Id::to_bytes`], kept in clear in the trie log keys.
+    pub id_len: usize,
+}
+
+impl KeyEncryption {
+    /// Number of bytes of `key` kept in clear, `None` if the key is not encrypted
+    fn clear_len(&self, key: &DatabaseKey) -> Option<usize> {
+        match key {
+            DatabaseKey::Trie(_) | DatabaseKey::Flat(_) => Some(self.identifier_len),
+            DatabaseKey::TrieLog(_) => Some(self.id_len + 1),
+            // The metadata keys are chosen by the user and the storage, not derived from the state.
+            DatabaseKey::Meta(_) => None,
+        }
+    }
+}
+
+/// Configuration for the encrypted database
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncryptedDbConfig {
+    /// Encrypt the keys as well as the values, `None` to store the keys in clear. Unlike the
+    /// values, the keys are encrypted deterministically: equal keys have the same encryption.
+    pub key_encryption: Option<KeyEncryption>,
+}
+
+#[derive(Clone)]
+struct Cipher {
+    aead: XChaCha20Poly1305,
+    /// Key deriving the nonces of the encrypted keys from their content.
+    hash_key: [u8; 32],
+}
+
+impl Cipher {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            aead: XChaCha20Poly1305::new(key.into()),
+            hash_key: blake3::derive_key(KEY_HASH_CONTEXT, key),
+        }
+    }
+}
+
+/// Database encrypting the values, and optionally the keys, written to the underlying database
+/// with XChaCha20-Poly1305, for deployments that cannot rely on disk encryption.
+///
+/// Each encryption records the id of its key, so that the keys can be rotated: after
+/// [`EncryptedDb::add_key`], new writes use the new key while the data written with the previous
+/// ones stays readable until [`EncryptedDb::reencrypt`] rewrites it, after which the previous keys
+/// can be removed. The values are bound to their key: they can't be moved to another key by
+/// someone without the encryption key.
+pub struct EncryptedDb<DB> {
+    db: DB,
+    ciphers: BTreeMap<u32, Cipher>,
+    current_key_id: u32,
+    config: EncryptedDbConfig,
+}
+
+impl<DB: fmt::Debug> fmt::Debug for EncryptedDb<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedDb")
+            .field("db", &self.db)
+            .field("key_ids", &self.ciphers.keys().collect::<Vec<_>>())
+            .field("current_key_id", &self.current_key_id)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<DB> EncryptedDb<DB> {
+    /// Creates an encrypted view of `db`, writing with the encryption `key` of id `key_id`
+    pub fn new(db: DB, key_id: u32, key: &[u8; 32], config: EncryptedDbConfig) -> Self {
+        Self {
+            db,
+            ciphers: BTreeMap::from([(key_id, Cipher::new(key))]),
+            current_key_id: key_id,
+            config,
+        }
+    }
+
+    /// Adds the encryption `key` of id `key_id`, used by the next writes. The keys previously
+    /// added are kept to read the data they encrypted.
+    pub fn add_key(&mut self, key_id: u32, key: &[u8; 32]) {
+        self.ciphers.insert(key_id, Cipher::new(key));
+        self.current_key_id = key_id;
+    }
+
+    /// Removes the encryption key `key_id`, returns whether it was removed. The current key can't
+    /// be removed, the data it encrypted must be rewritten first, see [`EncryptedDb::reencrypt`].
+    pub fn remove_key(&mut self, key_id: u32) -> bool {
+        key_id != self.current_key_id && self.ciphers.remove(&key_id).is_some()
+    }
+
+    /// Id of the encryption key used by the writes
+    pub fn current_key_id(&self) -> u32 {
+        self.current_key_id
+    }
+
+    pub fn inner(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+
+    fn cipher<E>(&self, key_id: u32) -> Result<&Cipher, EncryptedDbError<E>> {
+        self.ciphers
+            .get(&key_id)
+            .ok_or(EncryptedDbError::UnknownKey(key_id))
+    }
+
+    /// Ids of the encryption keys, starting with the current one
+    fn key_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        core::iter::once(self.current_key_id).chain(
+            self.ciphers
+                .keys()
+                .copied()
+                .filter(|key_id| *key_id != self.current_key_id),
+        )
+    }
+
+    fn encrypt_value<E>(
+        &self,
+        key: &DatabaseKey,
+        value: &[u8],
+    ) -> Result<ByteVec, EncryptedDbError<E>> {
+        let cipher = self.cipher(self.current_key_id)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = value_aad(key);
+        let ciphertext = cipher
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value,
+                    aad: &aad,
+                },
+            )
+            .expect("encryption of a value in memory");
+        let mut encrypted = ByteVec::with_capacity(OVERHEAD + value.len());
+        encrypted.extend_from_slice(&self.current_key_id.to_be_bytes());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    fn decrypt_value<E>(
+        &self,
+        key: &DatabaseKey,
+        encrypted: &[u8],
+    ) -> Result<ByteVec, EncryptedDbError<E>> {
+        let (key_id, nonce, ciphertext) = split_encrypted(encrypted)?;
+        let aad = value_aad(key);
+        let value = self
+            .cipher(key_id)?
+            .aead
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| EncryptedDbError::Decryption)?;
+        Ok(value.into())
+    }
+
+    /// Key stored in the underlying database for `key`, encrypted with the key `key_id`
+    fn encrypt_key<E>(
+        &self,
+        key: &DatabaseKey,
+        key_id: u32,
+    ) -> Result<ByteVec, EncryptedDbError<E>> {
+        let bytes = key.as_slice();
+        let Some(clear_len) = self.clear_len(key) else {
+            return Ok(bytes.into());
+        };
+        let (clear, suffix) = bytes.split_at(clear_len.min(bytes.len()));
+        let cipher = self.cipher(key_id)?;
+        // Synthetic nonce: the same key is always encrypted the same way.
+        let mut hasher = blake3::Hasher::new_keyed(&cipher.hash_key);
+        hasher.update(&[column_tag(key)]);
+        hasher.update(bytes);
+        let hash = hasher.finalize();
+        let nonce = XNonce::from_slice(&hash.as_bytes()[..NONCE_LEN]);
+        let ciphertext = cipher
+            .aead
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: suffix,
+                    aad: clear,
+                },
+            )
+            .expect("encryption of a key in memory");
+        let mut encrypted = ByteVec::with_capacity(bytes.len() + OVERHEAD);
+        encrypted.extend_from_slice(clear);
+        encrypted.extend_from_slice(&key_id.to_be_bytes());
+        encrypted.extend_from_slice(nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Decrypts a key of the underlying database, returns it with the id of its encryption key
+    fn decrypt_key<E>(
+        &self,
+        column: &DatabaseKey,
+        stored: &[u8],
+    ) -> Result<(ByteVec, u32), EncryptedDbError<E>> {
+        let Some(clear_len) = self.clear_len(column) else {
+            return Ok((stored.into(), self.current_key_id));
+        };
+        let Some(encrypted_len) = stored.len().checked_sub(OVERHEAD) else {
+            return Err(EncryptedDbError::Decryption);
+        };
+        // Keys shorter than the clear part are kept in clear entirely.
+        let (clear, encrypted) = stored.split_at(clear_len.min(encrypted_len));
+        let (key_id, nonce, ciphertext) = split_encrypted(encrypted)?;
+        let suffix = self
+            .cipher(key_id)?
+            .aead
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: clear,
+                },
+            )
+            .map_err(|_| EncryptedDbError::Decryption)?;
+        let mut key = ByteVec::from(clear);
+        key.extend_from_slice(&suffix);
+        Ok((key, key_id))
+    }
+
+    fn clear_len(&self, key: &DatabaseKey) -> Option<usize> {
+        self.config.key_encryption?.clear_len(key)
+    }
+}
+
+fn split_encrypted<E>(encrypted: &[u8]) -> Result<(u32, &XNonce, &[u8]), EncryptedDbError<E>> {
+    if encrypted.len() < OVERHEAD {
+        return Err(EncryptedDbError::Decryption);
+    }
+    let (key_id, rest) = encrypted.split_at(KEY_ID_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key_id = u32::from_be_bytes(key_id.try_into().expect("key id length"));
+    Ok((key_id, XNonce::from_slice(nonce), ciphertext))
+}
+
+fn column_tag(key: &DatabaseKey) -> u8 {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+        DatabaseKey::Meta(_) => 3,
+    }
+}
+
+fn value_aad(key: &DatabaseKey) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(key.as_slice().len() + 1);
+    aad.push(column_tag(key));
+    aad.extend_from_slice(key.as_slice());
+    aad
+}
+
+/// Key of the same column as `key`, with other bytes
+fn same_column<'a>(key: &DatabaseKey, bytes: &'a [u8]) -> DatabaseKey<'a> {
+    match key {
+        DatabaseKey::Trie(_) => DatabaseKey::Trie(bytes),
+        DatabaseKey::Flat(_) => DatabaseKey::Flat(bytes),
+        DatabaseKey::TrieLog(_) => DatabaseKey::TrieLog(bytes),
+        DatabaseKey::Meta(_) => DatabaseKey::Meta(bytes),
+    }
+}
+
+impl<DB: BonsaiDatabase> EncryptedDb<DB> {
+    /// Rewrites the data encrypted with a previous key with the current one, returns the number
+    /// of rewritten entries. The previous keys can be removed afterwards.
+    ///
+    /// Each column is read in memory at once.
+    pub fn reencrypt(&mut self) -> Result<usize, EncryptedDbError<DB::DatabaseError>> {
+        let mut rewritten = 0;
+        for column in [
+            DatabaseKey::Trie(&[]),
+            DatabaseKey::Flat(&[]),
+            DatabaseKey::TrieLog(&[]),
+            DatabaseKey::Meta(&[]),
+        ] {
+            let mut batch = self.db.create_batch();
+            let entries = self
+                .db
+                .get_by_prefix(&column)
+                .map_err(EncryptedDbError::Database)?;
+            for (stored_key, stored_value) in entries {
+                let (key, key_id) = self.decrypt_key(&column, &stored_key)?;
+                let key = same_column(&column, &key);
+                let (value_key_id, _, _) = split_encrypted(&stored_value)?;
+                if key_id == self.current_key_id && value_key_id == self.current_key_id {
+                    continue;
+                }
+                let value = self.decrypt_value(&key, &stored_value)?;
+                let new_key = self.encrypt_key(&key, self.current_key_id)?;
+                if new_key != stored_key {
+                    self.db
+                        .remove(&same_column(&column, &stored_key), Some(&mut batch))
+                        .map_err(EncryptedDbError::Database)?;
+                }
+                let new_value = self.encrypt_value(&key, &value)?;
+                self.db
+                    .insert(
+                        &same_column(&column, &new_key),
+                        &new_value,
+                        Some(&mut batch),
+                    )
+                    .map_err(EncryptedDbError::Database)?;
+                rewritten += 1;
+            }
+            self.db
+                .write_batch(batch)
+                .map_err(EncryptedDbError::Database)?;
+        }
+        Ok(rewritten)
+    }
+}
+
+impl<DB: BonsaiDatabase> BonsaiDatabase for EncryptedDb<DB> {
+    type Batch = DB::Batch;
+    type DatabaseError = EncryptedDbError<DB::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.db.create_batch()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        // The key may have been written with a previous encryption key.
+        let key_ids: Vec<_> = if self.clear_len(key).is_some() {
+            self.key_ids().collect()
+        } else {
+            vec![self.current_key_id]
+        };
+        for key_id in key_ids {
+            let stored_key = self.encrypt_key(key, key_id)?;
+            if let Some(value) = self
+                .db
+                .get(&same_column(key, &stored_key))
+                .map_err(EncryptedDbError::Database)?
+            {
+                return self.decrypt_value(key, &value).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        let bytes = prefix.as_slice();
+        let stored_prefix = match self.clear_len(prefix) {
+            Some(clear_len) => &bytes[..clear_len.min(bytes.len())],
+            None => bytes,
+        };
+        let mut values = Vec::new();
+        for (stored_key, stored_value) in self
+            .db
+            .get_by_prefix(&same_column(prefix, stored_prefix))
+            .map_err(EncryptedDbError::Database)?
+        {
+            let (key, _) = self.decrypt_key(prefix, &stored_key)?;
+            if key.starts_with(bytes) {
+                let value = self.decrypt_value(&same_column(prefix, &key), &stored_value)?;
+                values.push((key, value));
+            }
+        }
+        // The encrypted keys are not in order: trie log deserialization relies on it.
+        values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(values)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        mut batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        let old_value = self.remove_previous_keys(key, batch.as_deref_mut())?;
+        let stored_key = self.encrypt_key(key, self.current_key_id)?;
+        let stored_value = self.encrypt_value(key, value)?;
+        let current_value = self
+            .db
+            .insert(&same_column(key, &stored_key), &stored_value, batch)
+            .map_err(EncryptedDbError::Database)?;
+        match current_value {
+            Some(current_value) => self.decrypt_value(key, &current_value).map(Some),
+            None => Ok(old_value),
+        }
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        mut batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        let old_value = self.remove_previous_keys(key, batch.as_deref_mut())?;
+        let stored_key = self.encrypt_key(key, self.current_key_id)?;
+        let current_value = self
+            .db
+            .remove(&same_column(key, &stored_key), batch)
+            .map_err(EncryptedDbError::Database)?;
+        match current_value {
+            Some(current_value) => self.decrypt_value(key, &current_value).map(Some),
+            None => Ok(old_value),
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        if self.clear_len(prefix).is_none() {
+            return self
+                .db
+                .remove_by_prefix(prefix)
+                .map_err(EncryptedDbError::Database);
+        }
+        let mut batch = self.db.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.remove(&same_column(prefix, &key), Some(&mut batch))?;
+        }
+        self.db
+            .write_batch(batch)
+            .map_err(EncryptedDbError::Database)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.db
+            .write_batch(batch)
+            .map_err(EncryptedDbError::Database)
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.db.compact().map_err(EncryptedDbError::Database)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+impl<DB: BonsaiDatabase> EncryptedDb<DB> {
+    /// Removes the encryptions of `key` with the previous encryption keys, returns the value of
+    /// the last one found
+    fn remove_previous_keys(
+        &mut self,
+        key: &DatabaseKey,
+        mut batch: Option<&mut DB::Batch>,
+    ) -> Result<Option<ByteVec>, EncryptedDbError<DB::DatabaseError>> {
+        if self.clear_len(key).is_none() {
+            return Ok(None);
+        }
+        let mut old_value = None;
+        let previous_key_ids: Vec<_> = self.key_ids().skip(1).collect();
+        for key_id in previous_key_ids {
+            let stored_key = self.encrypt_key(key, key_id)?;
+            if let Some(value) = self
+                .db
+                .remove(&same_column(key, &stored_key), batch.as_deref_mut())
+                .map_err(EncryptedDbError::Database)?
+            {
+                old_value = Some(self.decrypt_value(key, &value)?);
+            }
+        }
+        Ok(old_value)
+    }
+}
+
+impl<ID, DB> BonsaiPersistentDatabase<ID> for EncryptedDb<DB>
+where
+    ID: Id,
+    DB: BonsaiPersistentDatabase<ID>,
+{
+    type Transaction<'a>
+        = EncryptedDb<DB::Transaction<'a>>
+    where
+        Self: 'a;
+    type DatabaseError = EncryptedDbError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError>;
+
+    fn snapshot(&mut self, id: ID) {
+        self.db.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.db.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.db.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let (snapshot_id, db) = self.db.transaction(id)?;
+        let transaction = EncryptedDb {
+            db,
+            ciphers: self.ciphers.clone(),
+            current_key_id: self.current_key_id,
+            config: self.config,
+        };
+        Some((snapshot_id, transaction))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.db
+            .merge(transaction.db)
+            .map_err(EncryptedDbError::Database)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DatabaseKey},
+    id::Id,
+    BonsaiDatabase, ByteVec, HashMap, Vec,
+};
+
+use super::overlay_db::{OverlayDbBatch, WriteSet};
+
+/// Copy-on-write view of a database, used by the forks created with
+/// [`crate::BonsaiStorage::fork`]. Reads go to the underlying database unless the key was written
+/// to, and writes are only kept in memory, see [`crate::databases::OverlayDb`] for an overlay
+/// owning its database.
+#[derive(Debug)]
+pub struct ForkDb<'db, DB: BonsaiDatabase> {
+    db: &'db DB,
+    writes: WriteSet,
+}
+
+impl<'db, DB: BonsaiDatabase> ForkDb<'db, DB> {
+    pub(crate) fn new(db: &'db DB) -> Self {
+        Self {
+            db,
+            writes: WriteSet::default(),
+        }
+    }
+
+    /// The leaf values and the metadata written to the fork, by database key.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn into_changes(
+        self,
+    ) -> (
+        HashMap<ByteVec, Option<ByteVec>>,
+        HashMap<ByteVec, Option<ByteVec>>,
+    ) {
+        self.writes.into_changes()
+    }
+
+    pub(crate) fn into_write_set(self) -> WriteSet {
+        self.writes
+    }
+}
+
+impl<DB: BonsaiDatabase> BonsaiDatabase for ForkDb<'_, DB> {
+    type Batch = OverlayDbBatch;
+    type DatabaseError = DB::DatabaseError;
+
+    fn create_batch(&self) -> Self::Batch {
+        OverlayDbBatch::default()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.get(self.db, key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        self.writes.get_many(self.db, keys)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        self.writes.get_by_prefix(self.db, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        self.writes.contains(self.db, key)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.write(self.db, key, Some(value), batch)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.write(self.db, key, None, batch)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        self.writes.remove_by_prefix(self.db, prefix)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.writes.extend(batch.0);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        log::debug!("{:?}", self);
+    }
+}
+
+impl<DB: BonsaiDatabase, ID: Id> BonsaiPersistentDatabase<ID> for ForkDb<'_, DB> {
+    type Transaction<'a>
+        = ForkDb<'a, DB>
+    where
+        Self: 'a;
+    type DatabaseError = DB::DatabaseError;
+
+    fn snapshot(&mut self, _id: ID) {
+        // Forks only live in memory and are not meant to outlive the storage they were created
+        // from, they don't keep snapshots.
+    }
+
+    fn transaction(&self, _id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        None
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.writes = transaction.writes;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::{error::Error as StdError, fmt};
+
+use log::trace;
+use tokio::runtime::Handle;
+use tonic::{
+    client::Grpc, codec::ProstCodec, codegen::http::uri::PathAndQuery, transport::Channel, Request,
+    Status,
+};
+
+use crate::{
+    bonsai_database::{DBError, DatabaseKey},
+    ByteVec,
+};
+
+use super::NodeFetcher;
+
+/// Path of the method called by a [`GrpcFetcher`].
+const FETCH_PATH: &str = "/bonsai.NodeFetcher/Fetch";
+
+/// Request of the `Fetch` method, see [`GrpcFetcher`].
+#[derive(Clone, PartialEq, prost::Message)]
+struct FetchRequest {
+    #[prost(uint32, tag = "1")]
+    key_type: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    key: Vec<u8>,
+}
+
+/// Response of the `Fetch` method, see [`GrpcFetcher`].
+#[derive(Clone, PartialEq, prost::Message)]
+struct FetchResponse {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    value: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub enum GrpcFetcherError {
+    Transport(tonic::transport::Error),
+    Status(Box<Status>),
+}
+
+impl fmt::Display for GrpcFetcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "gRPC transport error: {}", err),
+            Self::Status(status) => write!(f, "gRPC error: {}", status),
+        }
+    }
+}
+
+impl DBError for GrpcFetcherError {}
+
+impl StdError for GrpcFetcherError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err),
+            Self::Status(status) => Some(status.as_ref()),
+        }
+    }
+}
+
+/// [`NodeFetcher`] reading the trie nodes and leaves from a gRPC server implementing:
+///
+/// ```proto
+/// syntax = "proto3";
+/// package bonsai;
+///
+/// service NodeFetcher {
+///   rpc Fetch(FetchRequest) returns (FetchResponse);
+/// }
+///
+/// message FetchRequest {
+///   // 0 for a trie node, 1 for a leaf.
+///   uint32 key_type = 1;
+///   bytes key = 2;
+/// }
+///
+/// message FetchResponse {
+///   // Not set if the server doesn't have the key.
+///   optional bytes value = 1;
+/// }
+/// ```
+///
+/// The server should serve a fixed state, see [`NodeFetcher`]. The requests are sent by blocking
+/// on `runtime`, so the fetcher must not be used from an asynchronous context of that runtime.
+#[derive(Debug)]
+pub struct GrpcFetcher {
+    client: Grpc<Channel>,
+    runtime: Handle,
+}
+
+impl GrpcFetcher {
+    /// Creates a fetcher sending its requests on `channel`
+    pub fn new(channel: Channel, runtime: Handle) -> Self {
+        Self {
+            client: Grpc::new(channel),
+            runtime,
+        }
+    }
+}
+
+impl NodeFetcher for GrpcFetcher {
+    type Error = GrpcFetcherError;
+
+    fn fetch(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::Error> {
+        let (key_type, key) = match key {
+            DatabaseKey::Trie(key) => (0, key),
+            DatabaseKey::Flat(key) => (1, key),
+            DatabaseKey::TrieLog(_) | DatabaseKey::Meta(_) => return Ok(None),
+        };
+        trace!("Fetching {:?} over gRPC", key);
+        let request = FetchRequest {
+            key_type,
+            key: key.to_vec(),
+        };
+        let mut client = self.client.clone();
+        let response = self.runtime.block_on(async move {
+            client.ready().await.map_err(GrpcFetcherError::Transport)?;
+            client
+                .unary::<_, FetchResponse, _>(
+                    Request::new(request),
+                    PathAndQuery::from_static(FETCH_PATH),
+                    ProstCodec::default(),
+                )
+                .await
+                .map_err(|status| GrpcFetcherError::Status(Box::new(status)))
+        })?;
+        Ok(response.into_inner().value.map(Into::into))
+    }
+}
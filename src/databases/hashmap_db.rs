@@ -1,7 +1,7 @@
 use crate::{
     bonsai_database::{BonsaiPersistentDatabase, DBError},
     id::Id,
-    BTreeMap, BonsaiDatabase, HashMap, Vec,
+    Arc, BTreeMap, BonsaiDatabase, HashMap, Vec,
 };
 use crate::{ByteVec, DatabaseKey};
 use core::{fmt, fmt::Display};
@@ -20,27 +20,63 @@ impl Display for HashMapDbError {
 
 impl DBError for HashMapDbError {}
 
+/// In-memory database, mostly for tests. Each kind of [`DatabaseKey`] has its own map, as the
+/// column families of `RocksDB`, so the same bytes can be a key of each kind and prefix reads
+/// only return the keys of the kind asked for.
 #[derive(Clone, Default, Debug)]
 pub struct HashMapDb<ID: Id> {
     trie_db: HashMap<ByteVec, ByteVec>,
     flat_db: HashMap<ByteVec, ByteVec>,
     trie_log_db: HashMap<ByteVec, ByteVec>,
-    snapshots: BTreeMap<ID, HashMapDb<ID>>,
+    meta_db: HashMap<ByteVec, ByteVec>,
+    /// Snapshots are shared with the clones of this database, including the transactions.
+    snapshots: BTreeMap<ID, Arc<HashMapDb<ID>>>,
 }
 
+/// Column of a [`HashMapDb`] key, kept by [`HashMapDbBatch`] which cannot borrow the keys.
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    Trie,
+    Flat,
+    TrieLog,
+    Meta,
+}
+
+impl Column {
+    fn of(key: &DatabaseKey) -> Self {
+        match key {
+            DatabaseKey::Trie(_) => Column::Trie,
+            DatabaseKey::Flat(_) => Column::Flat,
+            DatabaseKey::TrieLog(_) => Column::TrieLog,
+            DatabaseKey::Meta(_) => Column::Meta,
+        }
+    }
+}
+
+/// Writes of a [`HashMapDb`] batch, applied in order by `write_batch`. `None` removes the key.
+#[derive(Debug, Default)]
+pub struct HashMapDbBatch(Vec<(Column, ByteVec, Option<ByteVec>)>);
+
 impl<ID: Id> HashMapDb<ID> {
     fn get_map(&self, key: &DatabaseKey) -> &HashMap<ByteVec, ByteVec> {
-        match key {
-            DatabaseKey::Trie(_) => &self.trie_db,
-            DatabaseKey::Flat(_) => &self.flat_db,
-            DatabaseKey::TrieLog(_) => &self.trie_log_db,
+        match Column::of(key) {
+            Column::Trie => &self.trie_db,
+            Column::Flat => &self.flat_db,
+            Column::TrieLog => &self.trie_log_db,
+            Column::Meta => &self.meta_db,
         }
     }
+
     fn get_map_mut(&mut self, key: &DatabaseKey) -> &mut HashMap<ByteVec, ByteVec> {
-        match key {
-            DatabaseKey::Trie(_) => &mut self.trie_db,
-            DatabaseKey::Flat(_) => &mut self.flat_db,
-            DatabaseKey::TrieLog(_) => &mut self.trie_log_db,
+        self.column_mut(Column::of(key))
+    }
+
+    fn column_mut(&mut self, column: Column) -> &mut HashMap<ByteVec, ByteVec> {
+        match column {
+            Column::Trie => &mut self.trie_db,
+            Column::Flat => &mut self.flat_db,
+            Column::TrieLog => &mut self.trie_log_db,
+            Column::Meta => &mut self.meta_db,
         }
     }
 
@@ -52,10 +88,12 @@ impl<ID: Id> HashMapDb<ID> {
 }
 
 impl<ID: Id> BonsaiDatabase for HashMapDb<ID> {
-    type Batch = ();
+    type Batch = HashMapDbBatch;
     type DatabaseError = HashMapDbError;
 
-    fn create_batch(&self) -> Self::Batch {}
+    fn create_batch(&self) -> Self::Batch {
+        HashMapDbBatch::default()
+    }
 
     fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
         let mut keys_to_remove = Vec::new();
@@ -87,24 +125,78 @@ impl<ID: Id> BonsaiDatabase for HashMapDb<ID> {
                 result.push((key.clone(), value.clone()));
             }
         }
+        // Same ordering as a prefix iteration in RocksDB: trie log deserialization relies on it.
+        result.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
         Ok(result)
     }
 
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        let mut keys: Vec<ByteVec> = self
+            .get_map(prefix)
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_slice()))
+            .cloned()
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
     fn insert(
         &mut self,
         key: &DatabaseKey,
         value: &[u8],
-        _batch: Option<&mut Self::Batch>,
+        batch: Option<&mut Self::Batch>,
     ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((Column::of(key), key.as_slice().into(), Some(value.into())));
+            return self.get(key);
+        }
         let db = self.get_map_mut(key);
         Ok(db.insert(key.as_slice().into(), value.into()))
     }
 
+    fn insert_many(
+        &mut self,
+        items: &[(DatabaseKey, &[u8])],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        if let Some(batch) = batch {
+            let old_values = items
+                .iter()
+                .map(|(key, _)| self.get_map(key).get(key.as_slice()).cloned())
+                .collect();
+            batch.0.extend(items.iter().map(|(key, value)| {
+                (
+                    Column::of(key),
+                    key.as_slice().into(),
+                    Some((*value).into()),
+                )
+            }));
+            return Ok(old_values);
+        }
+        Ok(items
+            .iter()
+            .map(|(key, value)| {
+                self.get_map_mut(key)
+                    .insert(key.as_slice().into(), (*value).into())
+            })
+            .collect())
+    }
+
     fn remove(
         &mut self,
         key: &DatabaseKey,
-        _batch: Option<&mut Self::Batch>,
+        batch: Option<&mut Self::Batch>,
     ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        if let Some(batch) = batch {
+            batch.0.push((Column::of(key), key.as_slice().into(), None));
+            return self.get(key);
+        }
         let db = self.get_map_mut(key);
         Ok(db.remove(key.as_slice()))
     }
@@ -114,7 +206,22 @@ impl<ID: Id> BonsaiDatabase for HashMapDb<ID> {
         Ok(db.contains_key(key.as_slice()))
     }
 
-    fn write_batch(&mut self, _batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for (column, key, value) in batch.0 {
+            let db = self.column_mut(column);
+            match value {
+                Some(value) => db.insert(key, value),
+                None => db.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.trie_db.shrink_to_fit();
+        self.flat_db.shrink_to_fit();
+        self.trie_log_db.shrink_to_fit();
+        self.meta_db.shrink_to_fit();
         Ok(())
     }
 
@@ -126,16 +233,43 @@ impl<ID: Id> BonsaiDatabase for HashMapDb<ID> {
 
 impl<ID: Id> BonsaiPersistentDatabase<ID> for HashMapDb<ID> {
     type DatabaseError = HashMapDbError;
-    type Transaction<'a> = HashMapDb<ID> where ID: 'a;
+    type Transaction<'a>
+        = HashMapDb<ID>
+    where
+        ID: 'a;
     fn snapshot(&mut self, id: ID) {
-        self.snapshots.insert(id, self.clone());
+        // Snapshots never have snapshots of their own, otherwise every snapshot would also
+        // copy all of the previous ones.
+        let snapshot = HashMapDb {
+            trie_db: self.trie_db.clone(),
+            flat_db: self.flat_db.clone(),
+            trie_log_db: self.trie_log_db.clone(),
+            meta_db: self.meta_db.clone(),
+            snapshots: BTreeMap::new(),
+        };
+        self.snapshots.insert(id, Arc::new(snapshot));
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.snapshots.remove(&id).is_some()
     }
 
     fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        // Snapshots are left untouched: the transaction works on its own copy of the closest
+        // snapshot taken at or before `id`. That snapshot is kept in the transaction so that
+        // transactions can be created from it in turn.
         self.snapshots
-            .range(..&id)
-            .next()
-            .map(|(id, snapshot)| (*id, snapshot.clone()))
+            .range(..=id)
+            .next_back()
+            .map(|(id, snapshot)| {
+                let mut txn = HashMapDb::clone(snapshot);
+                txn.snapshots.insert(*id, Arc::clone(snapshot));
+                (*id, txn)
+            })
     }
 
     fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
@@ -145,6 +279,7 @@ impl<ID: Id> BonsaiPersistentDatabase<ID> for HashMapDb<ID> {
         self.trie_db = transaction.trie_db;
         self.flat_db = transaction.flat_db;
         self.trie_log_db = transaction.trie_log_db;
+        self.meta_db = transaction.meta_db;
         Ok(())
     }
 }
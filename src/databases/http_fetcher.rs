@@ -0,0 +1,89 @@
+use std::{error::Error as StdError, fmt, io, io::Read};
+
+use log::trace;
+use ureq::Agent;
+
+use crate::{
+    bonsai_database::{DBError, DatabaseKey},
+    ByteVec,
+};
+
+use super::NodeFetcher;
+
+#[derive(Debug)]
+pub enum HttpFetcherError {
+    Http(Box<ureq::Error>),
+    Io(io::Error),
+}
+
+impl fmt::Display for HttpFetcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(err) => write!(f, "HTTP error: {}", err),
+            Self::Io(err) => write!(f, "HTTP io error: {}", err),
+        }
+    }
+}
+
+impl DBError for HttpFetcherError {}
+
+impl StdError for HttpFetcherError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Http(err) => Some(err.as_ref()),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+/// [`NodeFetcher`] reading the trie nodes and leaves from an HTTP server. The value at a key is
+/// the body of the response to `GET <url>/trie/<hex encoded key>`, or `GET <url>/flat/<hex
+/// encoded key>` for the leaves, and a `404 Not Found` response means the server doesn't have it.
+///
+/// The url should designate the state to serve, such as a block number, see [`NodeFetcher`].
+#[derive(Debug)]
+pub struct HttpFetcher {
+    agent: Agent,
+    url: String,
+}
+
+impl HttpFetcher {
+    /// Creates a fetcher sending its requests under `url`, without trailing slash
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_agent(Agent::new(), url)
+    }
+
+    /// Creates a fetcher sending its requests with `agent`, to configure timeouts or proxies
+    pub fn with_agent(agent: Agent, url: impl Into<String>) -> Self {
+        Self {
+            agent,
+            url: url.into(),
+        }
+    }
+}
+
+impl NodeFetcher for HttpFetcher {
+    type Error = HttpFetcherError;
+
+    fn fetch(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::Error> {
+        let (column, key) = match key {
+            DatabaseKey::Trie(key) => ("trie", key),
+            DatabaseKey::Flat(key) => ("flat", key),
+            DatabaseKey::TrieLog(_) | DatabaseKey::Meta(_) => return Ok(None),
+        };
+        let name: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+        let url = format!("{}/{column}/{name}", self.url);
+        trace!("Fetching {}", url);
+        let response = match self.agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(err) => return Err(HttpFetcherError::Http(Box::new(err))),
+        };
+        let mut value = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut value)
+            .map_err(HttpFetcherError::Io)?;
+        Ok(Some(value.into()))
+    }
+}
@@ -0,0 +1,466 @@
+use std::{borrow::Cow, collections::BTreeMap, error::Error as StdError, fmt, io, path::Path};
+
+use libmdbx::{
+    Database, Error, NoWriteMap, Table, TableFlags, Transaction, TransactionKind, WriteFlags, RO,
+    RW,
+};
+use log::trace;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    ByteVec,
+};
+
+const TRIE_TABLE: &str = "trie";
+const FLAT_TABLE: &str = "flat";
+const TRIE_LOG_TABLE: &str = "trie_log";
+const META_TABLE: &str = "meta";
+
+/// The tables of an [`MdbxDb`], indexed by [`table_index`].
+const TABLES: [&str; 4] = [TRIE_TABLE, FLAT_TABLE, TRIE_LOG_TABLE, META_TABLE];
+
+fn table_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+        DatabaseKey::Meta(_) => 3,
+    }
+}
+
+/// Opens the MDBX database in the directory `path`, creating it if it does not exist, along with
+/// the tables used by [`MdbxDb`].
+pub fn open_mdbx_db(path: impl AsRef<Path>) -> Result<Database<NoWriteMap>, MdbxDbError> {
+    std::fs::create_dir_all(path.as_ref())?;
+    let db = Database::<NoWriteMap>::new()
+        .set_max_tables(TABLES.len())
+        .open(path.as_ref())?;
+    let txn = db.begin_rw_txn()?;
+    for table in TABLES {
+        txn.create_table(Some(table), TableFlags::empty())?;
+    }
+    txn.commit()?;
+    Ok(db)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using MDBX as the underlying database.
+///
+/// The whole database is a single memory-mapped file. The snapshots are read-only MDBX
+/// transactions, which see the database as it was when they were taken. As long as a snapshot
+/// is kept, MDBX can't reuse the pages it sees, so the file grows with the changes made since the
+/// oldest snapshot: [`MdbxDbConfig::max_saved_snapshots`] should stay low. Each snapshot also
+/// takes a reader slot of the database.
+pub struct MdbxDb<'db, ID: Id> {
+    db: &'db Database<NoWriteMap>,
+    config: MdbxDbConfig,
+    snapshots: BTreeMap<ID, Transaction<'db, RO, NoWriteMap>>,
+}
+
+impl<ID: Id> fmt::Debug for MdbxDb<'_, ID> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MdbxDb")
+            .field("config", &self.config)
+            .field("snapshots", &self.snapshots.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Configuration for the MDBX database
+#[derive(Debug, Clone)]
+pub struct MdbxDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for MdbxDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(16),
+        }
+    }
+}
+
+impl<'db, ID: Id> MdbxDb<'db, ID> {
+    /// Creates a new MDBX wrapper from the given database, opened with [`open_mdbx_db`]
+    pub fn new(db: &'db Database<NoWriteMap>, config: MdbxDbConfig) -> Self {
+        trace!("MDBX database opened");
+        Self {
+            db,
+            config,
+            snapshots: BTreeMap::default(),
+        }
+    }
+}
+
+/// A batch of changes to an [`MdbxDb`], written in a single read-write transaction. `None` values
+/// are removals.
+#[derive(Debug, Default)]
+pub struct MdbxBatch(Vec<(usize, ByteVec, Option<ByteVec>)>);
+
+#[derive(Debug)]
+pub enum MdbxDbError {
+    Mdbx(Error),
+    Io(io::Error),
+}
+
+impl From<Error> for MdbxDbError {
+    fn from(err: Error) -> Self {
+        Self::Mdbx(err)
+    }
+}
+
+impl From<io::Error> for MdbxDbError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl fmt::Display for MdbxDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mdbx(err) => write!(f, "MDBX error: {}", err),
+            Self::Io(err) => write!(f, "MDBX io error: {}", err),
+        }
+    }
+}
+
+impl DBError for MdbxDbError {}
+
+impl StdError for MdbxDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Mdbx(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+fn read<K: TransactionKind>(
+    txn: &Transaction<'_, K, NoWriteMap>,
+    key: &DatabaseKey,
+) -> Result<Option<ByteVec>, MdbxDbError> {
+    let table = txn.open_table(Some(TABLES[table_index(key)]))?;
+    Ok(txn
+        .get::<Cow<[u8]>>(&table, key.as_slice())?
+        .map(|value| value.as_ref().into()))
+}
+
+fn read_prefix<K: TransactionKind>(
+    txn: &Transaction<'_, K, NoWriteMap>,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(ByteVec, ByteVec)>, MdbxDbError> {
+    let table = txn.open_table(Some(TABLES[table_index(prefix)]))?;
+    let mut cursor = txn.cursor(&table)?;
+    let mut entries = Vec::new();
+    for entry in cursor.iter_from::<Cow<[u8]>, Cow<[u8]>>(prefix.as_slice()) {
+        let (key, value) = entry?;
+        if !key.starts_with(prefix.as_slice()) {
+            break;
+        }
+        entries.push((key.as_ref().into(), value.as_ref().into()));
+    }
+    Ok(entries)
+}
+
+fn open_tables<'txn>(
+    txn: &'txn Transaction<'_, RW, NoWriteMap>,
+) -> Result<Vec<Table<'txn>>, MdbxDbError> {
+    TABLES
+        .iter()
+        .map(|table| Ok(txn.open_table(Some(table))?))
+        .collect()
+}
+
+/// Write `changes` to `db` in a single read-write transaction.
+fn write_changes<'a>(
+    db: &Database<NoWriteMap>,
+    changes: impl IntoIterator<Item = (usize, &'a [u8], Option<&'a [u8]>)>,
+) -> Result<(), MdbxDbError> {
+    let txn = db.begin_rw_txn()?;
+    {
+        let tables = open_tables(&txn)?;
+        for (table, key, value) in changes {
+            match value {
+                Some(value) => txn.put(&tables[table], key, value, WriteFlags::empty())?,
+                None => {
+                    txn.del(&tables[table], key, None)?;
+                }
+            }
+        }
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+impl<ID: Id> BonsaiDatabase for MdbxDb<'_, ID> {
+    type Batch = MdbxBatch;
+    type DatabaseError = MdbxDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        println!("{:?}", self)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Inserting into MDBX: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((table_index(key), key.as_slice().into(), Some(value.into())));
+        } else {
+            write_changes(self.db, [(table_index(key), key.as_slice(), Some(value))])?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Getting from MDBX: {:?}", key);
+        read(&self.db.begin_ro_txn()?, key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Getting {} keys from MDBX", keys.len());
+        let txn = self.db.begin_ro_txn()?;
+        keys.iter().map(|key| read(&txn, key)).collect()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        trace!("Getting from MDBX: {:?}", prefix);
+        read_prefix(&self.db.begin_ro_txn()?, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if MDBX contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Removing from MDBX: {:?}", key);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((table_index(key), key.as_slice().into(), None));
+        } else {
+            write_changes(self.db, [(table_index(key), key.as_slice(), None)])?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from MDBX: {:?}", prefix);
+        let table = table_index(prefix);
+        let keys = self.get_by_prefix(prefix)?;
+        write_changes(
+            self.db,
+            keys.iter().map(|(key, _)| (table, key.as_slice(), None)),
+        )
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        write_changes(
+            self.db,
+            batch
+                .0
+                .iter()
+                .map(|(table, key, value)| (*table, key.as_slice(), value.as_deref())),
+        )
+    }
+}
+
+/// Transaction on a snapshot of an [`MdbxDb`]. It reads the snapshot, and keeps its own changes
+/// in memory until it is merged, when they are written to the database in a single read-write
+/// transaction.
+pub struct MdbxTransaction<'a> {
+    db: &'a Database<NoWriteMap>,
+    snapshot: &'a Transaction<'a, RO, NoWriteMap>,
+    changes: BTreeMap<(usize, ByteVec), Option<ByteVec>>,
+}
+
+impl fmt::Debug for MdbxTransaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MdbxTransaction")
+            .field("changes", &self.changes.len())
+            .finish()
+    }
+}
+
+impl MdbxTransaction<'_> {
+    fn change(&self, key: &DatabaseKey) -> Option<&Option<ByteVec>> {
+        self.changes
+            .get(&(table_index(key), ByteVec::from(key.as_slice())))
+    }
+}
+
+impl BonsaiDatabase for MdbxTransaction<'_> {
+    type Batch = MdbxBatch;
+    type DatabaseError = MdbxDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        println!("{:?}", self)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Inserting into MDBX transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        let change = (table_index(key), key.as_slice().into(), Some(value.into()));
+        if let Some(batch) = batch {
+            batch.0.push(change);
+        } else {
+            self.write_batch(MdbxBatch(vec![change]))?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Getting from MDBX transaction: {:?}", key);
+        match self.change(key) {
+            Some(value) => Ok(value.clone()),
+            None => read(self.snapshot, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        trace!("Getting from MDBX transaction: {:?}", prefix);
+        let table = table_index(prefix);
+        let mut entries: BTreeMap<ByteVec, ByteVec> =
+            read_prefix(self.snapshot, prefix)?.into_iter().collect();
+        let changes = self
+            .changes
+            .range((table, ByteVec::from(prefix.as_slice()))..)
+            .take_while(|((change_table, key), _)| {
+                *change_table == table && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if MDBX transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Removing from MDBX transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        let change = (table_index(key), key.as_slice().into(), None);
+        if let Some(batch) = batch {
+            batch.0.push(change);
+        } else {
+            self.write_batch(MdbxBatch(vec![change]))?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from MDBX transaction: {:?}", prefix);
+        let table = table_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((table, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for (table, key, value) in batch.0 {
+            self.changes.insert((table, key), value);
+        }
+        Ok(())
+    }
+}
+
+impl<'db, ID: Id> BonsaiPersistentDatabase<ID> for MdbxDb<'db, ID> {
+    type Transaction<'a>
+        = MdbxTransaction<'a>
+    where
+        Self: 'a;
+    type DatabaseError = MdbxDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating MDBX snapshot");
+        let snapshot = self
+            .db
+            .begin_ro_txn()
+            .expect("critical: failed to begin the MDBX snapshot transaction");
+        self.snapshots.insert(id, snapshot);
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.snapshots.remove(&id).is_some()
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        trace!("Generating MDBX transaction");
+        let (id, snapshot) = self.snapshots.range(..=id).next_back()?;
+        Some((
+            *id,
+            MdbxTransaction {
+                db: self.db,
+                snapshot,
+                changes: BTreeMap::new(),
+            },
+        ))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        write_changes(
+            transaction.db,
+            transaction
+                .changes
+                .iter()
+                .map(|((table, key), value)| (*table, key.as_slice(), value.as_deref())),
+        )
+    }
+}
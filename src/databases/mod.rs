@@ -1,9 +1,73 @@
 #![allow(dead_code)]
+mod fork_db;
+pub use fork_db::ForkDb;
+
+mod overlay_db;
+pub use overlay_db::{OverlayDb, OverlayDbBatch};
+
 mod hashmap_db;
-pub use hashmap_db::HashMapDb;
+pub use hashmap_db::{HashMapDb, HashMapDbBatch};
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+mod compressed_db;
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+pub use compressed_db::{CompressedDb, CompressedDbConfig, CompressedDbError, Compression};
+
+#[cfg(feature = "encryption")]
+mod encrypted_db;
+#[cfg(feature = "encryption")]
+pub use encrypted_db::{EncryptedDb, EncryptedDbConfig, EncryptedDbError, KeyEncryption};
+
+mod dedup_db;
+pub use dedup_db::{DedupDb, DedupDbBatch, DedupDbError};
+
+#[cfg(feature = "std")]
+mod witness_db;
+#[cfg(feature = "std")]
+pub use witness_db::{WitnessCoverage, WitnessDb};
+
+#[cfg(feature = "std")]
+mod remote_db;
+#[cfg(feature = "std")]
+pub use remote_db::{NodeFetcher, RemoteDb, RemoteDbError};
+
+#[cfg(feature = "remote_http")]
+mod http_fetcher;
+#[cfg(feature = "remote_http")]
+pub use http_fetcher::{HttpFetcher, HttpFetcherError};
+
+#[cfg(feature = "remote_grpc")]
+mod grpc_fetcher;
+#[cfg(feature = "remote_grpc")]
+pub use grpc_fetcher::{GrpcFetcher, GrpcFetcherError};
+
+mod tiered_db;
+pub use tiered_db::{TieredDatabase, TieredDatabaseBatch, TieredDatabaseError};
 
 #[cfg(feature = "rocksdb")]
 mod rocks_db;
 
 #[cfg(feature = "rocksdb")]
-pub use rocks_db::{create_rocks_db, RocksDB, RocksDBBatch, RocksDBConfig, RocksDBTransaction};
+pub use rocks_db::{
+    create_rocks_db, open_rocks_db, RocksDB, RocksDBBatch, RocksDBConfig, RocksDBTransaction,
+};
+
+#[cfg(feature = "mdbx")]
+mod mdbx_db;
+
+#[cfg(feature = "mdbx")]
+pub use mdbx_db::{open_mdbx_db, MdbxBatch, MdbxDb, MdbxDbConfig, MdbxDbError, MdbxTransaction};
+
+#[cfg(feature = "redb")]
+mod redb_db;
+
+#[cfg(feature = "redb")]
+pub use redb_db::{open_redb_db, RedbBatch, RedbDb, RedbDbConfig, RedbDbError, RedbTransaction};
+
+#[cfg(feature = "object_store")]
+mod object_store_db;
+
+#[cfg(feature = "object_store")]
+pub use object_store_db::{
+    ObjectStoreDb, ObjectStoreDbBatch, ObjectStoreDbConfig, ObjectStoreDbError,
+};
@@ -0,0 +1,522 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    error::Error as StdError,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use object_store::{path::Path, ObjectStore, PutPayload};
+use tokio::runtime::Handle;
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    ByteVec,
+};
+use log::trace;
+
+#[derive(Debug)]
+pub enum ObjectStoreDbError {
+    ObjectStore(object_store::Error),
+    /// An object of the database has a name which is not a hex encoded key.
+    InvalidObjectName(Path),
+}
+
+impl From<object_store::Error> for ObjectStoreDbError {
+    fn from(err: object_store::Error) -> Self {
+        Self::ObjectStore(err)
+    }
+}
+
+impl fmt::Display for ObjectStoreDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ObjectStore(err) => write!(f, "Object store error: {}", err),
+            Self::InvalidObjectName(path) => write!(f, "Invalid object name: {}", path),
+        }
+    }
+}
+
+impl DBError for ObjectStoreDbError {}
+
+impl StdError for ObjectStoreDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::ObjectStore(err) => Some(err),
+            Self::InvalidObjectName(_) => None,
+        }
+    }
+}
+
+/// Column of an [`ObjectStoreDb`] key, stored under a directory of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Column {
+    Trie,
+    Flat,
+    TrieLog,
+    Meta,
+}
+
+impl Column {
+    fn of(key: &DatabaseKey) -> Self {
+        match key {
+            DatabaseKey::Trie(_) => Column::Trie,
+            DatabaseKey::Flat(_) => Column::Flat,
+            DatabaseKey::TrieLog(_) => Column::TrieLog,
+            DatabaseKey::Meta(_) => Column::Meta,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Trie => "trie",
+            Column::Flat => "flat",
+            Column::TrieLog => "trie_log",
+            Column::Meta => "meta",
+        }
+    }
+}
+
+/// Writes of an [`ObjectStoreDb`] batch, applied in order by `write_batch`. `None` removes the key.
+#[derive(Debug, Default)]
+pub struct ObjectStoreDbBatch(Vec<(Column, ByteVec, Option<ByteVec>)>);
+
+/// Configuration for the object store database
+#[derive(Debug, Clone)]
+pub struct ObjectStoreDbConfig {
+    /// Number of writes kept in memory before they are uploaded, `None` to only upload them on
+    /// [`ObjectStoreDb::flush`]
+    pub max_pending_writes: Option<usize>,
+    /// Maximum number of values kept in memory after they were read or uploaded
+    pub read_cache_size: usize,
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for ObjectStoreDbConfig {
+    fn default() -> Self {
+        Self {
+            max_pending_writes: Some(1024),
+            read_cache_size: 4096,
+            max_saved_snapshots: Some(100),
+        }
+    }
+}
+
+/// Values read from the object store, evicted in insertion order. `None` caches a missing key.
+#[derive(Debug, Default)]
+struct ReadCache {
+    values: HashMap<(Column, ByteVec), Option<ByteVec>>,
+    order: VecDeque<(Column, ByteVec)>,
+}
+
+impl ReadCache {
+    fn insert(&mut self, key: (Column, ByteVec), value: Option<ByteVec>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        if self.values.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > capacity {
+            if let Some(key) = self.order.pop_front() {
+                self.values.remove(&key);
+            }
+        }
+    }
+}
+
+/// Read-mostly database storing each key in its own object of an [`ObjectStore`], such as S3,
+/// under `<root>/<column>/<hex encoded key>`. It is meant as the cold tier of a
+/// [`super::TieredDatabase`], keeping the trie logs of archive nodes in cheap storage.
+///
+/// Writes are kept in memory and uploaded once there are
+/// [`ObjectStoreDbConfig::max_pending_writes`] of them, or by [`ObjectStoreDb::flush`], which
+/// should be called before the database is dropped: pending writes are lost otherwise. The
+/// database must be the only writer of its objects, the values it read are cached.
+///
+/// The object store is called by blocking on `runtime`, so the database must not be used from
+/// an asynchronous context of that runtime. Prefix reads list the whole column.
+///
+/// A snapshot keeps in memory the previous value of the keys written after it, so that the
+/// transactions read the values at their snapshot. The snapshots are not persisted. The writes of
+/// a transaction are only uploaded once merged.
+#[derive(Debug)]
+pub struct ObjectStoreDb<ID: Id> {
+    store: Arc<dyn ObjectStore>,
+    root: Path,
+    runtime: Handle,
+    config: ObjectStoreDbConfig,
+    pending_writes: BTreeMap<(Column, ByteVec), Option<ByteVec>>,
+    read_cache: Mutex<ReadCache>,
+    /// Value of the keys at each snapshot, saved when they are first written after it while it
+    /// is the latest one. Shared with the transactions.
+    snapshots: Arc<Mutex<BTreeMap<ID, SnapshotValues>>>,
+    /// Snapshot read by a transaction, `None` for the database.
+    snapshot: Option<ID>,
+    /// Pending writes of the database when the transaction was created.
+    base_writes: BTreeMap<(Column, ByteVec), Option<ByteVec>>,
+}
+
+type SnapshotValues = HashMap<(Column, ByteVec), Option<ByteVec>>;
+
+impl<ID: Id> ObjectStoreDb<ID> {
+    /// Creates a database storing its objects under `root` in `store`
+    pub fn new(
+        store: Arc<dyn ObjectStore>,
+        root: Path,
+        runtime: Handle,
+        config: ObjectStoreDbConfig,
+    ) -> Self {
+        Self {
+            store,
+            root,
+            runtime,
+            config,
+            pending_writes: BTreeMap::new(),
+            read_cache: Mutex::default(),
+            snapshots: Arc::default(),
+            snapshot: None,
+            base_writes: BTreeMap::new(),
+        }
+    }
+
+    /// Number of writes not uploaded yet
+    pub fn pending_writes(&self) -> usize {
+        self.pending_writes.len()
+    }
+
+    /// Uploads the pending writes. Does nothing on a transaction, whose writes are uploaded once
+    /// merged in the database.
+    pub fn flush(&mut self) -> Result<(), ObjectStoreDbError> {
+        if self.snapshot.is_some() {
+            return Ok(());
+        }
+        trace!("Uploading {} writes", self.pending_writes.len());
+        while let Some(((column, key), value)) = self.pending_writes.pop_first() {
+            let path = self.object_path(column, &key);
+            let store = &self.store;
+            let result = self.runtime.block_on(async {
+                match &value {
+                    Some(value) => store
+                        .put(&path, PutPayload::from(value.to_vec()))
+                        .await
+                        .map(|_| ()),
+                    None => match store.delete(&path).await {
+                        Err(object_store::Error::NotFound { .. }) => Ok(()),
+                        result => result,
+                    },
+                }
+            });
+            if let Err(err) = result {
+                // Keep the write so that the next flush retries it.
+                self.pending_writes.insert((column, key), value);
+                return Err(err.into());
+            }
+            self.cache_value((column, key), value);
+        }
+        Ok(())
+    }
+
+    fn flush_if_needed(&mut self) -> Result<(), ObjectStoreDbError> {
+        if self
+            .config
+            .max_pending_writes
+            .is_some_and(|max| self.pending_writes.len() >= max)
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn column_path(&self, column: Column) -> Path {
+        self.root.child(column.name())
+    }
+
+    fn object_path(&self, column: Column, key: &[u8]) -> Path {
+        let name: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.column_path(column).child(name)
+    }
+
+    fn cache_value(&self, key: (Column, ByteVec), value: Option<ByteVec>) {
+        self.read_cache.lock().expect("poisoned read cache").insert(
+            key,
+            value,
+            self.config.read_cache_size,
+        );
+    }
+
+    fn read(&self, column: Column, key: &[u8]) -> Result<Option<ByteVec>, ObjectStoreDbError> {
+        let cache_key = (column, ByteVec::from(key));
+        if let Some(value) = self.pending_writes.get(&cache_key) {
+            return Ok(value.clone());
+        }
+        if let Some(snapshot) = self.snapshot {
+            let snapshots = self.snapshots.lock().expect("poisoned snapshots");
+            let saved = snapshots
+                .range(snapshot..)
+                .find_map(|(_, values)| values.get(&cache_key));
+            if let Some(value) = saved {
+                return Ok(value.clone());
+            }
+        }
+        if let Some(value) = self.base_writes.get(&cache_key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = self
+            .read_cache
+            .lock()
+            .expect("poisoned read cache")
+            .values
+            .get(&cache_key)
+        {
+            return Ok(value.clone());
+        }
+        let path = self.object_path(column, key);
+        let store = &self.store;
+        let value = self.runtime.block_on(async {
+            match store.get(&path).await {
+                Ok(result) => Ok(Some(ByteVec::from(result.bytes().await?.as_ref()))),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(err) => Err(err),
+            }
+        })?;
+        self.cache_value(cache_key, value.clone());
+        Ok(value)
+    }
+
+    /// Keys of the uploaded objects of `column` starting with `prefix`
+    fn list(&self, column: Column, prefix: &[u8]) -> Result<Vec<ByteVec>, ObjectStoreDbError> {
+        let column_path = self.column_path(column);
+        let objects = self
+            .runtime
+            .block_on(self.store.list_with_delimiter(Some(&column_path)))?
+            .objects;
+        let mut keys = Vec::new();
+        for object in objects {
+            let key = object
+                .location
+                .filename()
+                .and_then(decode_hex)
+                .ok_or_else(|| ObjectStoreDbError::InvalidObjectName(object.location.clone()))?;
+            if key.starts_with(prefix) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Write `value` to `key`, saving its previous value for the latest snapshot if it is the
+    /// first write to it since.
+    fn write(
+        &mut self,
+        column: Column,
+        key: ByteVec,
+        value: Option<ByteVec>,
+    ) -> Result<(), ObjectStoreDbError> {
+        let key = (column, key);
+        if self.snapshot.is_none() {
+            let unsaved = self
+                .snapshots
+                .lock()
+                .expect("poisoned snapshots")
+                .last_key_value()
+                .is_some_and(|(_, values)| !values.contains_key(&key));
+            if unsaved {
+                let previous = self.read(column, &key.1)?;
+                let mut snapshots = self.snapshots.lock().expect("poisoned snapshots");
+                if let Some(mut latest) = snapshots.last_entry() {
+                    latest.get_mut().insert(key.clone(), previous);
+                }
+            }
+        }
+        self.pending_writes.insert(key, value);
+        Ok(())
+    }
+}
+
+fn decode_hex(name: &str) -> Option<ByteVec> {
+    if name.len() % 2 != 0 {
+        return None;
+    }
+    (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl<ID: Id> BonsaiDatabase for ObjectStoreDb<ID> {
+    type Batch = ObjectStoreDbBatch;
+    type DatabaseError = ObjectStoreDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        ObjectStoreDbBatch::default()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Getting from object store: {:?}", key);
+        self.read(Column::of(key), key.as_slice())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        trace!("Getting by prefix from object store: {:?}", prefix);
+        let column = Column::of(prefix);
+        let mut keys: BTreeSet<ByteVec> =
+            self.list(column, prefix.as_slice())?.into_iter().collect();
+        let in_prefix = |(write_column, key): &(Column, ByteVec)| {
+            *write_column == column && key.starts_with(prefix.as_slice())
+        };
+        keys.extend(
+            self.pending_writes
+                .keys()
+                .chain(self.base_writes.keys())
+                .filter(|key| in_prefix(key))
+                .map(|(_, key)| key.clone()),
+        );
+        if let Some(snapshot) = self.snapshot {
+            let snapshots = self.snapshots.lock().expect("poisoned snapshots");
+            for (_, values) in snapshots.range(snapshot..) {
+                keys.extend(
+                    values
+                        .keys()
+                        .filter(|key| in_prefix(key))
+                        .map(|(_, key)| key.clone()),
+                );
+            }
+        }
+        // Same ordering as a prefix iteration in RocksDB: trie log deserialization relies on it.
+        let mut values = Vec::new();
+        for key in keys {
+            if let Some(value) = self.read(column, &key)? {
+                values.push((key, value));
+            }
+        }
+        Ok(values)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Inserting into object store: {:?}", key);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((Column::of(key), key.as_slice().into(), Some(value.into())));
+        } else {
+            self.write(Column::of(key), key.as_slice().into(), Some(value.into()))?;
+            self.flush_if_needed()?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Removing from object store: {:?}", key);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch.0.push((Column::of(key), key.as_slice().into(), None));
+        } else {
+            self.write(Column::of(key), key.as_slice().into(), None)?;
+            self.flush_if_needed()?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        let column = Column::of(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.write(column, key, None)?;
+        }
+        self.flush_if_needed()
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for (column, key, value) in batch.0 {
+            self.write(column, key, value)?;
+        }
+        self.flush_if_needed()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        log::debug!("{:?}", self);
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for ObjectStoreDb<ID> {
+    type Transaction<'a>
+        = ObjectStoreDb<ID>
+    where
+        Self: 'a;
+    type DatabaseError = ObjectStoreDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        let mut snapshots = self.snapshots.lock().expect("poisoned snapshots");
+        snapshots.entry(id).or_default();
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while snapshots.len() > max_number_snapshot {
+                snapshots.pop_first();
+            }
+        }
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        let snapshots = self.snapshots.lock().expect("poisoned snapshots");
+        snapshots.keys().copied().collect()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        let mut snapshots = self.snapshots.lock().expect("poisoned snapshots");
+        let Some(values) = snapshots.remove(&id) else {
+            return false;
+        };
+        // The previous snapshot had the same values for the keys it didn't save.
+        if let Some((_, previous)) = snapshots.range_mut(..id).next_back() {
+            for (key, value) in values {
+                previous.entry(key).or_insert(value);
+            }
+        }
+        true
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let snapshots = self.snapshots.lock().expect("poisoned snapshots");
+        let snapshot_id = *snapshots.range(..=id).next_back()?.0;
+        let transaction = ObjectStoreDb {
+            store: Arc::clone(&self.store),
+            root: self.root.clone(),
+            runtime: self.runtime.clone(),
+            config: self.config.clone(),
+            pending_writes: BTreeMap::new(),
+            read_cache: Mutex::default(),
+            snapshots: Arc::clone(&self.snapshots),
+            snapshot: Some(snapshot_id),
+            base_writes: self.pending_writes.clone(),
+        };
+        Some((snapshot_id, transaction))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        for ((column, key), value) in transaction.pending_writes {
+            self.write(column, key, value)?;
+        }
+        self.flush_if_needed()
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DatabaseKey},
+    id::Id,
+    BonsaiDatabase, ByteVec, HashMap, Vec,
+};
+
+use super::ForkDb;
+
+/// Writes kept in memory over a base database, by kind of key: `None` marks a removed key. The
+/// reads go to the base database unless the key was written to.
+#[derive(Debug, Default)]
+pub(crate) struct WriteSet {
+    trie_db: HashMap<ByteVec, Option<ByteVec>>,
+    flat_db: HashMap<ByteVec, Option<ByteVec>>,
+    trie_log_db: HashMap<ByteVec, Option<ByteVec>>,
+    meta_db: HashMap<ByteVec, Option<ByteVec>>,
+}
+
+impl WriteSet {
+    fn get_map(&self, key: &DatabaseKey) -> &HashMap<ByteVec, Option<ByteVec>> {
+        match key {
+            DatabaseKey::Trie(_) => &self.trie_db,
+            DatabaseKey::Flat(_) => &self.flat_db,
+            DatabaseKey::TrieLog(_) => &self.trie_log_db,
+            DatabaseKey::Meta(_) => &self.meta_db,
+        }
+    }
+
+    fn get_map_mut(&mut self, key: &DatabaseKey) -> &mut HashMap<ByteVec, Option<ByteVec>> {
+        match key {
+            DatabaseKey::Trie(_) => &mut self.trie_db,
+            DatabaseKey::Flat(_) => &mut self.flat_db,
+            DatabaseKey::TrieLog(_) => &mut self.trie_log_db,
+            DatabaseKey::Meta(_) => &mut self.meta_db,
+        }
+    }
+
+    /// The leaf values and the metadata written, by database key.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn into_changes(
+        self,
+    ) -> (
+        HashMap<ByteVec, Option<ByteVec>>,
+        HashMap<ByteVec, Option<ByteVec>>,
+    ) {
+        (self.flat_db, self.meta_db)
+    }
+
+    /// Add the writes of `other`, which take precedence.
+    pub(crate) fn extend(&mut self, other: WriteSet) {
+        self.trie_db.extend(other.trie_db);
+        self.flat_db.extend(other.flat_db);
+        self.trie_log_db.extend(other.trie_log_db);
+        self.meta_db.extend(other.meta_db);
+    }
+
+    /// Every write, with the key it was written to.
+    fn changes(&self) -> impl Iterator<Item = (DatabaseKey<'_>, Option<&[u8]>)> {
+        fn column<'a>(
+            map: &'a HashMap<ByteVec, Option<ByteVec>>,
+            kind: fn(&'a [u8]) -> DatabaseKey<'a>,
+        ) -> impl Iterator<Item = (DatabaseKey<'a>, Option<&'a [u8]>)> {
+            map.iter()
+                .map(move |(key, value)| (kind(key.as_slice()), value.as_deref()))
+        }
+        column(&self.trie_db, DatabaseKey::Trie)
+            .chain(column(&self.flat_db, DatabaseKey::Flat))
+            .chain(column(&self.trie_log_db, DatabaseKey::TrieLog))
+            .chain(column(&self.meta_db, DatabaseKey::Meta))
+    }
+
+    pub(crate) fn get<DB: BonsaiDatabase>(
+        &self,
+        base: &DB,
+        key: &DatabaseKey,
+    ) -> Result<Option<ByteVec>, DB::DatabaseError> {
+        match self.get_map(key).get(key.as_slice()) {
+            Some(value) => Ok(value.clone()),
+            None => base.get(key),
+        }
+    }
+
+    pub(crate) fn get_many<DB: BonsaiDatabase>(
+        &self,
+        base: &DB,
+        keys: &[DatabaseKey],
+    ) -> Result<Vec<Option<ByteVec>>, DB::DatabaseError> {
+        let mut values: Vec<_> = keys
+            .iter()
+            .map(|key| self.get_map(key).get(key.as_slice()).cloned())
+            .collect();
+        let missing: Vec<_> = keys
+            .iter()
+            .zip(&values)
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| *key)
+            .collect();
+        let mut missing_values = base.get_many(&missing)?.into_iter();
+        for value in values.iter_mut().filter(|value| value.is_none()) {
+            *value = missing_values.next();
+        }
+        Ok(values.into_iter().map(Option::flatten).collect())
+    }
+
+    pub(crate) fn get_by_prefix<DB: BonsaiDatabase>(
+        &self,
+        base: &DB,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, DB::DatabaseError> {
+        let map = self.get_map(prefix);
+        let mut values: Vec<_> = base
+            .get_by_prefix(prefix)?
+            .into_iter()
+            .filter(|(key, _)| !map.contains_key(key))
+            .collect();
+        values.extend(map.iter().filter_map(|(key, value)| {
+            if key.starts_with(prefix.as_slice()) {
+                Some((key.clone(), value.clone()?))
+            } else {
+                None
+            }
+        }));
+        // Same ordering as the base database: trie log deserialization relies on it.
+        values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(values)
+    }
+
+    pub(crate) fn contains<DB: BonsaiDatabase>(
+        &self,
+        base: &DB,
+        key: &DatabaseKey,
+    ) -> Result<bool, DB::DatabaseError> {
+        match self.get_map(key).get(key.as_slice()) {
+            Some(value) => Ok(value.is_some()),
+            None => base.contains(key),
+        }
+    }
+
+    /// Write `value` to `key`, or remove it if `None`, returning its previous value. The write is
+    /// kept in `batch` if given, until it is added with [`WriteSet::extend`].
+    pub(crate) fn write<DB: BonsaiDatabase>(
+        &mut self,
+        base: &DB,
+        key: &DatabaseKey,
+        value: Option<&[u8]>,
+        batch: Option<&mut OverlayDbBatch>,
+    ) -> Result<Option<ByteVec>, DB::DatabaseError> {
+        let old_value = self.get(base, key)?;
+        let writes = match batch {
+            Some(batch) => &mut batch.0,
+            None => self,
+        };
+        writes
+            .get_map_mut(key)
+            .insert(key.as_slice().into(), value.map(Into::into));
+        Ok(old_value)
+    }
+
+    pub(crate) fn remove_by_prefix<DB: BonsaiDatabase>(
+        &mut self,
+        base: &DB,
+        prefix: &DatabaseKey,
+    ) -> Result<(), DB::DatabaseError> {
+        for (key, _) in self.get_by_prefix(base, prefix)? {
+            self.get_map_mut(prefix).insert(key, None);
+        }
+        Ok(())
+    }
+}
+
+/// Batch of an [`OverlayDb`] or of a [`ForkDb`], whose writes are only visible once it is written.
+#[derive(Debug, Default)]
+pub struct OverlayDbBatch(pub(crate) WriteSet);
+
+/// In-memory write set layered over a base database, which is only read from. Everything written
+/// to the overlay, commits and trie logs included, stays in memory until [`OverlayDb::freeze`]
+/// turns it into a batch of the base database.
+///
+/// This is the copy-on-write view of [`crate::BonsaiStorage::fork`] for any database owned by the
+/// overlay: a storage opened on an overlay can be used as an ephemeral fork, discarded by
+/// dropping it, or as a staging area whose commits are written to the base all at once. The
+/// overlay keeps no snapshots, so transactional states can't be created from it.
+#[derive(Debug)]
+pub struct OverlayDb<Base: BonsaiDatabase> {
+    base: Base,
+    writes: WriteSet,
+}
+
+impl<Base: BonsaiDatabase> OverlayDb<Base> {
+    pub fn new(base: Base) -> Self {
+        Self {
+            base,
+            writes: WriteSet::default(),
+        }
+    }
+
+    /// The base database, without the writes of the overlay.
+    pub fn base(&self) -> &Base {
+        &self.base
+    }
+
+    /// The base database, dropping the writes of the overlay.
+    pub fn into_base(self) -> Base {
+        self.base
+    }
+
+    /// The base database, and a batch of it holding the writes of the overlay, to be written
+    /// with [`BonsaiDatabase::write_batch`].
+    pub fn freeze(mut self) -> Result<(Base, Base::Batch), Base::DatabaseError> {
+        let mut batch = self.base.create_batch();
+        for (key, value) in self.writes.changes() {
+            match value {
+                Some(value) => self.base.insert(&key, value, Some(&mut batch))?,
+                None => self.base.remove(&key, Some(&mut batch))?,
+            };
+        }
+        Ok((self.base, batch))
+    }
+}
+
+impl<Base: BonsaiDatabase> BonsaiDatabase for OverlayDb<Base> {
+    type Batch = OverlayDbBatch;
+    type DatabaseError = Base::DatabaseError;
+
+    fn create_batch(&self) -> Self::Batch {
+        OverlayDbBatch::default()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.get(&self.base, key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        self.writes.get_many(&self.base, keys)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        self.writes.get_by_prefix(&self.base, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        self.writes.contains(&self.base, key)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.write(&self.base, key, Some(value), batch)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.writes.write(&self.base, key, None, batch)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        self.writes.remove_by_prefix(&self.base, prefix)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.writes.extend(batch.0);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        log::debug!("{:?}", self.writes);
+    }
+}
+
+impl<Base: BonsaiDatabase, ID: Id> BonsaiPersistentDatabase<ID> for OverlayDb<Base> {
+    type Transaction<'a>
+        = ForkDb<'a, Self>
+    where
+        Self: 'a;
+    type DatabaseError = Base::DatabaseError;
+
+    fn snapshot(&mut self, _id: ID) {
+        // The overlay only lives in memory, it doesn't keep snapshots.
+    }
+
+    fn transaction(&self, _id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        None
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.writes.extend(transaction.into_write_set());
+        Ok(())
+    }
+}
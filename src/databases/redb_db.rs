@@ -0,0 +1,438 @@
+use std::{collections::BTreeMap, error::Error as StdError, fmt, path::Path};
+
+use log::trace;
+use redb::{Database, Error, ReadTransaction, TableDefinition};
+
+use crate::{
+    bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    ByteVec,
+};
+
+type Table = TableDefinition<'static, &'static [u8], &'static [u8]>;
+
+const TRIE_TABLE: Table = TableDefinition::new("trie");
+const FLAT_TABLE: Table = TableDefinition::new("flat");
+const TRIE_LOG_TABLE: Table = TableDefinition::new("trie_log");
+const META_TABLE: Table = TableDefinition::new("meta");
+
+/// The tables of a [`RedbDb`], indexed by [`table_index`].
+const TABLES: [Table; 4] = [TRIE_TABLE, FLAT_TABLE, TRIE_LOG_TABLE, META_TABLE];
+
+fn table_index(key: &DatabaseKey) -> usize {
+    match key {
+        DatabaseKey::Trie(_) => 0,
+        DatabaseKey::Flat(_) => 1,
+        DatabaseKey::TrieLog(_) => 2,
+        DatabaseKey::Meta(_) => 3,
+    }
+}
+
+/// Opens the redb database file `path`, creating it if it does not exist, along with the tables
+/// used by [`RedbDb`].
+pub fn open_redb_db(path: impl AsRef<Path>) -> Result<Database, RedbDbError> {
+    let db = Database::create(path)?;
+    write_changes(&db, [])?;
+    Ok(db)
+}
+
+/// A struct that implements the `BonsaiDatabase` trait using redb as the underlying database.
+///
+/// redb is written in pure Rust, so this database needs neither a C++ toolchain nor CMake, unlike
+/// [`crate::databases::RocksDB`]. The batches are written in a single redb write transaction.
+///
+/// The snapshots are redb read transactions, which see the database as it was when they were
+/// taken. redb savepoints can only be read by restoring them in a write transaction, and redb
+/// runs one write transaction at a time, so the database could not be written while a
+/// transaction on a snapshot is alive. As long as a snapshot is kept, redb can't reuse the pages
+/// it sees, so the file grows with the changes made since the oldest snapshot:
+/// [`RedbDbConfig::max_saved_snapshots`] should stay low.
+pub struct RedbDb<ID: Id> {
+    db: Database,
+    config: RedbDbConfig,
+    snapshots: BTreeMap<ID, ReadTransaction>,
+}
+
+impl<ID: Id> fmt::Debug for RedbDb<ID> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbDb")
+            .field("config", &self.config)
+            .field("snapshots", &self.snapshots.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Configuration for the redb database
+#[derive(Debug, Clone)]
+pub struct RedbDbConfig {
+    /// Maximum number of snapshots kept in database
+    pub max_saved_snapshots: Option<usize>,
+}
+
+impl Default for RedbDbConfig {
+    fn default() -> Self {
+        Self {
+            max_saved_snapshots: Some(16),
+        }
+    }
+}
+
+impl<ID: Id> RedbDb<ID> {
+    /// Creates a new redb wrapper from the given database, opened with [`open_redb_db`]
+    pub fn new(db: Database, config: RedbDbConfig) -> Self {
+        trace!("redb database opened");
+        Self {
+            db,
+            config,
+            snapshots: BTreeMap::default(),
+        }
+    }
+}
+
+/// A batch of changes to a [`RedbDb`], written in a single write transaction. `None` values are
+/// removals.
+#[derive(Debug, Default)]
+pub struct RedbBatch(Vec<(usize, ByteVec, Option<ByteVec>)>);
+
+#[derive(Debug)]
+pub enum RedbDbError {
+    Redb(Box<Error>),
+}
+
+impl<E: Into<Error>> From<E> for RedbDbError {
+    fn from(err: E) -> Self {
+        Self::Redb(Box::new(err.into()))
+    }
+}
+
+impl fmt::Display for RedbDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Redb(err) => write!(f, "redb error: {}", err),
+        }
+    }
+}
+
+impl DBError for RedbDbError {}
+
+impl StdError for RedbDbError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Redb(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+fn read(txn: &ReadTransaction, key: &DatabaseKey) -> Result<Option<ByteVec>, RedbDbError> {
+    let table = txn.open_table(TABLES[table_index(key)])?;
+    Ok(table.get(key.as_slice())?.map(|value| value.value().into()))
+}
+
+fn read_prefix(
+    txn: &ReadTransaction,
+    prefix: &DatabaseKey,
+) -> Result<Vec<(ByteVec, ByteVec)>, RedbDbError> {
+    let table = txn.open_table(TABLES[table_index(prefix)])?;
+    let mut entries = Vec::new();
+    for entry in table.range(prefix.as_slice()..)? {
+        let (key, value) = entry?;
+        if !key.value().starts_with(prefix.as_slice()) {
+            break;
+        }
+        entries.push((key.value().into(), value.value().into()));
+    }
+    Ok(entries)
+}
+
+/// Write `changes` to `db` in a single write transaction. The tables are created if they don't
+/// exist.
+fn write_changes<'a>(
+    db: &Database,
+    changes: impl IntoIterator<Item = (usize, &'a [u8], Option<&'a [u8]>)>,
+) -> Result<(), RedbDbError> {
+    let txn = db.begin_write()?;
+    {
+        let mut tables = TABLES
+            .iter()
+            .map(|table| txn.open_table(*table))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (table, key, value) in changes {
+            match value {
+                Some(value) => tables[table].insert(key, value)?,
+                None => tables[table].remove(key)?,
+            };
+        }
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+impl<ID: Id> BonsaiDatabase for RedbDb<ID> {
+    type Batch = RedbBatch;
+    type DatabaseError = RedbDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        println!("{:?}", self)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Inserting into redb: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((table_index(key), key.as_slice().into(), Some(value.into())));
+        } else {
+            write_changes(&self.db, [(table_index(key), key.as_slice(), Some(value))])?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Getting from redb: {:?}", key);
+        read(&self.db.begin_read()?, key)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Getting {} keys from redb", keys.len());
+        let txn = self.db.begin_read()?;
+        keys.iter().map(|key| read(&txn, key)).collect()
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        trace!("Getting from redb: {:?}", prefix);
+        read_prefix(&self.db.begin_read()?, prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if redb contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Removing from redb: {:?}", key);
+        let old_value = self.get(key)?;
+        if let Some(batch) = batch {
+            batch
+                .0
+                .push((table_index(key), key.as_slice().into(), None));
+        } else {
+            write_changes(&self.db, [(table_index(key), key.as_slice(), None)])?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from redb: {:?}", prefix);
+        let table = table_index(prefix);
+        let keys = self.get_by_prefix(prefix)?;
+        write_changes(
+            &self.db,
+            keys.iter().map(|(key, _)| (table, key.as_slice(), None)),
+        )
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        write_changes(
+            &self.db,
+            batch
+                .0
+                .iter()
+                .map(|(table, key, value)| (*table, key.as_slice(), value.as_deref())),
+        )
+    }
+}
+
+/// Transaction on a snapshot of a [`RedbDb`]. It reads the snapshot, and keeps its own changes in
+/// memory until it is merged, when they are written to the database in a single write
+/// transaction.
+pub struct RedbTransaction<'a> {
+    snapshot: &'a ReadTransaction,
+    changes: BTreeMap<(usize, ByteVec), Option<ByteVec>>,
+}
+
+impl fmt::Debug for RedbTransaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbTransaction")
+            .field("changes", &self.changes.len())
+            .finish()
+    }
+}
+
+impl RedbTransaction<'_> {
+    fn change(&self, key: &DatabaseKey) -> Option<&Option<ByteVec>> {
+        self.changes
+            .get(&(table_index(key), ByteVec::from(key.as_slice())))
+    }
+}
+
+impl BonsaiDatabase for RedbTransaction<'_> {
+    type Batch = RedbBatch;
+    type DatabaseError = RedbDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        Self::Batch::default()
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        println!("{:?}", self)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Inserting into redb transaction: {:?} {:?}", key, value);
+        let old_value = self.get(key)?;
+        let change = (table_index(key), key.as_slice().into(), Some(value.into()));
+        if let Some(batch) = batch {
+            batch.0.push(change);
+        } else {
+            self.write_batch(RedbBatch(vec![change]))?;
+        }
+        Ok(old_value)
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Getting from redb transaction: {:?}", key);
+        match self.change(key) {
+            Some(value) => Ok(value.clone()),
+            None => read(self.snapshot, key),
+        }
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        trace!("Getting from redb transaction: {:?}", prefix);
+        let table = table_index(prefix);
+        let mut entries: BTreeMap<ByteVec, ByteVec> =
+            read_prefix(self.snapshot, prefix)?.into_iter().collect();
+        let changes = self
+            .changes
+            .range((table, ByteVec::from(prefix.as_slice()))..)
+            .take_while(|((change_table, key), _)| {
+                *change_table == table && key.starts_with(prefix.as_slice())
+            });
+        for ((_, key), value) in changes {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        trace!("Checking if redb transaction contains: {:?}", key);
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        trace!("Removing from redb transaction: {:?}", key);
+        let old_value = self.get(key)?;
+        let change = (table_index(key), key.as_slice().into(), None);
+        if let Some(batch) = batch {
+            batch.0.push(change);
+        } else {
+            self.write_batch(RedbBatch(vec![change]))?;
+        }
+        Ok(old_value)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        trace!("Removing from redb transaction: {:?}", prefix);
+        let table = table_index(prefix);
+        for (key, _) in self.get_by_prefix(prefix)? {
+            self.changes.insert((table, key), None);
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        for (table, key, value) in batch.0 {
+            self.changes.insert((table, key), value);
+        }
+        Ok(())
+    }
+}
+
+impl<ID: Id> BonsaiPersistentDatabase<ID> for RedbDb<ID> {
+    type Transaction<'a>
+        = RedbTransaction<'a>
+    where
+        Self: 'a;
+    type DatabaseError = RedbDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        trace!("Generating redb snapshot");
+        let snapshot = self
+            .db
+            .begin_read()
+            .expect("critical: failed to begin the redb snapshot transaction");
+        self.snapshots.insert(id, snapshot);
+        if let Some(max_number_snapshot) = self.config.max_saved_snapshots {
+            while self.snapshots.len() > max_number_snapshot {
+                self.snapshots.pop_first();
+            }
+        }
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.snapshots.remove(&id).is_some()
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        trace!("Generating redb transaction");
+        let (id, snapshot) = self.snapshots.range(..=id).next_back()?;
+        Some((
+            *id,
+            RedbTransaction {
+                snapshot,
+                changes: BTreeMap::new(),
+            },
+        ))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        write_changes(
+            &self.db,
+            transaction
+                .changes
+                .iter()
+                .map(|((table, key), value)| (*table, key.as_slice(), value.as_deref())),
+        )
+    }
+}
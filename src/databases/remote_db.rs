@@ -0,0 +1,371 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    trie::trie_db::{MetaKeyType, TrieKey, TrieKeyType},
+    BonsaiDatabase, ByteVec, HashMap, Vec,
+};
+use core::{fmt, fmt::Display};
+use std::sync::Mutex;
+
+/// Source of the trie nodes and leaves of a [`RemoteDb`], usually a full node.
+///
+/// The fetcher must serve a fixed state, the one the local database of the [`RemoteDb`] starts
+/// from: the nodes of a trie are fetched as they are needed, so nodes of different states would
+/// not make a trie.
+pub trait NodeFetcher {
+    type Error: DBError;
+
+    /// Value at `key`, a [`DatabaseKey::Trie`] or [`DatabaseKey::Flat`] key, `None` if the remote
+    /// node doesn't have it.
+    fn fetch(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::Error>;
+
+    /// Values at `keys`, in order. The default implementation fetches them one by one.
+    fn fetch_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::Error> {
+        keys.iter().map(|key| self.fetch(key)).collect()
+    }
+}
+
+impl<F: NodeFetcher + ?Sized> NodeFetcher for &F {
+    type Error = F::Error;
+
+    fn fetch(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::Error> {
+        (**self).fetch(key)
+    }
+
+    fn fetch_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::Error> {
+        (**self).fetch_many(keys)
+    }
+}
+
+/// Error of a [`RemoteDb`].
+#[derive(Debug)]
+pub enum RemoteDbError<E, F> {
+    Database(E),
+    Fetch(F),
+}
+
+impl<E: DBError, F: DBError> std::error::Error for RemoteDbError<E, F> {}
+
+impl<E: Display, F: Display> Display for RemoteDbError<E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteDbError::Database(err) => write!(f, "Database error: {}", err),
+            RemoteDbError::Fetch(err) => write!(f, "Fetch error: {}", err),
+        }
+    }
+}
+
+impl<E: DBError, F: DBError> DBError for RemoteDbError<E, F> {}
+
+/// Database reading through to a remote node: the trie nodes and leaves missing from the local
+/// database are fetched with a [`NodeFetcher`], so that a storage can be used from the state of a
+/// full node without downloading it first. The trie logs and the metadata are only local.
+///
+/// The fetched values, and the keys the remote node doesn't have, are cached in memory, and the
+/// fetched values are written to the local database with the next batch, so that they are fetched
+/// once. The trie nodes and leaves removed locally are marked as removed in the metadata, so that
+/// they are not fetched again.
+///
+/// Prefix reads only see the local database and the cached values: the nodes and leaves which
+/// were never fetched are not listed, nor removed by [`BonsaiDatabase::remove_by_prefix`].
+pub struct RemoteDb<DB, F> {
+    db: DB,
+    fetcher: F,
+    /// Values fetched since the last batch was written, by database key. `None` marks a key the
+    /// remote node doesn't have.
+    fetched: Mutex<HashMap<ByteVec, Option<ByteVec>>>,
+}
+
+impl<DB: fmt::Debug, F> fmt::Debug for RemoteDb<DB, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteDb")
+            .field("db", &self.db)
+            .field(
+                "fetched",
+                &self.fetched.lock().expect("poisoned fetch cache").len(),
+            )
+            .finish()
+    }
+}
+
+impl<DB, F> RemoteDb<DB, F> {
+    pub fn new(db: DB, fetcher: F) -> Self {
+        Self {
+            db,
+            fetcher,
+            fetched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &DB {
+        &self.db
+    }
+
+    /// The local database, dropping the cached values which were not written to it yet.
+    pub fn into_inner(self) -> DB {
+        self.db
+    }
+}
+
+/// Key of the fetched values and of the removal marks: the key type of `key`, then the key.
+/// `None` for the keys which are not fetched.
+fn remote_key(key: &DatabaseKey) -> Option<ByteVec> {
+    let key_type = match key {
+        DatabaseKey::Trie(_) => TrieKeyType::Trie,
+        DatabaseKey::Flat(_) => TrieKeyType::Flat,
+        DatabaseKey::TrieLog(_) | DatabaseKey::Meta(_) => return None,
+    };
+    let mut remote_key = ByteVec::with_capacity(1 + key.as_slice().len());
+    remote_key.push(key_type as u8);
+    remote_key.extend_from_slice(key.as_slice());
+    Some(remote_key)
+}
+
+/// Metadata key marking the key `remote_key` as removed.
+fn removed_key(remote_key: &[u8]) -> ByteVec {
+    TrieKey::new_meta(MetaKeyType::RemoteRemoved, remote_key)
+        .as_slice()
+        .into()
+}
+
+/// The database key of `remote_key`.
+fn database_key(remote_key: &[u8]) -> DatabaseKey<'_> {
+    match remote_key.split_first() {
+        Some((&key_type, key)) if key_type == TrieKeyType::Trie as u8 => DatabaseKey::Trie(key),
+        Some((_, key)) => DatabaseKey::Flat(key),
+        None => unreachable!("remote keys start with their key type"),
+    }
+}
+
+impl<DB: BonsaiDatabase, F: NodeFetcher> RemoteDb<DB, F> {
+    /// Cached value at `remote_key`, or whether the key was removed locally.
+    #[allow(clippy::type_complexity)]
+    fn local(
+        &self,
+        remote_key: &[u8],
+    ) -> Result<Option<Option<ByteVec>>, RemoteDbError<DB::DatabaseError, F::Error>> {
+        if let Some(value) = self
+            .fetched
+            .lock()
+            .expect("poisoned fetch cache")
+            .get(remote_key)
+        {
+            return Ok(Some(value.clone()));
+        }
+        let removed = self
+            .db
+            .contains(&DatabaseKey::Meta(&removed_key(remote_key)))
+            .map_err(RemoteDbError::Database)?;
+        Ok(removed.then_some(None))
+    }
+
+    /// Write `value` to the key `key` of the local database, or remove it, and return the
+    /// previous value.
+    fn replace(
+        &mut self,
+        key: &DatabaseKey,
+        value: Option<&[u8]>,
+        mut batch: Option<&mut DB::Batch>,
+    ) -> Result<Option<ByteVec>, RemoteDbError<DB::DatabaseError, F::Error>> {
+        let old_value = self.get(key)?;
+        if let Some(remote_key) = remote_key(key) {
+            self.fetched
+                .get_mut()
+                .expect("poisoned fetch cache")
+                .remove(&remote_key);
+            let removed_key = removed_key(&remote_key);
+            let removed_key = DatabaseKey::Meta(&removed_key);
+            match value {
+                Some(_) => self.db.remove(&removed_key, batch.as_deref_mut()),
+                None => self.db.insert(&removed_key, &[], batch.as_deref_mut()),
+            }
+            .map_err(RemoteDbError::Database)?;
+        }
+        match value {
+            Some(value) => self.db.insert(key, value, batch),
+            None => self.db.remove(key, batch),
+        }
+        .map_err(RemoteDbError::Database)?;
+        Ok(old_value)
+    }
+}
+
+impl<DB: BonsaiDatabase, F: NodeFetcher> BonsaiDatabase for RemoteDb<DB, F> {
+    type Batch = DB::Batch;
+    type DatabaseError = RemoteDbError<DB::DatabaseError, F::Error>;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.db.create_batch()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        let value = self.db.get(key).map_err(RemoteDbError::Database)?;
+        let Some(remote_key) = remote_key(key).filter(|_| value.is_none()) else {
+            return Ok(value);
+        };
+        if let Some(value) = self.local(&remote_key)? {
+            return Ok(value);
+        }
+        let value = self.fetcher.fetch(key).map_err(RemoteDbError::Fetch)?;
+        self.fetched
+            .lock()
+            .expect("poisoned fetch cache")
+            .insert(remote_key, value.clone());
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        let mut values = self.db.get_many(keys).map_err(RemoteDbError::Database)?;
+        let mut missing = Vec::new();
+        for (i, (key, value)) in keys.iter().zip(&mut values).enumerate() {
+            let Some(remote_key) = remote_key(key).filter(|_| value.is_none()) else {
+                continue;
+            };
+            match self.local(&remote_key)? {
+                Some(local) => *value = local,
+                None => missing.push((i, remote_key)),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(values);
+        }
+        let missing_keys: Vec<_> = missing.iter().map(|(i, _)| keys[*i]).collect();
+        let fetched = self
+            .fetcher
+            .fetch_many(&missing_keys)
+            .map_err(RemoteDbError::Fetch)?;
+        let mut cache = self.fetched.lock().expect("poisoned fetch cache");
+        for ((i, remote_key), value) in missing.into_iter().zip(fetched) {
+            values[i] = value.clone();
+            cache.insert(remote_key, value);
+        }
+        Ok(values)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        let mut entries = self
+            .db
+            .get_by_prefix(prefix)
+            .map_err(RemoteDbError::Database)?;
+        let Some(remote_prefix) = remote_key(prefix) else {
+            // The removal marks are an implementation detail of this database.
+            if let DatabaseKey::Meta(&[]) = prefix {
+                entries.retain(|(key, _)| key.first() != Some(&(MetaKeyType::RemoteRemoved as u8)));
+            }
+            return Ok(entries);
+        };
+        let cache = self.fetched.lock().expect("poisoned fetch cache");
+        entries.extend(cache.iter().filter_map(|(remote_key, value)| {
+            let value = value.as_ref()?;
+            remote_key
+                .starts_with(&remote_prefix)
+                .then(|| (remote_key[1..].into(), value.clone()))
+        }));
+        // Same ordering as the local database: trie log deserialization relies on it.
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.replace(key, Some(value), batch)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.replace(key, None, batch)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        if remote_key(prefix).is_none() {
+            return self
+                .db
+                .remove_by_prefix(prefix)
+                .map_err(RemoteDbError::Database);
+        }
+        let mut batch = self.create_batch();
+        for (key, _) in self.get_by_prefix(prefix)? {
+            let key = match prefix {
+                DatabaseKey::Trie(_) => DatabaseKey::Trie(&key),
+                _ => DatabaseKey::Flat(&key),
+            };
+            self.replace(&key, None, Some(&mut batch))?;
+        }
+        self.write_batch(batch)
+    }
+
+    fn write_batch(&mut self, mut batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        let fetched = core::mem::take(self.fetched.get_mut().expect("poisoned fetch cache"));
+        for (remote_key, value) in &fetched {
+            if let Some(value) = value {
+                self.db
+                    .insert(&database_key(remote_key), value, Some(&mut batch))
+                    .map_err(RemoteDbError::Database)?;
+            }
+        }
+        self.db.write_batch(batch).map_err(RemoteDbError::Database)
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.db.compact().map_err(RemoteDbError::Database)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+impl<ID, DB, F> BonsaiPersistentDatabase<ID> for RemoteDb<DB, F>
+where
+    ID: Id,
+    DB: BonsaiPersistentDatabase<ID>,
+    F: NodeFetcher,
+{
+    type Transaction<'a>
+        = RemoteDb<DB::Transaction<'a>, &'a F>
+    where
+        Self: 'a;
+    type DatabaseError =
+        RemoteDbError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError, F::Error>;
+
+    fn snapshot(&mut self, id: ID) {
+        self.db.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.db.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.db.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let (snapshot_id, db) = self.db.transaction(id)?;
+        Some((snapshot_id, RemoteDb::new(db, &self.fetcher)))
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.db
+            .merge(transaction.db)
+            .map_err(RemoteDbError::Database)
+    }
+}
@@ -21,6 +21,7 @@ use log::trace;
 const TRIE_LOG_CF: &str = "trie_log";
 const TRIE_CF: &str = "trie";
 const FLAT_CF: &str = "flat";
+const META_CF: &str = "meta";
 
 const CF_ERROR: &str = "critical: rocksdb column family operation failed";
 
@@ -31,20 +32,28 @@ pub fn create_rocks_db(path: impl AsRef<Path>) -> Result<OptimisticTransactionDB
         std::fs::remove_dir_all(path.as_ref()).unwrap();
     }
     std::fs::create_dir_all(path.as_ref()).unwrap();
+    open_rocks_db(path)
+}
+
+/// Opens the RocksDB database at the given path, creating it if it does not exist.
+///
+/// The column families used by [`RocksDB`] are created if they are missing, such as the `meta`
+/// column family in a database created by an earlier version of this crate. Databases opened
+/// by other means must declare all of them.
+pub fn open_rocks_db(path: impl AsRef<Path>) -> Result<OptimisticTransactionDB, Error> {
     let mut opts = Options::default();
     opts.create_if_missing(true);
     opts.create_missing_column_families(true);
-    let db = OptimisticTransactionDB::<MultiThreaded>::open_cf_descriptors(
+    OptimisticTransactionDB::<MultiThreaded>::open_cf_descriptors(
         &opts,
         path,
         vec![
             ColumnFamilyDescriptor::new(TRIE_LOG_CF, Options::default()),
             ColumnFamilyDescriptor::new(TRIE_CF, Options::default()),
             ColumnFamilyDescriptor::new(FLAT_CF, Options::default()),
+            ColumnFamilyDescriptor::new(META_CF, Options::default()),
         ],
-    )?;
-
-    Ok(db)
+    )
 }
 
 /// A struct that implements the `BonsaiDatabase` trait using RocksDB as the underlying database
@@ -163,6 +172,7 @@ impl DatabaseKey<'_> {
             DatabaseKey::Trie(_) => TRIE_CF,
             DatabaseKey::Flat(_) => FLAT_CF,
             DatabaseKey::TrieLog(_) => TRIE_LOG_CF,
+            DatabaseKey::Meta(_) => META_CF,
         }
     }
 }
@@ -212,12 +222,54 @@ where
         Ok(old_value.map(Into::into))
     }
 
+    fn insert_many(
+        &mut self,
+        items: &[(DatabaseKey, &[u8])],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Inserting {} keys into RocksDB", items.len());
+        let keys: Vec<_> = items.iter().map(|(key, _)| *key).collect();
+        let old_values = self.get_many(&keys)?;
+        // Without a batch, the pairs are still written at once through a batch of our own.
+        let mut own_batch = None;
+        let batch = match batch {
+            Some(batch) => batch,
+            None => own_batch.insert(self.create_batch()),
+        };
+        for (key, value) in items {
+            let handle_cf = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
+            batch.put_cf(&handle_cf, key.as_slice(), value);
+        }
+        if let Some(batch) = own_batch {
+            self.db.write(batch)?;
+        }
+        Ok(old_values)
+    }
+
     fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
         trace!("Getting from RocksDB: {:?}", key);
         let handle = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
         Ok(self.db.get_cf(&handle, key.as_slice())?.map(Into::into))
     }
 
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Getting {} keys from RocksDB", keys.len());
+        let handles: Vec<_> = keys
+            .iter()
+            .map(|key| self.db.cf_handle(key.get_cf()).expect(CF_ERROR))
+            .collect();
+        self.db
+            .multi_get_cf(
+                handles
+                    .iter()
+                    .zip(keys)
+                    .map(|(handle, key)| (handle, key.as_slice())),
+            )
+            .into_iter()
+            .map(|value| -> Result<_, Self::DatabaseError> { Ok(value?.map(Into::into)) })
+            .collect()
+    }
+
     fn get_by_prefix(
         &self,
         prefix: &DatabaseKey,
@@ -243,6 +295,27 @@ where
             .collect())
     }
 
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        trace!("Getting keys from RocksDB: {:?}", prefix);
+        let handle = self.db.cf_handle(prefix.get_cf()).expect(CF_ERROR);
+        // The raw iterator reads the keys without copying the values.
+        let mut iter = self.db.raw_iterator_cf(&handle);
+        iter.seek(prefix.as_slice());
+        let mut keys = Vec::new();
+        while let Some(key) = iter.key() {
+            if !key.starts_with(prefix.as_slice()) {
+                break;
+            }
+            keys.push(key.into());
+            iter.next();
+        }
+        iter.status()?;
+        Ok(keys)
+    }
+
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
         trace!("Checking if RocksDB contains: {:?}", key);
         let handle = self.db.cf_handle(key.get_cf()).expect(CF_ERROR);
@@ -294,6 +367,16 @@ where
     fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
         Ok(self.db.write(batch)?)
     }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        trace!("Compacting RocksDB");
+        for cf in [TRIE_LOG_CF, TRIE_CF, FLAT_CF, META_CF] {
+            let handle = self.db.cf_handle(cf).expect(CF_ERROR);
+            self.db
+                .compact_range_cf(&handle, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
+    }
 }
 
 // Future thoughts: Try to factorize with the code above
@@ -356,6 +439,24 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
         Ok(old_value.map(Into::into))
     }
 
+    fn insert_many(
+        &mut self,
+        items: &[(DatabaseKey, &[u8])],
+        mut batch: Option<&mut Self::Batch>,
+    ) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Inserting {} keys into RocksDB", items.len());
+        let keys: Vec<_> = items.iter().map(|(key, _)| *key).collect();
+        let old_values = self.get_many(&keys)?;
+        for (key, value) in items {
+            let handle_cf = self.column_families.get(key.get_cf()).expect(CF_ERROR);
+            match batch.as_deref_mut() {
+                Some(batch) => batch.put_cf(handle_cf, key.as_slice(), value),
+                None => self.txn.put_cf(handle_cf, key.as_slice(), value)?,
+            }
+        }
+        Ok(old_values)
+    }
+
     fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
         trace!("Getting from RocksDB: {:?}", key);
         let handle = self.column_families.get(key.get_cf()).expect(CF_ERROR);
@@ -365,6 +466,23 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
             .map(Into::into))
     }
 
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        trace!("Getting {} keys from RocksDB", keys.len());
+        self.txn
+            .multi_get_cf_opt(
+                keys.iter().map(|key| {
+                    (
+                        self.column_families.get(key.get_cf()).expect(CF_ERROR),
+                        key.as_slice(),
+                    )
+                }),
+                &self.read_options,
+            )
+            .into_iter()
+            .map(|value| -> Result<_, Self::DatabaseError> { Ok(value?.map(Into::into)) })
+            .collect()
+    }
+
     fn get_by_prefix(
         &self,
         prefix: &DatabaseKey,
@@ -390,6 +508,27 @@ impl<'db> BonsaiDatabase for RocksDBTransaction<'db> {
             .collect())
     }
 
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        trace!("Getting keys from RocksDB: {:?}", prefix);
+        let handle = self.column_families.get(prefix.get_cf()).expect(CF_ERROR);
+        // The raw iterator reads the keys without copying the values.
+        let mut iter = self.txn.raw_iterator_cf(handle);
+        iter.seek(prefix.as_slice());
+        let mut keys = Vec::new();
+        while let Some(key) = iter.key() {
+            if !key.starts_with(prefix.as_slice()) {
+                break;
+            }
+            keys.push(key.into());
+            iter.next();
+        }
+        iter.status()?;
+        Ok(keys)
+    }
+
     fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
         trace!("Checking if RocksDB contains: {:?}", key);
         let handle = self.column_families.get(key.get_cf()).expect(CF_ERROR);
@@ -451,7 +590,10 @@ impl<'db, ID> BonsaiPersistentDatabase<ID> for RocksDB<'db, ID>
 where
     ID: Id,
 {
-    type Transaction<'a> = RocksDBTransaction<'a> where Self: 'a;
+    type Transaction<'a>
+        = RocksDBTransaction<'a>
+    where
+        Self: 'a;
     type DatabaseError = RocksDBError;
 
     fn snapshot(&mut self, id: ID) {
@@ -465,9 +607,17 @@ where
         }
     }
 
+    fn snapshots(&self) -> Vec<ID> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.snapshots.remove(&id).is_some()
+    }
+
     fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
         trace!("Generating RocksDB transaction");
-        if let Some((id, snapshot)) = self.snapshots.range(..&id).next() {
+        if let Some((id, snapshot)) = self.snapshots.range(..=id).next_back() {
             let write_opts = WriteOptions::default();
             let mut txn_opts = OptimisticTransactionOptions::default();
             txn_opts.set_snapshot(true);
@@ -489,6 +639,10 @@ where
                 FLAT_CF.to_string(),
                 self.db.cf_handle(FLAT_CF).expect(CF_ERROR),
             );
+            column_families.insert(
+                META_CF.to_string(),
+                self.db.cf_handle(META_CF).expect(CF_ERROR),
+            );
             let boxed_txn = RocksDBTransaction {
                 txn,
                 column_families,
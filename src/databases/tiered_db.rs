@@ -0,0 +1,276 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DBError, DatabaseKey},
+    id::Id,
+    BonsaiDatabase, ByteVec, Vec,
+};
+use core::{fmt, fmt::Display};
+
+/// Error of a [`TieredDatabase`], from the database of the tier the key belongs to.
+#[derive(Debug)]
+pub enum TieredDatabaseError<HotError, ColdError> {
+    Hot(HotError),
+    Cold(ColdError),
+}
+
+#[cfg(feature = "std")]
+impl<HotError: DBError, ColdError: DBError> std::error::Error
+    for TieredDatabaseError<HotError, ColdError>
+{
+}
+
+impl<HotError: Display, ColdError: Display> Display for TieredDatabaseError<HotError, ColdError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TieredDatabaseError::Hot(err) => write!(f, "Hot database error: {}", err),
+            TieredDatabaseError::Cold(err) => write!(f, "Cold database error: {}", err),
+        }
+    }
+}
+
+impl<HotError: DBError, ColdError: DBError> DBError for TieredDatabaseError<HotError, ColdError> {}
+
+/// Writes of a [`TieredDatabase`] batch, in a batch of each tier.
+#[derive(Debug, Default)]
+pub struct TieredDatabaseBatch<HotBatch, ColdBatch> {
+    hot: HotBatch,
+    cold: ColdBatch,
+}
+
+/// Database storing the history, which is rarely read, apart from the current state: the trie
+/// logs are kept in the `cold` database, for instance on a slower disk or in an object store,
+/// while the trie nodes, the leaves and the metadata stay in the `hot` one.
+///
+/// Each tier keeps the snapshots of its own columns, which must be taken at the same commits: the
+/// transactional states read both.
+///
+/// Batches are written to the cold database first, so that a commit is never written without its
+/// trie log. The two writes are not atomic: a crash in between leaves the trie log of a commit that
+/// was not written, which is overwritten when that commit is made again.
+#[derive(Debug)]
+pub struct TieredDatabase<Hot, Cold> {
+    hot: Hot,
+    cold: Cold,
+}
+
+impl<Hot, Cold> TieredDatabase<Hot, Cold> {
+    pub fn new(hot: Hot, cold: Cold) -> Self {
+        Self { hot, cold }
+    }
+
+    pub fn hot(&self) -> &Hot {
+        &self.hot
+    }
+
+    pub fn cold(&self) -> &Cold {
+        &self.cold
+    }
+
+    pub fn into_inner(self) -> (Hot, Cold) {
+        (self.hot, self.cold)
+    }
+
+    fn is_cold(key: &DatabaseKey) -> bool {
+        matches!(key, DatabaseKey::TrieLog(_))
+    }
+}
+
+impl<Hot: BonsaiDatabase, Cold: BonsaiDatabase> BonsaiDatabase for TieredDatabase<Hot, Cold> {
+    type Batch = TieredDatabaseBatch<Hot::Batch, Cold::Batch>;
+    type DatabaseError = TieredDatabaseError<Hot::DatabaseError, Cold::DatabaseError>;
+
+    fn create_batch(&self) -> Self::Batch {
+        TieredDatabaseBatch {
+            hot: self.hot.create_batch(),
+            cold: self.cold.create_batch(),
+        }
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        if Self::is_cold(key) {
+            self.cold.get(key).map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot.get(key).map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn get_many(&self, keys: &[DatabaseKey]) -> Result<Vec<Option<ByteVec>>, Self::DatabaseError> {
+        let (cold_keys, hot_keys): (Vec<_>, Vec<_>) =
+            keys.iter().copied().partition(|key| Self::is_cold(key));
+        let mut cold_values = self
+            .cold
+            .get_many(&cold_keys)
+            .map_err(TieredDatabaseError::Cold)?
+            .into_iter();
+        let mut hot_values = self
+            .hot
+            .get_many(&hot_keys)
+            .map_err(TieredDatabaseError::Hot)?
+            .into_iter();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                if Self::is_cold(key) {
+                    cold_values.next().flatten()
+                } else {
+                    hot_values.next().flatten()
+                }
+            })
+            .collect())
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        if Self::is_cold(prefix) {
+            self.cold
+                .get_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot
+                .get_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        if Self::is_cold(prefix) {
+            self.cold
+                .get_keys_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot
+                .get_keys_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        if Self::is_cold(key) {
+            self.cold.contains(key).map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot.contains(key).map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        if Self::is_cold(key) {
+            self.cold
+                .insert(key, value, batch.map(|batch| &mut batch.cold))
+                .map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot
+                .insert(key, value, batch.map(|batch| &mut batch.hot))
+                .map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        if Self::is_cold(key) {
+            self.cold
+                .remove(key, batch.map(|batch| &mut batch.cold))
+                .map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot
+                .remove(key, batch.map(|batch| &mut batch.hot))
+                .map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        if Self::is_cold(prefix) {
+            self.cold
+                .remove_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Cold)
+        } else {
+            self.hot
+                .remove_by_prefix(prefix)
+                .map_err(TieredDatabaseError::Hot)
+        }
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.cold
+            .write_batch(batch.cold)
+            .map_err(TieredDatabaseError::Cold)?;
+        self.hot
+            .write_batch(batch.hot)
+            .map_err(TieredDatabaseError::Hot)
+    }
+
+    fn compact(&mut self) -> Result<(), Self::DatabaseError> {
+        self.cold.compact().map_err(TieredDatabaseError::Cold)?;
+        self.hot.compact().map_err(TieredDatabaseError::Hot)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.hot.dump_database();
+        self.cold.dump_database();
+    }
+}
+
+impl<ID, Hot, Cold> BonsaiPersistentDatabase<ID> for TieredDatabase<Hot, Cold>
+where
+    ID: Id,
+    Hot: BonsaiPersistentDatabase<ID>,
+    Cold: BonsaiPersistentDatabase<ID>,
+{
+    type Transaction<'a>
+        = TieredDatabase<Hot::Transaction<'a>, Cold::Transaction<'a>>
+    where
+        Self: 'a;
+    type DatabaseError = TieredDatabaseError<
+        <Hot as BonsaiPersistentDatabase<ID>>::DatabaseError,
+        <Cold as BonsaiPersistentDatabase<ID>>::DatabaseError,
+    >;
+
+    fn snapshot(&mut self, id: ID) {
+        self.hot.snapshot(id);
+        self.cold.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.hot.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.cold.remove_snapshot(id);
+        self.hot.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        let (snapshot_id, hot) = self.hot.transaction(id)?;
+        // The cold tier must use the snapshot taken at the same commit.
+        match self.cold.transaction(snapshot_id)? {
+            (cold_snapshot_id, cold) if cold_snapshot_id == snapshot_id => {
+                Some((snapshot_id, TieredDatabase { hot, cold }))
+            }
+            _ => None,
+        }
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        Self: 'a,
+    {
+        self.cold
+            .merge(transaction.cold)
+            .map_err(TieredDatabaseError::Cold)?;
+        self.hot
+            .merge(transaction.hot)
+            .map_err(TieredDatabaseError::Hot)
+    }
+}
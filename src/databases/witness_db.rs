@@ -0,0 +1,288 @@
+use crate::{
+    bonsai_database::{BonsaiPersistentDatabase, DatabaseKey},
+    databases::{hashmap_db::HashMapDbError, HashMapDb, HashMapDbBatch},
+    id::Id,
+    trie::{
+        merkle_node::{BinaryNode, Direction, EdgeNode, Height, Node, NodeHandle},
+        tree::{bitslice_to_bytes, encode_leaf, leaf_count_key},
+        trie_db::TrieKeyType,
+        TrieKey,
+    },
+    BitVec, BonsaiDatabase, ByteVec, HashSet, Path, ProofNode, Vec, Witness,
+};
+use starknet_types_core::felt::Felt;
+use std::sync::{Arc, Mutex};
+
+/// The reads of a [`WitnessDb`] its witness did not cover, shared with the database so that they
+/// can be checked once it is owned by a storage.
+#[derive(Debug, Clone, Default)]
+pub struct WitnessCoverage(Arc<Mutex<Vec<ByteVec>>>);
+
+impl WitnessCoverage {
+    /// Whether every trie node and leaf read so far was covered by the witness.
+    pub fn is_covered(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    /// The keys of the trie nodes and leaves read but not covered by the witness, in the order
+    /// they were read.
+    pub fn uncovered_reads(&self) -> Vec<ByteVec> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Database serving only the trie nodes and leaves of a [`Witness`], to re-execute the accesses
+/// it was recorded for without the full database, for instance in a stateless prover.
+///
+/// Reading a trie node or a leaf the witness does not cover returns `None` like a missing key, and
+/// is recorded in its [`WitnessCoverage`]. The writes are kept in memory and cover the keys they
+/// write, so that the re-execution can commit and compare the root hashes. The leaf counts, the
+/// raw payloads of the leaves and the metadata are not part of a witness: they are missing, as in
+/// an empty database, without being recorded.
+#[derive(Debug)]
+pub struct WitnessDb<ID: Id> {
+    db: HashMapDb<ID>,
+    /// Keys of the trie nodes and of the leaves whose value is known, present or not.
+    known_nodes: HashSet<ByteVec>,
+    known_leaves: HashSet<ByteVec>,
+    identifiers: Vec<ByteVec>,
+    coverage: WitnessCoverage,
+}
+
+impl<ID: Id> WitnessDb<ID> {
+    /// Database of the nodes of `witness`, made for tries of height `max_height`.
+    pub fn new(witness: &Witness, max_height: u8) -> Self {
+        let mut witness_db = Self {
+            db: HashMapDb::default(),
+            known_nodes: HashSet::new(),
+            known_leaves: HashSet::new(),
+            identifiers: witness.tries.keys().cloned().collect(),
+            coverage: WitnessCoverage::default(),
+        };
+        for (identifier, trie) in &witness.tries {
+            for key in &trie.keys {
+                witness_db.insert_path(identifier, trie.root, &trie.proof.0, key, max_height);
+            }
+        }
+        witness_db
+    }
+
+    /// Handle on the reads not covered by the witness, which stays valid once the database is
+    /// moved into a storage.
+    pub fn coverage(&self) -> WitnessCoverage {
+        self.coverage.clone()
+    }
+
+    /// Store the nodes on the path to `key` down from `root`, as far as `proof` has them, and the
+    /// leaf at its end.
+    fn insert_path(
+        &mut self,
+        identifier: &[u8],
+        root: Felt,
+        proof: &crate::HashMap<Felt, ProofNode>,
+        key: &BitVec,
+        max_height: u8,
+    ) {
+        let leaf_key = TrieKey::new(identifier, TrieKeyType::Flat, &bitslice_to_bytes(key));
+        let mut path = BitVec::new();
+        let mut hash = root;
+        loop {
+            let path_bytes: ByteVec = Path(path.clone()).into();
+            let node_key = TrieKey::new(identifier, TrieKeyType::Trie, &path_bytes);
+            if hash == Felt::ZERO && path.is_empty() {
+                // Empty trie: neither the root nor the leaf exist.
+                self.mark_known(&(&node_key).into());
+                break;
+            }
+            if path.len() == max_height as usize {
+                self.set(&leaf_key, Some(encode_leaf(&hash, None)));
+                return;
+            }
+            let height =
+                Height::new(path.len(), max_height).expect("The path is shorter than the keys");
+            let node = match proof.get(&hash) {
+                Some(ProofNode::Binary { left, right }) => {
+                    let direction = Direction::from(key[path.len()]);
+                    path.push(direction.into());
+                    let node = Node::Binary(BinaryNode {
+                        hash: Some(hash),
+                        height,
+                        left: NodeHandle::Hash(*left),
+                        right: NodeHandle::Hash(*right),
+                    });
+                    hash = match direction {
+                        Direction::Left => *left,
+                        Direction::Right => *right,
+                    };
+                    node
+                }
+                Some(ProofNode::Edge { child, path: edge }) => {
+                    let node = Node::Edge(EdgeNode {
+                        hash: Some(hash),
+                        height,
+                        path: edge.clone(),
+                        child: NodeHandle::Hash(*child),
+                    });
+                    self.set(&node_key, Some(node.encode_versioned()));
+                    if key.get(path.len()..path.len() + edge.len()) != Some(&edge.0) {
+                        // The edge leads elsewhere: the key is not in the trie.
+                        break;
+                    }
+                    path.extend_from_bitslice(&edge.0);
+                    hash = *child;
+                    continue;
+                }
+                // Not covered by the witness.
+                None => return,
+            };
+            self.set(&node_key, Some(node.encode_versioned()));
+        }
+        self.set(&leaf_key, None);
+    }
+
+    fn set(&mut self, key: &TrieKey, value: Option<ByteVec>) {
+        let key = key.into();
+        self.mark_known(&key);
+        if let Some(value) = value {
+            self.db
+                .insert(&key, &value, None)
+                .expect("HashMapDb can't fail");
+        }
+    }
+
+    /// Whether the value of `key` is known, so that it can be read, for the trie node and leaf
+    /// keys. The other keys are not part of a witness.
+    fn is_known(&self, key: &DatabaseKey) -> bool {
+        match key {
+            DatabaseKey::Trie(key) => {
+                self.known_nodes.contains(*key)
+                    || self
+                        .identifiers
+                        .iter()
+                        .any(|identifier| leaf_count_key(identifier).as_slice() == *key)
+            }
+            DatabaseKey::Flat(key) => self.known_leaves.contains(*key),
+            DatabaseKey::TrieLog(_) | DatabaseKey::Meta(_) => true,
+        }
+    }
+
+    fn check_covered(&self, key: &DatabaseKey) {
+        if !self.is_known(key) {
+            self.coverage.0.lock().unwrap().push(key.as_slice().into());
+        }
+    }
+
+    fn mark_known(&mut self, key: &DatabaseKey) {
+        match key {
+            DatabaseKey::Trie(key) => self.known_nodes.insert((*key).into()),
+            DatabaseKey::Flat(key) => self.known_leaves.insert((*key).into()),
+            DatabaseKey::TrieLog(_) | DatabaseKey::Meta(_) => false,
+        };
+    }
+}
+
+impl<ID: Id> BonsaiDatabase for WitnessDb<ID> {
+    type Batch = HashMapDbBatch;
+    type DatabaseError = HashMapDbError;
+
+    fn create_batch(&self) -> Self::Batch {
+        self.db.create_batch()
+    }
+
+    fn get(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.check_covered(key);
+        self.db.get(key)
+    }
+
+    fn get_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, Self::DatabaseError> {
+        self.db.get_by_prefix(prefix)
+    }
+
+    fn get_keys_by_prefix(
+        &self,
+        prefix: &DatabaseKey,
+    ) -> Result<Vec<ByteVec>, Self::DatabaseError> {
+        self.db.get_keys_by_prefix(prefix)
+    }
+
+    fn contains(&self, key: &DatabaseKey) -> Result<bool, Self::DatabaseError> {
+        self.check_covered(key);
+        self.db.contains(key)
+    }
+
+    fn insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.mark_known(key);
+        self.db.insert(key, value, batch)
+    }
+
+    fn remove(
+        &mut self,
+        key: &DatabaseKey,
+        batch: Option<&mut Self::Batch>,
+    ) -> Result<Option<ByteVec>, Self::DatabaseError> {
+        self.mark_known(key);
+        self.db.remove(key, batch)
+    }
+
+    fn remove_by_prefix(&mut self, prefix: &DatabaseKey) -> Result<(), Self::DatabaseError> {
+        self.db.remove_by_prefix(prefix)
+    }
+
+    fn write_batch(&mut self, batch: Self::Batch) -> Result<(), Self::DatabaseError> {
+        self.db.write_batch(batch)
+    }
+
+    #[cfg(test)]
+    fn dump_database(&self) {
+        self.db.dump_database();
+    }
+}
+
+/// The transactional states read and write the database without checking the coverage.
+impl<ID: Id> BonsaiPersistentDatabase<ID> for WitnessDb<ID> {
+    type Transaction<'a>
+        = HashMapDb<ID>
+    where
+        ID: 'a;
+    type DatabaseError = HashMapDbError;
+
+    fn snapshot(&mut self, id: ID) {
+        self.db.snapshot(id);
+    }
+
+    fn snapshots(&self) -> Vec<ID> {
+        self.db.snapshots()
+    }
+
+    fn remove_snapshot(&mut self, id: ID) -> bool {
+        self.db.remove_snapshot(id)
+    }
+
+    fn transaction(&self, id: ID) -> Option<(ID, Self::Transaction<'_>)> {
+        self.db.transaction(id)
+    }
+
+    fn merge<'a>(&mut self, transaction: Self::Transaction<'a>) -> Result<(), Self::DatabaseError>
+    where
+        ID: 'a,
+    {
+        // The keys written by the transactional state are known from now on.
+        for prefix in [DatabaseKey::Trie(&[]), DatabaseKey::Flat(&[])] {
+            for (key, _) in transaction.get_by_prefix(&prefix)? {
+                self.mark_known(&match prefix {
+                    DatabaseKey::Trie(_) => DatabaseKey::Trie(&key),
+                    _ => DatabaseKey::Flat(&key),
+                });
+            }
+        }
+        self.db.merge(transaction)
+    }
+}
@@ -1,7 +1,12 @@
 #[cfg(feature = "std")]
 use std::{error::Error, fmt::Display};
 
-use crate::{bonsai_database::DBError, String};
+use starknet_types_core::felt::Felt;
+
+use crate::{
+    bonsai_database::DBError, BitVec, Box, ByteVec, Path, ProofVerificationError, SavepointId,
+    StoredConfig, String, Vec,
+};
 
 /// All errors that can be returned by BonsaiStorage.
 #[derive(Debug)]
@@ -11,18 +16,211 @@ where
 {
     /// Error from the underlying trie.
     Trie(String),
+    /// The [`crate::BonsaiStorageConfig`] is not valid.
+    Config(ConfigError),
     /// Error when trying to go to a specific commit ID.
     GoTo(String),
     /// Error when working with a transactional state.
     Transaction(String),
     /// Error when trying to merge a transactional state.
     Merge(String),
+    /// Error when managing database snapshots.
+    Snapshot(SnapshotError),
+    /// Error when bulk loading a trie, see [`crate::BonsaiStorage::bulk_load`].
+    BulkLoad(String),
+    /// Error when working with a [`crate::TrieShard`].
+    Shard(ShardError),
+    /// Error from the database the state at a commit is written to, see
+    /// [`crate::BonsaiStorage::export_snapshot_into`].
+    #[cfg(feature = "std")]
+    ExportTarget(Box<dyn Error + Send + Sync>),
+    /// Error from the database a snapshot is imported from, see
+    /// [`crate::BonsaiStorage::import_snapshot`].
+    #[cfg(feature = "std")]
+    ImportSource(Box<dyn Error + Send + Sync>),
+    /// A snapshot can only be imported into a storage without commit nor uncommitted changes.
+    ImportIntoNonEmpty,
+    /// The imported snapshot has no commit.
+    EmptySnapshot,
+    /// The tries of the imported snapshot have the height `stored`, not the height `requested` of
+    /// the storage.
+    SnapshotHeightMismatch { stored: u8, requested: u8 },
+    /// The imported snapshot has the trie `identifier`, which has no trusted root hash.
+    UntrustedTrie { identifier: ByteVec },
+    /// A trie of the imported snapshot doesn't have its trusted root hash.
+    ImportRootMismatch(Box<ImportRootMismatch>),
+    /// A proof node is invalid, or a key of a trie known only by its root hash is not proven, see
+    /// [`crate::BonsaiStorage::set_proven_root`].
+    Proof(Box<ProofVerificationError>),
+    /// Error when applying a [`crate::ChangeBatch`].
+    Replay(ReplayError),
+    /// The changes of a commit could not be given to the [`crate::ChangeSink`] of the storage.
+    ChangeSink(ChangeSinkError),
+    /// A hook added with [`crate::BonsaiStorage::add_commit_hook`] aborted the commit `id` for this
+    /// `reason`.
+    CommitHookAborted { id: u64, reason: String },
+    /// The transactional state being merged changed these `(identifier, key)` leaves, which were also
+    /// changed by commits of the storage after the transactional state was created.
+    MergeConflict(Vec<(ByteVec, BitVec)>),
     /// Error from the underlying database.
     Database(DatabaseError),
-    /// Error when decoding a node
-    NodeDecodeError(parity_scale_codec::Error),
+    /// The node at `path` in the trie `identifier` is referenced by its parent but is not in the
+    /// database.
+    NodeNotFound { identifier: ByteVec, path: Path },
+    /// The root node of the trie `identifier` is not loaded.
+    RootNotFound { identifier: ByteVec },
+    /// The value stored in the database at `key` could not be decoded.
+    DecodeError {
+        key: ByteVec,
+        source: parity_scale_codec::Error,
+    },
+    /// A node of a trie could not be decoded.
+    CorruptedNode(Box<CorruptedNode>),
+    /// The trie log entry at `key` could not be decoded, see [`crate::ChangeBatch::deserialize`].
+    InvalidTrieLogKey { key: ByteVec },
+    /// The storage has uncommitted changes, which must be committed or discarded first.
+    UncommittedChanges,
+    /// The trie `identifier` has uncommitted changes, which must be committed or discarded first.
+    TrieUncommittedChanges { identifier: ByteVec },
+    /// The trie `identifier` is only known by its root hash and can't be modified, see
+    /// [`crate::BonsaiStorage::set_proven_root`].
+    ProvenTrieReadOnly { identifier: ByteVec },
+    /// The trie `identifier` has no proven root hash to insert proof nodes below, see
+    /// [`crate::BonsaiStorage::set_proven_root`].
+    NoProvenRoot { identifier: ByteVec },
     /// Malformated trie key.
     KeyLength { expected: usize, got: usize },
+    /// A node would be at `height`, past the height `max_height` of its trie.
+    HeightOverflow { height: usize, max_height: u8 },
+    /// The trie `identifier` was committed with the height `stored`, not with the height
+    /// `requested` of the storage.
+    TrieHeightMismatch {
+        identifier: ByteVec,
+        stored: u8,
+        requested: u8,
+    },
+    /// The felt is not a valid key of a trie of height 251, as it is not lower than 2^251.
+    FeltKeyOutOfRange(Felt),
+    /// The leaf count of the trie `identifier` would become negative, the stored count does not
+    /// match the leaves of the trie.
+    LeafCountUnderflow { identifier: ByteVec },
+    /// The savepoint was discarded by a commit or by a rollback to an earlier savepoint.
+    SavepointNotFound(SavepointId),
+    /// The storage was opened read-only, see [`crate::BonsaiStorageBuilder::read_only`].
+    ReadOnly,
+    /// The storage was modified after the commit was prepared with
+    /// [`crate::BonsaiStorage::prepare_commit`].
+    PreparedCommitStale,
+    /// The operation was stopped by its [`crate::CancellationToken`] before it changed anything.
+    Cancelled,
+    /// The trie `child` can't be linked to a parent which is itself or one of its descendants, see
+    /// [`crate::BonsaiStorage::link_child_trie`].
+    ChildTrieCycle { child: ByteVec },
+    /// The database has the schema `version`, written by a newer version of this crate which
+    /// supports up to `supported`, see [`crate::migrations`].
+    UnsupportedSchemaVersion { version: u32, supported: u32 },
+}
+
+/// The node at `path` in the trie `identifier`, stored at `key_bytes`, could not be decoded, see
+/// [`crate::BonsaiStorageConfig::quarantine_corrupted_nodes`].
+#[derive(Debug)]
+pub struct CorruptedNode {
+    pub identifier: ByteVec,
+    pub path: Path,
+    pub key_bytes: ByteVec,
+    pub source: parity_scale_codec::Error,
+}
+
+/// The trie `identifier` of a snapshot has the root hash `got` instead of the trusted `expected`
+/// one, see [`crate::BonsaiStorage::import_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportRootMismatch {
+    pub identifier: ByteVec,
+    pub expected: Felt,
+    pub got: Felt,
+}
+
+/// Why a [`crate::BonsaiStorageConfig`] is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `snapshot_interval` is 0.
+    ZeroSnapshotInterval,
+    /// `max_saved_trie_logs` is smaller than `snapshot_interval`, the commits between two
+    /// snapshots could not all be reached.
+    TrieLogsShorterThanSnapshotInterval {
+        max_saved_trie_logs: usize,
+        snapshot_interval: u64,
+    },
+    /// `auto_compaction` is `Some(0)`.
+    ZeroAutoCompaction,
+    /// `max_batch_bytes` is `Some(0)`.
+    ZeroMaxBatchBytes,
+    /// The hasher of the trie `identifier` has branch nodes of `arity` children, the tries can
+    /// only be binary.
+    UnsupportedArity { identifier: ByteVec, arity: usize },
+    /// The database was opened with the parameters `stored`, which differ from the `requested`
+    /// ones, see [`crate::StoredConfig`].
+    StoredConfigMismatch {
+        stored: Box<StoredConfig>,
+        requested: Box<StoredConfig>,
+    },
+    /// The height of the tries is neither given nor stored in the database, see
+    /// [`crate::BonsaiStorageBuilder::max_height`].
+    MissingMaxHeight,
+    /// A read-only storage can't send the changes of commits it can't make, see
+    /// [`crate::BonsaiStorageBuilder::read_only`].
+    ReadOnlyChangeSink,
+    /// The database has the schema `version` and must be upgraded, which a read-only storage
+    /// can't do, see [`crate::migrations`].
+    ReadOnlyNeedsMigration { version: u32 },
+}
+
+/// Error when managing database snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Only the latest commit `latest_id` can be snapshotted, not commit `id`.
+    NotLatestCommit { id: u64, latest_id: Option<u64> },
+}
+
+/// Why a [`crate::ChangeBatch`] could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The storage has uncommitted changes.
+    UncommittedChanges,
+    /// Commit `id` already exists with different changes.
+    CommitExists { id: u64 },
+    /// Commit `id` is older than the latest commit of the storage.
+    OlderThanLatest { id: u64, latest_id: u64 },
+    /// Commit `id` was not made from the current state of the storage: the values it replaces
+    /// are not the ones recorded in the batch.
+    StateMismatch { id: u64 },
+}
+
+/// Error when working with a [`crate::TrieShard`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardError {
+    /// The trie `identifier` is checked out by a shard, and can only be used through it.
+    CheckedOut { identifier: ByteVec },
+    /// The trie `identifier` is not checked out by the shard.
+    NotInShard { identifier: ByteVec },
+    /// `count` tries are checked out by shards, which the operation could rewrite.
+    OpenShards { count: usize },
+    /// The trie `identifier` is given twice to [`crate::SharedBonsaiStorage::open_shard`].
+    DuplicateTrie { identifier: ByteVec },
+    /// The trie `identifier` has uncommitted changes, and can't be checked out.
+    UncommittedChanges { identifier: ByteVec },
+    /// The shard commits `id`, which is older than the latest commit `latest_id` of the storage.
+    OlderThanLatest { id: u64, latest_id: u64 },
+}
+
+/// Why the changes of a commit could not be given to the [`crate::ChangeSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeSinkError {
+    /// The sink refused the changes of commit `id` for this `reason`.
+    Refused { id: u64, reason: String },
+    /// The changes of `waiting` earlier commits could not be sent yet, and there is no room left
+    /// to buffer the changes of commit `id`.
+    BufferFull { id: u64, waiting: usize },
 }
 
 impl<DatabaseError: DBError> core::convert::From<DatabaseError>
@@ -33,14 +231,6 @@ impl<DatabaseError: DBError> core::convert::From<DatabaseError>
     }
 }
 
-impl<DatabaseError: DBError> core::convert::From<parity_scale_codec::Error>
-    for BonsaiStorageError<DatabaseError>
-{
-    fn from(value: parity_scale_codec::Error) -> Self {
-        Self::NodeDecodeError(value)
-    }
-}
-
 #[cfg(feature = "std")]
 impl<DatabaseError> Display for BonsaiStorageError<DatabaseError>
 where
@@ -49,14 +239,261 @@ where
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BonsaiStorageError::Trie(e) => write!(f, "Trie error: {}", e),
+            BonsaiStorageError::Config(e) => write!(f, "Invalid configuration: {}", e),
             BonsaiStorageError::GoTo(e) => write!(f, "GoTo error: {}", e),
             BonsaiStorageError::Transaction(e) => write!(f, "Transaction error: {}", e),
             BonsaiStorageError::Merge(e) => write!(f, "Merge error: {}", e),
+            BonsaiStorageError::Snapshot(e) => write!(f, "Snapshot error: {}", e),
+            BonsaiStorageError::BulkLoad(e) => write!(f, "Bulk load error: {}", e),
+            BonsaiStorageError::Shard(e) => write!(f, "Shard error: {}", e),
+            BonsaiStorageError::ExportTarget(e) => write!(f, "Export target error: {}", e),
+            BonsaiStorageError::ImportSource(e) => write!(f, "Import source error: {}", e),
+            BonsaiStorageError::ImportIntoNonEmpty => {
+                write!(f, "A snapshot can only be imported into an empty storage")
+            }
+            BonsaiStorageError::EmptySnapshot => write!(f, "The snapshot has no commit"),
+            BonsaiStorageError::SnapshotHeightMismatch { stored, requested } => write!(
+                f,
+                "The tries of the snapshot have the height {stored}, not {requested}"
+            ),
+            BonsaiStorageError::UntrustedTrie { identifier } => write!(
+                f,
+                "Trie {:?} of the snapshot has no trusted root hash",
+                identifier.as_slice()
+            ),
+            BonsaiStorageError::ImportRootMismatch(e) => write!(
+                f,
+                "Trie {:?} of the snapshot has the root hash {:#x} instead of {:#x}",
+                e.identifier.as_slice(),
+                e.got,
+                e.expected
+            ),
+            BonsaiStorageError::Replay(e) => write!(f, "Replay error: {}", e),
+            BonsaiStorageError::Proof(e) => write!(f, "Proof error: {}", e),
+            BonsaiStorageError::ChangeSink(e) => write!(f, "Change sink error: {}", e),
+            BonsaiStorageError::CommitHookAborted { id, reason } => {
+                write!(f, "Commit {id} aborted by a hook: {reason}")
+            }
+            BonsaiStorageError::MergeConflict(keys) => {
+                write!(f, "Merge conflict on {} keys:", keys.len())?;
+                for (identifier, key) in keys {
+                    write!(f, " {:?}:{:b}", identifier.as_slice(), key)?;
+                }
+                Ok(())
+            }
             BonsaiStorageError::Database(e) => write!(f, "Database error: {}", e),
-            BonsaiStorageError::NodeDecodeError(e) => write!(f, "Node decode error: {}", e),
+            BonsaiStorageError::NodeNotFound { identifier, path } => write!(
+                f,
+                "Node {:b} of trie {:?} not found in the database",
+                path.0,
+                identifier.as_slice()
+            ),
+            BonsaiStorageError::RootNotFound { identifier } => {
+                write!(f, "Root node of trie {:?} not found", identifier.as_slice())
+            }
+            BonsaiStorageError::DecodeError { key, source } => write!(
+                f,
+                "Could not decode value at key {:?}: {}",
+                key.as_slice(),
+                source
+            ),
+            BonsaiStorageError::CorruptedNode(e) => write!(
+                f,
+                "Corrupted node {:b} of trie {:?} at key {:?}: {}",
+                e.path.0,
+                e.identifier.as_slice(),
+                e.key_bytes.as_slice(),
+                e.source
+            ),
+            BonsaiStorageError::InvalidTrieLogKey { key } => {
+                write!(f, "Invalid trie log key {:?}", key.as_slice())
+            }
+            BonsaiStorageError::UncommittedChanges => {
+                write!(f, "The storage has uncommitted changes")
+            }
+            BonsaiStorageError::TrieUncommittedChanges { identifier } => write!(
+                f,
+                "Trie {:?} has uncommitted changes",
+                identifier.as_slice()
+            ),
+            BonsaiStorageError::ProvenTrieReadOnly { identifier } => write!(
+                f,
+                "Trie {:?} is only known by its root hash and can't be modified",
+                identifier.as_slice()
+            ),
+            BonsaiStorageError::NoProvenRoot { identifier } => {
+                write!(f, "Trie {:?} has no proven root", identifier.as_slice())
+            }
             BonsaiStorageError::KeyLength { expected, got } => {
                 write!(f, "Malformated key length: expected {expected}, got {got}")
             }
+            BonsaiStorageError::HeightOverflow { height, max_height } => write!(
+                f,
+                "Node height {height} is past the height {max_height} of the trie"
+            ),
+            BonsaiStorageError::TrieHeightMismatch {
+                identifier,
+                stored,
+                requested,
+            } => write!(
+                f,
+                "Trie {:?} has the height {}, not {}",
+                identifier.as_slice(),
+                stored,
+                requested
+            ),
+            BonsaiStorageError::FeltKeyOutOfRange(key) => {
+                write!(f, "Felt key {:#x} is not lower than 2^251", key)
+            }
+            BonsaiStorageError::LeafCountUnderflow { identifier } => write!(
+                f,
+                "Leaf count of trie {:?} does not match its leaves",
+                identifier.as_slice()
+            ),
+            BonsaiStorageError::SavepointNotFound(savepoint) => {
+                write!(f, "Savepoint {:?} does not exist", savepoint)
+            }
+            BonsaiStorageError::ReadOnly => write!(f, "The storage is read-only"),
+            BonsaiStorageError::PreparedCommitStale => {
+                write!(f, "The tries changed since the commit was prepared")
+            }
+            BonsaiStorageError::Cancelled => write!(f, "The operation was cancelled"),
+            BonsaiStorageError::ChildTrieCycle { child } => write!(
+                f,
+                "Trie {:?} can't be a child of itself or of one of its children",
+                child.as_slice()
+            ),
+            BonsaiStorageError::UnsupportedSchemaVersion { version, supported } => write!(
+                f,
+                "The database has schema version {version}, this version supports up to {supported}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::ZeroSnapshotInterval => {
+                write!(f, "snapshot_interval must be greater than 0")
+            }
+            ConfigError::TrieLogsShorterThanSnapshotInterval {
+                max_saved_trie_logs,
+                snapshot_interval,
+            } => write!(
+                f,
+                "max_saved_trie_logs ({}) is smaller than snapshot_interval ({}), the commits \
+                 between two snapshots could not all be reached",
+                max_saved_trie_logs, snapshot_interval
+            ),
+            ConfigError::ZeroAutoCompaction => write!(f, "auto_compaction must be greater than 0"),
+            ConfigError::ZeroMaxBatchBytes => write!(f, "max_batch_bytes must be greater than 0"),
+            ConfigError::UnsupportedArity { identifier, arity } => write!(
+                f,
+                "the hasher of trie {:?} has an arity of {}, only binary tries are supported",
+                identifier.as_slice(),
+                arity
+            ),
+            ConfigError::StoredConfigMismatch { stored, requested } => write!(
+                f,
+                "the database was opened with {:?}, not {:?}",
+                stored, requested
+            ),
+            ConfigError::MissingMaxHeight => {
+                write!(f, "the height of the tries is neither given nor stored")
+            }
+            ConfigError::ReadOnlyChangeSink => {
+                write!(f, "a read-only storage can't have a change sink")
+            }
+            ConfigError::ReadOnlyNeedsMigration { version } => write!(
+                f,
+                "the database has the schema version {}, a read-only storage can't upgrade it",
+                version
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::NotLatestCommit { id, latest_id } => write!(
+                f,
+                "Only the latest commit {:?} can be snapshotted, got {}",
+                latest_id, id
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::UncommittedChanges => write!(f, "the storage has uncommitted changes"),
+            ReplayError::CommitExists { id } => {
+                write!(f, "commit {} already exists with different changes", id)
+            }
+            ReplayError::OlderThanLatest { id, latest_id } => write!(
+                f,
+                "commit {} is older than the latest commit {}",
+                id, latest_id
+            ),
+            ReplayError::StateMismatch { id } => write!(
+                f,
+                "commit {} was not made from the current state of the storage",
+                id
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for ChangeSinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeSinkError::Refused { id, reason } => {
+                write!(f, "changes of commit {} were refused: {}", id, reason)
+            }
+            ChangeSinkError::BufferFull { id, waiting } => write!(
+                f,
+                "changes of commit {} cannot be buffered, {} commits are waiting to be sent",
+                id, waiting
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for ShardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShardError::CheckedOut { identifier } => write!(
+                f,
+                "trie {:?} is checked out by a shard",
+                identifier.as_slice()
+            ),
+            ShardError::NotInShard { identifier } => {
+                write!(f, "trie {:?} is not in the shard", identifier.as_slice())
+            }
+            ShardError::OpenShards { count } => {
+                write!(f, "{} tries are checked out by shards", count)
+            }
+            ShardError::DuplicateTrie { identifier } => {
+                write!(f, "trie {:?} is given twice", identifier.as_slice())
+            }
+            ShardError::UncommittedChanges { identifier } => write!(
+                f,
+                "trie {:?} has uncommitted changes",
+                identifier.as_slice()
+            ),
+            ShardError::OlderThanLatest { id, latest_id } => write!(
+                f,
+                "commit {} is older than the latest commit {}",
+                id, latest_id
+            ),
         }
     }
 }
@@ -0,0 +1,175 @@
+//! Ethereum Merkle-Patricia tries, stored in a [`crate::BonsaiStorage`] next to the Starknet
+//! tries, so that a bridge can keep both of its commitments with the same commits, trie logs and
+//! snapshots.
+//!
+//! An Ethereum trie is a trie of the storage whose leaves keep their Ethereum key and value in
+//! their raw payload, see [`crate::BonsaiStorage::insert_raw`]. Its leaf keys are the first
+//! `max_height` bits of the Keccak-256 hash of the Ethereum keys, so the keys can have any length.
+//! The hexary trie of Ethereum, with its RLP encoded nodes, is only built to compute the root hash,
+//! see [`trie_root`].
+
+use crate::{
+    hashers::{ByteHasher, Keccak256},
+    vec, BTreeMap, BitVec, ByteVec, EncodeExt, Vec,
+};
+use parity_scale_codec::Decode;
+use starknet_types_core::felt::Felt;
+
+/// Root hash of an empty Ethereum trie, the hash of the RLP encoding of an empty string.
+pub const EMPTY_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// Root hash of the Ethereum trie holding the `(key, value)` pairs of `leaves`, the last value of
+/// a key taking precedence. Empty values are not part of the trie, as in Ethereum.
+pub fn trie_root<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    leaves: impl IntoIterator<Item = (K, V)>,
+) -> [u8; 32] {
+    let leaves: BTreeMap<Vec<u8>, V> = leaves
+        .into_iter()
+        .filter(|(_, value)| !value.as_ref().is_empty())
+        .map(|(key, value)| (nibbles(key.as_ref()), value))
+        .collect();
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let leaves: Vec<(&[u8], &[u8])> = leaves
+        .iter()
+        .map(|(key, value)| (key.as_slice(), value.as_ref()))
+        .collect();
+    keccak(&[&encode_node(&leaves, 0)])
+}
+
+/// Encoding of the node of the sorted `leaves`, which all have the same first `depth` nibbles.
+fn encode_node(leaves: &[(&[u8], &[u8])], depth: usize) -> Vec<u8> {
+    if let [(key, value)] = leaves {
+        return rlp_list(&[&hex_prefix(&key[depth..], true), &rlp_bytes(value)]);
+    }
+    let (first, last) = (leaves[0].0, leaves[leaves.len() - 1].0);
+    let common = first[depth..]
+        .iter()
+        .zip(&last[depth..])
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common > 0 {
+        let child = encode_node(leaves, depth + common);
+        return rlp_list(&[
+            &hex_prefix(&first[depth..depth + common], false),
+            &node_reference(child),
+        ]);
+    }
+
+    // Branch: the leaves are sorted, so a key ending here comes first and the others are grouped
+    // by their next nibble.
+    let mut items: Vec<Vec<u8>> = Vec::with_capacity(17);
+    let (value, mut rest) = match leaves {
+        [(key, value), rest @ ..] if key.len() == depth => (rlp_bytes(value), rest),
+        _ => (rlp_bytes(&[]), leaves),
+    };
+    for nibble in 0..16 {
+        let count = rest
+            .iter()
+            .take_while(|(key, _)| key[depth] == nibble)
+            .count();
+        let (children, next) = rest.split_at(count);
+        items.push(if children.is_empty() {
+            rlp_bytes(&[])
+        } else {
+            node_reference(encode_node(children, depth + 1))
+        });
+        rest = next;
+    }
+    items.push(value);
+    let items: Vec<&[u8]> = items.iter().map(Vec::as_slice).collect();
+    rlp_list(&items)
+}
+
+/// How a node is referred to by its parent: embedded if its encoding is shorter than a hash.
+fn node_reference(encoded: Vec<u8>) -> Vec<u8> {
+    if encoded.len() < 32 {
+        encoded
+    } else {
+        rlp_bytes(&keccak(&[&encoded]))
+    }
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// Hex-prefix encoding of a path of nibbles, with the flag telling leaves and extensions apart.
+fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 0x20 } else { 0x00 };
+    let mut bytes = vec![];
+    let rest = if path.len() % 2 == 1 {
+        bytes.push(flag | 0x10 | path[0]);
+        &path[1..]
+    } else {
+        bytes.push(flag);
+        path
+    };
+    bytes.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    rlp_bytes(&bytes)
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if let [byte @ 0..=0x7f] = bytes {
+        return vec![*byte];
+    }
+    let mut encoded = rlp_length(bytes.len(), 0x80);
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// RLP list of the already encoded `items`.
+fn rlp_list(items: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = rlp_length(items.iter().map(|item| item.len()).sum(), 0xc0);
+    for item in items {
+        encoded.extend_from_slice(item);
+    }
+    encoded
+}
+
+fn rlp_length(len: usize, offset: u8) -> Vec<u8> {
+    if len <= 55 {
+        return vec![offset + len as u8];
+    }
+    let len = len.to_be_bytes();
+    let len = &len[len.iter().take_while(|byte| **byte == 0).count()..];
+    let mut encoded = vec![offset + 55 + len.len() as u8];
+    encoded.extend_from_slice(len);
+    encoded
+}
+
+fn keccak(bytes: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    for bytes in bytes {
+        hasher.update(bytes);
+    }
+    hasher.finalize()
+}
+
+/// Leaf key of the Ethereum key `key` in a trie of height `max_height`.
+pub(crate) fn leaf_key(key: &[u8], max_height: u8) -> BitVec {
+    let mut leaf_key = BitVec::from_vec(keccak(&[key]).to_vec());
+    leaf_key.truncate(max_height as usize);
+    leaf_key
+}
+
+/// Raw payload of the leaf of `key`, and the value of the leaf which commits to it.
+pub(crate) fn encode_payload(key: &[u8], value: &[u8]) -> (ByteVec, Felt) {
+    let payload = (key, value).encode_bytevec();
+    let mut digest = keccak(&[&payload]);
+    digest[0] &= 0x03;
+    (payload, Felt::from_bytes_be(&digest))
+}
+
+/// The key and the value of a raw payload written by [`encode_payload`].
+pub(crate) fn decode_payload(
+    mut payload: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), parity_scale_codec::Error> {
+    <(Vec<u8>, Vec<u8>)>::decode(&mut payload)
+}
@@ -0,0 +1,91 @@
+//! Hashers for tries committing to something else than Starknet state, built on hash functions
+//! of bytes such as Keccak-256 or SHA-256.
+//!
+//! A trie only needs a [`StarkHash`] to hash its nodes: [`FeltHasher`] provides one for any
+//! [`ByteHasher`], by hashing the 32 bytes big-endian encodings of the felts one after the other.
+//! The 256 bits digest is truncated to its 250 lowest bits to fit in a felt, as is done for
+//! `starknet_keccak`, so the root hashes are not the plain digests of the nodes.
+
+use core::marker::PhantomData;
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// A hash function of bytes with a 256 bits digest, see [`FeltHasher`].
+pub trait ByteHasher {
+    fn new() -> Self;
+    fn update(&mut self, bytes: &[u8]);
+    fn finalize(self) -> [u8; 32];
+}
+
+/// The [`StarkHash`] of a [`ByteHasher`], to use it as the hasher of a trie.
+pub struct FeltHasher<B>(PhantomData<fn() -> B>);
+
+impl<B: ByteHasher> FeltHasher<B> {
+    fn hash_felts<'a>(felts: impl IntoIterator<Item = &'a Felt>) -> Felt {
+        let mut hasher = B::new();
+        for felt in felts {
+            hasher.update(&felt.to_bytes_be());
+        }
+        let mut digest = hasher.finalize();
+        digest[0] &= 0x03;
+        Felt::from_bytes_be(&digest)
+    }
+}
+
+impl<B: ByteHasher> StarkHash for FeltHasher<B> {
+    fn hash(felt_0: &Felt, felt_1: &Felt) -> Felt {
+        Self::hash_felts([felt_0, felt_1])
+    }
+
+    /// Digest of all the felts at once, rather than the chained hashes of the Starknet array
+    /// hashing.
+    fn hash_array(felts: &[Felt]) -> Felt {
+        Self::hash_felts(felts)
+    }
+
+    fn hash_single(felt: &Felt) -> Felt {
+        Self::hash_felts([felt])
+    }
+}
+
+/// Keccak-256, as used by Ethereum, not the standardized SHA3-256.
+#[cfg(feature = "keccak")]
+pub struct Keccak256(sha3::Keccak256);
+
+#[cfg(feature = "keccak")]
+impl ByteHasher for Keccak256 {
+    fn new() -> Self {
+        Self(sha3::Digest::new())
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        sha3::Digest::update(&mut self.0, bytes);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        sha3::Digest::finalize(self.0).into()
+    }
+}
+
+#[cfg(feature = "keccak")]
+pub type Keccak256Hasher = FeltHasher<Keccak256>;
+
+#[cfg(feature = "sha256")]
+pub struct Sha256(sha2::Sha256);
+
+#[cfg(feature = "sha256")]
+impl ByteHasher for Sha256 {
+    fn new() -> Self {
+        Self(sha2::Digest::new())
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(&mut self.0, bytes);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        sha2::Digest::finalize(self.0).into()
+    }
+}
+
+#[cfg(feature = "sha256")]
+pub type Sha256Hasher = FeltHasher<Sha256>;
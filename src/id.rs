@@ -6,6 +6,12 @@ pub trait Id: hash::Hash + PartialEq + Eq + PartialOrd + Ord + Debug + Copy + De
     fn to_bytes(&self) -> ByteVec;
     fn as_u64(self) -> u64;
     fn from_u64(v: u64) -> Self;
+    /// Inverse of `to_bytes`, `None` if `bytes` is not a valid id. The default implementation
+    /// reads the big-endian `u64` written by [`BasicId`].
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let id = Self::from_u64(u64::from_be_bytes(bytes.try_into().ok()?));
+        (id.to_bytes().as_slice() == bytes).then_some(id)
+    }
 }
 
 /// A basic ID type that can be used for testing.
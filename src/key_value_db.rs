@@ -1,13 +1,24 @@
-use crate::{format, BitVec, ByteVec, Change as ExternChange};
-use hashbrown::HashMap;
+use crate::{format, BTreeMap, ByteVec, Change as ExternChange, EncodeExt, ToString, Vec};
+use hashbrown::{hash_map::Entry, HashMap};
 use log::trace;
+use parity_scale_codec::Decode;
+use starknet_types_core::felt::Felt;
 
 use crate::{
     bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DatabaseKey},
-    changes::{Change, ChangeBatch, ChangeStore},
+    cancellation::{check_cancelled, CancellationToken},
+    changes::{trie_log_prefix, unframed_trie_log_prefix, Change, ChangeBatch, ChangeStore},
+    databases::ForkDb,
     id::Id,
-    trie::TrieKey,
-    BonsaiStorageConfig, BonsaiStorageError,
+    op_stats::OpCounters,
+    trie::{merkle_node::TrieHasher, trie_db::MetaKeyType, TrieKey},
+    BonsaiStorageConfig, BonsaiStorageError, ChangeSinkPolicy, MergeConflictPolicy, SnapshotError,
+    TransactionalStateInfo,
+};
+#[cfg(feature = "std")]
+use crate::{
+    commit_pins::{CommitGuard, CommitPins},
+    watch::Watches,
 };
 
 /// Crate Trie <= KeyValueDB => BonsaiDatabase
@@ -17,7 +28,39 @@ pub struct KeyValueDB<DB: BonsaiDatabase, ID: Id> {
     pub(crate) db: DB,
     pub(crate) changes_store: ChangeStore,
     pub(crate) config: KeyValueDBConfig,
-    pub(crate) _created_at: Option<ID>,
+    /// Id of the latest commit, or the id the transactional state was created at.
+    pub(crate) latest_id: Option<ID>,
+    /// Id the transactional state was created at, `None` for a regular storage.
+    pub(crate) created_at: Option<ID>,
+    /// Writes made by an in-progress atomic operation (see [`KeyValueDB::begin_staging`]) that are
+    /// only present in the pending batch and are not yet visible when reading from `db`.
+    pub(crate) staged: Option<HashMap<TrieKey, Option<ByteVec>>>,
+    /// Same as `staged` for the trie log entries, by trie log key.
+    pub(crate) staged_trie_logs: Option<BTreeMap<ByteVec, Option<ByteVec>>>,
+    /// Lowest id reverted to since the last snapshot cleanup. Snapshots taken after it belong to
+    /// the discarded commits and are removed before the next snapshot is created.
+    pub(crate) reverted_to: Option<ID>,
+    /// Trie log entries removed since the database was last compacted.
+    pub(crate) removed_since_compaction: u64,
+    /// Latest commit made by [`crate::BonsaiStorage::bulk_load`]. Its trie log doesn't record the
+    /// loaded leaves, so the commits before it can't be reverted to, and the transactional states
+    /// at or after it can't be created from the snapshots before it.
+    pub(crate) bulk_loaded_at: Option<ID>,
+    /// Latest commit undone by a revert whose trie log was kept, up to which
+    /// [`KeyValueDB::advance_to`] can go. The trie logs of the commits after the latest one are
+    /// all kept up to it.
+    pub(crate) reverted_from: Option<ID>,
+    /// Oldest commit whose trie log was kept past `max_saved_trie_logs` because a pinned commit
+    /// needed it. The trie logs from it are pruned once no pinned commit needs them anymore.
+    pub(crate) deferred_pruning: Option<ID>,
+    /// Work done since the last [`crate::BonsaiStorage::take_op_stats`].
+    pub(crate) stats: OpCounters,
+    /// Commits pinned by [`crate::BonsaiStorage::pin_commit`].
+    #[cfg(feature = "std")]
+    pub(crate) pins: CommitPins,
+    /// Leaves watched by [`crate::BonsaiStorage::watch`].
+    #[cfg(feature = "std")]
+    pub(crate) watches: Watches<ID>,
 }
 
 #[derive(Clone, Debug)]
@@ -28,6 +71,28 @@ pub struct KeyValueDBConfig {
     pub max_saved_snapshots: Option<usize>,
     /// Interval of commit between two snapshots creation.
     pub snapshot_interval: u64,
+    /// How conflicts are handled when merging a transactional state.
+    pub merge_conflict_policy: MergeConflictPolicy,
+    /// Maximum number of trie logs replayed to create a transactional state (None = unlimited).
+    pub max_transactional_state_replay: Option<u64>,
+    /// What a commit does when the change sink refuses its changes.
+    pub change_sink_policy: ChangeSinkPolicy,
+    /// Number of trie log entries removed after which the database is compacted (None = never).
+    pub auto_compaction: Option<u64>,
+    /// Bytes after which the batch of a commit is written and a new one started (None = never).
+    pub max_batch_bytes: Option<usize>,
+    /// Hashers of the tries which don't use the one of the storage.
+    pub trie_hashers: HashMap<ByteVec, TrieHasher>,
+    /// Number of leaves up to which a trie is stored as a single record (None = never).
+    pub max_inline_leaves: Option<usize>,
+    /// Move the undecodable nodes found by a migration aside instead of failing.
+    pub quarantine_corrupted_nodes: bool,
+    /// Keep the trie logs undone by a revert until the next commit.
+    pub keep_reverted_trie_logs: bool,
+    /// Number of tries kept in memory after a commit (None = unlimited).
+    pub max_cached_tries: Option<usize>,
+    /// Store zero values instead of removing the leaves set to zero.
+    pub store_zero_values: bool,
 }
 
 impl Default for KeyValueDBConfig {
@@ -36,6 +101,17 @@ impl Default for KeyValueDBConfig {
             max_saved_trie_logs: None,
             max_saved_snapshots: None,
             snapshot_interval: 5,
+            merge_conflict_policy: MergeConflictPolicy::default(),
+            max_transactional_state_replay: None,
+            change_sink_policy: ChangeSinkPolicy::default(),
+            auto_compaction: None,
+            max_batch_bytes: None,
+            trie_hashers: HashMap::new(),
+            max_inline_leaves: None,
+            quarantine_corrupted_nodes: false,
+            keep_reverted_trie_logs: false,
+            max_cached_tries: None,
+            store_zero_values: false,
         }
     }
 }
@@ -46,6 +122,17 @@ impl From<BonsaiStorageConfig> for KeyValueDBConfig {
             max_saved_trie_logs: value.max_saved_trie_logs,
             snapshot_interval: value.snapshot_interval,
             max_saved_snapshots: value.max_saved_snapshots,
+            merge_conflict_policy: value.merge_conflict_policy,
+            max_transactional_state_replay: value.max_transactional_state_replay,
+            change_sink_policy: value.change_sink_policy,
+            auto_compaction: value.auto_compaction,
+            max_batch_bytes: value.max_batch_bytes,
+            trie_hashers: value.trie_hashers,
+            max_inline_leaves: value.max_inline_leaves,
+            quarantine_corrupted_nodes: value.quarantine_corrupted_nodes,
+            keep_reverted_trie_logs: value.keep_reverted_trie_logs,
+            max_cached_tries: value.max_cached_tries,
+            store_zero_values: value.store_zero_values,
         }
     }
 }
@@ -56,6 +143,17 @@ impl From<KeyValueDBConfig> for BonsaiStorageConfig {
             max_saved_trie_logs: val.max_saved_trie_logs,
             snapshot_interval: val.snapshot_interval,
             max_saved_snapshots: val.max_saved_snapshots,
+            merge_conflict_policy: val.merge_conflict_policy,
+            max_transactional_state_replay: val.max_transactional_state_replay,
+            change_sink_policy: val.change_sink_policy,
+            auto_compaction: val.auto_compaction,
+            max_batch_bytes: val.max_batch_bytes,
+            trie_hashers: val.trie_hashers,
+            max_inline_leaves: val.max_inline_leaves,
+            quarantine_corrupted_nodes: val.quarantine_corrupted_nodes,
+            keep_reverted_trie_logs: val.keep_reverted_trie_logs,
+            max_cached_tries: val.max_cached_tries,
+            store_zero_values: val.store_zero_values,
         }
     }
 }
@@ -71,71 +169,1118 @@ where
             db: underline_db,
             changes_store,
             config,
-            _created_at: created_at,
+            latest_id: created_at,
+            created_at,
+            staged: None,
+            staged_trie_logs: None,
+            reverted_to: None,
+            removed_since_compaction: 0,
+            bulk_loaded_at: None,
+            reverted_from: None,
+            deferred_pruning: None,
+            stats: OpCounters::default(),
+            #[cfg(feature = "std")]
+            pins: CommitPins::default(),
+            #[cfg(feature = "std")]
+            watches: Watches::default(),
+        }
+    }
+
+    /// Copy-on-write view of this database at its current state, uncommitted changes included.
+    pub(crate) fn fork(&self) -> KeyValueDB<ForkDb<'_, DB>, ID> {
+        KeyValueDB {
+            db: ForkDb::new(&self.db),
+            changes_store: ChangeStore {
+                current_changes: self.changes_store.current_changes.clone(),
+                log_usage: self.changes_store.log_usage.clone(),
+                root_hashes: self.changes_store.root_hashes.clone(),
+            },
+            config: self.config.clone(),
+            latest_id: self.latest_id,
+            created_at: None,
+            staged: None,
+            staged_trie_logs: None,
+            reverted_to: None,
+            removed_since_compaction: 0,
+            bulk_loaded_at: self.bulk_loaded_at,
+            reverted_from: self.reverted_from,
+            deferred_pruning: self.deferred_pruning,
+            stats: OpCounters::default(),
+            #[cfg(feature = "std")]
+            pins: CommitPins::default(),
+            #[cfg(feature = "std")]
+            watches: Watches::default(),
         }
     }
 
+    /// Start recording writes in memory so that they can be read back before the batch they were
+    /// written to is applied to the database.
+    pub(crate) fn begin_staging(&mut self) {
+        self.staged = Some(HashMap::new());
+        self.staged_trie_logs = Some(BTreeMap::new());
+    }
+
+    pub(crate) fn end_staging(&mut self) {
+        self.staged = None;
+        self.staged_trie_logs = None;
+    }
+
+    fn stage(&mut self, key: &TrieKey, value: Option<ByteVec>) {
+        if let Some(staged) = &mut self.staged {
+            staged.insert(key.clone(), value);
+        }
+    }
+
+    /// Write `value` at `key` in the database, accounted for in the [`OpCounters`].
+    fn db_insert(
+        &mut self,
+        key: &DatabaseKey,
+        value: &[u8],
+        batch: Option<&mut DB::Batch>,
+    ) -> Result<Option<ByteVec>, DB::DatabaseError> {
+        self.stats.bytes_written(key.as_slice().len() + value.len());
+        self.db.insert(key, value, batch)
+    }
+
+    /// Remove `key` without recording it in the trie log of the next commit.
+    pub(crate) fn remove_untracked(
+        &mut self,
+        key: &TrieKey,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.db.remove(&DatabaseKey::from(key), Some(batch))?;
+        self.stage(key, None);
+        Ok(())
+    }
+
+    /// Insert `key` without recording it in the trie log of the next commit, for writes that don't
+    /// change the content of the tries.
+    pub(crate) fn insert_untracked(
+        &mut self,
+        key: &TrieKey,
+        value: &[u8],
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.db_insert(&DatabaseKey::from(key), value, Some(batch))?;
+        self.stage(key, Some(value.into()));
+        Ok(())
+    }
+
+    /// Read the id of the latest commit saved in the database, so that a reopened storage can be
+    /// reverted. Databases written before it was saved have none.
+    pub(crate) fn load_latest_id(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(id) = self.get_id(MetaKeyType::LatestId)? {
+            self.latest_id = Some(id);
+        }
+        Ok(())
+    }
+
+    /// Read the id of the latest bulk load, see [`KeyValueDB::bulk_loaded_at`].
+    pub(crate) fn load_bulk_loaded_at(
+        &mut self,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.bulk_loaded_at = self.get_id(MetaKeyType::BulkLoad)?;
+        Ok(())
+    }
+
+    /// Read the latest commit whose trie log was kept by a revert, see
+    /// [`KeyValueDB::reverted_from`].
+    pub(crate) fn load_reverted_from(
+        &mut self,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.reverted_from = self.get_id(MetaKeyType::RevertedFrom)?;
+        Ok(())
+    }
+
+    /// Read the oldest trie log whose pruning was deferred, see
+    /// [`KeyValueDB::deferred_pruning`].
+    pub(crate) fn load_deferred_pruning(
+        &mut self,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.deferred_pruning = self.get_id(MetaKeyType::DeferredPruning)?;
+        Ok(())
+    }
+
+    fn set_deferred_pruning(
+        &mut self,
+        id: Option<ID>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if id == self.deferred_pruning {
+            return Ok(());
+        }
+        let key = TrieKey::new_meta(MetaKeyType::DeferredPruning, &[]);
+        match id {
+            Some(id) => self.insert_untracked(&key, &id.as_u64().encode_bytevec(), batch)?,
+            None => self.remove_untracked(&key, batch)?,
+        }
+        self.deferred_pruning = id;
+        Ok(())
+    }
+
+    fn set_reverted_from(
+        &mut self,
+        id: Option<ID>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new_meta(MetaKeyType::RevertedFrom, &[]);
+        match id {
+            Some(id) => self.insert_untracked(&key, &id.as_u64().encode_bytevec(), batch)?,
+            None => self.remove_untracked(&key, batch)?,
+        }
+        self.reverted_from = id;
+        Ok(())
+    }
+
+    /// Remove the trie logs kept by the reverts after the latest commit, which a new commit
+    /// replaces.
+    fn discard_reverted_trie_logs(
+        &mut self,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let Some(reverted_from) = self.reverted_from else {
+            return Ok(());
+        };
+        let first = self.latest_id.map_or(0, |id| id.as_u64() + 1);
+        for id in first..=reverted_from.as_u64() {
+            self.remove_trie_log(ID::from_u64(id), batch)?;
+        }
+        self.set_reverted_from(None, batch)
+    }
+
+    fn get_id(
+        &self,
+        key_type: MetaKeyType,
+    ) -> Result<Option<ID>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new_meta(key_type, &[]);
+        let Some(id) = self.db.get(&DatabaseKey::from(&key))? else {
+            return Ok(None);
+        };
+        let id =
+            u64::decode(&mut id.as_slice()).map_err(|source| BonsaiStorageError::DecodeError {
+                key: key.as_slice().into(),
+                source,
+            })?;
+        Ok(Some(ID::from_u64(id)))
+    }
+
+    fn set_latest_id(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new_meta(MetaKeyType::LatestId, &[]);
+        self.db_insert(
+            &DatabaseKey::from(&key),
+            &id.as_u64().encode_bytevec(),
+            Some(batch),
+        )?;
+        self.latest_id = Some(id);
+        Ok(())
+    }
+
+    /// Entries of the trie log of commit `id`, in key order, staged writes included.
+    fn trie_log_entries(
+        &self,
+        id: ID,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.trie_log_entries_by_prefix(id.to_bytes())
+    }
+
+    /// Entries of the trie logs whose key starts with `prefix`, in key order, staged writes
+    /// included.
+    fn trie_log_entries_by_prefix(
+        &self,
+        prefix: ByteVec,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, BonsaiStorageError<DB::DatabaseError>> {
+        let entries = self.db.get_by_prefix(&DatabaseKey::TrieLog(&prefix))?;
+        let Some(staged) = &self.staged_trie_logs else {
+            return Ok(entries);
+        };
+        let mut entries: BTreeMap<_, _> = entries.into_iter().collect();
+        for (key, value) in staged
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+        {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    /// Entries of the trie logs of the commits following `id`, by commit, staged writes included.
+    #[allow(clippy::type_complexity)]
+    fn trie_logs_after(
+        &self,
+        id: ID,
+    ) -> Result<BTreeMap<ID, Vec<(ByteVec, ByteVec)>>, BonsaiStorageError<DB::DatabaseError>> {
+        match self.latest_id {
+            Some(latest_id) => self.trie_logs_between(id, latest_id),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Entries of the trie logs of the commits following `id` up to `up_to`, by commit, staged
+    /// writes included. Only the trie logs of these commits are read.
     #[allow(clippy::type_complexity)]
+    fn trie_logs_between(
+        &self,
+        id: ID,
+        up_to: ID,
+    ) -> Result<BTreeMap<ID, Vec<(ByteVec, ByteVec)>>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut trie_logs = BTreeMap::new();
+        for cur_id in id.as_u64() + 1..=up_to.as_u64() {
+            let cur_id = ID::from_u64(cur_id);
+            let entries = self.trie_log_entries(cur_id)?;
+            if !entries.is_empty() {
+                trie_logs.insert(cur_id, entries);
+            }
+        }
+        Ok(trie_logs)
+    }
+
+    fn insert_trie_log(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.db_insert(&DatabaseKey::TrieLog(key), value, Some(batch))?;
+        if let Some(staged) = &mut self.staged_trie_logs {
+            staged.insert(key.into(), Some(value.into()));
+        }
+        Ok(())
+    }
+
+    fn remove_trie_log_entry(
+        &mut self,
+        key: &[u8],
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.db.remove(&DatabaseKey::TrieLog(key), Some(batch))?;
+        if let Some(staged) = &mut self.staged_trie_logs {
+            staged.insert(key.into(), None);
+        }
+        self.removed_since_compaction += 1;
+        Ok(())
+    }
+
+    /// Changes made to the flat leaf storage by commit `id`, keyed by flat database key.
     pub(crate) fn get_changes(
         &self,
-        _id: ID,
-    ) -> Result<HashMap<BitVec, ExternChange>, BonsaiStorageError<DB::DatabaseError>> {
-        // if self.changes_store.id_queue.contains(&id) {
-        //     let mut leaf_changes = HashMap::new();
-        //     let changes = ChangeBatch::deserialize(
-        //         &id,
-        //         self.db
-        //             .get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?,
-        //     );
-        //     for (k, v) in changes.0 {
-        //         if let TrieKey::Flat(k) = k {
-        //             leaf_changes.insert(
-        //                 bytes_to_bitvec(&k),
-        //                 ExternChange {
-        //                     // SAFETY: We are sure that the values are valid Felt because they can be saved only by our crate
-        //                     old_value: v.old_value.map(|x| Felt::decode(&mut x.as_ref()).unwrap()),
-        //                     new_value: v.new_value.map(|x| Felt::decode(&mut x.as_ref()).unwrap()),
-        //                 },
-        //             );
-        //         }
-        //     }
-        //     Ok(leaf_changes)
-        // } else {
-        //     Err(BonsaiStorageError::GoTo(
-        //         "ID asked isn't in our ID records".to_string(),
-        //     ))
-        // }
-        todo!()
+        id: ID,
+    ) -> Result<HashMap<ByteVec, ExternChange>, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(leaf_changes(self.get_trie_log(id)?))
+    }
+
+    /// Same as `get_changes` for the trie `identifier`, reading only its entries of the trie log.
+    /// The trie logs written before their entries were framed by trie are read whole, and the
+    /// leaves of the other tries are returned as well for them.
+    pub(crate) fn get_trie_changes(
+        &self,
+        id: ID,
+        identifier: &[u8],
+    ) -> Result<HashMap<ByteVec, ExternChange>, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_trie_log(id)?;
+        let mut entries = self.trie_log_entries_by_prefix(trie_log_prefix(&id, identifier))?;
+        entries.extend(self.trie_log_entries_by_prefix(unframed_trie_log_prefix(&id))?);
+        Ok(leaf_changes(ChangeBatch::deserialize(&id, entries)?))
+    }
+
+    /// Changes made to the metadata by commit `id`, keyed by metadata key.
+    pub(crate) fn get_meta_changes(
+        &self,
+        id: ID,
+    ) -> Result<HashMap<ByteVec, Option<ByteVec>>, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self
+            .get_trie_log(id)?
+            .0
+            .into_iter()
+            .filter_map(|(key, change)| match key {
+                TrieKey::Meta(key) if !MetaKeyType::is_maintained_by_commit(&key) => {
+                    Some((key, change.new_value))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub(crate) fn get_trie_log(
+        &self,
+        id: ID,
+    ) -> Result<ChangeBatch, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_trie_log(id)?;
+        ChangeBatch::deserialize(&id, self.trie_log_entries(id)?)
+    }
+
+    fn check_trie_log(&self, id: ID) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if !self.has_trie_log(id) {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} isn't in our trie log records",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Net changes made to the flat leaf storage by the commits following `id`, up to the latest one.
+    pub(crate) fn get_changes_since(
+        &self,
+        id: ID,
+    ) -> Result<HashMap<ByteVec, ExternChange>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut leaf_changes: HashMap<ByteVec, ExternChange> = HashMap::new();
+        let Some(latest_id) = self.latest_id else {
+            return Ok(leaf_changes);
+        };
+        for cur_id in id.as_u64() + 1..=latest_id.as_u64() {
+            for (key, change) in self.get_changes(ID::from_u64(cur_id))? {
+                match leaf_changes.entry(key) {
+                    Entry::Occupied(mut entry) => entry.get_mut().new_value = change.new_value,
+                    Entry::Vacant(entry) => {
+                        entry.insert(change);
+                    }
+                }
+            }
+        }
+        leaf_changes.retain(|_, change| change.old_value != change.new_value);
+        Ok(leaf_changes)
+    }
+
+    /// Commits whose trie log has entries in the database, in increasing order.
+    pub(crate) fn trie_log_ids(&self) -> Result<Vec<ID>, BonsaiStorageError<DB::DatabaseError>> {
+        // Commit IDs of a given type all have the same length.
+        let id_len = ID::from_u64(0).to_bytes().len();
+        let mut ids = Vec::new();
+        // Only the keys are read, they start with their commit.
+        for key in self.db.get_keys_by_prefix(&DatabaseKey::TrieLog(&[]))? {
+            let id = key
+                .get(..id_len)
+                .and_then(ID::from_bytes)
+                .ok_or_else(|| BonsaiStorageError::InvalidTrieLogKey { key: key.clone() })?;
+            if ids.last() != Some(&id) {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Bytes used by the entries of the trie log of commit `id`, keys included, or `None` if it
+    /// has none in the database.
+    pub(crate) fn trie_log_size(
+        &self,
+        id: ID,
+    ) -> Result<Option<u64>, BonsaiStorageError<DB::DatabaseError>> {
+        let entries = self.trie_log_entries(id)?;
+        Ok((!entries.is_empty()).then(|| {
+            entries
+                .iter()
+                .map(|(key, value)| (key.len() + value.len()) as u64)
+                .sum()
+        }))
+    }
+
+    /// Whether the trie log of commit `id` is still in the database.
+    pub(crate) fn has_trie_log(&self, id: ID) -> bool {
+        let Some(latest_id) = self.latest_id else {
+            return false;
+        };
+        id <= latest_id && id.as_u64() >= self.oldest_trie_log(latest_id)
     }
 
     pub(crate) fn commit(&mut self, id: ID) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        // Insert flat db changes
         let mut batch = self.db.create_batch();
+        if let Err(err) = self.write_commit(id, &mut batch, Some(&mut 0)) {
+            self.discard_watched_changes();
+            return Err(err);
+        }
+        self.write_batch(batch)?;
+        self.auto_compact()
+    }
+
+    /// Same as `commit` but the trie logs are written to `batch` instead of being applied directly.
+    pub(crate) fn commit_to_batch(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.write_commit(id, batch, None)
+    }
+
+    /// Write the trie logs of commit `id` to `batch`. With `batch_bytes`, the bytes written to
+    /// `batch` so far, it is written early once it is full, see [`KeyValueDB::flush_full_batch`].
+    fn write_commit(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+        mut batch_bytes: Option<&mut usize>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.discard_reverted_trie_logs(batch)?;
+        let log_usage = core::mem::take(&mut self.changes_store.log_usage);
+        if self.config.max_saved_trie_logs != Some(0) {
+            // Recorded in the trie log as well, so that reverting the commit removes it.
+            let id_len = id.to_bytes().len() as u64;
+            for (identifier, (entries, bytes)) in log_usage {
+                let key = log_usage_key(id, &identifier);
+                let bytes = bytes + entries * id_len;
+                self.insert_in_trie(&identifier, &key, &bytes.encode_bytevec(), Some(batch))?;
+            }
+        }
+        self.write_root_hashes(id, batch)?;
         let current_changes = core::mem::take(&mut self.changes_store.current_changes);
         log::debug!("Committing id {id:?}");
+        #[cfg(feature = "std")]
+        self.watches.collect(id, &current_changes);
 
         if self.config.max_saved_trie_logs != Some(0) {
             // optim when trie logs are disabled.
             for (key, change) in current_changes.serialize(&id).iter() {
-                self.db
-                    .insert(&DatabaseKey::TrieLog(key), change, Some(&mut batch))?;
+                self.insert_trie_log(key, change, batch)?;
+                if let Some(batch_bytes) = batch_bytes.as_deref_mut() {
+                    self.flush_full_batch(batch, batch_bytes, key.len() + change.len())?;
+                }
             }
-            self.db.write_batch(batch)?;
 
-            if let Some(id) = self
-                .config
-                .max_saved_trie_logs
-                .and_then(|max_saved_trie_logs| id.as_u64().checked_sub(max_saved_trie_logs as _))
-            {
+            self.prune_trie_logs(id, batch)?;
+        }
+        self.set_latest_id(id, batch)
+    }
+
+    /// Account for `bytes` written to `batch`, on top of `batch_bytes`, and write it to start a new
+    /// one once they reach `max_batch_bytes`. Only for batches that don't need to be applied at
+    /// once.
+    pub(crate) fn flush_full_batch(
+        &mut self,
+        batch: &mut DB::Batch,
+        batch_bytes: &mut usize,
+        bytes: usize,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let Some(max_batch_bytes) = self.config.max_batch_bytes else {
+            return Ok(());
+        };
+        *batch_bytes += bytes;
+        if *batch_bytes >= max_batch_bytes {
+            let full = core::mem::replace(batch, self.db.create_batch());
+            self.db.write_batch(full)?;
+            *batch_bytes = 0;
+        }
+        Ok(())
+    }
+
+    /// Same as `commit_to_batch` for the tries written by a bulk load, whose root hashes go from
+    /// the empty one to the loaded one in `root_hashes`, by identifier. The trie log of the commit
+    /// doesn't record the loaded leaves.
+    pub(crate) fn commit_bulk_load(
+        &mut self,
+        id: ID,
+        root_hashes: impl IntoIterator<Item = (ByteVec, (Felt, Felt))>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.changes_store.root_hashes.extend(root_hashes);
+        let key = TrieKey::new_meta(MetaKeyType::BulkLoad, &[]);
+        self.insert_untracked(&key, &id.as_u64().encode_bytevec(), batch)?;
+        self.bulk_loaded_at = Some(id);
+        self.commit_to_batch(id, batch)
+    }
+
+    /// Remove the trie logs which fall out of the `max_saved_trie_logs` window when committing
+    /// `id`, except the ones a pinned commit needs, whose pruning is deferred.
+    fn prune_trie_logs(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let Some(target) = self
+            .config
+            .max_saved_trie_logs
+            .and_then(|max_saved_trie_logs| id.as_u64().checked_sub(max_saved_trie_logs as _))
+        else {
+            return Ok(());
+        };
+        let from = self
+            .deferred_pruning
+            .map_or(target, |deferred| deferred.as_u64().min(target));
+        let up_to = match self.pinned_trie_logs_from() {
+            Some(pinned) => target.min(pinned.saturating_sub(1)),
+            None => target,
+        };
+        if from <= up_to {
+            for id in from..=up_to {
                 log::debug!("Remove by prefix {id:?}");
-                self.db
-                    .remove_by_prefix(&DatabaseKey::TrieLog(&ID::from_u64(id).to_bytes()))?;
+                self.remove_trie_log(ID::from_u64(id), batch)?;
+                self.remove_commit_tag(ID::from_u64(id), batch)?;
+                self.remove_untracked(&commit_meta_key(ID::from_u64(id)), batch)?;
+                self.remove_log_usage(ID::from_u64(id), batch)?;
+            }
+        }
+        let deferred = (up_to < target).then(|| ID::from_u64(from.max(up_to + 1)));
+        self.set_deferred_pruning(deferred, batch)
+    }
+
+    /// Oldest commit whose trie log is needed by a pinned commit.
+    fn pinned_trie_logs_from(&self) -> Option<u64> {
+        #[cfg(feature = "std")]
+        return self.pins.trie_logs_from();
+        #[cfg(not(feature = "std"))]
+        None
+    }
+
+    /// Oldest commit whose trie log is still in the database when `latest_id` is the latest one,
+    /// the trie logs of the commits changing nothing aside.
+    fn oldest_trie_log(&self, latest_id: ID) -> u64 {
+        let Some(max_saved_trie_logs) = self.config.max_saved_trie_logs else {
+            return 0;
+        };
+        let mut oldest = (latest_id.as_u64() + 1).saturating_sub(max_saved_trie_logs as u64);
+        // Without trie logs, there is nothing to keep for the pinned commits.
+        if max_saved_trie_logs != 0 {
+            if let Some(deferred) = self.deferred_pruning {
+                oldest = oldest.min(deferred.as_u64());
+            }
+            if let Some(pinned) = self.pinned_trie_logs_from() {
+                oldest = oldest.min(pinned);
+            }
+        }
+        oldest
+    }
+
+    /// Record the root hashes of the tries changed by commit `id`, see
+    /// [`crate::BonsaiStorage::root_hash_at`]. They are recorded in the trie log as well, so that
+    /// reverting the commit removes them.
+    fn write_root_hashes(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let since_key = TrieKey::new_meta(MetaKeyType::RootHash, &[]);
+        if self.get(&since_key)?.is_none() {
+            // The tries changed by this commit get the root hash they had before it, and the
+            // other ones have not changed since.
+            let since = self.latest_id.unwrap_or(id);
+            self.insert(&since_key, &since.to_bytes(), Some(batch))?;
+        }
+        let root_hashes = core::mem::take(&mut self.changes_store.root_hashes);
+        for (identifier, (old_root_hash, new_root_hash)) in root_hashes {
+            if let Some(latest_id) = self.latest_id {
+                // First change of the trie since the root hashes are recorded. When adding to the
+                // latest commit, as a shard does, the trie had its old root hash until the commit
+                // before.
+                let before = if latest_id == id {
+                    id.as_u64().checked_sub(1).map(ID::from_u64)
+                } else {
+                    Some(latest_id)
+                };
+                let first_change = self.root_hashes(&identifier, id)?.is_empty();
+                if let Some(before) = before.filter(|_| first_change) {
+                    let key = root_hash_key(&identifier, before);
+                    self.insert_in_trie(
+                        &identifier,
+                        &key,
+                        &old_root_hash.encode_bytevec(),
+                        Some(batch),
+                    )?;
+                }
+            }
+            let key = root_hash_key(&identifier, id);
+            self.insert_in_trie(
+                &identifier,
+                &key,
+                &new_root_hash.encode_bytevec(),
+                Some(batch),
+            )?;
+            self.prune_root_hashes(&identifier, id, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the root hashes of the trie `identifier` which fall out of the `max_saved_trie_logs`
+    /// window when committing `id`, except the latest of them which is still its root hash at the
+    /// start of the window.
+    fn prune_root_hashes(
+        &mut self,
+        identifier: &[u8],
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if self.config.max_saved_trie_logs.is_none() {
+            return Ok(());
+        }
+        let start = self.oldest_trie_log(id);
+        let root_hashes = self.root_hashes(identifier, id)?;
+        let older = root_hashes.partition_point(|(root_id, _)| root_id.as_u64() < start);
+        for (root_id, _) in &root_hashes[..older.saturating_sub(1)] {
+            self.remove_untracked(&root_hash_key(identifier, *root_id), batch)?;
+        }
+        Ok(())
+    }
+
+    /// First commit with recorded root hashes, see [`crate::BonsaiStorage::root_hash_at`].
+    pub(crate) fn root_hashes_since(
+        &self,
+    ) -> Result<Option<ID>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new_meta(MetaKeyType::RootHash, &[]);
+        self.get(&key)?
+            .map(|since| {
+                ID::from_bytes(&since).ok_or_else(|| BonsaiStorageError::DecodeError {
+                    key: key.as_slice().into(),
+                    source: "invalid commit ID".into(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Recorded root hashes of the trie `identifier`, by increasing commit ID. `id` is any commit
+    /// ID, for their length.
+    pub(crate) fn root_hashes(
+        &self,
+        identifier: &[u8],
+        id: ID,
+    ) -> Result<Vec<(ID, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let prefix = TrieKey::new_meta(MetaKeyType::RootHash, identifier);
+        let key_len = prefix.as_slice().len() + id.to_bytes().len();
+        let mut root_hashes = Vec::new();
+        for (key, value) in self.get_by_prefix(&prefix)? {
+            // Root hashes of the tries whose identifier starts with this one.
+            if key.len() != key_len {
+                continue;
+            }
+            let decode_error = |source| BonsaiStorageError::DecodeError {
+                key: key.clone(),
+                source,
+            };
+            let root_id = ID::from_bytes(&key[prefix.as_slice().len()..])
+                .ok_or_else(|| decode_error("invalid commit ID".into()))?;
+            let root_hash = Felt::decode(&mut value.as_slice()).map_err(decode_error)?;
+            root_hashes.push((root_id, root_hash));
+        }
+        root_hashes.sort_by_key(|(root_id, _)| *root_id);
+        Ok(root_hashes)
+    }
+
+    /// Remove the tag of commit `id`, unless it was moved to another commit since then.
+    fn remove_commit_tag(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let commit_tag_key = TrieKey::new_meta(MetaKeyType::CommitTag, &id.to_bytes());
+        let Some(tag) = self.get(&commit_tag_key)? else {
+            return Ok(());
+        };
+        self.remove_untracked(&commit_tag_key, batch)?;
+        let tag_key = TrieKey::new_meta(MetaKeyType::Tag, &tag);
+        if self.get(&tag_key)? == Some(id.as_u64().encode_bytevec()) {
+            self.remove_untracked(&tag_key, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the trie log usage of commit `id`, see [`KeyValueDB::trie_log_usage`].
+    fn remove_log_usage(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let prefix = TrieKey::new_meta(MetaKeyType::LogUsage, &id.to_bytes());
+        for (key, _) in self.get_by_prefix(&prefix)? {
+            self.remove_untracked(&TrieKey::Meta(key), batch)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes used by the saved trie logs for the changes of the trie `identifier`. Only the
+    /// commits made since the usage is recorded are counted.
+    pub(crate) fn trie_log_usage(
+        &self,
+        identifier: &[u8],
+    ) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(latest_id) = self.latest_id else {
+            return Ok(0);
+        };
+        // Commit IDs of a given type all have the same length.
+        let id_len = latest_id.to_bytes().len();
+        let prefix = TrieKey::new_meta(MetaKeyType::LogUsage, &[]);
+        let mut usage = 0;
+        for (key, value) in self.get_by_prefix(&prefix)? {
+            if key.get(prefix.as_slice().len() + id_len..) != Some(identifier) {
+                continue;
             }
+            usage += u64::decode(&mut value.as_slice()).map_err(|source| {
+                BonsaiStorageError::DecodeError {
+                    key: key.clone(),
+                    source,
+                }
+            })?;
         }
+        Ok(usage)
+    }
+
+    fn remove_trie_log(
+        &mut self,
+        id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (key, _) in self.trie_log_entries(id)? {
+            self.remove_trie_log_entry(&key, batch)?;
+        }
+        Ok(())
+    }
 
+    pub(crate) fn compact(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.db.compact()?;
+        self.removed_since_compaction = 0;
         Ok(())
     }
 
+    /// Compact the database if enough trie log entries were removed, see
+    /// [`BonsaiStorageConfig::auto_compaction`].
+    pub(crate) fn auto_compact(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        match self.config.auto_compaction {
+            Some(threshold) if self.removed_since_compaction >= threshold => self.compact(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the changes of commit `id` as recorded by another database, along with their trie
+    /// log. This database must be at the parent commit.
+    pub(crate) fn apply_change_batch(
+        &mut self,
+        id: ID,
+        changes: &ChangeBatch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut batch = self.db.create_batch();
+        self.discard_reverted_trie_logs(&mut batch)?;
+        if self.config.max_saved_trie_logs != Some(0) {
+            // Pruning reads the database, it must not undo the writes of this commit pending in
+            // the batch, such as a tag moved to it.
+            self.prune_trie_logs(id, &mut batch)?;
+            for (key, value) in changes.serialize(&id) {
+                self.insert_trie_log(&key, value, &mut batch)?;
+            }
+        }
+        for (key, change) in &changes.0 {
+            match &change.new_value {
+                Some(new_value) => {
+                    self.db_insert(&DatabaseKey::from(key), new_value, Some(&mut batch))?;
+                }
+                None => {
+                    self.db.remove(&DatabaseKey::from(key), Some(&mut batch))?;
+                }
+            };
+        }
+        // The root hashes are pruned apart from the trie logs.
+        let id_bytes = id.to_bytes();
+        let changed_tries: Vec<ByteVec> = changes
+            .0
+            .keys()
+            .filter_map(|key| match key {
+                TrieKey::Meta(key) => key
+                    .strip_prefix(&[MetaKeyType::RootHash as u8])?
+                    .strip_suffix(id_bytes.as_slice())
+                    .map(ByteVec::from),
+                _ => None,
+            })
+            .collect();
+        for identifier in changed_tries {
+            self.prune_root_hashes(&identifier, id, &mut batch)?;
+        }
+        self.set_latest_id(id, &mut batch)?;
+        #[cfg(feature = "std")]
+        self.watches.collect(id, changes);
+        self.write_batch(batch)?;
+        self.auto_compact()
+    }
+
+    /// Revert the database to the state it had at commit `requested_id` by applying the trie logs of
+    /// every later commit backwards. Changes and trie log removals are written to `batch`. Stops
+    /// between two trie logs once `cancel` is cancelled.
+    pub(crate) fn revert_to(
+        &mut self,
+        requested_id: ID,
+        batch: &mut DB::Batch,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        // Clear current changes
+        self.changes_store.clear();
+
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                requested_id
+            )));
+        };
+        // If requested equals last recorded, do nothing
+        if requested_id == latest_id {
+            return Ok(());
+        }
+        self.check_revert_to(requested_id, latest_id)?;
+
+        // Revert changes, from the latest commit down to the one following the requested id
+        for (cur_id, trie_log) in self.trie_logs_after(requested_id)?.into_iter().rev() {
+            check_cancelled(cancel)?;
+            let keys: Vec<_> = trie_log.iter().map(|(key, _)| key.clone()).collect();
+            let changes = ChangeBatch::deserialize(&cur_id, trie_log)?;
+            for (key, change) in changes.0 {
+                match (&change.old_value, &change.new_value) {
+                    (Some(old_value), _) => {
+                        self.db_insert(&DatabaseKey::from(&key), old_value, Some(batch))?;
+                    }
+                    (None, Some(_)) => {
+                        self.db.remove(&DatabaseKey::from(&key), Some(batch))?;
+                    }
+                    (None, None) => continue,
+                };
+                self.stage(&key, change.old_value);
+            }
+            // Truncate trie logs at the requested id
+            if !self.config.keep_reverted_trie_logs {
+                for key in keys {
+                    self.remove_trie_log_entry(&key, batch)?;
+                }
+            }
+        }
+        if self.config.keep_reverted_trie_logs {
+            let reverted_from = self.reverted_from.map_or(latest_id, |id| id.max(latest_id));
+            self.set_reverted_from(Some(reverted_from), batch)?;
+        } else {
+            self.discard_reverted_trie_logs(batch)?;
+        }
+        self.set_latest_id(requested_id, batch)?;
+        self.reverted_to = Some(
+            self.reverted_to
+                .map_or(requested_id, |reverted_to| reverted_to.min(requested_id)),
+        );
+
+        Ok(())
+    }
+
+    /// Bring the database forward to the state it had at commit `requested_id`, after a revert
+    /// which kept the trie logs of the commits it undid, by applying them again. Changes are
+    /// written to `batch`.
+    pub(crate) fn advance_to(
+        &mut self,
+        requested_id: ID,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.changes_store.clear();
+
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                requested_id
+            )));
+        };
+        if requested_id == latest_id {
+            return Ok(());
+        }
+        if requested_id < latest_id {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} is older than the latest commit {:?}, it must be reverted to",
+                requested_id, latest_id
+            )));
+        }
+        let Some(reverted_from) = self.reverted_from.filter(|id| requested_id <= *id) else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} can't be advanced to: the kept trie logs go up to {:?}",
+                requested_id, self.reverted_from
+            )));
+        };
+
+        for (cur_id, trie_log) in self.trie_logs_between(latest_id, requested_id)? {
+            let changes = ChangeBatch::deserialize(&cur_id, trie_log)?;
+            for (key, change) in changes.0 {
+                match &change.new_value {
+                    Some(new_value) => {
+                        self.db_insert(&DatabaseKey::from(&key), new_value, Some(batch))?;
+                    }
+                    None => {
+                        self.db.remove(&DatabaseKey::from(&key), Some(batch))?;
+                    }
+                };
+                self.stage(&key, change.new_value);
+            }
+        }
+        for cur_id in latest_id.as_u64() + 1..=requested_id.as_u64() {
+            self.prune_trie_logs(ID::from_u64(cur_id), batch)?;
+        }
+        if requested_id == reverted_from {
+            self.set_reverted_from(None, batch)?;
+        }
+        self.set_latest_id(requested_id, batch)
+    }
+
+    /// Check that the trie logs needed to go back from `latest_id` to `requested_id` are there.
+    fn check_revert_to(
+        &self,
+        requested_id: ID,
+        latest_id: ID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if requested_id > latest_id {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} is more recent than the latest commit {:?}",
+                requested_id, latest_id
+            )));
+        }
+        if let Some(bulk_loaded_at) = self.bulk_loaded_at.filter(|id| requested_id < *id) {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} is before the bulk load of {:?}, which can't be reverted",
+                requested_id, bulk_loaded_at
+            )));
+        }
+        // Make sure the trie logs needed to go back to the requested id have not been pruned
+        if let Some(max_saved_trie_logs) = self.config.max_saved_trie_logs {
+            if requested_id.as_u64() + 1 < self.oldest_trie_log(latest_id) {
+                return Err(BonsaiStorageError::GoTo(format!(
+                    "Requested id {:?} was removed: only the last {} trie logs are kept",
+                    requested_id, max_saved_trie_logs
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stored values that the leaves of the trie `identifier` changed since commit `id` had at that
+    /// commit, keyed by flat database key. Only the entries of the trie are read from the trie
+    /// logs, along with the ones which are not framed by a trie: the leaves of the other tries
+    /// are returned as well for the trie logs written before they were framed.
+    pub(crate) fn trie_leaves_at(
+        &self,
+        identifier: &[u8],
+        id: ID,
+    ) -> Result<HashMap<ByteVec, Option<ByteVec>>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                id
+            )));
+        };
+        self.check_revert_to(id, latest_id)?;
+        let mut leaves = HashMap::new();
+        for cur_id in id.as_u64() + 1..=latest_id.as_u64() {
+            let cur_id = ID::from_u64(cur_id);
+            let mut entries =
+                self.trie_log_entries_by_prefix(trie_log_prefix(&cur_id, identifier))?;
+            entries.extend(self.trie_log_entries_by_prefix(unframed_trie_log_prefix(&cur_id))?);
+            for (key, change) in ChangeBatch::deserialize(&cur_id, entries)?.0 {
+                // The oldest change of a leaf has its value at `id`.
+                if let TrieKey::Flat(key) = key {
+                    leaves.entry(key).or_insert(change.old_value);
+                }
+            }
+        }
+        Ok(leaves)
+    }
+
+    /// Write the trie nodes and leaves at commit `id` to `target`, with the metadata needed to
+    /// open it as a storage whose latest commit is `id`. The database errors of `target` are
+    /// returned as [`BonsaiStorageError::ExportTarget`].
+    ///
+    /// The Trie and Flat columns are exported one leading byte at a time, with a batch per chunk.
+    /// The trie logs since `id` are read again for each chunk they change, to keep only the values
+    /// at `id` of the keys of one chunk in memory.
+    #[cfg(feature = "std")]
+    pub(crate) fn export_state<T: BonsaiDatabase>(
+        &self,
+        id: ID,
+        target: &mut T,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>>
+    where
+        T::DatabaseError: 'static,
+    {
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                id
+            )));
+        };
+        self.check_revert_to(id, latest_id)?;
+        let _guard = self.pins.pin(id, id.as_u64() + 1, None);
+
+        let export_error = |err: T::DatabaseError| BonsaiStorageError::ExportTarget(Box::new(err));
+        let per_trie_meta = [MetaKeyType::DiskUsage, MetaKeyType::TrieHeight]
+            .map(|key_type| TrieKey::new_meta(key_type, &[]));
+        let chunks: Vec<TrieKey> = (0..=u8::MAX)
+            .map(|byte| TrieKey::Trie(ByteVec::from(&[byte][..])))
+            .chain((0..=u8::MAX).map(|byte| TrieKey::Flat(ByteVec::from(&[byte][..]))))
+            .chain(per_trie_meta.iter().cloned())
+            .collect();
+        let chunk_of = |key: &TrieKey| match key {
+            TrieKey::Trie(key) => key.first().map(|&byte| byte as usize),
+            TrieKey::Flat(key) => key.first().map(|&byte| 256 + byte as usize),
+            TrieKey::Meta(key) => per_trie_meta
+                .iter()
+                .position(|prefix| key.starts_with(prefix.as_slice()))
+                .map(|i| 512 + i),
+        };
+        let logged = id.as_u64() + 1..=latest_id.as_u64();
+        let mut logged_chunks = vec![false; chunks.len()];
+        for cur_id in logged.clone() {
+            for key in self.get_trie_log(ID::from_u64(cur_id))?.0.keys() {
+                if let Some(chunk) = chunk_of(key) {
+                    logged_chunks[chunk] = true;
+                }
+            }
+        }
+
+        for (chunk, prefix) in chunks.iter().enumerate() {
+            let in_column = |key: ByteVec| match prefix {
+                TrieKey::Trie(_) => TrieKey::Trie(key),
+                TrieKey::Flat(_) => TrieKey::Flat(key),
+                TrieKey::Meta(_) => TrieKey::Meta(key),
+            };
+            let mut entries: HashMap<TrieKey, Option<ByteVec>> = self
+                .db
+                .get_by_prefix(&DatabaseKey::from(prefix))?
+                .into_iter()
+                .map(|(key, value)| (in_column(key), Some(value)))
+                .collect();
+            if logged_chunks[chunk] {
+                // The oldest change of a key since `id` has its value at `id`.
+                let mut old_values = HashMap::new();
+                for cur_id in logged.clone() {
+                    for (key, change) in self.get_trie_log(ID::from_u64(cur_id))?.0 {
+                        if chunk_of(&key) == Some(chunk) {
+                            old_values.entry(key).or_insert(change.old_value);
+                        }
+                    }
+                }
+                entries.extend(old_values);
+            }
+            let mut batch = target.create_batch();
+            for (key, value) in entries {
+                if let Some(value) = value {
+                    target
+                        .insert(&DatabaseKey::from(&key), &value, Some(&mut batch))
+                        .map_err(export_error)?;
+                }
+            }
+            target.write_batch(batch).map_err(export_error)?;
+        }
+
+        // The layout and the configuration of the database are not recorded by the trie logs.
+        let mut batch = target.create_batch();
+        for key_type in [MetaKeyType::SchemaVersion, MetaKeyType::Config] {
+            let key = TrieKey::new_meta(key_type, &[]);
+            if let Some(value) = self.db.get(&DatabaseKey::from(&key))? {
+                target
+                    .insert(&DatabaseKey::from(&key), &value, Some(&mut batch))
+                    .map_err(export_error)?;
+            }
+        }
+        let key = TrieKey::new_meta(MetaKeyType::LatestId, &[]);
+        target
+            .insert(
+                &DatabaseKey::from(&key),
+                &id.as_u64().encode_bytevec(),
+                Some(&mut batch),
+            )
+            .map_err(export_error)?;
+        target.write_batch(batch).map_err(export_error)
+    }
+
     pub(crate) fn create_batch(&self) -> DB::Batch {
         self.db.create_batch()
     }
@@ -149,19 +1294,93 @@ where
         key: &TrieKey,
     ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Getting from KeyValueDB: {:?}", key);
+        if let Some(value) = self.staged.as_ref().and_then(|staged| staged.get(key)) {
+            return Ok(value.clone());
+        }
+        self.stats.db_gets(1);
         Ok(self.db.get(&key.into())?)
     }
 
+    /// Same as `get` for many keys, the values are returned in the same order as `keys`.
+    pub(crate) fn get_many(
+        &self,
+        keys: &[TrieKey],
+    ) -> Result<Vec<Option<ByteVec>>, BonsaiStorageError<DB::DatabaseError>> {
+        trace!("Getting {} keys from KeyValueDB", keys.len());
+        let staged = self.staged.as_ref();
+        let (in_db, db_keys): (Vec<usize>, Vec<DatabaseKey>) = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| staged.is_none_or(|staged| !staged.contains_key(*key)))
+            .map(|(i, key)| (i, DatabaseKey::from(key)))
+            .unzip();
+        let mut values: Vec<Option<ByteVec>> = keys
+            .iter()
+            .map(|key| staged.and_then(|staged| staged.get(key)).cloned().flatten())
+            .collect();
+        self.stats.db_gets(db_keys.len());
+        for (i, value) in in_db.into_iter().zip(self.db.get_many(&db_keys)?) {
+            values[i] = value;
+        }
+        Ok(values)
+    }
+
+    /// Key-value pairs of the column of `prefix` whose key starts with it, staged writes included.
+    /// The keys are returned whole, in no particular order.
+    pub(crate) fn get_by_prefix(
+        &self,
+        prefix: &TrieKey,
+    ) -> Result<Vec<(ByteVec, ByteVec)>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut entries: HashMap<ByteVec, ByteVec> = self
+            .db
+            .get_by_prefix(&DatabaseKey::from(prefix))?
+            .into_iter()
+            .collect();
+        let prefix_type = u8::from(prefix);
+        for (key, value) in self.staged.iter().flatten() {
+            if u8::from(key) != prefix_type || !key.as_slice().starts_with(prefix.as_slice()) {
+                continue;
+            }
+            match value {
+                Some(value) => entries.insert(key.as_slice().into(), value.clone()),
+                None => entries.remove(key.as_slice()),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    /// Value of `key`, a leaf of the trie `identifier`, at commit `id`: the value it had before the
+    /// oldest of the following commits which changed it, or its current value if none did.
     pub(crate) fn get_at(
         &self,
-        _key: &TrieKey,
-        _id: ID,
+        identifier: &[u8],
+        key: &TrieKey,
+        id: ID,
     ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
-        todo!()
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                id
+            )));
+        };
+        self.check_revert_to(id, latest_id)?;
+        // The trie logs read are kept until the read is done.
+        #[cfg(feature = "std")]
+        let _guard = self.pins.pin(id, id.as_u64() + 1, None);
+        for cur_id in id.as_u64() + 1..=latest_id.as_u64() {
+            let cur_id = ID::from_u64(cur_id);
+            let mut entries =
+                self.trie_log_entries_by_prefix(trie_log_prefix(&cur_id, identifier))?;
+            entries.extend(self.trie_log_entries_by_prefix(unframed_trie_log_prefix(&cur_id))?);
+            if let Some(change) = ChangeBatch::deserialize(&cur_id, entries)?.0.remove(key) {
+                return Ok(change.old_value);
+            }
+        }
+        self.get(key)
     }
 
     pub(crate) fn get_latest_id(&self) -> Option<ID> {
-        todo!()
+        self.latest_id
     }
 
     pub(crate) fn contains(
@@ -169,42 +1388,131 @@ where
         key: &TrieKey,
     ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Contains from KeyValueDB: {:?}", key);
+        if let Some(value) = self.staged.as_ref().and_then(|staged| staged.get(key)) {
+            return Ok(value.is_some());
+        }
+        self.stats.db_gets(1);
         Ok(self.db.contains(&key.into())?)
     }
 
+    /// Insert `value` at `key`, returning the value it replaces.
     pub(crate) fn insert(
         &mut self,
         key: &TrieKey,
         value: &[u8],
         batch: Option<&mut DB::Batch>,
-    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Inserting into KeyValueDB: {:?} {:?}", key, value);
-        let old_value = self.db.insert(&key.into(), value, batch)?;
+        let old_value = self.db_insert(&key.into(), value, batch)?;
+        Ok(self.record_insert(key, value, old_value))
+    }
+
+    /// Record the insertion of `value` at `key`, over `old_value` in the database, returning the
+    /// value it replaces.
+    fn record_insert(
+        &mut self,
+        key: &TrieKey,
+        value: &[u8],
+        mut old_value: Option<ByteVec>,
+    ) -> Option<ByteVec> {
+        if let Some(staged) = &mut self.staged {
+            if let Some(staged_value) = staged.insert(key.clone(), Some(value.into())) {
+                old_value = staged_value;
+            }
+        }
         self.changes_store.current_changes.insert_in_place(
             key.clone(),
             Change {
-                old_value,
+                old_value: old_value.clone(),
                 new_value: Some(value.into()),
             },
         );
-        Ok(())
+        old_value
     }
 
+    /// Remove `key`, returning the value it had.
     pub(crate) fn remove(
         &mut self,
         key: &TrieKey,
         batch: Option<&mut DB::Batch>,
-    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
         trace!("Removing from KeyValueDB: {:?}", key);
-        let old_value = self.db.remove(&key.into(), batch)?;
+        let mut old_value = self.db.remove(&key.into(), batch)?;
+        if let Some(staged) = &mut self.staged {
+            if let Some(staged_value) = staged.insert(key.clone(), None) {
+                old_value = staged_value;
+            }
+        }
         self.changes_store.current_changes.insert_in_place(
             key.clone(),
             Change {
-                old_value,
+                old_value: old_value.clone(),
                 new_value: None,
             },
         );
-        Ok(())
+        Ok(old_value)
+    }
+
+    /// Same as `insert` for a key of the trie `identifier`, whose trie log entries are framed by
+    /// it.
+    pub(crate) fn insert_in_trie(
+        &mut self,
+        identifier: &[u8],
+        key: &TrieKey,
+        value: &[u8],
+        batch: Option<&mut DB::Batch>,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        let old_value = self.insert(key, value, batch)?;
+        self.changes_store
+            .current_changes
+            .1
+            .insert(key.clone(), identifier.into());
+        Ok(old_value)
+    }
+
+    /// Same as `insert_in_trie` for each pair, in order, written with a single
+    /// [`BonsaiDatabase::insert_many`].
+    pub(crate) fn insert_many_in_trie(
+        &mut self,
+        identifier: &[u8],
+        items: &[(TrieKey, ByteVec)],
+        batch: Option<&mut DB::Batch>,
+    ) -> Result<Vec<Option<ByteVec>>, BonsaiStorageError<DB::DatabaseError>> {
+        trace!("Inserting {} keys into KeyValueDB", items.len());
+        let keys: Vec<DatabaseKey> = items.iter().map(|(key, _)| key.into()).collect();
+        let db_items: Vec<_> = keys
+            .iter()
+            .zip(items)
+            .map(|(db_key, (_, value))| (*db_key, value.as_slice()))
+            .collect();
+        for (key, value) in &db_items {
+            self.stats.bytes_written(key.as_slice().len() + value.len());
+        }
+        let old_values = self.db.insert_many(&db_items, batch)?;
+        let mut replaced = Vec::with_capacity(items.len());
+        for ((key, value), old_value) in items.iter().zip(old_values) {
+            replaced.push(self.record_insert(key, value, old_value));
+            self.changes_store
+                .current_changes
+                .1
+                .insert(key.clone(), identifier.into());
+        }
+        Ok(replaced)
+    }
+
+    /// Same as `remove` for a key of the trie `identifier`.
+    pub(crate) fn remove_in_trie(
+        &mut self,
+        identifier: &[u8],
+        key: &TrieKey,
+        batch: Option<&mut DB::Batch>,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        let old_value = self.remove(key, batch)?;
+        self.changes_store
+            .current_changes
+            .1
+            .insert(key.clone(), identifier.into());
+        Ok(old_value)
     }
 
     pub(crate) fn write_batch(
@@ -212,7 +1520,20 @@ where
         batch: DB::Batch,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         trace!("Writing batch into KeyValueDB");
-        Ok(self.db.write_batch(batch)?)
+        if let Err(err) = self.db.write_batch(batch) {
+            self.discard_watched_changes();
+            return Err(err.into());
+        }
+        #[cfg(feature = "std")]
+        self.watches.notify();
+        Ok(())
+    }
+
+    /// Drop the changes to watched leaves recorded by commits which are not written, see
+    /// [`crate::BonsaiStorage::watch`].
+    pub(crate) fn discard_watched_changes(&mut self) {
+        #[cfg(feature = "std")]
+        self.watches.discard();
     }
 }
 
@@ -222,63 +1543,330 @@ where
     DB: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
 {
     pub(crate) fn create_snapshot(&mut self, id: ID) {
+        self.remove_stale_snapshots();
         if id.as_u64() % self.config.snapshot_interval == 0 {
             self.db.snapshot(id);
+            self.remove_old_snapshots();
+        }
+    }
+
+    /// Snapshot the state at the latest commit `id`, whatever the snapshot interval.
+    pub(crate) fn create_snapshot_now(
+        &mut self,
+        id: ID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        if self.latest_id != Some(id) {
+            return Err(BonsaiStorageError::Snapshot(
+                SnapshotError::NotLatestCommit {
+                    id: id.as_u64(),
+                    latest_id: self.latest_id.map(|id| id.as_u64()),
+                },
+            ));
+        }
+        self.remove_stale_snapshots();
+        self.db.snapshot(id);
+        self.remove_old_snapshots();
+        Ok(())
+    }
+
+    /// Ids of the snapshots that can be used, in increasing order.
+    pub(crate) fn snapshots(&self) -> Vec<ID> {
+        let mut snapshots = self.db.snapshots();
+        if let Some(reverted_to) = self.reverted_to {
+            snapshots.retain(|id| *id <= reverted_to);
+        }
+        snapshots
+    }
+
+    pub(crate) fn remove_snapshot(&mut self, id: ID) -> bool {
+        if self.reverted_to.is_some_and(|reverted_to| id > reverted_to) {
+            return false;
+        }
+        self.db.remove_snapshot(id)
+    }
+
+    /// Remove the snapshots of the commits discarded by [`KeyValueDB::revert_to`], before new
+    /// commits reuse their ids.
+    fn remove_stale_snapshots(&mut self) {
+        let Some(reverted_to) = self.reverted_to.take() else {
+            return;
+        };
+        for id in self.db.snapshots() {
+            if id > reverted_to {
+                self.db.remove_snapshot(id);
+            }
         }
     }
 
+    /// Keep only the latest `max_saved_snapshots` snapshots, whatever the database keeps, and the
+    /// ones of the pinned commits.
+    fn remove_old_snapshots(&mut self) {
+        let Some(max_saved_snapshots) = self.config.max_saved_snapshots else {
+            return;
+        };
+        let snapshots = self.db.snapshots();
+        let excess = snapshots.len().saturating_sub(max_saved_snapshots);
+        #[cfg(feature = "std")]
+        let pinned = self.pins.snapshots();
+        #[cfg(not(feature = "std"))]
+        let pinned: Vec<u64> = Vec::new();
+        // The snapshots of the pinned commits are kept on top of the latest ones.
+        for id in snapshots
+            .into_iter()
+            .take(excess)
+            .filter(|id| !pinned.contains(&id.as_u64()))
+        {
+            self.db.remove_snapshot(id);
+        }
+    }
+
+    /// Which snapshot a transactional state at `id` would be created from, `None` if there is
+    /// none. This does not check that the trie logs to replay are present.
+    pub(crate) fn transactional_state_info(&self, id: ID) -> Option<TransactionalStateInfo<ID>> {
+        if self.latest_id.is_none_or(|latest_id| id > latest_id) {
+            return None;
+        }
+        let snapshot_id = self
+            .snapshots()
+            .into_iter()
+            .take_while(|snapshot_id| *snapshot_id <= id)
+            .last()
+            .filter(|snapshot_id| self.can_replay(*snapshot_id, id))?;
+        Some(TransactionalStateInfo {
+            snapshot_id,
+            replayed_trie_logs: id.as_u64() - snapshot_id.as_u64(),
+        })
+    }
+
+    /// Keep what is needed to read commit `id` from being pruned while the returned guard is
+    /// alive: the trie logs of the following commits, and the snapshot a transactional state at
+    /// `id` is created from along with the trie logs replayed on top of it.
+    #[cfg(feature = "std")]
+    pub(crate) fn pin_commit(
+        &self,
+        id: ID,
+    ) -> Result<CommitGuard<ID>, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let Some(latest_id) = self.latest_id else {
+            return Err(BonsaiStorageError::GoTo(format!(
+                "Requested id {:?} has not been recorded: no commit has taken place yet",
+                id
+            )));
+        };
+        self.check_revert_to(id, latest_id)?;
+        let snapshot = self
+            .transactional_state_info(id)
+            .map(|info| info.snapshot_id)
+            .filter(|snapshot_id| {
+                (snapshot_id.as_u64() + 1..=id.as_u64())
+                    .all(|cur_id| self.has_trie_log(ID::from_u64(cur_id)))
+            });
+        let trie_logs_from = snapshot.unwrap_or(id).as_u64() + 1;
+        Ok(self.pins.pin(id, trie_logs_from, snapshot))
+    }
+
+    /// Whether the state at `id` can be rebuilt from the snapshot at `snapshot_id` with the trie
+    /// logs, which is not the case when a bulk load was made in between.
+    fn can_replay(&self, snapshot_id: ID, id: ID) -> bool {
+        self.bulk_loaded_at
+            .is_none_or(|bulk_loaded_at| snapshot_id >= bulk_loaded_at || id < bulk_loaded_at)
+    }
+
+    #[allow(clippy::type_complexity)]
     pub(crate) fn get_transaction(
         &self,
         id: ID,
     ) -> Result<
-        Option<DB::Transaction<'_>>,
+        Option<(DB::Transaction<'_>, TransactionalStateInfo<ID>)>,
         BonsaiStorageError<<DB::Transaction<'_> as BonsaiDatabase>::DatabaseError>,
     > {
         log::debug!("get_transaction {id:?}");
+        if self.latest_id.is_none_or(|latest_id| id > latest_id) {
+            return Ok(None);
+        }
         let Some((snap_id, mut txn)) = self.db.transaction(id) else {
             return Ok(None);
         };
         log::debug!("get_transaction {snap_id:?} {id:?}");
+        if !self.can_replay(snap_id, id) {
+            return Ok(None);
+        }
+        let info = TransactionalStateInfo {
+            snapshot_id: snap_id,
+            replayed_trie_logs: id.as_u64() - snap_id.as_u64(),
+        };
+        if let Some(max_replay) = self.config.max_transactional_state_replay {
+            if info.replayed_trie_logs > max_replay {
+                return Err(BonsaiStorageError::Transaction(format!(
+                    "creating a transactional state at {:?} from the snapshot at {:?} would replay {} \
+                     trie logs, the maximum is {}",
+                    id, snap_id, info.replayed_trie_logs, max_replay
+                )));
+            }
+        }
 
+        // The snapshot may be older than `id`, catch up by applying the trie logs forward.
         let mut batch = txn.create_batch();
-        for cur_id in snap_id.as_u64()..id.as_u64() {
+        for cur_id in snap_id.as_u64() + 1..=id.as_u64() {
             let cur_id = ID::from_u64(cur_id);
-            let changes = ChangeBatch::deserialize(
-                &cur_id,
-                self.db
-                    .get_by_prefix(&DatabaseKey::TrieLog(&cur_id.to_bytes()))
-                    .map_err(|_| {
-                        BonsaiStorageError::Transaction(format!(
-                            "database is missing trie logs for {:?}",
-                            cur_id
-                        ))
-                    })?,
-            );
-            // Apply backwards
+            if !self.has_trie_log(cur_id) {
+                return Err(BonsaiStorageError::Transaction(format!(
+                    "database is missing trie logs for {:?}",
+                    cur_id
+                )));
+            }
+            let trie_log = self
+                .db
+                .get_by_prefix(&DatabaseKey::TrieLog(&cur_id.to_bytes()))
+                .map_err(|_| {
+                    BonsaiStorageError::Transaction(format!(
+                        "database is missing trie logs for {:?}",
+                        cur_id
+                    ))
+                })?;
+            // Copy the trie log as well so that the transactional state can be reverted.
+            for (key, value) in &trie_log {
+                txn.insert(&DatabaseKey::TrieLog(key), value, Some(&mut batch))?;
+            }
+            let changes = ChangeBatch::deserialize(&cur_id, trie_log)?;
             for (key, change) in changes.0 {
                 let key = DatabaseKey::from(&key);
-                match (&change.old_value, &change.new_value) {
-                    (Some(old_value), Some(_)) => {
-                        txn.insert(&key, old_value, Some(&mut batch))?;
+                match &change.new_value {
+                    Some(new_value) => {
+                        txn.insert(&key, new_value, Some(&mut batch))?;
                     }
-                    (Some(old_value), None) => {
-                        txn.insert(&key, old_value, Some(&mut batch))?;
-                    }
-                    (None, Some(_)) => {
+                    None => {
                         txn.remove(&key, Some(&mut batch))?;
                     }
-                    (None, None) => unreachable!(),
                 };
             }
         }
         txn.write_batch(batch)?;
-        Ok(Some(txn))
+        Ok(Some((txn, info)))
     }
 
-    pub(crate) fn merge(
+    /// Apply commit `id` of `transaction` as is: its trie log is copied over and every new value it
+    /// records is written, trie nodes included. This database must be at the parent commit.
+    ///
+    /// Ids without trie logs were not committed and are skipped, except for the latest one, as
+    /// [`KeyValueDB::merge`] does.
+    pub(crate) fn fast_forward_commit(
         &mut self,
-        _transaction: KeyValueDB<DB::Transaction<'_>, ID>,
-    ) -> Result<(), BonsaiStorageError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError>> {
-        todo!("unused yet")
+        transaction: &KeyValueDB<DB::Transaction<'_>, ID>,
+        id: ID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let changes = transaction.get_trie_log(id).map_err(|_| {
+            BonsaiStorageError::Merge(format!(
+                "transactional state is missing trie logs for {:?}, they were pruned or disabled",
+                id
+            ))
+        })?;
+        if changes.is_empty() && transaction.latest_id != Some(id) {
+            return Ok(());
+        }
+
+        self.apply_change_batch(id, &changes)?;
+        self.create_snapshot(id);
+        Ok(())
     }
+
+    /// Get the leaf and metadata changes of every commit made in `transaction` since it was
+    /// created, in order. The leaves are returned as stored, with their raw payloads.
+    /// Fails if the transactional state was created at a commit this database doesn't have, or if
+    /// it was reverted past its creation point.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn merge(
+        &self,
+        transaction: KeyValueDB<DB::Transaction<'_>, ID>,
+    ) -> Result<
+        Vec<(
+            ID,
+            HashMap<ByteVec, Option<ByteVec>>,
+            HashMap<ByteVec, Option<ByteVec>>,
+        )>,
+        BonsaiStorageError<<DB as BonsaiPersistentDatabase<ID>>::DatabaseError>,
+    > {
+        let (Some(created_at), Some(txn_latest_id)) =
+            (transaction.created_at, transaction.latest_id)
+        else {
+            return Err(BonsaiStorageError::Merge(
+                "storage is not a transactional state".to_string(),
+            ));
+        };
+        if self
+            .latest_id
+            .is_none_or(|latest_id| created_at > latest_id)
+        {
+            return Err(BonsaiStorageError::Merge(format!(
+                "transactional state was created at {:?} which is more recent than the latest commit {:?}",
+                created_at, self.latest_id
+            )));
+        }
+        if txn_latest_id < created_at {
+            return Err(BonsaiStorageError::Merge(format!(
+                "transactional state created at {:?} was reverted to {:?}",
+                created_at, txn_latest_id
+            )));
+        }
+
+        let mut commits = Vec::new();
+        for cur_id in created_at.as_u64() + 1..=txn_latest_id.as_u64() {
+            let cur_id = ID::from_u64(cur_id);
+            let changes: HashMap<_, _> = transaction
+                .get_trie_log(cur_id)?
+                .0
+                .into_iter()
+                .filter_map(|(key, change)| match key {
+                    TrieKey::Flat(key) => Some((key, change.new_value)),
+                    _ => None,
+                })
+                .collect();
+            let meta_changes = transaction.get_meta_changes(cur_id)?;
+            // Ids without trie logs were not committed, except for the latest one
+            if !changes.is_empty() || !meta_changes.is_empty() || cur_id == txn_latest_id {
+                commits.push((cur_id, changes, meta_changes));
+            }
+        }
+        Ok(commits)
+    }
+}
+
+/// Changes made to the flat leaf storage by `changes`, keyed by flat database key.
+fn leaf_changes(changes: ChangeBatch) -> HashMap<ByteVec, ExternChange> {
+    let mut leaf_changes = HashMap::new();
+    for (key, change) in changes.0 {
+        if let TrieKey::Flat(key) = key {
+            leaf_changes.insert(
+                key,
+                ExternChange {
+                    // SAFETY: We are sure that the values are valid Felt because they can be saved only by our crate
+                    old_value: change
+                        .old_value
+                        .map(|x| Felt::decode(&mut x.as_ref()).unwrap()),
+                    new_value: change
+                        .new_value
+                        .map(|x| Felt::decode(&mut x.as_ref()).unwrap()),
+                },
+            );
+        }
+    }
+    leaf_changes
+}
+
+/// Key of the root hash of the trie `identifier` after commit `id`.
+fn root_hash_key<ID: Id>(identifier: &[u8], id: ID) -> TrieKey {
+    let mut key = ByteVec::from(identifier);
+    key.extend_from_slice(&id.to_bytes());
+    TrieKey::new_meta(MetaKeyType::RootHash, &key)
+}
+
+/// Key of the metadata of commit `id`, see [`crate::BonsaiStorage::commit_with_meta`].
+pub(crate) fn commit_meta_key<ID: Id>(id: ID) -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::CommitMeta, &id.to_bytes())
+}
+
+/// Key of the trie log usage of the trie `identifier` in commit `id`.
+fn log_usage_key<ID: Id>(id: ID, identifier: &[u8]) -> TrieKey {
+    let mut key = id.to_bytes();
+    key.extend_from_slice(identifier);
+    TrieKey::new_meta(MetaKeyType::LogUsage, &key)
 }
@@ -94,9 +94,12 @@ pub(crate) use hashbrown::hash_map;
 extern crate alloc;
 #[cfg(not(feature = "std"))]
 pub(crate) use alloc::{
-    collections::BTreeMap,
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
     format,
     string::{String, ToString},
+    sync::Arc,
     vec,
     vec::Vec,
 };
@@ -104,9 +107,12 @@ use core::fmt;
 use id::Id;
 #[cfg(feature = "std")]
 pub(crate) use std::{
-    collections::BTreeMap,
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
     format,
     string::{String, ToString},
+    sync::Arc,
     vec,
     vec::Vec,
 };
@@ -115,20 +121,69 @@ pub type ByteVec = smallvec::SmallVec<[u8; 32]>;
 pub type BitVec = bitvec::vec::BitVec<u8, bitvec::order::Msb0>;
 pub type BitSlice = bitvec::slice::BitSlice<u8, bitvec::order::Msb0>;
 
+mod cancellation;
+mod change_sink;
 mod changes;
+mod commit_hook;
+#[cfg(feature = "std")]
+mod commit_pins;
 mod key_value_db;
+mod op_stats;
+mod pending;
 mod trie;
+#[cfg(feature = "std")]
+mod watch;
+mod witness;
 
 mod bonsai_database;
 /// All databases already implemented in this crate.
 pub mod databases;
 mod error;
+#[cfg(feature = "eth")]
+pub mod eth;
+pub mod hashers;
 /// Definition and basic implementation of an CommitID
 pub mod id;
+pub mod migrations;
+#[cfg(feature = "std")]
+mod shared;
+pub mod starknet;
+mod storage_builder;
+mod stored_config;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-pub use bonsai_database::{BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey};
-pub use error::BonsaiStorageError;
-pub use trie::proof::{MultiProof, ProofNode};
+pub use bonsai_database::{
+    BonsaiDatabase, BonsaiPersistentDatabase, DBError, DatabaseKey, ParsedKey,
+};
+pub use cancellation::CancellationToken;
+pub use change_sink::{ChangeSink, ChangeSinkPolicy, LeafChange};
+pub use changes::ChangeBatch;
+pub use commit_hook::{CommitHook, PendingCommit};
+#[cfg(feature = "std")]
+pub use commit_pins::CommitGuard;
+pub use error::{
+    BonsaiStorageError, ChangeSinkError, ConfigError, CorruptedNode, ImportRootMismatch,
+    ReplayError, ShardError, SnapshotError,
+};
+pub use op_stats::OpStats;
+pub use pending::PendingState;
+#[cfg(feature = "std")]
+pub use shared::{SharedBonsaiStorage, TrieShard};
+pub use storage_builder::BonsaiStorageBuilder;
+pub use stored_config::StoredConfig;
+pub use trie::builder::IncrementalTrieBuilder;
+pub use trie::diff::{compare, TrieDivergence};
+pub use trie::fixed_depth::FixedDepthMerkleTree;
+pub use trie::iterator::{PathNode, TrieCursor};
+pub use trie::merkle_node::{NodeKind, TrieHasher, TrieNodeFamily};
+pub use trie::path::Path;
+pub use trie::proof::{MultiProof, ProofNode, ProofVerificationError};
+pub use trie::tree::{compute_root, MerkleTree};
+pub use trie::trees::{PreparedCommit, SavepointId};
+#[cfg(feature = "std")]
+pub use watch::WatchHandle;
+pub use witness::{TrieWitness, Witness};
 
 #[cfg(test)]
 mod tests;
@@ -150,9 +205,18 @@ pub(crate) trait EncodeExt: parity_scale_codec::Encode {
 }
 impl<T: parity_scale_codec::Encode> EncodeExt for T {}
 
+use cancellation::check_cancelled;
+use databases::ForkDb;
 use key_value_db::KeyValueDB;
+use parity_scale_codec::Decode;
 use starknet_types_core::{felt::Felt, hash::StarkHash};
-use trie::{tree::bytes_to_bitvec, trees::MerkleTrees};
+use trie::{
+    tree::split_flat_key,
+    trees::MerkleTrees,
+    trie_db::{MetaKeyType, TrieKeyType},
+    TrieKey,
+};
+use witness::WitnessRecorder;
 
 /// Structure that contains the configuration for the BonsaiStorage.
 /// A default implementation is provided with coherent values.
@@ -170,6 +234,74 @@ pub struct BonsaiStorageConfig {
     /// A database snapshot is created every `snapshot_interval` commits.
     /// Having more frequent snapshots occupies more disk space and has a slight performance impact on commits, but allows for more efficient transactional state creation.
     pub snapshot_interval: u64,
+    /// What to do when merging a transactional state which changed leaves that were also changed by
+    /// commits of the storage after the transactional state was created.
+    pub merge_conflict_policy: MergeConflictPolicy,
+    /// Maximum number of trie logs replayed on top of a snapshot to create a transactional state.
+    /// Creating a transactional state further away from its closest snapshot fails instead of
+    /// doing the expensive reconstruction. A value of None disables the limit.
+    pub max_transactional_state_replay: Option<u64>,
+    /// What a commit does when the [`ChangeSink`] of the storage refuses its changes.
+    pub change_sink_policy: ChangeSinkPolicy,
+    /// Compact the database, see [`BonsaiStorage::compact`], once this many trie log entries were
+    /// removed by pruning or reverts since the last compaction. A value of None disables automatic
+    /// compaction.
+    pub auto_compaction: Option<u64>,
+    /// Write the changes of [`BonsaiStorage::commit`] in batches of about this many bytes instead
+    /// of a single one, to bound the memory used by large commits. The root nodes of the tries and
+    /// the id of the commit are written after the rest, but the batches are not applied atomically
+    /// together. The other ways of committing still write a single batch. A value of None writes
+    /// the changes of a commit in a single batch.
+    pub max_batch_bytes: Option<usize>,
+    /// Hasher of each trie which doesn't use the hasher of the storage, by identifier, for instance
+    /// to keep Poseidon and Pedersen tries in the same storage. The hasher of a trie is chosen when
+    /// it is created and the storage must always be opened with it afterwards, as the hashes are
+    /// not checked. The transactional states use the hashers of the storage they are created from.
+    pub trie_hashers: HashMap<ByteVec, TrieHasher>,
+    /// Store the tries with at most this many leaves as a single record listing their leaves
+    /// instead of as nodes, the nodes being rebuilt from the leaves when the trie is read. This
+    /// saves the node reads and writes of the many small tries, such as the storage tries of most
+    /// contracts. A trie is stored as nodes once it grows past this number of leaves, and stays so
+    /// if it shrinks again, as do the tries written without this option. The storage must not be
+    /// opened without it afterwards, as the inline tries are not read then, and `Some(0)` keeps
+    /// reading them while storing the changed ones as nodes. A value of None stores all the tries
+    /// as nodes.
+    pub max_inline_leaves: Option<usize>,
+    /// Make [`BonsaiStorage::migrate_nodes`] move the nodes it can't decode out of the trie, to be
+    /// listed by [`BonsaiStorage::quarantined_nodes`] for offline analysis, instead of failing on
+    /// the first one. The trie is then missing these nodes, reading through them fails with
+    /// [`BonsaiStorageError::NodeNotFound`] until they are restored. Otherwise, and when a node
+    /// is read by any other operation, an undecodable node fails it with
+    /// [`BonsaiStorageError::CorruptedNode`].
+    pub quarantine_corrupted_nodes: bool,
+    /// Keep the trie logs of the commits undone by [`BonsaiStorage::revert_to`], so that
+    /// [`BonsaiStorage::advance_to`] can apply them again. They are removed by the next commit, as
+    /// it starts another history. Otherwise they are removed by the revert itself.
+    pub keep_reverted_trie_logs: bool,
+    /// Drop the tries kept in memory after a commit once there are more than this many, starting
+    /// with the ones the commit didn't change. Their nodes are read again from the database when
+    /// they are next used, so this bounds the memory of syncs touching many tries, such as the
+    /// storage tries of millions of contracts. A value of None keeps every trie used since the
+    /// storage was opened.
+    pub max_cached_tries: Option<usize>,
+    /// Store the leaves set to zero, by [`BonsaiStorage::insert`] and the other ways of setting
+    /// leaves, with zero as their value instead of removing them, for uses where zero is a value
+    /// like the others. Leaves are then only removed by [`BonsaiStorage::remove`] and the other
+    /// removals. The leaves already stored keep their meaning either way: a missing leaf was
+    /// removed, a stored one has a value, zero or not. This does not apply to the default value of
+    /// the sparse tries, which always removes the leaf, nor to the tries known by their root hash
+    /// only, see [`BonsaiStorage::set_proven_root`], whose zero leaves are read as missing.
+    pub store_zero_values: bool,
+}
+
+/// How conflicts are handled when merging a transactional state, see [`BonsaiStorage::merge`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeConflictPolicy {
+    /// Fail with [`BonsaiStorageError::MergeConflict`], leaving the storage untouched.
+    #[default]
+    Error,
+    /// The values of the transactional state, which is merged last, overwrite the committed ones.
+    LastWriterWins,
 }
 
 impl Default for BonsaiStorageConfig {
@@ -178,10 +310,113 @@ impl Default for BonsaiStorageConfig {
             max_saved_trie_logs: Some(500),
             max_saved_snapshots: Some(100),
             snapshot_interval: 5,
+            merge_conflict_policy: MergeConflictPolicy::default(),
+            max_transactional_state_replay: None,
+            change_sink_policy: ChangeSinkPolicy::default(),
+            auto_compaction: None,
+            max_batch_bytes: None,
+            trie_hashers: HashMap::new(),
+            max_inline_leaves: None,
+            quarantine_corrupted_nodes: false,
+            keep_reverted_trie_logs: false,
+            max_cached_tries: None,
+            store_zero_values: false,
+        }
+    }
+}
+
+impl BonsaiStorageConfig {
+    /// Check that the values of the configuration are coherent with each other. This is done by
+    /// [`BonsaiStorage::new`], the error describes the first problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.snapshot_interval == 0 {
+            return Err(ConfigError::ZeroSnapshotInterval);
+        }
+        // `Some(0)` disables the trie logs, only the snapshots themselves can be reached then.
+        if let Some(max_saved_trie_logs) = self.max_saved_trie_logs {
+            if max_saved_trie_logs != 0 && (max_saved_trie_logs as u64) < self.snapshot_interval {
+                return Err(ConfigError::TrieLogsShorterThanSnapshotInterval {
+                    max_saved_trie_logs,
+                    snapshot_interval: self.snapshot_interval,
+                });
+            }
+        }
+        if self.auto_compaction == Some(0) {
+            return Err(ConfigError::ZeroAutoCompaction);
+        }
+        if self.max_batch_bytes == Some(0) {
+            return Err(ConfigError::ZeroMaxBatchBytes);
+        }
+        for (identifier, hasher) in &self.trie_hashers {
+            if hasher.arity() != 2 {
+                return Err(ConfigError::UnsupportedArity {
+                    identifier: identifier.clone(),
+                    arity: hasher.arity(),
+                });
+            }
         }
+        Ok(())
+    }
+}
+
+/// Leaf and metadata changes of a fork, see [`BonsaiStorage::fork`].
+#[derive(Debug, Clone, Default)]
+pub struct ForkChanges {
+    /// Leaves as stored in the flat storage, along with their raw payloads.
+    leaves: HashMap<ByteVec, Option<ByteVec>>,
+    meta: HashMap<ByteVec, Option<ByteVec>>,
+}
+
+impl ForkChanges {
+    /// Number of leaves changed by the fork.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Whether the fork changed neither leaves nor metadata.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty() && self.meta.is_empty()
+    }
+}
+
+/// How a transactional state is created, see [`BonsaiStorage::get_transactional_state_with_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionalStateInfo<ChangeID> {
+    /// Commit of the snapshot the transactional state is created from.
+    pub snapshot_id: ChangeID,
+    /// Number of trie logs replayed on top of the snapshot to reach the requested commit.
+    pub replayed_trie_logs: u64,
+}
+
+/// Approximate bytes used in the database by a trie, see [`BonsaiStorage::disk_usage`]. Keys and
+/// values are counted, not the overhead of the database itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Bytes of the trie nodes.
+    pub trie: u64,
+    /// Bytes of the leaves, in the flat storage.
+    pub flat: u64,
+    /// Bytes of the saved trie logs recording the changes of the trie.
+    pub trie_logs: u64,
+}
+
+impl DiskUsage {
+    pub fn total(&self) -> u64 {
+        self.trie + self.flat + self.trie_logs
     }
 }
 
+/// In-memory state of a trie until the next commit, see [`BonsaiStorage::pending_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingStats {
+    /// Trie nodes loaded or created in memory.
+    pub nodes: usize,
+    /// Stored trie nodes to be removed by the next commit.
+    pub death_row: usize,
+    /// Leaves inserted or removed since the last commit.
+    pub modified_leaves: usize,
+}
+
 /// Structure used to represent a change in the trie for a specific value.
 /// It contains the old value and the new value.
 /// If the `old_value` is None, it means that the key was not present in the trie before the change.
@@ -197,6 +432,16 @@ pub struct Change {
 /// This structure is the main entry point to work with this crate.
 pub struct BonsaiStorage<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> {
     tries: MerkleTrees<H, DB, ChangeID>,
+    change_sink: Option<Arc<dyn ChangeSink<ChangeID>>>,
+    /// Changes refused by `change_sink`, oldest first, see [`ChangeSinkPolicy::Buffer`].
+    change_sink_buffer: VecDeque<(ChangeID, Vec<LeafChange>)>,
+    commit_hooks: Vec<Arc<CommitHook<ChangeID>>>,
+    /// Parent trie and key of the root hash of each child trie, see
+    /// [`BonsaiStorage::link_child_trie`].
+    child_tries: BTreeMap<ByteVec, (ByteVec, BitVec)>,
+    witness: WitnessRecorder,
+    /// Whether the database must not be written, see [`BonsaiStorageBuilder::read_only`].
+    read_only: bool,
 }
 
 impl<ChangeID: Id, DB: BonsaiDatabase + fmt::Debug, H: StarkHash + Send + Sync> fmt::Debug
@@ -219,6 +464,12 @@ where
     fn clone(&self) -> Self {
         Self {
             tries: self.tries.clone(),
+            change_sink: self.change_sink.clone(),
+            change_sink_buffer: self.change_sink_buffer.clone(),
+            commit_hooks: self.commit_hooks.clone(),
+            child_tries: self.child_tries.clone(),
+            witness: self.witness.clone(),
+            read_only: self.read_only,
         }
     }
 }
@@ -233,26 +484,89 @@ where
     H: StarkHash + Send + Sync,
 {
     /// Create a new bonsai storage instance
-    pub fn new(db: DB, config: BonsaiStorageConfig, max_height: u8) -> Self {
-        let key_value_db = KeyValueDB::new(db, config.into(), None);
-        Self {
-            tries: MerkleTrees::new(key_value_db, max_height),
-        }
+    ///
+    /// Databases written by older versions of this crate are upgraded first, see [`migrations`].
+    /// Same as [`BonsaiStorage::builder`] with `config` and `max_height`.
+    pub fn new(
+        db: DB,
+        config: BonsaiStorageConfig,
+        max_height: u8,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        Self::builder(db)
+            .config(config)
+            .max_height(max_height)
+            .build()
+    }
+
+    /// Open a storage on `db` with more options than [`BonsaiStorage::new`].
+    pub fn builder(db: DB) -> BonsaiStorageBuilder<ChangeID, DB, H> {
+        BonsaiStorageBuilder::new(db)
     }
 
+    /// Open a database with the parameters it was stored with, see [`StoredConfig`], and the
+    /// default values of the others. The tries with their own hasher need
+    /// [`BonsaiStorageBuilder::trie_hasher`], as the hashers are not stored. Fails with
+    /// [`ConfigError::MissingMaxHeight`] if the database was never opened by a version of this
+    /// crate which stores them.
+    pub fn open_existing(db: DB) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        Self::builder(db).build()
+    }
+
+    /// Same as [`BonsaiStorage::builder`] with `config`, `max_height` and
+    /// [`BonsaiStorageBuilder::transactional_state`].
     pub fn new_from_transactional_state(
         db: DB,
         config: BonsaiStorageConfig,
         max_height: u8,
         created_at: ChangeID,
     ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
-        let key_value_db = KeyValueDB::new(db, config.into(), Some(created_at));
-        let tries = MerkleTrees::<H, DB, ChangeID>::new(key_value_db, max_height);
-        Ok(Self { tries })
+        Self::builder(db)
+            .config(config)
+            .max_height(max_height)
+            .transactional_state(created_at)
+            .build()
+    }
+
+    /// Open the storage on `db`, already checked by [`BonsaiStorageBuilder::build`].
+    fn open(
+        db: DB,
+        config: BonsaiStorageConfig,
+        max_height: u8,
+        created_at: Option<ChangeID>,
+        read_only: bool,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        let mut key_value_db = KeyValueDB::new(db, config.into(), created_at);
+        if created_at.is_none() {
+            key_value_db.load_latest_id()?;
+        }
+        key_value_db.load_bulk_loaded_at()?;
+        if created_at.is_none() {
+            key_value_db.load_reverted_from()?;
+            key_value_db.load_deferred_pruning()?;
+        }
+        Ok(Self {
+            tries: MerkleTrees::new(key_value_db, max_height),
+            change_sink: None,
+            change_sink_buffer: VecDeque::new(),
+            commit_hooks: Vec::new(),
+            child_tries: BTreeMap::new(),
+            witness: WitnessRecorder::default(),
+            read_only,
+        })
+    }
+
+    /// Fail with [`BonsaiStorageError::ReadOnly`] if the storage was opened with
+    /// [`BonsaiStorageBuilder::read_only`].
+    fn check_writable<E: DBError>(&self) -> Result<(), BonsaiStorageError<E>> {
+        if self.read_only {
+            return Err(BonsaiStorageError::ReadOnly);
+        }
+        Ok(())
     }
 
     /// Insert a new key/value in the trie, overwriting the previous value if it exists.
-    /// If the value already exists it will overwrite it.
+    /// If the value already exists it will overwrite it. A value of [`Felt::ZERO`] removes the key,
+    /// unless the storage has [`BonsaiStorageConfig::store_zero_values`].
     pub fn insert(
         &mut self,
         identifier: &[u8],
@@ -260,6 +574,39 @@ where
         value: &Felt,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         self.tries.set(identifier, key, *value)?;
+        self.witness.record(identifier, key);
+        Ok(())
+    }
+
+    /// Get the value of a key, after inserting the one returned by `f` if it has none, for
+    /// instance to initialize a storage slot on its first access. The leaf is read once, and the
+    /// trie only traversed to insert it, instead of twice with [`BonsaiStorage::get`] followed by
+    /// [`BonsaiStorage::insert`]. Nothing is inserted when `f` returns a value which
+    /// [`BonsaiStorage::insert`] would remove.
+    pub fn get_or_insert_with(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        f: impl FnOnce() -> Felt,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        let value = self.tries.get_or_insert_with(identifier, key, f)?;
+        self.witness.record(identifier, key);
+        Ok(value)
+    }
+
+    /// Insert a key along with a raw payload, stored next to the leaf but not hashed by the trie:
+    /// `commitment` is the value of the leaf, and should commit to `raw`. The payload is read with
+    /// [`BonsaiStorage::get_raw`], and dropped when the key is inserted again without one. A zero
+    /// commitment removes the key, as with [`BonsaiStorage::insert`].
+    pub fn insert_raw(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        raw: &[u8],
+        commitment: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.set_raw(identifier, key, *commitment, raw)?;
+        self.witness.record(identifier, key);
         Ok(())
     }
 
@@ -270,281 +617,1968 @@ where
         identifier: &[u8],
         key: &BitSlice,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.set(identifier, key, Felt::ZERO)?;
+        self.tries.remove(identifier, key)?;
+        self.witness.record(identifier, key);
         Ok(())
     }
 
-    /// Get a value in the trie.
-    pub fn get(
-        &self,
+    /// Remove many keys of a trie, as [`BonsaiStorage::remove`] does for each of them, but the
+    /// trie nodes they share are read and traversed once.
+    pub fn remove_batch(
+        &mut self,
         identifier: &[u8],
-        key: &BitSlice,
-    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.get(identifier, key)
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let keys: Vec<_> = keys.into_iter().collect();
+        self.tries.remove_batch(identifier, &keys)?;
+        for key in &keys {
+            self.witness.record(identifier, key.as_ref());
+        }
+        Ok(())
     }
 
-    /// Gets a value in a trie at a given commit ID.
-    ///
-    /// Note that this is much faster that calling `revert_to1
-    /// as it only reverts storage for a single key.
-    pub fn get_at(
-        &self,
+    /// Remove all the keys of a trie starting with `prefix`, returning how many were removed. An
+    /// empty prefix clears the trie, for instance the storage of a replaced contract. The subtree
+    /// holding these keys is removed at once instead of key by key.
+    pub fn remove_prefix(
+        &mut self,
         identifier: &[u8],
-        key: &BitSlice,
-        id: ChangeID,
-    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.get_at(identifier, key, id)
+        prefix: &BitSlice,
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        let removed = self.tries.remove_prefix(identifier, prefix)?;
+        for key in &removed {
+            self.witness.record(identifier, key);
+        }
+        Ok(removed.len())
     }
 
-    /// Checks if the key exists in the trie.
-    pub fn contains(
-        &self,
+    /// Reset the trie `identifier` to the empty trie, along with its uncommitted changes. The next
+    /// commit has the empty root for this trie, and its trie log records the removal of every leaf,
+    /// so that [`BonsaiStorage::revert_to`] brings them back.
+    pub fn clear_trie(
+        &mut self,
         identifier: &[u8],
-        key: &BitSlice,
-    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.contains(identifier, key)
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.remove_prefix(identifier, BitSlice::empty())?;
+        Ok(())
     }
 
-    /// Go to a specific commit ID.
-    /// If insert/remove is called between the last `commit()` and a call to this function,
-    /// the in-memory changes will be discarded.
-    pub fn revert_to(
+    /// Write the root hash of the trie `child` as the leaf `key` of the trie `parent`, now and
+    /// before every commit changing the child trie, for instance the storage root of a contract in
+    /// the contract trie. The leaf is written as an uncommitted change, so that it is recorded in
+    /// the trie log of the commit and reverted along with the child trie. It is removed while the
+    /// child trie is empty. Child tries can have children of their own, whose root hashes are
+    /// written first. The changed child tries are hashed to do so, then again by the commit.
+    ///
+    /// Links are kept in memory, and copied to the forks and transactional states of the storage.
+    /// As [`BonsaiStorage::prepare_commit`] only takes `&self`, call
+    /// [`BonsaiStorage::update_child_tries`] before it: [`BonsaiStorage::commit_prepared`] fails
+    /// with [`BonsaiStorageError::PreparedCommitStale`] otherwise. Fails with
+    /// [`BonsaiStorageError::ChildTrieCycle`] if `parent` is `child` or one of its descendants.
+    pub fn link_child_trie(
         &mut self,
-        _requested_id: ChangeID,
+        child: &[u8],
+        parent: &[u8],
+        key: &BitSlice,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        // self.tries.reset_to_last_commit()?;
-
-        // let kv = self.tries.db_mut();
-
-        // // Clear current changes
-        // kv.changes_store.current_changes.0.clear();
-
-        // // If requested equals last recorded, do nothing
-        // if Some(&requested_id) == kv.changes_store.id_queue.back() {
-        //     return Ok(());
-        // }
-
-        // // Make sure we are not trying to revert with an invalid id
-        // let Some(id_position) = kv
-        //     .changes_store
-        //     .id_queue
-        //     .iter()
-        //     .position(|id| *id == requested_id)
-        // else {
-        //     return Err(BonsaiStorageError::GoTo(format!(
-        //         "Requested id {:?} was removed or has not been recorded",
-        //         requested_id
-        //     )));
-        // };
-
-        // // Accumulate changes from requested to last recorded
-        // let mut full = Vec::new();
-        // for id in kv
-        //     .changes_store
-        //     .id_queue
-        //     .iter()
-        //     .skip(id_position)
-        //     .rev()
-        //     .take_while(|id| *id != &requested_id)
-        // {
-        //     full.extend(
-        //         ChangeBatch::deserialize(
-        //             id,
-        //             kv.db.get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?,
-        //         )
-        //         .0,
-        //     );
-        // }
-
-        // // Revert changes
-        // let mut batch = kv.db.create_batch();
-        // for (key, change) in full.iter().rev() {
-        //     let key = DatabaseKey::from(key);
-        //     match (&change.old_value, &change.new_value) {
-        //         (Some(old_value), Some(_)) => {
-        //             kv.db.insert(&key, old_value, Some(&mut batch))?;
-        //         }
-        //         (Some(old_value), None) => {
-        //             kv.db.insert(&key, old_value, Some(&mut batch))?;
-        //         }
-        //         (None, Some(_)) => {
-        //             kv.db.remove(&key, Some(&mut batch))?;
-        //         }
-        //         (None, None) => unreachable!(),
-        //     };
-        // }
-
-        // // Truncate trie logs at the requested id
-        // let mut truncated = kv.changes_store.id_queue.split_off(id_position);
-        // if let Some(current) = truncated.pop_front() {
-        //     kv.changes_store.id_queue.push_back(current);
-        // }
-        // for id in truncated.iter() {
-        //     kv.db
-        //         .remove_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))?;
-        // }
+        let mut ancestor = Some(parent);
+        while let Some(identifier) = ancestor {
+            if identifier == child {
+                return Err(BonsaiStorageError::ChildTrieCycle {
+                    child: child.into(),
+                });
+            }
+            ancestor = self
+                .child_tries
+                .get(identifier)
+                .map(|(parent, _)| parent.as_slice());
+        }
+        Self::write_child_root(&mut self.tries, &mut self.witness, child, parent, key)?;
+        self.child_tries
+            .insert(child.into(), (parent.into(), key.to_bitvec()));
+        Ok(())
+    }
 
-        // // Write revert changes and trie logs truncation
-        // kv.db.write_batch(batch)?;
-        // Ok(())
-        todo!()
+    /// Stop writing the root hash of the trie `child` to its parent, see
+    /// [`BonsaiStorage::link_child_trie`], leaving the leaf already written. Returns whether it was
+    /// linked.
+    pub fn unlink_child_trie(&mut self, child: &[u8]) -> bool {
+        self.child_tries.remove(child).is_some()
     }
 
-    /// Get all changes applied at a certain commit ID.
-    #[allow(clippy::type_complexity)]
-    pub fn get_changes(
-        &self,
-        id: ChangeID,
-    ) -> Result<HashMap<BitVec, Change>, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.db_ref().get_changes(id)
+    /// Write the root hashes the child tries will have to their parents, as the commits do, see
+    /// [`BonsaiStorage::link_child_trie`]. Only the child tries with uncommitted changes, and
+    /// their ancestors, are read.
+    pub fn update_child_tries(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if self.child_tries.is_empty() {
+            return Ok(());
+        }
+        // The changed tries and their ancestors, with their depth.
+        let mut changed = BTreeMap::new();
+        for identifier in self.tries.changed_tries() {
+            let mut ancestors = Vec::new();
+            let mut identifier = identifier.as_slice();
+            while let Some((child, (parent, _))) = self.child_tries.get_key_value(identifier) {
+                ancestors.push(child);
+                identifier = parent;
+            }
+            let depth = ancestors.len();
+            for (i, identifier) in ancestors.into_iter().enumerate() {
+                changed.insert(identifier, depth - i);
+            }
+        }
+        // Deepest first, so that the root hash of a child trie includes the ones of its children.
+        let mut changed: Vec<_> = changed.into_iter().collect();
+        changed.sort_by_key(|(_, depth)| core::cmp::Reverse(*depth));
+        for (child, _) in changed {
+            let (parent, key) = &self.child_tries[child];
+            Self::write_child_root(&mut self.tries, &mut self.witness, child, parent, key)?;
+        }
+        Ok(())
     }
 
-    #[cfg(test)]
-    pub fn dump_database(&self) {
-        self.tries.db_ref().db.dump_database();
+    /// Write the root hash the trie `child` will have as the leaf `key` of the trie `parent`,
+    /// unless it already holds it.
+    fn write_child_root(
+        tries: &mut MerkleTrees<H, DB, ChangeID>,
+        witness: &mut WitnessRecorder,
+        child: &[u8],
+        parent: &[u8],
+        key: &BitSlice,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        // The empty child trie has no leaf, even in the tries storing zero values.
+        let root_hash = Some(tries.pending_root_hash(child)?).filter(|hash| *hash != Felt::ZERO);
+        if tries.get(parent, key)? != root_hash {
+            match root_hash {
+                Some(root_hash) => tries.set(parent, key, root_hash)?,
+                None => tries.remove(parent, key)?,
+            }
+            witness.record(parent, key);
+        }
+        Ok(())
     }
 
-    #[cfg(test)]
-    pub fn dump(&self) {
-        self.tries.dump();
+    /// Get a value in the trie.
+    pub fn get(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let value = self.tries.get(identifier, key)?;
+        self.witness.record(identifier, key);
+        Ok(value)
     }
 
-    /// Get trie root hash at the latest commit
-    pub fn root_hash(
+    /// Get the raw payload of a key, see [`BonsaiStorage::insert_raw`]. It is empty for the keys
+    /// inserted with [`BonsaiStorage::insert`].
+    pub fn get_raw(
         &self,
         identifier: &[u8],
-    ) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.root_hash(identifier)
+        key: &BitSlice,
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        let raw = self.tries.get_raw(identifier, key)?;
+        self.witness.record(identifier, key);
+        Ok(raw.map(|raw| raw.to_vec()))
     }
 
-    /// This function must be used with transactional state only.
-    /// Similar to `commit` but without optimizations.
-    pub fn transactional_commit(
+    /// Set `key` of the Ethereum trie `identifier` to `value`, see [`eth`]. An empty value removes
+    /// the key, as in Ethereum.
+    #[cfg(feature = "eth")]
+    pub fn eth_insert(
         &mut self,
-        id: ChangeID,
+        identifier: &[u8],
+        key: &[u8],
+        value: &[u8],
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.commit()?;
-        self.tries.db_mut().commit(id)?;
-        Ok(())
+        let leaf_key = eth::leaf_key(key, self.tries.max_height);
+        if value.is_empty() {
+            return self.remove(identifier, &leaf_key);
+        }
+        if let Some((stored_key, _)) = self.eth_leaf(identifier, &leaf_key)? {
+            if stored_key != key {
+                return Err(BonsaiStorageError::Trie(format!(
+                    "Ethereum keys {stored_key:?} and {key:?} have the same leaf key"
+                )));
+            }
+        }
+        let (payload, commitment) = eth::encode_payload(key, value);
+        self.insert_raw(identifier, &leaf_key, &payload, &commitment)
     }
 
-    /// Get all the keys in a specific trie.
-    pub fn get_keys(
+    /// Get the value of `key` in the Ethereum trie `identifier`, see [`eth`].
+    #[cfg(feature = "eth")]
+    pub fn eth_get(
         &self,
         identifier: &[u8],
-    ) -> Result<Vec<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.get_keys(identifier)
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        let leaf_key = eth::leaf_key(key, self.tries.max_height);
+        Ok(self
+            .eth_leaf(identifier, &leaf_key)?
+            .filter(|(stored_key, _)| stored_key == key)
+            .map(|(_, value)| value))
     }
 
-    /// Get all the key-value pairs in a specific trie.
-    #[allow(clippy::type_complexity)]
-    pub fn get_key_value_pairs(
+    /// Root hash of the Ethereum trie `identifier`, uncommitted changes included, see [`eth`].
+    /// The hexary trie is built from all the leaves, so this takes time proportional to the number
+    /// of keys. The root hash at a previous commit is the one of its transactional state.
+    #[cfg(feature = "eth")]
+    pub fn eth_root_hash(
         &self,
         identifier: &[u8],
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.get_key_value_pairs(identifier)
+    ) -> Result<[u8; 32], BonsaiStorageError<DB::DatabaseError>> {
+        let leaves = self
+            .tries
+            .raw_leaves(identifier)?
+            .into_iter()
+            .map(|payload| {
+                eth::decode_payload(&payload).map_err(|source| BonsaiStorageError::DecodeError {
+                    key: identifier.into(),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(eth::trie_root(leaves))
     }
 
-    /// Get the id from the latest commit, or `None` if no commit has taken place yet.
-    pub fn get_latest_id(&self) -> Option<ChangeID> {
-        self.tries.db_ref().get_latest_id()
+    /// Ethereum key and value stored at `leaf_key`.
+    #[cfg(feature = "eth")]
+    #[allow(clippy::type_complexity)]
+    fn eth_leaf(
+        &self,
+        identifier: &[u8],
+        leaf_key: &BitSlice,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(payload) = self.get_raw(identifier, leaf_key)? else {
+            return Ok(None);
+        };
+        eth::decode_payload(&payload)
+            .map(Some)
+            .map_err(|source| BonsaiStorageError::DecodeError {
+                key: identifier.into(),
+                source,
+            })
     }
 
-    pub fn get_multi_proof(
+    /// Same as [`BonsaiStorage::insert`] with the key converted using [`Path::from_felt_251`], for
+    /// tries of height 251 keyed by felts such as Starknet contract addresses and storage keys.
+    /// Keys greater than or equal to 2^251 return [`BonsaiStorageError::FeltKeyOutOfRange`].
+    pub fn insert_felt(
         &mut self,
         identifier: &[u8],
-        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
-    ) -> Result<MultiProof, BonsaiStorageError<DB::DatabaseError>> {
-        self.tries.get_multi_proof(identifier, keys)
+        key: &Felt,
+        value: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let key = Path::from_felt_251(key).ok_or(BonsaiStorageError::FeltKeyOutOfRange(*key))?;
+        self.insert(identifier, &key, value)
     }
-}
 
-impl<ChangeID, DB, H> BonsaiStorage<ChangeID, DB, H>
-where
-    DB: BonsaiDatabase + BonsaiPersistentDatabase<ChangeID>,
-    ChangeID: id::Id,
-    H: StarkHash + Send + Sync,
-{
-    /// Update trie and database using all changes since the last commit.
-    pub fn commit(
+    /// Same as [`BonsaiStorage::remove`] with the key converted using [`Path::from_felt_251`].
+    pub fn remove_felt(
         &mut self,
-        id: ChangeID,
-    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
-        self.tries.commit()?;
-        self.tries.db_mut().commit(id)?;
-        self.tries.db_mut().create_snapshot(id);
-        Ok(())
+        identifier: &[u8],
+        key: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let key = Path::from_felt_251(key).ok_or(BonsaiStorageError::FeltKeyOutOfRange(*key))?;
+        self.remove(identifier, &key)
     }
 
-    #[allow(clippy::type_complexity)]
-    /// Get a transactional state of the trie at a specific commit ID.
-    ///
-    /// Transactional state allow you to fetch a point-in-time state of the trie. You can
-    /// apply changes to this state and merge it back into the main trie.
-    pub fn get_transactional_state(
+    /// Same as [`BonsaiStorage::get`] with the key converted using [`Path::from_felt_251`].
+    pub fn get_felt(
         &self,
-        change_id: ChangeID,
-        config: BonsaiStorageConfig,
-    ) -> Result<
-        Option<BonsaiStorage<ChangeID, DB::Transaction<'_>, H>>,
-        BonsaiStorageError<<DB::Transaction<'_> as BonsaiDatabase>::DatabaseError>,
-    > {
-        // If requested equals last recorded, do nothing
-        // if Some(&change_id) == self.tries.db_ref().changes_store.id_queue.back() {
-        //     return Ok(());
-        // }
+        identifier: &[u8],
+        key: &Felt,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = Path::from_felt_251(key).ok_or(BonsaiStorageError::FeltKeyOutOfRange(*key))?;
+        self.get(identifier, &key)
+    }
 
-        if let Some(transaction) = self.tries.db_ref().get_transaction(change_id)? {
-            Ok(Some(BonsaiStorage::new_from_transactional_state(
-                transaction,
-                config,
-                self.tries.max_height,
-                change_id,
-            )?))
-        } else {
-            Ok(None)
+    /// Get many values in a trie, in the same order as `keys`.
+    ///
+    /// This is equivalent to calling [`BonsaiStorage::get`] for each key, but the values are read
+    /// from the database in a single batch, which is much faster on backends such as RocksDB.
+    pub fn get_many(
+        &self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<Vec<Option<Felt>>, BonsaiStorageError<DB::DatabaseError>> {
+        let keys: Vec<_> = keys.into_iter().collect();
+        let values = self.tries.get_many(identifier, &keys)?;
+        for key in &keys {
+            self.witness.record(identifier, key.as_ref());
         }
+        Ok(values)
     }
 
-    /// Get a copy of the config that can be used to create a transactional state or a new bonsai storage.
-    pub fn get_config(&self) -> BonsaiStorageConfig {
-        self.tries.db_ref().get_config().into()
+    /// Gets a value in a trie at a given commit ID, from the trie logs of the following commits.
+    ///
+    /// Note that this is much faster that calling `revert_to`
+    /// as it only reverts storage for a single key. The commit must still be reachable by
+    /// `revert_to`, see [`BonsaiStorage::pin_commit`] to keep it so.
+    pub fn get_at(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+        id: ChangeID,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.get_at(identifier, key, id)
     }
 
-    /// Merge a transactional state into the main trie.
-    pub fn merge(
-        &mut self,
-        transactional_bonsai_storage: BonsaiStorage<ChangeID, DB::Transaction<'_>, H>,
-    ) -> Result<(), BonsaiStorageError<<DB as BonsaiPersistentDatabase<ChangeID>>::DatabaseError>>
+    /// Write the state at commit `id` to `target`, an empty database, so that it can be opened as
+    /// a standalone storage whose latest commit is `id`, for instance to bootstrap a node. Only
+    /// the trie nodes and leaves are written, with the configuration and the metadata of the
+    /// tries: neither trie logs nor user metadata. The commit must still be reachable by
+    /// `revert_to`, and uncommitted changes are left out.
+    #[cfg(feature = "std")]
+    pub fn export_snapshot_into<T: BonsaiDatabase>(
+        &self,
+        id: ChangeID,
+        target: &mut T,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>>
     where
-        <DB as BonsaiDatabase>::DatabaseError: core::fmt::Debug,
+        T::DatabaseError: 'static,
     {
-        // memorize changes
-        let MerkleTrees { db, trees, .. } = transactional_bonsai_storage.tries;
+        self.tries.db_ref().export_state(id, target)
+    }
 
-        self.tries.db_mut().merge(db)?;
+    /// Same as [`BonsaiStorage::export_snapshot_into`] into a new RocksDB database at `path`,
+    /// which can be opened with [`databases::open_rocks_db`].
+    #[cfg(feature = "rocksdb")]
+    pub fn export_snapshot(
+        &self,
+        id: ChangeID,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let db = databases::create_rocks_db(path)
+            .map_err(|err| BonsaiStorageError::ExportTarget(Box::new(err)))?;
+        let mut target = databases::RocksDB::new(&db, databases::RocksDBConfig::default());
+        self.export_snapshot_into(id, &mut target)
+    }
 
-        // apply changes
-        for (identifier, tree) in trees {
-            for (k, op) in tree.cache_leaf_modified() {
-                match op {
-                    crate::trie::tree::InsertOrRemove::Insert(v) => {
-                        self.insert(&identifier, &bytes_to_bitvec(k), v)
-                            .map_err(|e| {
-                                BonsaiStorageError::Merge(format!(
-                                    "While merging insert({:?} {}) faced error: {:?}",
-                                    k, v, e
-                                ))
-                            })?;
-                    }
-                    crate::trie::tree::InsertOrRemove::Remove => {
-                        self.remove(&identifier, &bytes_to_bitvec(k)).map_err(|e| {
-                            BonsaiStorageError::Merge(format!(
-                                "While merging remove({:?}) faced error: {:?}",
-                                k, e
-                            ))
-                        })?;
-                    }
+    /// Checks if the key exists in the trie.
+    pub fn contains(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        let contains = self.tries.contains(identifier, key)?;
+        self.witness.record(identifier, key);
+        Ok(contains)
+    }
+
+    /// Store auxiliary data, such as the block hash of a commit, at `key`. Metadata lives in its
+    /// own column, next to the tries: it is written by the next commit, recorded in its trie log
+    /// and reverted along with the tries.
+    pub fn put_meta(&mut self, key: &[u8], value: &[u8]) {
+        let key = TrieKey::new_meta(MetaKeyType::User, key);
+        self.tries.set_meta(key.as_slice(), Some(value));
+    }
+
+    /// Remove the metadata stored at `key`, on the next commit.
+    pub fn remove_meta(&mut self, key: &[u8]) {
+        let key = TrieKey::new_meta(MetaKeyType::User, key);
+        self.tries.set_meta(key.as_slice(), None);
+    }
+
+    /// Get the metadata stored at `key`, including the uncommitted changes.
+    pub fn get_meta(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries
+            .get_meta(TrieKey::new_meta(MetaKeyType::User, key).as_slice())
+    }
+
+    /// Get the commit tagged with `tag`, see [`BonsaiStorage::commit_with_tag`].
+    pub fn find_commit_by_tag(
+        &self,
+        tag: &[u8; 32],
+    ) -> Result<Option<ChangeID>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new_meta(MetaKeyType::Tag, tag);
+        let Some(id) = self.tries.get_meta(key.as_slice())? else {
+            return Ok(None);
+        };
+        let id =
+            u64::decode(&mut id.as_slice()).map_err(|source| BonsaiStorageError::DecodeError {
+                key: key.as_slice().into(),
+                source,
+            })?;
+        Ok(Some(ChangeID::from_u64(id)))
+    }
+
+    /// Build the empty trie `identifier` from `sorted_leaves`, by increasing key, and commit it as
+    /// `id`. Returns the root hash of the trie.
+    ///
+    /// This is much faster than inserting the leaves and committing them: the trie is built bottom
+    /// up in a single pass, see [`IncrementalTrieBuilder`], and written in large batches, without
+    /// recording the leaves in the trie log of the commit, nor taking a snapshot or sending them to
+    /// the change sink. As a consequence, the commits before `id` can't be reverted to anymore,
+    /// and the transactional states at or after `id` need a snapshot taken after the load, see
+    /// [`BonsaiStorage::create_snapshot_now`].
+    ///
+    /// The storage must have no uncommitted changes, and `id` must be greater than the latest
+    /// commit. If the load fails, the leaves and nodes already written stay in the database
+    /// without a root, and are overwritten by another load of the same leaves.
+    pub fn bulk_load(
+        &mut self,
+        identifier: &[u8],
+        sorted_leaves: impl IntoIterator<Item = (BitVec, Felt)>,
+        id: ChangeID,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        if self.tries.has_uncommitted_changes() {
+            return Err(BonsaiStorageError::UncommittedChanges);
+        }
+        if let Some(latest_id) = self
+            .tries
+            .db_ref()
+            .get_latest_id()
+            .filter(|latest_id| id <= *latest_id)
+        {
+            return Err(BonsaiStorageError::BulkLoad(format!(
+                "id {:?} is not greater than the latest commit {:?}",
+                id, latest_id
+            )));
+        }
+        if self.tries.root_hash(identifier)? != self.tries.empty_root(identifier) {
+            return Err(BonsaiStorageError::BulkLoad(format!(
+                "trie {:?} is not empty",
+                identifier
+            )));
+        }
+        self.tries.bulk_load(identifier, sorted_leaves, id)
+    }
+
+    /// Import the state written by [`BonsaiStorage::export_snapshot_into`] to `reader`, which may
+    /// come from an untrusted source, and commit it at the latest commit of `reader`, which is
+    /// returned. `expected_roots` holds the trusted root hash of every trie, by identifier.
+    ///
+    /// Only the leaves of `reader` are read: the tries are rebuilt from them as
+    /// [`BonsaiStorage::bulk_load`] does, and their root hashes checked against the trusted ones.
+    /// The import fails with [`BonsaiStorageError::ImportRootMismatch`] if a root hash differs,
+    /// or with [`BonsaiStorageError::UntrustedTrie`] if `reader` has a trie without trusted root
+    /// hash. The leaves and the nodes below the roots are staged in the database in chunks, and
+    /// the roots are written with the commit once every root hash is verified, so that the state
+    /// becomes visible at once, or not at all. The staged entries are removed if the import fails.
+    /// The raw payloads of the leaves are not imported.
+    ///
+    /// The storage must be empty.
+    #[cfg(feature = "std")]
+    pub fn import_snapshot<R: BonsaiDatabase>(
+        &mut self,
+        reader: &R,
+        expected_roots: &HashMap<ByteVec, Felt>,
+    ) -> Result<ChangeID, BonsaiStorageError<DB::DatabaseError>>
+    where
+        R::DatabaseError: 'static,
+    {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        if self.tries.has_uncommitted_changes() || self.tries.db_ref().get_latest_id().is_some() {
+            return Err(BonsaiStorageError::ImportIntoNonEmpty);
+        }
+        let import_error = |err: R::DatabaseError| BonsaiStorageError::ImportSource(Box::new(err));
+        let key = TrieKey::new_meta(MetaKeyType::LatestId, &[]);
+        let Some(id) = reader.get(&DatabaseKey::from(&key)).map_err(import_error)? else {
+            return Err(BonsaiStorageError::EmptySnapshot);
+        };
+        let id =
+            u64::decode(&mut id.as_slice()).map_err(|source| BonsaiStorageError::DecodeError {
+                key: key.as_slice().into(),
+                source,
+            })?;
+        let id = ChangeID::from_u64(id);
+        self.tries.import_snapshot(reader, expected_roots, id)?;
+        Ok(id)
+    }
+
+    /// Go to a specific commit ID.
+    /// If insert/remove is called between the last `commit()` and a call to this function,
+    /// the in-memory changes will be discarded.
+    ///
+    /// The leaves written to the parents of the child tries, see
+    /// [`BonsaiStorage::link_child_trie`], are recorded in the trie logs of their commits like the
+    /// others, so the linked tries are reverted together to their state at `requested_id`.
+    pub fn revert_to(
+        &mut self,
+        requested_id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.revert_to_with(requested_id, None)
+    }
+
+    /// Same as [`BonsaiStorage::revert_to`], stopping with [`BonsaiStorageError::Cancelled`] once
+    /// `cancel` is cancelled. The trie logs are applied to a batch which is written at the end, so
+    /// a cancelled revert writes nothing, only the in-memory changes are discarded.
+    pub fn revert_to_cancellable(
+        &mut self,
+        requested_id: ChangeID,
+        cancel: &CancellationToken,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.revert_to_with(requested_id, Some(cancel))
+    }
+
+    fn revert_to_with(
+        &mut self,
+        requested_id: ChangeID,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        self.tries.reset_to_last_commit();
+
+        let kv = self.tries.db_mut();
+        let mut batch = kv.create_batch();
+        kv.revert_to(requested_id, &mut batch, cancel)?;
+        kv.write_batch(batch)?;
+        kv.auto_compact()?;
+        self.change_sink_buffer
+            .retain(|(id, _)| *id <= requested_id);
+        Ok(())
+    }
+
+    /// Go forward to a specific commit ID undone by [`BonsaiStorage::revert_to`], by applying
+    /// again the trie logs it kept with [`BonsaiStorageConfig::keep_reverted_trie_logs`]. This is
+    /// possible up to [`BonsaiStorage::get_reverted_from`], until the next commit discards them.
+    pub fn advance_to(
+        &mut self,
+        requested_id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        self.tries.reset_to_last_commit();
+
+        let kv = self.tries.db_mut();
+        let mut batch = kv.create_batch();
+        kv.advance_to(requested_id, &mut batch)?;
+        kv.write_batch(batch)?;
+        kv.auto_compact()
+    }
+
+    /// Latest commit undone by [`BonsaiStorage::revert_to`] whose trie log is kept, up to which
+    /// [`BonsaiStorage::advance_to`] can go, or `None` if there is none.
+    pub fn get_reverted_from(&self) -> Option<ChangeID> {
+        self.tries.db_ref().reverted_from
+    }
+
+    /// Go back to a specific commit ID for the trie `identifier` only: the leaves changed since
+    /// then get back their values as uncommitted changes, which the next commit makes. The other
+    /// tries and the history of the storage are left untouched.
+    ///
+    /// The trie must have no uncommitted changes, and the trie logs of the commits since `id` are
+    /// needed, of which only the entries of this trie are read.
+    ///
+    /// The child tries of the trie, see [`BonsaiStorage::link_child_trie`], are reverted along with
+    /// it, as its leaves hold their root hashes, and so are their own children. The leaf of the
+    /// trie in its parent is then updated, so that the root hashes of its ancestors match it.
+    pub fn revert_identifier_to(
+        &mut self,
+        identifier: &[u8],
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let tries = self.with_descendants(identifier);
+        // Checked for all of them first, so that none is reverted if one can't be.
+        if let Some(changed) = self
+            .tries
+            .changed_tries()
+            .find(|changed| tries.contains(changed))
+        {
+            return Err(BonsaiStorageError::TrieUncommittedChanges {
+                identifier: changed.clone(),
+            });
+        }
+        for identifier in &tries {
+            self.tries.revert_trie_to(identifier, id)?;
+        }
+        self.update_child_tries()
+    }
+
+    /// The trie `identifier` followed by its descendants, see [`BonsaiStorage::link_child_trie`].
+    fn with_descendants(&self, identifier: &[u8]) -> Vec<ByteVec> {
+        let mut tries = vec![ByteVec::from(identifier)];
+        let mut i = 0;
+        while i < tries.len() {
+            let parent = tries[i].clone();
+            tries.extend(
+                self.child_tries
+                    .iter()
+                    .filter(|(_, (child_parent, _))| *child_parent == parent)
+                    .map(|(child, _)| child.clone()),
+            );
+            i += 1;
+        }
+        tries
+    }
+
+    /// Ask the database to reclaim the space left by the removed trie logs and values, which some
+    /// databases, like RocksDB, only do in the background. This can take a while on large
+    /// databases, see [`BonsaiStorageConfig::auto_compaction`] to do it automatically.
+    pub fn compact(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.db_mut().compact()
+    }
+
+    /// Keep a pending block as the uncommitted changes of the storage, see [`PendingState`]. The
+    /// storage must have no uncommitted changes.
+    pub fn pending_state(
+        &mut self,
+    ) -> Result<PendingState<'_, ChangeID, DB, H>, BonsaiStorageError<DB::DatabaseError>> {
+        PendingState::new(self)
+    }
+
+    /// Save the current uncommitted changes, so that they can later be restored with
+    /// [`BonsaiStorage::rollback_to_savepoint`]. The changes made after a savepoint are recorded
+    /// in an in-memory undo log, the database is not touched.
+    ///
+    /// Savepoints are discarded on commit, revert and merge.
+    pub fn savepoint(&mut self) -> SavepointId {
+        self.tries.savepoint()
+    }
+
+    /// Discard the uncommitted changes made since `savepoint` was taken. The savepoint stays valid,
+    /// but the savepoints taken after it are discarded.
+    pub fn rollback_to_savepoint(
+        &mut self,
+        savepoint: SavepointId,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.rollback_to_savepoint(savepoint)
+    }
+
+    /// Get all changes applied at a certain commit ID.
+    #[allow(clippy::type_complexity)]
+    pub fn get_changes(
+        &self,
+        id: ChangeID,
+    ) -> Result<HashMap<BitVec, Change>, BonsaiStorageError<DB::DatabaseError>> {
+        let max_height = self.tries.max_height;
+        Ok(self
+            .tries
+            .db_ref()
+            .get_changes(id)?
+            .into_iter()
+            .map(|(key, change)| (split_flat_key(&key, max_height).1, change))
+            .collect())
+    }
+
+    /// Changes applied to the trie `identifier` at a certain commit ID. Only the part of the trie
+    /// log of the commit about this trie is read.
+    pub fn get_trie_changes(
+        &self,
+        identifier: &[u8],
+        id: ChangeID,
+    ) -> Result<HashMap<BitVec, Change>, BonsaiStorageError<DB::DatabaseError>> {
+        let max_height = self.tries.max_height;
+        Ok(self
+            .tries
+            .db_ref()
+            .get_trie_changes(id, identifier)?
+            .into_iter()
+            .filter_map(|(key, change)| {
+                // The trie logs written before they were framed by trie have the other tries.
+                let (key_identifier, key) = split_flat_key(&key, max_height);
+                (key_identifier == identifier).then_some((key, change))
+            })
+            .collect())
+    }
+
+    /// Get all the database changes of a commit, trie nodes and metadata included, so that another
+    /// storage can replay it with [`BonsaiStorage::apply_change_batch`]. This requires the trie log
+    /// of the commit.
+    pub fn get_change_batch(
+        &self,
+        id: ChangeID,
+    ) -> Result<ChangeBatch, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.db_ref().get_trie_log(id)
+    }
+
+    /// Commits whose trie log is in the database, in increasing order: the ones that
+    /// [`BonsaiStorage::revert_to`] can undo, followed by the ones that
+    /// [`BonsaiStorage::advance_to`] can apply again. Commits that changed nothing have no trie
+    /// log and are not listed, reverting them has nothing to undo.
+    ///
+    /// This reads the database rather than relying on
+    /// [`BonsaiStorageConfig::max_saved_trie_logs`], so it shows the trie logs missing after a
+    /// crash or a change of configuration.
+    pub fn list_trie_logs(&self) -> Result<Vec<ChangeID>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.db_ref().trie_log_ids()
+    }
+
+    /// Bytes used in the database by the trie log of commit `id`, or `None` if it isn't there.
+    pub fn get_trie_log_size(
+        &self,
+        id: ChangeID,
+    ) -> Result<Option<u64>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.db_ref().trie_log_size(id)
+    }
+
+    #[cfg(test)]
+    pub fn dump_database(&self) {
+        self.tries.db_ref().db.dump_database();
+    }
+
+    #[cfg(test)]
+    pub fn dump(&self) {
+        self.tries.dump();
+    }
+
+    /// Get trie root hash at the latest commit
+    pub fn root_hash(
+        &self,
+        identifier: &[u8],
+    ) -> Result<BonsaiTrieHash, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.root_hash(identifier)
+    }
+
+    /// Root hash of a specific trie at a given commit ID, recorded by the commits so that it is not
+    /// recomputed. `None` if it is not known: the commit is not saved anymore, or the root hashes
+    /// were not recorded yet by the version of this crate which made it.
+    pub fn root_hash_at(
+        &self,
+        identifier: &[u8],
+        id: ChangeID,
+    ) -> Result<Option<BonsaiTrieHash>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.root_hash_at(identifier, id)
+    }
+
+    /// This function must be used with transactional state only.
+    /// Similar to `commit` but without optimizations.
+    pub fn transactional_commit(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.update_child_tries()?;
+        self.tries.commit()?;
+        self.tries.db_mut().commit(id)?;
+        Ok(())
+    }
+
+    /// Get the keys of a specific trie at the last commit, in increasing order: the `limit` first
+    /// ones after `start_after`, a key returned by a previous call, or from the first one without
+    /// it. See [`BonsaiStorage::iter_keys`] to go through all of them.
+    pub fn get_keys(
+        &self,
+        identifier: &[u8],
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        self.iter_keys(identifier, start_after)
+            .take(limit)
+            .collect()
+    }
+
+    /// Same as [`BonsaiStorage::get_keys`], stopping with [`BonsaiStorageError::Cancelled`] once
+    /// `cancel` is cancelled.
+    pub fn get_keys_cancellable(
+        &self,
+        identifier: &[u8],
+        start_after: Option<&[u8]>,
+        limit: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+        self.iter_keys(identifier, start_after)
+            .take(limit)
+            .map(|key| {
+                check_cancelled(Some(cancel))?;
+                key
+            })
+            .collect()
+    }
+
+    /// Iterate over the keys of a specific trie at the last commit, in increasing order, after
+    /// `start_after` if there is one. The nodes of the trie are read as the iteration goes, so the
+    /// keys are never all in memory at once.
+    pub fn iter_keys<'a>(
+        &'a self,
+        identifier: &'a [u8],
+        start_after: Option<&[u8]>,
+    ) -> impl Iterator<Item = Result<Vec<u8>, BonsaiStorageError<DB::DatabaseError>>> + 'a {
+        self.tries
+            .iter_leaves(identifier, start_after.map(BitSlice::from_slice))
+            .map(|leaf| leaf.map(|(key, _)| key.into_vec()))
+    }
+
+    /// Get the key-value pairs of a specific trie at the last commit, paginated as
+    /// [`BonsaiStorage::get_keys`]. The values are the leaves as stored in the database.
+    #[allow(clippy::type_complexity)]
+    pub fn get_key_value_pairs(
+        &self,
+        identifier: &[u8],
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.iter_key_value_pairs(identifier, start_after)
+            .take(limit)
+            .collect()
+    }
+
+    /// Iterate over the key-value pairs of a specific trie at the last commit, as
+    /// [`BonsaiStorage::iter_keys`].
+    #[allow(clippy::type_complexity)]
+    pub fn iter_key_value_pairs<'a>(
+        &'a self,
+        identifier: &'a [u8],
+        start_after: Option<&[u8]>,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), BonsaiStorageError<DB::DatabaseError>>> + 'a
+    {
+        self.tries
+            .iter_leaves(identifier, start_after.map(BitSlice::from_slice))
+            .map(move |leaf| {
+                let (key, _) = leaf?;
+                let value = self.tries.stored_leaf(identifier, &key)?.ok_or_else(|| {
+                    BonsaiStorageError::Trie(format!(
+                        "Leaf {key:?} of trie {identifier:?} is not in the flat storage"
+                    ))
+                })?;
+                Ok((key.into_vec(), value.into_vec()))
+            })
+    }
+
+    /// Iterate over the internal nodes of a specific trie at the last commit, in increasing path
+    /// order: the path leading to each node, from the root at the empty path, its kind and its
+    /// hash. The leaves are not nodes, they are read with [`BonsaiStorage::iter_key_value_pairs`].
+    ///
+    /// The nodes are all read before the iteration starts. The nodes of a trie stored inline, see
+    /// [`BonsaiStorageConfig::max_inline_leaves`], are rebuilt from its leaves, as they are not
+    /// stored.
+    pub fn iter_nodes(
+        &self,
+        identifier: &[u8],
+    ) -> Result<impl Iterator<Item = (BitVec, NodeKind, Felt)>, BonsaiStorageError<DB::DatabaseError>>
+    {
+        Ok(self
+            .tries
+            .committed_nodes(identifier)?
+            .into_iter()
+            .map(|(path, node, hash)| (path, NodeKind::from(&node), hash)))
+    }
+
+    /// Iterate over the keys and values of a specific trie at the last commit whose value matches
+    /// `filter`, in increasing key order. The values are the ones of the trie nodes read by the
+    /// iteration, so the leaves that don't match are never copied.
+    pub fn scan<'a>(
+        &'a self,
+        identifier: &'a [u8],
+        filter: impl Fn(&Felt) -> bool + 'a,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Felt), BonsaiStorageError<DB::DatabaseError>>> + 'a
+    {
+        self.tries
+            .iter_leaves(identifier, None)
+            .filter(move |leaf| leaf.as_ref().map_or(true, |(_, value)| filter(value)))
+            .map(|leaf| leaf.map(|(key, value)| (key.into_vec(), value)))
+    }
+
+    /// Get the number of leaves in a specific trie, including the changes that are not committed
+    /// yet.
+    ///
+    /// The count is kept in the database and updated on commit, so this does not iterate over the
+    /// keys. Tries committed by versions of this crate without this counter are not counted.
+    pub fn len(&self, identifier: &[u8]) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.len(identifier)
+    }
+
+    /// Approximate bytes used in the database by a specific trie, at the last commit, to find the
+    /// tries that take the most space.
+    ///
+    /// The usage is kept in the database and updated on commit. Tries committed by versions of
+    /// this crate without it are measured by reading all of their nodes and leaves, until their
+    /// next commit stores it, and only the trie logs written since then are counted.
+    pub fn disk_usage(
+        &self,
+        identifier: &[u8],
+    ) -> Result<DiskUsage, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.disk_usage(identifier)
+    }
+
+    /// In-memory state of the tries with uncommitted changes or loaded nodes, by identifier. It
+    /// grows with the changes until the next commit, which embedders can watch to commit early
+    /// when a block changes too much.
+    pub fn pending_stats(&self) -> HashMap<ByteVec, PendingStats> {
+        self.tries.pending_stats()
+    }
+
+    /// Work done by the storage since the last call, which resets the counters, for instance to
+    /// compare the cost of importing each block.
+    pub fn take_op_stats(&self) -> OpStats {
+        self.tries.db_ref().stats.take()
+    }
+
+    /// Rewrite the nodes of a specific trie that were written with an older version of the node
+    /// encoding, returns the number of rewritten nodes.
+    ///
+    /// Old nodes stay readable, and the nodes changed by a commit are always written with the
+    /// current version, so tries migrate lazily without this. It rewrites the remaining ones in
+    /// bulk, for example before a future version drops the support of an old encoding. The
+    /// rewrites are not recorded in the trie logs, as the content of the trie does not change.
+    pub fn migrate_nodes(
+        &mut self,
+        identifier: &[u8],
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.migrate_nodes(identifier)
+    }
+
+    /// Paths and stored bytes of the nodes of a specific trie moved out of it by
+    /// [`BonsaiStorage::migrate_nodes`] as they could not be decoded, see
+    /// [`BonsaiStorageConfig::quarantine_corrupted_nodes`].
+    pub fn quarantined_nodes(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Vec<(Path, ByteVec)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.quarantined_nodes(identifier)
+    }
+
+    /// Rebuild the nodes of a specific trie from its leaves at the last commit, returns the number
+    /// of nodes rewritten or removed.
+    ///
+    /// The flat storage holds the values of the leaves, from which the nodes are computed: this
+    /// repairs a trie whose nodes are corrupted or missing, without syncing it again. The leaves
+    /// and the rebuilt nodes are all held in memory, and only the nodes which differ from the
+    /// rebuilt ones are written. They are not recorded in the trie logs: the rebuilt nodes are the
+    /// ones the commits of the leaves wrote, so the trie can still be reverted. The record of a
+    /// trie stored inline is rebuilt in the same way.
+    ///
+    /// The trie must have no uncommitted changes.
+    pub fn rebuild_from_leaves(
+        &mut self,
+        identifier: &[u8],
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.rebuild_from_leaves(identifier)
+    }
+
+    /// Replace the configuration of the storage, and the parameters stored in the database with
+    /// it, see [`StoredConfig`]. Fails with [`ConfigError::StoredConfigMismatch`] when it stops
+    /// storing small tries inline, as the tries already inline would not be read anymore.
+    pub fn reconfigure(
+        &mut self,
+        config: BonsaiStorageConfig,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_writable()?;
+        config.validate().map_err(BonsaiStorageError::Config)?;
+        let stored = StoredConfig::new(
+            &self.tries.db_ref().get_config().into(),
+            self.tries.max_height,
+        );
+        let requested = StoredConfig::new(&config, self.tries.max_height);
+        if stored.max_inline_leaves.is_some() && requested.max_inline_leaves.is_none() {
+            return Err(BonsaiStorageError::Config(
+                ConfigError::StoredConfigMismatch {
+                    stored: Box::new(stored),
+                    requested: Box::new(requested),
+                },
+            ));
+        }
+        let db = self.tries.db_mut();
+        stored_config::write(&mut db.db, &requested)?;
+        db.config = config.into();
+        Ok(())
+    }
+
+    /// Whether a specific trie has no leaves, see [`BonsaiStorage::len`].
+    pub fn is_empty(
+        &self,
+        identifier: &[u8],
+    ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.len(identifier)? == 0)
+    }
+
+    /// Get the id from the latest commit, or `None` if no commit has taken place yet.
+    pub fn get_latest_id(&self) -> Option<ChangeID> {
+        self.tries.db_ref().get_latest_id()
+    }
+
+    /// Load the trie nodes on the paths to `keys` into memory ahead of time, so that subsequent
+    /// calls to [`BonsaiStorage::insert`] and [`BonsaiStorage::remove`] on these keys don't need to
+    /// read them from the database. The nodes stay in memory until the next commit or revert.
+    pub fn prefetch(
+        &mut self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.prefetch(identifier, keys)
+    }
+
+    /// Cursor seeking the keys of the trie `identifier` with its uncommitted changes, which can
+    /// also collect proofs on the way, see [`TrieCursor`].
+    pub fn cursor(&self, identifier: &[u8]) -> TrieCursor<'_, H, DB, ChangeID> {
+        self.tries.cursor(identifier)
+    }
+
+    pub fn get_multi_proof(
+        &self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<MultiProof, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.get_multi_proof(identifier, keys)
+    }
+
+    /// Record the keys read or written from now on by [`BonsaiStorage::get`],
+    /// [`BonsaiStorage::get_many`], [`BonsaiStorage::get_raw`], [`BonsaiStorage::contains`],
+    /// [`BonsaiStorage::insert`], [`BonsaiStorage::insert_raw`] and [`BonsaiStorage::remove`],
+    /// until [`BonsaiStorage::finish_witness`] returns the trie nodes they touched. Starting again
+    /// drops the keys recorded so far.
+    #[cfg(feature = "std")]
+    pub fn record_witness(&mut self) {
+        self.witness.start();
+    }
+
+    /// Stop recording and return the [`Witness`] of the keys accessed since
+    /// [`BonsaiStorage::record_witness`]: for each trie, the nodes on their paths at the last
+    /// commit, along with its root hash. It is meant to be taken before the accessed changes are
+    /// committed, which would make it prove the new values instead. The witness is empty if
+    /// nothing was recorded.
+    #[cfg(feature = "std")]
+    pub fn finish_witness(&mut self) -> Result<Witness, BonsaiStorageError<DB::DatabaseError>> {
+        let mut witness = Witness::default();
+        for (identifier, keys) in self.witness.finish().unwrap_or_default() {
+            let mut keys: Vec<_> = keys.into_iter().collect();
+            keys.sort();
+            let (root, proof) = self.tries.committed_multi_proof(&identifier, &keys)?;
+            witness
+                .tries
+                .insert(identifier, TrieWitness { root, keys, proof });
+        }
+        Ok(witness)
+    }
+
+    /// Know the trie `identifier` only by its `root` hash instead of the database, for stateless
+    /// verification and light clients: [`BonsaiStorage::get`], [`BonsaiStorage::get_many`] and
+    /// [`BonsaiStorage::contains`] then answer from the proof nodes given to
+    /// [`BonsaiStorage::insert_proof_nodes`], and fail with [`BonsaiStorageError::Proof`] for the
+    /// keys they don't prove. [`BonsaiStorage::root_hash`] returns `root`, and the trie cannot be
+    /// modified.
+    ///
+    /// The trie must have no uncommitted changes. Setting another root drops the known proof
+    /// nodes.
+    pub fn set_proven_root(
+        &mut self,
+        identifier: &[u8],
+        root: Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.set_proven_root(identifier, root)
+    }
+
+    /// Go back to reading the trie `identifier` from the database, see
+    /// [`BonsaiStorage::set_proven_root`].
+    pub fn clear_proven_root(&mut self, identifier: &[u8]) {
+        self.tries.proven.remove(identifier);
+    }
+
+    /// Add the nodes of `proof`, such as one made by [`BonsaiStorage::get_multi_proof`], to the
+    /// trie `identifier` known by its root hash, see [`BonsaiStorage::set_proven_root`]. Only the
+    /// nodes linked to the root by their hashes are kept, and their number is returned. A node
+    /// whose hash does not match fails with [`BonsaiStorageError::Proof`].
+    pub fn insert_proof_nodes(
+        &mut self,
+        identifier: &[u8],
+        proof: MultiProof,
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        self.tries.insert_proof_nodes(identifier, proof)
+    }
+
+    /// Move the storage behind a lock so that it can be shared between threads, see
+    /// [`SharedBonsaiStorage`].
+    #[cfg(feature = "std")]
+    pub fn into_shared(self) -> SharedBonsaiStorage<ChangeID, DB, H> {
+        SharedBonsaiStorage::new(self)
+    }
+
+    /// The database of the storage, dropping its uncommitted changes.
+    pub fn into_db(self) -> DB {
+        self.tries.db.db
+    }
+
+    /// Create a copy-on-write fork of the storage at its current state, uncommitted changes
+    /// included. The fork reads from the same database, but everything written to it, commits
+    /// included, stays in memory. Only the nodes already loaded in memory are copied, which makes
+    /// forks cheap enough to evaluate several candidate states in parallel.
+    ///
+    /// A fork borrows the storage, so its changes must be extracted with
+    /// [`BonsaiStorage::into_changes`] before they can be applied with
+    /// [`BonsaiStorage::merge_fork`]. Dropping it, or calling [`BonsaiStorage::discard`], discards
+    /// them. Savepoints are not carried over to the fork.
+    pub fn fork(&self) -> BonsaiStorage<ChangeID, ForkDb<'_, DB>, H> {
+        BonsaiStorage {
+            tries: self.tries.fork(),
+            change_sink: None,
+            change_sink_buffer: VecDeque::new(),
+            commit_hooks: Vec::new(),
+            child_tries: self.child_tries.clone(),
+            witness: WitnessRecorder::default(),
+            read_only: false,
+        }
+    }
+
+    /// Apply the changes of a fork, see [`BonsaiStorage::fork`]. The leaves and metadata it
+    /// changed, including in its commits, are written again as uncommitted changes of this
+    /// storage, overwriting its own uncommitted changes to them.
+    pub fn merge_fork(
+        &mut self,
+        changes: ForkChanges,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (key, value) in changes.leaves {
+            let (identifier, key) = split_flat_key(&key, self.tries.max_height);
+            self.tries.set_stored(identifier, &key, value.as_deref())?;
+        }
+        for (key, value) in changes.meta {
+            self.tries.set_meta(&key, value.as_deref());
+        }
+        Ok(())
+    }
+}
+
+impl<ChangeID, DB, H> BonsaiStorage<ChangeID, ForkDb<'_, DB>, H>
+where
+    DB: BonsaiDatabase,
+    ChangeID: id::Id,
+    H: StarkHash + Send + Sync,
+{
+    /// Extract the changes of this fork compared to the storage it was forked from, to be applied
+    /// with [`BonsaiStorage::merge_fork`].
+    pub fn into_changes(self) -> Result<ForkChanges, BonsaiStorageError<DB::DatabaseError>> {
+        let MerkleTrees {
+            db,
+            trees,
+            mut meta,
+            ..
+        } = self.tries;
+        let (flat_changes, meta_changes) = db.db.into_changes();
+        let mut changes: HashMap<_, _> = flat_changes.into_iter().collect();
+        for (identifier, tree) in trees {
+            for (key, value) in tree.stored_leaf_changes() {
+                let key = TrieKey::new(&identifier, TrieKeyType::Flat, key);
+                changes.insert(key.as_slice().into(), value);
+            }
+        }
+        for (key, value) in meta_changes {
+            if !MetaKeyType::is_maintained_by_commit(&key) {
+                meta.entry(key).or_insert(value);
+            }
+        }
+        Ok(ForkChanges {
+            leaves: changes,
+            meta,
+        })
+    }
+
+    /// Drop this fork along with all of its changes.
+    pub fn discard(self) {}
+}
+
+impl<ChangeID, DB, H> BonsaiStorage<ChangeID, DB, H>
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<ChangeID>,
+    ChangeID: id::Id,
+    H: StarkHash + Send + Sync,
+{
+    /// Update trie and database using all changes since the last commit.
+    ///
+    /// With the `audit` feature, the root hash of each written trie is then computed again from
+    /// its leaves in the database, and the commit panics if it is not the one it wrote. This needs
+    /// a database holding all the leaves, unlike the ones built from a witness or reading through
+    /// a remote node.
+    pub fn commit(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        self.update_child_tries()?;
+        self.run_commit_hooks(id)?;
+        self.send_to_change_sink(id)?;
+        self.tries.commit()?;
+        self.tries.db_mut().commit(id)?;
+        self.tries.db_mut().create_snapshot(id);
+        #[cfg(feature = "audit")]
+        self.tries.audit()?;
+        Ok(())
+    }
+
+    /// Same as [`BonsaiStorage::commit`], stopping with [`BonsaiStorageError::Cancelled`] once
+    /// `cancel` is cancelled, for instance so that a node shuts down without waiting for a large
+    /// commit. The changes are hashed as with [`BonsaiStorage::prepare_commit`], so a cancelled
+    /// commit leaves them uncommitted and writes nothing. Once they are hashed, the commit is
+    /// finished with [`BonsaiStorage::commit_prepared`] without checking `cancel` anymore.
+    pub fn commit_cancellable(
+        &mut self,
+        id: ChangeID,
+        cancel: &CancellationToken,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        self.update_child_tries()?;
+        let prepared = self.tries.prepare_commit(Some(cancel))?;
+        self.commit_prepared(id, prepared)
+    }
+
+    /// Call `hook` before every commit from now on, with the root hashes the tries will have and
+    /// the number of leaves changed, for instance to check the state root against a block header.
+    /// Returning an error aborts the commit with [`BonsaiStorageError::CommitHookAborted`] before anything
+    /// is written, leaving the changes uncommitted. The hooks are called in the order they were
+    /// added, before the [`ChangeSink`].
+    ///
+    /// This applies to [`BonsaiStorage::commit`] and [`BonsaiStorage::commit_prepared`]. The root
+    /// hashes are computed before the commit computes them again, so the changed nodes are hashed
+    /// twice while a hook is registered.
+    pub fn add_commit_hook(
+        &mut self,
+        hook: impl Fn(&PendingCommit<ChangeID>) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.commit_hooks.push(Arc::new(hook));
+    }
+
+    /// Remove the hooks added with [`BonsaiStorage::add_commit_hook`].
+    pub fn clear_commit_hooks(&mut self) {
+        self.commit_hooks.clear();
+    }
+
+    fn run_commit_hooks(
+        &self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        if self.commit_hooks.is_empty() {
+            return Ok(());
+        }
+        let (root_hashes, changes) = self.tries.pending_root_hashes()?;
+        let pending = PendingCommit {
+            id,
+            root_hashes,
+            changes,
+        };
+        for hook in &self.commit_hooks {
+            hook(&pending).map_err(|reason| BonsaiStorageError::CommitHookAborted {
+                id: id.as_u64(),
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Send the leaf changes of every commit to `sink` from now on, see [`ChangeSink`]. The changes
+    /// of the commits waiting for the previous sink are sent to this one.
+    ///
+    /// The commits of [`BonsaiStorage::reorg`] and of the transactional states merged with
+    /// [`BonsaiStorage::merge`] are sent as well. Reverts are not reported, but drop the waiting
+    /// changes of the reverted commits.
+    pub fn set_change_sink(&mut self, sink: Arc<dyn ChangeSink<ChangeID>>) {
+        self.change_sink = Some(sink);
+    }
+
+    /// Stop sending changes, dropping the ones that are waiting to be sent.
+    pub fn clear_change_sink(&mut self) {
+        self.change_sink = None;
+        self.change_sink_buffer.clear();
+    }
+
+    /// Try to send the changes waiting because the sink refused them, see
+    /// [`ChangeSinkPolicy::Buffer`]. Returns the number of commits still waiting.
+    pub fn flush_change_sink(&mut self) -> usize {
+        if let Some(sink) = &self.change_sink {
+            while let Some((id, changes)) = self.change_sink_buffer.front() {
+                if sink.send(*id, changes).is_err() {
+                    break;
+                }
+                self.change_sink_buffer.pop_front();
+            }
+        }
+        self.change_sink_buffer.len()
+    }
+
+    /// Call `callback` with the id of every commit which changes the leaf `key` of the trie
+    /// `identifier` and its change, until the returned handle is dropped, for instance to send
+    /// them to a channel. This spares reading the changes of every commit to follow a few leaves.
+    ///
+    /// The changes are read from the trie log of the commit as it is made, and given to the
+    /// callback once the commit is written. Like the [`ChangeSink`], this covers the commits of
+    /// [`BonsaiStorage::reorg`] and of the transactional states merged with
+    /// [`BonsaiStorage::merge`], while reverts are not reported. This only takes `&self`, so that
+    /// the readers of a [`SharedBonsaiStorage`] can watch leaves as well.
+    #[cfg(feature = "std")]
+    pub fn watch(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+        callback: impl Fn(ChangeID, &LeafChange) + Send + Sync + 'static,
+    ) -> WatchHandle<ChangeID> {
+        self.tries
+            .db_ref()
+            .watches
+            .watch(identifier, key, Arc::new(callback))
+    }
+
+    fn send_to_change_sink(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        if self.change_sink.is_none() {
+            return Ok(());
+        }
+        let changes = self.tries.leaf_changes()?;
+        self.send_leaf_changes(id, changes)
+            .map_err(BonsaiStorageError::ChangeSink)
+    }
+
+    /// Send the leaf changes of the commits of `transaction` that [`MerkleTrees::fast_forward`]
+    /// applies, as [`BonsaiStorage::commit`] would have.
+    fn send_fast_forward_to_change_sink(
+        &mut self,
+        transaction: &MerkleTrees<H, DB::Transaction<'_>, ChangeID>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiPersistentDatabase<ChangeID>>::DatabaseError>>
+    {
+        if self.change_sink.is_none() {
+            return Ok(());
+        }
+        let db = &transaction.db;
+        // Invalid transactional states are reported by the fast-forward itself.
+        let (Some(created_at), Some(txn_latest_id)) = (db.created_at, db.latest_id) else {
+            return Ok(());
+        };
+        for id in created_at.as_u64() + 1..=txn_latest_id.as_u64() {
+            let id = ChangeID::from_u64(id);
+            let trie_log = db.get_trie_log(id).map_err(|_| {
+                BonsaiStorageError::Merge(format!(
+                    "transactional state is missing trie logs for {:?}, they were pruned or disabled",
+                    id
+                ))
+            })?;
+            // Same commits as `KeyValueDB::fast_forward_commit`.
+            if trie_log.is_empty() && db.latest_id != Some(id) {
+                continue;
+            }
+            let mut changes = Vec::new();
+            for (key, change) in trie_log.0 {
+                let TrieKey::Flat(key) = key else {
+                    continue;
+                };
+                let decode = |value: Option<ByteVec>| {
+                    value
+                        .map(|value| {
+                            Felt::decode(&mut value.as_slice()).map_err(|source| {
+                                BonsaiStorageError::DecodeError {
+                                    key: key.clone(),
+                                    source,
+                                }
+                            })
+                        })
+                        .transpose()
+                };
+                let old_value = decode(change.old_value)?;
+                let new_value = decode(change.new_value)?;
+                let (identifier, leaf_key) = split_flat_key(&key, self.tries.max_height);
+                changes.push(LeafChange {
+                    identifier: identifier.into(),
+                    key: leaf_key,
+                    old_value,
+                    new_value,
+                });
+            }
+            self.send_leaf_changes(id, changes)
+                .map_err(BonsaiStorageError::ChangeSink)?;
+        }
+        Ok(())
+    }
+
+    fn send_leaf_changes(
+        &mut self,
+        id: ChangeID,
+        changes: Vec<LeafChange>,
+    ) -> Result<(), ChangeSinkError> {
+        let Some(sink) = self.change_sink.clone() else {
+            return Ok(());
+        };
+        // Changes are sent in order, behind the ones that are waiting.
+        let err = match self.flush_change_sink() {
+            0 => match sink.send(id, &changes) {
+                Ok(()) => return Ok(()),
+                Err(reason) => ChangeSinkError::Refused {
+                    id: id.as_u64(),
+                    reason,
+                },
+            },
+            waiting => ChangeSinkError::BufferFull {
+                id: id.as_u64(),
+                waiting,
+            },
+        };
+        match self.tries.db_ref().config.change_sink_policy {
+            ChangeSinkPolicy::Buffer { max_commits }
+                if self.change_sink_buffer.len() < max_commits =>
+            {
+                self.change_sink_buffer.push_back((id, changes));
+                Ok(())
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// Same as [`BonsaiStorage::commit`], also tagging the commit with `tag`, usually the hash of
+    /// the block it is the state of, so that it can be found with
+    /// [`BonsaiStorage::find_commit_by_tag`].
+    ///
+    /// Tags are stored with the metadata: reverting the commit removes its tag, and it is removed
+    /// as well when its trie log is pruned. Tagging another commit with the same tag moves it.
+    pub fn commit_with_tag(
+        &mut self,
+        id: ChangeID,
+        tag: &[u8; 32],
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let tag_key = TrieKey::new_meta(MetaKeyType::Tag, tag);
+        let commit_tag_key = TrieKey::new_meta(MetaKeyType::CommitTag, &id.to_bytes());
+        self.tries
+            .set_meta(tag_key.as_slice(), Some(&id.as_u64().encode_bytevec()));
+        self.tries.set_meta(commit_tag_key.as_slice(), Some(tag));
+        self.commit(id)
+    }
+
+    /// Same as [`BonsaiStorage::commit`], also attaching `meta` to the commit, such as the hash
+    /// and the timestamp of the block it is the state of, see
+    /// [`BonsaiStorage::get_commit_meta`].
+    ///
+    /// The metadata of a commit is meant to be small: it is stored with the metadata and recorded
+    /// in the trie log of the commit. Reverting the commit removes it, and it is removed as well
+    /// when its trie log is pruned. If the commit fails, `meta` is not kept with the uncommitted
+    /// changes.
+    pub fn commit_with_meta(
+        &mut self,
+        id: ChangeID,
+        meta: &[u8],
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let key = key_value_db::commit_meta_key(id);
+        self.tries.set_meta(key.as_slice(), Some(meta));
+        let result = self.commit(id);
+        if result.is_err() {
+            self.tries.unset_meta(key.as_slice());
+        }
+        result
+    }
+
+    /// The metadata attached to commit `id` by [`BonsaiStorage::commit_with_meta`], `None` if it
+    /// has none, or if its trie log was pruned.
+    pub fn get_commit_meta(
+        &self,
+        id: ChangeID,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.tries
+            .get_meta(key_value_db::commit_meta_key(id).as_slice())
+    }
+
+    /// Compute the hashes and database updates of the uncommitted changes without modifying the
+    /// storage, so that it can still be read from other threads meanwhile. This is the expensive
+    /// part of a commit, which is then finished with [`BonsaiStorage::commit_prepared`].
+    ///
+    /// The tries with uncommitted changes are copied to do so, which costs more memory than
+    /// [`BonsaiStorage::commit`].
+    pub fn prepare_commit(
+        &self,
+    ) -> Result<PreparedCommit, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.tries.prepare_commit(None)
+    }
+
+    /// Finish a commit prepared with [`BonsaiStorage::prepare_commit`]. Fails if changes were
+    /// made to the storage after it was prepared.
+    pub fn commit_prepared(
+        &mut self,
+        id: ChangeID,
+        prepared: PreparedCommit,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        self.update_child_tries()?;
+        self.tries.check_prepared(&prepared)?;
+        self.run_commit_hooks(id)?;
+        self.send_to_change_sink(id)?;
+        self.write_commit_batch(id, |tries, batch| {
+            tries.commit_prepared_to_batch(prepared, batch)
+        })
+    }
+
+    /// Commit the changes of a [`TrieShard`] as `id`, which may be the latest commit: its trie log
+    /// is then extended with the changes to the tries of the shard.
+    #[cfg(feature = "std")]
+    pub(crate) fn commit_shard(
+        &mut self,
+        id: ChangeID,
+        prepared: PreparedCommit,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        if let Some(latest_id) = self
+            .tries
+            .db_ref()
+            .get_latest_id()
+            .filter(|latest_id| id < *latest_id)
+        {
+            return Err(BonsaiStorageError::Shard(ShardError::OlderThanLatest {
+                id: id.as_u64(),
+                latest_id: latest_id.as_u64(),
+            }));
+        }
+        self.write_commit_batch(id, |tries, batch| tries.write_prepared(prepared, batch))
+    }
+
+    /// Commit `id` with the trie changes written by `write`, in a single batch along with the trie
+    /// log.
+    fn write_commit_batch(
+        &mut self,
+        id: ChangeID,
+        write: impl FnOnce(
+            &mut MerkleTrees<H, DB, ChangeID>,
+            &mut DB::Batch,
+        )
+            -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let previous_latest_id = self.tries.db_ref().get_latest_id();
+        let previous_reverted_to = self.tries.db_ref().reverted_to;
+        let previous_reverted_from = self.tries.db_ref().reverted_from;
+        let previous_deferred_pruning = self.tries.db_ref().deferred_pruning;
+
+        // The trie changes and the trie log go in the same batch, so that a crash cannot leave one
+        // without the other.
+        let mut batch = self.tries.db_ref().create_batch();
+        self.tries.db_mut().begin_staging();
+        let res = write(&mut self.tries, &mut batch)
+            .and_then(|()| self.tries.db_mut().commit_to_batch(id, &mut batch));
+        self.tries.db_mut().end_staging();
+
+        match res.and_then(|()| self.tries.db_mut().write_batch(batch)) {
+            Ok(()) => {
+                self.tries.db_mut().create_snapshot(id);
+                #[cfg(feature = "audit")]
+                self.tries.audit()?;
+                self.tries.db_mut().auto_compact()
+            }
+            Err(err) => {
+                self.tries.db_mut().discard_watched_changes();
+                self.tries.db_mut().latest_id = previous_latest_id;
+                self.tries.db_mut().reverted_to = previous_reverted_to;
+                self.tries.db_mut().reverted_from = previous_reverted_from;
+                self.tries.db_mut().deferred_pruning = previous_deferred_pruning;
+                Err(err)
+            }
+        }
+    }
+
+    /// Replay commit `id` of another storage from its [`ChangeBatch`], see
+    /// [`BonsaiStorage::get_change_batch`]. The trie nodes are written as is, so that both storages
+    /// end up with the same roots without rehashing.
+    ///
+    /// The storage must have no uncommitted changes, and be at the state the commit was made from:
+    /// the values it replaces are checked against the ones recorded in the batch. Replaying the
+    /// latest commit again with the same changes does nothing, so that replays are idempotent.
+    pub fn apply_change_batch(
+        &mut self,
+        id: ChangeID,
+        changes: &ChangeBatch,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        if self.tries.has_uncommitted_changes() {
+            return Err(BonsaiStorageError::Replay(ReplayError::UncommittedChanges));
+        }
+        self.tries.check_no_shards()?;
+        let kv = self.tries.db_ref();
+        let latest_id = kv.get_latest_id();
+        let keys: Vec<_> = changes.0.keys().cloned().collect();
+        let values = kv.get_many(&keys)?;
+        let matches = |expected: fn(&changes::Change) -> &Option<ByteVec>| {
+            keys.iter()
+                .zip(&values)
+                .all(|(key, value)| expected(&changes.0[key]) == value)
+        };
+        if latest_id == Some(id) {
+            if matches(|change| &change.new_value) {
+                return Ok(());
+            }
+            return Err(BonsaiStorageError::Replay(ReplayError::CommitExists {
+                id: id.as_u64(),
+            }));
+        }
+        if let Some(latest_id) = latest_id.filter(|latest_id| id < *latest_id) {
+            return Err(BonsaiStorageError::Replay(ReplayError::OlderThanLatest {
+                id: id.as_u64(),
+                latest_id: latest_id.as_u64(),
+            }));
+        }
+        if !matches(|change| &change.old_value) {
+            return Err(BonsaiStorageError::Replay(ReplayError::StateMismatch {
+                id: id.as_u64(),
+            }));
+        }
+
+        // The loaded nodes are outdated once the changes are written.
+        self.tries.reset_to_last_commit();
+        self.tries.db_mut().apply_change_batch(id, changes)?;
+        self.tries.db_mut().create_snapshot(id);
+        Ok(())
+    }
+
+    /// Keep commit `id` readable by [`BonsaiStorage::get_at`] and
+    /// [`BonsaiStorage::get_transactional_state`] while the returned guard is alive: the
+    /// following commits don't prune the trie logs and the snapshot it needs, even past
+    /// `max_saved_trie_logs` and `max_saved_snapshots`. They are pruned by the first commit after
+    /// the guard is dropped.
+    ///
+    /// This only takes `&self`, so that the readers of a [`SharedBonsaiStorage`] can pin the
+    /// commits they read across the write guards taken in between.
+    #[cfg(feature = "std")]
+    pub fn pin_commit(
+        &self,
+        id: ChangeID,
+    ) -> Result<CommitGuard<ChangeID>, BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>>
+    {
+        self.tries.db_ref().pin_commit(id)
+    }
+
+    /// Ids of the commits which have a database snapshot, in increasing order. Transactional states
+    /// are created from the closest snapshot at or before the requested commit.
+    pub fn list_snapshots(&self) -> Vec<ChangeID> {
+        self.tries.db_ref().snapshots()
+    }
+
+    /// Snapshot the database at the latest commit `id`, in addition to the snapshots taken every
+    /// `snapshot_interval` commits. This allows pinning snapshots at known commits, such as epoch
+    /// boundaries.
+    ///
+    /// Snapshots created this way still count towards the maximum number of snapshots the database
+    /// keeps, if any.
+    pub fn create_snapshot_now(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.db_mut().create_snapshot_now(id)
+    }
+
+    /// Delete the snapshot taken at commit `id`, returns whether it existed.
+    pub fn delete_snapshot(&mut self, id: ChangeID) -> bool {
+        self.tries.db_mut().remove_snapshot(id)
+    }
+
+    /// Handle a chain reorganization: revert to the common ancestor `to_id` and apply the commits
+    /// of the new branch on top of it, in order.
+    ///
+    /// Each item of `new_branch` is a commit ID along with the `(identifier, key, value)` changes of
+    /// that commit. As with [`BonsaiStorage::insert`], a value of [`Felt::ZERO`] removes the key.
+    /// Commit IDs must be strictly increasing and more recent than `to_id`, this is checked before
+    /// anything is reverted.
+    ///
+    /// The revert and all of the new commits are written to the database in a single batch, with one
+    /// trie log per new commit. This means the operation is atomic for backends which apply batches
+    /// atomically, such as RocksDB. Only the last commit of the new branch may get a snapshot. An
+    /// empty `new_branch` is the same as [`BonsaiStorage::revert_to`]. The new commits are sent to
+    /// the [`ChangeSink`] of the storage, if any.
+    ///
+    /// If insert/remove is called between the last `commit()` and a call to this function,
+    /// the in-memory changes will be discarded.
+    pub fn reorg(
+        &mut self,
+        to_id: ChangeID,
+        new_branch: impl IntoIterator<
+            Item = (
+                ChangeID,
+                impl IntoIterator<Item = (impl AsRef<[u8]>, impl AsRef<BitSlice>, Felt)>,
+            ),
+        >,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        let new_branch: Vec<_> = new_branch.into_iter().collect();
+        let mut last_id = to_id;
+        for (id, _) in &new_branch {
+            if *id <= last_id {
+                return Err(BonsaiStorageError::GoTo(format!(
+                    "Reorg commit ids must be strictly increasing and more recent than {:?}, got {:?}",
+                    to_id, id
+                )));
+            }
+            last_id = *id;
+        }
+
+        if new_branch.is_empty() {
+            return self.revert_to(to_id);
+        }
+
+        self.tries.reset_to_last_commit();
+        let previous_latest_id = self.tries.db_ref().get_latest_id();
+        let previous_reverted_to = self.tries.db_ref().reverted_to;
+        let previous_reverted_from = self.tries.db_ref().reverted_from;
+        let previous_deferred_pruning = self.tries.db_ref().deferred_pruning;
+        let previous_change_sink_buffer = self.change_sink_buffer.clone();
+
+        let mut batch = self.tries.db_ref().create_batch();
+        self.tries.db_mut().begin_staging();
+        let res = self.reorg_to_batch(to_id, new_branch, &mut batch);
+        self.tries.db_mut().end_staging();
+
+        match res {
+            Ok(()) => {
+                self.tries.db_mut().write_batch(batch)?;
+                self.tries.db_mut().create_snapshot(last_id);
+                #[cfg(feature = "audit")]
+                self.tries.audit()?;
+                self.tries.db_mut().auto_compact()
+            }
+            Err(err) => {
+                // The batch is dropped unwritten, discard the partially applied branch.
+                self.tries.reset_to_last_commit();
+                self.tries.db_mut().discard_watched_changes();
+                self.tries.db_mut().latest_id = previous_latest_id;
+                self.tries.db_mut().reverted_to = previous_reverted_to;
+                self.tries.db_mut().reverted_from = previous_reverted_from;
+                self.tries.db_mut().deferred_pruning = previous_deferred_pruning;
+                self.change_sink_buffer = previous_change_sink_buffer;
+                Err(err)
+            }
+        }
+    }
+
+    fn reorg_to_batch(
+        &mut self,
+        to_id: ChangeID,
+        new_branch: Vec<(
+            ChangeID,
+            impl IntoIterator<Item = (impl AsRef<[u8]>, impl AsRef<BitSlice>, Felt)>,
+        )>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.tries.db_mut().revert_to(to_id, batch, None)?;
+        self.change_sink_buffer.retain(|(id, _)| *id <= to_id);
+
+        for (id, changes) in new_branch {
+            for (identifier, key, value) in changes {
+                self.tries.set(identifier.as_ref(), key.as_ref(), value)?;
+            }
+            self.update_child_tries()?;
+            self.send_to_change_sink(id)?;
+            self.tries.commit_to_batch(batch)?;
+            self.tries.db_mut().commit_to_batch(id, batch)?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::type_complexity)]
+    /// Get a transactional state of the trie at a specific commit ID.
+    ///
+    /// Transactional state allow you to fetch a point-in-time state of the trie. You can
+    /// apply changes to this state and merge it back into the main trie.
+    ///
+    /// When the transaction type of the database is itself a [`BonsaiPersistentDatabase`], as is
+    /// the case for [`databases::HashMapDb`], transactional states can be created from a
+    /// transactional state as well, at its creation commit or any of its later commits. Merging
+    /// them applies their changes to the transactional state they were created from.
+    pub fn get_transactional_state(
+        &self,
+        change_id: ChangeID,
+        config: BonsaiStorageConfig,
+    ) -> Result<
+        Option<BonsaiStorage<ChangeID, DB::Transaction<'_>, H>>,
+        BonsaiStorageError<<DB::Transaction<'_> as BonsaiDatabase>::DatabaseError>,
+    > {
+        // If requested equals last recorded, do nothing
+        // if Some(&change_id) == self.tries.db_ref().changes_store.id_queue.back() {
+        //     return Ok(());
+        // }
+
+        Ok(self
+            .get_transactional_state_with_info(change_id, config)?
+            .map(|(transactional_state, _)| transactional_state))
+    }
+
+    /// Same as [`BonsaiStorage::get_transactional_state`], also returning which snapshot was used
+    /// and how many trie logs were replayed on top of it.
+    #[allow(clippy::type_complexity)]
+    pub fn get_transactional_state_with_info(
+        &self,
+        change_id: ChangeID,
+        mut config: BonsaiStorageConfig,
+    ) -> Result<
+        Option<(
+            BonsaiStorage<ChangeID, DB::Transaction<'_>, H>,
+            TransactionalStateInfo<ChangeID>,
+        )>,
+        BonsaiStorageError<<DB::Transaction<'_> as BonsaiDatabase>::DatabaseError>,
+    > {
+        let Some((transaction, info)) = self.tries.db_ref().get_transaction(change_id)? else {
+            return Ok(None);
+        };
+        // The hashers and the way the tries are stored belong to the tries, not to the
+        // transactional state.
+        config.trie_hashers = self.tries.db_ref().config.trie_hashers.clone();
+        config.max_inline_leaves = self.tries.db_ref().config.max_inline_leaves;
+        let mut transactional_state = BonsaiStorage::new_from_transactional_state(
+            transaction,
+            config,
+            self.tries.max_height,
+            change_id,
+        )?;
+        transactional_state.child_tries = self.child_tries.clone();
+        Ok(Some((transactional_state, info)))
+    }
+
+    /// How a transactional state at `change_id` would be created, without creating it. This allows
+    /// detecting expensive reconstructions beforehand. Returns `None` if there is no snapshot to
+    /// create it from.
+    pub fn transactional_state_info(
+        &self,
+        change_id: ChangeID,
+    ) -> Option<TransactionalStateInfo<ChangeID>> {
+        self.tries.db_ref().transactional_state_info(change_id)
+    }
+
+    /// Leaves of the trie `identifier` that differ between the commits `id_a` and `id_b`, as changes
+    /// from `id_a` to `id_b`: added leaves have no old value and removed leaves no new value.
+    ///
+    /// The two versions of the trie are walked together from transactional states at these
+    /// commits, skipping the subtries which have the same hash in both, so the cost depends on
+    /// the number of changed leaves rather than on the number of commits between `id_a` and
+    /// `id_b`. Fails with [`BonsaiStorageError::Transaction`] if no transactional state can be
+    /// created at one of the commits.
+    pub fn diff(
+        &self,
+        identifier: &[u8],
+        id_a: ChangeID,
+        id_b: ChangeID,
+    ) -> Result<
+        HashMap<BitVec, Change>,
+        BonsaiStorageError<<DB as BonsaiPersistentDatabase<ChangeID>>::DatabaseError>,
+    > {
+        let state = |id| {
+            self.get_transactional_state(id, self.get_config())?
+                .ok_or_else(|| {
+                    BonsaiStorageError::Transaction(format!(
+                        "no transactional state can be created at {:?}",
+                        id
+                    ))
+                })
+        };
+        let (state_a, state_b) = (state(id_a)?, state(id_b)?);
+        trie::diff::diff::<_, _, H>(
+            identifier,
+            self.tries.max_height,
+            state_a.tries.db_ref(),
+            state_b.tries.db_ref(),
+        )
+    }
+
+    /// Get a copy of the config that can be used to create a transactional state or a new bonsai storage.
+    pub fn get_config(&self) -> BonsaiStorageConfig {
+        self.tries.db_ref().get_config().into()
+    }
+
+    /// Merge a transactional state into the main trie.
+    ///
+    /// If the main trie was not committed since the transactional state was created and has no
+    /// uncommitted changes, the merge is a fast-forward: the commits made in the transactional
+    /// state with [`BonsaiStorage::transactional_commit`] are copied node by node as commits with
+    /// the same IDs, and its uncommitted changes are taken over as is. This is the only case where
+    /// the leaves are not inserted again.
+    ///
+    /// Otherwise, each changed leaf is inserted again: the changes committed in the transactional
+    /// state are applied as uncommitted changes of the main trie, followed by its own uncommitted
+    /// changes, overwriting the uncommitted changes of the main trie. Metadata is carried over the
+    /// same way, but is not checked for conflicts.
+    ///
+    /// If the main trie committed a new value for a leaf changed by the transactional state since
+    /// it was created, the merge fails with [`BonsaiStorageError::MergeConflict`] unless
+    /// [`MergeConflictPolicy::LastWriterWins`] is configured. Detecting conflicts requires the trie
+    /// logs of these commits.
+    pub fn merge(
+        &mut self,
+        transactional_bonsai_storage: BonsaiStorage<ChangeID, DB::Transaction<'_>, H>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiPersistentDatabase<ChangeID>>::DatabaseError>>
+    where
+        <DB as BonsaiDatabase>::DatabaseError: core::fmt::Debug,
+    {
+        self.check_writable()?;
+        self.tries.check_no_shards()?;
+        let transaction = transactional_bonsai_storage.tries;
+        let created_at = transaction.db.created_at;
+        if self.tries.can_fast_forward(created_at) {
+            self.send_fast_forward_to_change_sink(&transaction)?;
+            return self.tries.fast_forward(transaction).map_err(|e| {
+                BonsaiStorageError::Merge(format!(
+                    "While fast-forwarding to the transactional state faced error: {:?}",
+                    e
+                ))
+            });
+        }
+
+        // memorize changes
+        let MerkleTrees {
+            db, trees, meta, ..
+        } = transaction;
+
+        let commits = self.tries.db_ref().merge(db)?;
+        let mut uncommitted_changes = HashMap::new();
+        for (identifier, tree) in trees {
+            for (k, value) in tree.stored_leaf_changes() {
+                let key = TrieKey::new(&identifier, TrieKeyType::Flat, k);
+                uncommitted_changes.insert(key.as_slice().into(), value);
+            }
+        }
+
+        let kv = self.tries.db_ref();
+        if kv.config.merge_conflict_policy == MergeConflictPolicy::Error {
+            if let Some(created_at) = created_at {
+                let committed_changes = kv.get_changes_since(created_at).map_err(|e| {
+                    BonsaiStorageError::Merge(format!(
+                        "While looking for conflicts since {:?} faced error: {:?}",
+                        created_at, e
+                    ))
+                })?;
+                let mut changes = HashMap::new();
+                for (_, commit_changes, _) in &commits {
+                    changes.extend(commit_changes);
+                }
+                changes.extend(&uncommitted_changes);
+                let conflicts: Vec<_> = changes
+                    .into_iter()
+                    .filter(|(key, _)| committed_changes.contains_key(*key))
+                    .map(|(key, _)| {
+                        let (identifier, key) = split_flat_key(key, self.tries.max_height);
+                        (identifier.into(), key)
+                    })
+                    .collect();
+                if !conflicts.is_empty() {
+                    return Err(BonsaiStorageError::MergeConflict(conflicts));
+                }
+            }
+        }
+
+        for (_, changes, meta_changes) in commits {
+            self.apply_merged_changes(changes)?;
+            for (key, value) in meta_changes {
+                self.tries.set_meta(&key, value.as_deref());
+            }
+        }
+        for (key, value) in meta {
+            self.tries.set_meta(&key, value.as_deref());
+        }
+        self.apply_merged_changes(uncommitted_changes)
+    }
+
+    fn apply_merged_changes(
+        &mut self,
+        changes: HashMap<ByteVec, Option<ByteVec>>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiPersistentDatabase<ChangeID>>::DatabaseError>>
+    where
+        <DB as BonsaiDatabase>::DatabaseError: core::fmt::Debug,
+    {
+        for (key, value) in changes {
+            let (identifier, key) = split_flat_key(&key, self.tries.max_height);
+            match value {
+                Some(stored) => {
+                    self.tries
+                        .set_stored(identifier, &key, Some(&stored))
+                        .map_err(|e| {
+                            BonsaiStorageError::Merge(format!(
+                                "While merging insert({:?} {:?}) faced error: {:?}",
+                                key, stored, e
+                            ))
+                        })?;
+                }
+                None => {
+                    self.remove(identifier, &key).map_err(|e| {
+                        BonsaiStorageError::Merge(format!(
+                            "While merging remove({:?}) faced error: {:?}",
+                            key, e
+                        ))
+                    })?;
                 }
             }
         }
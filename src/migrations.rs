@@ -0,0 +1,230 @@
+//! Upgrades of the databases written by older versions of this crate.
+//!
+//! The version of the layout of a database is stored in its metadata. [`crate::BonsaiStorage::new`]
+//! runs the steps of [`migrations`] that the database has not gone through yet, and
+//! [`migrate`] can run them beforehand, to know what they would rewrite with a dry run or to
+//! follow their progress.
+
+use crate::{
+    databases::ForkDb,
+    trie::{
+        merkle_node::Node,
+        trie_db::{MetaKeyType, TrieKey},
+    },
+    vec, BonsaiDatabase, BonsaiStorageError, DatabaseKey, EncodeExt, Vec,
+};
+use parity_scale_codec::Decode;
+
+/// Version of the layout of the databases written by this version of the crate. Databases written
+/// before it was stored have version 0.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Runs a migration step, reporting its progress, and returns the number of rewritten entries. A
+/// dry run only counts the entries to rewrite, without writing them.
+type RunStep<DB> = fn(
+    &mut DB,
+    bool,
+    &mut dyn FnMut(u64, u64),
+) -> Result<u64, <DB as BonsaiDatabase>::DatabaseError>;
+
+/// A step upgrading a database to the schema `version` from the previous one.
+pub struct Migration<DB: BonsaiDatabase> {
+    /// Schema version of the database once the step is done.
+    pub version: u32,
+    /// What the step changes.
+    pub description: &'static str,
+    /// Steps are run again when the migration is interrupted before the new version is stored, so
+    /// they must be idempotent.
+    run: RunStep<DB>,
+}
+
+/// Progress of a migration step, see [`MigrationOptions::progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationProgress {
+    /// Version the running step upgrades the database to.
+    pub version: u32,
+    /// Work done by the step: entries, or chunks of entries for the steps going through a whole
+    /// column, which is read in chunks.
+    pub done: u64,
+    /// Work the step does, in the same unit as `done`.
+    pub total: u64,
+}
+
+/// How [`migrate`] runs.
+#[derive(Default)]
+pub struct MigrationOptions<'a> {
+    /// Only count the entries the steps would rewrite, the database and its schema version are
+    /// left untouched. The steps run against a copy-on-write view of the database, which discards
+    /// anything they write.
+    pub dry_run: bool,
+    /// Called as the steps go through the entries of the database.
+    pub progress: Option<&'a mut dyn FnMut(MigrationProgress)>,
+}
+
+/// A step run by [`migrate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationOutcome {
+    /// Version the step upgraded the database to.
+    pub version: u32,
+    /// What the step changed.
+    pub description: &'static str,
+    /// Number of entries the step rewrote.
+    pub rewritten: u64,
+}
+
+/// Steps run by [`migrate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Schema version of the database before the migration.
+    pub from: u32,
+    /// Schema version of the database after the migration, or that it would have after a dry run.
+    pub to: u32,
+    pub steps: Vec<MigrationOutcome>,
+}
+
+/// The migration steps known by this version of the crate, by increasing version.
+pub fn migrations<DB: BonsaiDatabase>() -> Vec<Migration<DB>> {
+    vec![
+        Migration {
+            version: 1,
+            description: "Tag the trie nodes with the version of their encoding",
+            run: tag_nodes,
+        },
+        Migration {
+            version: 2,
+            description: "Frame the entries of the new trie logs by trie identifier",
+            run: frame_trie_logs,
+        },
+    ]
+}
+
+fn schema_version_key() -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::SchemaVersion, &[])
+}
+
+/// Schema version of the database, 0 if it was written before the version was stored.
+pub fn schema_version<DB: BonsaiDatabase>(
+    db: &DB,
+) -> Result<u32, BonsaiStorageError<DB::DatabaseError>> {
+    let key = schema_version_key();
+    let Some(version) = db.get(&DatabaseKey::from(&key))? else {
+        return Ok(0);
+    };
+    u32::decode(&mut version.as_slice()).map_err(|source| BonsaiStorageError::DecodeError {
+        key: key.as_slice().into(),
+        source,
+    })
+}
+
+/// Upgrade the database to [`SCHEMA_VERSION`] by running the steps it has not gone through yet,
+/// then store its new version. Fails with [`BonsaiStorageError::UnsupportedSchemaVersion`] if the
+/// database was written by a newer version of this crate.
+pub fn migrate<DB: BonsaiDatabase>(
+    db: &mut DB,
+    options: MigrationOptions<'_>,
+) -> Result<MigrationReport, BonsaiStorageError<DB::DatabaseError>> {
+    let from = schema_version(db)?;
+    if from > SCHEMA_VERSION {
+        return Err(BonsaiStorageError::UnsupportedSchemaVersion {
+            version: from,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    let mut progress = options.progress;
+    if options.dry_run {
+        return run_steps(&mut ForkDb::new(db), from, true, &mut progress);
+    }
+    let report = run_steps(db, from, false, &mut progress)?;
+    if from != SCHEMA_VERSION {
+        let key = schema_version_key();
+        db.insert(
+            &DatabaseKey::from(&key),
+            &SCHEMA_VERSION.encode_bytevec(),
+            None,
+        )?;
+    }
+    Ok(report)
+}
+
+fn run_steps<DB: BonsaiDatabase>(
+    db: &mut DB,
+    from: u32,
+    dry_run: bool,
+    progress: &mut Option<&mut dyn FnMut(MigrationProgress)>,
+) -> Result<MigrationReport, BonsaiStorageError<DB::DatabaseError>> {
+    let mut steps = Vec::new();
+    for step in migrations::<DB>() {
+        if step.version <= from {
+            continue;
+        }
+        let mut report = |done, total| {
+            if let Some(progress) = progress {
+                progress(MigrationProgress {
+                    version: step.version,
+                    done,
+                    total,
+                });
+            }
+        };
+        let rewritten = (step.run)(db, dry_run, &mut report)?;
+        steps.push(MigrationOutcome {
+            version: step.version,
+            description: step.description,
+            rewritten,
+        });
+    }
+    Ok(MigrationReport {
+        from,
+        to: SCHEMA_VERSION,
+        steps,
+    })
+}
+
+/// Rewrite the nodes written before their encoding was versioned with the current encoding, see
+/// [`crate::BonsaiStorage::migrate_nodes`]. The stored disk usages of the tries don't count the
+/// added tags, they are approximate.
+///
+/// The column is read and written in chunks of the keys sharing their first byte, so that it is
+/// never all in memory, and the progress counts these chunks. An interrupted run leaves the chunks
+/// already written tagged, which are skipped when it runs again.
+fn tag_nodes<DB: BonsaiDatabase>(
+    db: &mut DB,
+    dry_run: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64, DB::DatabaseError> {
+    let chunks = u8::MAX as u64 + 1;
+    let mut rewritten = 0;
+    for first_byte in 0..=u8::MAX {
+        let mut batch = db.create_batch();
+        for (key, value) in db.get_by_prefix(&DatabaseKey::Trie(&[first_byte]))? {
+            // The other entries of the column are the leaf counts, whose 8 bytes are too short to
+            // be decoded as a node.
+            if let Ok((node, 0)) = Node::decode_versioned(&value) {
+                if !dry_run {
+                    db.insert(
+                        &DatabaseKey::Trie(&key),
+                        &node.encode_versioned(),
+                        Some(&mut batch),
+                    )?;
+                }
+                rewritten += 1;
+            }
+        }
+        if !dry_run {
+            db.write_batch(batch)?;
+        }
+        progress(first_byte as u64 + 1, chunks);
+    }
+    Ok(rewritten)
+}
+
+/// Nothing to rewrite: the trie logs written before stay readable, only the new ones are framed by
+/// trie identifier, see [`crate::ChangeBatch::serialize`]. Older versions of this crate can't read
+/// them, which the new schema version prevents.
+fn frame_trie_logs<DB: BonsaiDatabase>(
+    _db: &mut DB,
+    _dry_run: bool,
+    _progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64, DB::DatabaseError> {
+    Ok(0)
+}
@@ -0,0 +1,85 @@
+//! Counters of the work done by a storage, see [`crate::BonsaiStorage::take_op_stats`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Work done by a storage since the last call to [`crate::BonsaiStorage::take_op_stats`], for
+/// instance to account for the import of each block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// Keys read from the database, the writes of a commit which are read back before the batch
+    /// is written aside.
+    pub db_gets: u64,
+    /// Trie nodes decoded after being read from the database.
+    pub node_decodes: u64,
+    /// Trie nodes found in memory instead of being read from the database.
+    pub cache_hits: u64,
+    /// Trie nodes hashed to commit the changes or compute their root hash.
+    pub nodes_hashed: u64,
+    /// Bytes of the keys and values written to the database.
+    pub bytes_written: u64,
+}
+
+/// The counters behind [`OpStats`]. Reads only take `&self`, so they are atomic.
+#[derive(Debug, Default)]
+pub(crate) struct OpCounters {
+    db_gets: AtomicU64,
+    node_decodes: AtomicU64,
+    cache_hits: AtomicU64,
+    nodes_hashed: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl OpCounters {
+    pub(crate) fn db_gets(&self, count: usize) {
+        self.db_gets.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn node_decoded(&self) {
+        self.node_decodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn nodes_hashed(&self, count: usize) {
+        self.nodes_hashed.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_written(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Add the counts of `stats`.
+    #[cfg(feature = "std")]
+    pub(crate) fn add(&self, stats: OpStats) {
+        self.db_gets.fetch_add(stats.db_gets, Ordering::Relaxed);
+        self.node_decodes
+            .fetch_add(stats.node_decodes, Ordering::Relaxed);
+        self.cache_hits
+            .fetch_add(stats.cache_hits, Ordering::Relaxed);
+        self.nodes_hashed
+            .fetch_add(stats.nodes_hashed, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(stats.bytes_written, Ordering::Relaxed);
+    }
+
+    /// The counts since the last call, which resets them.
+    pub(crate) fn take(&self) -> OpStats {
+        OpStats {
+            db_gets: self.db_gets.swap(0, Ordering::Relaxed),
+            node_decodes: self.node_decodes.swap(0, Ordering::Relaxed),
+            cache_hits: self.cache_hits.swap(0, Ordering::Relaxed),
+            nodes_hashed: self.nodes_hashed.swap(0, Ordering::Relaxed),
+            bytes_written: self.bytes_written.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "bench")]
+impl Clone for OpCounters {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
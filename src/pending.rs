@@ -0,0 +1,84 @@
+//! A pending block kept as the uncommitted changes of a storage, see [`PendingState`].
+
+use crate::{
+    id::Id, BitSlice, BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorage, BonsaiStorageError,
+};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// The changes of a block not committed yet on top of the latest commit of a [`BonsaiStorage`],
+/// created with [`BonsaiStorage::pending_state`]. A sequencer applies the transactions of the
+/// pending block to it as they come, reads its root hashes, and commits it once the block is
+/// closed, or clears it to build the block again. Unlike a transactional state, no snapshot is
+/// read: the changes are the uncommitted changes of the storage itself.
+pub struct PendingState<'a, ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> {
+    storage: &'a mut BonsaiStorage<ChangeID, DB, H>,
+}
+
+impl<'a, ChangeID, DB, H> PendingState<'a, ChangeID, DB, H>
+where
+    ChangeID: Id,
+    DB: BonsaiDatabase,
+    H: StarkHash + Send + Sync,
+{
+    pub(crate) fn new(
+        storage: &'a mut BonsaiStorage<ChangeID, DB, H>,
+    ) -> Result<Self, BonsaiStorageError<DB::DatabaseError>> {
+        if storage.tries.has_uncommitted_changes() {
+            return Err(BonsaiStorageError::UncommittedChanges);
+        }
+        Ok(Self { storage })
+    }
+
+    /// Add `changes` to the pending block, by trie identifier and key, as with
+    /// [`BonsaiStorage::insert`]: setting a leaf to [`Felt::ZERO`] removes it unless the storage has
+    /// [`crate::BonsaiStorageConfig::store_zero_values`].
+    pub fn apply_pending(
+        &mut self,
+        changes: impl IntoIterator<Item = (impl AsRef<[u8]>, impl AsRef<BitSlice>, Felt)>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (identifier, key, value) in changes {
+            self.storage
+                .insert(identifier.as_ref(), key.as_ref(), &value)?;
+        }
+        Ok(())
+    }
+
+    /// Root hash of the trie `identifier` with the pending changes. The hashes of the changed
+    /// nodes are computed again on each call.
+    pub fn pending_root(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        self.storage.tries.pending_root_hash(identifier)
+    }
+
+    /// Value of a leaf with the pending changes, see [`BonsaiStorage::get`].
+    pub fn get(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        self.storage.get(identifier, key)
+    }
+
+    /// Discard the pending changes, going back to the latest commit.
+    pub fn clear_pending(&mut self) {
+        self.storage.tries.reset_to_last_commit();
+    }
+}
+
+impl<ChangeID, DB, H> PendingState<'_, ChangeID, DB, H>
+where
+    ChangeID: Id,
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<ChangeID>,
+    H: StarkHash + Send + Sync,
+{
+    /// Commit the pending changes as `id`, see [`BonsaiStorage::commit`]. The next pending block
+    /// is then built on top of this commit.
+    pub fn finalize_pending(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        self.storage.commit(id)
+    }
+}
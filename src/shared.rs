@@ -0,0 +1,208 @@
+use crate::{
+    id::Id, trie::tree::MerkleTree, Arc, BitSlice, BonsaiDatabase, BonsaiPersistentDatabase,
+    BonsaiStorage, BonsaiStorageError, ByteVec, DBError, HashMap, ShardError,
+};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+/// A [`BonsaiStorage`] that can be shared between threads, created with
+/// [`BonsaiStorage::into_shared`]. Clones of this handle refer to the same storage.
+///
+/// The storage is behind a readers-writer lock: any number of threads can hold a
+/// [`SharedBonsaiStorage::read`] guard, which gives access to the `&self` methods such as `get`,
+/// `root_hash`, `get_multi_proof` or `get_transactional_state`, while a
+/// [`SharedBonsaiStorage::write`] guard is exclusive and is needed to insert, commit, revert or
+/// merge.
+///
+/// A transactional state borrows the read guard it was created from, so the storage cannot be
+/// committed to while it is alive: take a write guard to merge it only after dropping it, or use
+/// a database whose transactions don't borrow the storage. Likewise, the lock is not reentrant and
+/// taking a write guard on a thread which holds a read guard deadlocks.
+///
+/// A thread panicking while holding a guard does not make the storage unusable, its uncommitted
+/// changes may be partially applied though.
+///
+/// Threads working on disjoint tries can also commit concurrently, each through its own
+/// [`TrieShard`] created with [`SharedBonsaiStorage::shard`].
+pub struct SharedBonsaiStorage<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync>(
+    Arc<RwLock<BonsaiStorage<ChangeID, DB, H>>>,
+);
+
+impl<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> Clone
+    for SharedBonsaiStorage<ChangeID, DB, H>
+{
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync>
+    SharedBonsaiStorage<ChangeID, DB, H>
+{
+    pub(crate) fn new(storage: BonsaiStorage<ChangeID, DB, H>) -> Self {
+        Self(Arc::new(RwLock::new(storage)))
+    }
+
+    /// Shared access to the storage, blocks while a write guard is held.
+    pub fn read(&self) -> RwLockReadGuard<'_, BonsaiStorage<ChangeID, DB, H>> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Exclusive access to the storage, blocks while any other guard is held.
+    pub fn write(&self) -> RwLockWriteGuard<'_, BonsaiStorage<ChangeID, DB, H>> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Same as [`SharedBonsaiStorage::read`], but returns `None` instead of blocking.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, BonsaiStorage<ChangeID, DB, H>>> {
+        match self.0.try_read() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Same as [`SharedBonsaiStorage::write`], but returns `None` instead of blocking.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, BonsaiStorage<ChangeID, DB, H>>> {
+        match self.0.try_write() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Get the storage back, or this handle if it has other clones.
+    pub fn try_unwrap(self) -> Result<BonsaiStorage<ChangeID, DB, H>, Self> {
+        Arc::try_unwrap(self.0)
+            .map(|lock| lock.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .map_err(Self)
+    }
+
+    /// Check out the tries `identifiers`, so that they are modified and committed through the
+    /// returned shard, concurrently with the other shards and with the other tries of the storage.
+    ///
+    /// The tries must have no uncommitted changes, and must not belong to another shard. While the
+    /// shard is alive, they cannot be modified through the storage, and the storage cannot be
+    /// reverted, reorged, merged into or replayed to, as this could rewrite them.
+    pub fn shard(
+        &self,
+        identifiers: &[&[u8]],
+    ) -> Result<TrieShard<ChangeID, DB, H>, BonsaiStorageError<DB::DatabaseError>> {
+        let trees = self.write().tries.open_shard(identifiers)?;
+        Ok(TrieShard {
+            storage: self.clone(),
+            trees,
+        })
+    }
+}
+
+fn not_in_shard<E: DBError>(identifier: &[u8]) -> BonsaiStorageError<E> {
+    BonsaiStorageError::Shard(ShardError::NotInShard {
+        identifier: identifier.into(),
+    })
+}
+
+/// Some tries of a [`SharedBonsaiStorage`], modified and committed independently of the other
+/// tries, see [`SharedBonsaiStorage::shard`].
+///
+/// The changes are hashed while holding a read guard, so that shards hash at the same time, and
+/// only writing them to the database takes a write guard. They go to the trie log of the commit
+/// they are made as, which other shards or the storage itself may also commit to, so that the
+/// commits of each shard can be made with the ids of the commits of the storage: reverting a commit
+/// reverts the changes of all its shards. The commits of a shard are not sent to the
+/// [`crate::ChangeSink`] of the storage.
+///
+/// Dropping the shard gives its tries back to the storage and discards its uncommitted changes. It
+/// takes a write guard, so it must not be dropped by a thread holding a guard of the storage.
+pub struct TrieShard<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> {
+    storage: SharedBonsaiStorage<ChangeID, DB, H>,
+    trees: HashMap<ByteVec, MerkleTree<H>>,
+}
+
+impl<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> TrieShard<ChangeID, DB, H> {
+    /// Identifiers of the tries of the shard.
+    pub fn identifiers(&self) -> impl Iterator<Item = &[u8]> {
+        self.trees.keys().map(|identifier| identifier.as_slice())
+    }
+
+    /// Same as [`BonsaiStorage::insert`], for a trie of the shard.
+    pub fn insert(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        value: &Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let tree = self
+            .trees
+            .get_mut(identifier)
+            .ok_or_else(|| not_in_shard(identifier))?;
+        tree.set(self.storage.read().tries.db_ref(), key, *value)
+    }
+
+    /// Same as [`BonsaiStorage::remove`], for a trie of the shard.
+    pub fn remove(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let tree = self
+            .trees
+            .get_mut(identifier)
+            .ok_or_else(|| not_in_shard(identifier))?;
+        tree.remove(self.storage.read().tries.db_ref(), key)
+    }
+
+    /// Same as [`BonsaiStorage::get`], for a trie of the shard.
+    pub fn get(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let tree = self
+            .trees
+            .get(identifier)
+            .ok_or_else(|| not_in_shard(identifier))?;
+        tree.get(self.storage.read().tries.db_ref(), key)
+    }
+}
+
+impl<ChangeID, DB, H> TrieShard<ChangeID, DB, H>
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<ChangeID>,
+    ChangeID: Id,
+    H: StarkHash + Send + Sync,
+{
+    /// Commit the changes of the shard as `id`, which must not be older than the latest commit of
+    /// the storage. Committing as the latest commit adds the changes to it. The uncommitted changes
+    /// are lost if the commit fails.
+    pub fn commit(
+        &mut self,
+        id: ChangeID,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let prepared = self
+            .storage
+            .read()
+            .tries
+            .prepare_shard_commit(&mut self.trees);
+        let res = prepared.and_then(|prepared| self.storage.write().commit_shard(id, prepared));
+        if res.is_err() {
+            for (identifier, tree) in &mut self.trees {
+                *tree = MerkleTree::with_hasher(
+                    identifier.clone(),
+                    tree.max_height,
+                    tree.hasher.clone(),
+                )
+                .store_zero_values(tree.store_zero);
+            }
+        }
+        res
+    }
+}
+
+impl<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> Drop
+    for TrieShard<ChangeID, DB, H>
+{
+    fn drop(&mut self) {
+        self.storage.write().tries.close_shard(self.trees.keys());
+    }
+}
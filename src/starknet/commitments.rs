@@ -0,0 +1,48 @@
+//! Leaves of the Starknet contracts and classes tries, and the global state root they commit to.
+//!
+//! The contracts trie has a leaf per deployed contract, at its address, and the classes trie a
+//! leaf per declared Sierra class, at its class hash. Both are Pedersen tries of height 251,
+//! except that the classes trie uses Poseidon. See the "State" page of the Starknet documentation.
+
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon, StarkHash},
+};
+
+/// `'STARKNET_STATE_V0'` as a short string, the first element hashed into the global state root.
+pub const STARKNET_STATE_V0: Felt =
+    Felt::from_hex_unchecked("0x535441524b4e45545f53544154455f5630");
+
+/// `'CONTRACT_CLASS_LEAF_V0'` as a short string, the first element hashed into a leaf of the
+/// classes trie.
+pub const CONTRACT_CLASS_LEAF_V0: Felt =
+    Felt::from_hex_unchecked("0x434f4e54524143545f434c4153535f4c4541465f5630");
+
+/// Version of the contract state hash, the last element hashed into a leaf of the contracts trie.
+pub const CONTRACT_STATE_HASH_VERSION: Felt = Felt::ZERO;
+
+/// Value of the leaf of a contract in the contracts trie:
+/// `h(h(h(class_hash, storage_root), nonce), 0)` with `h` the Pedersen hash, where `storage_root`
+/// is the root hash of the storage trie of the contract.
+pub fn contract_leaf(class_hash: &Felt, storage_root: &Felt, nonce: &Felt) -> Felt {
+    let hash = Pedersen::hash(class_hash, storage_root);
+    let hash = Pedersen::hash(&hash, nonce);
+    Pedersen::hash(&hash, &CONTRACT_STATE_HASH_VERSION)
+}
+
+/// Value of the leaf of a class in the classes trie: the Poseidon hash of
+/// [`CONTRACT_CLASS_LEAF_V0`] and the compiled class hash of the class.
+pub fn class_leaf(compiled_class_hash: &Felt) -> Felt {
+    Poseidon::hash(&CONTRACT_CLASS_LEAF_V0, compiled_class_hash)
+}
+
+/// Global state root of Starknet, committed to by the block headers: the Poseidon hash of
+/// [`STARKNET_STATE_V0`] and the two roots. Before the classes trie was introduced by Starknet
+/// 0.11, its root is zero and the global root is the root of the contracts trie.
+pub fn compute_global_state_root(contracts_root: &Felt, classes_root: &Felt) -> Felt {
+    if *classes_root == Felt::ZERO {
+        *contracts_root
+    } else {
+        Poseidon::hash_array(&[STARKNET_STATE_V0, *contracts_root, *classes_root])
+    }
+}
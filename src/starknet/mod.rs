@@ -0,0 +1,4 @@
+//! Starknet specifics built on top of the tries of a [`crate::BonsaiStorage`].
+
+pub mod commitments;
+pub mod storage;
@@ -0,0 +1,55 @@
+//! Keys of the storage tries of Starknet contracts, derived from the storage variables declared
+//! in their source, to read a variable by its name.
+//!
+//! A storage variable `name` is at the address `starknet_keccak(name)`, and the entry of a map
+//! at `keys` is at `h(...h(h(starknet_keccak(name), keys[0]), keys[1])..., keys[n])` with `h` the
+//! Pedersen hash, reduced modulo [`ADDR_BOUND`]. A value spanning several felts, such as a
+//! `u256`, is stored at the following addresses, see [`storage_key`].
+
+use crate::{BitVec, Path};
+use starknet_types_core::{
+    felt::{Felt, NonZeroFelt},
+    hash::{Pedersen, StarkHash},
+};
+
+/// `2^251 - 256`, storage addresses are lower so that a value can span up to 256 felts from its
+/// address while staying a key of the trie.
+pub const ADDR_BOUND: Felt =
+    Felt::from_hex_unchecked("0x7ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff00");
+
+/// The 250 low bits of the Keccak-256 digest of `bytes`, which Starknet uses for the selectors
+/// of the entry points and the addresses of the storage variables.
+#[cfg(feature = "keccak")]
+pub fn starknet_keccak(bytes: &[u8]) -> Felt {
+    use crate::hashers::{ByteHasher, Keccak256};
+
+    let mut hasher = Keccak256::new();
+    hasher.update(bytes);
+    let mut digest = hasher.finalize();
+    digest[0] &= 0x03;
+    Felt::from_bytes_be(&digest)
+}
+
+/// Address of the storage variable `name`, or of its entry at `keys` if it is a map.
+#[cfg(feature = "keccak")]
+pub fn storage_var_address(name: &str, keys: &[Felt]) -> Felt {
+    map_entry_address(&starknet_keccak(name.as_bytes()), keys)
+}
+
+/// Address of the entry at `keys` of the map whose address is `base`, `base` itself reduced
+/// modulo [`ADDR_BOUND`] without keys.
+pub fn map_entry_address(base: &Felt, keys: &[Felt]) -> Felt {
+    let address = keys
+        .iter()
+        .fold(*base, |address, key| Pedersen::hash(&address, key));
+    address.mod_floor(&NonZeroFelt::from_felt_unchecked(ADDR_BOUND))
+}
+
+/// Key in the storage trie of a contract of the felt `offset` of the value at `address`, reduced
+/// modulo [`ADDR_BOUND`] first.
+pub fn storage_key(address: &Felt, offset: u8) -> BitVec {
+    let address = map_entry_address(address, &[]) + Felt::from(offset);
+    Path::from_felt_251(&address)
+        .expect("Addresses are lower than 2^251")
+        .0
+}
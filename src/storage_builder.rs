@@ -0,0 +1,178 @@
+//! Options of a [`BonsaiStorage`] when it is opened, see [`BonsaiStorageBuilder`].
+
+use crate::{
+    id::Id,
+    migrations::{self, MigrationOptions, SCHEMA_VERSION},
+    stored_config, Arc, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError,
+    Box, ByteVec, ChangeSink, ConfigError, StoredConfig, TrieHasher, Vec,
+};
+use core::marker::PhantomData;
+use starknet_types_core::hash::StarkHash;
+
+/// Opens a [`BonsaiStorage`] on the database `db`, see [`BonsaiStorage::builder`].
+///
+/// The configuration and the height of the tries default to the ones stored in the database, see
+/// [`StoredConfig`]. Databases written by older versions of this crate are upgraded first, see
+/// [`migrations`], unless the storage is read-only.
+pub struct BonsaiStorageBuilder<ChangeID: Id, DB: BonsaiDatabase, H: StarkHash + Send + Sync> {
+    db: DB,
+    config: Option<BonsaiStorageConfig>,
+    max_height: Option<u8>,
+    trie_hashers: Vec<(ByteVec, TrieHasher)>,
+    max_cached_tries: Option<usize>,
+    read_only: bool,
+    change_sink: Option<Arc<dyn ChangeSink<ChangeID>>>,
+    created_at: Option<ChangeID>,
+    _hasher: PhantomData<H>,
+}
+
+impl<ChangeID, DB, H> BonsaiStorageBuilder<ChangeID, DB, H>
+where
+    ChangeID: Id,
+    DB: BonsaiDatabase,
+    H: StarkHash + Send + Sync,
+{
+    pub fn new(db: DB) -> Self {
+        Self {
+            db,
+            config: None,
+            max_height: None,
+            trie_hashers: Vec::new(),
+            max_cached_tries: None,
+            read_only: false,
+            change_sink: None,
+            created_at: None,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Use `config` instead of the configuration stored in the database, or of the default one
+    /// for a new database. It must match the stored parameters.
+    pub fn config(mut self, config: BonsaiStorageConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Height of the tries, required for a new database.
+    pub fn max_height(mut self, max_height: u8) -> Self {
+        self.max_height = Some(max_height);
+        self
+    }
+
+    /// Hash the trie `identifier` with `hasher` instead of `H`, see
+    /// [`BonsaiStorageConfig::trie_hashers`].
+    pub fn trie_hasher(mut self, identifier: &[u8], hasher: TrieHasher) -> Self {
+        self.trie_hashers.push((identifier.into(), hasher));
+        self
+    }
+
+    /// Keep at most `max` tries in memory after a commit, see
+    /// [`BonsaiStorageConfig::max_cached_tries`]. It is not stored in the database, so it can be
+    /// set along with the stored configuration.
+    pub fn max_cached_tries(mut self, max: usize) -> Self {
+        self.max_cached_tries = Some(max);
+        self
+    }
+
+    /// Open the database without writing to it: it is neither upgraded nor given a stored
+    /// configuration, and everything that writes to it fails with
+    /// [`BonsaiStorageError::ReadOnly`]. Changes can still be made in memory and read back.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Send the leaf changes of every commit to `sink`, see [`BonsaiStorage::set_change_sink`].
+    pub fn change_sink(mut self, sink: Arc<dyn ChangeSink<ChangeID>>) -> Self {
+        self.change_sink = Some(sink);
+        self
+    }
+
+    /// Open `db` as the transactional state of commit `created_at`, see
+    /// [`BonsaiStorage::get_transactional_state`]. The database is neither upgraded nor checked
+    /// against its stored configuration, as it is a view of a database that already was.
+    pub fn transactional_state(mut self, created_at: ChangeID) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    /// Open the storage. Fails with [`BonsaiStorageError::Config`] when the options can't be
+    /// used together or with the database.
+    pub fn build(
+        mut self,
+    ) -> Result<BonsaiStorage<ChangeID, DB, H>, BonsaiStorageError<DB::DatabaseError>> {
+        let config_error = |err| Err(BonsaiStorageError::Config(err));
+        if self.read_only && self.change_sink.is_some() {
+            return config_error(ConfigError::ReadOnlyChangeSink);
+        }
+        let trie_hashers = self.trie_hashers;
+        let max_cached_tries = self.max_cached_tries;
+        let with_options = |mut config: BonsaiStorageConfig| {
+            config.trie_hashers.extend(trie_hashers.iter().cloned());
+            if max_cached_tries.is_some() {
+                config.max_cached_tries = max_cached_tries;
+            }
+            config.validate().map(|()| config)
+        };
+        // An invalid configuration is rejected before the database is written.
+        let config = match self.config.map(with_options).transpose() {
+            Ok(config) => config,
+            Err(err) => return config_error(err),
+        };
+        let stored = match self.created_at {
+            Some(_) => None,
+            None if self.read_only => {
+                // Older databases that the migration would leave as they are, such as empty
+                // ones, can be read.
+                let version = migrations::schema_version(&self.db)?;
+                if version != SCHEMA_VERSION {
+                    let options = MigrationOptions {
+                        dry_run: true,
+                        ..Default::default()
+                    };
+                    let report = migrations::migrate(&mut self.db, options)?;
+                    if report.steps.iter().any(|step| step.rewritten > 0) {
+                        return config_error(ConfigError::ReadOnlyNeedsMigration { version });
+                    }
+                }
+                stored_config::read(&self.db)?
+            }
+            None => {
+                migrations::migrate(&mut self.db, Default::default())?;
+                stored_config::read(&self.db)?
+            }
+        };
+        let config = match config {
+            Some(config) => config,
+            None => {
+                let config = stored.map_or_else(Default::default, |stored| stored.to_config());
+                match with_options(config) {
+                    Ok(config) => config,
+                    Err(err) => return config_error(err),
+                }
+            }
+        };
+        let Some(max_height) = self.max_height.or(stored.map(|stored| stored.max_height)) else {
+            return config_error(ConfigError::MissingMaxHeight);
+        };
+
+        if self.created_at.is_none() {
+            let requested = StoredConfig::new(&config, max_height);
+            match stored {
+                Some(stored) if stored != requested => {
+                    return config_error(ConfigError::StoredConfigMismatch {
+                        stored: Box::new(stored),
+                        requested: Box::new(requested),
+                    })
+                }
+                Some(_) => {}
+                None if self.read_only => {}
+                None => stored_config::write(&mut self.db, &requested)?,
+            }
+        }
+        let mut storage =
+            BonsaiStorage::open(self.db, config, max_height, self.created_at, self.read_only)?;
+        storage.change_sink = self.change_sink;
+        Ok(storage)
+    }
+}
@@ -0,0 +1,80 @@
+//! The parameters a database must be reopened with, stored in its metadata, see [`StoredConfig`].
+
+use crate::{
+    trie::trie_db::{MetaKeyType, TrieKey},
+    BonsaiDatabase, BonsaiStorageConfig, BonsaiStorageError, DatabaseKey, EncodeExt,
+};
+use parity_scale_codec::{Decode, Encode};
+
+/// The parameters of a storage that change how its database is read, stored in the database when
+/// it is first opened. [`crate::BonsaiStorage::new`] fails with
+/// [`crate::ConfigError::StoredConfigMismatch`] when they differ from the stored ones, as a
+/// different height would read the tries wrongly, and different retention parameters would
+/// silently prune the saved commits or leave gaps between the snapshots. They are changed with
+/// [`crate::BonsaiStorage::reconfigure`], and [`crate::BonsaiStorage::open_existing`] opens a
+/// database with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct StoredConfig {
+    /// Height of the tries.
+    pub max_height: u8,
+    /// See [`BonsaiStorageConfig::max_saved_trie_logs`].
+    pub max_saved_trie_logs: Option<u64>,
+    /// See [`BonsaiStorageConfig::max_saved_snapshots`].
+    pub max_saved_snapshots: Option<u64>,
+    /// See [`BonsaiStorageConfig::snapshot_interval`].
+    pub snapshot_interval: u64,
+    /// See [`BonsaiStorageConfig::max_inline_leaves`].
+    pub max_inline_leaves: Option<u64>,
+}
+
+impl StoredConfig {
+    pub fn new(config: &BonsaiStorageConfig, max_height: u8) -> Self {
+        Self {
+            max_height,
+            max_saved_trie_logs: config.max_saved_trie_logs.map(|n| n as u64),
+            max_saved_snapshots: config.max_saved_snapshots.map(|n| n as u64),
+            snapshot_interval: config.snapshot_interval,
+            max_inline_leaves: config.max_inline_leaves.map(|n| n as u64),
+        }
+    }
+
+    /// The default configuration with these parameters.
+    pub fn to_config(&self) -> BonsaiStorageConfig {
+        BonsaiStorageConfig {
+            max_saved_trie_logs: self.max_saved_trie_logs.map(|n| n as usize),
+            max_saved_snapshots: self.max_saved_snapshots.map(|n| n as usize),
+            snapshot_interval: self.snapshot_interval,
+            max_inline_leaves: self.max_inline_leaves.map(|n| n as usize),
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn stored_config_key() -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::Config, &[])
+}
+
+/// The configuration stored in the database, `None` if it was not opened since it is stored.
+pub(crate) fn read<DB: BonsaiDatabase>(
+    db: &DB,
+) -> Result<Option<StoredConfig>, BonsaiStorageError<DB::DatabaseError>> {
+    let key = stored_config_key();
+    let Some(config) = db.get(&DatabaseKey::from(&key))? else {
+        return Ok(None);
+    };
+    StoredConfig::decode(&mut config.as_slice())
+        .map(Some)
+        .map_err(|source| BonsaiStorageError::DecodeError {
+            key: key.as_slice().into(),
+            source,
+        })
+}
+
+pub(crate) fn write<DB: BonsaiDatabase>(
+    db: &mut DB,
+    config: &StoredConfig,
+) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+    let key = stored_config_key();
+    db.insert(&DatabaseKey::from(&key), &config.encode_bytevec(), None)?;
+    Ok(())
+}
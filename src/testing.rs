@@ -0,0 +1,585 @@
+//! Utilities to test the tries, or an integration of them, against a simple reference
+//! implementation.
+//!
+//! This is enabled by the `testing` feature. The [proptest] strategies in this module generate
+//! sequences of [`Step`]s, which [`check_against_reference`] applies to a [`BonsaiStorage`] using
+//! any database and id type. [`fuzz_ops`] and [`fuzz_ops_on`] are entrypoints for fuzzers.
+//! [`run_database_suite`] checks that a database implementation behaves as the storage expects.
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, Id},
+    trie::tree::MerkleTree,
+    BTreeMap, BitSlice, BitVec, BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorage,
+    BonsaiStorageConfig, DatabaseKey, Vec,
+};
+use core::{fmt, marker::PhantomData};
+use proptest::prelude::*;
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, StarkHash},
+};
+
+/// A Merkle-Patricia trie that only keeps its leaves, and recomputes the root hash from them with
+/// [`MerkleTree::root_from_sorted_leaves`] on every call to [`ReferenceMerkleTree::root_hash`].
+pub struct ReferenceMerkleTree<H: StarkHash> {
+    leaves: BTreeMap<BitVec, Felt>,
+    max_height: u8,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: StarkHash> fmt::Debug for ReferenceMerkleTree<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReferenceMerkleTree")
+            .field("leaves", &self.leaves)
+            .field("max_height", &self.max_height)
+            .finish()
+    }
+}
+
+// NB: #[derive(Clone)] would require H: Clone.
+impl<H: StarkHash> Clone for ReferenceMerkleTree<H> {
+    fn clone(&self) -> Self {
+        Self {
+            leaves: self.leaves.clone(),
+            max_height: self.max_height,
+            _hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: StarkHash + Send + Sync> ReferenceMerkleTree<H> {
+    pub fn new(max_height: u8) -> Self {
+        Self {
+            leaves: BTreeMap::new(),
+            max_height,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Set the value of a leaf, a value of [`Felt::ZERO`] removes it.
+    ///
+    /// # Panics
+    ///
+    /// If the key is not `max_height` bits long.
+    pub fn insert(&mut self, key: &BitSlice, value: Felt) {
+        assert_eq!(key.len(), self.max_height as usize, "Invalid key length");
+        if value == Felt::ZERO {
+            self.leaves.remove(key);
+        } else {
+            self.leaves.insert(key.to_bitvec(), value);
+        }
+    }
+
+    pub fn remove(&mut self, key: &BitSlice) {
+        self.insert(key, Felt::ZERO)
+    }
+
+    pub fn get(&self, key: &BitSlice) -> Option<Felt> {
+        self.leaves.get(key).copied()
+    }
+
+    pub fn root_hash(&self) -> Felt {
+        let leaves: Vec<_> = self
+            .leaves
+            .iter()
+            .map(|(key, value)| (key.as_bitslice(), *value))
+            .collect();
+        MerkleTree::<H>::root_from_sorted_leaves(&leaves)
+    }
+}
+
+/// An operation on a trie.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Step {
+    Insert(BitVec, Felt),
+    Remove(BitVec),
+    Commit,
+}
+
+impl fmt::Debug for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Insert(key, value) => write!(f, "Insert({key:b}, {value:#x})"),
+            Step::Remove(key) => write!(f, "Remove({key:b})"),
+            Step::Commit => write!(f, "Commit"),
+        }
+    }
+}
+
+/// Keys of a trie of height `max_height`.
+pub fn key(max_height: u8) -> impl Strategy<Value = BitVec> {
+    proptest::collection::vec(any::<bool>(), max_height as usize)
+        .prop_map(|bits| bits.into_iter().collect())
+}
+
+/// Non-zero leaf values.
+pub fn value() -> impl Strategy<Value = Felt> {
+    any::<[u8; 32]>()
+        .prop_map(|bytes| Felt::from_bytes_be(&bytes))
+        .prop_filter("zero removes the leaf", |value| *value != Felt::ZERO)
+}
+
+pub fn step(max_height: u8) -> impl Strategy<Value = Step> {
+    prop_oneof![
+        (key(max_height), value()).prop_map(|(key, value)| Step::Insert(key, value)),
+        key(max_height).prop_map(Step::Remove),
+        Just(Step::Commit),
+    ]
+}
+
+pub fn steps(max_height: u8) -> impl Strategy<Value = Vec<Step>> {
+    proptest::collection::vec(step(max_height), 0..100)
+}
+
+/// Apply `steps` to the trie `identifier` of `storage` and to a [`ReferenceMerkleTree`], using
+/// `next_id` to get the id of every commit. The tries are committed once more at the end.
+///
+/// # Panics
+///
+/// If an operation fails, or if the two tries disagree on the root hash after a commit or on the
+/// value of a leaf.
+pub fn check_against_reference<ID, DB, H>(
+    storage: &mut BonsaiStorage<ID, DB, H>,
+    identifier: &[u8],
+    steps: &[Step],
+    mut next_id: impl FnMut() -> ID,
+) where
+    ID: Id,
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<ID>,
+    H: StarkHash + Send + Sync,
+{
+    let mut reference = ReferenceMerkleTree::<H>::new(storage.tries.max_height);
+    let mut touched_keys = Vec::new();
+    for step in steps.iter().chain([&Step::Commit]) {
+        log::trace!("== STEP == {step:?}");
+        match step {
+            Step::Insert(key, value) => {
+                storage.insert(identifier, key, value).unwrap();
+                reference.insert(key, *value);
+                touched_keys.push(key.clone());
+            }
+            Step::Remove(key) => {
+                storage.remove(identifier, key).unwrap();
+                reference.remove(key);
+                touched_keys.push(key.clone());
+            }
+            Step::Commit => {
+                storage.commit(next_id()).unwrap();
+                assert_eq!(
+                    storage.root_hash(identifier).unwrap(),
+                    reference.root_hash(),
+                    "Root hash mismatch with the reference trie"
+                );
+            }
+        }
+    }
+
+    for key in touched_keys {
+        assert_eq!(
+            storage.get(identifier, &key).unwrap(),
+            reference.get(&key),
+            "Value mismatch with the reference trie for key {key:b}"
+        );
+    }
+}
+
+/// Identifiers of the tries used by [`fuzz_ops`]. They have the same length so that none is a
+/// prefix of another.
+const FUZZ_IDENTIFIERS: [&[u8]; 3] = [b"id0", b"id1", b"id2"];
+/// Height of the tries used by [`fuzz_ops`], small so that operations often hit the same keys.
+const FUZZ_HEIGHT: u8 = 8;
+
+/// Fuzzing entrypoint, to be called with the input of the fuzzer, for example from a `cargo fuzz`
+/// target: `fuzz_target!(|data: &[u8]| bonsai_trie::testing::fuzz_ops(data));`
+///
+/// `data` is decoded as a sequence of insert, remove, commit, revert and proof operations on
+/// several tries of a [`HashMapDb`] storage, which are also applied to [`ReferenceMerkleTree`]s.
+/// The trie logs of all the commits are kept, so that any of them can be reverted to.
+///
+/// # Panics
+///
+/// If any of these invariants is broken:
+/// - After a commit, root hashes and values are the same as in the reference tries.
+/// - After a revert, root hashes are the same as when the commit was made.
+/// - Proofs of committed tries verify and prove the values of the reference tries.
+/// - Once all the leaves are removed, no trie node is left in the database.
+pub fn fuzz_ops(data: &[u8]) {
+    fuzz_ops_on(HashMapDb::<BasicId>::default(), data)
+}
+
+/// Same as [`fuzz_ops`] with the empty database `db`, to fuzz other database implementations.
+pub fn fuzz_ops_on<DB>(db: DB, data: &[u8])
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: None,
+        ..Default::default()
+    };
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, config, FUZZ_HEIGHT).unwrap();
+    let mut references =
+        [(); FUZZ_IDENTIFIERS.len()].map(|_| ReferenceMerkleTree::<Pedersen>::new(FUZZ_HEIGHT));
+    // Commits that can be reverted to, with the state of the tries at that point.
+    let mut history: Vec<(BasicId, Vec<Felt>, Vec<ReferenceMerkleTree<Pedersen>>)> = Vec::new();
+    let mut has_changes = false;
+
+    let mut data = data.iter().copied();
+    let mut next = || data.next();
+    let fuzz_key = |byte: u8| BitVec::from_element(byte);
+
+    while let Some(op) = next() {
+        match op % 6 {
+            0..=2 => {
+                let (Some(trie), Some(key), Some(value)) = (next(), next(), next()) else {
+                    break;
+                };
+                let trie = trie as usize % FUZZ_IDENTIFIERS.len();
+                // A value of zero removes the leaf.
+                let value = if op % 6 == 2 { 0 } else { value };
+                let key = fuzz_key(key);
+                log::trace!("== FUZZ == set {trie} {key:b} => {value}");
+                storage
+                    .insert(FUZZ_IDENTIFIERS[trie], &key, &Felt::from(value))
+                    .unwrap();
+                references[trie].insert(&key, Felt::from(value));
+                has_changes = true;
+            }
+            3 => {
+                let id = BasicId::new(history.last().map_or(0, |(id, ..)| id.as_u64() + 1));
+                log::trace!("== FUZZ == commit {id:?}");
+                storage.commit(id).unwrap();
+                has_changes = false;
+                let roots = fuzz_check_tries(&storage, &references);
+                history.push((id, roots, references.to_vec()));
+            }
+            4 => {
+                let Some(index) = next() else { break };
+                if history.is_empty() {
+                    continue;
+                }
+                let index = index as usize % history.len();
+                let (id, roots, reverted) = history[index].clone();
+                log::trace!("== FUZZ == revert to {id:?}");
+                storage.revert_to(id).unwrap();
+                history.truncate(index + 1);
+                references.clone_from_slice(&reverted);
+                has_changes = false;
+                assert_eq!(fuzz_check_tries(&storage, &references), roots);
+            }
+            _ => {
+                let (Some(trie), Some(keys)) = (next(), next()) else {
+                    break;
+                };
+                // Root hashes are only available for committed tries.
+                if has_changes {
+                    continue;
+                }
+                let trie = trie as usize % FUZZ_IDENTIFIERS.len();
+                let keys: Vec<_> = (0..keys % 4 + 1)
+                    .filter_map(|_| next())
+                    .map(fuzz_key)
+                    .collect();
+                log::trace!("== FUZZ == proof {trie} {keys:?}");
+                let identifier = FUZZ_IDENTIFIERS[trie];
+                let root = storage.root_hash(identifier).unwrap();
+                // There is nothing to prove in an empty trie.
+                if root == Felt::ZERO {
+                    continue;
+                }
+                let proof = storage.get_multi_proof(identifier, &keys).unwrap();
+                let values = proof.verify_proof::<Pedersen>(root, &keys, FUZZ_HEIGHT);
+                for (key, value) in keys.iter().zip(values) {
+                    let expected = references[trie].get(key).unwrap_or(Felt::ZERO);
+                    assert_eq!(value.unwrap(), expected, "Invalid proof for key {key:b}");
+                }
+            }
+        }
+    }
+
+    // Remove everything to look for leftover nodes.
+    for (trie, identifier) in FUZZ_IDENTIFIERS.iter().enumerate() {
+        for key in 0..=u8::MAX {
+            storage.remove(identifier, &fuzz_key(key)).unwrap();
+            references[trie].remove(&fuzz_key(key));
+        }
+    }
+    let id = BasicId::new(history.last().map_or(0, |(id, ..)| id.as_u64() + 1));
+    storage.commit(id).unwrap();
+    assert!(fuzz_check_tries(&storage, &references)
+        .iter()
+        .all(|root| *root == Felt::ZERO));
+    let db = &storage.tries.db_ref().db;
+    for column in [DatabaseKey::Trie(&[]), DatabaseKey::Flat(&[])] {
+        assert_eq!(
+            db.get_by_prefix(&column).unwrap(),
+            [],
+            "Leftover {column:?} entries"
+        );
+    }
+}
+
+/// Check the values and root hashes of the committed tries of [`fuzz_ops`] against the reference
+/// tries, and return the root hashes.
+fn fuzz_check_tries<DB>(
+    storage: &BonsaiStorage<BasicId, DB, Pedersen>,
+    references: &[ReferenceMerkleTree<Pedersen>],
+) -> Vec<Felt>
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    FUZZ_IDENTIFIERS
+        .iter()
+        .zip(references)
+        .map(|(identifier, reference)| {
+            for key in 0..=u8::MAX {
+                let key = BitVec::from_element(key);
+                assert_eq!(
+                    storage.get(identifier, &key).unwrap(),
+                    reference.get(&key),
+                    "Value mismatch with the reference trie for key {key:b}"
+                );
+            }
+            let root = storage.root_hash(identifier).unwrap();
+            assert_eq!(
+                root,
+                reference.root_hash(),
+                "Root hash mismatch with the reference trie"
+            );
+            root
+        })
+        .collect()
+}
+
+/// Check that the databases made by `new_db`, empty ones, behave as the storage expects from a
+/// [`BonsaiDatabase`] and a [`BonsaiPersistentDatabase`], for instance from the tests of another
+/// database implementation: `run_database_suite(|| MyDb::open_temporary())`.
+///
+/// # Panics
+///
+/// If any of these invariants is broken:
+/// - Each kind of [`DatabaseKey`] has its own keys: the same bytes can be a key of every kind.
+/// - `insert`, `insert_many` and `remove` return the previous value, `get_many` returns the
+///   values of `get`.
+/// - `get_by_prefix` returns the whole keys that start with the prefix, in increasing order, which
+///   the trie logs rely on. `get_keys_by_prefix` returns the same keys, and `remove_by_prefix`
+///   removes exactly them.
+/// - The writes to a batch are not visible until `write_batch`, which applies them in order.
+/// - A transaction reads the closest snapshot at or before its id, its writes change neither the
+///   database nor the snapshot, and `snapshots` lists the snapshots in increasing order if the
+///   database implements it. Merging is not checked, as a transaction may borrow its database.
+///   These are skipped for a database that keeps no snapshots, like [`OverlayDb`], whose
+///   `transaction` returns `None`.
+///
+/// [`OverlayDb`]: crate::databases::OverlayDb
+pub fn run_database_suite<DB>(mut new_db: impl FnMut() -> DB)
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    check_key_kinds(&mut new_db());
+    check_prefixes(&mut new_db());
+    check_batches(&mut new_db());
+    check_transactions(&mut new_db());
+}
+
+fn all_kinds(key: &[u8]) -> [DatabaseKey<'_>; 4] {
+    [
+        DatabaseKey::Trie(key),
+        DatabaseKey::Flat(key),
+        DatabaseKey::TrieLog(key),
+        DatabaseKey::Meta(key),
+    ]
+}
+
+fn check_key_kinds<DB: BonsaiDatabase>(db: &mut DB) {
+    for (i, key) in all_kinds(b"key").iter().enumerate() {
+        assert_eq!(db.insert(key, &[i as u8], None).unwrap(), None, "{key:?}");
+    }
+    for (i, key) in all_kinds(b"key").iter().enumerate() {
+        assert_eq!(
+            db.get(key).unwrap().as_deref(),
+            Some(&[i as u8][..]),
+            "{key:?} is not kept apart from the other kinds"
+        );
+        assert!(db.contains(key).unwrap(), "{key:?}");
+        assert_eq!(
+            db.insert(key, b"new", None).unwrap().as_deref(),
+            Some(&[i as u8][..]),
+            "insert must return the previous value of {key:?}"
+        );
+    }
+    let keys = all_kinds(b"key");
+    let missing = DatabaseKey::Flat(b"missing");
+    let values = db.get_many(&[keys[1], missing, keys[0]]).unwrap();
+    assert_eq!(
+        values,
+        [db.get(&keys[1]).unwrap(), None, db.get(&keys[0]).unwrap()],
+        "get_many must return the values of get, in order"
+    );
+    for key in &keys {
+        assert_eq!(
+            db.remove(key, None).unwrap().as_deref(),
+            Some(&b"new"[..]),
+            "remove must return the previous value of {key:?}"
+        );
+        assert_eq!(db.get(key).unwrap(), None, "{key:?} was removed");
+        assert!(!db.contains(key).unwrap(), "{key:?} was removed");
+        assert_eq!(db.remove(key, None).unwrap(), None, "{key:?} was removed");
+    }
+}
+
+fn check_prefixes<DB: BonsaiDatabase>(db: &mut DB) {
+    // Inserted out of order, with keys that are prefixes of others.
+    let keys: [&[u8]; 6] = [b"ab\x01", b"b", b"ab", b"a", b"abc", b"ab\x00"];
+    for key in keys {
+        db.insert(&DatabaseKey::Trie(key), key, None).unwrap();
+    }
+    db.insert(&DatabaseKey::Flat(b"ab"), b"flat", None).unwrap();
+
+    let entries = db.get_by_prefix(&DatabaseKey::Trie(b"ab")).unwrap();
+    let expected: Vec<(crate::ByteVec, crate::ByteVec)> =
+        [&b"ab"[..], b"ab\x00", b"ab\x01", b"abc"]
+            .into_iter()
+            .map(|key| (key.into(), key.into()))
+            .collect();
+    assert_eq!(
+        entries, expected,
+        "get_by_prefix must return the whole keys with the prefix, of its kind, in increasing order"
+    );
+    assert_eq!(
+        db.get_keys_by_prefix(&DatabaseKey::Trie(b"ab")).unwrap(),
+        expected.into_iter().map(|(key, _)| key).collect::<Vec<_>>(),
+        "get_keys_by_prefix must return the keys of get_by_prefix"
+    );
+    assert_eq!(
+        db.get_by_prefix(&DatabaseKey::Trie(b"")).unwrap().len(),
+        keys.len(),
+        "the empty prefix matches every key of its kind"
+    );
+
+    db.remove_by_prefix(&DatabaseKey::Trie(b"ab")).unwrap();
+    let left: Vec<_> = db
+        .get_by_prefix(&DatabaseKey::Trie(b""))
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(
+        left,
+        [&b"a"[..], b"b"].map(crate::ByteVec::from),
+        "remove_by_prefix must remove exactly the keys with the prefix"
+    );
+    assert!(
+        db.contains(&DatabaseKey::Flat(b"ab")).unwrap(),
+        "remove_by_prefix must only remove the keys of its kind"
+    );
+}
+
+fn check_batches<DB: BonsaiDatabase>(db: &mut DB) {
+    let (a, b) = (DatabaseKey::Flat(b"a"), DatabaseKey::Flat(b"b"));
+    db.insert(&a, b"old", None).unwrap();
+    let mut batch = db.create_batch();
+    assert_eq!(
+        db.insert(&a, b"new", Some(&mut batch)).unwrap().as_deref(),
+        Some(&b"old"[..]),
+        "insert in a batch must return the value in the database"
+    );
+    db.insert(&b, b"inserted", Some(&mut batch)).unwrap();
+    db.remove(&b, Some(&mut batch)).unwrap();
+    db.insert(&b, b"reinserted", Some(&mut batch)).unwrap();
+    assert_eq!(
+        db.get(&a).unwrap().as_deref(),
+        Some(&b"old"[..]),
+        "the writes of a batch must not be visible before write_batch"
+    );
+    assert_eq!(db.get(&b).unwrap(), None);
+
+    db.write_batch(batch).unwrap();
+    assert_eq!(db.get(&a).unwrap().as_deref(), Some(&b"new"[..]));
+    assert_eq!(
+        db.get(&b).unwrap().as_deref(),
+        Some(&b"reinserted"[..]),
+        "the writes of a batch must be applied in order"
+    );
+
+    let c = DatabaseKey::Flat(b"c");
+    let mut batch = db.create_batch();
+    assert_eq!(
+        db.insert_many(&[(a, b"many"), (c, b"c")], Some(&mut batch))
+            .unwrap(),
+        [Some(b"new"[..].into()), None],
+        "insert_many in a batch must return the values in the database, in order"
+    );
+    assert_eq!(db.get(&c).unwrap(), None);
+    db.write_batch(batch).unwrap();
+    assert_eq!(db.get(&a).unwrap().as_deref(), Some(&b"many"[..]));
+    assert_eq!(
+        db.insert_many(&[(c, b"c2"), (b, b"b2")], None).unwrap(),
+        [Some(b"c"[..].into()), Some(b"reinserted"[..].into())],
+        "insert_many must return the previous values, in order"
+    );
+    assert_eq!(db.get(&c).unwrap().as_deref(), Some(&b"c2"[..]));
+    assert_eq!(db.get(&b).unwrap().as_deref(), Some(&b"b2"[..]));
+}
+
+fn check_transactions<DB>(db: &mut DB)
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    let key = DatabaseKey::Trie(b"key");
+    db.insert(&key, b"0", None).unwrap();
+    db.snapshot(BasicId::new(0));
+    db.insert(&key, b"2", None).unwrap();
+    db.snapshot(BasicId::new(2));
+
+    let Some((id, txn)) = db.transaction(BasicId::new(1)) else {
+        assert!(
+            db.snapshots().is_empty(),
+            "the snapshot 0 is at or before 1"
+        );
+        return;
+    };
+    assert_eq!(
+        id,
+        BasicId::new(0),
+        "the closest snapshot at or before 1 is 0"
+    );
+    assert_eq!(txn.get(&key).unwrap().as_deref(), Some(&b"0"[..]));
+    drop(txn);
+    assert!(
+        db.transaction(BasicId::new(3))
+            .is_some_and(|(id, _)| id == BasicId::new(2)),
+        "the closest snapshot at or before 3 is 2"
+    );
+    let snapshots = db.snapshots();
+    assert!(
+        snapshots.is_empty() || snapshots == [BasicId::new(0), BasicId::new(2)],
+        "snapshots must be listed in increasing order, got {snapshots:?}"
+    );
+
+    let (_, mut txn) = db.transaction(BasicId::new(2)).unwrap();
+    txn.insert(&key, b"3", None).unwrap();
+    assert_eq!(txn.get(&key).unwrap().as_deref(), Some(&b"3"[..]));
+    assert_eq!(
+        db.get(&key).unwrap().as_deref(),
+        Some(&b"2"[..]),
+        "the writes of a transaction must not be visible in the database"
+    );
+    drop(txn);
+    assert_eq!(
+        db.transaction(BasicId::new(2))
+            .unwrap()
+            .1
+            .get(&key)
+            .unwrap()
+            .as_deref(),
+        Some(&b"2"[..]),
+        "the writes of a transaction must not change its snapshot"
+    );
+
+    if !snapshots.is_empty() {
+        assert!(db.remove_snapshot(BasicId::new(0)));
+        assert!(!db.remove_snapshot(BasicId::new(0)));
+        assert_eq!(db.snapshots(), [BasicId::new(2)]);
+    }
+}
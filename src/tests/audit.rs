@@ -0,0 +1,83 @@
+#![cfg(all(feature = "std", feature = "audit"))]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    trie::{
+        tree::{bitslice_to_bytes, encode_leaf},
+        trie_db::TrieKeyType,
+        TrieKey,
+    },
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, DatabaseKey,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+fn storage() -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap()
+}
+
+#[test]
+fn commits_pass_hashmap_db() {
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = SmallRng::seed_from_u64(5);
+    for _ in 0..10 {
+        for _ in 0..50 {
+            let identifier = [rng.gen_range(0..3)];
+            let key = BitVec::from_vec(vec![rng.gen_range(0..4), rng.gen(), rng.gen()]);
+            let value = Felt::from(rng.gen_range(0..4u8));
+            bonsai_storage.insert(&identifier, &key, &value).unwrap();
+        }
+        bonsai_storage
+            .remove_prefix(&[0], &BitVec::from_vec(vec![rng.gen_range(0..4)]))
+            .unwrap();
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+    }
+
+    let id = id_builder.new_id();
+    bonsai_storage
+        .insert(&[1], &BitVec::from_vec(vec![1, 2, 3]), &Felt::ONE)
+        .unwrap();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    bonsai_storage.commit_prepared(id, prepared).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "is not the one of its leaves")]
+fn tampered_leaf_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    for i in 0..4u8 {
+        bonsai_storage
+            .insert(&identifier, &BitVec::from_vec(vec![1, 2, i]), &Felt::ONE)
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // A leaf changed behind the back of the trie.
+    let key = TrieKey::new(
+        &identifier,
+        TrieKeyType::Flat,
+        &bitslice_to_bytes(&BitVec::from_vec(vec![1, 2, 0])),
+    );
+    bonsai_storage
+        .tries
+        .db_mut()
+        .db
+        .insert(
+            &DatabaseKey::from(&key),
+            &encode_leaf(&Felt::TWO, None),
+            None,
+        )
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 9]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+}
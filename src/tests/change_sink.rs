@@ -0,0 +1,345 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, ChangeSink, ChangeSinkError,
+    ChangeSinkPolicy, LeafChange,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+#[derive(Default)]
+struct TestSink {
+    full: AtomicBool,
+    received: Mutex<Vec<(BasicId, Vec<LeafChange>)>>,
+}
+
+impl ChangeSink<BasicId> for TestSink {
+    fn send(&self, id: BasicId, changes: &[LeafChange]) -> Result<(), String> {
+        if self.full.load(Ordering::SeqCst) {
+            return Err("full".to_string());
+        }
+        self.received.lock().unwrap().push((id, changes.to_vec()));
+        Ok(())
+    }
+}
+
+fn storage(policy: ChangeSinkPolicy) -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    let config = BonsaiStorageConfig {
+        change_sink_policy: policy,
+        ..Default::default()
+    };
+    BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap()
+}
+
+#[test]
+fn fail_commit_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage = storage(ChangeSinkPolicy::FailCommit);
+    let sink = Arc::new(TestSink::default());
+    bonsai_storage.set_change_sink(sink.clone());
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    let id0 = id_builder.new_id();
+    bonsai_storage.commit(id0).unwrap();
+
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(3u32))
+        .unwrap();
+    // Not an actual change.
+    bonsai_storage.remove(&identifier, &key1).unwrap();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    let id1 = id_builder.new_id();
+    sink.full.store(true, Ordering::SeqCst);
+    assert!(matches!(
+        bonsai_storage.commit(id1),
+        Err(BonsaiStorageError::ChangeSink(
+            ChangeSinkError::Refused { .. }
+        ))
+    ));
+    // The changes are still there, the commit can be retried.
+    sink.full.store(false, Ordering::SeqCst);
+    bonsai_storage.commit(id1).unwrap();
+
+    let received = sink.received.lock().unwrap();
+    assert_eq!(
+        *received,
+        vec![
+            (
+                id0,
+                vec![LeafChange {
+                    identifier: identifier.as_slice().into(),
+                    key: key1.clone(),
+                    old_value: None,
+                    new_value: Some(Felt::from(1u32)),
+                }]
+            ),
+            (
+                id1,
+                vec![LeafChange {
+                    identifier: identifier.as_slice().into(),
+                    key: key2,
+                    old_value: None,
+                    new_value: Some(Felt::from(3u32)),
+                }]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn buffer_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage = storage(ChangeSinkPolicy::Buffer { max_commits: 2 });
+    let sink = Arc::new(TestSink::default());
+    bonsai_storage.set_change_sink(sink.clone());
+    let mut id_builder = BasicIdBuilder::new();
+
+    sink.full.store(true, Ordering::SeqCst);
+    let mut ids = Vec::new();
+    for i in 0..3u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+        let id = id_builder.new_id();
+        let res = bonsai_storage.commit(id);
+        if i < 2 {
+            res.unwrap();
+            ids.push(id);
+        } else {
+            assert!(matches!(
+                res,
+                Err(BonsaiStorageError::ChangeSink(
+                    ChangeSinkError::BufferFull { waiting: 2, .. }
+                ))
+            ));
+        }
+    }
+
+    // The last buffered commit is reverted.
+    bonsai_storage.revert_to(ids[0]).unwrap();
+    assert_eq!(bonsai_storage.flush_change_sink(), 1);
+    sink.full.store(false, Ordering::SeqCst);
+    assert_eq!(bonsai_storage.flush_change_sink(), 0);
+
+    let id = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 9]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id).unwrap();
+    let received: Vec<_> = sink
+        .received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(received, vec![ids[0], id]);
+}
+
+#[test]
+fn reorg_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage = storage(ChangeSinkPolicy::Buffer { max_commits: 3 });
+    let sink = Arc::new(TestSink::default());
+    bonsai_storage.set_change_sink(sink.clone());
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    sink.full.store(true, Ordering::SeqCst);
+    let id0 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id0).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    // The reverted commit is dropped and the new branch is sent after the commits kept.
+    let id2 = id_builder.new_id();
+    let id3 = id_builder.new_id();
+    bonsai_storage
+        .reorg(
+            id0,
+            vec![
+                (
+                    id2,
+                    vec![(identifier.clone(), key1.clone(), Felt::from(5u32))],
+                ),
+                (
+                    id3,
+                    vec![(identifier.clone(), key2.clone(), Felt::from(6u32))],
+                ),
+            ],
+        )
+        .unwrap();
+    sink.full.store(false, Ordering::SeqCst);
+    assert_eq!(bonsai_storage.flush_change_sink(), 0);
+
+    let received = sink.received.lock().unwrap();
+    assert_eq!(
+        *received,
+        vec![
+            (
+                id0,
+                vec![LeafChange {
+                    identifier: identifier.as_slice().into(),
+                    key: key1.clone(),
+                    old_value: None,
+                    new_value: Some(Felt::from(1u32)),
+                }]
+            ),
+            (
+                id2,
+                vec![LeafChange {
+                    identifier: identifier.as_slice().into(),
+                    key: key1,
+                    old_value: Some(Felt::from(1u32)),
+                    new_value: Some(Felt::from(5u32)),
+                }]
+            ),
+            (
+                id3,
+                vec![LeafChange {
+                    identifier: identifier.as_slice().into(),
+                    key: key2,
+                    old_value: None,
+                    new_value: Some(Felt::from(6u32)),
+                }]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn merge_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage = storage(ChangeSinkPolicy::FailCommit);
+    let sink = Arc::new(TestSink::default());
+    bonsai_storage.set_change_sink(sink.clone());
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let id0 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id0).unwrap();
+
+    let mut txn = bonsai_storage
+        .get_transactional_state(id0, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.insert(&identifier, &key1, &Felt::from(2u32)).unwrap();
+    txn.insert(&identifier, &key2, &Felt::from(3u32)).unwrap();
+    let id1 = id_builder.new_id();
+    txn.transactional_commit(id1).unwrap();
+
+    // The commits of a fast-forwarded transactional state are refused like regular commits.
+    sink.full.store(true, Ordering::SeqCst);
+    let res = bonsai_storage.merge(txn);
+    assert!(matches!(
+        res,
+        Err(BonsaiStorageError::ChangeSink(
+            ChangeSinkError::Refused { .. }
+        ))
+    ));
+    sink.full.store(false, Ordering::SeqCst);
+
+    let mut txn = bonsai_storage
+        .get_transactional_state(id0, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.insert(&identifier, &key1, &Felt::from(2u32)).unwrap();
+    txn.insert(&identifier, &key2, &Felt::from(3u32)).unwrap();
+    txn.transactional_commit(id1).unwrap();
+    bonsai_storage.merge(txn).unwrap();
+
+    let mut received = sink.received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].0, id0);
+    let (id, changes) = &mut received[1];
+    assert_eq!(*id, id1);
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    assert_eq!(
+        *changes,
+        vec![
+            LeafChange {
+                identifier: identifier.as_slice().into(),
+                key: key1,
+                old_value: Some(Felt::from(1u32)),
+                new_value: Some(Felt::from(2u32)),
+            },
+            LeafChange {
+                identifier: identifier.as_slice().into(),
+                key: key2,
+                old_value: None,
+                new_value: Some(Felt::from(3u32)),
+            },
+        ]
+    );
+}
+
+#[test]
+fn raw_leaves_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage = storage(ChangeSinkPolicy::FailCommit);
+    let sink = Arc::new(TestSink::default());
+    bonsai_storage.set_change_sink(sink.clone());
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 1]);
+    bonsai_storage
+        .insert_raw(&identifier, &key, b"first", &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Only the commitment is a change, not the payload.
+    bonsai_storage
+        .insert_raw(&identifier, &key, b"second", &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage
+        .insert(&identifier, &key, &Felt::TWO)
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+
+    let received = sink.received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    assert!(received[1].1.is_empty());
+    assert_eq!(
+        received[2],
+        (
+            id2,
+            vec![LeafChange {
+                identifier: identifier.as_slice().into(),
+                key,
+                old_value: Some(Felt::ONE),
+                new_value: Some(Felt::TWO),
+            }]
+        )
+    );
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+fn storage() -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap()
+}
+
+/// Check that the leaf `key` of the trie `parent` holds the root hash of the trie `child`.
+fn assert_linked(
+    bonsai_storage: &BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>,
+    child: &[u8],
+    parent: &[u8],
+    key: &BitVec,
+) {
+    let root_hash = bonsai_storage.root_hash(child).unwrap();
+    assert_eq!(
+        bonsai_storage.get(parent, key).unwrap(),
+        Some(root_hash).filter(|root_hash| *root_hash != Felt::ZERO)
+    );
+}
+
+#[test]
+fn nested_hashmap_db() {
+    let (classes, contracts, storage_trie) = (vec![1], vec![2], vec![3]);
+    let contract_key = BitVec::from_vec(vec![0, 0, 2]);
+    let storage_key = BitVec::from_vec(vec![0, 0, 3]);
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage
+        .link_child_trie(&contracts, &classes, &contract_key)
+        .unwrap();
+    bonsai_storage
+        .link_child_trie(&storage_trie, &contracts, &storage_key)
+        .unwrap();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage
+        .insert(&storage_trie, &key, &Felt::ONE)
+        .unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    assert_linked(&bonsai_storage, &storage_trie, &contracts, &storage_key);
+    assert_linked(&bonsai_storage, &contracts, &classes, &contract_key);
+    let classes_root = bonsai_storage.root_hash(&classes).unwrap();
+
+    // Reverting the child trie reverts its parents along with it.
+    bonsai_storage
+        .insert(&storage_trie, &key, &Felt::TWO)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_linked(&bonsai_storage, &contracts, &classes, &contract_key);
+    assert_ne!(bonsai_storage.root_hash(&classes).unwrap(), classes_root);
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_linked(&bonsai_storage, &storage_trie, &contracts, &storage_key);
+    assert_eq!(bonsai_storage.root_hash(&classes).unwrap(), classes_root);
+
+    // The leaf of an empty child trie is removed.
+    bonsai_storage.clear_trie(&storage_trie).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get(&contracts, &storage_key).unwrap(), None);
+    assert_linked(&bonsai_storage, &contracts, &classes, &contract_key);
+
+    // Once unlinked, the leaf is left as is.
+    assert!(bonsai_storage.unlink_child_trie(&storage_trie));
+    assert!(!bonsai_storage.unlink_child_trie(&storage_trie));
+    bonsai_storage
+        .insert(&storage_trie, &key, &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get(&contracts, &storage_key).unwrap(), None);
+}
+
+#[test]
+fn link_hashmap_db() {
+    let (parent, child) = (vec![1], vec![2]);
+    let child_key = BitVec::from_vec(vec![0, 0, 2]);
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage.insert(&child, &key, &Felt::ONE).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Linking writes the root hash of the child trie right away.
+    bonsai_storage
+        .link_child_trie(&child, &parent, &child_key)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_linked(&bonsai_storage, &child, &parent, &child_key);
+    for (child, parent) in [(&parent, &child), (&parent, &parent)] {
+        assert!(matches!(
+            bonsai_storage.link_child_trie(child, parent, &child_key),
+            Err(BonsaiStorageError::ChildTrieCycle { .. })
+        ));
+    }
+
+    // A commit prepared before updating the child tries is stale.
+    bonsai_storage.insert(&child, &key, &Felt::TWO).unwrap();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    assert!(matches!(
+        bonsai_storage.commit_prepared(id_builder.new_id(), prepared),
+        Err(BonsaiStorageError::PreparedCommitStale)
+    ));
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    bonsai_storage
+        .commit_prepared(id_builder.new_id(), prepared)
+        .unwrap();
+    assert_linked(&bonsai_storage, &child, &parent, &child_key);
+
+    // So are the commits of a reorg and of a transactional state.
+    let id = bonsai_storage.get_latest_id().unwrap();
+    let new_id = id_builder.new_id();
+    bonsai_storage
+        .reorg(id, [(new_id, [(child.clone(), key.clone(), Felt::THREE)])])
+        .unwrap();
+    assert_linked(&bonsai_storage, &child, &parent, &child_key);
+    let mut txn = bonsai_storage
+        .get_transactional_state(new_id, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.insert(&child, &key, &Felt::ONE).unwrap();
+    txn.transactional_commit(id_builder.new_id()).unwrap();
+    bonsai_storage.merge(txn).unwrap();
+    assert_linked(&bonsai_storage, &child, &parent, &child_key);
+}
+
+#[test]
+fn revert_identifier_hashmap_db() {
+    let (contracts, storage_trie, nested) = (vec![1], vec![2], vec![3]);
+    let storage_key = BitVec::from_vec(vec![0, 0, 2]);
+    let nested_key = BitVec::from_vec(vec![0, 0, 3]);
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage
+        .link_child_trie(&storage_trie, &contracts, &storage_key)
+        .unwrap();
+    bonsai_storage
+        .link_child_trie(&nested, &storage_trie, &nested_key)
+        .unwrap();
+    bonsai_storage.insert(&nested, &key, &Felt::ONE).unwrap();
+    bonsai_storage
+        .insert(&storage_trie, &key, &Felt::ONE)
+        .unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let contracts_root = bonsai_storage.root_hash(&contracts).unwrap();
+
+    bonsai_storage.insert(&nested, &key, &Felt::TWO).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // The uncommitted changes of a descendant keep the trie from being reverted.
+    bonsai_storage.insert(&nested, &key, &Felt::THREE).unwrap();
+    assert!(matches!(
+        bonsai_storage.revert_identifier_to(&storage_trie, id1),
+        Err(BonsaiStorageError::TrieUncommittedChanges { identifier }) if identifier.as_slice() == nested.as_slice()
+    ));
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Reverting the storage trie reverts its child, and updates its leaf in its parent.
+    bonsai_storage
+        .revert_identifier_to(&storage_trie, id1)
+        .unwrap();
+    assert_eq!(bonsai_storage.get(&nested, &key).unwrap(), Some(Felt::ONE));
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_linked(&bonsai_storage, &nested, &storage_trie, &nested_key);
+    assert_eq!(
+        bonsai_storage.root_hash(&contracts).unwrap(),
+        contracts_root
+    );
+}
@@ -0,0 +1,98 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder, Id},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, PendingCommit,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn commit_hook_hashmap_db() {
+    let identifier = vec![1];
+    let other_identifier = vec![2];
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&other_identifier, &key1, &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let other_root = bonsai_storage.root_hash(&other_identifier).unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::<PendingCommit<BasicId>>::new()));
+    let expected_root = Arc::new(Mutex::new(Felt::ZERO));
+    bonsai_storage.add_commit_hook({
+        let received = received.clone();
+        move |pending| {
+            received.lock().unwrap().push(pending.clone());
+            Ok(())
+        }
+    });
+    bonsai_storage.add_commit_hook({
+        let identifier = identifier.clone();
+        let expected_root = expected_root.clone();
+        move |pending| match pending.root_hashes.get(identifier.as_slice()) {
+            Some(root) if *root != *expected_root.lock().unwrap() => {
+                Err(format!("unexpected root {:#x}", root))
+            }
+            _ => Ok(()),
+        }
+    });
+
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::TWO)
+        .unwrap();
+    let id1 = id_builder.new_id();
+    assert!(matches!(
+        bonsai_storage.commit(id1),
+        Err(BonsaiStorageError::CommitHookAborted { id, reason })
+            if id == id1.as_u64() && reason.starts_with("unexpected root")
+    ));
+    // Nothing was written and the changes are still there.
+    assert_eq!(bonsai_storage.get_latest_id(), Some(BasicId::new(0)));
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key2).unwrap(),
+        Some(Felt::TWO)
+    );
+
+    let pending = received.lock().unwrap()[0].clone();
+    assert_eq!(pending.id, id1);
+    assert_eq!(pending.changes, 2);
+    // Only the tries with uncommitted changes are given.
+    assert_eq!(pending.root_hashes.len(), 1);
+    *expected_root.lock().unwrap() = pending.root_hashes[identifier.as_slice()];
+    bonsai_storage.commit(id1).unwrap();
+    assert_eq!(bonsai_storage.get_latest_id(), Some(id1));
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        pending.root_hashes[identifier.as_slice()]
+    );
+    assert_eq!(
+        bonsai_storage.root_hash(&other_identifier).unwrap(),
+        other_root
+    );
+
+    // Prepared commits are checked as well.
+    bonsai_storage.remove(&identifier, &key1).unwrap();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    assert!(bonsai_storage
+        .commit_prepared(id_builder.new_id(), prepared)
+        .is_err());
+    bonsai_storage.clear_commit_hooks();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    bonsai_storage
+        .commit_prepared(BasicId::new(2), prepared)
+        .unwrap();
+    assert_eq!(received.lock().unwrap().len(), 3);
+    assert_eq!(bonsai_storage.get(&identifier, &key1).unwrap(), None);
+}
@@ -0,0 +1,169 @@
+#![cfg(any(feature = "zstd", feature = "lz4"))]
+use crate::{
+    databases::{CompressedDb, CompressedDbConfig, Compression, HashMapDb},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+const IDENTIFIER: &[u8] = b"id";
+
+fn stored_size(db: &HashMapDb<BasicId>, prefix: &DatabaseKey) -> usize {
+    db.get_by_prefix(prefix)
+        .unwrap()
+        .iter()
+        .map(|(_, value)| value.len())
+        .sum()
+}
+
+/// Commits the same leaves in a compressed storage and in a plain one, returns the storages.
+#[allow(clippy::type_complexity)]
+fn compare_with_plain(
+    config: CompressedDbConfig,
+    leaves: impl Fn(u32) -> (BitVec, Felt),
+    commits: u32,
+) -> (
+    BonsaiStorage<BasicId, CompressedDb<HashMapDb<BasicId>>, Pedersen>,
+    BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>,
+) {
+    let db = CompressedDb::new(HashMapDb::<BasicId>::default(), config).unwrap();
+    let mut bonsai_storage = BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut plain_storage = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    for commit in 0..commits {
+        for i in commit * 100..(commit + 1) * 100 {
+            let (key, value) = leaves(i);
+            bonsai_storage.insert(IDENTIFIER, &key, &value).unwrap();
+            plain_storage.insert(IDENTIFIER, &key, &value).unwrap();
+        }
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        plain_storage.commit(id).unwrap();
+    }
+    assert_eq!(
+        bonsai_storage.root_hash(IDENTIFIER).unwrap(),
+        plain_storage.root_hash(IDENTIFIER).unwrap()
+    );
+    assert_eq!(
+        bonsai_storage
+            .get_key_value_pairs(IDENTIFIER, None, usize::MAX)
+            .unwrap(),
+        plain_storage
+            .get_key_value_pairs(IDENTIFIER, None, usize::MAX)
+            .unwrap()
+    );
+    (bonsai_storage, plain_storage)
+}
+
+fn leaf(i: u32) -> (BitVec, Felt) {
+    let key = BitVec::from_vec(i.to_be_bytes()[1..].to_vec());
+    (key, Felt::from(i))
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_dictionary() {
+    let config = CompressedDbConfig {
+        trie: Compression::Zstd { level: 3 },
+        flat: Compression::Zstd { level: 3 },
+        trie_log: Compression::Zstd { level: 3 },
+    };
+    let (mut bonsai_storage, mut plain_storage) = compare_with_plain(config, leaf, 3);
+
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    let id = db
+        .train_dictionary(&DatabaseKey::Trie(IDENTIFIER), 1000, 4096)
+        .unwrap();
+    assert_eq!(id, Some(0));
+    assert_eq!(
+        db.train_dictionary(&DatabaseKey::Meta(&[]), 1000, 4096)
+            .unwrap(),
+        None
+    );
+
+    // The nodes written after the training use the dictionary, the others stay readable.
+    let mut id_builder = BasicIdBuilder::new();
+    id_builder.new_id();
+    id_builder.new_id();
+    id_builder.new_id();
+    for i in 300..400 {
+        let (key, value) = leaf(i);
+        bonsai_storage.insert(IDENTIFIER, &key, &value).unwrap();
+        plain_storage.insert(IDENTIFIER, &key, &value).unwrap();
+    }
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    plain_storage.commit(id).unwrap();
+    let root_hash = plain_storage.root_hash(IDENTIFIER).unwrap();
+    assert_eq!(bonsai_storage.root_hash(IDENTIFIER).unwrap(), root_hash);
+
+    let inner = bonsai_storage.tries.db_ref().db.inner();
+    let plain = &plain_storage.tries.db_ref().db;
+    let trie = DatabaseKey::Trie(IDENTIFIER);
+    assert!(stored_size(inner, &trie) < stored_size(plain, &trie));
+
+    // The dictionary is loaded from the database.
+    let db = CompressedDb::new(inner.clone(), config).unwrap();
+    let reopened: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(reopened.root_hash(IDENTIFIER).unwrap(), root_hash);
+    let (key, value) = leaf(350);
+    assert_eq!(reopened.get(IDENTIFIER, &key).unwrap(), Some(value));
+
+    // Without its dictionary, a node can't be decompressed.
+    let mut inner = inner.clone();
+    let dictionaries = DatabaseKey::Meta(&[6]);
+    inner.remove_by_prefix(&dictionaries).unwrap();
+    let db = CompressedDb::new(inner, config).unwrap();
+    assert!(db.get_by_prefix(&trie).is_err());
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn lz4() {
+    let config = CompressedDbConfig {
+        trie: Compression::Lz4,
+        flat: Compression::None,
+        trie_log: Compression::Lz4,
+    };
+    let (mut bonsai_storage, mut plain_storage) = compare_with_plain(config, leaf, 2);
+
+    let mut id_builder = BasicIdBuilder::new();
+    let id1 = id_builder.new_id();
+    bonsai_storage.revert_to(id1).unwrap();
+    plain_storage.revert_to(id1).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(IDENTIFIER).unwrap(),
+        plain_storage.root_hash(IDENTIFIER).unwrap()
+    );
+    let (key, _) = leaf(150);
+    assert_eq!(bonsai_storage.get(IDENTIFIER, &key).unwrap(), None);
+
+    let txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    let (key, value) = leaf(50);
+    assert_eq!(txn.get(IDENTIFIER, &key).unwrap(), Some(value));
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn conformance() {
+    crate::testing::run_database_suite(|| {
+        CompressedDb::new(
+            HashMapDb::<BasicId>::default(),
+            CompressedDbConfig {
+                trie: Compression::Zstd { level: 3 },
+                flat: Compression::Zstd { level: 3 },
+                trie_log: Compression::Zstd { level: 3 },
+            },
+        )
+        .unwrap()
+    });
+}
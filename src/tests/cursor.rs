@@ -0,0 +1,150 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiStorage, BonsaiStorageConfig,
+    BonsaiStorageError, PathNode, ProofNode,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::collections::BTreeMap;
+
+const IDENTIFIER: &[u8] = b"id";
+
+fn storage_with_leaves() -> (
+    BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>,
+    BTreeMap<BitVec, Felt>,
+) {
+    let mut storage = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut leaves = BTreeMap::new();
+    for _ in 0..100 {
+        let key = BitVec::from_vec(vec![rng.gen(), rng.gen(), rng.gen()]);
+        let value = Felt::from(rng.gen_range(1..1000u32));
+        storage.insert(IDENTIFIER, &key, &value).unwrap();
+        leaves.insert(key, value);
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    // The cursor sees the uncommitted changes.
+    let removed: Vec<_> = leaves.keys().step_by(7).cloned().collect();
+    for key in removed {
+        storage.remove(IDENTIFIER, &key).unwrap();
+        leaves.remove(&key);
+    }
+    for i in 0..10u8 {
+        let key = BitVec::from_vec(vec![i, 0, i]);
+        storage.insert(IDENTIFIER, &key, &Felt::ONE).unwrap();
+        leaves.insert(key, Felt::ONE);
+    }
+    (storage, leaves)
+}
+
+#[test]
+fn seek_to() {
+    let (storage, leaves) = storage_with_leaves();
+    let mut cursor = storage.cursor(IDENTIFIER);
+    for (key, value) in &leaves {
+        cursor.seek_to(key).unwrap();
+        assert_eq!(cursor.value(), Some(*value));
+        assert_eq!(cursor.path(), key.as_bitslice());
+        let nodes: Vec<_> = cursor.path_nodes().collect();
+        assert!(matches!(
+            nodes[0],
+            PathNode::Binary { height: 0 } | PathNode::Edge { height: 0, .. }
+        ));
+        if let PathNode::Edge { height, path } = nodes[nodes.len() - 1] {
+            assert_eq!(height + path.len(), 24);
+        }
+    }
+    cursor
+        .seek_to(&BitVec::from_vec(vec![0xff, 0xff, 0xff]))
+        .unwrap();
+    assert_eq!(cursor.value(), None);
+    assert!(matches!(
+        cursor.seek_to(&BitVec::from_vec(vec![0, 0, 0, 0])),
+        Err(BonsaiStorageError::KeyLength {
+            expected: 24,
+            got: 32
+        })
+    ));
+}
+
+#[test]
+fn seek_to_with_siblings() {
+    let (storage, leaves) = storage_with_leaves();
+    let keys: Vec<_> = leaves.keys().take(20).collect();
+    let proof = storage.get_multi_proof(IDENTIFIER, &keys).unwrap();
+    let children: Vec<Felt> = proof
+        .0
+        .values()
+        .flat_map(|node| match node {
+            ProofNode::Binary { left, right } => vec![*left, *right],
+            ProofNode::Edge { .. } => vec![],
+        })
+        .collect();
+
+    // The siblings are the children of the binary nodes on the paths, which are in the proof.
+    let mut cursor = storage.cursor(IDENTIFIER);
+    for key in keys {
+        let mut siblings = Vec::new();
+        cursor
+            .seek_to_with_siblings(key, |path, hash| {
+                assert_eq!(path[..path.len() - 1], key[..path.len() - 1]);
+                assert_ne!(path[path.len() - 1], key[path.len() - 1]);
+                siblings.push(hash);
+            })
+            .unwrap();
+        assert!(siblings.iter().all(|hash| children.contains(hash)));
+    }
+
+    // Seeking from scratch passes all the siblings on the path.
+    let key = leaves.keys().nth(30).unwrap();
+    let mut siblings = 0;
+    let mut cursor = storage.cursor(IDENTIFIER);
+    cursor
+        .seek_to_with_siblings(key, |_, _| siblings += 1)
+        .unwrap();
+    let binary_nodes = cursor
+        .path_nodes()
+        .filter(|node| matches!(node, PathNode::Binary { .. }))
+        .count();
+    assert_eq!(siblings, binary_nodes);
+}
+
+#[test]
+fn seek_ge() {
+    let (storage, leaves) = storage_with_leaves();
+    let mut cursor = storage.cursor(IDENTIFIER);
+    let mut rng = SmallRng::seed_from_u64(4);
+    let mut probes: Vec<BitVec> = leaves.keys().step_by(3).cloned().collect();
+    probes.extend((0..100).map(|_| BitVec::from_vec(vec![rng.gen(), rng.gen(), rng.gen()])));
+    probes.push(BitVec::from_vec(vec![0, 0, 0]));
+    probes.push(BitVec::from_vec(vec![0xff, 0xff, 0xff]));
+    for probe in probes {
+        let expected = leaves
+            .range(probe.clone()..)
+            .next()
+            .map(|(key, value)| (key.clone(), *value));
+        assert_eq!(cursor.seek_ge(&probe).unwrap(), expected);
+        if let Some((key, value)) = expected {
+            assert_eq!(cursor.path(), key.as_bitslice());
+            assert_eq!(cursor.value(), Some(value));
+        }
+    }
+    assert!(matches!(
+        cursor.seek_ge(&BitVec::from_vec(vec![0, 0])),
+        Err(BonsaiStorageError::KeyLength { .. })
+    ));
+
+    // An empty trie has no leaf.
+    assert_eq!(
+        storage
+            .cursor(b"other")
+            .seek_ge(&BitVec::from_vec(vec![0, 0, 0]))
+            .unwrap(),
+        None
+    );
+}
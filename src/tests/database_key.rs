@@ -0,0 +1,101 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig,
+    DatabaseKey, ParsedKey,
+};
+use bitvec::{order::Msb0, view::BitView};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+const IDENTIFIERS: [&[u8]; 2] = [b"id0", b"id1"];
+
+fn key(i: u8) -> BitVec {
+    [i, i.wrapping_mul(7), 3].view_bits::<Msb0>().to_bitvec()
+}
+
+#[test]
+fn keys_parse_back() {
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    for id in 0..3u8 {
+        for i in id * 5..id * 5 + 20 {
+            let identifier = IDENTIFIERS[(i % 2) as usize];
+            let value = Felt::from(u64::from(id) * 100 + u64::from(i) + 1);
+            storage.insert(identifier, &key(i), &value).unwrap();
+        }
+        storage.put_meta(b"height", &[id]);
+        storage.commit(BasicId::new(id.into())).unwrap();
+    }
+
+    let db = &storage.tries.db_ref().db;
+    let columns = [
+        DatabaseKey::Trie(&[]),
+        DatabaseKey::Flat(&[]),
+        DatabaseKey::TrieLog(&[]),
+        DatabaseKey::Meta(&[]),
+    ];
+    let (mut nodes, mut leaves, mut logs) = (0, 0, 0);
+    for column in columns {
+        for (bytes, _) in db.get_by_prefix(&column).unwrap() {
+            let key = match column {
+                DatabaseKey::Trie(_) => DatabaseKey::Trie(&bytes),
+                DatabaseKey::Flat(_) => DatabaseKey::Flat(&bytes),
+                DatabaseKey::TrieLog(_) => DatabaseKey::TrieLog(&bytes),
+                DatabaseKey::Meta(_) => DatabaseKey::Meta(&bytes),
+            };
+            let parsed = key
+                .parse(IDENTIFIERS[0].len(), 8)
+                .unwrap_or_else(|| panic!("{key:?} does not parse"));
+            match parsed {
+                ParsedKey::TrieNode { identifier, path } => {
+                    assert!(IDENTIFIERS.contains(&identifier));
+                    assert_eq!(DatabaseKey::trie_node_bytes(identifier, &path.0), bytes);
+                    nodes += 1;
+                }
+                ParsedKey::LeafCount { identifier } | ParsedKey::InlineTrie { identifier } => {
+                    assert!(IDENTIFIERS.contains(&identifier));
+                }
+                ParsedKey::Leaf { identifier, key } => {
+                    assert_eq!(DatabaseKey::leaf_bytes(identifier, &key), bytes);
+                    assert!(storage.get(identifier, &key).unwrap().is_some());
+                    leaves += 1;
+                }
+                ParsedKey::TrieLog { id, .. } => {
+                    assert!(id < &3u64.to_be_bytes()[..]);
+                    logs += 1;
+                }
+                ParsedKey::Meta { kind, key } => {
+                    if kind == 0 {
+                        assert_eq!(key, b"height");
+                        assert_eq!(DatabaseKey::user_meta_bytes(key), bytes);
+                    }
+                }
+            }
+        }
+    }
+    assert_eq!(leaves, 30);
+    assert!(nodes > leaves / 2);
+    assert!(logs > leaves);
+
+    assert_eq!(DatabaseKey::Trie(b"id").parse(3, 8), None);
+    assert_eq!(DatabaseKey::Flat(b"id0\x10\x01").parse(3, 8), None);
+    assert_eq!(DatabaseKey::TrieLog(&[0; 8]).parse(3, 8), None);
+}
+
+#[cfg(all(feature = "key_checks", debug_assertions))]
+#[test]
+#[should_panic(expected = "is a prefix of the trie identifier")]
+fn prefix_identifiers_panic() {
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    storage.insert(b"id", &key(1), &Felt::ONE).unwrap();
+    storage.insert(b"id0", &key(2), &Felt::ONE).unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+}
@@ -0,0 +1,138 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{DedupDb, HashMapDb},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, ByteVec, DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+/// Reference counts of the shared nodes of `db`.
+fn shared_nodes(db: &DedupDb<HashMapDb<BasicId>>) -> Vec<u64> {
+    db.inner()
+        .get_by_prefix(&DatabaseKey::Meta(&[10]))
+        .unwrap()
+        .into_iter()
+        .map(|(_, value)| u64::from_le_bytes(value[..8].try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn shared_nodes_hashmap_db() {
+    let identifiers: [ByteVec; 2] = [vec![1].into(), vec![2].into()];
+    let db = DedupDb::new(HashMapDb::<BasicId>::default());
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut reference: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    // Two tries with the same leaves.
+    let leaves: Vec<_> = (0..20u32)
+        .map(|i| {
+            (
+                BitVec::from_vec((i * 7919).to_be_bytes()[1..].to_vec()),
+                Felt::from(i + 1),
+            )
+        })
+        .collect();
+    for identifier in &identifiers {
+        for (key, value) in &leaves {
+            bonsai_storage.insert(identifier, key, value).unwrap();
+        }
+    }
+    for (key, value) in &leaves {
+        reference.insert(&identifiers[0], key, value).unwrap();
+    }
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    reference.commit(id1).unwrap();
+    let root_hash = reference.root_hash(&identifiers[0]).unwrap();
+    for identifier in &identifiers {
+        assert_eq!(bonsai_storage.root_hash(identifier).unwrap(), root_hash);
+    }
+
+    // Their nodes are stored once, referenced by both.
+    let nodes = reference
+        .tries
+        .db_ref()
+        .db
+        .get_by_prefix(&DatabaseKey::Trie(&identifiers[0]))
+        .unwrap();
+    // The leaf count is stored in the trie column as well.
+    let node_count = nodes.len() - 1;
+    let counts = shared_nodes(&bonsai_storage.tries.db_ref().db);
+    assert_eq!(counts, vec![2; node_count]);
+    // The nodes are read as they were written.
+    let db = &bonsai_storage.tries.db_ref().db;
+    for (key, node) in &nodes {
+        let mut key = key.clone();
+        key[0] = identifiers[1][0];
+        assert_eq!(
+            db.get(&DatabaseKey::Trie(&key)).unwrap().as_ref(),
+            Some(node)
+        );
+    }
+    assert_eq!(
+        db.get_by_prefix(&DatabaseKey::Trie(&identifiers[0]))
+            .unwrap(),
+        nodes
+    );
+
+    // Changing one of the tries only stores its new nodes.
+    bonsai_storage
+        .insert(&identifiers[1], &leaves[0].0, &Felt::from(100u32))
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+    let counts = shared_nodes(&bonsai_storage.tries.db_ref().db);
+    assert!(counts.len() > node_count);
+    assert!(counts.iter().filter(|count| **count == 2).count() < node_count);
+    assert_eq!(
+        bonsai_storage.root_hash(&identifiers[0]).unwrap(),
+        root_hash
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifiers[1], &leaves[0].0).unwrap(),
+        Some(Felt::from(100u32))
+    );
+    let txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(txn.root_hash(&identifiers[1]).unwrap(), root_hash);
+
+    // Reverting restores the references.
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(
+        shared_nodes(&bonsai_storage.tries.db_ref().db),
+        vec![2; node_count]
+    );
+    assert_eq!(
+        bonsai_storage.root_hash(&identifiers[1]).unwrap(),
+        root_hash
+    );
+
+    // The nodes are removed with their last reference.
+    for (key, _) in &leaves {
+        bonsai_storage.remove(&identifiers[0], key).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        shared_nodes(&bonsai_storage.tries.db_ref().db),
+        vec![1; node_count]
+    );
+    for (key, _) in &leaves {
+        bonsai_storage.remove(&identifiers[1], key).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert!(shared_nodes(&bonsai_storage.tries.db_ref().db).is_empty());
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(|| DedupDb::new(HashMapDb::<BasicId>::default()));
+}
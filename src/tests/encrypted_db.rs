@@ -0,0 +1,173 @@
+#![cfg(feature = "encryption")]
+use crate::{
+    databases::{EncryptedDb, EncryptedDbConfig, EncryptedDbError, HashMapDb, KeyEncryption},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+const IDENTIFIER: &[u8] = b"id";
+
+fn key_encryption() -> EncryptedDbConfig {
+    EncryptedDbConfig {
+        key_encryption: Some(KeyEncryption {
+            identifier_len: IDENTIFIER.len(),
+            id_len: 8,
+        }),
+    }
+}
+
+#[test]
+fn basics() {
+    let db = EncryptedDb::new(
+        HashMapDb::<BasicId>::default(),
+        1,
+        &[1; 32],
+        key_encryption(),
+    );
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut clear_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let id1 = id_builder.new_id();
+    let id2 = id_builder.new_id();
+    for (key, value, id) in [
+        (&key1, Felt::from(1u32), id1),
+        (&key2, Felt::from(2u32), id2),
+    ] {
+        bonsai_storage.insert(IDENTIFIER, key, &value).unwrap();
+        bonsai_storage.commit(id).unwrap();
+        clear_storage.insert(IDENTIFIER, key, &value).unwrap();
+        clear_storage.commit(id).unwrap();
+    }
+    assert_eq!(
+        bonsai_storage.root_hash(IDENTIFIER).unwrap(),
+        clear_storage.root_hash(IDENTIFIER).unwrap()
+    );
+    assert_eq!(
+        bonsai_storage
+            .get_key_value_pairs(IDENTIFIER, None, usize::MAX)
+            .unwrap(),
+        clear_storage
+            .get_key_value_pairs(IDENTIFIER, None, usize::MAX)
+            .unwrap()
+    );
+
+    // Neither the leaves nor their keys are stored in clear.
+    let inner = bonsai_storage.tries.db_ref().db.inner();
+    let mut flat_key = IDENTIFIER.to_vec();
+    flat_key.extend_from_slice(&[1, 2, 1]);
+    assert_eq!(inner.get(&DatabaseKey::Flat(&flat_key)).unwrap(), None);
+    let stored = inner.get_by_prefix(&DatabaseKey::Flat(IDENTIFIER)).unwrap();
+    assert_eq!(stored.len(), 2);
+    for (key, value) in stored {
+        assert!(key.starts_with(IDENTIFIER));
+        assert!(!key.ends_with(&[1, 2, 1]) && !key.ends_with(&[1, 2, 2]));
+        assert_ne!(value.as_slice(), Felt::from(1u32).to_bytes_be());
+    }
+
+    let txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(txn.get(IDENTIFIER, &key2).unwrap(), None);
+
+    bonsai_storage.revert_to(id1).unwrap();
+    clear_storage.revert_to(id1).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(IDENTIFIER).unwrap(),
+        clear_storage.root_hash(IDENTIFIER).unwrap()
+    );
+    assert_eq!(bonsai_storage.get(IDENTIFIER, &key2).unwrap(), None);
+}
+
+#[test]
+fn key_rotation() {
+    let db = EncryptedDb::new(
+        HashMapDb::<BasicId>::default(),
+        1,
+        &[1; 32],
+        key_encryption(),
+    );
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(IDENTIFIER, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // The data written with the first key stays readable with the second one.
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    db.add_key(2, &[2; 32]);
+    assert!(!db.remove_key(2));
+    bonsai_storage
+        .insert(IDENTIFIER, &key2, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage
+        .insert(IDENTIFIER, &key1, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root_hash = bonsai_storage.root_hash(IDENTIFIER).unwrap();
+    assert_eq!(
+        bonsai_storage.get(IDENTIFIER, &key1).unwrap(),
+        Some(Felt::from(3u32))
+    );
+    // The leaf written again only has its encryption with the second key.
+    let inner = bonsai_storage.tries.db_ref().db.inner();
+    assert_eq!(
+        inner
+            .get_by_prefix(&DatabaseKey::Flat(IDENTIFIER))
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    assert!(db.reencrypt().unwrap() > 0);
+    assert_eq!(db.reencrypt().unwrap(), 0);
+    assert!(db.remove_key(1));
+    assert_eq!(bonsai_storage.root_hash(IDENTIFIER).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.get(IDENTIFIER, &key2).unwrap(),
+        Some(Felt::from(2u32))
+    );
+
+    // Data written with a removed key can't be read.
+    let mut db = EncryptedDb::new(
+        HashMapDb::<BasicId>::default(),
+        1,
+        &[1; 32],
+        EncryptedDbConfig::default(),
+    );
+    db.insert(&DatabaseKey::Meta(b"key"), b"value", None)
+        .unwrap();
+    db.add_key(2, &[2; 32]);
+    assert!(db.remove_key(1));
+    assert!(matches!(
+        db.get(&DatabaseKey::Meta(b"key")),
+        Err(EncryptedDbError::UnknownKey(1))
+    ));
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(|| {
+        EncryptedDb::new(
+            HashMapDb::<BasicId>::default(),
+            1,
+            &[1; 32],
+            EncryptedDbConfig::default(),
+        )
+    });
+}
@@ -0,0 +1,106 @@
+#![cfg(all(feature = "std", feature = "eth"))]
+use crate::{
+    databases::HashMapDb,
+    eth::{trie_root, EMPTY_ROOT},
+    id::BasicId,
+    BonsaiStorage, BonsaiStorageConfig,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use starknet_types_core::hash::Pedersen;
+use std::collections::HashMap;
+
+const IDENTIFIER: &[u8] = b"eth";
+
+fn root(hex: &str) -> [u8; 32] {
+    let mut root = [0; 32];
+    for (i, byte) in root.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    root
+}
+
+fn storage() -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        251,
+    )
+    .unwrap()
+}
+
+// The tries of the Ethereum trie tests.
+#[test]
+fn eth_root_vectors() {
+    let vectors: [(&[(&str, &str)], &str); 3] = [
+        (
+            &[
+                ("doe", "reindeer"),
+                ("dog", "puppy"),
+                ("dogglesworth", "cat"),
+            ],
+            "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3",
+        ),
+        (
+            &[
+                ("do", "verb"),
+                ("horse", "stallion"),
+                ("doge", "coin"),
+                ("dog", "puppy"),
+            ],
+            "5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84",
+        ),
+        (
+            &[("A", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")],
+            "d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab",
+        ),
+    ];
+    assert_eq!(trie_root::<&[u8], &[u8]>([]), EMPTY_ROOT);
+    assert_eq!(storage().eth_root_hash(IDENTIFIER).unwrap(), EMPTY_ROOT);
+    for (leaves, expected) in vectors {
+        assert_eq!(trie_root(leaves.iter().copied()), root(expected));
+
+        let mut storage = storage();
+        for (key, value) in leaves {
+            storage
+                .eth_insert(IDENTIFIER, key.as_bytes(), value.as_bytes())
+                .unwrap();
+        }
+        assert_eq!(storage.eth_root_hash(IDENTIFIER).unwrap(), root(expected));
+        storage.commit(BasicId::new(0)).unwrap();
+        assert_eq!(storage.eth_root_hash(IDENTIFIER).unwrap(), root(expected));
+    }
+}
+
+#[test]
+fn eth_trie_commits() {
+    let mut storage = storage();
+    let mut rng = SmallRng::seed_from_u64(4);
+    let mut leaves = HashMap::new();
+    let mut roots = Vec::new();
+    for id in 0..4 {
+        for _ in 0..50 {
+            // Short keys, so that some are prefixes of others.
+            let key: Vec<u8> = (0..rng.gen_range(1..4)).map(|_| rng.gen()).collect();
+            let value: Vec<u8> = (0..rng.gen_range(0..80)).map(|_| rng.gen()).collect();
+            storage.eth_insert(IDENTIFIER, &key, &value).unwrap();
+            let expected = (!value.is_empty()).then(|| value.clone());
+            assert_eq!(storage.eth_get(IDENTIFIER, &key).unwrap(), expected);
+            leaves.insert(key, value);
+        }
+        // Another trie, whose leaves must not be mixed with the Ethereum ones.
+        storage.eth_insert(b"et2", &[id], b"other trie").unwrap();
+        let root = trie_root(&leaves);
+        assert_eq!(storage.eth_root_hash(IDENTIFIER).unwrap(), root);
+        storage.commit(BasicId::new(id as u64)).unwrap();
+        assert_eq!(storage.eth_root_hash(IDENTIFIER).unwrap(), root);
+        roots.push(root);
+    }
+
+    let state = storage
+        .get_transactional_state(BasicId::new(1), storage.get_config())
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.eth_root_hash(IDENTIFIER).unwrap(), roots[1]);
+    storage.revert_to(BasicId::new(2)).unwrap();
+    assert_eq!(storage.eth_root_hash(IDENTIFIER).unwrap(), roots[2]);
+}
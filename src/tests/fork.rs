@@ -0,0 +1,194 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{create_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn basics() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    // Uncommitted changes are forked as well.
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+
+    let id = id_builder.new_id();
+    let mut fork = bonsai_storage.fork();
+    assert_eq!(
+        fork.get(&identifier, &key2).unwrap(),
+        Some(Felt::from(2u32))
+    );
+    fork.insert(&identifier, &key3, &Felt::from(3u32)).unwrap();
+    fork.remove(&identifier, &key1).unwrap();
+    fork.put_meta(b"committed", b"1");
+    fork.commit(id).unwrap();
+    fork.put_meta(b"uncommitted", b"2");
+    let fork_root_hash = fork.root_hash(&identifier).unwrap();
+    assert_eq!(fork.get(&identifier, &key1).unwrap(), None);
+
+    // The commit of the fork stayed in memory.
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(Felt::from(1u32))
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &key3).unwrap(), None);
+    assert_eq!(bonsai_storage.get_meta(b"committed").unwrap(), None);
+
+    let changes = fork.into_changes().unwrap();
+    assert_eq!(changes.len(), 3);
+    bonsai_storage.merge_fork(changes).unwrap();
+    bonsai_storage.commit(id).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        fork_root_hash
+    );
+    assert!(bonsai_storage.get_meta(b"committed").unwrap().is_some());
+    assert!(bonsai_storage.get_meta(b"uncommitted").unwrap().is_some());
+}
+
+#[test]
+fn commit_and_revert_in_fork_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let keys: Vec<_> = (0..8u8).map(|i| BitVec::from_vec(vec![i, 2, i])).collect();
+    let mut root_hashes = Vec::new();
+    let mut ids = Vec::new();
+    for (i, key) in keys[..4].iter().enumerate() {
+        bonsai_storage
+            .insert(&identifier, key, &Felt::from(i as u32 + 1))
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+        root_hashes.push(bonsai_storage.root_hash(&identifier).unwrap());
+    }
+
+    let mut fork = bonsai_storage.fork();
+    for (i, key) in keys[4..].iter().enumerate() {
+        fork.insert(&identifier, key, &Felt::from(i as u32 + 10))
+            .unwrap();
+        fork.remove(&identifier, &keys[i]).unwrap();
+        let id = id_builder.new_id();
+        fork.commit(id).unwrap();
+        ids.push(id);
+        root_hashes.push(fork.root_hash(&identifier).unwrap());
+    }
+
+    // Revert to a commit of the fork, then to one made before the fork, whose trie logs are
+    // read from the forked storage.
+    fork.revert_to(ids[5]).unwrap();
+    assert_eq!(fork.root_hash(&identifier).unwrap(), root_hashes[5]);
+    assert_eq!(fork.get(&identifier, &keys[6]).unwrap(), None);
+    assert_eq!(
+        fork.get(&identifier, &keys[5]).unwrap(),
+        Some(Felt::from(11u32))
+    );
+    fork.revert_to(ids[1]).unwrap();
+    assert_eq!(fork.root_hash(&identifier).unwrap(), root_hashes[1]);
+    assert_eq!(
+        fork.get(&identifier, &keys[0]).unwrap(),
+        Some(Felt::from(1u32))
+    );
+    assert_eq!(fork.get(&identifier, &keys[2]).unwrap(), None);
+
+    // The forked storage is untouched.
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        root_hashes[3]
+    );
+
+    // Merging the fork brings the storage to the state the fork was reverted to.
+    let changes = fork.into_changes().unwrap();
+    bonsai_storage.merge_fork(changes).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        root_hashes[1]
+    );
+}
+
+#[test]
+fn parallel_forks_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    for i in 0..10u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Evaluate two candidates, only keep the second one.
+    let id = id_builder.new_id();
+    let candidate = |value: u32| {
+        let mut fork = bonsai_storage.fork();
+        fork.insert(
+            &identifier,
+            &BitVec::from_vec(vec![1, 2, 0]),
+            &Felt::from(value),
+        )
+        .unwrap();
+        fork.commit(id).unwrap();
+        (fork.root_hash(&identifier).unwrap(), fork)
+    };
+    let (root_hash1, (root_hash2, changes)) = std::thread::scope(|s| {
+        let handle1 = s.spawn(|| {
+            let (root_hash, fork) = candidate(100);
+            fork.discard();
+            root_hash
+        });
+        let handle2 = s.spawn(|| {
+            let (root_hash, fork) = candidate(200);
+            (root_hash, fork.into_changes().unwrap())
+        });
+        (handle1.join().unwrap(), handle2.join().unwrap())
+    });
+    assert_ne!(root_hash1, root_hash2);
+
+    bonsai_storage.merge_fork(changes).unwrap();
+    bonsai_storage.commit(id).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash2);
+    assert_eq!(
+        bonsai_storage
+            .get(&identifier, &BitVec::from_vec(vec![1, 2, 0]))
+            .unwrap(),
+        Some(Felt::from(200u32))
+    );
+}
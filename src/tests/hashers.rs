@@ -0,0 +1,91 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiStorage, BonsaiStorageConfig, MerkleTree,
+};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// Root hash of the trie of height 8 holding `leaves`, committed through a storage.
+fn committed_root<H: StarkHash + Send + Sync>(leaves: &[(u8, u64)]) -> Felt {
+    let mut storage: BonsaiStorage<BasicId, _, H> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        8,
+    )
+    .unwrap();
+    let mut keys = Vec::new();
+    for (key, value) in leaves {
+        let key = BitVec::from_vec(vec![*key]);
+        storage.insert(&[], &key, &Felt::from(*value)).unwrap();
+        keys.push((key, Felt::from(*value)));
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    let root = storage.root_hash(&[]).unwrap();
+
+    keys.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let sorted: Vec<_> = keys.iter().map(|(key, value)| (&key[..], *value)).collect();
+    assert_eq!(MerkleTree::<H>::root_from_sorted_leaves(&sorted), root);
+    let proof = storage
+        .get_multi_proof(&[], keys.iter().map(|(key, _)| key))
+        .unwrap();
+    let proven: Vec<Felt> = proof
+        .verify_proof::<H>(root, keys.iter().map(|(key, _)| key), 8)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        proven,
+        keys.iter().map(|(_, value)| *value).collect::<Vec<_>>()
+    );
+    root
+}
+
+// The expected hashes were computed with an independent implementation of the node hashing.
+const SINGLE_LEAF: &[(u8, u64)] = &[(0b1010_1010, 1)];
+const THREE_LEAVES: &[(u8, u64)] = &[
+    (0b0000_0011, 0x22),
+    (0b1111_0000, 0x33),
+    (0b0000_0001, 0x11),
+];
+
+#[cfg(feature = "keccak")]
+#[test]
+fn keccak256_root_vectors() {
+    use crate::hashers::Keccak256Hasher;
+
+    assert_eq!(
+        Keccak256Hasher::hash(&Felt::ONE, &Felt::TWO),
+        Felt::from_hex("0x10b7bceb6e7df5418fb78d8ee546e97c83a08bbccc01a0644d599ccd2a7c2e0")
+            .unwrap()
+    );
+    assert_eq!(
+        committed_root::<Keccak256Hasher>(SINGLE_LEAF),
+        Felt::from_hex("0x28a97a57f6e98e528d06cfd912b1558885d800ffb32673ff157763eb04eca18")
+            .unwrap()
+    );
+    assert_eq!(
+        committed_root::<Keccak256Hasher>(THREE_LEAVES),
+        Felt::from_hex("0x264c0046e390763b59269ac40aff3290e12aa9ba962edbcbd8bbbbe2d1c4aa5")
+            .unwrap()
+    );
+}
+
+#[cfg(feature = "sha256")]
+#[test]
+fn sha256_root_vectors() {
+    use crate::hashers::Sha256Hasher;
+
+    assert_eq!(
+        Sha256Hasher::hash(&Felt::ONE, &Felt::TWO),
+        Felt::from_hex("0x2ba9329f8932c12192b37849f772104d20048f76434a3290512d9d814e4116f")
+            .unwrap()
+    );
+    assert_eq!(
+        committed_root::<Sha256Hasher>(SINGLE_LEAF),
+        Felt::from_hex("0x2df25a5634cd735a0d66a15e2216b5d31254447bab9524a7232aef3bdde2e02")
+            .unwrap()
+    );
+    assert_eq!(
+        committed_root::<Sha256Hasher>(THREE_LEAVES),
+        Felt::from_hex("0x281bf078317b5107f1d6ef2775e2114319442a0984983944372e17ad4ec09e4")
+            .unwrap()
+    );
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig,
+    DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn key_kinds_are_separate() {
+    let mut db = HashMapDb::<BasicId>::default();
+    let key = b"key".as_slice();
+    let kinds = [
+        DatabaseKey::Trie(key),
+        DatabaseKey::Flat(key),
+        DatabaseKey::TrieLog(key),
+        DatabaseKey::Meta(key),
+    ];
+    for (i, kind) in kinds.iter().enumerate() {
+        db.insert(kind, &[i as u8], None).unwrap();
+    }
+    for (i, kind) in kinds.iter().enumerate() {
+        assert_eq!(db.get(kind).unwrap().unwrap().as_slice(), &[i as u8]);
+        let prefix = match kind {
+            DatabaseKey::Trie(_) => DatabaseKey::Trie(b"k"),
+            DatabaseKey::Flat(_) => DatabaseKey::Flat(b"k"),
+            DatabaseKey::TrieLog(_) => DatabaseKey::TrieLog(b"k"),
+            DatabaseKey::Meta(_) => DatabaseKey::Meta(b"k"),
+        };
+        assert_eq!(
+            db.get_by_prefix(&prefix).unwrap(),
+            [(key.into(), [i as u8].as_slice().into())]
+        );
+    }
+
+    db.remove_by_prefix(&DatabaseKey::Flat(b"k")).unwrap();
+    assert_eq!(db.get(&DatabaseKey::Flat(key)).unwrap(), None);
+    assert!(db.contains(&DatabaseKey::Trie(key)).unwrap());
+}
+
+#[test]
+fn keys_are_only_leaves() {
+    let identifier = b"id".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        8,
+    )
+    .unwrap();
+    for key in [0b0000_0001u8, 0b0000_0010, 0b1000_0000] {
+        storage
+            .insert(identifier, &BitVec::from_vec(vec![key]), &Felt::ONE)
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    assert_eq!(
+        storage.get_keys(identifier, None, usize::MAX).unwrap(),
+        [[0b0000_0001], [0b0000_0010], [0b1000_0000]]
+    );
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(HashMapDb::<BasicId>::default);
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig,
+    DatabaseKey, ParsedKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+const IDENTIFIER: &[u8] = b"id";
+
+type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+
+fn storage(max_inline_leaves: Option<usize>) -> Storage {
+    let config = BonsaiStorageConfig {
+        max_inline_leaves,
+        ..Default::default()
+    };
+    BonsaiStorage::new(HashMapDb::default(), config, 24).unwrap()
+}
+
+fn key(i: u8) -> BitVec {
+    BitVec::from_vec(vec![i, i.wrapping_mul(7), 3])
+}
+
+/// Number of trie nodes and of inline trie records of `IDENTIFIER` in the database.
+fn stored(storage: &Storage) -> (usize, usize) {
+    let (mut nodes, mut records) = (0, 0);
+    let db = &storage.tries.db_ref().db;
+    for (bytes, _) in db.get_by_prefix(&DatabaseKey::Trie(&[])).unwrap() {
+        match DatabaseKey::Trie(&bytes).parse(IDENTIFIER.len(), 8) {
+            Some(ParsedKey::TrieNode { .. }) => nodes += 1,
+            Some(ParsedKey::InlineTrie { identifier }) => {
+                assert_eq!(identifier, IDENTIFIER);
+                records += 1;
+            }
+            _ => {}
+        }
+    }
+    (nodes, records)
+}
+
+/// Apply the same changes to both storages, and check that they see the same trie.
+fn commit(storages: &mut [Storage; 2], id: u64, changes: &[(u8, u64)]) {
+    for storage in storages.iter_mut() {
+        for (i, value) in changes {
+            storage
+                .insert(IDENTIFIER, &key(*i), &Felt::from(*value))
+                .unwrap();
+        }
+        storage.commit(BasicId::new(id)).unwrap();
+    }
+    check_same(storages);
+}
+
+fn check_same([nodes, inline]: &[Storage; 2]) {
+    assert_eq!(
+        inline.root_hash(IDENTIFIER).unwrap(),
+        nodes.root_hash(IDENTIFIER).unwrap()
+    );
+    assert_eq!(
+        inline.get_keys(IDENTIFIER, None, usize::MAX).unwrap(),
+        nodes.get_keys(IDENTIFIER, None, usize::MAX).unwrap()
+    );
+    let keys = [key(1), key(2), key(9)];
+    assert_eq!(
+        inline.get_multi_proof(IDENTIFIER, &keys).unwrap().0,
+        nodes.get_multi_proof(IDENTIFIER, &keys).unwrap().0
+    );
+    assert_eq!(
+        inline.iter_nodes(IDENTIFIER).unwrap().collect::<Vec<_>>(),
+        nodes.iter_nodes(IDENTIFIER).unwrap().collect::<Vec<_>>()
+    );
+    for i in 0..10 {
+        assert_eq!(
+            inline.get(IDENTIFIER, &key(i)).unwrap(),
+            nodes.get(IDENTIFIER, &key(i)).unwrap()
+        );
+    }
+}
+
+#[test]
+fn small_tries_are_inlined() {
+    let mut storages = [storage(None), storage(Some(4))];
+    commit(&mut storages, 0, &[(1, 10), (2, 20), (3, 30)]);
+    assert_eq!(stored(&storages[1]), (0, 1));
+    commit(&mut storages, 1, &[(2, 0), (4, 40)]);
+    assert_eq!(stored(&storages[1]), (0, 1));
+
+    // The uncommitted changes of an inline trie are hashed as the ones of the other tries.
+    for storage in storages.iter_mut() {
+        storage
+            .insert(IDENTIFIER, &key(5), &Felt::from(50u64))
+            .unwrap();
+    }
+    check_same(&storages);
+    for storage in storages.iter_mut() {
+        storage.commit(BasicId::new(2)).unwrap();
+    }
+    assert_eq!(stored(&storages[1]), (0, 1));
+
+    // Growing past the limit stores the nodes, and the trie stays stored as nodes afterwards.
+    commit(&mut storages, 3, &[(6, 60)]);
+    assert_eq!(stored(&storages[1]), stored(&storages[0]));
+    commit(&mut storages, 4, &[(1, 0), (3, 0), (4, 0)]);
+    assert_eq!(stored(&storages[1]), stored(&storages[0]));
+
+    for id in 0..5 {
+        assert_eq!(
+            storages[1]
+                .root_hash_at(IDENTIFIER, BasicId::new(id))
+                .unwrap(),
+            storages[0]
+                .root_hash_at(IDENTIFIER, BasicId::new(id))
+                .unwrap()
+        );
+    }
+    assert_eq!(
+        storages[1]
+            .diff(IDENTIFIER, BasicId::new(0), BasicId::new(3))
+            .unwrap(),
+        storages[0]
+            .diff(IDENTIFIER, BasicId::new(0), BasicId::new(3))
+            .unwrap()
+    );
+
+    // Reverting brings the record back.
+    for storage in storages.iter_mut() {
+        storage.revert_to(BasicId::new(1)).unwrap();
+    }
+    check_same(&storages);
+    assert_eq!(stored(&storages[1]), (0, 1));
+
+    // Removing all the leaves removes the record.
+    commit(&mut storages, 2, &[(1, 0), (3, 0), (4, 0)]);
+    assert_eq!(stored(&storages[1]), (0, 0));
+    assert_eq!(storages[1].root_hash(IDENTIFIER).unwrap(), Felt::ZERO);
+}
+
+#[test]
+fn large_new_tries_are_stored_as_nodes() {
+    let mut storages = [storage(None), storage(Some(4))];
+    let changes: Vec<_> = (0..10).map(|i| (i, i as u64 + 1)).collect();
+    commit(&mut storages, 0, &changes);
+    assert_eq!(stored(&storages[1]), stored(&storages[0]));
+}
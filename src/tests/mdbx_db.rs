@@ -0,0 +1,71 @@
+#![cfg(feature = "mdbx")]
+use crate::{
+    databases::{open_mdbx_db, MdbxDb, MdbxDbConfig},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn conformance() {
+    let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+    let dbs: Vec<_> = dirs
+        .iter()
+        .map(|dir| open_mdbx_db(dir.path()).unwrap())
+        .collect();
+    let mut dbs = dbs.iter();
+    crate::testing::run_database_suite(|| {
+        MdbxDb::<BasicId>::new(dbs.next().unwrap(), MdbxDbConfig::default())
+    });
+}
+
+#[test]
+fn commit_revert_and_reopen() {
+    let identifier = b"id";
+    let dir = tempfile::tempdir().unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut root_hashes = Vec::new();
+    {
+        let db = open_mdbx_db(dir.path()).unwrap();
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+            MdbxDb::new(&db, MdbxDbConfig::default()),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        for i in 0..3u64 {
+            for j in 0..10u64 {
+                let key = BitVec::from_vec(vec![i as u8, j as u8, 0]);
+                bonsai_storage
+                    .insert(identifier, &key, &Felt::from(i * 100 + j + 1))
+                    .unwrap();
+            }
+            let id = id_builder.new_id();
+            bonsai_storage.commit(id).unwrap();
+            root_hashes.push((id, bonsai_storage.root_hash(identifier).unwrap()));
+        }
+
+        let (id, root_hash) = root_hashes[1];
+        let txn = bonsai_storage
+            .get_transactional_state(id, BonsaiStorageConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(txn.root_hash(identifier).unwrap(), root_hash);
+
+        bonsai_storage.revert_to(id).unwrap();
+        assert_eq!(bonsai_storage.root_hash(identifier).unwrap(), root_hash);
+    }
+
+    // The state is read back from the file.
+    let db = open_mdbx_db(dir.path()).unwrap();
+    let bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        MdbxDb::<BasicId>::new(&db, MdbxDbConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(identifier).unwrap(),
+        root_hashes[1].1
+    );
+}
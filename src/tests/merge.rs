@@ -4,7 +4,7 @@
 use crate::{
     databases::{create_rocks_db, RocksDB, RocksDBConfig, RocksDBTransaction},
     id::{BasicId, BasicIdBuilder},
-    BitVec, BonsaiStorage, BonsaiStorageConfig,
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, ByteVec,
 };
 use once_cell::sync::Lazy;
 use rocksdb::OptimisticTransactionDB;
@@ -61,7 +61,7 @@ fn init_test<'a>(
 
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(db, RocksDBConfig::default()), config, 24).unwrap();
 
     let mut id_builder = BasicIdBuilder::new();
 
@@ -261,10 +261,10 @@ fn merge_conflict_commited_vs_commited() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -289,10 +289,10 @@ fn merge_conflict_commited_vs_commited_change_order() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -319,10 +319,10 @@ fn merge_conflict_commited_vs_commited_and_noncommited() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -349,10 +349,10 @@ fn merge_conflict_commited_and_noncommited_vs_commited() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -377,10 +377,10 @@ fn merge_conflict_commited_and_noncommited_vs_noncommited() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -402,10 +402,10 @@ fn merge_conflict_commited_vs_noncommited() {
 
     match bonsai_storage.merge(bonsai_at_txn) {
         Ok(_) => panic!("Expected merge conflict error"),
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -518,10 +518,10 @@ fn merge_conflict_commited_noncommited_vs_commited_noncommited() {
         Ok(_) => {
             panic!("Expected merge conflict error")
         }
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(keys, [(ByteVec::new(), PAIR2.0.clone())])
+        }
+        Err(err) => panic!("Expected merge conflict error, got {err}"),
     }
 }
 
@@ -542,13 +542,54 @@ fn merge_nonconflict_commited_vs_commited() {
         .transactional_commit(id_builder.new_id())
         .unwrap();
 
-    match bonsai_storage.merge(bonsai_at_txn) {
-        Ok(_) => {
-            panic!("Expected merge conflict error")
-        }
-        Err(err) => assert_eq!(
-            err.to_string(),
-            "Merge error: Transaction created_at BasicId(0) is lower than the last recorded id"
-        ),
+    bonsai_storage.merge(bonsai_at_txn).unwrap();
+
+    assert_eq!(
+        bonsai_storage.get(&identifier, &PAIR2.0).unwrap(),
+        Some(PAIR2.1)
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &PAIR3.0).unwrap(),
+        Some(PAIR3.1)
+    );
+}
+
+#[test]
+fn merge_structural_same_root_hash() {
+    let db = create_rocks_db(tempfile::tempdir().unwrap().path()).unwrap();
+    let (identifier, mut bonsai_storage, mut bonsai_at_txn, mut id_builder, _) = init_test(&db);
+
+    let reference_db = create_rocks_db(tempfile::tempdir().unwrap().path()).unwrap();
+    let (_, mut reference, _, _, _) = init_test(&reference_db);
+
+    let txn_id = id_builder.new_id();
+    for i in 0..100u8 {
+        let key = BitVec::from_vec(vec![i, 1, 2]);
+        let value = Felt::from(i as u64 + 1);
+        bonsai_at_txn.insert(&identifier, &key, &value).unwrap();
+        reference.insert(&identifier, &key, &value).unwrap();
     }
+    bonsai_at_txn.transactional_commit(txn_id).unwrap();
+    reference.commit(txn_id).unwrap();
+
+    bonsai_at_txn.remove(&identifier, &PAIR1.0).unwrap();
+    reference.remove(&identifier, &PAIR1.0).unwrap();
+
+    bonsai_storage.merge(bonsai_at_txn).unwrap();
+
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    reference.commit(id).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        reference.root_hash(&identifier).unwrap()
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &PAIR1.0).unwrap(), None);
+
+    // the commit of the transactional state is part of the history
+    bonsai_storage.revert_to(txn_id).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &PAIR1.0).unwrap(),
+        Some(PAIR1.1)
+    );
 }
@@ -17,7 +17,8 @@ fn test_key_retrieval() {
     let rocksdb = create_rocks_db(tempdir.path()).unwrap();
     let db = RocksDB::new(&rocksdb, RocksDBConfig::default());
     let mut bonsai =
-        BonsaiStorage::<BasicId, _, Pedersen>::new(db, BonsaiStorageConfig::default(), 251);
+        BonsaiStorage::<BasicId, _, Pedersen>::new(db, BonsaiStorageConfig::default(), 251)
+            .unwrap();
 
     let block_0 = vec![
         (
@@ -393,7 +394,7 @@ fn test_key_retrieval() {
             Felt::from_bytes_be_slice(contract_address)
         );
 
-        let keys = bonsai.get_keys(contract_address).unwrap();
+        let keys = bonsai.get_keys(contract_address, None, usize::MAX).unwrap();
         log::debug!("{keys:?}");
         for k in keys {
             // if all has gone well, the db should contain the first 251 bits of the key,
@@ -412,7 +413,9 @@ fn test_key_retrieval() {
             Felt::from_bytes_be_slice(contract_address)
         );
 
-        let kv = bonsai.get_key_value_pairs(contract_address).unwrap();
+        let kv = bonsai
+            .get_key_value_pairs(contract_address, None, usize::MAX)
+            .unwrap();
         log::debug!("{kv:?}");
         for (k, v) in kv {
             let k = Felt::from_bytes_be_slice(&k);
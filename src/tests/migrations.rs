@@ -0,0 +1,115 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    migrations::{migrate, schema_version, MigrationOptions, MigrationProgress, SCHEMA_VERSION},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, DatabaseKey,
+};
+use parity_scale_codec::Encode;
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+/// Database of a trie with two leaves, written as the versions without a schema version did.
+fn legacy_db() -> (HashMapDb<BasicId>, Felt, usize) {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 1]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 2]), &Felt::TWO)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root_hash = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let mut db = bonsai_storage.tries.db_ref().db.clone();
+    db.remove_by_prefix(&DatabaseKey::Meta(&[7])).unwrap();
+    let nodes = db.get_by_prefix(&DatabaseKey::Trie(&[])).unwrap();
+    let mut untagged = 0;
+    for (key, node) in nodes {
+        // Leave the leaf count as it is.
+        if node.len() != 8 {
+            db.insert(&DatabaseKey::Trie(&key), &node[1..], None)
+                .unwrap();
+            untagged += 1;
+        }
+    }
+    (db, root_hash, untagged)
+}
+
+#[test]
+fn dry_run_hashmap_db() {
+    let (mut db, _, untagged) = legacy_db();
+    assert_eq!(schema_version(&db).unwrap(), 0);
+    let before = db.get_by_prefix(&DatabaseKey::Trie(&[])).unwrap();
+
+    let mut progress = Vec::new();
+    let report = migrate(
+        &mut db,
+        MigrationOptions {
+            dry_run: true,
+            progress: Some(&mut |step| progress.push(step)),
+        },
+    )
+    .unwrap();
+    assert_eq!((report.from, report.to), (0, SCHEMA_VERSION));
+    assert_eq!(report.steps.len(), 2);
+    assert_eq!(report.steps[0].rewritten, untagged as u64);
+    assert_eq!(report.steps[1].rewritten, 0);
+    // The nodes are gone through in chunks of the keys sharing their first byte.
+    assert_eq!(progress.len(), 256);
+    assert_eq!(
+        progress.last(),
+        Some(&MigrationProgress {
+            version: 1,
+            done: 256,
+            total: 256
+        })
+    );
+
+    // Nothing was written.
+    assert_eq!(schema_version(&db).unwrap(), 0);
+    assert_eq!(db.get_by_prefix(&DatabaseKey::Trie(&[])).unwrap(), before);
+}
+
+#[test]
+fn migrate_on_open_hashmap_db() {
+    let identifier = vec![];
+    let (db, root_hash, untagged) = legacy_db();
+    let bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage
+            .get(&identifier, &BitVec::from_vec(vec![1, 2, 2]))
+            .unwrap(),
+        Some(Felt::TWO)
+    );
+
+    // The nodes are tagged with the version of their encoding.
+    let mut db = bonsai_storage.tries.db_ref().db.clone();
+    assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+    let nodes = db.get_by_prefix(&DatabaseKey::Trie(&[])).unwrap();
+    let tagged = nodes.iter().filter(|(_, node)| node[0] == 0x81).count();
+    assert_eq!(tagged, untagged);
+    let report = migrate(&mut db, MigrationOptions::default()).unwrap();
+    assert!(report.steps.is_empty());
+
+    // Databases written by newer versions are refused.
+    db.insert(
+        &DatabaseKey::Meta(&[7]),
+        &(SCHEMA_VERSION + 1).encode(),
+        None,
+    )
+    .unwrap();
+    assert!(matches!(
+        BonsaiStorage::<BasicId, _, Pedersen>::new(db, BonsaiStorageConfig::default(), 24),
+        Err(BonsaiStorageError::UnsupportedSchemaVersion { version, supported })
+            if version == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+    ));
+}
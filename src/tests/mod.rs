@@ -1,7 +1,35 @@
+mod audit;
+mod change_sink;
+mod child_tries;
+mod commit_hook;
+mod compressed_db;
+mod cursor;
+mod database_key;
+mod dedup_db;
+mod encrypted_db;
+mod eth;
+mod fork;
+mod hashers;
+mod hashmap_db;
+mod inline_tries;
 mod madara_comparison;
-// mod merge;
+mod mdbx_db;
+mod merge;
 mod merkle_tree;
+mod migrations;
+mod object_store_db;
+mod overlay_db;
+mod pending;
 mod proptest;
+mod redb_db;
+mod remote_db;
+mod rocks_db;
+mod shared;
 mod simple;
-// mod transactional_state;
+mod starknet;
+mod storage_builder;
+mod tiered;
+mod transactional_state;
 mod trie_log;
+mod watch;
+mod witness_db;
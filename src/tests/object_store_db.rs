@@ -0,0 +1,152 @@
+#![cfg(feature = "object_store")]
+use std::sync::Arc;
+
+use crate::{
+    databases::{HashMapDb, ObjectStoreDb, ObjectStoreDbConfig, TieredDatabase},
+    id::{BasicId, BasicIdBuilder, Id},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, DatabaseKey,
+};
+use object_store::{memory::InMemory, path::Path, ObjectStore};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use tokio::runtime::{Builder, Runtime};
+
+fn object_count(runtime: &Runtime, store: &InMemory, column: &str) -> usize {
+    let path = Path::from("archive").child(column);
+    runtime
+        .block_on(store.list_with_delimiter(Some(&path)))
+        .unwrap()
+        .objects
+        .len()
+}
+
+#[test]
+fn conformance() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    crate::testing::run_database_suite(|| {
+        ObjectStoreDb::<BasicId>::new(
+            Arc::new(InMemory::new()),
+            Path::from("archive"),
+            runtime.handle().clone(),
+            ObjectStoreDbConfig::default(),
+        )
+    });
+}
+
+#[test]
+fn write_back_cache() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let store = Arc::new(InMemory::new());
+    let config = ObjectStoreDbConfig {
+        max_pending_writes: None,
+        ..Default::default()
+    };
+    let mut db = ObjectStoreDb::<BasicId>::new(
+        store.clone(),
+        Path::from("archive"),
+        runtime.handle().clone(),
+        config,
+    );
+
+    let mut batch = db.create_batch();
+    db.insert(&DatabaseKey::TrieLog(&[0, 1]), &[1], Some(&mut batch))
+        .unwrap();
+    db.insert(&DatabaseKey::TrieLog(&[0, 2]), &[2], Some(&mut batch))
+        .unwrap();
+    db.insert(&DatabaseKey::TrieLog(&[1, 1]), &[3], Some(&mut batch))
+        .unwrap();
+    db.write_batch(batch).unwrap();
+
+    // The writes are readable before they are uploaded.
+    assert_eq!(db.pending_writes(), 3);
+    assert_eq!(object_count(&runtime, &store, "trie_log"), 0);
+    assert_eq!(
+        db.get(&DatabaseKey::TrieLog(&[0, 2])).unwrap(),
+        Some([2].as_slice().into())
+    );
+
+    db.flush().unwrap();
+    assert_eq!(db.pending_writes(), 0);
+    assert_eq!(object_count(&runtime, &store, "trie_log"), 3);
+
+    db.remove(&DatabaseKey::TrieLog(&[0, 1]), None).unwrap();
+    assert_eq!(
+        db.get_by_prefix(&DatabaseKey::TrieLog(&[0])).unwrap(),
+        vec![([0, 2].as_slice().into(), [2].as_slice().into())]
+    );
+    db.flush().unwrap();
+
+    // A new database reads the uploaded objects.
+    let db = ObjectStoreDb::<BasicId>::new(
+        store.clone(),
+        Path::from("archive"),
+        runtime.handle().clone(),
+        ObjectStoreDbConfig::default(),
+    );
+    assert_eq!(db.get(&DatabaseKey::TrieLog(&[0, 1])).unwrap(), None);
+    assert_eq!(
+        db.get_by_prefix(&DatabaseKey::TrieLog(&[])).unwrap(),
+        vec![
+            ([0, 2].as_slice().into(), [2].as_slice().into()),
+            ([1, 1].as_slice().into(), [3].as_slice().into()),
+        ]
+    );
+    assert!(!db.contains(&DatabaseKey::Trie(&[0, 2])).unwrap());
+}
+
+#[test]
+fn cold_tier() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let store = Arc::new(InMemory::new());
+    let config = ObjectStoreDbConfig {
+        max_pending_writes: Some(1),
+        ..Default::default()
+    };
+    let cold = ObjectStoreDb::new(
+        store.clone(),
+        Path::from("archive"),
+        runtime.handle().clone(),
+        config,
+    );
+    let db = TieredDatabase::new(HashMapDb::<BasicId>::default(), cold);
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let identifier = vec![];
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+
+    assert_ne!(object_count(&runtime, &store, "trie_log"), 0);
+    assert_eq!(object_count(&runtime, &store, "trie"), 0);
+
+    let txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(txn.root_hash(&identifier).unwrap(), root_hash1);
+    assert_eq!(txn.get(&identifier, &key2).unwrap(), None);
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash1);
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), None);
+    // The trie log of the reverted commit was removed from the object store.
+    assert!(bonsai_storage
+        .tries
+        .db_ref()
+        .db
+        .cold()
+        .get_by_prefix(&DatabaseKey::TrieLog(&id2.to_bytes()))
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::{HashMapDb, OverlayDb},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorage, BonsaiStorageConfig,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+fn storage<DB>(db: DB) -> BonsaiStorage<BasicId, DB, Pedersen>
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap()
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(|| OverlayDb::new(HashMapDb::<BasicId>::default()));
+}
+
+#[test]
+fn freeze_writes_commits_to_base() {
+    let identifier = b"id";
+    let mut id_builder = BasicIdBuilder::new();
+    let mut bonsai_storage = storage(OverlayDb::new(HashMapDb::<BasicId>::default()));
+    for i in 0..3u64 {
+        for j in 0..10u64 {
+            let key = BitVec::from_vec(vec![i as u8, j as u8, 0]);
+            bonsai_storage
+                .insert(identifier, &key, &Felt::from(i * 100 + j + 1))
+                .unwrap();
+        }
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+    }
+    let root_hash = bonsai_storage.root_hash(identifier).unwrap();
+
+    let overlay = bonsai_storage.into_db();
+    overlay.base().assert_empty();
+    let (mut base, batch) = overlay.freeze().unwrap();
+    base.assert_empty();
+    base.write_batch(batch).unwrap();
+
+    let bonsai_storage = storage(base);
+    assert_eq!(bonsai_storage.root_hash(identifier).unwrap(), root_hash);
+    let key = BitVec::from_vec(vec![2, 9, 0]);
+    assert_eq!(
+        bonsai_storage.get(identifier, &key).unwrap(),
+        Some(Felt::from(210u64))
+    );
+}
+
+#[test]
+fn ephemeral_fork() {
+    let identifier = b"id";
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let mut bonsai_storage = storage(OverlayDb::new(HashMapDb::<BasicId>::default()));
+    bonsai_storage
+        .insert(identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(BasicId::new(0)).unwrap();
+    let root_hash = bonsai_storage.root_hash(identifier).unwrap();
+    let (mut base, batch) = bonsai_storage.into_db().freeze().unwrap();
+    base.write_batch(batch).unwrap();
+
+    // The fork changes and commits the state of the base, in memory.
+    let mut fork = storage(OverlayDb::new(base));
+    fork.remove(identifier, &key1).unwrap();
+    fork.insert(identifier, &key2, &Felt::from(2u32)).unwrap();
+    fork.commit(BasicId::new(1)).unwrap();
+    assert_eq!(fork.get(identifier, &key1).unwrap(), None);
+    assert_ne!(fork.root_hash(identifier).unwrap(), root_hash);
+
+    let bonsai_storage = storage(fork.into_db().into_base());
+    assert_eq!(bonsai_storage.root_hash(identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.get(identifier, &key1).unwrap(),
+        Some(Felt::from(1u32))
+    );
+    assert_eq!(bonsai_storage.get(identifier, &key2).unwrap(), None);
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiStorage, BonsaiStorageConfig,
+    BonsaiStorageError,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+
+fn new_storage() -> Storage {
+    BonsaiStorage::new(HashMapDb::default(), BonsaiStorageConfig::default(), 24).unwrap()
+}
+
+fn key(i: u8) -> BitVec {
+    BitVec::from_vec(vec![i, 0, 1])
+}
+
+#[test]
+fn basics() {
+    let (a, b) = (b"a".as_slice(), b"b".as_slice());
+    let mut storage = new_storage();
+    storage.insert(a, &key(1), &Felt::ONE).unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+    let committed_root = storage.root_hash(a).unwrap();
+
+    // The same blocks committed directly.
+    let mut reference = new_storage();
+    reference.insert(a, &key(1), &Felt::ONE).unwrap();
+    reference.commit(BasicId::new(0)).unwrap();
+    reference.insert(a, &key(2), &Felt::TWO).unwrap();
+    reference.remove(a, &key(1)).unwrap();
+    reference.insert(b, &key(3), &Felt::THREE).unwrap();
+    reference.commit(BasicId::new(1)).unwrap();
+
+    let mut pending = storage.pending_state().unwrap();
+    pending.apply_pending([(a, key(2), Felt::TWO)]).unwrap();
+    pending
+        .apply_pending([(a, key(1), Felt::ZERO), (b, key(3), Felt::THREE)])
+        .unwrap();
+    assert_eq!(pending.get(a, &key(1)).unwrap(), None);
+    assert_eq!(pending.get(b, &key(3)).unwrap(), Some(Felt::THREE));
+    assert_eq!(
+        pending.pending_root(a).unwrap(),
+        reference.root_hash(a).unwrap()
+    );
+    assert_eq!(
+        pending.pending_root(b).unwrap(),
+        reference.root_hash(b).unwrap()
+    );
+
+    // The block is built again.
+    pending.clear_pending();
+    assert_eq!(pending.pending_root(a).unwrap(), committed_root);
+    assert_eq!(pending.get(a, &key(1)).unwrap(), Some(Felt::ONE));
+    pending
+        .apply_pending([
+            (a, key(2), Felt::TWO),
+            (a, key(1), Felt::ZERO),
+            (b, key(3), Felt::THREE),
+        ])
+        .unwrap();
+    pending.finalize_pending(BasicId::new(1)).unwrap();
+
+    // The next block starts from the committed one.
+    assert_eq!(
+        pending.pending_root(a).unwrap(),
+        reference.root_hash(a).unwrap()
+    );
+    pending.apply_pending([(b, key(4), Felt::ONE)]).unwrap();
+    assert_ne!(
+        pending.pending_root(b).unwrap(),
+        reference.root_hash(b).unwrap()
+    );
+    assert_eq!(storage.get_latest_id(), Some(BasicId::new(1)));
+    assert_eq!(
+        storage.root_hash(a).unwrap(),
+        reference.root_hash(a).unwrap()
+    );
+
+    // The pending block is kept as uncommitted changes.
+    assert!(matches!(
+        storage.pending_state(),
+        Err(BonsaiStorageError::UncommittedChanges)
+    ));
+    storage.commit(BasicId::new(2)).unwrap();
+    assert!(storage.pending_state().is_ok());
+}
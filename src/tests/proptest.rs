@@ -1,67 +1,30 @@
 #![cfg(feature = "std")]
 use crate::databases::HashMapDb;
-use crate::id::BasicId;
+#[cfg(feature = "rocksdb")]
+use crate::databases::{create_rocks_db, RocksDB, RocksDBConfig};
+use crate::id::{BasicId, BasicIdBuilder};
 use crate::key_value_db::KeyValueDB;
+use crate::testing::{self, Step};
 use crate::trie::tree::MerkleTree;
-use crate::{BitVec, HashMap};
+use crate::{BonsaiStorage, HashMap};
 use bitvec::bitvec;
 use bitvec::order::Msb0;
-use core::fmt::{self, Debug};
 use proptest::prelude::*;
-use proptest_derive::Arbitrary;
 use smallvec::smallvec;
 use starknet_types_core::felt::Felt;
 use starknet_types_core::hash::Pedersen;
 
-#[derive(PartialEq, Eq, Hash)]
-struct Key(BitVec);
-impl fmt::Debug for Key {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:b}", self.0)
-    }
-}
-impl Arbitrary for Key {
-    type Parameters = ();
-    type Strategy = BoxedStrategy<Self>;
-
-    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
-        <[bool; 5]>::arbitrary()
-            .prop_map(|arr| arr.into_iter().collect::<BitVec>())
-            .prop_map(Self)
-            .boxed()
-    }
-}
-
-#[derive(PartialEq, Eq, Hash)]
-struct Value(Felt);
-impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:#x}", self.0)
-    }
-}
-impl Arbitrary for Value {
+#[derive(Debug)]
+struct MerkleTreeInsertProblem(Vec<Step>);
+impl Arbitrary for MerkleTreeInsertProblem {
     type Parameters = ();
     type Strategy = BoxedStrategy<Self>;
 
     fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
-        <[bool; 251]>::arbitrary()
-            .prop_map(|arr| arr.into_iter().collect::<BitVec>())
-            .prop_map(|vec| Felt::from_bytes_be(vec.as_raw_slice().try_into().unwrap()))
-            .prop_map(Self)
-            .boxed()
+        testing::steps(5).prop_map(Self).boxed()
     }
 }
 
-#[derive(Debug, Arbitrary)]
-enum Step {
-    Insert(Key, Value),
-    Remove(Key),
-    Commit,
-}
-
-#[derive(Debug, Arbitrary)]
-struct MerkleTreeInsertProblem(Vec<Step>);
-
 impl MerkleTreeInsertProblem {
     fn check(&self) {
         let mut hashmap_db = KeyValueDB::<_, BasicId>::new(
@@ -77,14 +40,14 @@ impl MerkleTreeInsertProblem {
         for step in &self.0 {
             match step {
                 Step::Insert(k, v) => {
-                    log::trace!("== STEP == setting {k:?} => {v:?}");
-                    ckv.insert(k.0.clone(), v.0);
-                    tree.set(&hashmap_db, &k.0, v.0).unwrap();
+                    log::trace!("== STEP == setting {k:b} => {v:#x}");
+                    ckv.insert(k.clone(), *v);
+                    tree.set(&hashmap_db, k, *v).unwrap();
                 }
                 Step::Remove(k) => {
-                    log::trace!("== STEP == removing {k:?}");
-                    ckv.insert(k.0.clone(), Felt::ZERO);
-                    tree.set(&hashmap_db, &k.0, Felt::ZERO).unwrap();
+                    log::trace!("== STEP == removing {k:b}");
+                    ckv.insert(k.clone(), Felt::ZERO);
+                    tree.set(&hashmap_db, k, Felt::ZERO).unwrap();
                 }
                 Step::Commit => {
                     log::trace!("== STEP == commit");
@@ -100,7 +63,7 @@ impl MerkleTreeInsertProblem {
             log::trace!("checking {k:b}.....");
             let v2 = tree.get(&hashmap_db, k).unwrap().unwrap_or_default();
             log::trace!("checking that {k:b} => {v:#x}, (tree returned {v2:#x})");
-            assert_eq!(Value(*v), Value(v2))
+            assert_eq!(*v, v2)
         }
 
         // check for leaks
@@ -130,6 +93,16 @@ proptest::proptest! {
     }
 }
 
+proptest::proptest! {
+    #[test]
+    fn proptest_reference(steps in testing::steps(5)) {
+        let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+            BonsaiStorage::new(HashMapDb::<BasicId>::default(), Default::default(), 5).unwrap();
+        let mut id_builder = BasicIdBuilder::new();
+        testing::check_against_reference(&mut bonsai_storage, &[], &steps, || id_builder.new_id());
+    }
+}
+
 #[test]
 fn test_merkle_pb_1() {
     use Step::*;
@@ -137,17 +110,17 @@ fn test_merkle_pb_1() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 1,0,0,1,1]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,0,0,1,1],
+            Felt::from_hex("0x20").unwrap(),
         ),
-        Remove(Key(bitvec![u8, Msb0; 1,0,0,1,1])),
-        Remove(Key(bitvec![u8, Msb0; 0,0,0,0,0])),
+        Remove(bitvec![u8, Msb0; 1,0,0,1,1]),
+        Remove(bitvec![u8, Msb0; 0,0,0,0,0]),
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Commit,
-        Remove(Key(bitvec![u8, Msb0; 0,0,0,0,0])),
+        Remove(bitvec![u8, Msb0; 0,0,0,0,0]),
     ]);
 
     pb.check();
@@ -160,17 +133,17 @@ fn test_merkle_pb_2() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 0,1,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,1,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         // Remove(
-        //     Key(bitvec![u8, Msb0; 0,0,0,0,0]),
+        //     bitvec![u8, Msb0; 0,0,0,0,0],
         // ),
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,0]),
-            Value(Felt::from_hex("0x80").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,0],
+            Felt::from_hex("0x80").unwrap(),
         ),
-        Remove(Key(bitvec![u8, Msb0; 0,0,0,0,0])),
+        Remove(bitvec![u8, Msb0; 0,0,0,0,0]),
         Commit,
     ]);
 
@@ -184,19 +157,19 @@ fn test_merkle_pb_3() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 1,0,0,0,0]),
-            Value(Felt::from_hex("0x21").unwrap()),
+            bitvec![u8, Msb0; 1,0,0,0,0],
+            Felt::from_hex("0x21").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 1,1,0,0,0]),
-            Value(Felt::from_hex("0x22").unwrap()),
+            bitvec![u8, Msb0; 1,1,0,0,0],
+            Felt::from_hex("0x22").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 1,1,0,1,0]),
-            Value(Felt::from_hex("0x23").unwrap()),
+            bitvec![u8, Msb0; 1,1,0,1,0],
+            Felt::from_hex("0x23").unwrap(),
         ),
-        Remove(Key(bitvec![u8, Msb0; 1,0,0,0,0])),
-        Remove(Key(bitvec![u8, Msb0; 1,0,0,0,0])),
+        Remove(bitvec![u8, Msb0; 1,0,0,0,0]),
+        Remove(bitvec![u8, Msb0; 1,0,0,0,0]),
         Commit,
     ]);
 
@@ -210,16 +183,16 @@ fn test_merkle_pb_4() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         // Remove(
-        //     Key(bitvec![u8, Msb0; 0,0,0,0,0]),
+        //     bitvec![u8, Msb0; 0,0,0,0,0],
         // ),
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,1,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,0,1,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Commit,
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,0]),
-            Value(Felt::from_hex("0x21").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,0],
+            Felt::from_hex("0x21").unwrap(),
         ),
         Commit,
     ]);
@@ -234,12 +207,12 @@ fn test_merkle_pb_5() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,1]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,1],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,1,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,0,1,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
     ]);
 
@@ -253,24 +226,24 @@ fn test_merkle_pb_6() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 1,0,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,0,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 1,1,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,1,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Commit,
         Insert(
-            Key(bitvec![u8, Msb0; 1,1,0,1,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,1,0,1,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 1,0,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,0,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
-        Remove(Key(bitvec![u8, Msb0; 1,0,0,0,0])),
-        Remove(Key(bitvec![u8, Msb0; 1,0,0,0,0])),
+        Remove(bitvec![u8, Msb0; 1,0,0,0,0]),
+        Remove(bitvec![u8, Msb0; 1,0,0,0,0]),
     ]);
 
     pb.check();
@@ -283,19 +256,36 @@ fn test_merkle_pb_7() {
     log::set_max_level(log::LevelFilter::Trace);
     let pb = MerkleTreeInsertProblem(vec![
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Insert(
-            Key(bitvec![u8, Msb0; 1,0,0,0,0]),
-            Value(Felt::from_hex("0x20").unwrap()),
+            bitvec![u8, Msb0; 1,0,0,0,0],
+            Felt::from_hex("0x20").unwrap(),
         ),
         Commit,
         Insert(
-            Key(bitvec![u8, Msb0; 0,0,0,0,0]),
-            Value(Felt::from_hex("0x40").unwrap()),
+            bitvec![u8, Msb0; 0,0,0,0,0],
+            Felt::from_hex("0x40").unwrap(),
         ),
     ]);
 
     pb.check();
 }
+
+proptest::proptest! {
+    // Every case hashes the reference tries many times, which is slow in debug builds.
+    #![proptest_config(ProptestConfig::with_cases(32))]
+    #[test]
+    fn proptest_fuzz_ops(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+        testing::fuzz_ops(&data);
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn proptest_fuzz_ops_rocksdb(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let db = create_rocks_db(tempdir.path()).unwrap();
+        testing::fuzz_ops_on(RocksDB::new(&db, RocksDBConfig::default()), &data);
+    }
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "redb")]
+use crate::{
+    databases::{open_redb_db, RedbDb, RedbDbConfig},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn conformance() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut count = 0;
+    crate::testing::run_database_suite(|| {
+        count += 1;
+        let db = open_redb_db(dir.path().join(format!("{count}.redb"))).unwrap();
+        RedbDb::<BasicId>::new(db, RedbDbConfig::default())
+    });
+}
+
+#[test]
+fn commit_revert_and_reopen() {
+    let identifier = b"id";
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bonsai.redb");
+    let mut id_builder = BasicIdBuilder::new();
+    let mut root_hashes = Vec::new();
+    {
+        let db = open_redb_db(&path).unwrap();
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+            RedbDb::new(db, RedbDbConfig::default()),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        for i in 0..3u64 {
+            for j in 0..10u64 {
+                let key = BitVec::from_vec(vec![i as u8, j as u8, 0]);
+                bonsai_storage
+                    .insert(identifier, &key, &Felt::from(i * 100 + j + 1))
+                    .unwrap();
+            }
+            let id = id_builder.new_id();
+            bonsai_storage.commit(id).unwrap();
+            root_hashes.push((id, bonsai_storage.root_hash(identifier).unwrap()));
+        }
+
+        let (id, root_hash) = root_hashes[1];
+        let txn = bonsai_storage
+            .get_transactional_state(id, BonsaiStorageConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(txn.root_hash(identifier).unwrap(), root_hash);
+
+        bonsai_storage.revert_to(id).unwrap();
+        assert_eq!(bonsai_storage.root_hash(identifier).unwrap(), root_hash);
+    }
+
+    // The state is read back from the file.
+    let db = open_redb_db(&path).unwrap();
+    let bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        RedbDb::<BasicId>::new(db, RedbDbConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(identifier).unwrap(),
+        root_hashes[1].1
+    );
+}
@@ -0,0 +1,176 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::{HashMapDb, NodeFetcher, RemoteDb},
+    id::BasicId,
+    BitVec, BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorage, BonsaiStorageConfig, ByteVec,
+    DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::cell::Cell;
+
+const IDENTIFIER: &[u8] = b"id";
+
+fn storage<DB>(db: DB) -> BonsaiStorage<BasicId, DB, Pedersen>
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap()
+}
+
+fn key(i: u8) -> BitVec {
+    BitVec::from_vec(vec![i, i.wrapping_mul(7), 0])
+}
+
+/// Database of a full node with 20 leaves committed.
+fn full_node() -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    let mut full_node = storage(HashMapDb::default());
+    for i in 0..20 {
+        full_node
+            .insert(IDENTIFIER, &key(i), &Felt::from(i as u64 + 1))
+            .unwrap();
+    }
+    full_node.commit(BasicId::new(0)).unwrap();
+    full_node
+}
+
+/// Fetcher reading the database of a full node, counting its fetches.
+struct LocalFetcher {
+    db: HashMapDb<BasicId>,
+    fetches: Cell<usize>,
+}
+
+impl NodeFetcher for LocalFetcher {
+    type Error = <HashMapDb<BasicId> as BonsaiDatabase>::DatabaseError;
+
+    fn fetch(&self, key: &DatabaseKey) -> Result<Option<ByteVec>, Self::Error> {
+        self.fetches.set(self.fetches.get() + 1);
+        self.db.get(key)
+    }
+}
+
+#[test]
+fn conformance() {
+    let fetcher = LocalFetcher {
+        db: HashMapDb::default(),
+        fetches: Cell::new(0),
+    };
+    crate::testing::run_database_suite(|| RemoteDb::new(HashMapDb::<BasicId>::default(), &fetcher));
+}
+
+#[test]
+// The local database holds only some of the leaves, which `audit` takes for a corrupted trie.
+#[cfg(not(feature = "audit"))]
+fn reads_through_and_caches() {
+    let full_node = full_node();
+    let root_hash = full_node.root_hash(IDENTIFIER).unwrap();
+    let fetcher = LocalFetcher {
+        db: full_node.into_db(),
+        fetches: Cell::new(0),
+    };
+    let mut light = storage(RemoteDb::new(HashMapDb::<BasicId>::default(), &fetcher));
+    assert_eq!(light.root_hash(IDENTIFIER).unwrap(), root_hash);
+    assert_eq!(
+        light.get(IDENTIFIER, &key(3)).unwrap(),
+        Some(Felt::from(4u64))
+    );
+    let fetches = fetcher.fetches.get();
+    assert_eq!(
+        light.get(IDENTIFIER, &key(3)).unwrap(),
+        Some(Felt::from(4u64))
+    );
+    assert_eq!(fetcher.fetches.get(), fetches);
+
+    // The same changes as on the full node give the same root hash.
+    let mut full_node = storage(fetcher.db.clone());
+    apply_changes(&mut full_node);
+    apply_changes(&mut light);
+    assert_eq!(
+        light.root_hash(IDENTIFIER).unwrap(),
+        full_node.root_hash(IDENTIFIER).unwrap()
+    );
+
+    // Removed leaves are not fetched again, and the fetched values are stored locally.
+    assert_eq!(light.get(IDENTIFIER, &key(5)).unwrap(), None);
+    let fetches = fetcher.fetches.get();
+    let light = storage(RemoteDb::new(light.into_db().into_inner(), &fetcher));
+    assert_eq!(
+        light.root_hash(IDENTIFIER).unwrap(),
+        full_node.root_hash(IDENTIFIER).unwrap()
+    );
+    assert_eq!(light.get(IDENTIFIER, &key(5)).unwrap(), None);
+    assert_eq!(
+        light.get(IDENTIFIER, &key(3)).unwrap(),
+        Some(Felt::from(40u64))
+    );
+    assert_eq!(fetcher.fetches.get(), fetches);
+}
+
+fn apply_changes<DB>(storage: &mut BonsaiStorage<BasicId, DB, Pedersen>)
+where
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<BasicId>,
+{
+    storage
+        .insert(IDENTIFIER, &key(30), &Felt::from(31u64))
+        .unwrap();
+    storage
+        .insert(IDENTIFIER, &key(3), &Felt::from(40u64))
+        .unwrap();
+    storage.remove(IDENTIFIER, &key(5)).unwrap();
+    storage.commit(BasicId::new(1)).unwrap();
+}
+
+#[cfg(feature = "remote_http")]
+#[test]
+fn http_fetcher() {
+    use crate::databases::HttpFetcher;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    let full_node = full_node();
+    let root_hash = full_node.root_hash(IDENTIFIER).unwrap();
+    let db = full_node.into_db();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/state", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            // `GET /state/<column>/<hex key> HTTP/1.1`
+            let path = request.split(' ').nth(1).unwrap();
+            let mut parts = path.split('/').skip(2);
+            let (column, name) = (parts.next().unwrap(), parts.next().unwrap());
+            let key: Vec<u8> = (0..name.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&name[i..i + 2], 16).unwrap())
+                .collect();
+            let key = match column {
+                "trie" => DatabaseKey::Trie(&key),
+                _ => DatabaseKey::Flat(&key),
+            };
+            let (status, body) = match db.get(&key).unwrap() {
+                Some(value) => ("200 OK", value.to_vec()),
+                None => ("404 Not Found", vec![]),
+            };
+            let header = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    let light = storage(RemoteDb::new(
+        HashMapDb::<BasicId>::default(),
+        HttpFetcher::new(url),
+    ));
+    assert_eq!(light.root_hash(IDENTIFIER).unwrap(), root_hash);
+    assert_eq!(
+        light.get(IDENTIFIER, &key(3)).unwrap(),
+        Some(Felt::from(4u64))
+    );
+    assert_eq!(light.get(IDENTIFIER, &key(30)).unwrap(), None);
+}
@@ -0,0 +1,15 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::databases::{create_rocks_db, RocksDB, RocksDBConfig};
+
+#[test]
+fn conformance() {
+    let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+    let dbs: Vec<_> = dirs
+        .iter()
+        .map(|dir| create_rocks_db(dir.path()).unwrap())
+        .collect();
+    let mut dbs = dbs.iter();
+    crate::testing::run_database_suite(|| {
+        RocksDB::new(dbs.next().unwrap(), RocksDBConfig::default())
+    });
+}
@@ -0,0 +1,237 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{HashMapDb, RocksDB},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, ShardError,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::{sync::Barrier, thread};
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn storages_are_send_sync() {
+    assert_send_sync::<BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>>();
+    assert_send_sync::<BonsaiStorage<BasicId, RocksDB<'static, BasicId>, Pedersen>>();
+    assert_send_sync::<crate::SharedBonsaiStorage<BasicId, RocksDB<'static, BasicId>, Pedersen>>();
+}
+
+#[test]
+fn shared_storage_hashmap_db() {
+    let identifier = vec![];
+    let storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let shared = storage.into_shared();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    {
+        let mut storage = shared.write();
+        storage
+            .insert(&identifier, &key1, &Felt::from(1u32))
+            .unwrap();
+        storage.commit(id_builder.new_id()).unwrap();
+    }
+    let id1 = id_builder.new_id();
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let shared = shared.clone();
+            let identifier = &identifier;
+            let key1 = &key1;
+            s.spawn(move || {
+                let storage = shared.read();
+                assert_eq!(
+                    storage.get(identifier, key1).unwrap(),
+                    Some(Felt::from(1u32))
+                );
+            });
+        }
+        let shared = shared.clone();
+        let identifier = &identifier;
+        let key2 = &key2;
+        s.spawn(move || {
+            let mut storage = shared.write();
+            storage.insert(identifier, key2, &Felt::from(2u32)).unwrap();
+            storage.commit(id1).unwrap();
+        });
+    });
+
+    // A transactional state keeps the storage read-locked while it lives.
+    let storage = shared.read();
+    let txn = storage
+        .get_transactional_state(BasicId::new(0), BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(txn.get(&identifier, &key2).unwrap(), None);
+    assert!(shared.try_read().is_some());
+    assert!(shared.try_write().is_none());
+    drop(txn);
+    drop(storage);
+    assert!(shared.try_write().is_some());
+
+    let storage = shared.try_unwrap().ok().unwrap();
+    assert_eq!(
+        storage.get(&identifier, &key2).unwrap(),
+        Some(Felt::from(2u32))
+    );
+}
+
+#[test]
+fn concurrent_commits_hashmap_db() {
+    let (class, contract) = (b"class".as_slice(), b"contract".as_slice());
+    let new_storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap()
+    };
+    let leaves = |block: u32, identifier: &[u8]| {
+        (0..20u32)
+            .map(|i| {
+                let key = BitVec::from_vec(vec![identifier.len() as u8, block as u8, i as u8]);
+                (key, Felt::from(block * 100 + i + 1))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Same commits made by a single thread.
+    let mut expected = new_storage();
+    for block in 0..3 {
+        for identifier in [class, contract] {
+            for (key, value) in leaves(block, identifier) {
+                expected.insert(identifier, &key, &value).unwrap();
+            }
+        }
+        expected.commit(BasicId::new(block.into())).unwrap();
+    }
+
+    let shared = new_storage().into_shared();
+    let mut class_shard = shared.shard(&[class]).unwrap();
+    let mut contract_shard = shared.shard(&[contract]).unwrap();
+    assert!(matches!(
+        shared.shard(&[class]),
+        Err(BonsaiStorageError::Shard(ShardError::CheckedOut { identifier })) if identifier.as_slice() == class
+    ));
+    assert!(matches!(
+        shared
+            .write()
+            .insert(contract, &BitVec::from_vec(vec![1, 2, 3]), &Felt::ONE),
+        Err(BonsaiStorageError::Shard(ShardError::CheckedOut { .. }))
+    ));
+    assert!(matches!(
+        class_shard.insert(contract, &BitVec::from_vec(vec![1, 2, 3]), &Felt::ONE),
+        Err(BonsaiStorageError::Shard(ShardError::NotInShard { identifier })) if identifier.as_slice() == contract
+    ));
+    // The shards can't commit a block once the other one committed the next.
+    let barrier = Barrier::new(2);
+    thread::scope(|s| {
+        for shard in [&mut class_shard, &mut contract_shard] {
+            let barrier = &barrier;
+            s.spawn(move || {
+                let identifier = shard.identifiers().next().unwrap().to_vec();
+                for block in 0..3 {
+                    for (key, value) in leaves(block, &identifier) {
+                        shard.insert(&identifier, &key, &value).unwrap();
+                    }
+                    shard.commit(BasicId::new(block.into())).unwrap();
+                    barrier.wait();
+                }
+            });
+        }
+    });
+    assert!(shared.write().revert_to(BasicId::new(1)).is_err());
+    drop((class_shard, contract_shard));
+
+    let mut storage = shared.try_unwrap().ok().unwrap();
+    for identifier in [class, contract] {
+        assert_eq!(
+            storage.root_hash(identifier).unwrap(),
+            expected.root_hash(identifier).unwrap()
+        );
+        for block in 0..3 {
+            let id = BasicId::new(block);
+            assert_eq!(
+                storage.root_hash_at(identifier, id).unwrap(),
+                expected.root_hash_at(identifier, id).unwrap()
+            );
+        }
+    }
+
+    // Reverting a commit reverts the changes of both shards.
+    storage.revert_to(BasicId::new(1)).unwrap();
+    expected.revert_to(BasicId::new(1)).unwrap();
+    for identifier in [class, contract] {
+        assert_eq!(
+            storage.root_hash(identifier).unwrap(),
+            expected.root_hash(identifier).unwrap()
+        );
+    }
+}
+
+#[test]
+fn concurrent_proofs_hashmap_db() {
+    let identifier = b"id".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let key = |i: u8| BitVec::from_vec(vec![i, i.wrapping_mul(37), 1]);
+    for i in 0..50 {
+        storage
+            .insert(identifier, &key(i), &Felt::from(i as u32 + 1))
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    // The proofs cover the uncommitted changes.
+    for i in 40..60 {
+        storage
+            .insert(identifier, &key(i), &Felt::from(i as u32 + 100))
+            .unwrap();
+    }
+
+    let tree = storage.tries.trees.get(identifier).unwrap();
+    let loaded_nodes = tree.nodes.len();
+    let root = tree.pending_root_hash(storage.tries.db_ref()).unwrap();
+    let shared = storage.into_shared();
+    thread::scope(|s| {
+        for thread in 0..4u8 {
+            let shared = shared.clone();
+            s.spawn(move || {
+                let storage = shared.read();
+                let keys: Vec<_> = (thread * 15..thread * 15 + 20).map(key).collect();
+                let proof = storage.get_multi_proof(identifier, &keys).unwrap();
+                let values = proof
+                    .verify_proof::<Pedersen>(root, &keys, 24)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                for (i, value) in (thread * 15..).zip(values) {
+                    let expected = match i {
+                        0..40 => i as u32 + 1,
+                        40..60 => i as u32 + 100,
+                        _ => 0,
+                    };
+                    assert_eq!(value, Felt::from(expected));
+                }
+            });
+        }
+    });
+
+    // The proofs left the tree as it was.
+    let mut storage = shared.try_unwrap().ok().unwrap();
+    assert_eq!(
+        storage.tries.trees.get(identifier).unwrap().nodes.len(),
+        loaded_nodes
+    );
+    storage.commit(BasicId::new(1)).unwrap();
+    assert_eq!(storage.root_hash(identifier).unwrap(), root);
+}
@@ -1,11 +1,22 @@
 #![cfg(all(feature = "std", feature = "rocksdb"))]
 use crate::{
-    databases::{create_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
+    compare, compute_root,
+    databases::{create_rocks_db, open_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
     id::{BasicId, BasicIdBuilder},
-    BitVec, BonsaiStorage, BonsaiStorageConfig,
+    trie::merkle_node::{hash_edge_node, EdgeNode, Height, Node},
+    trie::trie_db::{MetaKeyType, TrieKey},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, ByteVec,
+    CancellationToken, ConfigError, DatabaseKey, FixedDepthMerkleTree, IncrementalTrieBuilder,
+    MerkleTree, MultiProof, NodeKind, OpStats, Path, PendingStats, ProofNode,
+    ProofVerificationError, ReplayError, TrieDivergence, TrieHasher, TrieNodeFamily,
 };
-use bitvec::view::BitView;
-use starknet_types_core::{felt::Felt, hash::Pedersen};
+use parity_scale_codec::{Decode, Encode};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon, StarkHash},
+};
+use std::collections::HashMap;
 
 #[test]
 fn basics() {
@@ -14,7 +25,7 @@ fn basics() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
     let pair1 = (
         vec![1, 2, 1],
@@ -58,7 +69,13 @@ fn basics() {
             .unwrap(),
         None
     );
-    assert_eq!(bonsai_storage.get_keys(&identifier).unwrap().len(), 2);
+    assert_eq!(
+        bonsai_storage
+            .get_keys(&identifier, None, usize::MAX)
+            .unwrap()
+            .len(),
+        2
+    );
 }
 
 #[test]
@@ -69,7 +86,7 @@ fn root_hash_similar_rocks_db() {
         let db = create_rocks_db(tempdir.path()).unwrap();
         let config = BonsaiStorageConfig::default();
         let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-            BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+            BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
         let mut id_builder = BasicIdBuilder::new();
         let pair1 = (
             vec![1, 2, 1],
@@ -116,7 +133,7 @@ fn root_hash_similar_rocks_db() {
         let db = create_rocks_db(tempdir.path()).unwrap();
         let config = BonsaiStorageConfig::default();
         let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-            BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+            BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
         let mut id_builder = BasicIdBuilder::new();
         let pair1 = (
             vec![1, 2, 3],
@@ -156,13 +173,13 @@ fn starknet_specific() {
     let db1 = create_rocks_db(tempdir1.path()).unwrap();
     let config1 = BonsaiStorageConfig::default();
     let mut bonsai_storage1: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db1, RocksDBConfig::default()), config1, 251);
+        BonsaiStorage::new(RocksDB::new(&db1, RocksDBConfig::default()), config1, 251).unwrap();
 
     let tempdir2 = tempfile::tempdir().unwrap();
     let db2 = create_rocks_db(tempdir2.path()).unwrap();
     let config2 = BonsaiStorageConfig::default();
     let mut bonsai_storage2: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db2, RocksDBConfig::default()), config2, 251);
+        BonsaiStorage::new(RocksDB::new(&db2, RocksDBConfig::default()), config2, 251).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let contract_states = vec![
@@ -179,7 +196,7 @@ fn starknet_specific() {
     for contract_state in contract_states {
         let key = contract_state.address;
         let value = contract_state.state_hash;
-        let key = Felt::from_hex(key).unwrap().to_bytes_be().view_bits()[5..].to_bitvec();
+        let key = Path::from_felt_251(&Felt::from_hex(key).unwrap()).unwrap();
         let value = Felt::from_hex(value).unwrap();
         bonsai_storage1
             .insert(&identifier, &key, &value)
@@ -202,7 +219,7 @@ fn starknet_specific() {
     for contract_state in contract_states {
         let key = contract_state.address;
         let value = contract_state.state_hash;
-        let key = Felt::from_hex(key).unwrap().to_bytes_be().view_bits()[5..].to_bitvec();
+        let key = Path::from_felt_251(&Felt::from_hex(key).unwrap()).unwrap();
         let value = Felt::from_hex(value).unwrap();
 
         bonsai_storage1
@@ -236,7 +253,8 @@ fn root_hash_similar_hashmap_db() {
     let root_hash_1 = {
         let db = HashMapDb::<BasicId>::default();
         let config = BonsaiStorageConfig::default();
-        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(db, config, 24);
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+            BonsaiStorage::new(db, config, 24).unwrap();
         let mut id_builder = BasicIdBuilder::new();
         let pair1 = (
             vec![1, 2, 1],
@@ -281,7 +299,8 @@ fn root_hash_similar_hashmap_db() {
     let root_hash_2 = {
         let db = HashMapDb::<BasicId>::default();
         let config = BonsaiStorageConfig::default();
-        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(db, config, 24);
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+            BonsaiStorage::new(db, config, 24).unwrap();
         let mut id_builder = BasicIdBuilder::new();
         let pair1 = (
             vec![1, 2, 3],
@@ -320,7 +339,7 @@ fn double_insert() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 251);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 251).unwrap();
     let mut id_builder = BasicIdBuilder::new();
     let contract_states = vec![
         ContractState {
@@ -351,7 +370,7 @@ fn double_insert() {
         let value = contract_state.state_hash;
 
         let key = Felt::from_hex(key).unwrap();
-        let bitkey = key.to_bytes_be().view_bits()[5..].to_bitvec();
+        let bitkey = Path::from_felt_251(&key).unwrap();
         let value = Felt::from_hex(value).unwrap();
         bonsai_storage
             .insert(&identifier, &bitkey, &value)
@@ -383,7 +402,7 @@ fn double_identifier() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 251);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 251).unwrap();
     let mut id_builder = BasicIdBuilder::new();
     let contract_states = vec![
         ContractState {
@@ -414,7 +433,7 @@ fn double_identifier() {
         let value = contract_state.state_hash;
 
         let key = Felt::from_hex(key).unwrap();
-        let bitkey = key.to_bytes_be().view_bits()[5..].to_bitvec();
+        let bitkey = Path::from_felt_251(&key).unwrap();
         let value = Felt::from_hex(value).unwrap();
         bonsai_storage
             .insert(&identifier, &bitkey, &value)
@@ -435,10 +454,20 @@ fn double_identifier() {
     let root_hash2 = bonsai_storage.root_hash(&identifier2).unwrap();
     assert_eq!(root_hash, root_hash2);
     assert_eq!(
-        bonsai_storage.get_keys(&identifier).unwrap(),
-        bonsai_storage.get_keys(&identifier2).unwrap()
+        bonsai_storage
+            .get_keys(&identifier, None, usize::MAX)
+            .unwrap(),
+        bonsai_storage
+            .get_keys(&identifier2, None, usize::MAX)
+            .unwrap()
+    );
+    assert_eq!(
+        bonsai_storage
+            .get_keys(&identifier, None, usize::MAX)
+            .unwrap()
+            .len(),
+        5
     );
-    assert_eq!(bonsai_storage.get_keys(&identifier).unwrap().len(), 5);
 }
 
 // #[test]
@@ -448,7 +477,7 @@ fn double_identifier() {
 //     let db = create_rocks_db(tempdir.path()).unwrap();
 //     let config = BonsaiStorageConfig::default();
 //     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
 //     let mut id_builder = BasicIdBuilder::new();
 //     let pair1 = (vec![1, 2, 1], Felt::from_hex("0x01").unwrap());
 //     let bitvec = BitVec::from_vec(pair1.0.clone());
@@ -491,15 +520,11 @@ fn double_identifier() {
 //     );
 // }
 
-fn keyer(felt: Felt) -> BitVec {
-    felt.to_bytes_be().view_bits()[5..].to_bitvec()
-}
-
 #[test]
 fn test_insert_zero() {
     let config = BonsaiStorageConfig::default();
     let bonsai_db = HashMapDb::<BasicId>::default();
-    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251);
+    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251).unwrap();
     let identifier =
         "0x056e4fed965fccd7fb01fcadd827470338f35ced62275328929d0d725b5707ba".as_bytes();
 
@@ -532,7 +557,7 @@ fn test_insert_zero() {
         let key: Felt = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -587,7 +612,7 @@ fn test_insert_zero() {
         let key: Felt = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -615,7 +640,7 @@ fn test_insert_zero() {
         let key: Felt = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -642,7 +667,7 @@ fn test_block_7_starknet() {
     let _ = env_logger::builder().is_test(true).try_init();
     let config = BonsaiStorageConfig::default();
     let bonsai_db = HashMapDb::<BasicId>::default();
-    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251);
+    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251).unwrap();
     let identifier =
         "0x056e4fed965fccd7fb01fcadd827470338f35ced62275328929d0d725b5707ba".as_bytes();
 
@@ -671,7 +696,7 @@ fn test_block_7_starknet() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -741,7 +766,7 @@ fn test_block_7_starknet() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -777,7 +802,7 @@ fn test_block_7_starknet() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -803,7 +828,7 @@ fn test_block_7_starknet() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -829,7 +854,7 @@ fn test_block_7_starknet() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -853,7 +878,7 @@ fn test_block_7_starknet() {
 fn test_block_7_starknet_2() {
     let config = BonsaiStorageConfig::default();
     let bonsai_db = HashMapDb::<BasicId>::default();
-    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251);
+    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251).unwrap();
     let identifier = "0x421203c58e1b4a6c3675be26cfaa18d2b6b42695ca206be1f08ce29f7f1bc7c".as_bytes();
 
     // Insert Block 5 storage changes for contract `0x421203c58e1b4a6c3675be26cfaa18d2b6b42695ca206be1f08ce29f7f1bc7c`
@@ -893,7 +918,7 @@ fn test_block_7_starknet_2() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -933,7 +958,7 @@ fn test_block_7_starknet_2() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -959,7 +984,7 @@ fn test_block_7_starknet_2() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -983,7 +1008,7 @@ fn test_block_7_starknet_2() {
 fn test_block_9() {
     let config = BonsaiStorageConfig::default();
     let bonsai_db = HashMapDb::<BasicId>::default();
-    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251);
+    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(bonsai_db, config, 251).unwrap();
     let identifier =
         "0x06F3C934BA4EC49245CB9A42FC715E4D589AA502AF69BE13916127A538D525CE".as_bytes();
 
@@ -1000,7 +1025,7 @@ fn test_block_9() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -1027,7 +1052,7 @@ fn test_block_9() {
         let key = Felt::from_hex(key_hex).unwrap();
         let value = Felt::from_hex(value_hex).unwrap();
         bonsai_storage
-            .insert(identifier, keyer(key).as_bitslice(), &value)
+            .insert_felt(identifier, &key, &value)
             .expect("Failed to insert storage update into trie");
     }
 
@@ -1046,3 +1071,3004 @@ fn test_block_9() {
             .unwrap()
     );
 }
+
+#[test]
+fn savepoint_rollback() {
+    let identifier = vec![];
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let committed_root_hash = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let savepoint1 = bonsai_storage.savepoint();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    let savepoint2 = bonsai_storage.savepoint();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage.remove(&identifier, &key2).unwrap();
+    bonsai_storage
+        .insert(&identifier, &key3, &Felt::from(4u32))
+        .unwrap();
+
+    bonsai_storage.rollback_to_savepoint(savepoint2).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(Felt::from(1u32))
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key2).unwrap(),
+        Some(Felt::from(2u32))
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &key3).unwrap(), None);
+
+    bonsai_storage.rollback_to_savepoint(savepoint1).unwrap();
+    assert!(bonsai_storage.rollback_to_savepoint(savepoint2).is_err());
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), None);
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        committed_root_hash
+    );
+
+    // Savepoints do not survive a commit.
+    assert!(matches!(
+        bonsai_storage.rollback_to_savepoint(savepoint1),
+        Err(BonsaiStorageError::SavepointNotFound(savepoint)) if savepoint == savepoint1
+    ));
+}
+
+#[test]
+fn savepoint_rollback_restructured_trie() {
+    let identifier = vec![0];
+    let other_identifier = vec![1];
+    let keys: Vec<_> = (0..64u8)
+        .map(|i| BitVec::from_vec(vec![i.wrapping_mul(37), 2, i]))
+        .collect();
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut expected: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let id = id_builder.new_id();
+    for storage in [&mut bonsai_storage, &mut expected] {
+        for (i, key) in keys.iter().enumerate().step_by(2) {
+            storage
+                .insert(&identifier, key, &Felt::from(i as u32 + 1))
+                .unwrap();
+        }
+        storage.commit(id).unwrap();
+        // Uncommitted changes made before the savepoint are kept.
+        for (i, key) in keys.iter().enumerate().skip(1).step_by(4) {
+            storage
+                .insert(&identifier, key, &Felt::from(i as u32 + 1))
+                .unwrap();
+        }
+        storage.remove(&identifier, &keys[0]).unwrap();
+    }
+
+    let savepoint = bonsai_storage.savepoint();
+    for _ in 0..2 {
+        // Splits and merges of the nodes loaded before and after the savepoint.
+        for (i, key) in keys.iter().enumerate() {
+            if i % 3 == 0 {
+                bonsai_storage.remove(&identifier, key).unwrap();
+            } else {
+                bonsai_storage
+                    .insert(&identifier, key, &Felt::from(i as u32 + 100))
+                    .unwrap();
+            }
+        }
+        for key in &keys[..40] {
+            bonsai_storage.remove(&identifier, key).unwrap();
+        }
+        bonsai_storage
+            .insert(&other_identifier, &keys[1], &Felt::ONE)
+            .unwrap();
+        bonsai_storage.put_meta(b"block_hash", b"hash");
+
+        // The savepoint stays valid after a rollback.
+        bonsai_storage.rollback_to_savepoint(savepoint).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                bonsai_storage.get(&identifier, key).unwrap(),
+                expected.get(&identifier, key).unwrap(),
+                "key {i}"
+            );
+        }
+        assert_eq!(bonsai_storage.get_meta(b"block_hash").unwrap(), None);
+    }
+
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    expected.commit(id).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        expected.root_hash(&identifier).unwrap()
+    );
+    assert_eq!(
+        bonsai_storage.root_hash(&other_identifier).unwrap(),
+        Felt::ZERO
+    );
+}
+
+#[test]
+fn get_many() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let keys: Vec<_> = (1..=4u8).map(|i| BitVec::from_vec(vec![1, 2, i])).collect();
+    for (i, key) in keys[..3].iter().enumerate() {
+        bonsai_storage
+            .insert(&identifier, key, &Felt::from(i as u32 + 1))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage
+        .insert(&identifier, &keys[0], &Felt::from(5u32))
+        .unwrap();
+    bonsai_storage.remove(&identifier, &keys[1]).unwrap();
+
+    let values = bonsai_storage.get_many(&identifier, &keys).unwrap();
+    assert_eq!(
+        values,
+        vec![Some(Felt::from(5u32)), None, Some(Felt::from(3u32)), None]
+    );
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(bonsai_storage.get(&identifier, key).unwrap(), value);
+    }
+}
+
+#[test]
+fn prefetch() {
+    let identifier = vec![];
+    let mut id_builder = BasicIdBuilder::new();
+    let keys: Vec<_> = (1..=8u8).map(|i| BitVec::from_vec(vec![i, 2, i])).collect();
+    let mut root_hashes = vec![];
+    for prefetch in [false, true] {
+        let tempdir = tempfile::tempdir().unwrap();
+        let db = create_rocks_db(tempdir.path()).unwrap();
+        let config = BonsaiStorageConfig::default();
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+            BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            bonsai_storage
+                .insert(&identifier, key, &Felt::from(i as u32 + 1))
+                .unwrap();
+        }
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+        if prefetch {
+            bonsai_storage.prefetch(&identifier, &keys[2..6]).unwrap();
+            assert!(!bonsai_storage.tries.trees[&identifier[..]].nodes.is_empty());
+        }
+        for key in &keys[2..6] {
+            bonsai_storage
+                .insert(&identifier, key, &Felt::from(42u32))
+                .unwrap();
+        }
+        bonsai_storage.remove(&identifier, &keys[3]).unwrap();
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+        root_hashes.push(bonsai_storage.root_hash(&identifier).unwrap());
+    }
+    assert_eq!(root_hashes[0], root_hashes[1]);
+}
+
+#[test]
+fn felt_keys() {
+    let identifier = vec![];
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 251).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = Felt::from_hex("0x313ad57fdf765addc71329abf8d74ac2bce6d46da8c2b9b82255a5076620301")
+        .unwrap();
+    bonsai_storage
+        .insert_felt(&identifier, &key, &Felt::from(42u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        bonsai_storage.get_felt(&identifier, &key).unwrap(),
+        Some(Felt::from(42u32))
+    );
+    assert_eq!(
+        bonsai_storage
+            .get(&identifier, &Path::from_felt_251(&key).unwrap())
+            .unwrap(),
+        Some(Felt::from(42u32))
+    );
+
+    bonsai_storage.remove_felt(&identifier, &key).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get_felt(&identifier, &key).unwrap(), None);
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), Felt::ZERO);
+
+    // 2^251 + 1 would have the same key as 1.
+    let too_large =
+        Felt::from_hex("0x800000000000000000000000000000000000000000000000000000000000001")
+            .unwrap();
+    assert!(matches!(
+        bonsai_storage.insert_felt(&identifier, &too_large, &Felt::ONE),
+        Err(BonsaiStorageError::FeltKeyOutOfRange(key)) if key == too_large
+    ));
+    assert!(matches!(
+        bonsai_storage.get_felt(&identifier, &too_large),
+        Err(BonsaiStorageError::FeltKeyOutOfRange(_))
+    ));
+    assert!(matches!(
+        bonsai_storage.remove_felt(&identifier, &too_large),
+        Err(BonsaiStorageError::FeltKeyOutOfRange(_))
+    ));
+}
+
+#[test]
+fn root_without_database() {
+    let identifier = vec![];
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 251).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    assert_eq!(
+        MerkleTree::<Pedersen>::empty_root(),
+        bonsai_storage.root_hash(&identifier).unwrap()
+    );
+
+    let mut leaves = vec![
+        (
+            Path::from_felt_251(
+                &Felt::from_hex(
+                    "0x313ad57fdf765addc71329abf8d74ac2bce6d46da8c2b9b82255a5076620301",
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+            Felt::from(42u32),
+        ),
+        (
+            Path::from_felt_251(&Felt::from(7u32)).unwrap(),
+            Felt::from(1u32),
+        ),
+        (
+            Path::from_felt_251(&Felt::from(8u32)).unwrap(),
+            Felt::from(2u32),
+        ),
+    ];
+    bonsai_storage
+        .insert(&identifier, &leaves[0].0, &leaves[0].1)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        MerkleTree::<Pedersen>::single_leaf_root(&leaves[0].0, leaves[0].1),
+        bonsai_storage.root_hash(&identifier).unwrap()
+    );
+
+    for (key, value) in &leaves[1..] {
+        bonsai_storage.insert(&identifier, key, value).unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    leaves.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    let leaves: Vec<_> = leaves
+        .iter()
+        .map(|(key, value)| (key.as_ref(), *value))
+        .collect();
+    assert_eq!(
+        MerkleTree::<Pedersen>::root_from_sorted_leaves(&leaves),
+        bonsai_storage.root_hash(&identifier).unwrap()
+    );
+}
+
+#[test]
+fn compute_root_matches_storage() {
+    let identifier = vec![];
+    let db = HashMapDb::<BasicId>::default();
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 251).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = SmallRng::seed_from_u64(42);
+
+    let mut leaves = vec![];
+    for _ in 0..300 {
+        let key = Path::from_felt_251(&Felt::from(rng.gen::<u64>())).unwrap();
+        let value = Felt::from(rng.gen::<u64>());
+        bonsai_storage.insert(&identifier, &key, &value).unwrap();
+        leaves.push((key.0, value));
+    }
+    // Leaves can be given in any order.
+    leaves.shuffle(&mut rng);
+    // Overwritten and removed leaves, the last value of a key wins.
+    for i in 0..10 {
+        let value = if i % 2 == 0 {
+            Felt::ZERO
+        } else {
+            Felt::from(rng.gen::<u64>())
+        };
+        let key = leaves[i * 7].0.clone();
+        bonsai_storage.insert(&identifier, &key, &value).unwrap();
+        leaves.push((key, value));
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    assert_eq!(
+        compute_root::<Pedersen>(leaves),
+        bonsai_storage.root_hash(&identifier).unwrap()
+    );
+    assert_eq!(compute_root::<Pedersen>([]), Felt::ZERO);
+}
+
+#[test]
+fn invalid_config() {
+    let new_storage = |config| {
+        BonsaiStorage::<BasicId, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 251)
+    };
+    assert!(new_storage(BonsaiStorageConfig::default()).is_ok());
+    assert!(matches!(
+        new_storage(BonsaiStorageConfig {
+            snapshot_interval: 0,
+            ..Default::default()
+        }),
+        Err(BonsaiStorageError::Config(
+            ConfigError::ZeroSnapshotInterval
+        ))
+    ));
+    assert!(matches!(
+        new_storage(BonsaiStorageConfig {
+            max_saved_trie_logs: Some(2),
+            snapshot_interval: 5,
+            ..Default::default()
+        }),
+        Err(BonsaiStorageError::Config(
+            ConfigError::TrieLogsShorterThanSnapshotInterval {
+                max_saved_trie_logs: 2,
+                snapshot_interval: 5
+            }
+        ))
+    ));
+    // Disabled trie logs don't need to cover a snapshot interval.
+    assert!(new_storage(BonsaiStorageConfig {
+        max_saved_trie_logs: Some(0),
+        ..Default::default()
+    })
+    .is_ok());
+}
+
+#[test]
+fn stored_config_hashmap_db() {
+    let identifier = vec![];
+    type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(10),
+        max_inline_leaves: Some(4),
+        ..Default::default()
+    };
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut storage = Storage::new(HashMapDb::default(), config.clone(), 24).unwrap();
+    storage.insert(&identifier, &key, &Felt::ONE).unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+    let db = || storage.tries.db_ref().db.clone();
+
+    let is_mismatch = |result: Result<Storage, _>| {
+        matches!(
+            result,
+            Err(BonsaiStorageError::Config(
+                ConfigError::StoredConfigMismatch { .. }
+            ))
+        )
+    };
+    assert!(is_mismatch(Storage::new(db(), config.clone(), 251)));
+    assert!(is_mismatch(Storage::new(
+        db(),
+        BonsaiStorageConfig {
+            max_saved_trie_logs: Some(5),
+            ..config.clone()
+        },
+        24
+    )));
+
+    // The reopened storage has the stored parameters and the latest commit.
+    let mut reopened = Storage::open_existing(db()).unwrap();
+    assert_eq!(reopened.get_latest_id(), Some(BasicId::new(0)));
+    assert_eq!(reopened.get(&identifier, &key).unwrap(), Some(Felt::ONE));
+    let reopened_config = reopened.get_config();
+    assert_eq!(reopened_config.max_saved_trie_logs, Some(10));
+    assert_eq!(reopened_config.max_inline_leaves, Some(4));
+    assert!(matches!(
+        Storage::open_existing(HashMapDb::default()),
+        Err(BonsaiStorageError::Config(ConfigError::MissingMaxHeight))
+    ));
+
+    // Reconfiguring changes the stored parameters, but the inline tries must still be read.
+    assert!(matches!(
+        reopened.reconfigure(BonsaiStorageConfig {
+            max_inline_leaves: None,
+            ..config.clone()
+        }),
+        Err(BonsaiStorageError::Config(
+            ConfigError::StoredConfigMismatch { .. }
+        ))
+    ));
+    let new_config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(5),
+        ..config.clone()
+    };
+    reopened.reconfigure(new_config.clone()).unwrap();
+    let db = reopened.tries.db_ref().db.clone();
+    assert!(is_mismatch(Storage::new(db.clone(), config, 24)));
+    assert!(Storage::new(db, new_config, 24).is_ok());
+}
+
+#[test]
+fn trie_heights_hashmap_db() {
+    type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let mut long_key = BitVec::from_vec(vec![1; 32]);
+    long_key.truncate(251);
+    let mut storage =
+        Storage::new(HashMapDb::default(), BonsaiStorageConfig::default(), 251).unwrap();
+    storage.insert(b"a", &long_key, &Felt::ONE).unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+
+    // The heights of the tries are checked even without the stored configuration.
+    let mut db = storage.tries.db_ref().db.clone();
+    let config_key = TrieKey::new_meta(MetaKeyType::Config, &[]);
+    db.remove(&DatabaseKey::from(&config_key), None).unwrap();
+    let mut reopened = Storage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let is_mismatch = |err| {
+        matches!(
+            err,
+            BonsaiStorageError::TrieHeightMismatch {
+                stored: 251,
+                requested: 24,
+                ..
+            }
+        )
+    };
+    assert!(is_mismatch(
+        reopened.insert(b"a", &key, &Felt::TWO).unwrap_err()
+    ));
+    assert!(is_mismatch(reopened.root_hash(b"a").unwrap_err()));
+
+    // The other tries are stored with the height of the storage they are committed with.
+    reopened.insert(b"b", &key, &Felt::TWO).unwrap();
+    reopened.commit(BasicId::new(1)).unwrap();
+    let db = reopened.tries.db_ref().db.clone();
+    let mut storage = Storage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    storage.insert(b"b", &key, &Felt::THREE).unwrap();
+    storage.commit(BasicId::new(2)).unwrap();
+}
+
+#[test]
+fn prepared_commit_concurrent_reads() {
+    let identifier = vec![];
+    let new_storage = || {
+        BonsaiStorage::<BasicId, _, Pedersen>::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            251,
+        )
+        .unwrap()
+    };
+    let mut bonsai_storage = new_storage();
+    let mut expected_storage = new_storage();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = SmallRng::seed_from_u64(42);
+    let mut ids = Vec::new();
+
+    for _ in 0..2 {
+        let id = id_builder.new_id();
+        ids.push(id);
+        let leaves: Vec<_> = (0..100)
+            .map(|_| {
+                (
+                    Path::from_felt_251(&Felt::from(rng.gen::<u64>())).unwrap(),
+                    Felt::from(rng.gen::<u64>()),
+                )
+            })
+            .collect();
+        for (key, value) in &leaves {
+            bonsai_storage.insert(&identifier, key, value).unwrap();
+            expected_storage.insert(&identifier, key, value).unwrap();
+        }
+        expected_storage.commit(id).unwrap();
+
+        // The storage can be read while the commit is prepared.
+        let prepared = std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                for (key, value) in &leaves {
+                    assert_eq!(bonsai_storage.get(&identifier, key).unwrap(), Some(*value));
+                }
+                bonsai_storage
+                    .get_multi_proof(&identifier, leaves.iter().map(|(key, _)| key))
+                    .unwrap();
+            });
+            let prepared = bonsai_storage.prepare_commit().unwrap();
+            reader.join().unwrap();
+            prepared
+        });
+        bonsai_storage.commit_prepared(id, prepared).unwrap();
+        assert_eq!(
+            bonsai_storage.root_hash(&identifier).unwrap(),
+            expected_storage.root_hash(&identifier).unwrap()
+        );
+        assert_eq!(
+            bonsai_storage.len(&identifier).unwrap(),
+            expected_storage.len(&identifier).unwrap()
+        );
+    }
+
+    // The prepared commits wrote their trie logs along with the trie changes.
+    bonsai_storage.revert_to(ids[0]).unwrap();
+    expected_storage.revert_to(ids[0]).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        expected_storage.root_hash(&identifier).unwrap()
+    );
+
+    // A prepared commit can't be used once the storage changed.
+    bonsai_storage
+        .insert(
+            &identifier,
+            &Path::from_felt_251(&Felt::ONE).unwrap(),
+            &Felt::ONE,
+        )
+        .unwrap();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    bonsai_storage
+        .insert(
+            &identifier,
+            &Path::from_felt_251(&Felt::TWO).unwrap(),
+            &Felt::TWO,
+        )
+        .unwrap();
+    assert!(matches!(
+        bonsai_storage.commit_prepared(id_builder.new_id(), prepared),
+        Err(BonsaiStorageError::PreparedCommitStale)
+    ));
+}
+
+#[test]
+fn metadata() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.put_meta(b"block_hash", b"hash0");
+    assert_eq!(
+        bonsai_storage.get_meta(b"block_hash").unwrap().as_deref(),
+        Some(&b"hash0"[..])
+    );
+    let id0 = id_builder.new_id();
+    bonsai_storage.commit(id0).unwrap();
+    let root_hash = bonsai_storage.root_hash(&identifier).unwrap();
+
+    // Metadata can be committed on its own and does not change the tries.
+    bonsai_storage.put_meta(b"block_hash", b"hash1");
+    bonsai_storage.put_meta(b"sync", b"done");
+    let savepoint = bonsai_storage.savepoint();
+    bonsai_storage.remove_meta(b"sync");
+    assert_eq!(bonsai_storage.get_meta(b"sync").unwrap(), None);
+    bonsai_storage.rollback_to_savepoint(savepoint).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.get_meta(b"sync").unwrap().as_deref(),
+        Some(&b"done"[..])
+    );
+
+    // Transactional states see the metadata of their commit.
+    let txn = bonsai_storage
+        .get_transactional_state(id0, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        txn.get_meta(b"block_hash").unwrap().as_deref(),
+        Some(&b"hash0"[..])
+    );
+    assert_eq!(txn.get_meta(b"sync").unwrap(), None);
+    drop(txn);
+
+    let mut txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.put_meta(b"block_hash", b"hash2");
+    let id2 = id_builder.new_id();
+    txn.transactional_commit(id2).unwrap();
+    bonsai_storage.merge(txn).unwrap();
+    assert_eq!(
+        bonsai_storage.get_meta(b"block_hash").unwrap().as_deref(),
+        Some(&b"hash2"[..])
+    );
+
+    // Reverting the tries reverts the metadata.
+    bonsai_storage.revert_to(id0).unwrap();
+    assert_eq!(
+        bonsai_storage.get_meta(b"block_hash").unwrap().as_deref(),
+        Some(&b"hash0"[..])
+    );
+    assert_eq!(bonsai_storage.get_meta(b"sync").unwrap(), None);
+}
+
+#[test]
+fn metadata_existing_rocks_db() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+
+    // A database created before the metadata column family existed.
+    {
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = rocksdb::OptimisticTransactionDB::<rocksdb::MultiThreaded>::open_cf(
+            &opts,
+            tempdir.path(),
+            ["trie_log", "trie", "flat"],
+        )
+        .unwrap();
+        let flat = db.cf_handle("flat").unwrap();
+        db.put_cf(&flat, b"existing", b"value").unwrap();
+    }
+
+    let root_hash = {
+        let db = open_rocks_db(tempdir.path()).unwrap();
+        assert!(db.cf_handle("meta").is_some());
+        let flat = db.cf_handle("flat").unwrap();
+        assert_eq!(
+            db.get_cf(&flat, b"existing").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+
+        let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+            RocksDB::new(&db, RocksDBConfig::default()),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, 1]),
+                &Felt::from(1u32),
+            )
+            .unwrap();
+        bonsai_storage.put_meta(b"block_hash", b"hash0");
+        bonsai_storage
+            .commit(BasicIdBuilder::new().new_id())
+            .unwrap();
+        bonsai_storage.root_hash(&identifier).unwrap()
+    };
+
+    // Reopening the database keeps the tries and the metadata.
+    let db = open_rocks_db(tempdir.path()).unwrap();
+    let bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.get_meta(b"block_hash").unwrap().as_deref(),
+        Some(&b"hash0"[..])
+    );
+}
+
+#[test]
+fn commit_tags_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let tags = [[1u8; 32], [2; 32], [1; 32], [3; 32], [4; 32]];
+    let mut ids = Vec::new();
+    for (i, tag) in tags.iter().enumerate() {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i as u8]),
+                &Felt::from(i as u32 + 1),
+            )
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit_with_tag(id, tag).unwrap();
+        ids.push(id);
+
+        match i {
+            // The tag of the first commit was moved before its trie log was pruned.
+            2 => {
+                assert_eq!(
+                    bonsai_storage.find_commit_by_tag(&tags[0]).unwrap(),
+                    Some(ids[2])
+                );
+                assert_eq!(
+                    bonsai_storage.find_commit_by_tag(&tags[1]).unwrap(),
+                    Some(ids[1])
+                );
+            }
+            3 => {
+                assert_eq!(bonsai_storage.find_commit_by_tag(&tags[1]).unwrap(), None);
+                assert_eq!(
+                    bonsai_storage.find_commit_by_tag(&tags[3]).unwrap(),
+                    Some(ids[3])
+                );
+            }
+            4 => {
+                assert_eq!(bonsai_storage.find_commit_by_tag(&tags[2]).unwrap(), None);
+                assert_eq!(
+                    bonsai_storage.find_commit_by_tag(&tags[4]).unwrap(),
+                    Some(ids[4])
+                );
+            }
+            _ => {}
+        }
+    }
+
+    bonsai_storage.revert_to(ids[3]).unwrap();
+    assert_eq!(bonsai_storage.find_commit_by_tag(&tags[4]).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.find_commit_by_tag(&tags[3]).unwrap(),
+        Some(ids[3])
+    );
+}
+
+#[test]
+fn commit_meta_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+
+    for i in 0..4u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+        bonsai_storage
+            .commit_with_meta(BasicId::new(i.into()), &[i; 40])
+            .unwrap();
+    }
+    // The metadata of the pruned commits is removed along with their trie log.
+    for i in 0..2u8 {
+        assert_eq!(
+            bonsai_storage
+                .get_commit_meta(BasicId::new(i.into()))
+                .unwrap(),
+            None
+        );
+    }
+    for i in 2..4u8 {
+        assert_eq!(
+            bonsai_storage
+                .get_commit_meta(BasicId::new(i.into()))
+                .unwrap()
+                .as_deref(),
+            Some(&[i; 40][..])
+        );
+    }
+
+    bonsai_storage.revert_to(BasicId::new(2)).unwrap();
+    assert_eq!(
+        bonsai_storage.get_commit_meta(BasicId::new(3)).unwrap(),
+        None
+    );
+    assert!(bonsai_storage
+        .get_commit_meta(BasicId::new(2))
+        .unwrap()
+        .is_some());
+    bonsai_storage.commit(BasicId::new(3)).unwrap();
+    assert_eq!(
+        bonsai_storage.get_commit_meta(BasicId::new(3)).unwrap(),
+        None
+    );
+
+    // The metadata of a failed commit is not left with the uncommitted changes.
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 9]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.add_commit_hook(|_| Err("aborted".into()));
+    assert!(bonsai_storage
+        .commit_with_meta(BasicId::new(4), &[4])
+        .is_err());
+    bonsai_storage.clear_commit_hooks();
+    assert_eq!(
+        bonsai_storage.get_commit_meta(BasicId::new(4)).unwrap(),
+        None
+    );
+    bonsai_storage.commit(BasicId::new(4)).unwrap();
+    assert_eq!(
+        bonsai_storage.get_commit_meta(BasicId::new(4)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn op_stats_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert_eq!(bonsai_storage.take_op_stats(), OpStats::default());
+
+    for i in 0..8u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+    }
+    bonsai_storage.commit(BasicId::new(0)).unwrap();
+    let stats = bonsai_storage.take_op_stats();
+    // The 8 leaves are under 7 binary nodes and an edge from the root.
+    assert_eq!(stats.nodes_hashed, 8);
+    assert!(stats.db_gets > 0);
+    assert!(stats.bytes_written > 0);
+    // The counters are reset by each call.
+    assert_eq!(bonsai_storage.take_op_stats(), OpStats::default());
+
+    // Reading a leaf back walks the nodes down from the root, decoding each of them.
+    bonsai_storage
+        .get_multi_proof(&identifier, [BitVec::from_vec(vec![1, 2, 3])])
+        .unwrap();
+    let stats = bonsai_storage.take_op_stats();
+    assert!(stats.node_decodes > 0);
+    assert!(stats.db_gets >= stats.node_decodes);
+    assert_eq!(stats.nodes_hashed, 0);
+    assert_eq!(stats.bytes_written, 0);
+
+    // Nodes already loaded by an update are not read again.
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 3]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.take_op_stats();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 4]), &Felt::ONE)
+        .unwrap();
+    let stats = bonsai_storage.take_op_stats();
+    assert!(stats.cache_hits > 0);
+}
+
+#[test]
+fn cancellation_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let first_id = id_builder.new_id();
+    for i in 0..8u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+    }
+    bonsai_storage.commit(first_id).unwrap();
+    let first_root = bonsai_storage.root_hash(&identifier).unwrap();
+    let first_keys = bonsai_storage.get_keys(&identifier, None, 100).unwrap();
+
+    for i in 8..16u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+    }
+    let cancel = CancellationToken::new();
+    cancel.clone().cancel();
+    let second_id = id_builder.new_id();
+    assert!(matches!(
+        bonsai_storage.commit_cancellable(second_id, &cancel),
+        Err(BonsaiStorageError::Cancelled)
+    ));
+    // Nothing was written, the changes are still there to be committed.
+    assert_eq!(bonsai_storage.get_latest_id(), Some(first_id));
+    assert_eq!(
+        bonsai_storage
+            .get(&identifier, &BitVec::from_vec(vec![1, 2, 9]))
+            .unwrap(),
+        Some(Felt::from(10))
+    );
+    bonsai_storage
+        .commit_cancellable(second_id, &CancellationToken::new())
+        .unwrap();
+    let second_root = bonsai_storage.root_hash(&identifier).unwrap();
+    assert_ne!(second_root, first_root);
+    assert_eq!(
+        bonsai_storage
+            .get_keys(&identifier, None, 100)
+            .unwrap()
+            .len(),
+        16
+    );
+
+    assert!(matches!(
+        bonsai_storage.get_keys_cancellable(&identifier, None, 100, &cancel),
+        Err(BonsaiStorageError::Cancelled)
+    ));
+
+    assert!(matches!(
+        bonsai_storage.revert_to_cancellable(first_id, &cancel),
+        Err(BonsaiStorageError::Cancelled)
+    ));
+    assert_eq!(bonsai_storage.get_latest_id(), Some(second_id));
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), second_root);
+    bonsai_storage
+        .revert_to_cancellable(first_id, &CancellationToken::new())
+        .unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), first_root);
+    assert_eq!(
+        bonsai_storage
+            .get_keys_cancellable(&identifier, None, 100, &CancellationToken::new())
+            .unwrap(),
+        first_keys
+    );
+}
+
+#[test]
+fn replay_change_batches_hashmap_db() {
+    type DatabaseError = <HashMapDb<BasicId> as crate::BonsaiDatabase>::DatabaseError;
+    let identifier = vec![];
+    let new_storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap()
+    };
+    let mut source = new_storage();
+    let mut replica = new_storage();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut ids = Vec::new();
+    let mut root_hashes = Vec::new();
+    for i in 0..4u8 {
+        source
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 1, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+        source
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 10),
+            )
+            .unwrap();
+        if i > 0 {
+            source
+                .remove(&identifier, &BitVec::from_vec(vec![1, 1, i - 1]))
+                .unwrap();
+        }
+        source.put_meta(b"height", &[i]);
+        let id = id_builder.new_id();
+        source.commit(id).unwrap();
+        ids.push(id);
+        root_hashes.push(source.root_hash(&identifier).unwrap());
+    }
+
+    for (id, root_hash) in ids.iter().zip(root_hashes) {
+        // Send the changes over the network.
+        let changes = source.get_change_batch(*id).unwrap();
+        let serialized: Vec<_> = changes
+            .serialize(id)
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+        let changes = crate::ChangeBatch::deserialize::<_, DatabaseError>(id, serialized).unwrap();
+
+        replica.apply_change_batch(*id, &changes).unwrap();
+        replica.apply_change_batch(*id, &changes).unwrap();
+        assert_eq!(replica.root_hash(&identifier).unwrap(), root_hash);
+    }
+    assert_eq!(
+        replica.get_meta(b"height").unwrap().as_deref(),
+        Some(&[3][..])
+    );
+    assert_eq!(
+        replica.len(&identifier).unwrap(),
+        source.len(&identifier).unwrap()
+    );
+
+    // Malformed trie logs are rejected.
+    let changes = source.get_change_batch(ids[0]).unwrap();
+    let mut serialized: Vec<(ByteVec, ByteVec)> = changes
+        .serialize(&ids[0])
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    let truncated: ByteVec = serialized[0].0[..8].into();
+    let garbage: ByteVec = [0xff; 16].as_slice().into();
+    let mut other_id = serialized[0].0.clone();
+    other_id[7] ^= 1;
+    for invalid_key in [truncated, garbage, other_id] {
+        serialized[0].0 = invalid_key.clone();
+        assert!(matches!(
+            crate::ChangeBatch::deserialize::<_, DatabaseError>(&ids[0], serialized.clone()),
+            Err(BonsaiStorageError::InvalidTrieLogKey { key }) if key == invalid_key
+        ));
+    }
+
+    // A commit made from another state is rejected.
+    let changes = source.get_change_batch(ids[1]).unwrap();
+    assert!(matches!(
+        replica.apply_change_batch(id_builder.new_id(), &changes),
+        Err(BonsaiStorageError::Replay(
+            ReplayError::StateMismatch { .. }
+        ))
+    ));
+
+    // The replayed commits can be reverted like regular ones.
+    replica.revert_to(ids[1]).unwrap();
+    source.revert_to(ids[1]).unwrap();
+    assert_eq!(
+        replica.root_hash(&identifier).unwrap(),
+        source.root_hash(&identifier).unwrap()
+    );
+}
+
+#[test]
+fn raw_values_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut plain_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![1, 2, 3]);
+
+    // Only the commitments are hashed.
+    bonsai_storage
+        .insert_raw(&identifier, &key1, b"first", &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::TWO)
+        .unwrap();
+    plain_storage
+        .insert(&identifier, &key1, &Felt::ONE)
+        .unwrap();
+    plain_storage
+        .insert(&identifier, &key2, &Felt::TWO)
+        .unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(b"first".to_vec())
+    );
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    plain_storage.commit(id1).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        plain_storage.root_hash(&identifier).unwrap()
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(Felt::ONE)
+    );
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(b"first".to_vec())
+    );
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key2).unwrap(),
+        Some(vec![])
+    );
+    assert_eq!(bonsai_storage.get_raw(&identifier, &key3).unwrap(), None);
+
+    // The payload changes with the same commitment, and is dropped by a plain insert.
+    bonsai_storage
+        .insert_raw(&identifier, &key1, b"second", &Felt::ONE)
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::TWO)
+        .unwrap();
+    let savepoint = bonsai_storage.savepoint();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::ONE)
+        .unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(vec![])
+    );
+    bonsai_storage.rollback_to_savepoint(savepoint).unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(b"second".to_vec())
+    );
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        plain_storage.root_hash(&identifier).unwrap()
+    );
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(b"second".to_vec())
+    );
+
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(vec![])
+    );
+
+    // Reverts restore the payloads.
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key1).unwrap(),
+        Some(b"first".to_vec())
+    );
+
+    // So do forks.
+    let mut fork = bonsai_storage.fork();
+    fork.insert_raw(&identifier, &key3, b"third", &Felt::THREE)
+        .unwrap();
+    let changes = fork.into_changes().unwrap();
+    bonsai_storage.merge_fork(changes).unwrap();
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key3).unwrap(),
+        Some(b"third".to_vec())
+    );
+
+    // A zero commitment removes the key and its payload.
+    bonsai_storage
+        .insert_raw(&identifier, &key1, b"removed", &Felt::ZERO)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.get_raw(&identifier, &key1).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.get_raw(&identifier, &key3).unwrap(),
+        Some(b"third".to_vec())
+    );
+}
+
+#[test]
+fn compare_hashmap_db() {
+    let identifier = vec![];
+    let storage = |leaves: &[(BitVec, Felt)]| {
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        for (key, value) in leaves {
+            bonsai_storage.insert(&identifier, key, value).unwrap();
+        }
+        bonsai_storage
+            .commit(BasicIdBuilder::new().new_id())
+            .unwrap();
+        bonsai_storage
+    };
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![200, 0, 0]);
+    let storage_a = storage(&[(key1.clone(), Felt::ONE), (key2.clone(), Felt::TWO)]);
+    let storage_b = storage(&[
+        (key1.clone(), Felt::ONE),
+        (key2.clone(), Felt::THREE),
+        (key3.clone(), Felt::ONE),
+    ]);
+    assert_eq!(
+        compare(&storage_a, &storage_a, &identifier).unwrap(),
+        vec![]
+    );
+
+    // The leaf only in the second trie is reported at the top of the subtrie holding it.
+    let subtrie_hash = hash_edge_node::<Pedersen>(&Path(key3[1..].to_bitvec()), Felt::ONE);
+    assert_eq!(
+        compare(&storage_a, &storage_b, &identifier).unwrap(),
+        vec![
+            TrieDivergence {
+                path: Path(key2),
+                hash_a: Some(Felt::TWO),
+                hash_b: Some(Felt::THREE),
+            },
+            TrieDivergence {
+                path: Path(BitVec::repeat(true, 1)),
+                hash_a: None,
+                hash_b: Some(subtrie_hash),
+            },
+        ]
+    );
+
+    // A node whose hash is wrong although its children are right.
+    let mut db = storage_a.tries.db_ref().db.clone();
+    let root_key = Path::default().trie_db_key(&identifier);
+    let root = db.get(&DatabaseKey::Trie(&root_key)).unwrap().unwrap();
+    let (mut root, _) = Node::decode_versioned(&root).unwrap();
+    let Node::Edge(edge) = &mut root else {
+        panic!("The root of two close leaves is an edge");
+    };
+    edge.hash = Some(Felt::ONE);
+    db.insert(
+        &DatabaseKey::Trie(&root_key),
+        &root.encode_versioned(),
+        None,
+    )
+    .unwrap();
+    let corrupted: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(
+        compare(&storage_a, &corrupted, &identifier).unwrap(),
+        vec![TrieDivergence {
+            path: Path::default(),
+            hash_a: Some(storage_a.root_hash(&identifier).unwrap()),
+            hash_b: Some(Felt::ONE),
+        }]
+    );
+
+    let other_height: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        32,
+    )
+    .unwrap();
+    assert!(compare(&storage_a, &other_height, &identifier).is_err());
+}
+
+#[test]
+fn corrupted_node_heights_hashmap_db() {
+    let identifier = vec![];
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    for key in [[0, 0, 1], [0, 0, 2]] {
+        storage
+            .insert(&identifier, &BitVec::from_vec(key.to_vec()), &Felt::ONE)
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+
+    assert_eq!(Height::new(24, 24).map(Height::get), Some(24));
+    assert_eq!(Height::new(25, 24), None);
+    assert_eq!(Height::new(256, u8::MAX), None);
+    assert!(Height::decode(&mut &256u64.encode()[..]).is_err());
+    assert_eq!(
+        Height::decode(&mut &Height::from(7).encode()[..]).unwrap(),
+        Height::from(7)
+    );
+
+    let root_key = Path::default().trie_db_key(&identifier);
+    let root = storage
+        .tries
+        .db_ref()
+        .db
+        .get(&DatabaseKey::Trie(&root_key))
+        .unwrap()
+        .unwrap();
+    let (root, _) = Node::decode_versioned(&root).unwrap();
+    let corrupt = |corruption: &dyn Fn(&mut EdgeNode)| {
+        let mut root = root.clone();
+        let Node::Edge(edge) = &mut root else {
+            panic!("The root of two close leaves is an edge");
+        };
+        corruption(edge);
+        let mut db = storage.tries.db_ref().db.clone();
+        db.insert(
+            &DatabaseKey::Trie(&root_key),
+            &root.encode_versioned(),
+            None,
+        )
+        .unwrap();
+        let mut corrupted: BonsaiStorage<BasicId, _, Pedersen> =
+            BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+        let key = BitVec::from_vec(vec![1, 2, 3]);
+        assert!(matches!(
+            corrupted.get_multi_proof(&identifier, [&key]),
+            Err(BonsaiStorageError::CorruptedNode(node)) if node.path.0.is_empty()
+        ));
+        assert!(matches!(
+            corrupted.insert(&identifier, &key, &Felt::ONE),
+            Err(BonsaiStorageError::CorruptedNode(node)) if node.path.0.is_empty()
+        ));
+    };
+    // The root is not at height 0.
+    corrupt(&|edge| edge.height = Height::from(3));
+    // The edge goes past the leaves.
+    corrupt(&|edge| edge.path.extend([false; 3]));
+}
+
+#[test]
+fn quarantine_corrupted_nodes_hashmap_db() {
+    let identifier = b"id".to_vec();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    for key in [[0, 0, 1], [0, 0, 2]] {
+        storage
+            .insert(&identifier, &BitVec::from_vec(key.to_vec()), &Felt::ONE)
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+
+    // The binary node above the two leaves.
+    let path = Path(BitVec::repeat(false, 22));
+    let mut db = storage.tries.db_ref().db.clone();
+    db.insert(
+        &DatabaseKey::Trie(&path.trie_db_key(&identifier)),
+        &[0xff, 0xff],
+        None,
+    )
+    .unwrap();
+    let open = |quarantine_corrupted_nodes| {
+        let config = BonsaiStorageConfig {
+            quarantine_corrupted_nodes,
+            ..Default::default()
+        };
+        BonsaiStorage::<BasicId, _, Pedersen>::new(db.clone(), config, 24).unwrap()
+    };
+    let key = BitVec::from_vec(vec![0, 0, 1]);
+
+    let mut corrupted = open(false);
+    let is_corrupted = |err: &BonsaiStorageError<_>| {
+        matches!(
+            err,
+            BonsaiStorageError::CorruptedNode(node)
+                if node.identifier.as_slice() == identifier && node.path == path
+        )
+    };
+    assert!(is_corrupted(
+        &corrupted.get_multi_proof(&identifier, [&key]).unwrap_err()
+    ));
+    assert!(is_corrupted(
+        &corrupted.migrate_nodes(&identifier).unwrap_err()
+    ));
+    assert_eq!(corrupted.quarantined_nodes(&identifier).unwrap(), vec![]);
+
+    let mut quarantined = open(true);
+    assert_eq!(quarantined.migrate_nodes(&identifier).unwrap(), 0);
+    assert_eq!(
+        quarantined.quarantined_nodes(&identifier).unwrap(),
+        vec![(path.clone(), ByteVec::from_slice(&[0xff, 0xff]))]
+    );
+    assert_eq!(quarantined.quarantined_nodes(b"i").unwrap(), vec![]);
+    assert!(matches!(
+        quarantined.get_multi_proof(&identifier, [&key]),
+        Err(BonsaiStorageError::NodeNotFound { path: p, .. }) if p == path
+    ));
+    // The leaves are still readable from the flat storage.
+    assert_eq!(quarantined.get(&identifier, &key).unwrap(), Some(Felt::ONE));
+}
+
+#[test]
+fn rebuild_from_leaves_hashmap_db() {
+    let identifier = b"id".to_vec();
+    let open = |db, max_inline_leaves| {
+        let config = BonsaiStorageConfig {
+            max_inline_leaves,
+            quarantine_corrupted_nodes: true,
+            ..Default::default()
+        };
+        BonsaiStorage::<BasicId, _, Pedersen>::new(db, config, 24).unwrap()
+    };
+    let keys = [[0, 0, 1], [0, 0, 2], [0, 0, 3]].map(|key| BitVec::from_vec(key.to_vec()));
+
+    for max_inline_leaves in [None, Some(4)] {
+        let mut storage = open(HashMapDb::<BasicId>::default(), max_inline_leaves);
+        for key in &keys {
+            storage.insert(&identifier, key, &Felt::ONE).unwrap();
+        }
+        storage.commit(BasicId::new(0)).unwrap();
+        let root_hash = storage.root_hash(&identifier).unwrap();
+        assert_eq!(storage.rebuild_from_leaves(&identifier).unwrap(), 0);
+
+        // A corrupted node, a stray one, and the record of the inline trie.
+        let corrupted = Path(BitVec::repeat(false, 22));
+        let stray = Path(BitVec::repeat(true, 3));
+        let mut db = storage.tries.db_ref().db.clone();
+        for path in [&corrupted, &stray] {
+            db.insert(
+                &DatabaseKey::Trie(&path.trie_db_key(&identifier)),
+                &[0xff, 0xff],
+                None,
+            )
+            .unwrap();
+        }
+        let mut inline_key = identifier.clone();
+        inline_key.extend([0xff, 0]);
+        if max_inline_leaves.is_some() {
+            db.insert(&DatabaseKey::Trie(&inline_key), &[0xff], None)
+                .unwrap();
+        }
+
+        let mut storage = open(db, max_inline_leaves);
+        let expected = if max_inline_leaves.is_some() { 3 } else { 2 };
+        assert_eq!(storage.rebuild_from_leaves(&identifier).unwrap(), expected);
+        assert_eq!(storage.rebuild_from_leaves(&identifier).unwrap(), 0);
+        assert_eq!(storage.root_hash(&identifier).unwrap(), root_hash);
+        assert!(storage.get_multi_proof(&identifier, &keys).is_ok());
+        assert_eq!(storage.migrate_nodes(&identifier).unwrap(), 0);
+        assert_eq!(storage.quarantined_nodes(&identifier).unwrap(), vec![]);
+
+        // The trie is still reverted through its trie logs.
+        storage.remove(&identifier, &keys[0]).unwrap();
+        storage.commit(BasicId::new(1)).unwrap();
+        storage.revert_to(BasicId::new(0)).unwrap();
+        assert_eq!(storage.root_hash(&identifier).unwrap(), root_hash);
+        assert_eq!(storage.rebuild_from_leaves(&identifier).unwrap(), 0);
+
+        storage.insert(&identifier, &keys[0], &Felt::TWO).unwrap();
+        assert!(matches!(
+            storage.rebuild_from_leaves(&identifier),
+            Err(BonsaiStorageError::TrieUncommittedChanges { .. })
+        ));
+    }
+}
+
+#[test]
+fn incremental_builder_hashmap_db() {
+    let identifier = vec![1, 2];
+    let mut rng = SmallRng::seed_from_u64(7);
+    let mut leaves: Vec<(BitVec, Felt)> = (0..200)
+        .map(|_| {
+            let key = rng.gen_range(0u32..1 << 24);
+            (
+                BitVec::from_vec(key.to_be_bytes()[1..].to_vec()),
+                Felt::from(rng.gen_range(1u64..u64::MAX)),
+            )
+        })
+        .collect();
+    leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+    leaves.dedup_by(|(a, _), (b, _)| a == b);
+
+    let mut db = HashMapDb::<BasicId>::default();
+    let mut batch = db.create_batch();
+    let mut builder = IncrementalTrieBuilder::<Pedersen>::new(&identifier, 24);
+    for (key, value) in &leaves {
+        builder.push(&mut db, &mut batch, key, *value).unwrap();
+    }
+    // Zero values are skipped.
+    builder
+        .push(
+            &mut db,
+            &mut batch,
+            &BitVec::from_vec(vec![0xFF; 3]),
+            Felt::ZERO,
+        )
+        .unwrap();
+    let root_hash = builder.finish(&mut db, &mut batch).unwrap();
+    db.write_batch(batch).unwrap();
+
+    let sorted: Vec<_> = leaves
+        .iter()
+        .map(|(key, value)| (key.as_bitslice(), *value))
+        .collect();
+    assert_eq!(
+        root_hash,
+        MerkleTree::<Pedersen>::root_from_sorted_leaves(&sorted)
+    );
+    let mut id_builder = BasicIdBuilder::new();
+    let mut reference: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    for (key, value) in leaves.iter().rev() {
+        reference.insert(&identifier, key, value).unwrap();
+    }
+    reference.commit(id_builder.new_id()).unwrap();
+    assert_eq!(root_hash, reference.root_hash(&identifier).unwrap());
+
+    // The built trie can be read and updated by a storage.
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.len(&identifier).unwrap(),
+        leaves.len() as u64
+    );
+    for (key, value) in &leaves {
+        assert_eq!(bonsai_storage.get(&identifier, key).unwrap(), Some(*value));
+    }
+    let updates = [
+        (leaves[3].0.clone(), Felt::ZERO),
+        (leaves[50].0.clone(), Felt::THREE),
+        (BitVec::from_vec(vec![0xFF; 3]), Felt::TWO),
+    ];
+    for (key, value) in &updates {
+        bonsai_storage.insert(&identifier, key, value).unwrap();
+        reference.insert(&identifier, key, value).unwrap();
+    }
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    reference.commit(id).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        reference.root_hash(&identifier).unwrap()
+    );
+
+    // Keys must be increasing and have the height of the trie.
+    let mut db = HashMapDb::<BasicId>::default();
+    let mut batch = db.create_batch();
+    let mut builder = IncrementalTrieBuilder::<Pedersen>::new(&identifier, 24);
+    builder
+        .push(&mut db, &mut batch, &leaves[1].0, Felt::ONE)
+        .unwrap();
+    for key in [&leaves[1].0, &leaves[0].0] {
+        assert!(matches!(
+            builder.push(&mut db, &mut batch, key, Felt::ONE),
+            Err(BonsaiStorageError::Trie(_))
+        ));
+    }
+    assert!(matches!(
+        builder.push(
+            &mut db,
+            &mut batch,
+            &BitVec::from_vec(vec![0xFF; 4]),
+            Felt::ONE
+        ),
+        Err(BonsaiStorageError::KeyLength {
+            expected: 24,
+            got: 32
+        })
+    ));
+
+    let builder = IncrementalTrieBuilder::<Pedersen>::new(&identifier, 24);
+    assert_eq!(builder.finish(&mut db, &mut batch).unwrap(), Felt::ZERO);
+}
+
+#[test]
+fn bulk_load_hashmap_db() {
+    let identifier = vec![1];
+    let other_identifier = vec![2];
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let other_key = BitVec::from_vec(vec![1, 2, 3]);
+    bonsai_storage
+        .insert(&other_identifier, &other_key, &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let before = id_builder.new_id();
+    bonsai_storage.commit(before).unwrap();
+
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut leaves: Vec<(BitVec, Felt)> = (0..300)
+        .map(|_| {
+            let key = rng.gen_range(0u32..1 << 24);
+            (
+                BitVec::from_vec(key.to_be_bytes()[1..].to_vec()),
+                Felt::from(rng.gen_range(1u64..u64::MAX)),
+            )
+        })
+        .collect();
+    leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+    leaves.dedup_by(|(a, _), (b, _)| a == b);
+    let sorted: Vec<_> = leaves
+        .iter()
+        .map(|(key, value)| (key.as_bitslice(), *value))
+        .collect();
+    let expected_root = MerkleTree::<Pedersen>::root_from_sorted_leaves(&sorted);
+
+    // The storage must have no uncommitted changes, and the id must be a new one.
+    bonsai_storage
+        .insert(&other_identifier, &other_key, &Felt::TWO)
+        .unwrap();
+    let load_id = id_builder.new_id();
+    assert!(matches!(
+        bonsai_storage.bulk_load(&identifier, leaves.clone(), load_id),
+        Err(BonsaiStorageError::UncommittedChanges)
+    ));
+    bonsai_storage.revert_to(before).unwrap();
+    assert!(matches!(
+        bonsai_storage.bulk_load(&identifier, leaves.clone(), before),
+        Err(BonsaiStorageError::BulkLoad(_))
+    ));
+
+    let root_hash = bonsai_storage
+        .bulk_load(&identifier, leaves.clone(), load_id)
+        .unwrap();
+    assert_eq!(root_hash, expected_root);
+    assert_eq!(bonsai_storage.get_latest_id(), Some(load_id));
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier, load_id).unwrap(),
+        Some(root_hash)
+    );
+    assert_eq!(
+        bonsai_storage.len(&identifier).unwrap(),
+        leaves.len() as u64
+    );
+    for (key, value) in &leaves {
+        assert_eq!(bonsai_storage.get(&identifier, key).unwrap(), Some(*value));
+    }
+    assert_eq!(
+        bonsai_storage.get(&other_identifier, &other_key).unwrap(),
+        Some(Felt::ONE)
+    );
+    // Only the leaves of empty tries can be loaded.
+    assert!(matches!(
+        bonsai_storage.bulk_load(&identifier, leaves.clone(), id_builder.new_id()),
+        Err(BonsaiStorageError::BulkLoad(_))
+    ));
+
+    // The commits before the load are out of reach.
+    assert!(matches!(
+        bonsai_storage.revert_to(before),
+        Err(BonsaiStorageError::GoTo(_))
+    ));
+    assert!(bonsai_storage
+        .get_transactional_state(load_id, BonsaiStorageConfig::default())
+        .unwrap()
+        .is_none());
+    assert!(bonsai_storage
+        .get_transactional_state(before, BonsaiStorageConfig::default())
+        .unwrap()
+        .is_some());
+    bonsai_storage.create_snapshot_now(load_id).unwrap();
+    let transactional_state = bonsai_storage
+        .get_transactional_state(load_id, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        transactional_state.root_hash(&identifier).unwrap(),
+        root_hash
+    );
+
+    // The loaded trie is updated as any other.
+    let mut reference: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    for (key, value) in &leaves {
+        reference.insert(&identifier, key, value).unwrap();
+    }
+    reference.commit(BasicId::new(0)).unwrap();
+    for (key, value) in [
+        (&leaves[0].0, Felt::ZERO),
+        (&leaves[100].0, Felt::ONE),
+        (&other_key, Felt::ONE),
+    ] {
+        bonsai_storage.insert(&identifier, key, &value).unwrap();
+        reference.insert(&identifier, key, &value).unwrap();
+    }
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    reference.commit(BasicId::new(1)).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        reference.root_hash(&identifier).unwrap()
+    );
+    bonsai_storage.revert_to(load_id).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+}
+
+#[test]
+fn max_batch_bytes_hashmap_db() {
+    let identifiers = [vec![1], vec![2, 3]];
+    let storage = |max_batch_bytes| -> BonsaiStorage<BasicId, _, Pedersen> {
+        let config = BonsaiStorageConfig {
+            max_batch_bytes,
+            ..Default::default()
+        };
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap()
+    };
+    // The batches are written every few entries.
+    let mut chunked = storage(Some(100));
+    let mut single = storage(None);
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = SmallRng::seed_from_u64(5);
+    for _ in 0..4 {
+        for _ in 0..50 {
+            let identifier = identifiers.choose(&mut rng).unwrap();
+            let key = BitVec::from_vec(rng.gen_range(0u32..1 << 24).to_be_bytes()[1..].to_vec());
+            let value = Felt::from(rng.gen_range(0u64..4));
+            chunked.insert(identifier, &key, &value).unwrap();
+            single.insert(identifier, &key, &value).unwrap();
+        }
+        let id = id_builder.new_id();
+        chunked.commit(id).unwrap();
+        single.commit(id).unwrap();
+    }
+
+    // Both end up with the same database.
+    let columns = [
+        DatabaseKey::Trie(&[]),
+        DatabaseKey::Flat(&[]),
+        DatabaseKey::TrieLog(&[]),
+        DatabaseKey::Meta(&[]),
+    ];
+    for column in &columns {
+        let mut chunked_entries = chunked.tries.db_ref().db.get_by_prefix(column).unwrap();
+        let mut single_entries = single.tries.db_ref().db.get_by_prefix(column).unwrap();
+        chunked_entries.sort();
+        single_entries.sort();
+        assert_eq!(chunked_entries, single_entries);
+    }
+    for identifier in &identifiers {
+        assert_eq!(
+            chunked.root_hash(identifier).unwrap(),
+            single.root_hash(identifier).unwrap()
+        );
+    }
+    chunked.revert_to(BasicId::new(1)).unwrap();
+    single.revert_to(BasicId::new(1)).unwrap();
+    for identifier in &identifiers {
+        assert_eq!(
+            chunked.root_hash(identifier).unwrap(),
+            single.root_hash(identifier).unwrap()
+        );
+    }
+
+    assert_eq!(
+        BonsaiStorageConfig {
+            max_batch_bytes: Some(0),
+            ..Default::default()
+        }
+        .validate(),
+        Err(ConfigError::ZeroMaxBatchBytes)
+    );
+}
+
+#[test]
+fn commit_from_rayon_worker_hashmap_db() {
+    let storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap()
+    };
+    // More tries than the commit pipeline holds, committed from outside and inside the pool.
+    let mut outside = storage();
+    let mut inside = storage();
+    let mut rng = SmallRng::seed_from_u64(8);
+    for _ in 0..200 {
+        let identifier = [rng.gen_range(0u8..16)];
+        let key = BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec());
+        let value = Felt::from(rng.gen_range(1u64..8));
+        outside.insert(&identifier, &key, &value).unwrap();
+        inside.insert(&identifier, &key, &value).unwrap();
+    }
+    outside.commit(BasicId::new(0)).unwrap();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap()
+        .install(|| inside.commit(BasicId::new(0)))
+        .unwrap();
+    for identifier in 0u8..16 {
+        assert_eq!(
+            outside.root_hash(&[identifier]).unwrap(),
+            inside.root_hash(&[identifier]).unwrap()
+        );
+    }
+}
+
+#[test]
+fn proven_root_hashmap_db() {
+    let identifier = vec![1];
+    let mut full: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut rng = SmallRng::seed_from_u64(3);
+    let keys: Vec<BitVec> = (0..64)
+        .map(|_| BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec()))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        full.insert(&identifier, key, &Felt::from(i + 1)).unwrap();
+        full.insert(&[2], key, &Felt::from(i + 2)).unwrap();
+    }
+    full.commit(BasicId::new(0)).unwrap();
+    let root = full.root_hash(&identifier).unwrap();
+    let absent = BitVec::from_vec(vec![0, 0, 0]);
+    let proven_keys = [&keys[3], &keys[10], &absent];
+    let proof = full.get_multi_proof(&identifier, proven_keys).unwrap();
+
+    let mut light: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert!(light
+        .insert_proof_nodes(&identifier, proof.clone())
+        .is_err());
+    light.set_proven_root(&identifier, root).unwrap();
+    assert_eq!(light.root_hash(&identifier).unwrap(), root);
+    assert!(matches!(
+        light.get(&identifier, &keys[3]),
+        Err(BonsaiStorageError::Proof(err)) if matches!(*err, ProofVerificationError::MissingNode { .. })
+    ));
+
+    // A node that does not hash to its key is refused.
+    let mut tampered = proof.clone();
+    tampered.0.insert(
+        root,
+        ProofNode::Binary {
+            left: Felt::ONE,
+            right: Felt::TWO,
+        },
+    );
+    assert!(matches!(
+        light.insert_proof_nodes(&identifier, tampered),
+        Err(BonsaiStorageError::Proof(err)) if matches!(*err, ProofVerificationError::HashMismatch { .. })
+    ));
+
+    // Nodes which are not linked to the root are ignored.
+    let other = full.get_multi_proof(&[2], [&keys[0]]).unwrap();
+    assert_eq!(light.insert_proof_nodes(&identifier, other).unwrap(), 0);
+    assert_eq!(
+        light
+            .insert_proof_nodes(&identifier, proof.clone())
+            .unwrap(),
+        proof.0.len()
+    );
+    assert_eq!(light.insert_proof_nodes(&identifier, proof).unwrap(), 0);
+
+    assert_eq!(
+        light.get_many(&identifier, proven_keys).unwrap(),
+        full.get_many(&identifier, proven_keys).unwrap()
+    );
+    assert!(light.contains(&identifier, &keys[10]).unwrap());
+    assert!(!light.contains(&identifier, &absent).unwrap());
+    // Keys on paths the proof does not cover can't be answered.
+    let unproven = keys
+        .iter()
+        .find(|key| light.get(&identifier, key).is_err())
+        .unwrap();
+    assert!(full.get(&identifier, unproven).unwrap().is_some());
+    assert!(light.insert(&identifier, &keys[3], &Felt::ONE).is_err());
+
+    light.set_proven_root(&[2], Felt::ZERO).unwrap();
+    assert_eq!(light.get(&[2], &keys[3]).unwrap(), None);
+
+    light.clear_proven_root(&identifier);
+    assert_eq!(light.root_hash(&identifier).unwrap(), Felt::ZERO);
+    assert_eq!(light.get(&identifier, &keys[3]).unwrap(), None);
+}
+
+#[test]
+fn witness_hashmap_db() {
+    let (a, b, c) = (
+        b"contracts".to_vec(),
+        b"classes".to_vec(),
+        b"storage".to_vec(),
+    );
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut rng = SmallRng::seed_from_u64(5);
+    let keys: Vec<BitVec> = (0..64)
+        .map(|_| BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec()))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        for identifier in [&a, &b, &c] {
+            bonsai_storage
+                .insert(identifier, key, &Felt::from(i + 1))
+                .unwrap();
+        }
+    }
+    bonsai_storage.commit(BasicId::new(0)).unwrap();
+    let new_key = BitVec::from_vec(vec![0, 0, 0]);
+    let mut committed = HashMap::new();
+    for identifier in [&a, &b] {
+        for key in keys.iter().chain([&new_key]) {
+            let value = bonsai_storage.get(identifier, key).unwrap();
+            committed.insert((identifier.clone(), key.clone()), value);
+        }
+        committed.insert(
+            (identifier.clone(), BitVec::new()),
+            Some(bonsai_storage.root_hash(identifier).unwrap()),
+        );
+    }
+
+    // Nothing is recorded before the witness is started.
+    bonsai_storage.get(&c, &keys[0]).unwrap();
+    bonsai_storage.record_witness();
+    bonsai_storage.get(&a, &keys[1]).unwrap();
+    bonsai_storage.get_many(&a, [&keys[2], &keys[3]]).unwrap();
+    bonsai_storage.contains(&a, &new_key).unwrap();
+    bonsai_storage.insert(&b, &keys[4], &Felt::TWO).unwrap();
+    bonsai_storage.insert(&b, &new_key, &Felt::ONE).unwrap();
+    bonsai_storage.remove(&b, &keys[5]).unwrap();
+    let witness = bonsai_storage.finish_witness().unwrap();
+    bonsai_storage.get(&c, &keys[6]).unwrap();
+    assert!(bonsai_storage.finish_witness().unwrap().tries.is_empty());
+
+    assert_eq!(witness.tries.len(), 2);
+    let mut expected_a = vec![
+        keys[1].clone(),
+        keys[2].clone(),
+        keys[3].clone(),
+        new_key.clone(),
+    ];
+    let mut expected_b = vec![keys[4].clone(), keys[5].clone(), new_key.clone()];
+    expected_a.sort();
+    expected_b.sort();
+    assert_eq!(witness.tries[a.as_slice()].keys, expected_a);
+    assert_eq!(witness.tries[b.as_slice()].keys, expected_b);
+
+    // The witness proves the committed values of the keys, even the written ones.
+    for (identifier, trie) in &witness.tries {
+        assert_eq!(
+            Some(trie.root),
+            committed[&(identifier.to_vec(), BitVec::new())]
+        );
+        let mut light: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        light.set_proven_root(identifier, trie.root).unwrap();
+        light
+            .insert_proof_nodes(identifier, trie.proof.clone())
+            .unwrap();
+        for key in &trie.keys {
+            assert_eq!(
+                light.get(identifier, key).unwrap(),
+                committed[&(identifier.to_vec(), key.clone())]
+            );
+        }
+    }
+}
+
+#[test]
+fn trie_hashers_hashmap_db() {
+    let (contracts, classes, bulk) = (b"contracts", b"classes", b"bulk");
+    let mut config = BonsaiStorageConfig::default();
+    for identifier in [classes.as_slice(), bulk] {
+        config
+            .trie_hashers
+            .insert(identifier.into(), TrieHasher::new::<Poseidon>());
+    }
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut pedersen: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut poseidon: BonsaiStorage<BasicId, _, Poseidon> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+
+    let mut rng = SmallRng::seed_from_u64(5);
+    let mut keys: Vec<BitVec> = (0..64)
+        .map(|_| BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec()))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    for (i, key) in keys.iter().enumerate() {
+        let value = Felt::from(i + 1);
+        storage.insert(contracts, key, &value).unwrap();
+        storage.insert(classes, key, &value).unwrap();
+        pedersen.insert(contracts, key, &value).unwrap();
+        poseidon.insert(classes, key, &value).unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    pedersen.commit(BasicId::new(0)).unwrap();
+    poseidon.commit(BasicId::new(0)).unwrap();
+    let classes_root = poseidon.root_hash(classes).unwrap();
+    assert_eq!(
+        storage.root_hash(contracts).unwrap(),
+        pedersen.root_hash(contracts).unwrap()
+    );
+    assert_eq!(storage.root_hash(classes).unwrap(), classes_root);
+    assert_ne!(classes_root, pedersen.root_hash(contracts).unwrap());
+
+    // The proofs of a trie are checked with its own hasher.
+    let proof = storage.get_multi_proof(classes, [&keys[5]]).unwrap();
+    assert_eq!(
+        proof
+            .verify_proof::<Poseidon>(classes_root, [&keys[5]], 24)
+            .next()
+            .unwrap()
+            .unwrap(),
+        Felt::from(6)
+    );
+
+    // So are the bulk loaded tries.
+    let leaves = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.clone(), Felt::from(i + 1)));
+    assert_eq!(
+        storage.bulk_load(bulk, leaves, BasicId::new(1)).unwrap(),
+        classes_root
+    );
+
+    // The transactional states keep the hashers of the storage.
+    storage.create_snapshot_now(BasicId::new(1)).unwrap();
+    let mut txn = storage
+        .get_transactional_state(BasicId::new(1), BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.insert(classes, &keys[0], &Felt::from(1000)).unwrap();
+    txn.commit(BasicId::new(2)).unwrap();
+    poseidon
+        .insert(classes, &keys[0], &Felt::from(1000))
+        .unwrap();
+    poseidon.commit(BasicId::new(1)).unwrap();
+    assert_eq!(
+        txn.root_hash(classes).unwrap(),
+        poseidon.root_hash(classes).unwrap()
+    );
+}
+
+#[test]
+fn sparse_trie_hashmap_db() {
+    let (identifier, bulk) = (b"sparse", b"bulk");
+    let default = Felt::from(7);
+    let hasher = TrieHasher::sparse::<Pedersen>(default);
+    let mut config = BonsaiStorageConfig::default();
+    for identifier in [identifier.as_slice(), bulk] {
+        config
+            .trie_hashers
+            .insert(identifier.into(), hasher.clone());
+    }
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 8).unwrap();
+    // Root of the complete binary tree of the 256 keys, hashed level by level.
+    let smt_root = |leaves: &HashMap<u8, Felt>| {
+        let mut level: Vec<Felt> = (0..=255u8)
+            .map(|key| leaves.get(&key).copied().unwrap_or(default))
+            .collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| Pedersen::hash(&pair[0], &pair[1]))
+                .collect();
+        }
+        level[0]
+    };
+    let key = |byte: u8| BitVec::from_vec(vec![byte]);
+
+    let mut leaves = HashMap::new();
+    assert_eq!(storage.root_hash(identifier).unwrap(), smt_root(&leaves));
+    assert_eq!(storage.get(identifier, &key(3)).unwrap(), Some(default));
+    assert!(!storage.contains(identifier, &key(3)).unwrap());
+
+    let mut rng = SmallRng::seed_from_u64(9);
+    // Above the default value, which would remove the keys.
+    for _ in 0..40 {
+        let (byte, value) = (rng.gen::<u8>(), Felt::from(rng.gen_range(8..100u64)));
+        storage.insert(identifier, &key(byte), &value).unwrap();
+        leaves.insert(byte, value);
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    let root = storage.root_hash(identifier).unwrap();
+    assert_eq!(root, smt_root(&leaves));
+
+    // Setting a key to the default value removes it.
+    let (&removed, _) = leaves.iter().next().unwrap();
+    storage.insert(identifier, &key(removed), &default).unwrap();
+    leaves.remove(&removed);
+    assert!(!storage.contains(identifier, &key(removed)).unwrap());
+    storage.commit(BasicId::new(1)).unwrap();
+    assert_eq!(storage.root_hash(identifier).unwrap(), smt_root(&leaves));
+    assert_eq!(
+        storage.get(identifier, &key(removed)).unwrap(),
+        Some(default)
+    );
+
+    // The proofs of default values are non-membership proofs.
+    let member = *leaves.keys().next().unwrap();
+    let proof = storage
+        .get_multi_proof(identifier, [key(member), key(removed)])
+        .unwrap();
+    let values: Vec<Felt> = proof
+        .verify_proof_with(
+            hasher.clone(),
+            storage.root_hash(identifier).unwrap(),
+            [key(member), key(removed)],
+            8,
+        )
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values, [leaves[&member], Felt::ZERO]);
+    let compact = proof.encode_compact(storage.root_hash(identifier).unwrap(), 8);
+    let decoded = MultiProof::decode_compact_with(&hasher, &compact, 8).unwrap();
+    assert_eq!(decoded.0, proof.0);
+
+    // A bulk load gives the same root as the inserts.
+    let mut sorted: Vec<_> = leaves.iter().map(|(byte, value)| (*byte, *value)).collect();
+    sorted.sort_by_key(|(byte, _)| *byte);
+    let loaded = storage
+        .bulk_load(
+            bulk,
+            sorted.iter().map(|(byte, value)| (key(*byte), *value)),
+            BasicId::new(2),
+        )
+        .unwrap();
+    assert_eq!(loaded, smt_root(&leaves));
+}
+
+#[test]
+fn fixed_depth_trie_hashmap_db() {
+    let identifier = b"binary";
+    let mut config = BonsaiStorageConfig::default();
+    config.trie_hashers.insert(
+        identifier.as_slice().into(),
+        FixedDepthMerkleTree::<Poseidon>::hasher(),
+    );
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 8).unwrap();
+    assert_eq!(
+        storage.root_hash(identifier).unwrap(),
+        FixedDepthMerkleTree::<Poseidon>::empty_root(8)
+    );
+
+    let mut leaves = [Felt::ZERO; 256];
+    let mut rng = SmallRng::seed_from_u64(3);
+    for _ in 0..20 {
+        let (byte, value) = (rng.gen::<u8>(), Felt::from(rng.gen::<u64>()));
+        storage
+            .insert(identifier, &BitVec::from_vec(vec![byte]), &value)
+            .unwrap();
+        leaves[byte as usize] = value;
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+
+    // Plain binary Merkle tree of the 256 leaves, without edge nodes.
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| Poseidon::hash(&pair[0], &pair[1]))
+            .collect();
+    }
+    assert_eq!(storage.root_hash(identifier).unwrap(), level[0]);
+}
+
+/// Node family hashing the branches and edges with arrays of Poseidon hashes.
+struct ArrayFamily {
+    arity: usize,
+}
+
+impl TrieNodeFamily for ArrayFamily {
+    fn name(&self) -> &str {
+        "array"
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn hash_branch(&self, children: &[Felt]) -> Felt {
+        Poseidon::hash_array(children)
+    }
+
+    fn hash_edge(&self, path: &Path, child_hash: Felt, _depth: usize, _max_height: u8) -> Felt {
+        let path_felt = Felt::from(path.iter().fold(0u64, |acc, bit| 2 * acc + *bit as u64));
+        Poseidon::hash_array(&[child_hash, path_felt, Felt::from(path.len())])
+    }
+}
+
+#[test]
+fn trie_node_families_hashmap_db() {
+    let identifier = b"array";
+    let mut config = BonsaiStorageConfig::default();
+    config.trie_hashers.insert(
+        identifier.as_slice().into(),
+        TrieHasher::from_family(ArrayFamily { arity: 2 }),
+    );
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 8).unwrap();
+    let (left, right) = (
+        BitVec::from_vec(vec![0b0000_0001]),
+        BitVec::from_vec(vec![0b1000_0001]),
+    );
+    storage.insert(identifier, &left, &Felt::ONE).unwrap();
+    storage.insert(identifier, &right, &Felt::TWO).unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+
+    // The root is a branch whose children are edges of the last 7 bits.
+    let family = ArrayFamily { arity: 2 };
+    let edge = |key: &BitVec, value| family.hash_edge(&Path(key[1..].to_bitvec()), value, 1, 8);
+    let expected = family.hash_branch(&[edge(&left, Felt::ONE), edge(&right, Felt::TWO)]);
+    assert_eq!(storage.root_hash(identifier).unwrap(), expected);
+    let proof = storage.get_multi_proof(identifier, [&left]).unwrap();
+    assert_eq!(
+        proof
+            .verify_proof_with(
+                TrieHasher::from_family(ArrayFamily { arity: 2 }),
+                expected,
+                [&left],
+                8
+            )
+            .next()
+            .unwrap()
+            .unwrap(),
+        Felt::ONE
+    );
+
+    // The tries can only be binary.
+    config.trie_hashers.insert(
+        identifier.as_slice().into(),
+        TrieHasher::from_family(ArrayFamily { arity: 16 }),
+    );
+    assert_eq!(
+        config.validate(),
+        Err(ConfigError::UnsupportedArity {
+            identifier: identifier.as_slice().into(),
+            arity: 16
+        })
+    );
+}
+
+#[test]
+// The identifiers of the tries prefix each other, which `key_checks` rejects.
+#[cfg(not(feature = "key_checks"))]
+fn paginated_keys_hashmap_db() {
+    // The leaves of `keys2` share the prefix of the flat keys of `keys`.
+    let (identifier, other) = (b"keys".as_slice(), b"keys2".as_slice());
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    assert!(storage.iter_keys(identifier, None).next().is_none());
+
+    let mut rng = SmallRng::seed_from_u64(8);
+    let mut leaves = std::collections::BTreeMap::new();
+    for i in 0..100u64 {
+        let mut key = rng.gen::<[u8; 3]>().to_vec();
+        // Odd, so that the even keys are not in the trie.
+        key[2] |= 1;
+        storage
+            .insert(
+                identifier,
+                &BitVec::from_vec(key.clone()),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+        storage
+            .insert(other, &BitVec::from_vec(key.clone()), &Felt::ONE)
+            .unwrap();
+        leaves.insert(key, Felt::from(i + 1));
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    // Uncommitted changes are not listed.
+    storage
+        .insert(identifier, &BitVec::from_vec(vec![0, 0, 0]), &Felt::ONE)
+        .unwrap();
+
+    let mut pages = Vec::new();
+    let mut start_after = None;
+    loop {
+        let page = storage
+            .get_key_value_pairs(identifier, start_after.as_deref(), 7)
+            .unwrap();
+        let Some((last, _)) = page.last() else {
+            break;
+        };
+        start_after = Some(last.clone());
+        pages.extend(page);
+    }
+    let expected: Vec<_> = leaves
+        .iter()
+        .map(|(key, value)| (key.clone(), value.encode()))
+        .collect();
+    assert_eq!(pages, expected);
+
+    // The cursor does not have to be a key of the trie.
+    let (middle, _) = leaves.iter().nth(50).unwrap();
+    let mut before_middle = middle.clone();
+    before_middle[2] -= 1;
+    let keys: Vec<Vec<u8>> = storage
+        .iter_keys(identifier, Some(&before_middle))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(keys, leaves.keys().skip(50).cloned().collect::<Vec<_>>());
+    assert_eq!(
+        storage.get_keys(identifier, Some(middle), 1).unwrap(),
+        leaves.keys().skip(51).take(1).cloned().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn scan_hashmap_db() {
+    let identifier = b"balances".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        8,
+    )
+    .unwrap();
+    for key in 0..=255u8 {
+        let value = Felt::from(key % 10 + 1);
+        storage
+            .insert(identifier, &BitVec::from_vec(vec![key]), &value)
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+
+    let matching: Vec<(Vec<u8>, Felt)> = storage
+        .scan(identifier, |value| *value == Felt::from(10))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let expected: Vec<_> = (0..=255u8)
+        .filter(|key| key % 10 == 9)
+        .map(|key| (vec![key], Felt::from(10)))
+        .collect();
+    assert_eq!(matching, expected);
+}
+
+#[test]
+fn compact_proof_hashmap_db() {
+    let identifier = b"a".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        251,
+    )
+    .unwrap();
+    let key = |i: u64| BitVec::from_vec(Felt::from(i).to_bytes_be().to_vec())[5..].to_bitvec();
+    let mut rng = SmallRng::seed_from_u64(3);
+    for _ in 0..200 {
+        let i = rng.gen_range(0..1000u64);
+        storage
+            .insert(identifier, &key(i), &Felt::from(i + 1))
+            .unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    let root = storage.root_hash(identifier).unwrap();
+
+    // Adjacent keys share most of their path, whose nodes are in the proof once.
+    let keys: Vec<_> = (100..140).map(key).collect();
+    let proof = storage.get_multi_proof(identifier, &keys).unwrap();
+    let single = storage.get_multi_proof(identifier, [&keys[0]]).unwrap();
+    assert!(proof.0.len() < keys.len() * single.0.len() / 4);
+
+    let compact = proof.encode_compact(root, 251);
+    assert!(compact.len() < proof.0.len() * 64);
+    let decoded = MultiProof::decode_compact::<Pedersen>(&compact, 251).unwrap();
+    assert_eq!(decoded.0, proof.0);
+    let values: Vec<Felt> = decoded
+        .verify_proof::<Pedersen>(root, &keys, 251)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let expected: Vec<Felt> = keys
+        .iter()
+        .map(|key| storage.get(identifier, key).unwrap().unwrap_or(Felt::ZERO))
+        .collect();
+    assert_eq!(values, expected);
+
+    // The nodes of other tries are left out.
+    let mut mixed = proof.clone();
+    mixed.0.extend(single.0.clone());
+    mixed.0.insert(
+        Felt::THREE,
+        ProofNode::Binary {
+            left: Felt::ONE,
+            right: Felt::TWO,
+        },
+    );
+    assert_eq!(mixed.encode_compact(root, 251), compact);
+
+    // An empty trie has nothing to encode.
+    assert!(proof.encode_compact(Felt::ONE, 251).is_empty());
+    assert!(MultiProof::decode_compact::<Pedersen>(&[], 251)
+        .unwrap()
+        .0
+        .is_empty());
+    for bytes in [
+        &compact[..compact.len() - 1],
+        &[compact.as_slice(), &[0]].concat(),
+    ] {
+        assert!(MultiProof::decode_compact::<Pedersen>(bytes, 251).is_err());
+    }
+    assert!(MultiProof::decode_compact::<Pedersen>(&compact, 8).is_err());
+}
+
+#[test]
+fn proof_rpc_json_hashmap_db() {
+    let binary = ProofNode::Binary {
+        left: Felt::ONE,
+        right: Felt::from_hex_unchecked("0xabc"),
+    };
+    assert_eq!(
+        serde_json::to_value(&binary).unwrap(),
+        serde_json::json!({"left": "0x1", "right": "0xabc"})
+    );
+    let edge = ProofNode::Edge {
+        child: Felt::TWO,
+        path: Path(BitVec::from_iter([false, true, false, true])),
+    };
+    let json = serde_json::json!({"path": "0x5", "length": 4, "child": "0x2"});
+    assert_eq!(serde_json::to_value(&edge).unwrap(), json);
+    assert_eq!(serde_json::from_value::<ProofNode>(json).unwrap(), edge);
+    for invalid in [
+        serde_json::json!({"path": "0x5", "length": 2, "child": "0x2"}),
+        serde_json::json!({"path": "0x0", "length": 0, "child": "0x2"}),
+        serde_json::json!({"path": "5", "length": 4, "child": "0x2"}),
+        serde_json::json!({"left": "0x1", "right": "0x2", "child": "0x2"}),
+    ] {
+        assert!(serde_json::from_value::<ProofNode>(invalid).is_err());
+    }
+
+    let identifier = b"a".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        251,
+    )
+    .unwrap();
+    let key = |i: u64| BitVec::from_vec(Felt::from(i).to_bytes_be().to_vec())[5..].to_bitvec();
+    for i in [1, 2, 7, 1000, 1u64 << 40] {
+        storage.insert(identifier, &key(i), &Felt::from(i)).unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    let root = storage.root_hash(identifier).unwrap();
+    let keys = [key(2), key(1000), key(3)];
+    let proof = storage.get_multi_proof(identifier, &keys).unwrap();
+
+    let json = serde_json::to_value(&proof).unwrap();
+    let hashes: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|mapping| Felt::from_hex(mapping["node_hash"].as_str().unwrap()).unwrap())
+        .collect();
+    assert_eq!(hashes.len(), proof.0.len());
+    assert!(hashes.windows(2).all(|pair| pair[0] < pair[1]));
+    let decoded: MultiProof = serde_json::from_value(json).unwrap();
+    assert_eq!(decoded.0, proof.0);
+    let values: Vec<Felt> = decoded
+        .verify_proof::<Pedersen>(root, &keys, 251)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values, [Felt::TWO, Felt::from(1000), Felt::ZERO]);
+}
+
+#[test]
+fn remove_batch_and_prefix_hashmap_db() {
+    let identifier = vec![];
+    let new_storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap()
+    };
+    let mut batched = new_storage();
+    let mut one_by_one = new_storage();
+    let mut id_builder = BasicIdBuilder::new();
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut keys: Vec<BitVec> = (0..200)
+        .map(|_| BitVec::from_vec(vec![rng.gen_range(0..4), rng.gen(), rng.gen()]))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    let (committed, uncommitted) = keys.split_at(150);
+
+    let id0 = id_builder.new_id();
+    for storage in [&mut batched, &mut one_by_one] {
+        for (i, key) in committed.iter().enumerate() {
+            storage
+                .insert(&identifier, key, &Felt::from(i + 1))
+                .unwrap();
+        }
+        storage.commit(id0).unwrap();
+        for (i, key) in uncommitted.iter().enumerate() {
+            storage
+                .insert(&identifier, key, &Felt::from(i + 1000))
+                .unwrap();
+        }
+    }
+
+    // Committed and uncommitted keys starting with 1, then a prefix ending inside a byte.
+    let prefixes = [
+        BitVec::from_vec(vec![1]),
+        BitVec::from_vec(vec![2, 0xa0])[..11].to_bitvec(),
+    ];
+    for prefix in &prefixes {
+        let removed: Vec<_> = keys.iter().filter(|key| key.starts_with(prefix)).collect();
+        assert!(!removed.is_empty());
+        assert_eq!(
+            batched.remove_prefix(&identifier, prefix).unwrap(),
+            removed.len()
+        );
+        for key in removed {
+            one_by_one.remove(&identifier, key).unwrap();
+        }
+    }
+    assert_eq!(
+        batched
+            .remove_prefix(&identifier, &BitVec::from_vec(vec![9]))
+            .unwrap(),
+        0
+    );
+    // A missing key is ignored.
+    let batch: Vec<BitVec> = keys
+        .iter()
+        .filter(|key| key[..8] == BitVec::from_vec(vec![3])[..])
+        .step_by(2)
+        .cloned()
+        .chain([BitVec::from_vec(vec![3, 0xff, 0xff])])
+        .collect();
+    batched.remove_batch(&identifier, &batch).unwrap();
+    for key in &batch {
+        one_by_one.remove(&identifier, key).unwrap();
+    }
+
+    let id1 = id_builder.new_id();
+    batched.commit(id1).unwrap();
+    one_by_one.commit(id1).unwrap();
+    let root = one_by_one.root_hash(&identifier).unwrap();
+    assert_eq!(batched.root_hash(&identifier).unwrap(), root);
+    assert_eq!(
+        batched.get_keys(&identifier, None, 1000).unwrap(),
+        one_by_one.get_keys(&identifier, None, 1000).unwrap()
+    );
+
+    // An empty prefix clears the trie, which a revert brings back.
+    let left = batched.get_keys(&identifier, None, 1000).unwrap().len();
+    assert_eq!(
+        batched.remove_prefix(&identifier, &BitVec::new()).unwrap(),
+        left
+    );
+    batched.commit(id_builder.new_id()).unwrap();
+    assert_eq!(batched.root_hash(&identifier).unwrap(), Felt::ZERO);
+    assert!(batched
+        .get_keys(&identifier, None, 1000)
+        .unwrap()
+        .is_empty());
+    batched.revert_to(id1).unwrap();
+    assert_eq!(batched.root_hash(&identifier).unwrap(), root);
+
+    assert!(matches!(
+        batched.remove_prefix(&identifier, &BitVec::from_vec(vec![1, 2, 3, 4])),
+        Err(BonsaiStorageError::KeyLength { .. })
+    ));
+}
+
+#[test]
+fn clear_trie_hashmap_db() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let (cleared, kept) = (vec![1], vec![2]);
+    for i in 0..16u8 {
+        let key = BitVec::from_vec(vec![i, 2, i]);
+        bonsai_storage
+            .insert(&cleared, &key, &Felt::from(i + 1))
+            .unwrap();
+        bonsai_storage
+            .insert(&kept, &key, &Felt::from(i + 1))
+            .unwrap();
+    }
+    let id0 = id_builder.new_id();
+    bonsai_storage.commit(id0).unwrap();
+    let cleared_root = bonsai_storage.root_hash(&cleared).unwrap();
+    let kept_root = bonsai_storage.root_hash(&kept).unwrap();
+
+    // The uncommitted changes are dropped as well.
+    bonsai_storage
+        .insert(&cleared, &BitVec::from_vec(vec![7, 7, 7]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.clear_trie(&cleared).unwrap();
+    assert_eq!(bonsai_storage.len(&cleared).unwrap(), 0);
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&cleared).unwrap(), Felt::ZERO);
+    assert_eq!(bonsai_storage.root_hash(&kept).unwrap(), kept_root);
+    assert_eq!(bonsai_storage.len(&cleared).unwrap(), 0);
+    assert_eq!(
+        bonsai_storage
+            .get(&cleared, &BitVec::from_vec(vec![3, 2, 3]))
+            .unwrap(),
+        None
+    );
+    let changes = bonsai_storage.get_trie_changes(&cleared, id1).unwrap();
+    assert_eq!(changes.len(), 16);
+    assert!(changes.values().all(|change| change.new_value.is_none()));
+
+    // The trie can be filled again.
+    bonsai_storage
+        .insert(&cleared, &BitVec::from_vec(vec![1, 1, 1]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.len(&cleared).unwrap(), 1);
+
+    bonsai_storage.revert_to(id0).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&cleared).unwrap(), cleared_root);
+    assert_eq!(bonsai_storage.root_hash(&kept).unwrap(), kept_root);
+    assert_eq!(bonsai_storage.len(&cleared).unwrap(), 16);
+}
+
+#[test]
+fn pending_stats_hashmap_db() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let (changed, untouched) = (vec![1], vec![2]);
+    for i in 0..8u8 {
+        for identifier in [&changed, &untouched] {
+            bonsai_storage
+                .insert(identifier, &BitVec::from_vec(vec![1, 2, i]), &Felt::ONE)
+                .unwrap();
+        }
+    }
+    assert_eq!(bonsai_storage.pending_stats().len(), 2);
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert!(bonsai_storage.pending_stats().is_empty());
+
+    for i in 8..11u8 {
+        bonsai_storage
+            .insert(&changed, &BitVec::from_vec(vec![1, 2, i]), &Felt::TWO)
+            .unwrap();
+    }
+    bonsai_storage
+        .remove(&changed, &BitVec::from_vec(vec![1, 2, 0]))
+        .unwrap();
+    let stats = bonsai_storage.pending_stats();
+    assert_eq!(stats.len(), 1);
+    let PendingStats {
+        nodes,
+        death_row,
+        modified_leaves,
+    } = stats[&changed[..]];
+    assert_eq!(modified_leaves, 4);
+    assert!(nodes > 0);
+    assert!(death_row > 0);
+
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert!(bonsai_storage.pending_stats().is_empty());
+}
+
+#[test]
+fn split_edge_keeps_other_tries_hashmap_db() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let (first, second) = (vec![1], vec![2]);
+    let key = BitVec::from_vec(vec![0x80, 0, 0]);
+    bonsai_storage.insert(&second, &key, &Felt::ONE).unwrap();
+    // The first leaf of the first trie is below an edge at height 2 with the path `00`, the path
+    // of the root node of the second trie once prefixed by its identifier.
+    for key in [[0, 0, 0], [0x40, 0, 0]] {
+        bonsai_storage
+            .insert(&first, &BitVec::from_vec(key.to_vec()), &Felt::ONE)
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root = bonsai_storage.root_hash(&second).unwrap();
+
+    // Splitting the edge only removes nodes of the first trie.
+    bonsai_storage
+        .insert(&first, &BitVec::from_vec(vec![0, 0, 1]), &Felt::ONE)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage.insert(&second, &key, &Felt::TWO).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    bonsai_storage.insert(&second, &key, &Felt::ONE).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&second).unwrap(), root);
+}
+
+#[test]
+fn max_cached_tries_hashmap_db() {
+    let config = BonsaiStorageConfig {
+        max_cached_tries: Some(2),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    for identifier in 1..4u8 {
+        bonsai_storage
+            .insert(&[identifier], &key, &Felt::from(identifier))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.tries.trees.len(), 2);
+    let root_hashes: Vec<_> = (1..4u8)
+        .map(|identifier| bonsai_storage.root_hash(&[identifier]).unwrap())
+        .collect();
+
+    // The tries the commit didn't change are dropped first.
+    bonsai_storage.insert(&[4], &key, &Felt::ONE).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.tries.trees.len(), 2);
+    assert!(bonsai_storage.tries.trees.contains_key([4].as_slice()));
+
+    // The dropped tries are read again from the database.
+    for identifier in 1..4u8 {
+        assert_eq!(
+            bonsai_storage.get(&[identifier], &key).unwrap(),
+            Some(Felt::from(identifier))
+        );
+        assert_eq!(
+            bonsai_storage.root_hash(&[identifier]).unwrap(),
+            root_hashes[identifier as usize - 1]
+        );
+        bonsai_storage
+            .insert(&[identifier], &key, &Felt::from(identifier + 10))
+            .unwrap();
+    }
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.tries.trees.len(), 2);
+    for identifier in 1..4u8 {
+        assert_eq!(
+            bonsai_storage.get(&[identifier], &key).unwrap(),
+            Some(Felt::from(identifier + 10))
+        );
+    }
+}
+
+#[test]
+fn get_or_insert_with_hashmap_db() {
+    let identifier = vec![1];
+    let storage = || {
+        let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap();
+        for i in 0..20u8 {
+            bonsai_storage
+                .insert(&identifier, &BitVec::from_vec(vec![i, i, 0]), &Felt::ONE)
+                .unwrap();
+        }
+        bonsai_storage.commit(BasicId::new(0)).unwrap();
+        bonsai_storage.take_op_stats();
+        bonsai_storage
+    };
+    let mut bonsai_storage = storage();
+    let existing = BitVec::from_vec(vec![3, 3, 0]);
+    let key = BitVec::from_vec(vec![3, 3, 1]);
+
+    let value = bonsai_storage
+        .get_or_insert_with(&identifier, &existing, || unreachable!())
+        .unwrap();
+    assert_eq!(value, Felt::ONE);
+    let value = bonsai_storage
+        .get_or_insert_with(&identifier, &key, || Felt::TWO)
+        .unwrap();
+    assert_eq!(value, Felt::TWO);
+    let value = bonsai_storage
+        .get_or_insert_with(&identifier, &key, || Felt::THREE)
+        .unwrap();
+    assert_eq!(value, Felt::TWO);
+    let db_gets = bonsai_storage.take_op_stats().db_gets;
+    bonsai_storage.commit(BasicId::new(1)).unwrap();
+
+    // Same trie as with a read followed by an insert, which reads more.
+    let mut other = storage();
+    assert_eq!(other.get(&identifier, &existing).unwrap(), Some(Felt::ONE));
+    assert_eq!(other.get(&identifier, &key).unwrap(), None);
+    other.insert(&identifier, &key, &Felt::TWO).unwrap();
+    assert_eq!(other.get(&identifier, &key).unwrap(), Some(Felt::TWO));
+    assert!(other.take_op_stats().db_gets > db_gets);
+    other.commit(BasicId::new(1)).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        other.root_hash(&identifier).unwrap()
+    );
+
+    // Nothing is inserted for a zero value.
+    let key = BitVec::from_vec(vec![4, 4, 1]);
+    let value = bonsai_storage
+        .get_or_insert_with(&identifier, &key, || Felt::ZERO)
+        .unwrap();
+    assert_eq!(value, Felt::ZERO);
+    assert_eq!(bonsai_storage.get(&identifier, &key).unwrap(), None);
+}
+
+#[test]
+fn store_zero_values_hashmap_db() {
+    let identifier = vec![1];
+    let storage = |max_inline_leaves| {
+        let config = BonsaiStorageConfig {
+            store_zero_values: true,
+            max_inline_leaves,
+            ..Default::default()
+        };
+        BonsaiStorage::<BasicId, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 24)
+            .unwrap()
+    };
+    let zero_key = BitVec::from_vec(vec![1, 2, 3]);
+    let key = BitVec::from_vec(vec![1, 2, 4]);
+    let leaves = [(zero_key.clone(), Felt::ZERO), (key.clone(), Felt::ONE)];
+
+    // Zero is a value like the others, also for the inline tries and bulk loads.
+    let mut root_hashes = Vec::new();
+    for max_inline_leaves in [None, Some(4)] {
+        let mut bonsai_storage = storage(max_inline_leaves);
+        for (key, value) in &leaves {
+            bonsai_storage.insert(&identifier, key, value).unwrap();
+        }
+        bonsai_storage.commit(BasicId::new(0)).unwrap();
+        assert_eq!(
+            bonsai_storage.get(&identifier, &zero_key).unwrap(),
+            Some(Felt::ZERO)
+        );
+        assert_eq!(bonsai_storage.len(&identifier).unwrap(), 2);
+        root_hashes.push(bonsai_storage.root_hash(&identifier).unwrap());
+    }
+    let mut bonsai_storage = storage(None);
+    let root_hash = bonsai_storage
+        .bulk_load(&identifier, leaves.clone(), BasicId::new(0))
+        .unwrap();
+    root_hashes.push(root_hash);
+    assert_eq!(root_hashes[0], root_hashes[1]);
+    assert_eq!(root_hashes[0], root_hashes[2]);
+    assert_ne!(
+        root_hashes[0],
+        MerkleTree::<Pedersen>::single_leaf_root(&key, Felt::ONE)
+    );
+
+    // Only removals remove the leaves, and reverts bring them back.
+    bonsai_storage.remove(&identifier, &zero_key).unwrap();
+    bonsai_storage.commit(BasicId::new(1)).unwrap();
+    assert_eq!(bonsai_storage.get(&identifier, &zero_key).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        MerkleTree::<Pedersen>::single_leaf_root(&key, Felt::ONE)
+    );
+    bonsai_storage.revert_to(BasicId::new(0)).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &zero_key).unwrap(),
+        Some(Felt::ZERO)
+    );
+    assert_eq!(
+        bonsai_storage.root_hash(&identifier).unwrap(),
+        root_hashes[0]
+    );
+    assert_eq!(
+        bonsai_storage
+            .get_or_insert_with(&identifier, &zero_key, || unreachable!())
+            .unwrap(),
+        Felt::ZERO
+    );
+}
+
+#[test]
+fn iter_nodes_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        8,
+    )
+    .unwrap();
+    assert_eq!(bonsai_storage.iter_nodes(&identifier).unwrap().count(), 0);
+    for key in [0b0000_0000u8, 0b0000_0001, 0b1000_0000] {
+        bonsai_storage
+            .insert(&identifier, &BitVec::from_vec(vec![key]), &Felt::ONE)
+            .unwrap();
+    }
+    bonsai_storage.commit(BasicId::new(0)).unwrap();
+    // The uncommitted changes are not nodes yet.
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![0xff]), &Felt::ONE)
+        .unwrap();
+
+    let nodes: Vec<_> = bonsai_storage.iter_nodes(&identifier).unwrap().collect();
+    let path = |bits: &[bool]| bits.iter().copied().collect::<BitVec>();
+    let edge = |bits: &[bool]| NodeKind::Edge { path: path(bits) };
+    let zeros = [false; 6];
+    assert_eq!(
+        nodes
+            .iter()
+            .map(|(path, kind, _)| (path.clone(), kind.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (path(&[]), NodeKind::Binary),
+            (path(&[false]), edge(&zeros)),
+            (path(&[false; 7]), NodeKind::Binary),
+            (path(&[true]), edge(&[false; 7])),
+        ]
+    );
+    assert_eq!(nodes[0].2, bonsai_storage.root_hash(&identifier).unwrap());
+}
@@ -0,0 +1,171 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb,
+    id::BasicId,
+    starknet::{
+        commitments::{
+            class_leaf, compute_global_state_root, contract_leaf, CONTRACT_CLASS_LEAF_V0,
+            STARKNET_STATE_V0,
+        },
+        storage::{map_entry_address, storage_key, ADDR_BOUND},
+    },
+    BonsaiStorage, BonsaiStorageConfig, Path, TrieHasher,
+};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon, StarkHash},
+};
+
+#[test]
+fn short_strings() {
+    assert_eq!(
+        STARKNET_STATE_V0,
+        Felt::from_bytes_be_slice(b"STARKNET_STATE_V0")
+    );
+    assert_eq!(
+        CONTRACT_CLASS_LEAF_V0,
+        Felt::from_bytes_be_slice(b"CONTRACT_CLASS_LEAF_V0")
+    );
+}
+
+#[test]
+fn leaves() {
+    // A contract state of mainnet, from the contract state hash tests of pathfinder.
+    let class_hash = Felt::from_hex_unchecked(
+        "0x2ff4903e17f87b298ded00c44bfeb22874c5f73be2ced8f1d9d9556fb509779",
+    );
+    let storage_root = Felt::from_hex_unchecked(
+        "0x4fb440e8ca9b74fc12a22ebffe0bc0658206337897226117b985434c239c028",
+    );
+    assert_eq!(
+        contract_leaf(&class_hash, &storage_root, &Felt::ZERO),
+        Felt::from_hex_unchecked(
+            "0x7161b591c893836263a64f2a7e0d829c92f6956148a60ce5e99a3f55c7973f3"
+        )
+    );
+
+    let (class_hash, storage_root, nonce) = (Felt::from(1u64), Felt::from(2u64), Felt::from(3u64));
+    let expected = Pedersen::hash(
+        &Pedersen::hash(&Pedersen::hash(&class_hash, &storage_root), &nonce),
+        &Felt::ZERO,
+    );
+    assert_eq!(contract_leaf(&class_hash, &storage_root, &nonce), expected);
+
+    let compiled_class_hash = Felt::from(4u64);
+    assert_eq!(
+        class_leaf(&compiled_class_hash),
+        Poseidon::hash(&CONTRACT_CLASS_LEAF_V0, &compiled_class_hash)
+    );
+}
+
+#[test]
+fn global_state_root() {
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig {
+            trie_hashers: [(b"classes"[..].into(), TrieHasher::new::<Poseidon>())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        },
+        251,
+    )
+    .unwrap();
+    let key = |i: u64| Path::from_felt_251(&Felt::from(i)).unwrap();
+    storage
+        .insert(b"storage", &key(1), &Felt::from(7u64))
+        .unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+    let storage_root = storage.root_hash(b"storage").unwrap();
+    let leaf = contract_leaf(&Felt::from(5u64), &storage_root, &Felt::ONE);
+    storage.insert(b"contracts", &key(2), &leaf).unwrap();
+    storage.commit(BasicId::new(1)).unwrap();
+    let contracts_root = storage.root_hash(b"contracts").unwrap();
+
+    // Before any class is declared, the global root is the root of the contracts trie.
+    let classes_root = storage.root_hash(b"classes").unwrap();
+    assert_eq!(classes_root, Felt::ZERO);
+    assert_eq!(
+        compute_global_state_root(&contracts_root, &classes_root),
+        contracts_root
+    );
+
+    let leaf = class_leaf(&Felt::from(6u64));
+    storage.insert(b"classes", &key(5), &leaf).unwrap();
+    storage.commit(BasicId::new(2)).unwrap();
+    let classes_root = storage.root_hash(b"classes").unwrap();
+    assert_eq!(
+        compute_global_state_root(&contracts_root, &classes_root),
+        Poseidon::hash_array(&[STARKNET_STATE_V0, contracts_root, classes_root])
+    );
+}
+
+#[test]
+fn storage_keys() {
+    let base = Felt::from(0x1234u64);
+    let (owner, spender) = (Felt::from(1u64), Felt::from(2u64));
+    assert_eq!(map_entry_address(&base, &[]), base);
+    assert_eq!(
+        map_entry_address(&base, &[owner, spender]),
+        Pedersen::hash(&Pedersen::hash(&base, &owner), &spender)
+    );
+    // Addresses are reduced below 2^251 - 256.
+    assert_eq!(map_entry_address(&ADDR_BOUND, &[]), Felt::ZERO);
+    assert_eq!(
+        map_entry_address(&(ADDR_BOUND + Felt::from(3u64)), &[]),
+        Felt::from(3u64)
+    );
+
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        251,
+    )
+    .unwrap();
+    let address = map_entry_address(&base, &[owner]);
+    // The high felt of a `u256` follows its low felt.
+    storage
+        .insert_felt(b"storage", &address, &Felt::from(5u64))
+        .unwrap();
+    storage
+        .insert_felt(b"storage", &(address + Felt::ONE), &Felt::from(6u64))
+        .unwrap();
+    storage.commit(BasicId::new(0)).unwrap();
+    assert_eq!(
+        storage.get(b"storage", &storage_key(&address, 0)).unwrap(),
+        Some(Felt::from(5u64))
+    );
+    assert_eq!(
+        storage.get(b"storage", &storage_key(&address, 1)).unwrap(),
+        Some(Felt::from(6u64))
+    );
+    assert_eq!(storage_key(&(ADDR_BOUND - Felt::ONE), 255).len(), 251);
+}
+
+#[cfg(feature = "keccak")]
+#[test]
+fn storage_var_keys() {
+    use crate::starknet::storage::{starknet_keccak, storage_var_address};
+
+    // Selectors of entry points are computed the same way as storage variable addresses.
+    assert_eq!(
+        starknet_keccak(b"transfer"),
+        Felt::from_hex_unchecked(
+            "0x83afd3f4caedc6eebf44246fe54e38c95e3179a5ec9ea81740eca5b482d12e"
+        )
+    );
+    assert_eq!(
+        starknet_keccak(b"__execute__"),
+        Felt::from_hex_unchecked(
+            "0x15d40a3d6ca2ac30f4031e42be28da9b056fef9bb7357ac5e85627ee876e5ad"
+        )
+    );
+
+    let base = starknet_keccak(b"ERC20_balances");
+    assert_eq!(storage_var_address("ERC20_balances", &[]), base);
+    let owner = Felt::from(0xabcu64);
+    assert_eq!(
+        storage_var_address("ERC20_balances", &[owner]),
+        map_entry_address(&base, &[owner])
+    );
+}
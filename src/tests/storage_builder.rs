@@ -0,0 +1,131 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb, id::BasicId, BitVec, BonsaiStorage, BonsaiStorageConfig,
+    BonsaiStorageError, ChangeSink, ConfigError, LeafChange, TrieHasher,
+};
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon},
+};
+use std::sync::Arc;
+
+type Storage = BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen>;
+
+struct NoopSink;
+
+impl ChangeSink<BasicId> for NoopSink {
+    fn send(&self, _id: BasicId, _changes: &[LeafChange]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn key(i: u8) -> BitVec {
+    BitVec::from_vec(vec![i, 0, 1])
+}
+
+/// A database with a commit of a Pedersen trie `a` and of a Poseidon trie `b`.
+fn committed_db() -> HashMapDb<BasicId> {
+    let mut storage = Storage::builder(HashMapDb::default())
+        .max_height(24)
+        .trie_hasher(b"b", TrieHasher::new::<Poseidon>())
+        .build()
+        .unwrap();
+    for identifier in [b"a", b"b"] {
+        storage.insert(identifier, &key(1), &Felt::ONE).unwrap();
+    }
+    storage.commit(BasicId::new(0)).unwrap();
+    storage.into_db()
+}
+
+#[test]
+fn defaults_to_the_stored_config() {
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(20),
+        ..Default::default()
+    };
+    let storage = Storage::new(HashMapDb::default(), config, 24).unwrap();
+    let storage = Storage::builder(storage.into_db()).build().unwrap();
+    assert_eq!(storage.get_config().max_saved_trie_logs, Some(20));
+    assert!(matches!(
+        Storage::builder(HashMapDb::default()).build(),
+        Err(BonsaiStorageError::Config(ConfigError::MissingMaxHeight))
+    ));
+}
+
+#[test]
+fn trie_hashers() {
+    let db = committed_db();
+    let storage = Storage::builder(db.clone())
+        .trie_hasher(b"b", TrieHasher::new::<Poseidon>())
+        .build()
+        .unwrap();
+    assert!(storage.get_config().trie_hashers.contains_key(&b"b"[..]));
+    let storage = Storage::builder(storage.into_db()).build().unwrap();
+    assert!(storage.get_config().trie_hashers.is_empty());
+}
+
+#[test]
+fn max_cached_tries() {
+    let db = committed_db();
+    let storage = Storage::builder(db).max_cached_tries(1).build().unwrap();
+    assert_eq!(storage.get_config().max_cached_tries, Some(1));
+    let config = BonsaiStorageConfig {
+        max_cached_tries: Some(4),
+        ..Default::default()
+    };
+    let storage = Storage::builder(storage.into_db())
+        .config(config)
+        .max_cached_tries(2)
+        .build()
+        .unwrap();
+    assert_eq!(storage.get_config().max_cached_tries, Some(2));
+}
+
+#[test]
+fn read_only() {
+    let db = committed_db();
+    let mut storage = Storage::builder(db.clone())
+        .read_only(true)
+        .trie_hasher(b"b", TrieHasher::new::<Poseidon>())
+        .build()
+        .unwrap();
+    assert_eq!(storage.get_latest_id(), Some(BasicId::new(0)));
+
+    // Changes are made in memory, but not written.
+    storage.insert(b"a", &key(2), &Felt::TWO).unwrap();
+    assert_eq!(storage.get(b"a", &key(2)).unwrap(), Some(Felt::TWO));
+    assert!(matches!(
+        storage.commit(BasicId::new(1)),
+        Err(BonsaiStorageError::ReadOnly)
+    ));
+    assert!(matches!(
+        storage.revert_to(BasicId::new(0)),
+        Err(BonsaiStorageError::ReadOnly)
+    ));
+    assert!(matches!(
+        storage.migrate_nodes(b"a"),
+        Err(BonsaiStorageError::ReadOnly)
+    ));
+    let storage = Storage::builder(storage.into_db()).build().unwrap();
+    assert_eq!(storage.get_latest_id(), Some(BasicId::new(0)));
+    assert_eq!(storage.get(b"a", &key(2)).unwrap(), None);
+
+    // A new database is not given a stored configuration.
+    let storage = Storage::builder(HashMapDb::default())
+        .max_height(24)
+        .read_only(true)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        Storage::open_existing(storage.into_db()),
+        Err(BonsaiStorageError::Config(ConfigError::MissingMaxHeight))
+    ));
+
+    assert!(matches!(
+        Storage::builder(db)
+            .read_only(true)
+            .change_sink(Arc::new(NoopSink))
+            .build(),
+        Err(BonsaiStorageError::Config(ConfigError::ReadOnlyChangeSink))
+    ));
+}
@@ -0,0 +1,63 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{HashMapDb, TieredDatabase},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, DatabaseKey,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+#[test]
+fn trie_logs_in_cold_database_hashmap_db() {
+    let identifier = vec![];
+    let db = TieredDatabase::new(HashMapDb::<BasicId>::default(), HashMapDb::default());
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit(id2).unwrap();
+
+    // The trie logs are only in the cold database, the state only in the hot one.
+    let db = &bonsai_storage.tries.db_ref().db;
+    let trie_logs = DatabaseKey::TrieLog(&[]);
+    assert!(db.hot().get_by_prefix(&trie_logs).unwrap().is_empty());
+    assert!(!db.cold().get_by_prefix(&trie_logs).unwrap().is_empty());
+    for prefix in [DatabaseKey::Trie(&[]), DatabaseKey::Flat(&[])] {
+        assert!(!db.hot().get_by_prefix(&prefix).unwrap().is_empty());
+        assert!(db.cold().get_by_prefix(&prefix).unwrap().is_empty());
+    }
+
+    // The transactional states read the snapshots of both databases.
+    let txn = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(txn.root_hash(&identifier).unwrap(), root_hash1);
+    assert_eq!(txn.get(&identifier, &key2).unwrap(), None);
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash1);
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(Felt::from(1u32))
+    );
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(|| {
+        TieredDatabase::new(HashMapDb::<BasicId>::default(), HashMapDb::default())
+    });
+}
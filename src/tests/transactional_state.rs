@@ -1,8 +1,9 @@
 #![cfg(all(feature = "std", feature = "rocksdb"))]
 use crate::{
-    databases::{create_rocks_db, RocksDB, RocksDBConfig},
-    id::BasicIdBuilder,
-    BitVec, BonsaiStorage, BonsaiStorageConfig,
+    databases::{create_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, Change, HashMap,
+    MergeConflictPolicy, SnapshotError, TransactionalStateInfo,
 };
 use log::LevelFilter;
 use starknet_types_core::{felt::Felt, hash::Pedersen};
@@ -331,7 +332,7 @@ fn transactional_state_after_uncommitted() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -373,7 +374,7 @@ fn merge_override() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -416,7 +417,7 @@ fn merge_remove() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -455,7 +456,7 @@ fn merge_txn_revert() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -515,7 +516,7 @@ fn merge_invalid() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -540,7 +541,7 @@ fn merge_invalid() {
     bonsai_at_txn.transactional_commit(id2).unwrap();
 
     let pair2 = (
-        vec![1, 2, 3],
+        vec![1, 2, 2],
         Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
     );
     bonsai_storage
@@ -562,7 +563,7 @@ fn many_snapshots() {
         ..Default::default()
     };
     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -596,3 +597,625 @@ fn many_snapshots() {
         .unwrap()
         .unwrap();
 }
+
+#[test]
+fn basics_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 3]),
+        Felt::from_hex("0x66342762FD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &pair1.0, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let id2 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &pair2.0, &pair2.1)
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &pair1.0).unwrap(),
+        Some(pair1.1)
+    );
+    assert_eq!(bonsai_at_txn.get(&identifier, &pair2.0).unwrap(), None);
+    assert_eq!(bonsai_at_txn.root_hash(&identifier).unwrap(), root_hash1);
+
+    // id2 has no snapshot of its own, it is reached by applying the trie log of id2 to the
+    // snapshot of id1
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id2, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &pair2.0).unwrap(),
+        Some(pair2.1)
+    );
+    assert_eq!(bonsai_at_txn.root_hash(&identifier).unwrap(), root_hash2);
+
+    // not committed yet
+    assert!(bonsai_storage
+        .get_transactional_state(id_builder.new_id(), bonsai_storage.get_config())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn merge_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FDD5D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 3]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair3 = (
+        BitVec::from_vec(vec![1, 2, 4]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052F").unwrap(),
+    );
+
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &pair1.0, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    let mut bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    bonsai_at_txn.remove(&identifier, &pair1.0).unwrap();
+    bonsai_at_txn
+        .insert(&identifier, &pair2.0, &pair2.1)
+        .unwrap();
+    bonsai_at_txn
+        .transactional_commit(id_builder.new_id())
+        .unwrap();
+    bonsai_at_txn
+        .insert(&identifier, &pair3.0, &pair3.1)
+        .unwrap();
+
+    bonsai_storage.merge(bonsai_at_txn).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    assert_eq!(bonsai_storage.get(&identifier, &pair1.0).unwrap(), None);
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair2.0).unwrap(),
+        Some(pair2.1)
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair3.0).unwrap(),
+        Some(pair3.1)
+    );
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair1.0).unwrap(),
+        Some(pair1.1)
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &pair2.0).unwrap(), None);
+}
+
+fn merge_conflict_hashmap_db(policy: MergeConflictPolicy) {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        merge_conflict_policy: policy,
+        ..Default::default()
+    };
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FDD5D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 3]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &pair1.0, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    let mut bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    bonsai_at_txn.remove(&identifier, &pair1.0).unwrap();
+    bonsai_at_txn
+        .insert(&identifier, &pair2.0, &pair2.1)
+        .unwrap();
+
+    // the storage changes pair1 after the transactional state was created
+    bonsai_storage
+        .insert(&identifier, &pair1.0, &pair2.1)
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    match bonsai_storage.merge(bonsai_at_txn) {
+        Err(BonsaiStorageError::MergeConflict(keys)) => {
+            assert_eq!(policy, MergeConflictPolicy::Error);
+            assert_eq!(keys, vec![(identifier.as_slice().into(), pair1.0.clone())]);
+            assert_eq!(
+                bonsai_storage.get(&identifier, &pair1.0).unwrap(),
+                Some(pair2.1)
+            );
+            assert_eq!(bonsai_storage.get(&identifier, &pair2.0).unwrap(), None);
+        }
+        Err(err) => panic!("unexpected error {err}"),
+        Ok(()) => {
+            assert_eq!(policy, MergeConflictPolicy::LastWriterWins);
+            assert_eq!(bonsai_storage.get(&identifier, &pair1.0).unwrap(), None);
+            assert_eq!(
+                bonsai_storage.get(&identifier, &pair2.0).unwrap(),
+                Some(pair2.1)
+            );
+        }
+    }
+}
+
+#[test]
+fn merge_conflict_error_hashmap_db() {
+    merge_conflict_hashmap_db(MergeConflictPolicy::Error);
+}
+
+#[test]
+fn merge_conflict_last_writer_wins_hashmap_db() {
+    merge_conflict_hashmap_db(MergeConflictPolicy::LastWriterWins);
+}
+
+#[test]
+fn nested_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage = BonsaiStorage::<_, _, Pedersen>::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        BitVec::from_vec(vec![1, 2, 2]),
+        Felt::from_hex("0x66342762FDD5D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair2 = (
+        BitVec::from_vec(vec![1, 2, 3]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let pair3 = (
+        BitVec::from_vec(vec![1, 2, 4]),
+        Felt::from_hex("0x66342762FDD54D033c195fec3ce2568b62052F").unwrap(),
+    );
+
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &pair1.0, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    // pending block
+    let mut bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, bonsai_storage.get_config())
+        .unwrap()
+        .unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_at_txn
+        .insert(&identifier, &pair2.0, &pair2.1)
+        .unwrap();
+    bonsai_at_txn.transactional_commit(id2).unwrap();
+
+    // two candidate transactions on top of the pending block
+    let mut candidate1: BonsaiStorage<_, _, Pedersen> = bonsai_at_txn
+        .get_transactional_state(id2, bonsai_at_txn.get_config())
+        .unwrap()
+        .unwrap();
+    let mut candidate2: BonsaiStorage<_, _, Pedersen> = bonsai_at_txn
+        .get_transactional_state(id2, bonsai_at_txn.get_config())
+        .unwrap()
+        .unwrap();
+    candidate1.insert(&identifier, &pair3.0, &pair3.1).unwrap();
+    candidate2.remove(&identifier, &pair1.0).unwrap();
+    assert_eq!(
+        candidate1.get(&identifier, &pair1.0).unwrap(),
+        Some(pair1.1)
+    );
+    assert_eq!(
+        candidate1.get(&identifier, &pair2.0).unwrap(),
+        Some(pair2.1)
+    );
+    assert_eq!(candidate2.get(&identifier, &pair3.0).unwrap(), None);
+
+    bonsai_at_txn.merge(candidate1).unwrap();
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &pair3.0).unwrap(),
+        Some(pair3.1)
+    );
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &pair1.0).unwrap(),
+        Some(pair1.1)
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &pair3.0).unwrap(), None);
+
+    bonsai_storage.merge(bonsai_at_txn).unwrap();
+    let id3 = id_builder.new_id();
+    bonsai_storage.commit(id3).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair1.0).unwrap(),
+        Some(pair1.1)
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair2.0).unwrap(),
+        Some(pair2.1)
+    );
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair3.0).unwrap(),
+        Some(pair3.1)
+    );
+
+    bonsai_storage.revert_to(id2).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &pair2.0).unwrap(),
+        Some(pair2.1)
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &pair3.0).unwrap(), None);
+}
+
+#[test]
+fn manual_snapshots_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 100,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let id0 = id_builder.new_id();
+    bonsai_storage.commit(id0).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0]);
+
+    // Only the latest commit can be snapshotted.
+    assert!(matches!(
+        bonsai_storage.create_snapshot_now(id1),
+        Err(BonsaiStorageError::Snapshot(
+            SnapshotError::NotLatestCommit { .. }
+        ))
+    ));
+    bonsai_storage.create_snapshot_now(id2).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0, id2]);
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id2, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &key).unwrap(),
+        Some(Felt::from(2u32))
+    );
+
+    assert!(bonsai_storage.delete_snapshot(id2));
+    assert!(!bonsai_storage.delete_snapshot(id2));
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0]);
+}
+
+#[test]
+fn max_saved_snapshots_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 1,
+        max_saved_snapshots: Some(2),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut ids = Vec::new();
+    for i in 0..5u8 {
+        bonsai_storage
+            .insert(&identifier, &BitVec::from_vec(vec![1, 2, i]), &Felt::ONE)
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        ids.push(id);
+    }
+    assert_eq!(bonsai_storage.list_snapshots(), ids[3..]);
+
+    // Manual snapshots count as well.
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 5]), &Felt::ONE)
+        .unwrap();
+    let id = id_builder.new_id();
+    bonsai_storage.commit(id).unwrap();
+    bonsai_storage.create_snapshot_now(id).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![ids[4], id]);
+}
+
+#[test]
+fn stale_snapshots_after_revert_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 100,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let old_key = BitVec::from_vec(vec![1, 2, 2]);
+    let new_key = BitVec::from_vec(vec![1, 2, 3]);
+    let id0 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &new_key, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id0).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &old_key, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    bonsai_storage.create_snapshot_now(id1).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0, id1]);
+
+    // The snapshot of the reverted commit must not be used for the new one with the same id.
+    bonsai_storage.revert_to(id0).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0]);
+    bonsai_storage
+        .insert(&identifier, &new_key, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id0]);
+
+    let bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(bonsai_at_txn.get(&identifier, &old_key).unwrap(), None);
+    assert_eq!(
+        bonsai_at_txn.get(&identifier, &new_key).unwrap(),
+        Some(Felt::from(3u32))
+    );
+}
+
+#[test]
+fn transactional_state_info_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 3,
+        max_transactional_state_replay: Some(1),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let ids: Vec<_> = (0..5).map(|_| id_builder.new_id()).collect();
+    for (i, id) in ids.iter().enumerate() {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i as u8]),
+                &Felt::from(i as u32 + 1),
+            )
+            .unwrap();
+        bonsai_storage.commit(*id).unwrap();
+    }
+    assert_eq!(bonsai_storage.list_snapshots(), vec![ids[0], ids[3]]);
+
+    assert_eq!(
+        bonsai_storage.transactional_state_info(ids[2]),
+        Some(TransactionalStateInfo {
+            snapshot_id: ids[0],
+            replayed_trie_logs: 2,
+        })
+    );
+    assert_eq!(
+        bonsai_storage.transactional_state_info(BasicId::new(5)),
+        None
+    );
+
+    // Replaying two trie logs is over the limit.
+    assert!(matches!(
+        bonsai_storage.get_transactional_state(ids[2], BonsaiStorageConfig::default()),
+        Err(BonsaiStorageError::Transaction(_))
+    ));
+    let (bonsai_at_txn, info) = bonsai_storage
+        .get_transactional_state_with_info(ids[4], BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        info,
+        TransactionalStateInfo {
+            snapshot_id: ids[3],
+            replayed_trie_logs: 1,
+        }
+    );
+    assert_eq!(
+        bonsai_at_txn
+            .get(&identifier, &BitVec::from_vec(vec![1, 2, 4]))
+            .unwrap(),
+        Some(Felt::from(5u32))
+    );
+}
+
+#[test]
+fn merge_trie_logs_disabled_hashmap_db() {
+    let identifier = vec![];
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let value = Felt::from(1u32);
+    let config = BonsaiStorageConfig::default();
+    let txn_config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(0),
+        ..config.clone()
+    };
+
+    for main_advanced in [false, true] {
+        let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+            BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap();
+        let mut id_builder = BasicIdBuilder::new();
+        let id1 = id_builder.new_id();
+        bonsai_storage.commit(id1).unwrap();
+
+        let mut bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+            .get_transactional_state(id1, txn_config.clone())
+            .unwrap()
+            .unwrap();
+        if main_advanced {
+            bonsai_storage.commit(id_builder.new_id()).unwrap();
+        }
+        let latest_id = bonsai_storage.get_latest_id();
+        bonsai_at_txn.insert(&identifier, &key, &value).unwrap();
+        bonsai_at_txn
+            .transactional_commit(id_builder.new_id())
+            .unwrap();
+
+        // The commits of the transactional state can't be read back, with or without fast-forward.
+        assert!(matches!(
+            bonsai_storage.merge(bonsai_at_txn),
+            Err(BonsaiStorageError::Merge(_) | BonsaiStorageError::GoTo(_))
+        ));
+        assert_eq!(bonsai_storage.get_latest_id(), latest_id);
+        assert_eq!(bonsai_storage.get(&identifier, &key).unwrap(), None);
+    }
+}
+
+#[test]
+fn fast_forward_skips_uncommitted_ids_hashmap_db() {
+    let identifier = vec![];
+    let key = BitVec::from_vec(vec![1, 2, 3]);
+    let value = Felt::from(1u32);
+    let config = BonsaiStorageConfig {
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+
+    let mut bonsai_at_txn: BonsaiStorage<_, _, Pedersen> = bonsai_storage
+        .get_transactional_state(id1, config)
+        .unwrap()
+        .unwrap();
+    let id2 = id_builder.new_id();
+    let id3 = id_builder.new_id();
+    bonsai_at_txn.insert(&identifier, &key, &value).unwrap();
+    bonsai_at_txn.transactional_commit(id3).unwrap();
+    bonsai_storage.merge(bonsai_at_txn).unwrap();
+
+    assert_eq!(bonsai_storage.get_latest_id(), Some(id3));
+    assert_eq!(bonsai_storage.list_snapshots(), vec![id1, id3]);
+    assert_eq!(bonsai_storage.get(&identifier, &key).unwrap(), Some(value));
+    assert!(bonsai_storage.get_change_batch(id2).unwrap().is_empty());
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.get(&identifier, &key).unwrap(), None);
+}
+
+#[test]
+fn diff_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage =
+        BonsaiStorage::<_, _, Pedersen>::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key = |i: u32| BitVec::from_vec(i.to_be_bytes()[1..].to_vec());
+
+    // Leaves of each commit, which adds, modifies and removes some of them.
+    let mut commits = Vec::new();
+    let mut leaves = HashMap::new();
+    for commit in 0..4u32 {
+        for i in 0..100u32 {
+            let value = match (i + commit) % 5 {
+                0 => None,
+                1 | 2 => Some(Felt::from(i + 1)),
+                _ => Some(Felt::from(i * 1000 + commit)),
+            };
+            let key = key(i * 7919 % 65536);
+            match value {
+                Some(value) => {
+                    bonsai_storage.insert(&identifier, &key, &value).unwrap();
+                    leaves.insert(key, value);
+                }
+                None => {
+                    bonsai_storage.remove(&identifier, &key).unwrap();
+                    leaves.remove(&key);
+                }
+            }
+        }
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        commits.push((id, leaves.clone()));
+    }
+
+    for (id_a, leaves_a) in &commits {
+        for (id_b, leaves_b) in &commits {
+            let mut expected = HashMap::new();
+            for key in leaves_a.keys().chain(leaves_b.keys()) {
+                let change = Change {
+                    old_value: leaves_a.get(key).copied(),
+                    new_value: leaves_b.get(key).copied(),
+                };
+                if change.old_value != change.new_value {
+                    expected.insert(key.clone(), change);
+                }
+            }
+            assert_eq!(
+                bonsai_storage.diff(&identifier, *id_a, *id_b).unwrap(),
+                expected
+            );
+        }
+    }
+    assert!(bonsai_storage
+        .diff(&identifier, commits[0].0, commits[3].0)
+        .unwrap()
+        .values()
+        .any(|change| change.old_value.is_some() && change.new_value.is_some()));
+
+    // The commits must be reachable.
+    assert!(matches!(
+        bonsai_storage.diff(&identifier, commits[0].0, id_builder.new_id()),
+        Err(BonsaiStorageError::Transaction(_))
+    ));
+}
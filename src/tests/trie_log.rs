@@ -1,10 +1,20 @@
 #![cfg(all(feature = "std", feature = "rocksdb"))]
 use crate::{
-    databases::{create_rocks_db, RocksDB, RocksDBConfig},
-    id::BasicIdBuilder,
-    BitVec, BonsaiStorage, BonsaiStorageConfig,
+    changes::Change,
+    databases::{create_rocks_db, HashMapDb, RocksDB, RocksDBConfig},
+    id::{BasicId, BasicIdBuilder, Id},
+    trie::{
+        tree::{disk_usage_key, is_node_key, leaf_count_key},
+        TrieKey,
+    },
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, BonsaiStorageError, BonsaiTrieHash,
+    ByteVec, ChangeBatch, ConfigError, DatabaseKey, DiskUsage, HashMap, TrieHasher,
+};
+use parity_scale_codec::Encode;
+use starknet_types_core::{
+    felt::Felt,
+    hash::{Pedersen, Poseidon},
 };
-use starknet_types_core::{felt::Felt, hash::Pedersen};
 
 #[test]
 fn basics() {
@@ -13,7 +23,7 @@ fn basics() {
     let db = create_rocks_db(tempdir.path()).unwrap();
     let config = BonsaiStorageConfig::default();
     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
     let mut id_builder = BasicIdBuilder::new();
 
     let pair1 = (
@@ -26,7 +36,7 @@ fn basics() {
         .insert(&identifier, &bitvec, pair1.1)
         .unwrap();
     bonsai_storage.commit(id1).unwrap();
-    let _root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
 
     let id2 = id_builder.new_id();
     let pair2 = (
@@ -38,181 +48,1397 @@ fn basics() {
         .insert(&identifier, &bitvec, pair2.1)
         .unwrap();
     bonsai_storage.commit(id2).unwrap();
-    let _root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
-
-    // let id3 = id_builder.new_id();
-    // let bitvec = BitVec::from_vec(pair1.0);
-    // bonsai_storage.remove(&identifier, &bitvec).unwrap();
-    // bonsai_storage.commit(id3).unwrap();
-
-    // bonsai_storage.revert_to(id2).unwrap();
-    // let revert_root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
-
-    // bonsai_storage.revert_to(id1).unwrap();
-    // let revert_root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-
-    // assert_eq!(root_hash2, revert_root_hash2);
-    // assert_eq!(root_hash1, revert_root_hash1);
-}
-
-// #[test]
-// fn unrecorded_revert() {
-//     let identifier = vec![];
-//     let tempdir = tempfile::tempdir().unwrap();
-//     let db = create_rocks_db(tempdir.path()).unwrap();
-//     let config = BonsaiStorageConfig::default();
-//     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
-//     let mut id_builder = BasicIdBuilder::new();
-
-//     let pair1 = (
-//         vec![1, 2, 3],
-//         Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let id1 = id_builder.new_id();
-//     let bitvec = BitVec::from_vec(pair1.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, &pair1.1)
-//         .unwrap();
-//     bonsai_storage.commit(id1).unwrap();
-
-//     let uncommited_id = id_builder.new_id();
-//     bonsai_storage.revert_to(uncommited_id).unwrap_err();
-// }
-
-// #[test]
-// fn in_place_revert() {
-//     let identifier = vec![];
-//     let tempdir = tempfile::tempdir().unwrap();
-//     let db = create_rocks_db(tempdir.path()).unwrap();
-//     let config = BonsaiStorageConfig::default();
-//     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
-//     let mut id_builder = BasicIdBuilder::new();
-
-//     let pair1 = (vec![1, 2, 3], &BonsaiTrieHash::default());
-//     let id1 = id_builder.new_id();
-//     let bitvec = BitVec::from_vec(pair1.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, pair1.1)
-//         .unwrap();
-//     bonsai_storage.commit(id1).unwrap();
-//     let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-
-//     bonsai_storage.revert_to(id1).unwrap();
-//     assert_eq!(root_hash1, bonsai_storage.root_hash(&identifier).unwrap());
-// }
-
-// #[test]
-// fn truncated_revert() {
-//     let identifier = vec![];
-//     let tempdir = tempfile::tempdir().unwrap();
-//     let db = create_rocks_db(tempdir.path()).unwrap();
-//     let config = BonsaiStorageConfig::default();
-//     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
-//     let mut id_builder = BasicIdBuilder::new();
-
-//     let pair1 = (
-//         vec![1, 2, 1],
-//         &Felt::from_hex("0x16342762FDD54D033c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let id1 = id_builder.new_id();
-//     let bitvec = BitVec::from_vec(pair1.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, pair1.1)
-//         .unwrap();
-//     bonsai_storage.commit(id1).unwrap();
-//     let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-
-//     let id2 = id_builder.new_id();
-//     let pair2 = (
-//         vec![1, 2, 2],
-//         &Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let bitvec = BitVec::from_vec(pair2.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, pair2.1)
-//         .unwrap();
-//     bonsai_storage.commit(id2).unwrap();
-
-//     bonsai_storage.revert_to(id1).unwrap();
-//     let revert_root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-//     bonsai_storage.revert_to(id2).unwrap_err();
-
-//     assert_eq!(root_hash1, revert_root_hash1);
-// }
-
-// #[test]
-// fn double_revert() {
-//     let identifier = vec![];
-//     let tempdir = tempfile::tempdir().unwrap();
-//     let db = create_rocks_db(tempdir.path()).unwrap();
-//     let config = BonsaiStorageConfig::default();
-//     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
-//     let mut id_builder = BasicIdBuilder::new();
-
-//     let pair1 = (
-//         vec![1, 2, 1],
-//         &Felt::from_hex("0x16342762FDD54D033c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let id1 = id_builder.new_id();
-//     let bitvec = BitVec::from_vec(pair1.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, pair1.1)
-//         .unwrap();
-//     bonsai_storage.commit(id1).unwrap();
-//     let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-
-//     let id2 = id_builder.new_id();
-//     let pair2 = (
-//         vec![1, 2, 2],
-//         &Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let bitvec = BitVec::from_vec(pair2.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, pair2.1)
-//         .unwrap();
-//     bonsai_storage.commit(id2).unwrap();
-
-//     bonsai_storage.revert_to(id1).unwrap();
-//     let revert1 = bonsai_storage.root_hash(&identifier).unwrap();
-//     bonsai_storage.revert_to(id1).unwrap();
-//     let revert2 = bonsai_storage.root_hash(&identifier).unwrap();
-
-//     assert_eq!(root_hash1, revert1);
-//     assert_eq!(revert1, revert2);
-// }
-
-// #[test]
-// fn remove_and_reinsert() {
-//     let identifier = vec![];
-//     let tempdir = tempfile::tempdir().unwrap();
-//     let db = create_rocks_db(tempdir.path()).unwrap();
-//     let config = BonsaiStorageConfig::default();
-//     let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
-//         BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24);
-//     let mut id_builder = BasicIdBuilder::new();
-
-//     let pair1 = (
-//         vec![1, 2, 3],
-//         Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
-//     );
-//     let id1 = id_builder.new_id();
-//     let bitvec = BitVec::from_vec(pair1.0.clone());
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, &pair1.1)
-//         .unwrap();
-//     bonsai_storage.remove(&identifier, &bitvec).unwrap();
-//     bonsai_storage.commit(id1).unwrap();
-//     let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
-//     let id2 = id_builder.new_id();
-//     bonsai_storage
-//         .insert(&identifier, &bitvec, &pair1.1)
-//         .unwrap();
-//     bonsai_storage.commit(id2).unwrap();
-
-//     bonsai_storage.revert_to(id1).unwrap();
-//     assert_eq!(root_hash1, bonsai_storage.root_hash(&identifier).unwrap());
-// }
+    let root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let id3 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0);
+    bonsai_storage.remove(&identifier, &bitvec).unwrap();
+    bonsai_storage.commit(id3).unwrap();
+
+    bonsai_storage.revert_to(id2).unwrap();
+    let revert_root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    bonsai_storage.revert_to(id1).unwrap();
+    let revert_root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    assert_eq!(root_hash2, revert_root_hash2);
+    assert_eq!(root_hash1, revert_root_hash1);
+}
+
+#[test]
+fn unrecorded_revert() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        vec![1, 2, 3],
+        Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
+    );
+    let id1 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    let uncommited_id = id_builder.new_id();
+    bonsai_storage.revert_to(uncommited_id).unwrap_err();
+}
+
+#[test]
+fn in_place_revert() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (vec![1, 2, 3], &BonsaiTrieHash::default());
+    let id1 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(root_hash1, bonsai_storage.root_hash(&identifier).unwrap());
+}
+
+#[test]
+fn truncated_revert() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        vec![1, 2, 1],
+        &Felt::from_hex("0x16342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let id1 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let id2 = id_builder.new_id();
+    let pair2 = (
+        vec![1, 2, 2],
+        &Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
+    );
+    let bitvec = BitVec::from_vec(pair2.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, pair2.1)
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+
+    bonsai_storage.revert_to(id1).unwrap();
+    let revert_root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    bonsai_storage.revert_to(id2).unwrap_err();
+
+    assert_eq!(root_hash1, revert_root_hash1);
+}
+
+#[test]
+fn double_revert() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        vec![1, 2, 1],
+        &Felt::from_hex("0x16342762FDD54D033c195fec3ce2568b62052e").unwrap(),
+    );
+    let id1 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    let id2 = id_builder.new_id();
+    let pair2 = (
+        vec![1, 2, 2],
+        &Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
+    );
+    let bitvec = BitVec::from_vec(pair2.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, pair2.1)
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+
+    bonsai_storage.revert_to(id1).unwrap();
+    let revert1 = bonsai_storage.root_hash(&identifier).unwrap();
+    bonsai_storage.revert_to(id1).unwrap();
+    let revert2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    assert_eq!(root_hash1, revert1);
+    assert_eq!(revert1, revert2);
+}
+
+#[test]
+fn remove_and_reinsert() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let pair1 = (
+        vec![1, 2, 3],
+        Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap(),
+    );
+    let id1 = id_builder.new_id();
+    let bitvec = BitVec::from_vec(pair1.0.clone());
+    bonsai_storage
+        .insert(&identifier, &bitvec, &pair1.1)
+        .unwrap();
+    bonsai_storage.remove(&identifier, &bitvec).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &bitvec, &pair1.1)
+        .unwrap();
+    bonsai_storage.commit(id2).unwrap();
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(root_hash1, bonsai_storage.root_hash(&identifier).unwrap());
+}
+
+#[test]
+fn reorg() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![1, 2, 3]);
+    let value1 = Felt::from_hex("0x16342762FDD54D033c195fec3ce2568b62052e").unwrap();
+    let value2 = Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap();
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key1, &value1).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    // old branch
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key2, &value2).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let id3 = id_builder.new_id();
+    bonsai_storage.remove(&identifier, &key1).unwrap();
+    bonsai_storage.commit(id3).unwrap();
+
+    // new branch, reusing the ids of the old one and going one commit further
+    let id4 = id_builder.new_id();
+    bonsai_storage
+        .reorg(
+            id1,
+            [
+                (id2, vec![(&identifier, &key3, value2)]),
+                (id3, vec![(&identifier, &key1, value2)]),
+                (id4, vec![(&identifier, &key3, Felt::ZERO)]),
+            ],
+        )
+        .unwrap();
+    assert_eq!(bonsai_storage.get_latest_id(), Some(id4));
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(value2)
+    );
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), None);
+    assert_eq!(bonsai_storage.get(&identifier, &key3).unwrap(), None);
+
+    // same state built without reorg
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let mut expected: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        RocksDB::new(&db, RocksDBConfig::default()),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    expected.insert(&identifier, &key1, &value2).unwrap();
+    expected.commit(id4).unwrap();
+    let root_hash4 = expected.root_hash(&identifier).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash4);
+
+    // every commit of the new branch has its own trie log
+    bonsai_storage.revert_to(id3).unwrap();
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key3).unwrap(),
+        Some(value2)
+    );
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash1);
+}
+
+#[test]
+fn reorg_invalid_ids() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let value = Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap();
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key1, &value).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key2, &value).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    // id1 is not more recent than the common ancestor
+    bonsai_storage
+        .reorg(id1, [(id1, vec![(&identifier, &key2, Felt::ZERO)])])
+        .unwrap_err();
+
+    assert_eq!(bonsai_storage.get_latest_id(), Some(id2));
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash2);
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), Some(value));
+}
+
+#[test]
+fn reorg_error_hashmap_db() {
+    let identifier = vec![];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let value = Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap();
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key1, &value).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key2, &value).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+    let root_hash2 = bonsai_storage.root_hash(&identifier).unwrap();
+
+    // the key is too short, this fails after the revert was written to the batch
+    bonsai_storage
+        .reorg(
+            id1,
+            [(
+                id2,
+                vec![(&identifier, BitVec::from_vec(vec![1, 2]), value)],
+            )],
+        )
+        .unwrap_err();
+
+    assert_eq!(bonsai_storage.get_latest_id(), Some(id2));
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), Some(value));
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash2);
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.get(&identifier, &key2).unwrap(), None);
+}
+
+#[test]
+fn reorg_longer_than_trie_log_window() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let ids: Vec<_> = (0..5).map(|_| id_builder.new_id()).collect();
+    let value = Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap();
+
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 0]), &value)
+        .unwrap();
+    bonsai_storage.commit(ids[0]).unwrap();
+    bonsai_storage
+        .insert(&identifier, &BitVec::from_vec(vec![1, 2, 1]), &value)
+        .unwrap();
+    bonsai_storage.commit(ids[1]).unwrap();
+
+    // the trie logs of the new branch are pruned while they are only in the pending batch
+    bonsai_storage
+        .reorg(
+            ids[0],
+            (1..5).map(|i| {
+                (
+                    ids[i],
+                    vec![(&identifier, BitVec::from_vec(vec![1, 3, i as u8]), value)],
+                )
+            }),
+        )
+        .unwrap();
+
+    for (i, id) in ids.iter().enumerate() {
+        let trie_log = bonsai_storage
+            .tries
+            .db_ref()
+            .db
+            .get_by_prefix(&DatabaseKey::TrieLog(&id.to_bytes()))
+            .unwrap();
+        assert_eq!(trie_log.is_empty(), i <= 2, "trie log of {:?}", id);
+    }
+}
+
+#[test]
+fn reopen_hashmap_db() {
+    let identifier = vec![];
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let value = Felt::from_hex("0x66342762FDD54D3c195fec3ce2568b62052e").unwrap();
+
+    let id1 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key1, &value).unwrap();
+    bonsai_storage.commit(id1).unwrap();
+    let root_hash1 = bonsai_storage.root_hash(&identifier).unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.insert(&identifier, &key2, &value).unwrap();
+    bonsai_storage.commit(id2).unwrap();
+
+    let db = bonsai_storage.tries.db_ref().db.clone();
+    let mut reopened: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(db, config, 24).unwrap();
+    assert_eq!(reopened.get_latest_id(), Some(id2));
+
+    // an empty new branch only reverts
+    reopened
+        .reorg(id1, Vec::<(_, Vec<(Vec<u8>, BitVec, Felt)>)>::new())
+        .unwrap();
+    assert_eq!(reopened.get_latest_id(), Some(id1));
+    assert_eq!(reopened.get(&identifier, &key2).unwrap(), None);
+    assert_eq!(reopened.root_hash(&identifier).unwrap(), root_hash1);
+}
+
+#[test]
+fn leaf_count_revert() {
+    let identifier1 = vec![1];
+    let identifier2 = vec![2];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig::default();
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    let key3 = BitVec::from_vec(vec![1, 2, 3]);
+    assert!(bonsai_storage.is_empty(&identifier1).unwrap());
+
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier1, &key2, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier2, &key1, &Felt::from(3u32))
+        .unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 2);
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 2);
+    assert_eq!(bonsai_storage.len(&identifier2).unwrap(), 1);
+
+    // Updating a leaf does not change the count.
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(4u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier1, &key3, &Felt::from(5u32))
+        .unwrap();
+    bonsai_storage.remove(&identifier2, &key1).unwrap();
+    // Removing a missing leaf does not change the count either.
+    bonsai_storage.remove(&identifier2, &key2).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 3);
+    assert!(bonsai_storage.is_empty(&identifier2).unwrap());
+
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 2);
+    assert_eq!(bonsai_storage.len(&identifier2).unwrap(), 1);
+    assert_eq!(
+        bonsai_storage
+            .get_keys(&identifier1, None, usize::MAX)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn leaf_count_missing_hashmap_db() {
+    let identifier1 = vec![1];
+    let identifier2 = vec![2];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier1, &key2, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier2, &key1, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+
+    // Databases written before the leaf counts were stored have none.
+    let count_key = leaf_count_key(&identifier1);
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    assert!(db
+        .remove(&DatabaseKey::Trie(count_key.as_slice()), None)
+        .unwrap()
+        .is_some());
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 2);
+    assert_eq!(bonsai_storage.len(&identifier2).unwrap(), 1);
+
+    bonsai_storage.remove(&identifier1, &key1).unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 1);
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(bonsai_storage.len(&identifier1).unwrap(), 1);
+    // The count is stored again by the commit.
+    assert!(bonsai_storage
+        .tries
+        .db_ref()
+        .db
+        .get(&DatabaseKey::Trie(count_key.as_slice()))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn disk_usage_hashmap_db() {
+    let identifier1 = vec![1];
+    let identifier2 = vec![2];
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    assert_eq!(
+        bonsai_storage.disk_usage(&identifier1).unwrap(),
+        DiskUsage::default()
+    );
+
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier1, &key2, &Felt::from(2u32))
+        .unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage.commit(id1).unwrap();
+    let usage1 = bonsai_storage.disk_usage(&identifier1).unwrap();
+    assert!(usage1.trie > 0 && usage1.flat > 0 && usage1.trie_logs > 0);
+    assert_eq!(usage1.total(), usage1.trie + usage1.flat + usage1.trie_logs);
+    assert_eq!(
+        bonsai_storage.disk_usage(&identifier2).unwrap(),
+        DiskUsage::default()
+    );
+
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier2, &key1, &Felt::from(4u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let usage2 = bonsai_storage.disk_usage(&identifier1).unwrap();
+    assert_eq!((usage2.trie, usage2.flat), (usage1.trie, usage1.flat));
+    assert!(usage2.trie_logs > usage1.trie_logs);
+
+    // The stored usage matches the one measured from the nodes and leaves, as for the tries
+    // committed before it was stored.
+    let usage_key = disk_usage_key(&identifier1);
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    assert!(db
+        .remove(&DatabaseKey::Meta(usage_key.as_slice()), None)
+        .unwrap()
+        .is_some());
+    assert_eq!(bonsai_storage.disk_usage(&identifier1).unwrap(), usage2);
+
+    // Reverting restores the usage.
+    bonsai_storage.revert_to(id1).unwrap();
+    assert_eq!(bonsai_storage.disk_usage(&identifier1).unwrap(), usage1);
+
+    // The trie logs of the trie are pruned with the commits touching other tries.
+    for i in 0..2u32 {
+        bonsai_storage
+            .insert(&identifier2, &key2, &Felt::from(i + 5))
+            .unwrap();
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+    }
+    assert_eq!(
+        bonsai_storage.disk_usage(&identifier1).unwrap().trie_logs,
+        0
+    );
+
+    bonsai_storage.remove(&identifier1, &key1).unwrap();
+    bonsai_storage.remove(&identifier1, &key2).unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let usage = bonsai_storage.disk_usage(&identifier1).unwrap();
+    assert_eq!((usage.trie, usage.flat), (0, 0));
+    assert!(usage.trie_logs > 0);
+}
+
+#[test]
+fn root_hash_at_hashmap_db() {
+    let identifier1 = vec![1];
+    let identifier2 = vec![2];
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+
+    let mut root_hashes = vec![];
+    for (identifier, key, value) in [
+        (&identifier1, &key1, 1u32),
+        (&identifier2, &key1, 2),
+        (&identifier1, &key2, 3),
+    ] {
+        bonsai_storage
+            .insert(identifier, key, &Felt::from(value))
+            .unwrap();
+        let id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+        root_hashes.push((
+            id,
+            bonsai_storage.root_hash(&identifier1).unwrap(),
+            bonsai_storage.root_hash(&identifier2).unwrap(),
+        ));
+    }
+    for (id, root_hash1, root_hash2) in &root_hashes {
+        assert_eq!(
+            bonsai_storage.root_hash_at(&identifier1, *id).unwrap(),
+            Some(*root_hash1)
+        );
+        assert_eq!(
+            bonsai_storage.root_hash_at(&identifier2, *id).unwrap(),
+            Some(*root_hash2)
+        );
+    }
+    let id4 = id_builder.new_id();
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id4).unwrap(),
+        None
+    );
+
+    // Reverting removes the root hashes of the reverted commits.
+    let (id2, root_hash1, _) = root_hashes[1];
+    bonsai_storage.revert_to(id2).unwrap();
+    let (id3, _, _) = root_hashes[2];
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id3).unwrap(),
+        None
+    );
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id2).unwrap(),
+        Some(root_hash1)
+    );
+
+    // The root hashes of the pruned commits are not known anymore, the root hash of a trie which
+    // did not change since then still is.
+    let mut id = id2;
+    for i in 0..4u32 {
+        bonsai_storage
+            .insert(&identifier2, &key2, &Felt::from(i + 4))
+            .unwrap();
+        id = id_builder.new_id();
+        bonsai_storage.commit(id).unwrap();
+    }
+    let (id1, _, _) = root_hashes[0];
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id1).unwrap(),
+        None
+    );
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id).unwrap(),
+        Some(root_hash1)
+    );
+    // The commits of the window, and the latest one before it.
+    assert_eq!(
+        bonsai_storage
+            .tries
+            .db_ref()
+            .db
+            .get_by_prefix(&DatabaseKey::Meta(&[8, 2]))
+            .unwrap()
+            .len(),
+        4
+    );
+
+    // Databases written before the root hashes were recorded only know them from the next commit.
+    let db = &mut bonsai_storage.tries.db_mut().db;
+    db.remove_by_prefix(&DatabaseKey::Meta(&[8])).unwrap();
+    assert_eq!(bonsai_storage.root_hash_at(&identifier1, id).unwrap(), None);
+    let root_hash2 = bonsai_storage.root_hash(&identifier2).unwrap();
+    bonsai_storage
+        .insert(&identifier1, &key1, &Felt::from(8u32))
+        .unwrap();
+    let next_id = id_builder.new_id();
+    bonsai_storage.commit(next_id).unwrap();
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, id).unwrap(),
+        Some(root_hash1)
+    );
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier2, id).unwrap(),
+        Some(root_hash2)
+    );
+    assert_eq!(
+        bonsai_storage.root_hash_at(&identifier1, next_id).unwrap(),
+        Some(bonsai_storage.root_hash(&identifier1).unwrap())
+    );
+}
+
+#[test]
+fn migrate_nodes_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    let root_hash = bonsai_storage.root_hash(&identifier).unwrap();
+    let usage = bonsai_storage.disk_usage(&identifier).unwrap();
+
+    // Write the nodes as the versions without the encoding tag did.
+    let mut db = bonsai_storage.tries.db_ref().db.clone();
+    let mut nodes = db.get_by_prefix(&DatabaseKey::Trie(&identifier)).unwrap();
+    nodes.retain(|(key, _)| is_node_key(key, &identifier));
+    for (key, node) in &nodes {
+        db.insert(&DatabaseKey::Trie(key), &node[1..], None)
+            .unwrap();
+    }
+    let usage_key = disk_usage_key(&identifier);
+    let legacy_usage = (usage.trie - nodes.len() as u64, usage.flat);
+    db.insert(
+        &DatabaseKey::Meta(usage_key.as_slice()),
+        &legacy_usage.encode(),
+        None,
+    )
+    .unwrap();
+
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+    assert_eq!(
+        bonsai_storage.get(&identifier, &key1).unwrap(),
+        Some(Felt::from(1u32))
+    );
+
+    assert_eq!(
+        bonsai_storage.migrate_nodes(&identifier).unwrap(),
+        nodes.len()
+    );
+    assert_eq!(bonsai_storage.migrate_nodes(&identifier).unwrap(), 0);
+    assert_eq!(bonsai_storage.disk_usage(&identifier).unwrap(), usage);
+    assert_eq!(
+        bonsai_storage
+            .tries
+            .db_ref()
+            .db
+            .get_by_prefix(&DatabaseKey::Trie(&identifier))
+            .unwrap()
+            .into_iter()
+            .filter(|(key, _)| is_node_key(key, &identifier))
+            .collect::<Vec<_>>(),
+        nodes
+    );
+    assert_eq!(bonsai_storage.root_hash(&identifier).unwrap(), root_hash);
+}
+
+#[test]
+fn auto_compaction() {
+    let identifier = vec![];
+    let tempdir = tempfile::tempdir().unwrap();
+    let db = create_rocks_db(tempdir.path()).unwrap();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        snapshot_interval: 1,
+        auto_compaction: Some(16),
+        ..Default::default()
+    };
+    let mut bonsai_storage: BonsaiStorage<_, _, Pedersen> =
+        BonsaiStorage::new(RocksDB::new(&db, RocksDBConfig::default()), config, 24).unwrap();
+    let mut id_builder = BasicIdBuilder::new();
+
+    let mut removed = 0;
+    let mut compacted = false;
+    for i in 0..10u8 {
+        bonsai_storage
+            .insert(
+                &identifier,
+                &BitVec::from_vec(vec![1, 2, i]),
+                &Felt::from(i + 1),
+            )
+            .unwrap();
+        bonsai_storage.commit(id_builder.new_id()).unwrap();
+        let removed_since_compaction = bonsai_storage.tries.db_ref().removed_since_compaction;
+        assert!(removed_since_compaction < 16);
+        compacted |= removed_since_compaction < removed;
+        removed = removed_since_compaction;
+    }
+    assert!(compacted);
+
+    bonsai_storage.compact().unwrap();
+    assert_eq!(bonsai_storage.tries.db_ref().removed_since_compaction, 0);
+    assert!(
+        BonsaiStorageConfig {
+            auto_compaction: Some(0),
+            ..Default::default()
+        }
+        .validate()
+            == Err(ConfigError::ZeroAutoCompaction)
+    );
+}
+
+#[test]
+fn interleaved_trie_log_keys() {
+    type DatabaseError = <HashMapDb<BasicId> as BonsaiDatabase>::DatabaseError;
+    let id = BasicId::new(1);
+    // Once serialized, the entries of the metadata key sort between the ones of the node key.
+    let node_key = TrieKey::Trie(vec![8, 0].into());
+    let meta_key = TrieKey::Meta(vec![8, 0, 0, 0, 0, 0, 0, 0, 0].into());
+    let mut changes = ChangeBatch::default();
+    for (key, old_value, new_value) in [(&node_key, 1, 2), (&meta_key, 3, 4)] {
+        changes.0.insert(
+            key.clone(),
+            Change {
+                old_value: Some(vec![old_value].into()),
+                new_value: Some(vec![new_value].into()),
+            },
+        );
+    }
+    let mut serialized: Vec<_> = changes
+        .serialize(&id)
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    serialized.sort();
+
+    let changes = ChangeBatch::deserialize::<_, DatabaseError>(&id, serialized).unwrap();
+    assert_eq!(changes.len(), 2);
+    for (key, old_value, new_value) in [(&node_key, 1, 2), (&meta_key, 3, 4)] {
+        let change = &changes.0[key];
+        assert_eq!(change.old_value.as_deref(), Some(&[old_value][..]));
+        assert_eq!(change.new_value.as_deref(), Some(&[new_value][..]));
+    }
+}
+
+#[test]
+// The identifiers of the tries prefix each other, which `key_checks` rejects.
+#[cfg(not(feature = "key_checks"))]
+fn trie_changes_hashmap_db() {
+    let (a, ab) = (b"a".as_slice(), b"ab".as_slice());
+    let new_storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(
+            HashMapDb::<BasicId>::default(),
+            BonsaiStorageConfig::default(),
+            24,
+        )
+        .unwrap()
+    };
+    let mut storage = new_storage();
+    let id = BasicId::new(0);
+    let (key_a, key_ab) = (
+        BitVec::from_vec(vec![1, 2, 3]),
+        BitVec::from_vec(vec![4, 5, 6]),
+    );
+    storage.insert(a, &key_a, &Felt::ONE).unwrap();
+    storage.insert(ab, &key_ab, &Felt::TWO).unwrap();
+    storage.put_meta(b"height", &[0]);
+    storage.commit(id).unwrap();
+
+    let only = |key: &BitVec, value: Felt| {
+        let mut changes = crate::HashMap::new();
+        changes.insert(
+            key.clone(),
+            crate::Change {
+                old_value: None,
+                new_value: Some(value),
+            },
+        );
+        changes
+    };
+    assert_eq!(
+        storage.get_trie_changes(a, id).unwrap(),
+        only(&key_a, Felt::ONE)
+    );
+    assert_eq!(
+        storage.get_trie_changes(ab, id).unwrap(),
+        only(&key_ab, Felt::TWO)
+    );
+
+    // The entries of each trie are framed by its identifier, the metadata ones are not.
+    let changes = storage.get_change_batch(id).unwrap();
+    let height_key = TrieKey::new_meta(crate::trie::trie_db::MetaKeyType::User, b"height");
+    assert!(changes.0.contains_key(&height_key));
+    assert!(!changes.1.contains_key(&height_key));
+    for (key, identifier) in &changes.1 {
+        assert!([a, ab].contains(&identifier.as_slice()));
+        if !matches!(key, TrieKey::Meta(_)) {
+            assert!(key.as_slice().starts_with(identifier));
+        }
+    }
+    let prefixes = [
+        crate::changes::unframed_trie_log_prefix(&id),
+        crate::changes::trie_log_prefix(&id, a),
+        crate::changes::trie_log_prefix(&id, ab),
+    ];
+    for prefix in &prefixes {
+        let entries = changes.serialize(&id);
+        assert!(entries.iter().any(|(key, _)| key.starts_with(prefix)));
+        assert!(entries
+            .iter()
+            .all(|(key, _)| prefixes.iter().any(|prefix| key.starts_with(prefix))));
+    }
+
+    // Trie logs written before they were framed are still read.
+    let mut unframed = changes.clone();
+    unframed.1.clear();
+    let mut replica = new_storage();
+    replica.apply_change_batch(id, &unframed).unwrap();
+    assert_eq!(
+        replica.get_trie_changes(a, id).unwrap(),
+        only(&key_a, Felt::ONE)
+    );
+    assert_eq!(
+        replica.get_trie_changes(ab, id).unwrap(),
+        only(&key_ab, Felt::TWO)
+    );
+    assert_eq!(
+        replica.root_hash(ab).unwrap(),
+        storage.root_hash(ab).unwrap()
+    );
+}
+
+#[test]
+fn revert_identifier_hashmap_db() {
+    let (a, b) = (b"a".as_slice(), b"b".as_slice());
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut root_hashes = Vec::new();
+    for block in 0..3u8 {
+        for identifier in [a, b] {
+            let key = BitVec::from_vec(vec![1, block, 0]);
+            storage
+                .insert(identifier, &key, &Felt::from(block + 1))
+                .unwrap();
+            // Overwrite the leaf of the first commit, and remove it in the last one.
+            let first = BitVec::from_vec(vec![1, 0, 0]);
+            match block {
+                1 => storage.insert(identifier, &first, &Felt::TWO).unwrap(),
+                2 => storage.remove(identifier, &first).unwrap(),
+                _ => {}
+            }
+        }
+        storage.commit(BasicId::new(block.into())).unwrap();
+        root_hashes.push((storage.root_hash(a).unwrap(), storage.root_hash(b).unwrap()));
+    }
+
+    storage.revert_identifier_to(a, BasicId::new(0)).unwrap();
+    storage.commit(BasicId::new(3)).unwrap();
+    assert_eq!(storage.root_hash(a).unwrap(), root_hashes[0].0);
+    assert_eq!(storage.root_hash(b).unwrap(), root_hashes[2].1);
+    assert_eq!(
+        storage.get(a, &BitVec::from_vec(vec![1, 0, 0])).unwrap(),
+        Some(Felt::ONE)
+    );
+    assert_eq!(
+        storage.get(a, &BitVec::from_vec(vec![1, 2, 0])).unwrap(),
+        None
+    );
+
+    // The revert is a regular commit, which can itself be reverted.
+    storage.revert_to(BasicId::new(2)).unwrap();
+    assert_eq!(storage.root_hash(a).unwrap(), root_hashes[2].0);
+
+    storage
+        .insert(b, &BitVec::from_vec(vec![2, 0, 0]), &Felt::ONE)
+        .unwrap();
+    assert!(storage.revert_identifier_to(b, BasicId::new(0)).is_err());
+}
+
+#[test]
+fn list_trie_logs_hashmap_db() {
+    let identifier = b"a".as_slice();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(3),
+        snapshot_interval: 1,
+        ..Default::default()
+    };
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config, 24).unwrap();
+    assert_eq!(storage.list_trie_logs().unwrap(), vec![]);
+    for block in 0..5u8 {
+        let key = BitVec::from_vec(vec![1, block, 0]);
+        storage.insert(identifier, &key, &Felt::ONE).unwrap();
+        storage.commit(BasicId::new(block.into())).unwrap();
+    }
+    // Commits that change nothing have no trie log, but still prune the oldest one.
+    storage.commit(BasicId::new(5)).unwrap();
+    let ids = [3, 4].map(BasicId::new);
+    assert_eq!(storage.list_trie_logs().unwrap(), ids);
+    for id in ids {
+        let size = storage.get_trie_log_size(id).unwrap().unwrap();
+        let changes = storage.get_change_batch(id).unwrap();
+        let entries = changes.serialize(&id);
+        assert_eq!(
+            size,
+            entries
+                .iter()
+                .map(|(key, value)| (key.len() + value.len()) as u64)
+                .sum::<u64>()
+        );
+    }
+    assert_eq!(storage.get_trie_log_size(BasicId::new(2)).unwrap(), None);
+    assert_eq!(storage.get_trie_log_size(BasicId::new(5)).unwrap(), None);
+
+    storage.revert_to(BasicId::new(3)).unwrap();
+    assert_eq!(storage.list_trie_logs().unwrap(), [BasicId::new(3)]);
+}
+
+#[test]
+fn advance_to_hashmap_db() {
+    let identifier = b"a".as_slice();
+    let config = BonsaiStorageConfig {
+        keep_reverted_trie_logs: true,
+        ..Default::default()
+    };
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap();
+    let first = BitVec::from_vec(vec![1, 0, 0]);
+    let mut root_hashes = Vec::new();
+    for block in 0..5u8 {
+        let key = BitVec::from_vec(vec![1, block, 0]);
+        storage
+            .insert(identifier, &key, &Felt::from(block + 1))
+            .unwrap();
+        match block {
+            2 => storage.insert(identifier, &first, &Felt::TWO).unwrap(),
+            3 => storage.remove(identifier, &first).unwrap(),
+            _ => {}
+        }
+        storage.commit(BasicId::new(block.into())).unwrap();
+        root_hashes.push(storage.root_hash(identifier).unwrap());
+    }
+
+    storage.revert_to(BasicId::new(1)).unwrap();
+    assert_eq!(storage.get_reverted_from(), Some(BasicId::new(4)));
+    assert_eq!(
+        storage.list_trie_logs().unwrap(),
+        (0..5).map(BasicId::new).collect::<Vec<_>>()
+    );
+    assert!(storage.advance_to(BasicId::new(5)).is_err());
+    storage.advance_to(BasicId::new(3)).unwrap();
+    assert_eq!(storage.get_latest_id(), Some(BasicId::new(3)));
+    assert_eq!(storage.root_hash(identifier).unwrap(), root_hashes[3]);
+    assert_eq!(storage.get(identifier, &first).unwrap(), None);
+    assert!(storage.advance_to(BasicId::new(2)).is_err());
+
+    // The kept trie logs are found again when the storage is reopened.
+    storage.revert_to(BasicId::new(2)).unwrap();
+    assert_eq!(storage.root_hash(identifier).unwrap(), root_hashes[2]);
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(storage.into_db(), config, 24).unwrap();
+    assert_eq!(storage.get_reverted_from(), Some(BasicId::new(4)));
+    storage.advance_to(BasicId::new(4)).unwrap();
+    assert_eq!(storage.root_hash(identifier).unwrap(), root_hashes[4]);
+    assert_eq!(storage.get_reverted_from(), None);
+
+    // A new commit discards the kept trie logs.
+    storage.revert_to(BasicId::new(2)).unwrap();
+    let key = BitVec::from_vec(vec![2, 0, 0]);
+    storage.insert(identifier, &key, &Felt::ONE).unwrap();
+    storage.commit(BasicId::new(3)).unwrap();
+    assert_eq!(storage.get_reverted_from(), None);
+    assert_eq!(
+        storage.list_trie_logs().unwrap(),
+        (0..4).map(BasicId::new).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        storage
+            .get_trie_changes(identifier, BasicId::new(3))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(storage.advance_to(BasicId::new(4)).is_err());
+    storage.revert_to(BasicId::new(2)).unwrap();
+    assert_eq!(storage.root_hash(identifier).unwrap(), root_hashes[2]);
+
+    // The trie logs are removed by the revert without the option.
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(storage.into_db(), BonsaiStorageConfig::default(), 24).unwrap();
+    storage.revert_to(BasicId::new(1)).unwrap();
+    assert_eq!(storage.get_reverted_from(), None);
+    assert_eq!(storage.list_trie_logs().unwrap(), [0, 1].map(BasicId::new));
+    assert!(storage.advance_to(BasicId::new(2)).is_err());
+}
+
+#[test]
+fn pin_commit_hashmap_db() {
+    let identifier = b"a".as_slice();
+    let config = BonsaiStorageConfig {
+        max_saved_trie_logs: Some(2),
+        max_saved_snapshots: Some(1),
+        snapshot_interval: 2,
+        ..Default::default()
+    };
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap();
+    let key = BitVec::from_vec(vec![1, 0, 0]);
+    let removed = BitVec::from_vec(vec![2, 0, 0]);
+    let commit = |storage: &mut BonsaiStorage<BasicId, _, Pedersen>, block: u64| {
+        storage
+            .insert(identifier, &key, &Felt::from(block + 1))
+            .unwrap();
+        storage.commit(BasicId::new(block)).unwrap();
+    };
+    storage.insert(identifier, &removed, &Felt::ONE).unwrap();
+    for block in 0..2 {
+        commit(&mut storage, block);
+    }
+    storage.remove(identifier, &removed).unwrap();
+    for block in 2..4 {
+        commit(&mut storage, block);
+    }
+    assert_eq!(
+        storage.get_at(identifier, &key, BasicId::new(1)).unwrap(),
+        Some(Felt::TWO)
+    );
+    assert_eq!(
+        storage.get_at(identifier, &key, BasicId::new(3)).unwrap(),
+        Some(Felt::from(4))
+    );
+    assert_eq!(
+        storage
+            .get_at(identifier, &removed, BasicId::new(1))
+            .unwrap(),
+        Some(Felt::ONE)
+    );
+    assert_eq!(
+        storage
+            .get_at(identifier, &removed, BasicId::new(2))
+            .unwrap(),
+        None
+    );
+    assert!(storage.get_at(identifier, &key, BasicId::new(0)).is_err());
+    assert!(storage.pin_commit(BasicId::new(0)).is_err());
+
+    // The trie logs after a pinned commit are kept past `max_saved_trie_logs`.
+    let guard = storage.pin_commit(BasicId::new(1)).unwrap();
+    assert_eq!(guard.id(), BasicId::new(1));
+    for block in 4..7 {
+        commit(&mut storage, block);
+    }
+    assert_eq!(
+        storage.get_at(identifier, &key, BasicId::new(1)).unwrap(),
+        Some(Felt::TWO)
+    );
+    assert_eq!(
+        storage.list_trie_logs().unwrap(),
+        (2..7).map(BasicId::new).collect::<Vec<_>>()
+    );
+    drop(guard);
+    commit(&mut storage, 7);
+    assert!(storage.get_at(identifier, &key, BasicId::new(1)).is_err());
+    assert_eq!(storage.list_trie_logs().unwrap(), [6, 7].map(BasicId::new));
+
+    // So is the snapshot a transactional state at a pinned commit is created from.
+    assert_eq!(storage.list_snapshots(), [BasicId::new(6)]);
+    let guard = storage.pin_commit(BasicId::new(7)).unwrap();
+    for block in 8..11 {
+        commit(&mut storage, block);
+    }
+    assert_eq!(storage.list_snapshots(), [6, 10].map(BasicId::new));
+    let state = storage
+        .get_transactional_state(BasicId::new(7), storage.get_config())
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.get(identifier, &key).unwrap(), Some(Felt::from(8)));
+    drop(state);
+
+    // The pruning deferred by a pin is resumed after the storage is reopened.
+    let storage = storage.into_db();
+    drop(guard);
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(storage, config, 24).unwrap();
+    assert_eq!(
+        storage.list_trie_logs().unwrap(),
+        (7..11).map(BasicId::new).collect::<Vec<_>>()
+    );
+    commit(&mut storage, 11);
+    assert_eq!(
+        storage.list_trie_logs().unwrap(),
+        [10, 11].map(BasicId::new)
+    );
+}
+
+#[test]
+fn export_snapshot_hashmap_db() {
+    let identifier = b"a".as_slice();
+    let mut storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let first = BitVec::from_vec(vec![1, 0, 0]);
+    let mut root_hashes = Vec::new();
+    for block in 0..4u8 {
+        let key = BitVec::from_vec(vec![1, block, 0]);
+        storage
+            .insert(identifier, &key, &Felt::from(block + 1))
+            .unwrap();
+        if block == 2 {
+            storage.remove(identifier, &first).unwrap();
+        }
+        storage.put_meta(b"block", &[block]);
+        storage.commit(BasicId::new(block.into())).unwrap();
+        root_hashes.push(storage.root_hash(identifier).unwrap());
+    }
+    storage.insert(identifier, &first, &Felt::from(10)).unwrap();
+
+    for id in [1, 3] {
+        let mut target = HashMapDb::<BasicId>::default();
+        storage
+            .export_snapshot_into(BasicId::new(id), &mut target)
+            .unwrap();
+        let mut exported: BonsaiStorage<BasicId, _, Pedersen> =
+            BonsaiStorage::open_existing(target).unwrap();
+        assert_eq!(exported.get_latest_id(), Some(BasicId::new(id)));
+        assert_eq!(
+            exported.root_hash(identifier).unwrap(),
+            root_hashes[id as usize]
+        );
+        let first_value = (id < 2).then_some(Felt::ONE);
+        assert_eq!(exported.get(identifier, &first).unwrap(), first_value);
+        assert_eq!(exported.list_trie_logs().unwrap(), vec![]);
+        assert_eq!(exported.get_meta(b"block").unwrap(), None);
+
+        // The exported database goes on from the exported commit.
+        exported.insert(identifier, &first, &Felt::TWO).unwrap();
+        exported.commit(BasicId::new(id + 1)).unwrap();
+        exported.revert_to(BasicId::new(id)).unwrap();
+        assert_eq!(
+            exported.root_hash(identifier).unwrap(),
+            root_hashes[id as usize]
+        );
+    }
+    assert!(storage
+        .export_snapshot_into(BasicId::new(4), &mut HashMapDb::<BasicId>::default())
+        .is_err());
+}
+
+#[test]
+fn import_snapshot_hashmap_db() {
+    let config = BonsaiStorageConfig {
+        trie_hashers: [(b"b".as_slice().into(), TrieHasher::new::<Poseidon>())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let new_storage = || -> BonsaiStorage<BasicId, _, Pedersen> {
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 24).unwrap()
+    };
+    let mut storage = new_storage();
+    for block in 0..3u8 {
+        for identifier in [b"a", b"b"] {
+            let key = BitVec::from_vec(vec![block, 1, 2]);
+            storage
+                .insert(identifier, &key, &Felt::from(block + 1))
+                .unwrap();
+        }
+        storage.commit(BasicId::new(block.into())).unwrap();
+    }
+    let mut snapshot = HashMapDb::<BasicId>::default();
+    storage
+        .export_snapshot_into(BasicId::new(1), &mut snapshot)
+        .unwrap();
+    let trusted: HashMap<ByteVec, Felt> = [b"a", b"b"]
+        .map(|identifier| {
+            let root_hash = storage.root_hash_at(identifier, BasicId::new(1)).unwrap();
+            (identifier.as_slice().into(), root_hash.unwrap())
+        })
+        .into_iter()
+        .collect();
+
+    let mut imported = new_storage();
+    assert_eq!(
+        imported.import_snapshot(&snapshot, &trusted).unwrap(),
+        BasicId::new(1)
+    );
+    assert_eq!(imported.get_latest_id(), Some(BasicId::new(1)));
+    for (identifier, root_hash) in &trusted {
+        assert_eq!(imported.root_hash(identifier).unwrap(), *root_hash);
+    }
+    assert!(matches!(
+        imported.import_snapshot(&snapshot, &trusted),
+        Err(BonsaiStorageError::ImportIntoNonEmpty)
+    ));
+
+    // A tampered leaf changes the root hash, and nothing is written.
+    let mut tampered = snapshot.clone();
+    let (key, value) = tampered
+        .get_by_prefix(&DatabaseKey::Flat(b"a"))
+        .unwrap()
+        .remove(0);
+    let mut value = value.to_vec();
+    value[31] ^= 1;
+    tampered
+        .insert(&DatabaseKey::Flat(&key), &value, None)
+        .unwrap();
+    let mut imported = new_storage();
+    assert!(matches!(
+        imported.import_snapshot(&tampered, &trusted),
+        Err(BonsaiStorageError::ImportRootMismatch(mismatch)) if mismatch.identifier.as_slice() == b"a"
+            && mismatch.expected == trusted[b"a".as_slice()]
+    ));
+    assert_eq!(imported.get_latest_id(), None);
+    assert_eq!(
+        imported
+            .get(b"b", &BitVec::from_vec(vec![0, 1, 2]))
+            .unwrap(),
+        None
+    );
+
+    // The tries staged before the mismatch are removed.
+    let mut tampered = snapshot.clone();
+    let (key, value) = tampered
+        .get_by_prefix(&DatabaseKey::Flat(b"b"))
+        .unwrap()
+        .remove(0);
+    let mut value = value.to_vec();
+    value[31] ^= 1;
+    tampered
+        .insert(&DatabaseKey::Flat(&key), &value, None)
+        .unwrap();
+    assert!(matches!(
+        imported.import_snapshot(&tampered, &trusted),
+        Err(BonsaiStorageError::ImportRootMismatch(mismatch)) if mismatch.identifier.as_slice() == b"b"
+    ));
+    assert_eq!(imported.get_latest_id(), None);
+    assert_eq!(
+        imported
+            .get(b"a", &BitVec::from_vec(vec![0, 1, 2]))
+            .unwrap(),
+        None
+    );
+
+    // So does a trie without trusted root hash.
+    let mut partial = trusted.clone();
+    partial.remove(b"b".as_slice());
+    assert!(matches!(
+        imported.import_snapshot(&snapshot, &partial),
+        Err(BonsaiStorageError::UntrustedTrie { identifier }) if identifier.as_slice() == b"b"
+    ));
+    assert_eq!(imported.get_latest_id(), None);
+
+    // The height of the tries must be the one of the storage.
+    let mut other_height: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(HashMapDb::<BasicId>::default(), config.clone(), 32).unwrap();
+    assert!(matches!(
+        other_height.import_snapshot(&snapshot, &trusted),
+        Err(BonsaiStorageError::SnapshotHeightMismatch {
+            stored: 24,
+            requested: 32
+        })
+    ));
+
+    // The storage is still empty, and takes the snapshot.
+    assert_eq!(
+        imported.import_snapshot(&snapshot, &trusted).unwrap(),
+        BasicId::new(1)
+    );
+    assert!(matches!(
+        imported.import_snapshot(&snapshot, &trusted),
+        Err(BonsaiStorageError::ImportIntoNonEmpty)
+    ));
+    assert!(matches!(
+        new_storage().import_snapshot(&HashMapDb::<BasicId>::default(), &trusted),
+        Err(BonsaiStorageError::EmptySnapshot)
+    ));
+}
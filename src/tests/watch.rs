@@ -0,0 +1,152 @@
+#![cfg(feature = "std")]
+use crate::{
+    databases::HashMapDb,
+    id::{BasicId, BasicIdBuilder},
+    BitVec, BonsaiStorage, BonsaiStorageConfig, LeafChange,
+};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+use std::sync::{mpsc, Arc, Mutex};
+
+fn storage() -> BonsaiStorage<BasicId, HashMapDb<BasicId>, Pedersen> {
+    BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap()
+}
+
+fn change(key: &BitVec, old_value: Option<u32>, new_value: Option<u32>) -> LeafChange {
+    LeafChange {
+        identifier: [1][..].into(),
+        key: key.clone(),
+        old_value: old_value.map(Felt::from),
+        new_value: new_value.map(Felt::from),
+    }
+}
+
+#[test]
+fn commits_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    let handle = bonsai_storage.watch(&identifier, &key1, move |id, change| {
+        sender.lock().unwrap().send((id, change.clone())).unwrap();
+    });
+    // The same key of another trie is not watched.
+    bonsai_storage
+        .insert(&[2], &key1, &Felt::from(7u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    let id0 = id_builder.new_id();
+    bonsai_storage.commit(id0).unwrap();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![(id0, change(&key1, None, Some(1)))]
+    );
+
+    // Setting the same value is not a change.
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(3u32))
+        .unwrap();
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(receiver.try_iter().count(), 0);
+
+    // Prepared commits are watched as well.
+    bonsai_storage.remove(&identifier, &key1).unwrap();
+    let prepared = bonsai_storage.prepare_commit().unwrap();
+    let id2 = id_builder.new_id();
+    bonsai_storage.commit_prepared(id2, prepared).unwrap();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![(id2, change(&key1, Some(1), None))]
+    );
+
+    // An aborted commit is not reported.
+    bonsai_storage.add_commit_hook(|_| Err("aborted".to_string()));
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(4u32))
+        .unwrap();
+    assert!(bonsai_storage.commit(id_builder.new_id()).is_err());
+    bonsai_storage.clear_commit_hooks();
+    assert_eq!(receiver.try_iter().count(), 0);
+
+    drop(handle);
+    bonsai_storage.commit(id_builder.new_id()).unwrap();
+    assert_eq!(receiver.try_iter().count(), 0);
+}
+
+#[test]
+fn reorg_and_merge_hashmap_db() {
+    let identifier = vec![1];
+    let mut bonsai_storage = storage();
+    let mut id_builder = BasicIdBuilder::new();
+    let key1 = BitVec::from_vec(vec![1, 2, 1]);
+    let key2 = BitVec::from_vec(vec![1, 2, 2]);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let _handle = bonsai_storage.watch(&identifier, &key2, {
+        let received = received.clone();
+        move |id, change: &LeafChange| received.lock().unwrap().push((id, change.clone()))
+    });
+    let id0 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key1, &Felt::from(1u32))
+        .unwrap();
+    bonsai_storage.commit(id0).unwrap();
+    let id1 = id_builder.new_id();
+    bonsai_storage
+        .insert(&identifier, &key2, &Felt::from(2u32))
+        .unwrap();
+    bonsai_storage.commit(id1).unwrap();
+
+    // The revert itself is not reported, the commits of the new branch are.
+    let id2 = id_builder.new_id();
+    let id3 = id_builder.new_id();
+    bonsai_storage
+        .reorg(
+            id0,
+            vec![
+                (
+                    id2,
+                    vec![(identifier.clone(), key1.clone(), Felt::from(5u32))],
+                ),
+                (
+                    id3,
+                    vec![(identifier.clone(), key2.clone(), Felt::from(6u32))],
+                ),
+            ],
+        )
+        .unwrap();
+
+    let mut txn = bonsai_storage
+        .get_transactional_state(id3, BonsaiStorageConfig::default())
+        .unwrap()
+        .unwrap();
+    txn.insert(&identifier, &key2, &Felt::from(7u32)).unwrap();
+    let id4 = id_builder.new_id();
+    txn.transactional_commit(id4).unwrap();
+    bonsai_storage.merge(txn).unwrap();
+
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![
+            (id1, change(&key2, None, Some(2))),
+            (id3, change(&key2, None, Some(6))),
+            (id4, change(&key2, Some(6), Some(7))),
+        ]
+    );
+}
@@ -0,0 +1,87 @@
+#![cfg(all(feature = "std", feature = "rocksdb"))]
+use crate::{
+    databases::{HashMapDb, WitnessDb},
+    id::BasicId,
+    BitVec, BonsaiDatabase, BonsaiStorage, BonsaiStorageConfig, Witness,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use starknet_types_core::{felt::Felt, hash::Pedersen};
+
+const CONTRACTS: &[u8] = b"contracts";
+const CLASSES: &[u8] = b"classes";
+
+/// The accesses of a block, replayed from the witness alone.
+fn block<DB: BonsaiDatabase>(
+    storage: &mut BonsaiStorage<BasicId, DB, Pedersen>,
+    keys: &[BitVec],
+) -> Vec<Option<Felt>> {
+    let mut reads = Vec::new();
+    for key in &keys[..8] {
+        reads.push(storage.get(CONTRACTS, key).unwrap());
+    }
+    reads.push(storage.get(CONTRACTS, &keys[100]).unwrap());
+    reads.push(Some(Felt::from(
+        storage.contains(CLASSES, &keys[9]).unwrap(),
+    )));
+    for (i, key) in keys[10..16].iter().chain(&keys[64..70]).enumerate() {
+        storage.insert(CLASSES, key, &Felt::from(i + 100)).unwrap();
+    }
+    reads
+}
+
+#[test]
+fn conformance() {
+    crate::testing::run_database_suite(|| WitnessDb::<BasicId>::new(&Witness::default(), 24));
+}
+
+#[test]
+// The witness holds only some of the leaves, which `audit` takes for a corrupted trie.
+#[cfg(not(feature = "audit"))]
+fn replay_from_witness_hashmap_db() {
+    let mut bonsai_storage: BonsaiStorage<BasicId, _, Pedersen> = BonsaiStorage::new(
+        HashMapDb::<BasicId>::default(),
+        BonsaiStorageConfig::default(),
+        24,
+    )
+    .unwrap();
+    let mut rng = SmallRng::seed_from_u64(7);
+    let keys: Vec<BitVec> = (0..128)
+        .map(|_| BitVec::from_vec(rng.gen::<[u8; 3]>().to_vec()))
+        .collect();
+    for (i, key) in keys[..64].iter().enumerate() {
+        for identifier in [CONTRACTS, CLASSES] {
+            bonsai_storage
+                .insert(identifier, key, &Felt::from(i + 1))
+                .unwrap();
+        }
+    }
+    bonsai_storage.commit(BasicId::new(0)).unwrap();
+
+    bonsai_storage.record_witness();
+    let reads = block(&mut bonsai_storage, &keys);
+    let witness = bonsai_storage.finish_witness().unwrap();
+    bonsai_storage.commit(BasicId::new(1)).unwrap();
+
+    let witness_db = WitnessDb::<BasicId>::new(&witness, 24);
+    let coverage = witness_db.coverage();
+    let mut stateless: BonsaiStorage<BasicId, _, Pedersen> =
+        BonsaiStorage::new(witness_db, BonsaiStorageConfig::default(), 24).unwrap();
+    assert_eq!(
+        stateless.root_hash(CONTRACTS).unwrap(),
+        witness.tries[CONTRACTS].root
+    );
+    assert_eq!(block(&mut stateless, &keys), reads);
+    stateless.commit(BasicId::new(1)).unwrap();
+    for identifier in [CONTRACTS, CLASSES] {
+        assert_eq!(
+            stateless.root_hash(identifier).unwrap(),
+            bonsai_storage.root_hash(identifier).unwrap()
+        );
+    }
+    assert!(coverage.is_covered(), "{:?}", coverage.uncovered_reads());
+
+    // A key the witness was not recorded for can't be read.
+    assert_eq!(stateless.get(CONTRACTS, &keys[40]).unwrap(), None);
+    assert!(!coverage.is_covered());
+    assert_eq!(coverage.uncovered_reads().len(), 1);
+}
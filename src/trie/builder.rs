@@ -0,0 +1,219 @@
+//! Streaming construction of a trie from sorted leaves, see [`IncrementalTrieBuilder`].
+
+use super::{
+    merkle_node::{BinaryNode, EdgeNode, Node, NodeHandle, TrieHasher},
+    path::Path,
+    tree::{bitslice_to_bytes, encode_leaf, leaf_count_key, node_height},
+    trie_db::TrieKeyType,
+    TrieKey,
+};
+use crate::{
+    format, BitSlice, BitVec, BonsaiDatabase, BonsaiStorageError, ByteVec, DatabaseKey, EncodeExt,
+    Vec,
+};
+use core::marker::PhantomData;
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// A complete subtrie: all the leaves under its position have been pushed.
+struct Subtrie {
+    /// A key of the subtrie, whose first `depth` bits are the position of its top node.
+    key: BitVec,
+    /// Depth of its top node, which is a leaf at the height of the trie and a binary node
+    /// otherwise.
+    depth: usize,
+    hash: Felt,
+    /// Depth of the binary node separating it from the previous subtrie of the stack.
+    split: usize,
+}
+
+/// Builds a trie from leaves pushed in increasing key order, in a single pass: the nodes are
+/// written to a batch as soon as all the leaves under them are known, so the memory used only
+/// depends on the height of the trie.
+///
+/// The trie `identifier` must be empty in the database. Its nodes, leaves and leaf count are
+/// written without trie log, and the trie can be read by a [`crate::BonsaiStorage`] once the
+/// batch is written.
+pub struct IncrementalTrieBuilder<H: StarkHash> {
+    identifier: ByteVec,
+    max_height: u8,
+    /// Subtries waiting for the binary node joining them to the next ones, by increasing `split`.
+    stack: Vec<Subtrie>,
+    leaves: u64,
+    hasher: TrieHasher,
+    /// Whether zero values are leaves, see [`IncrementalTrieBuilder::store_zero_values`].
+    store_zero: bool,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: StarkHash> IncrementalTrieBuilder<H> {
+    pub fn new(identifier: &[u8], max_height: u8) -> Self {
+        Self {
+            identifier: identifier.into(),
+            max_height,
+            stack: Vec::new(),
+            leaves: 0,
+            hasher: TrieHasher::new::<H>(),
+            store_zero: false,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Builder whose nodes are hashed with `hasher` instead of `H`.
+    pub fn with_hasher(identifier: &[u8], max_height: u8, hasher: TrieHasher) -> Self {
+        Self {
+            hasher,
+            ..Self::new(identifier, max_height)
+        }
+    }
+
+    /// Keep the leaves pushed with a zero value instead of skipping them, as the tries storing zero
+    /// values do, see [`crate::BonsaiStorageConfig::store_zero_values`].
+    pub fn store_zero_values(mut self, store_zero: bool) -> Self {
+        self.store_zero = store_zero;
+        self
+    }
+
+    /// Add a leaf, whose key must be greater than the one of the previous leaf. Zero values, unless
+    /// they are stored, and the default values of a sparse trie, are skipped, as they are not
+    /// stored in a trie.
+    pub fn push<DB: BonsaiDatabase>(
+        &mut self,
+        db: &mut DB,
+        batch: &mut DB::Batch,
+        key: &BitSlice,
+        value: Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if key.len() != self.max_height as usize {
+            return Err(BonsaiStorageError::KeyLength {
+                expected: self.max_height as usize,
+                got: key.len(),
+            });
+        }
+        if (value == Felt::ZERO && !self.store_zero) || Some(value) == self.hasher.default_value() {
+            return Ok(());
+        }
+        let split = match self.stack.last() {
+            Some(last) if last.key.as_bitslice() >= key => {
+                return Err(BonsaiStorageError::Trie(format!(
+                    "Leaves must be pushed in increasing key order, {:b} comes after {:b}",
+                    key, last.key
+                )));
+            }
+            Some(last) => last.key.iter().zip(key).take_while(|(a, b)| a == b).count(),
+            None => 0,
+        };
+        // The subtries below the new binary node are complete.
+        while self.stack.last().is_some_and(|last| last.split > split) {
+            self.join_last(db, batch)?;
+        }
+        let flat_key = TrieKey::new(&self.identifier, TrieKeyType::Flat, &bitslice_to_bytes(key));
+        db.insert(
+            &DatabaseKey::from(&flat_key),
+            &encode_leaf(&value, None),
+            Some(batch),
+        )?;
+        self.leaves += 1;
+        self.stack.push(Subtrie {
+            key: key.to_bitvec(),
+            depth: self.max_height as usize,
+            hash: value,
+            split,
+        });
+        Ok(())
+    }
+
+    /// Write the remaining nodes and the leaf count, and return the root hash of the trie.
+    pub fn finish<DB: BonsaiDatabase>(
+        mut self,
+        db: &mut DB,
+        batch: &mut DB::Batch,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        while self.stack.len() > 1 {
+            self.join_last(db, batch)?;
+        }
+        let Some(root) = self.stack.pop() else {
+            return Ok(self.hasher.empty_root(self.max_height));
+        };
+        let key = leaf_count_key(&self.identifier);
+        db.insert(
+            &DatabaseKey::from(&key),
+            &self.leaves.encode_bytevec(),
+            Some(batch),
+        )?;
+        self.attach(db, batch, &root, 0)
+    }
+
+    /// Join the last two subtries of the stack with a binary node.
+    fn join_last<DB: BonsaiDatabase>(
+        &mut self,
+        db: &mut DB,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let right = self.stack.pop().expect("Joining an empty stack");
+        let left = self.stack.pop().expect("Joining a single subtrie");
+        let depth = right.split;
+        let left_hash = self.attach(db, batch, &left, depth + 1)?;
+        let right_hash = self.attach(db, batch, &right, depth + 1)?;
+        let hash = self.hasher.hash_binary_node(left_hash, right_hash);
+        let node = Node::Binary(BinaryNode {
+            hash: Some(hash),
+            height: node_height::<DB::DatabaseError>(depth, self.max_height)?,
+            left: NodeHandle::Hash(left_hash),
+            right: NodeHandle::Hash(right_hash),
+        });
+        self.write_node(db, batch, &left.key[..depth], node)?;
+        self.stack.push(Subtrie {
+            key: left.key,
+            depth,
+            hash,
+            split: left.split,
+        });
+        Ok(())
+    }
+
+    /// Hash of `subtrie` as a child at `depth`, with the edge leading to its top node if there is
+    /// one.
+    fn attach<DB: BonsaiDatabase>(
+        &self,
+        db: &mut DB,
+        batch: &mut DB::Batch,
+        subtrie: &Subtrie,
+        depth: usize,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        if subtrie.depth == depth {
+            return Ok(subtrie.hash);
+        }
+        let path = Path(subtrie.key[depth..subtrie.depth].to_bitvec());
+        let hash = self
+            .hasher
+            .hash_edge_node(&path, subtrie.hash, depth, self.max_height);
+        let edge = Node::Edge(EdgeNode {
+            hash: Some(hash),
+            height: node_height::<DB::DatabaseError>(depth, self.max_height)?,
+            path,
+            child: NodeHandle::Hash(subtrie.hash),
+        });
+        self.write_node(db, batch, &subtrie.key[..depth], edge)?;
+        Ok(hash)
+    }
+
+    fn write_node<DB: BonsaiDatabase>(
+        &self,
+        db: &mut DB,
+        batch: &mut DB::Batch,
+        path: &BitSlice,
+        node: Node,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new(
+            &self.identifier,
+            TrieKeyType::Trie,
+            &Path(path.to_bitvec()).to_bytes(),
+        );
+        db.insert(
+            &DatabaseKey::from(&key),
+            &node.encode_versioned(),
+            Some(batch),
+        )?;
+        Ok(())
+    }
+}
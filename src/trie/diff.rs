@@ -0,0 +1,377 @@
+//! Structural comparison of two committed versions of a trie, see [`crate::BonsaiStorage::diff`]
+//! and [`compare`].
+
+use super::{
+    inline::InlineTrie,
+    merkle_node::{Node, NodeHandle, TrieHasher},
+    path::{Path, PathKey},
+    tree::decode_node,
+    trees::trie_hasher,
+    trie_db::TrieKeyType,
+    TrieKey,
+};
+use crate::{
+    format, id::Id, key_value_db::KeyValueDB, BitSlice, BitVec, BonsaiDatabase, BonsaiStorage,
+    BonsaiStorageError, ByteVec, Change, DBError, HashMap, Vec,
+};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// A path where two tries diverge, see [`compare`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrieDivergence {
+    pub path: Path,
+    /// Hash of the subtrie under `path` in the first trie, `None` if it has no leaf there. The
+    /// hash of a leaf is its value.
+    pub hash_a: Option<Felt>,
+    /// Hash of the subtrie under `path` in the second trie.
+    pub hash_b: Option<Felt>,
+}
+
+/// Reads the committed nodes of a trie.
+pub(crate) trait NodeSource<E: DBError> {
+    /// The node at `path` in a trie of height `max_height`, which is not found if the trie is
+    /// stored inline.
+    fn load(
+        &self,
+        identifier: &[u8],
+        path: &BitSlice,
+        max_height: u8,
+    ) -> Result<Option<Node>, BonsaiStorageError<E>>;
+
+    /// The record of the trie `identifier` if it is stored inline, see [`InlineTrie`].
+    fn inline_trie(&self, _identifier: &[u8]) -> Result<Option<InlineTrie>, BonsaiStorageError<E>> {
+        Ok(None)
+    }
+}
+
+impl<DB: BonsaiDatabase, ID: Id> NodeSource<DB::DatabaseError> for KeyValueDB<DB, ID> {
+    fn load(
+        &self,
+        identifier: &[u8],
+        path: &BitSlice,
+        max_height: u8,
+    ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new(
+            identifier,
+            TrieKeyType::Trie,
+            &Path(path.to_bitvec()).to_bytes(),
+        );
+        let Some(node) = self.get(&key)? else {
+            return Ok(None);
+        };
+        let node = decode_node(identifier, path, &key, &node, max_height)?;
+        Ok(Some(node))
+    }
+
+    fn inline_trie(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Option<InlineTrie>, BonsaiStorageError<DB::DatabaseError>> {
+        InlineTrie::read(self, identifier)
+    }
+}
+
+/// Nodes of a trie read from `db`, or rebuilt once from its record if it is stored inline.
+struct TrieNodes<'a, E: DBError> {
+    db: &'a dyn NodeSource<E>,
+    inline_nodes: Option<HashMap<TrieKey, ByteVec>>,
+}
+
+impl<'a, E: DBError> TrieNodes<'a, E> {
+    fn new<H: StarkHash>(
+        db: &'a dyn NodeSource<E>,
+        identifier: &[u8],
+        max_height: u8,
+        hasher: &TrieHasher,
+    ) -> Result<Self, BonsaiStorageError<E>> {
+        let inline_nodes = match db.inline_trie(identifier)? {
+            Some(record) => Some(record.nodes::<H, E>(identifier, max_height, hasher)?),
+            None => None,
+        };
+        Ok(Self { db, inline_nodes })
+    }
+}
+
+impl<E: DBError> NodeSource<E> for TrieNodes<'_, E> {
+    fn load(
+        &self,
+        identifier: &[u8],
+        path: &BitSlice,
+        max_height: u8,
+    ) -> Result<Option<Node>, BonsaiStorageError<E>> {
+        let Some(nodes) = &self.inline_nodes else {
+            return self.db.load(identifier, path, max_height);
+        };
+        let key = TrieKey::new(identifier, TrieKeyType::Trie, &PathKey::node(path));
+        let Some(node) = nodes.get(&key) else {
+            return Ok(None);
+        };
+        let node = decode_node(identifier, path, &key, node, max_height)?;
+        Ok(Some(node))
+    }
+}
+
+/// The subtrie under a path of a committed trie.
+#[derive(Clone, PartialEq)]
+enum Subtrie {
+    Empty,
+    /// The node stored at the path.
+    Node(Node),
+    /// Middle of an edge: the rest of its path, and the hash of its child.
+    Edge(BitVec, Felt),
+    Leaf(Felt),
+}
+
+impl Subtrie {
+    /// Hash of the subtrie at `depth` in a trie of height `max_height`.
+    fn hash(&self, hasher: &TrieHasher, depth: usize, max_height: u8) -> Option<Felt> {
+        match self {
+            Subtrie::Empty => None,
+            Subtrie::Node(node) => node.get_hash(),
+            Subtrie::Edge(rest, child) => {
+                Some(hasher.hash_edge_node(&Path(rest.clone()), *child, depth, max_height))
+            }
+            Subtrie::Leaf(value) => Some(*value),
+        }
+    }
+
+    /// Whether the two subtries are known to be the same without going through their children.
+    fn same(&self, other: &Subtrie) -> bool {
+        match (self, other) {
+            (Subtrie::Node(a), Subtrie::Node(b)) => a.get_hash() == b.get_hash(),
+            _ => self == other,
+        }
+    }
+}
+
+/// Walks two versions of a trie together.
+struct TrieWalk<'a, E: DBError> {
+    identifier: &'a [u8],
+    max_height: u8,
+    a: &'a dyn NodeSource<E>,
+    b: &'a dyn NodeSource<E>,
+    hasher: TrieHasher,
+}
+
+impl<E: DBError> TrieWalk<'_, E> {
+    fn root(&self, db: &dyn NodeSource<E>) -> Result<Subtrie, BonsaiStorageError<E>> {
+        Ok(db
+            .load(self.identifier, BitSlice::empty(), self.max_height)?
+            .map_or(Subtrie::Empty, Subtrie::Node))
+    }
+
+    /// The subtrie under `path`, whose parent references it with `child`.
+    fn child(
+        &self,
+        db: &dyn NodeSource<E>,
+        path: &BitSlice,
+        child: Felt,
+    ) -> Result<Subtrie, BonsaiStorageError<E>> {
+        if path.len() == self.max_height as usize {
+            return Ok(Subtrie::Leaf(child));
+        }
+        match db.load(self.identifier, path, self.max_height)? {
+            Some(node) => Ok(Subtrie::Node(node)),
+            None => Err(BonsaiStorageError::NodeNotFound {
+                identifier: self.identifier.into(),
+                path: Path(path.to_bitvec()),
+            }),
+        }
+    }
+
+    /// The subtrie under `path` followed by `bit`, where `subtrie` is under `path`.
+    fn descend(
+        &self,
+        db: &dyn NodeSource<E>,
+        subtrie: &Subtrie,
+        path: &BitSlice,
+        bit: bool,
+    ) -> Result<Subtrie, BonsaiStorageError<E>> {
+        let (rest, child) = match subtrie {
+            Subtrie::Empty | Subtrie::Leaf(_) => return Ok(Subtrie::Empty),
+            Subtrie::Node(Node::Binary(binary)) => {
+                let child = if bit { binary.right } else { binary.left };
+                let mut child_path = path.to_bitvec();
+                child_path.push(bit);
+                return self.child(db, &child_path, handle_hash(child)?);
+            }
+            Subtrie::Node(Node::Edge(edge)) => (&edge.path.0[..], handle_hash(edge.child)?),
+            Subtrie::Edge(rest, child) => (&rest[..], *child),
+        };
+        if rest.is_empty() || rest[0] != bit {
+            return Ok(Subtrie::Empty);
+        }
+        let mut child_path = path.to_bitvec();
+        child_path.push(bit);
+        if rest.len() == 1 {
+            self.child(db, &child_path, child)
+        } else {
+            Ok(Subtrie::Edge(rest[1..].to_bitvec(), child))
+        }
+    }
+
+    /// The left and right children of `a` and `b`, the subtries under `path`.
+    fn children(
+        &self,
+        a: &Subtrie,
+        b: &Subtrie,
+        path: &BitSlice,
+    ) -> Result<[(Subtrie, Subtrie); 2], BonsaiStorageError<E>> {
+        Ok([
+            (
+                self.descend(self.a, a, path, false)?,
+                self.descend(self.b, b, path, false)?,
+            ),
+            (
+                self.descend(self.a, a, path, true)?,
+                self.descend(self.b, b, path, true)?,
+            ),
+        ])
+    }
+
+    /// Collect the leaves that differ between `a` and `b`, the subtries under `path`.
+    fn changed_leaves(
+        &self,
+        a: Subtrie,
+        b: Subtrie,
+        path: &mut BitVec,
+        changes: &mut HashMap<BitVec, Change>,
+    ) -> Result<(), BonsaiStorageError<E>> {
+        if a.same(&b) {
+            return Ok(());
+        }
+        if path.len() == self.max_height as usize {
+            let value = |subtrie: Subtrie| match subtrie {
+                Subtrie::Leaf(value) => Some(value),
+                _ => None,
+            };
+            changes.insert(
+                path.clone(),
+                Change {
+                    old_value: value(a),
+                    new_value: value(b),
+                },
+            );
+            return Ok(());
+        }
+        let children = self.children(&a, &b, path)?;
+        for (bit, (child_a, child_b)) in [false, true].into_iter().zip(children) {
+            path.push(bit);
+            self.changed_leaves(child_a, child_b, path, changes)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Collect the topmost paths where `a` and `b`, the subtries under `path`, differ while their
+    /// children don't explain the difference.
+    fn divergences(
+        &self,
+        a: Subtrie,
+        b: Subtrie,
+        path: &mut BitVec,
+        divergences: &mut Vec<TrieDivergence>,
+    ) -> Result<(), BonsaiStorageError<E>> {
+        if a.same(&b) {
+            return Ok(());
+        }
+        let leaf_or_empty =
+            path.len() == self.max_height as usize || a == Subtrie::Empty || b == Subtrie::Empty;
+        if !leaf_or_empty {
+            let children = self.children(&a, &b, path)?;
+            if children.iter().any(|(a, b)| !a.same(b)) {
+                for (bit, (child_a, child_b)) in [false, true].into_iter().zip(children) {
+                    path.push(bit);
+                    self.divergences(child_a, child_b, path, divergences)?;
+                    path.pop();
+                }
+                return Ok(());
+            }
+        }
+        divergences.push(TrieDivergence {
+            path: Path(path.clone()),
+            hash_a: a.hash(&self.hasher, path.len(), self.max_height),
+            hash_b: b.hash(&self.hasher, path.len(), self.max_height),
+        });
+        Ok(())
+    }
+}
+
+fn handle_hash<E: DBError>(handle: NodeHandle) -> Result<Felt, BonsaiStorageError<E>> {
+    handle.as_hash().ok_or_else(|| {
+        BonsaiStorageError::Trie("Committed node references an in-memory node".into())
+    })
+}
+
+/// Leaves of the trie `identifier` that differ between the committed tries of `a` and `b`, as
+/// changes from `a` to `b`. The subtries with the same hash in both are skipped.
+pub(crate) fn diff<DB: BonsaiDatabase, ID: Id, H: StarkHash>(
+    identifier: &[u8],
+    max_height: u8,
+    a: &KeyValueDB<DB, ID>,
+    b: &KeyValueDB<DB, ID>,
+) -> Result<HashMap<BitVec, Change>, BonsaiStorageError<DB::DatabaseError>> {
+    let hasher = trie_hasher::<H>(&a.config, identifier);
+    let a = TrieNodes::new::<H>(a, identifier, max_height, &hasher)?;
+    let b = TrieNodes::new::<H>(b, identifier, max_height, &hasher)?;
+    let walk = TrieWalk {
+        identifier,
+        max_height,
+        a: &a,
+        b: &b,
+        hasher,
+    };
+    let mut changes = HashMap::new();
+    walk.changed_leaves(
+        walk.root(walk.a)?,
+        walk.root(walk.b)?,
+        &mut BitVec::new(),
+        &mut changes,
+    )?;
+    Ok(changes)
+}
+
+/// Compare the committed tries `identifier` of two storages, to find where they diverge when their
+/// root hashes don't match. The two tries are walked together, skipping the subtries with the same
+/// hash in both, and the topmost paths where they differ are returned in key order: leaves with
+/// different values, subtries that only one of the tries has, and nodes whose hashes differ
+/// although their children are the same. The uncommitted changes are ignored.
+pub fn compare<IA, IB, DA, DB, H>(
+    storage_a: &BonsaiStorage<IA, DA, H>,
+    storage_b: &BonsaiStorage<IB, DB, H>,
+    identifier: &[u8],
+) -> Result<Vec<TrieDivergence>, BonsaiStorageError<DA::DatabaseError>>
+where
+    IA: Id,
+    IB: Id,
+    DA: BonsaiDatabase,
+    DB: BonsaiDatabase<DatabaseError = DA::DatabaseError>,
+    H: StarkHash + Send + Sync,
+{
+    let (a, b) = (&storage_a.tries, &storage_b.tries);
+    if a.max_height != b.max_height {
+        return Err(BonsaiStorageError::Trie(format!(
+            "Cannot compare tries of heights {} and {}",
+            a.max_height, b.max_height
+        )));
+    }
+    let hasher = trie_hasher::<H>(&a.db_ref().config, identifier);
+    let max_height = a.max_height;
+    let a = TrieNodes::new::<H>(a.db_ref(), identifier, max_height, &hasher)?;
+    let b = TrieNodes::new::<H>(b.db_ref(), identifier, max_height, &hasher)?;
+    let walk = TrieWalk {
+        identifier,
+        max_height,
+        a: &a,
+        b: &b,
+        hasher,
+    };
+    let mut divergences = Vec::new();
+    walk.divergences(
+        walk.root(walk.a)?,
+        walk.root(walk.b)?,
+        &mut BitVec::new(),
+        &mut divergences,
+    )?;
+    Ok(divergences)
+}
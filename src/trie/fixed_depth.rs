@@ -0,0 +1,70 @@
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+use crate::{trie::merkle_node::TrieHasher, ByteVec, MerkleTree};
+
+/// A [`MerkleTree`] committing to its leaves as a plain binary Merkle tree, for instance for
+/// circuits which can't verify the paths of edge nodes.
+///
+/// The root hash is the one of the complete binary tree of height `max_height` whose leaves are
+/// the values of the keys, zero for the missing ones, every binary node being hashed with `H`. The
+/// nodes are still stored compressed in edges, so the tree shares the storage, commits and trie
+/// logs of the Patricia tries, only its hashes differ. The missing keys read as zero.
+///
+/// To use it in a [`crate::BonsaiStorage`], set the trie hasher of its identifier to
+/// [`FixedDepthMerkleTree::hasher`] in [`crate::BonsaiStorageConfig::trie_hashers`].
+pub struct FixedDepthMerkleTree<H: StarkHash>(MerkleTree<H>);
+
+impl<H: StarkHash + Send + Sync> FixedDepthMerkleTree<H> {
+    pub fn new(identifier: ByteVec, max_height: u8) -> Self {
+        Self(MerkleTree::with_hasher(
+            identifier,
+            max_height,
+            Self::hasher(),
+        ))
+    }
+
+    /// The hasher of the fixed depth trees: a sparse trie hasher whose default value is zero.
+    pub fn hasher() -> TrieHasher {
+        TrieHasher::sparse::<H>(Felt::ZERO)
+    }
+
+    /// Root hash of a tree of height `max_height` without any leaf.
+    pub fn empty_root(max_height: u8) -> Felt {
+        Self::hasher().empty_root(max_height)
+    }
+
+    pub fn into_inner(self) -> MerkleTree<H> {
+        self.0
+    }
+}
+
+impl<H: StarkHash> fmt::Debug for FixedDepthMerkleTree<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FixedDepthMerkleTree")
+            .field(&self.0)
+            .finish()
+    }
+}
+
+impl<H: StarkHash> Clone for FixedDepthMerkleTree<H> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<H: StarkHash> Deref for FixedDepthMerkleTree<H> {
+    type Target = MerkleTree<H>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<H: StarkHash> DerefMut for FixedDepthMerkleTree<H> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
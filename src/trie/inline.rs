@@ -0,0 +1,216 @@
+//! Tries stored as a single record listing their leaves instead of their nodes, see
+//! [`crate::BonsaiStorageConfig::max_inline_leaves`].
+
+use super::{
+    builder::IncrementalTrieBuilder,
+    merkle_node::{Node, TrieHasher},
+    tree::{bytes_to_bitvec, decode_leaf, is_node_key, InsertOrRemove},
+    trees::Updates,
+    trie_db::TrieKeyType,
+    TrieKey,
+};
+use crate::{
+    databases::HashMapDb,
+    format,
+    id::{BasicId, Id},
+    key_value_db::KeyValueDB,
+    BonsaiDatabase, BonsaiStorageError, ByteVec, DBError, DatabaseKey, EncodeExt, HashMap, Vec,
+};
+use parity_scale_codec::Decode;
+use starknet_types_core::{felt::Felt, hash::StarkHash};
+
+/// The paths of the trie nodes start with their length in bits, which is lower than `0xff`, and
+/// the leaf count is stored at `0xff` alone, so this can't collide with either.
+const INLINE_TRIE_KEY: [u8; 2] = [u8::MAX, 0];
+
+/// Key of the record of the trie `identifier` when it is stored inline. It is stored with the trie
+/// nodes so that its changes are recorded in the trie logs.
+pub(crate) fn inline_trie_key(identifier: &[u8]) -> TrieKey {
+    TrieKey::new(identifier, TrieKeyType::Trie, &INLINE_TRIE_KEY)
+}
+
+/// Record of a trie stored inline: its root hash and its leaves, from which its nodes are rebuilt.
+/// The leaves are also in the flat storage, as for the other tries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InlineTrie {
+    pub(crate) root_hash: Felt,
+    /// The values of the leaves by increasing key, the keys being the ones of the flat storage
+    /// without the identifier of the trie.
+    pub(crate) leaves: Vec<(ByteVec, Felt)>,
+}
+
+impl InlineTrie {
+    pub(crate) fn encode(&self) -> ByteVec {
+        let leaves: Vec<(&[u8], &Felt)> = self
+            .leaves
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value))
+            .collect();
+        (&self.root_hash, leaves).encode_bytevec()
+    }
+
+    pub(crate) fn decode(mut bytes: &[u8]) -> Result<Self, parity_scale_codec::Error> {
+        let (root_hash, leaves) = <(Felt, Vec<(Vec<u8>, Felt)>)>::decode(&mut bytes)?;
+        Ok(Self {
+            root_hash,
+            leaves: leaves
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        })
+    }
+
+    /// The record of the trie `identifier`, `None` if it is not stored inline.
+    pub(crate) fn read<DB: BonsaiDatabase, ID: Id>(
+        db: &KeyValueDB<DB, ID>,
+        identifier: &[u8],
+    ) -> Result<Option<Self>, BonsaiStorageError<DB::DatabaseError>> {
+        if db.config.max_inline_leaves.is_none() {
+            return Ok(None);
+        }
+        let key = inline_trie_key(identifier);
+        db.get(&key)?
+            .map(|bytes| {
+                Self::decode(&bytes).map_err(|source| BonsaiStorageError::DecodeError {
+                    key: key.as_slice().into(),
+                    source,
+                })
+            })
+            .transpose()
+    }
+
+    /// The nodes of the trie `identifier` by key, encoded as in the database. They are rebuilt
+    /// from the leaves, which costs hashing all of them.
+    pub(crate) fn nodes<H: StarkHash, E: DBError>(
+        &self,
+        identifier: &[u8],
+        max_height: u8,
+        hasher: &TrieHasher,
+    ) -> Result<HashMap<TrieKey, ByteVec>, BonsaiStorageError<E>> {
+        build_nodes::<H, E>(identifier, max_height, hasher, &self.leaves)
+    }
+}
+
+/// The nodes of the trie `identifier` with the leaves `sorted_leaves`, by key, encoded as in the
+/// database. The keys of the leaves are the ones of the flat storage without the identifier of
+/// the trie, and zero values are leaves like the others.
+pub(crate) fn build_nodes<H: StarkHash, E: DBError>(
+    identifier: &[u8],
+    max_height: u8,
+    hasher: &TrieHasher,
+    sorted_leaves: &[(ByteVec, Felt)],
+) -> Result<HashMap<TrieKey, ByteVec>, BonsaiStorageError<E>> {
+    // The builder writes the trie to a scratch database, which can't fail.
+    let scratch_error = |err| match err {
+        BonsaiStorageError::KeyLength { expected, got } => {
+            BonsaiStorageError::KeyLength { expected, got }
+        }
+        err => BonsaiStorageError::Trie(format!("Rebuilding the nodes of a trie: {:?}", err)),
+    };
+    let mut db = HashMapDb::<BasicId>::default();
+    let mut batch = db.create_batch();
+    let mut builder =
+        IncrementalTrieBuilder::<H>::with_hasher(identifier, max_height, hasher.clone())
+            .store_zero_values(true);
+    for (key, value) in sorted_leaves {
+        builder
+            .push(&mut db, &mut batch, &bytes_to_bitvec(key), *value)
+            .map_err(scratch_error)?;
+    }
+    builder.finish(&mut db, &mut batch).map_err(scratch_error)?;
+    db.write_batch(batch)
+        .map_err(|err| scratch_error(err.into()))?;
+    let entries = db
+        .get_by_prefix(&DatabaseKey::Trie(identifier))
+        .map_err(|err| scratch_error(err.into()))?;
+    Ok(entries
+        .into_iter()
+        .filter(|(key, _)| is_node_key(key, identifier))
+        .map(|(key, node)| (TrieKey::Trie(key), node))
+        .collect())
+}
+
+/// Replace the node updates of the trie `identifier`, committed by a [`super::MerkleTree`], with
+/// the update of its record while it has at most `max_leaves` leaves. Once it grows past them, its
+/// record is removed and all its nodes are written instead. The updates of the tries stored as
+/// nodes are left as they are.
+pub(crate) fn inline_updates<H: StarkHash, DB: BonsaiDatabase, ID: Id>(
+    db: &KeyValueDB<DB, ID>,
+    identifier: &[u8],
+    max_height: u8,
+    hasher: &TrieHasher,
+    max_leaves: usize,
+    mut updates: Updates,
+) -> Result<Updates, BonsaiStorageError<DB::DatabaseError>> {
+    let root_key = TrieKey::new(identifier, TrieKeyType::Trie, &[0]);
+    let record = InlineTrie::read(db, identifier)?;
+    if record.is_none() && db.contains(&root_key)? {
+        return Ok(updates);
+    }
+
+    let mut leaves: HashMap<ByteVec, Felt> = record
+        .as_ref()
+        .map(|record| record.leaves.iter().cloned().collect())
+        .unwrap_or_default();
+    for (key, value) in &updates {
+        let TrieKey::Flat(key) = key else { continue };
+        let Some(leaf_key) = key.strip_prefix(identifier) else {
+            continue;
+        };
+        match value {
+            InsertOrRemove::Insert(value) => {
+                let (value, _) =
+                    decode_leaf(value).map_err(|source| BonsaiStorageError::DecodeError {
+                        key: key.as_slice().into(),
+                        source,
+                    })?;
+                leaves.insert(leaf_key.into(), value);
+            }
+            InsertOrRemove::Remove => {
+                leaves.remove(leaf_key);
+            }
+        }
+    }
+    // A new trie too large to be inlined already has all its nodes in the updates.
+    if record.is_none() && leaves.len() > max_leaves {
+        return Ok(updates);
+    }
+
+    let root_hash = match updates.iter().find(|(key, _)| *key == root_key) {
+        Some((_, InsertOrRemove::Insert(node))) => {
+            let (node, _) =
+                Node::decode_versioned(node).map_err(|source| BonsaiStorageError::DecodeError {
+                    key: root_key.as_slice().into(),
+                    source,
+                })?;
+            node.get_hash()
+                .expect("The committed node has no computed hash")
+        }
+        Some((_, InsertOrRemove::Remove)) => hasher.empty_root(max_height),
+        // Only raw payloads changed.
+        None => record
+            .as_ref()
+            .map_or(hasher.empty_root(max_height), |record| record.root_hash),
+    };
+    let mut leaves: Vec<_> = leaves.into_iter().collect();
+    leaves.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    let trie = InlineTrie { root_hash, leaves };
+
+    // The nodes of an inline trie are not stored.
+    updates.retain(|(key, _)| !matches!(key, TrieKey::Trie(key) if is_node_key(key, identifier)));
+    let key = inline_trie_key(identifier);
+    if trie.leaves.len() > max_leaves {
+        let nodes = trie.nodes::<H, _>(identifier, max_height, hasher)?;
+        updates.extend(
+            nodes
+                .into_iter()
+                .map(|(key, node)| (key, InsertOrRemove::Insert(node))),
+        );
+        updates.push((key, InsertOrRemove::Remove));
+    } else if !trie.leaves.is_empty() {
+        updates.push((key, InsertOrRemove::Insert(trie.encode())));
+    } else if record.is_some() {
+        updates.push((key, InsertOrRemove::Remove));
+    }
+    Ok(updates)
+}
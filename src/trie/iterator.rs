@@ -1,10 +1,16 @@
 use super::{
+    inline::InlineTrie,
     merkle_node::{Direction, Node, NodeHandle},
-    path::Path,
-    tree::{MerkleTree, NodeKey},
+    path::{Path, PathKey},
+    tree::{decode_node, MerkleTree, NodeKey, RootHandle},
+    trie_db::TrieKeyType,
+    TrieKey,
 };
-use crate::{id::Id, key_value_db::KeyValueDB, BitSlice, BonsaiDatabase, BonsaiStorageError, Vec};
-use core::{fmt, marker::PhantomData};
+use crate::{
+    format, id::Id, key_value_db::KeyValueDB, BitSlice, BitVec, BonsaiDatabase, BonsaiStorageError,
+    ByteVec, Cow, HashMap, Vec,
+};
+use core::{cmp::Ordering, fmt, marker::PhantomData};
 use starknet_types_core::{felt::Felt, hash::StarkHash};
 
 /// This trait's function will be called on every node visited during a seek operation.
@@ -228,6 +234,491 @@ impl<'a, H: StarkHash + Send + Sync, DB: BonsaiDatabase, ID: Id> MerkleTreeItera
     }
 }
 
+/// A node reached by a [`NodeResolver`]: an in-memory node of the tree, or a node loaded from the
+/// database into the scratch cache of the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ResolvedNode {
+    Tree(NodeKey),
+    Scratch(usize),
+}
+
+/// Resolves the nodes of a [`MerkleTree`] from a shared reference. The nodes loaded from the
+/// database and the hashes of the in-memory nodes go to a scratch cache local to the resolver
+/// instead of the tree, so several resolvers can read the same tree at once.
+pub(crate) struct NodeResolver<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> {
+    /// Owned when the trie has no tree in memory.
+    tree: Cow<'a, MerkleTree<H>>,
+    db: &'a KeyValueDB<DB, ID>,
+    /// The nodes of the trie rebuilt from its record, when it is stored inline and the tree
+    /// didn't load them.
+    inline_nodes: Option<HashMap<TrieKey, ByteVec>>,
+    scratch: Vec<Node>,
+    /// Index in `scratch` of the loaded nodes, by key.
+    loaded: HashMap<TrieKey, usize>,
+    /// Hashes of the in-memory nodes of the tree.
+    hashes: HashMap<NodeKey, Felt>,
+}
+
+impl<'a, H: StarkHash + Send + Sync, DB: BonsaiDatabase, ID: Id> NodeResolver<'a, H, DB, ID> {
+    pub(crate) fn new(tree: Cow<'a, MerkleTree<H>>, db: &'a KeyValueDB<DB, ID>) -> Self {
+        Self {
+            tree,
+            db,
+            inline_nodes: None,
+            scratch: Vec::new(),
+            loaded: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// The root node, `None` if the tree is empty.
+    pub(crate) fn root(
+        &mut self,
+    ) -> Result<Option<ResolvedNode>, BonsaiStorageError<DB::DatabaseError>> {
+        match self.tree.root_node {
+            Some(RootHandle::Loaded(node_key)) => Ok(Some(ResolvedNode::Tree(node_key))),
+            Some(RootHandle::Empty) => Ok(None),
+            None => {
+                if let Some(root) = self.load(&Path::default())? {
+                    return Ok(Some(root));
+                }
+                if self.tree.inline_nodes.is_some() || self.inline_nodes.is_some() {
+                    return Ok(None);
+                }
+                let Some(record) = InlineTrie::read(self.db, &self.tree.identifier)? else {
+                    return Ok(None);
+                };
+                self.inline_nodes = Some(record.nodes::<H, _>(
+                    &self.tree.identifier,
+                    self.tree.max_height,
+                    &self.tree.hasher,
+                )?);
+                self.load(&Path::default())
+            }
+        }
+    }
+
+    /// Load the node at `path` from the database, unless it was already loaded.
+    fn load(
+        &mut self,
+        path: &Path,
+    ) -> Result<Option<ResolvedNode>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = TrieKey::new(
+            &self.tree.identifier,
+            TrieKeyType::Trie,
+            &PathKey::node(path),
+        );
+        if let Some(index) = self.loaded.get(&key) {
+            self.db.stats.cache_hit();
+            return Ok(Some(ResolvedNode::Scratch(*index)));
+        }
+        if self.tree.death_row.contains(&key) {
+            return Ok(None);
+        }
+        let node = match self
+            .inline_nodes
+            .as_ref()
+            .or(self.tree.inline_nodes.as_ref())
+        {
+            Some(nodes) => nodes.get(&key).cloned(),
+            None => self.db.get(&key)?,
+        };
+        let Some(node) = node else { return Ok(None) };
+        let node = decode_node(
+            &self.tree.identifier,
+            path,
+            &key,
+            &node,
+            self.tree.max_height,
+        )?;
+        self.db.stats.node_decoded();
+        self.scratch.push(node);
+        self.loaded.insert(key, self.scratch.len() - 1);
+        Ok(Some(ResolvedNode::Scratch(self.scratch.len() - 1)))
+    }
+
+    pub(crate) fn node(
+        &self,
+        node: ResolvedNode,
+    ) -> Result<&Node, BonsaiStorageError<DB::DatabaseError>> {
+        match node {
+            ResolvedNode::Tree(node_key) => self.tree.nodes.get(node_key).ok_or_else(|| {
+                BonsaiStorageError::Trie(format!("Dangling in-memory node key: {node_key:?}"))
+            }),
+            ResolvedNode::Scratch(index) => Ok(&self.scratch[index]),
+        }
+    }
+
+    /// Same as [`NodeResolver::node`], for the nodes already returned by it.
+    fn resolved(&self, node: ResolvedNode) -> &Node {
+        match node {
+            ResolvedNode::Tree(node_key) => &self.tree.nodes[node_key],
+            ResolvedNode::Scratch(index) => &self.scratch[index],
+        }
+    }
+
+    /// The child `handle` of a node, which is at `path`.
+    pub(crate) fn child(
+        &mut self,
+        handle: NodeHandle,
+        path: &Path,
+    ) -> Result<ResolvedNode, BonsaiStorageError<DB::DatabaseError>> {
+        match handle {
+            NodeHandle::InMemory(node_key) => {
+                self.db.stats.cache_hit();
+                Ok(ResolvedNode::Tree(node_key))
+            }
+            NodeHandle::Hash(_) => {
+                self.load(path)?
+                    .ok_or_else(|| BonsaiStorageError::NodeNotFound {
+                        identifier: self.tree.identifier.clone(),
+                        path: path.clone(),
+                    })
+            }
+        }
+    }
+
+    /// Hash of the node `handle`, computed if it is in memory.
+    pub(crate) fn hash(
+        &mut self,
+        handle: NodeHandle,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        let node_key = match handle {
+            NodeHandle::Hash(hash) => return Ok(hash),
+            NodeHandle::InMemory(node_key) => node_key,
+        };
+        if let Some(hash) = self.hashes.get(&node_key) {
+            return Ok(*hash);
+        }
+        // As when committing, the hashes of the in-memory nodes are recomputed: the ones they
+        // hold are not reset when they are modified.
+        let hash = match self.node(ResolvedNode::Tree(node_key))? {
+            Node::Binary(binary) => {
+                let (left, right) = (binary.left, binary.right);
+                let left = self.hash(left)?;
+                let right = self.hash(right)?;
+                self.tree.hasher.hash_binary_node(left, right)
+            }
+            Node::Edge(edge) => {
+                let (path, child, height) = (edge.path.clone(), edge.child, edge.height);
+                let child = self.hash(child)?;
+                self.tree
+                    .hasher
+                    .hash_edge_node(&path, child, height.get(), self.tree.max_height)
+            }
+        };
+        self.hashes.insert(node_key, hash);
+        Ok(hash)
+    }
+
+    /// Hash of the resolved node `node`.
+    pub(crate) fn node_hash(
+        &mut self,
+        node: ResolvedNode,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        match node {
+            ResolvedNode::Tree(node_key) => self.hash(NodeHandle::InMemory(node_key)),
+            ResolvedNode::Scratch(index) => Ok(self.scratch[index]
+                .get_hash()
+                .expect("The stored node has no computed hash")),
+        }
+    }
+
+    /// The first leaf of the subtree `handle` at `path`, by key.
+    fn first_leaf(
+        &mut self,
+        mut handle: NodeHandle,
+        mut path: Path,
+    ) -> Result<(BitVec, Felt), BonsaiStorageError<DB::DatabaseError>> {
+        while path.len() < self.tree.max_height as usize {
+            let node = self.child(handle, &path)?;
+            match self.node(node)? {
+                Node::Binary(binary) => {
+                    handle = binary.left;
+                    path.push(false);
+                }
+                Node::Edge(edge) => {
+                    handle = edge.child;
+                    path.extend_from_bitslice(&edge.path);
+                }
+            }
+        }
+        let value = handle.as_hash().expect("The leaves are not in memory");
+        Ok((path.0, value))
+    }
+}
+
+/// A node on the path of a [`TrieCursor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathNode<'a> {
+    /// A binary node at `height`.
+    Binary { height: usize },
+    /// An edge node at `height`, followed by `path`.
+    Edge { height: usize, path: &'a BitSlice },
+}
+
+/// Cursor seeking keys in a trie, created with [`crate::BonsaiStorage::cursor`] or
+/// [`MerkleTree::cursor`]. It only reads the tree: the nodes it loads from the database stay in
+/// the cursor, so several of them can be used at once, as for [`MerkleTree::get_multi_proof`].
+///
+/// Seeking keys in increasing order is the most efficient, as a seek starts from the nodes on the
+/// path shared with the previous one.
+pub struct TrieCursor<'a, H: StarkHash, DB: BonsaiDatabase, ID: Id> {
+    pub(crate) resolver: NodeResolver<'a, H, DB, ID>,
+    /// Current iteration path.
+    pub(crate) current_path: Path,
+    /// The nodes in the current path with their heights, as in [`MerkleTreeIterator`].
+    pub(crate) current_nodes_heights: Vec<(ResolvedNode, usize)>,
+    /// Current leaf hash, see [`MerkleTreeIterator`].
+    pub(crate) leaf_hash: Option<Felt>,
+}
+
+impl<H: StarkHash, DB: BonsaiDatabase, ID: Id> fmt::Debug for TrieCursor<'_, H, DB, ID> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrieCursor")
+            .field("cur_path", &self.current_path)
+            .field("current_nodes_heights", &self.current_nodes_heights)
+            .field("leaf_hash", &self.leaf_hash)
+            .finish()
+    }
+}
+
+impl<'a, H: StarkHash + Send + Sync, DB: BonsaiDatabase, ID: Id> TrieCursor<'a, H, DB, ID> {
+    pub(crate) fn new(tree: Cow<'a, MerkleTree<H>>, db: &'a KeyValueDB<DB, ID>) -> Self {
+        Self {
+            resolver: NodeResolver::new(tree, db),
+            current_path: Default::default(),
+            current_nodes_heights: Vec::with_capacity(251),
+            leaf_hash: None,
+        }
+    }
+
+    /// Path of the last node reached by the last seek. It leaves the key sought at the first edge
+    /// which doesn't follow it, and may go past it when the key is shorter than the leaves' keys.
+    pub fn path(&self) -> &BitSlice {
+        &self.current_path
+    }
+
+    /// The nodes on [`TrieCursor::path`], from the root.
+    pub fn path_nodes(&self) -> impl Iterator<Item = PathNode<'_>> {
+        self.current_nodes_heights.iter().map(|(node, height)| {
+            match self.resolver.resolved(*node) {
+                Node::Binary(_) => PathNode::Binary { height: *height },
+                Node::Edge(edge) => PathNode::Edge {
+                    height: *height,
+                    path: &edge.path,
+                },
+            }
+        })
+    }
+
+    /// Value of the leaf at the key of the last seek, `None` if there is no leaf there.
+    pub fn value(&self) -> Option<Felt> {
+        self.leaf_hash
+    }
+
+    pub fn seek_to(&mut self, key: &BitSlice) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_key_length(key.len(), false)?;
+        self.traverse_to(&mut |_, _, _| Ok(()), key)
+    }
+
+    /// Same as [`TrieCursor::seek_to`], calling `on_sibling` with the path and the hash of the
+    /// sibling of each binary node taken on the way to `key`: along with the value of `key`, they
+    /// are enough to compute the root hash. The siblings on the path shared with the previous seek
+    /// are skipped, as they were passed to the previous seek.
+    pub fn seek_to_with_siblings(
+        &mut self,
+        key: &BitSlice,
+        mut on_sibling: impl FnMut(&BitSlice, Felt),
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_key_length(key.len(), false)?;
+        let mut sibling_path = BitVec::new();
+        self.traverse_to(
+            &mut |resolver, node, path| {
+                let Node::Binary(binary) = resolver.node(node)? else {
+                    return Ok(());
+                };
+                let direction = Direction::from(!key[path.len()]);
+                let sibling = binary.get_child(direction);
+                let hash = resolver.hash(sibling)?;
+                sibling_path.clear();
+                sibling_path.extend_from_bitslice(path);
+                sibling_path.push(bool::from(direction));
+                on_sibling(&sibling_path, hash);
+                Ok(())
+            },
+            key,
+        )
+    }
+
+    /// Seek the first leaf with a key greater than or equal to `key`, and return its key and
+    /// value. `None` if there is none, in which case the cursor is left where it was.
+    pub fn seek_ge(
+        &mut self,
+        key: &BitSlice,
+    ) -> Result<Option<(BitVec, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_key_length(key.len(), true)?;
+        let Some(found) = self.first_leaf_from(key)? else {
+            return Ok(None);
+        };
+        self.seek_to(&found.0)?;
+        Ok(Some(found))
+    }
+
+    fn first_leaf_from(
+        &mut self,
+        key: &BitSlice,
+    ) -> Result<Option<(BitVec, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let resolver = &mut self.resolver;
+        let Some(mut node) = resolver.root()? else {
+            return Ok(None);
+        };
+        // The subtrees right of the path to `key`, the closest last.
+        let mut greater: Vec<(NodeHandle, Path)> = Vec::new();
+        let mut path = Path::default();
+        loop {
+            let (handle, ordering) = match resolver.node(node)? {
+                Node::Binary(binary) => {
+                    let direction = Direction::from(key[path.len()]);
+                    if direction == Direction::Left {
+                        let mut right_path = path.clone();
+                        right_path.push(true);
+                        greater.push((binary.right, right_path));
+                    }
+                    path.push(bool::from(direction));
+                    (binary.get_child(direction), Ordering::Equal)
+                }
+                Node::Edge(edge) => {
+                    let ordering = edge
+                        .path
+                        .as_bitslice()
+                        .cmp(&key[path.len()..path.len() + edge.path.len()]);
+                    path.extend_from_bitslice(&edge.path);
+                    (edge.child, ordering)
+                }
+            };
+            match ordering {
+                Ordering::Equal if path.len() == key.len() => {
+                    let value = handle.as_hash().expect("The leaves are not in memory");
+                    return Ok(Some((path.0, value)));
+                }
+                Ordering::Equal => node = resolver.child(handle, &path)?,
+                Ordering::Greater => return resolver.first_leaf(handle, path).map(Some),
+                Ordering::Less => break,
+            }
+        }
+        greater
+            .pop()
+            .map(|(handle, path)| resolver.first_leaf(handle, path))
+            .transpose()
+    }
+
+    /// Keys longer than the leaves' keys are rejected, and shorter ones too when `exact`.
+    fn check_key_length(
+        &self,
+        len: usize,
+        exact: bool,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let max_height = self.resolver.tree.max_height as usize;
+        if len > max_height || (exact && len < max_height) {
+            return Err(BonsaiStorageError::KeyLength {
+                expected: max_height,
+                got: len,
+            });
+        }
+        Ok(())
+    }
+
+    fn traverse_one(
+        &mut self,
+        node: ResolvedNode,
+        height: usize,
+        key: &BitSlice,
+    ) -> Result<Option<ResolvedNode>, BonsaiStorageError<DB::DatabaseError>> {
+        self.current_nodes_heights
+            .push((node, self.current_path.len()));
+
+        let (node_handle, path_matches) = match self.resolver.node(node)? {
+            Node::Binary(binary_node) => {
+                let next_direction = Direction::from(key[self.current_path.len()]);
+                self.current_path.push(bool::from(next_direction));
+                (binary_node.get_child(next_direction), true)
+            }
+            Node::Edge(edge_node) => {
+                self.current_path.extend_from_bitslice(&edge_node.path);
+                (edge_node.child, edge_node.path_matches(key, height))
+            }
+        };
+
+        if !path_matches || self.current_path.len() >= key.len() {
+            self.leaf_hash = if path_matches && self.current_path.len() == key.len() {
+                node_handle.as_hash()
+            } else {
+                None
+            };
+            return Ok(None); // end of traversal
+        }
+
+        self.resolver
+            .child(node_handle, &self.current_path)
+            .map(Some)
+    }
+
+    /// Seek `key` as [`MerkleTreeIterator::traverse_to`], calling `visit` with the nodes visited
+    /// and their paths.
+    pub(crate) fn traverse_to(
+        &mut self,
+        visit: &mut impl FnMut(
+            &mut NodeResolver<'a, H, DB, ID>,
+            ResolvedNode,
+            &BitSlice,
+        ) -> Result<(), BonsaiStorageError<DB::DatabaseError>>,
+        key: &BitSlice,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if key.is_empty() {
+            self.current_nodes_heights.clear();
+            self.current_path.clear();
+            self.leaf_hash = None;
+            return Ok(());
+        }
+
+        let shared_prefix_len = self
+            .current_path
+            .iter()
+            .zip(key)
+            .take_while(|(a, b)| *a == *b)
+            .count();
+        let nodes_new_len = if shared_prefix_len == 0 {
+            0
+        } else {
+            self.current_nodes_heights
+                .partition_point(|(_node, height)| *height < shared_prefix_len)
+        };
+        self.current_nodes_heights.truncate(nodes_new_len);
+        self.current_path.truncate(key.len());
+
+        let mut next_to_visit = if let Some((node, height)) = self.current_nodes_heights.pop() {
+            self.current_path.truncate(height);
+            self.traverse_one(node, height, key)?
+        } else {
+            // Start from tree root.
+            self.current_path.clear();
+            let Some(node) = self.resolver.root()? else {
+                // empty tree, not found
+                self.leaf_hash = None;
+                return Ok(());
+            };
+            Some(node)
+        };
+
+        while let Some(node) = next_to_visit {
+            visit(&mut self.resolver, node, &self.current_path)?;
+            next_to_visit = self.traverse_one(node, self.current_path.len(), key)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     //! The tree used in this series of tests looks like this:
@@ -284,7 +775,8 @@ mod tests {
             RocksDB::<BasicId>::new(&db, RocksDBConfig::default()),
             BonsaiStorageConfig::default(),
             8,
-        );
+        )
+        .unwrap();
 
         bonsai_storage
             .insert(&[], bits![u8, Msb0; 0,0,0,1,0,0,0,0], &ONE)
@@ -407,7 +899,7 @@ mod tests {
                 iter.seek_to(bits![u8, Msb0; ]).unwrap();
                 assert_eq!(iter.leaf_hash, None);
                 assert_eq!(iter.current_path.0, bits![u8, Msb0; ]);
-                assert_eq!(iter.cur_nodes_ids(), vec![]);
+                assert_eq!(iter.cur_nodes_ids(), Vec::<u64>::new());
                 println!("{iter:?}");
             },
             // case 10
@@ -0,0 +1,121 @@
+//! In-order iteration over the committed leaves of a trie, see [`crate::BonsaiStorage::iter_keys`].
+
+use super::{
+    diff::NodeSource,
+    merkle_node::{Node, NodeHandle},
+    path::Path,
+    tree::bytes_to_bitvec,
+};
+use crate::{vec, BitSlice, BitVec, BonsaiStorageError, DBError, Vec};
+use starknet_types_core::felt::Felt;
+
+/// Iterator over the committed leaves of a trie in increasing key order, which loads the nodes as
+/// it goes. The subtries whose keys are all up to `start_after` are not loaded.
+pub(crate) struct CommittedLeaves<'a, E: DBError> {
+    db: &'a dyn NodeSource<E>,
+    identifier: &'a [u8],
+    max_height: u8,
+    start_after: Option<BitVec>,
+    /// The subtries left to visit, the next one last: the value of a leaf, or `None` for a node
+    /// to load.
+    stack: Vec<(BitVec, Option<Felt>)>,
+}
+
+impl<'a, E: DBError> CommittedLeaves<'a, E> {
+    pub(crate) fn new(
+        db: &'a dyn NodeSource<E>,
+        identifier: &'a [u8],
+        max_height: u8,
+        start_after: Option<&BitSlice>,
+    ) -> Self {
+        Self {
+            db,
+            identifier,
+            max_height,
+            // Keys given as bytes may have padding bits beyond the height.
+            start_after: start_after.map(|start_after| {
+                start_after[..start_after.len().min(max_height as usize)].to_bitvec()
+            }),
+            stack: vec![(BitVec::new(), None)],
+        }
+    }
+
+    /// Whether all the keys under `path` are up to `start_after`.
+    fn skipped(&self, path: &BitSlice) -> bool {
+        let Some(start_after) = &self.start_after else {
+            return false;
+        };
+        let len = path.len().min(start_after.len());
+        path[..len] < start_after[..len] || path == start_after.as_bitslice()
+    }
+
+    /// Visit the child `child` of a node, under `path`.
+    fn push(&mut self, path: BitVec, child: NodeHandle) -> Result<(), BonsaiStorageError<E>> {
+        if self.skipped(&path) {
+            return Ok(());
+        }
+        let hash = child.as_hash().ok_or_else(|| {
+            BonsaiStorageError::Trie("Committed node references an in-memory node".into())
+        })?;
+        let leaf = (path.len() == self.max_height as usize).then_some(hash);
+        self.stack.push((path, leaf));
+        Ok(())
+    }
+
+    /// Load the node under `path`, and visit its children.
+    fn expand(&mut self, path: BitVec) -> Result<(), BonsaiStorageError<E>> {
+        let node = match self.db.load(self.identifier, &path, self.max_height)? {
+            Some(node) => node,
+            // An empty trie has no root, and neither has an inline one.
+            None if path.is_empty() => {
+                if let Some(record) = self.db.inline_trie(self.identifier)? {
+                    for (key, value) in record.leaves.iter().rev() {
+                        let key = bytes_to_bitvec(key);
+                        if !self.skipped(&key) {
+                            self.stack.push((key, Some(*value)));
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            None => {
+                return Err(BonsaiStorageError::NodeNotFound {
+                    identifier: self.identifier.into(),
+                    path: Path(path),
+                })
+            }
+        };
+        match node {
+            Node::Binary(binary) => {
+                for (bit, child) in [(true, binary.right), (false, binary.left)] {
+                    let mut child_path = path.clone();
+                    child_path.push(bit);
+                    self.push(child_path, child)?;
+                }
+            }
+            Node::Edge(edge) => {
+                let mut child_path = path;
+                child_path.extend_from_bitslice(&edge.path.0);
+                self.push(child_path, edge.child)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<E: DBError> Iterator for CommittedLeaves<'_, E> {
+    type Item = Result<(BitVec, Felt), BonsaiStorageError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, leaf)) = self.stack.pop() {
+            if let Some(value) = leaf {
+                return Some(Ok((path, value)));
+            }
+            if let Err(err) = self.expand(path) {
+                self.stack.clear();
+                return Some(Err(err));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,58 @@
+use super::trees::MerkleTrees;
+use crate::{
+    format, id::Id, BonsaiDatabase, BonsaiPersistentDatabase, BonsaiStorageError, ToString,
+};
+use starknet_types_core::hash::StarkHash;
+
+impl<H, DB, CommitID> MerkleTrees<H, DB, CommitID>
+where
+    H: StarkHash + Send + Sync,
+    DB: BonsaiDatabase + BonsaiPersistentDatabase<CommitID>,
+    CommitID: Id,
+{
+    /// Whether a transactional state created at `created_at` can be merged with
+    /// [`MerkleTrees::fast_forward`]: nothing happened in these tries since it was created, so that
+    /// its state is a descendant of ours.
+    pub(crate) fn can_fast_forward(&self, created_at: Option<CommitID>) -> bool {
+        created_at.is_some()
+            && created_at == self.db.get_latest_id()
+            && !self.has_uncommitted_changes()
+    }
+
+    /// Fast-forward merge of a transactional state, see [`MerkleTrees::can_fast_forward`]. This is
+    /// the only case where the merge is structural: when both sides have changes, the leaves are
+    /// inserted again by [`crate::BonsaiStorage::merge`].
+    ///
+    /// The commits of the transactional state are applied node by node from their trie logs, so the
+    /// tries are neither traversed nor rehashed. Its in-memory trees, which hold the uncommitted
+    /// changes and the nodes already loaded, then replace ours along with its uncommitted metadata
+    /// as they are based on the same database state.
+    pub(crate) fn fast_forward(
+        &mut self,
+        transaction: MerkleTrees<H, DB::Transaction<'_>, CommitID>,
+    ) -> Result<(), BonsaiStorageError<<DB as BonsaiDatabase>::DatabaseError>> {
+        let MerkleTrees {
+            db, trees, meta, ..
+        } = transaction;
+        let (Some(created_at), Some(txn_latest_id)) = (db.created_at, db.latest_id) else {
+            return Err(BonsaiStorageError::Merge(
+                "storage is not a transactional state".to_string(),
+            ));
+        };
+        if txn_latest_id < created_at {
+            return Err(BonsaiStorageError::Merge(format!(
+                "transactional state created at {:?} was reverted to {:?}",
+                created_at, txn_latest_id
+            )));
+        }
+
+        for id in created_at.as_u64() + 1..=txn_latest_id.as_u64() {
+            self.db.fast_forward_commit(&db, CommitID::from_u64(id))?;
+        }
+        self.savepoints.clear();
+        self.generation += 1;
+        self.trees.extend(trees);
+        self.meta = meta;
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@
 //! For more information about how these Starknet trees are structured, see
 //! [`MerkleTree`](super::merkle_tree::MerkleTree).
 
-use crate::BitSlice;
+use crate::{Arc, BitSlice, BitVec, ByteVec, EncodeExt, Vec};
 use bitvec::view::BitView;
 use core::fmt;
 use parity_scale_codec::{Decode, Encode};
@@ -12,6 +12,15 @@ use starknet_types_core::{felt::Felt, hash::StarkHash};
 
 use super::{path::Path, tree::NodeKey};
 
+/// Version of the encoding of the nodes written to the database.
+///
+/// Nodes are stored as a tag byte, `0x80 | version`, followed by the encoding of this version.
+/// Nodes written before the encoding was versioned have no tag and start with the SCALE variant
+/// index of [`Node`], 0 or 1: they are decoded as version 0.
+pub(crate) const NODE_ENCODING_VERSION: u8 = 1;
+
+const VERSION_TAG: u8 = 0x80;
+
 /// A node in a Binary Merkle-Patricia Tree graph.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
 pub enum Node {
@@ -21,6 +30,26 @@ pub enum Node {
     Edge(EdgeNode),
 }
 
+/// Kind of a committed node of a trie, see [`crate::BonsaiStorage::iter_nodes`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A binary node, with two children.
+    Binary,
+    /// An edge node, followed by `path`.
+    Edge { path: BitVec },
+}
+
+impl From<&Node> for NodeKind {
+    fn from(node: &Node) -> Self {
+        match node {
+            Node::Binary(_) => NodeKind::Binary,
+            Node::Edge(edge) => NodeKind::Edge {
+                path: edge.path.0.clone(),
+            },
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
 pub enum NodeHandle {
     Hash(Felt),
@@ -52,7 +81,7 @@ pub struct BinaryNode {
     pub hash: Option<Felt>,
     /// The height of this node in the tree.
     // TODO: this field will be removed in the future.
-    pub height: u64,
+    pub height: Height,
     /// [Left](Direction::Left) child.
     pub left: NodeHandle,
     /// [Right](Direction::Right) child.
@@ -67,13 +96,68 @@ pub struct EdgeNode {
     pub hash: Option<Felt>,
     /// The starting height of this node in the tree.
     // TODO: this field will be removed in the future.
-    pub height: u64,
+    pub height: Height,
     /// The path this edge takes.
     pub path: Path,
     /// The child of this node.
     pub child: NodeHandle,
 }
 
+/// Height of a node in a trie, the length of the path leading to it. The height of a trie is at
+/// most 255, so the heights of its nodes always fit in a byte.
+///
+/// It is encoded as a `u64` in the nodes, for compatibility: decoding fails on a larger value
+/// instead of truncating it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Height(u8);
+
+impl Height {
+    /// `height` if it is at most `max_height`, the height of the trie.
+    pub fn new(height: usize, max_height: u8) -> Option<Self> {
+        u8::try_from(height)
+            .ok()
+            .filter(|height| *height <= max_height)
+            .map(Self)
+    }
+
+    pub fn get(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl From<u8> for Height {
+    fn from(height: u8) -> Self {
+        Self(height)
+    }
+}
+
+impl From<Height> for u64 {
+    fn from(height: Height) -> Self {
+        height.0 as u64
+    }
+}
+
+impl Encode for Height {
+    fn size_hint(&self) -> usize {
+        u64::from(*self).size_hint()
+    }
+
+    fn encode_to<T: parity_scale_codec::Output + ?Sized>(&self, dest: &mut T) {
+        u64::from(*self).encode_to(dest)
+    }
+}
+
+impl Decode for Height {
+    fn decode<I: parity_scale_codec::Input>(
+        input: &mut I,
+    ) -> Result<Self, parity_scale_codec::Error> {
+        let height = u64::decode(input)?;
+        u8::try_from(height)
+            .map(Self)
+            .map_err(|_| "node height overflow".into())
+    }
+}
+
 /// Describes the direction a child of a [BinaryNode] may have.
 ///
 /// Binary nodes have two children, one left and one right.
@@ -133,7 +217,7 @@ impl BinaryNode {
     ///
     /// The direction of the key.
     pub fn direction(&self, key: &BitSlice) -> Direction {
-        key[self.height as usize].into()
+        key[self.height.get()].into()
     }
 
     /// Returns the [Left] or [Right] child.
@@ -190,6 +274,50 @@ impl Node {
             Node::Edge(edge) => edge.hash,
         }
     }
+
+    /// Encoding of the node in the database, with the [`NODE_ENCODING_VERSION`] tag.
+    pub(crate) fn encode_versioned(&self) -> ByteVec {
+        let mut bytes = ByteVec::from(&[VERSION_TAG | NODE_ENCODING_VERSION][..]);
+        bytes.extend_from_slice(&self.encode_bytevec());
+        bytes
+    }
+
+    /// Decode a node from the database, written with any version of the encoding. Returns the node
+    /// and the version it was written with.
+    pub(crate) fn decode_versioned(bytes: &[u8]) -> Result<(Self, u8), parity_scale_codec::Error> {
+        let Some(&tag) = bytes.first() else {
+            return Err("empty node".into());
+        };
+        if tag & VERSION_TAG == 0 {
+            return Ok((Node::decode(&mut &bytes[..])?, 0));
+        }
+        match tag & !VERSION_TAG {
+            // The layout did not change, only the tag was added.
+            1 => Ok((Node::decode(&mut &bytes[1..])?, 1)),
+            _ => Err("unknown node encoding version".into()),
+        }
+    }
+
+    /// Same as [`Node::decode_versioned`] for the node at `height` in a trie of height
+    /// `max_height`, which fails if the node is not consistent with them.
+    pub(crate) fn decode_at(
+        bytes: &[u8],
+        height: usize,
+        max_height: u8,
+    ) -> Result<(Self, u8), parity_scale_codec::Error> {
+        let (node, version) = Self::decode_versioned(bytes)?;
+        let (node_height, end) = match &node {
+            Node::Binary(binary) => (binary.height, height + 1),
+            Node::Edge(edge) => (edge.height, height + edge.path.len()),
+        };
+        if node_height.get() != height {
+            return Err("node height inconsistent with its path".into());
+        }
+        if end > max_height as usize {
+            return Err("node past the height of the trie".into());
+        }
+        Ok((node, version))
+    }
 }
 
 impl EdgeNode {
@@ -200,7 +328,7 @@ impl EdgeNode {
     /// * `key` - The key to check if the path matches with the edge node.
     /// * `node_height` - The height of the edge node.
     pub fn path_matches(&self, key: &BitSlice, node_height: usize) -> bool {
-        assert_eq!(self.height as usize, node_height);
+        assert_eq!(self.height.get(), node_height);
         let lower_bound = node_height.min(key.len());
         let upper_bound = (node_height + self.path.0.len()).min(key.len());
         log::trace!(
@@ -221,7 +349,7 @@ impl EdgeNode {
     ///
     /// * `key` - The key to get the common path from.
     pub fn common_path(&self, key: &BitSlice) -> &BitSlice {
-        let key_path = key.iter().skip(self.height as usize);
+        let key_path = key.iter().skip(self.height.get());
         let common_length = key_path
             .zip(self.path.0.iter())
             .take_while(|(a, b)| a == b)
@@ -247,13 +375,215 @@ pub fn hash_edge_node<H: StarkHash>(path: &Path, child_hash: Felt) -> Felt {
     H::hash(&child_hash, &felt_path) + length
 }
 
+/// The node layer of a trie: the shape of its nodes and the rule hashing them, which a
+/// [`TrieHasher`] applies.
+///
+/// The nodes are stored as the binary nodes and edges of [`Node`] whatever their family, which
+/// only decides how they are hashed: the tries of all the families share the storage, the commits
+/// and the trie logs. Families of another arity, such as Verkle tries, can describe themselves,
+/// but the storage only supports binary ones for now, see
+/// [`crate::ConfigError::UnsupportedArity`].
+pub trait TrieNodeFamily: Send + Sync {
+    /// Name of the family, for debugging.
+    fn name(&self) -> &str;
+
+    /// Number of children of the branch nodes.
+    fn arity(&self) -> usize {
+        2
+    }
+
+    /// Hash of a branch node from the hashes of its `arity` children, in the order of the keys.
+    fn hash_branch(&self, children: &[Felt]) -> Felt;
+
+    /// Hash of the edge at `depth` in a trie of height `max_height`, whose path `path` leads to a
+    /// node of hash `child_hash`.
+    fn hash_edge(&self, path: &Path, child_hash: Felt, depth: usize, max_height: u8) -> Felt;
+
+    /// Root hash of an empty trie of height `max_height`, which has no node.
+    fn empty_root(&self, _max_height: u8) -> Felt {
+        Felt::ZERO
+    }
+
+    /// Value of the missing keys, `None` if they are absent rather than set to a default value.
+    /// The families with a default value hash the empty subtries of each height to
+    /// [`TrieNodeFamily::empty_root`], the subtries of the others always have a node.
+    fn default_value(&self) -> Option<Felt> {
+        None
+    }
+}
+
+/// The binary Merkle-Patricia tries of Starknet.
+struct Patricia {
+    name: &'static str,
+    binary: fn(Felt, Felt) -> Felt,
+    edge: fn(&Path, Felt) -> Felt,
+}
+
+impl Patricia {
+    fn new<H: StarkHash>() -> Self {
+        Self {
+            name: core::any::type_name::<H>(),
+            binary: hash_binary_node::<H>,
+            edge: hash_edge_node::<H>,
+        }
+    }
+}
+
+impl TrieNodeFamily for Patricia {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn hash_branch(&self, children: &[Felt]) -> Felt {
+        (self.binary)(children[0], children[1])
+    }
+
+    fn hash_edge(&self, path: &Path, child_hash: Felt, _depth: usize, _max_height: u8) -> Felt {
+        (self.edge)(path, child_hash)
+    }
+}
+
+/// Sparse Merkle trees, see [`TrieHasher::sparse`].
+struct Sparse {
+    patricia: Patricia,
+    /// The hashes of the empty subtries by their number of levels, from the default value of a
+    /// leaf up to an empty trie of the maximum height.
+    empty: Vec<Felt>,
+}
+
+impl TrieNodeFamily for Sparse {
+    fn name(&self) -> &str {
+        self.patricia.name
+    }
+
+    fn hash_branch(&self, children: &[Felt]) -> Felt {
+        self.patricia.hash_branch(children)
+    }
+
+    /// The edge stands for the binary nodes along its path, whose other children are empty
+    /// subtries.
+    fn hash_edge(&self, path: &Path, child_hash: Felt, depth: usize, max_height: u8) -> Felt {
+        let mut hash = child_hash;
+        for (i, bit) in path.iter().enumerate().rev() {
+            // The sibling is at `depth + i + 1`, as is the subtrie hashed so far.
+            let sibling = self.empty[max_height as usize - depth - i - 1];
+            hash = if *bit {
+                (self.patricia.binary)(sibling, hash)
+            } else {
+                (self.patricia.binary)(hash, sibling)
+            };
+        }
+        hash
+    }
+
+    fn empty_root(&self, max_height: u8) -> Felt {
+        self.empty[max_height as usize]
+    }
+
+    fn default_value(&self) -> Option<Felt> {
+        Some(self.empty[0])
+    }
+}
+
+/// The [`TrieNodeFamily`] of a trie, chosen at runtime so that the tries of a storage can use
+/// different hashers, see [`crate::BonsaiStorageConfig::trie_hashers`].
+///
+/// A hasher created with [`TrieHasher::sparse`] gives its tries the semantics of a sparse Merkle
+/// tree: every key has a value, the default one unless it was set to another one, and the root
+/// hash is the one of the complete binary tree of all the keys. The empty subtries are not
+/// stored, they hash to a value precomputed for each height, so the proofs of default values
+/// stay as short as the non-membership proofs of the other tries.
+#[derive(Clone)]
+pub struct TrieHasher(Arc<dyn TrieNodeFamily>);
+
+impl TrieHasher {
+    /// Hasher of the Starknet tries, hashing their nodes with `H`.
+    pub fn new<H: StarkHash>() -> Self {
+        Self(Arc::new(Patricia::new::<H>()))
+    }
+
+    /// Hasher of sparse tries whose missing keys have the value `default`.
+    ///
+    /// As in the other tries, a key set to zero is removed, so a sparse trie whose default is
+    /// not zero can't hold zero values: setting a key to zero or to the default value both set it
+    /// back to the default value.
+    pub fn sparse<H: StarkHash>(default: Felt) -> Self {
+        // Up to the highest trie the heights stored in a `u8` allow.
+        let mut empty = Vec::with_capacity(u8::MAX as usize + 1);
+        empty.push(default);
+        for level in 0..u8::MAX as usize {
+            empty.push(H::hash(&empty[level], &empty[level]));
+        }
+        Self(Arc::new(Sparse {
+            patricia: Patricia::new::<H>(),
+            empty,
+        }))
+    }
+
+    /// Hasher of the tries of another node family.
+    pub fn from_family(family: impl TrieNodeFamily + 'static) -> Self {
+        Self(Arc::new(family))
+    }
+
+    /// Number of children of the branch nodes, see [`TrieNodeFamily::arity`].
+    pub fn arity(&self) -> usize {
+        self.0.arity()
+    }
+
+    /// Value of the missing keys, for sparse tries.
+    pub fn default_value(&self) -> Option<Felt> {
+        self.0.default_value()
+    }
+
+    /// Root hash of an empty trie of height `max_height`.
+    pub fn empty_root(&self, max_height: u8) -> Felt {
+        self.0.empty_root(max_height)
+    }
+
+    pub(crate) fn hash_binary_node(&self, left_hash: Felt, right_hash: Felt) -> Felt {
+        self.0.hash_branch(&[left_hash, right_hash])
+    }
+
+    /// Hash of the edge at `depth` in a trie of height `max_height`.
+    pub(crate) fn hash_edge_node(
+        &self,
+        path: &Path,
+        child_hash: Felt,
+        depth: usize,
+        max_height: u8,
+    ) -> Felt {
+        self.0.hash_edge(path, child_hash, depth, max_height)
+    }
+
+    /// Whether `hash` at `depth` is the one of an empty subtrie of a sparse trie, which has no
+    /// node.
+    pub(crate) fn is_empty_subtrie(&self, hash: Felt, depth: usize, max_height: u8) -> bool {
+        let Some(subtrie_height) = u8::try_from(depth)
+            .ok()
+            .and_then(|depth| max_height.checked_sub(depth))
+        else {
+            return false;
+        };
+        self.default_value().is_some() && self.empty_root(subtrie_height) == hash
+    }
+}
+
+impl fmt::Debug for TrieHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrieHasher")
+            .field("name", &self.0.name())
+            .field("default_value", &self.default_value())
+            .finish()
+    }
+}
+
 #[test]
 fn test_path_matches_basic() {
     let path =
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 0,
+        height: Height::from(0),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -268,7 +598,7 @@ fn test_path_matches_with_height() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 8,
+        height: Height::from(8),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -283,7 +613,7 @@ fn test_path_matches_only_part_with_height() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 8,
+        height: Height::from(8),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -300,7 +630,7 @@ fn test_path_dont_match() {
         Path(BitSlice::from_slice(&[0b10111010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 0,
+        height: Height::from(0),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -315,7 +645,7 @@ fn test_common_path_basic() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 0,
+        height: Height::from(0),
         path: path.clone(),
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -330,7 +660,7 @@ fn test_common_path_only_part() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 0,
+        height: Height::from(0),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -348,7 +678,7 @@ fn test_common_path_part_with_height() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 8,
+        height: Height::from(8),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
@@ -363,7 +693,7 @@ fn test_no_common_path() {
         Path(BitSlice::from_slice(&[0b10101010, 0b01010101, 0b10101010, 0b01010101]).to_bitvec());
     let edge = EdgeNode {
         hash: None,
-        height: 0,
+        height: Height::from(0),
         path,
         child: NodeHandle::Hash(Felt::ZERO),
     };
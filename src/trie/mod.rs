@@ -1,6 +1,12 @@
+pub(crate) mod builder;
+pub(crate) mod diff;
+pub(crate) mod fixed_depth;
+pub(crate) mod inline;
 pub(crate) mod iterator;
-mod merkle_node;
-mod path;
+pub(crate) mod leaves;
+mod merge;
+pub(crate) mod merkle_node;
+pub(crate) mod path;
 pub(crate) mod proof;
 pub mod tree;
 pub(crate) mod trees;
@@ -1,14 +1,17 @@
 use super::merkle_node::Direction;
-use crate::{BitVec, ByteVec, EncodeExt};
+use crate::{BitSlice, BitVec, ByteVec, DatabaseKey, EncodeExt};
+use bitvec::{field::BitField, order::Msb0, view::BitView};
 use core::{
     fmt,
     ops::{Deref, DerefMut},
 };
 use parity_scale_codec::{Decode, Encode, Error, Input, Output};
+use starknet_types_core::felt::Felt;
 
 #[cfg(all(feature = "std", test))]
 use rstest::rstest;
 
+/// A path in a trie, from the root. Leaf keys are paths of the height of the trie.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Path(pub BitVec);
 
@@ -28,6 +31,70 @@ impl Path {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The canonical key of a Starknet trie of height 251 for `felt`: its 251 low bits, big-endian.
+    ///
+    /// Starknet addresses and storage keys are lower than 2^251. Larger felts return `None`, as
+    /// their key would be the same as the one of a lower felt.
+    pub fn from_felt_251(felt: &Felt) -> Option<Self> {
+        let bytes = felt.to_bytes_be();
+        let (high, low) = bytes.view_bits::<Msb0>().split_at(5);
+        high.not_any().then(|| Self(low.to_bitvec()))
+    }
+
+    /// The felt whose low bits are this path, as in [`Path::from_felt_251`]. Paths longer than 251
+    /// bits are reduced modulo the field prime.
+    pub fn to_felt(&self) -> Felt {
+        let mut bytes = [0u8; 32];
+        let bits = bytes.view_bits_mut();
+        let len = self.0.len().min(bits.len());
+        bits[256 - len..].copy_from_bitslice(&self.0[self.0.len() - len..]);
+        Felt::from_bytes_be(&bytes)
+    }
+
+    /// Encoded path: its length in bits as a byte followed by the bits, most significant first.
+    pub fn to_bytes(&self) -> ByteVec {
+        self.encode_bytevec()
+    }
+
+    /// Decode a path encoded with [`Path::to_bytes`].
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        Self::decode(&mut bytes)
+    }
+
+    /// Database key of the trie node at this path in the trie `identifier`, in the
+    /// [`DatabaseKey::Trie`](crate::DatabaseKey::Trie) column.
+    pub fn trie_db_key(&self, identifier: &[u8]) -> ByteVec {
+        DatabaseKey::trie_node_bytes(identifier, &self.0)
+    }
+
+    /// Database key of the leaf with this key in the trie `identifier`, in the
+    /// [`DatabaseKey::Flat`](crate::DatabaseKey::Flat) column.
+    pub fn flat_db_key(&self, identifier: &[u8]) -> ByteVec {
+        DatabaseKey::leaf_bytes(identifier, &self.0)
+    }
+}
+
+impl From<BitVec> for Path {
+    fn from(bits: BitVec) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<&BitSlice> for Path {
+    fn from(bits: &BitSlice) -> Self {
+        Self(bits.to_bitvec())
+    }
+}
+
+impl AsRef<BitSlice> for Path {
+    fn as_ref(&self) -> &BitSlice {
+        &self.0
+    }
 }
 
 impl fmt::Debug for Path {
@@ -120,6 +187,65 @@ impl Decode for Path {
     }
 }
 
+/// Longest encoding of a path: its length in bits, then at most 256 bits.
+const MAX_PATH_KEY_LEN: usize = 33;
+
+/// Encoding of a path in the database keys, built on the stack. The keys of the nodes and leaves
+/// are built for every access to the trie, and the longest ones don't fit in a [`ByteVec`]
+/// without allocating.
+#[derive(Clone, Copy)]
+pub(crate) struct PathKey {
+    len: u8,
+    bytes: [u8; MAX_PATH_KEY_LEN],
+}
+
+impl PathKey {
+    /// Key of the node at `path`, the same as [`Path::to_bytes`].
+    pub(crate) fn node(path: &BitSlice) -> Self {
+        debug_assert!(
+            path.len() <= u8::MAX as usize,
+            "path too long: {}",
+            path.len()
+        );
+        let mut bytes = [0; MAX_PATH_KEY_LEN];
+        bytes[0] = path.len() as u8;
+        for (byte, chunk) in bytes[1..].iter_mut().zip(path.chunks(8)) {
+            *byte = chunk.load_be::<u8>() << (8 - chunk.len());
+        }
+        Self {
+            len: (1 + (path.len() + 7) / 8) as u8,
+            bytes,
+        }
+    }
+
+    /// Key of the leaf `key`, the same as the node key except for the empty key, which has an
+    /// empty leaf key.
+    pub(crate) fn leaf(key: &BitSlice) -> Self {
+        let mut leaf = Self::node(key);
+        if key.is_empty() {
+            leaf.len = 0;
+        }
+        leaf
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl Deref for PathKey {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for PathKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
 /// Convert Path to SByteVec can be used, for example, to create keys for the database
 impl From<Path> for ByteVec {
     fn from(path: Path) -> Self {
@@ -162,3 +288,44 @@ fn test_shared_path_encode_decode(#[case] input: &[u8]) {
     let decoded = Path::decode(&mut &encoded[..]).unwrap();
     assert_eq!(path, decoded);
 }
+
+#[cfg(all(feature = "std", test))]
+#[rstest]
+#[case("0x0")]
+#[case("0x1")]
+#[case("0x313ad57fdf765addc71329abf8d74ac2bce6d46da8c2b9b82255a5076620301")]
+#[case("0x7ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")]
+fn test_felt_251_round_trip(#[case] felt: &str) {
+    let felt = Felt::from_hex(felt).unwrap();
+    let path = Path::from_felt_251(&felt).unwrap();
+    assert_eq!(path.len(), 251);
+    assert_eq!(path.to_felt(), felt);
+    assert_eq!(Path::from_bytes(&path.to_bytes()).unwrap(), path);
+}
+
+#[cfg(all(feature = "std", test))]
+#[rstest]
+#[case("0x800000000000000000000000000000000000000000000000000000000000000")]
+#[case("0x800000000000011000000000000000000000000000000000000000000000000")]
+fn test_felt_251_out_of_range(#[case] felt: &str) {
+    assert_eq!(Path::from_felt_251(&Felt::from_hex(felt).unwrap()), None);
+}
+
+#[cfg(all(feature = "std", test))]
+#[rstest]
+#[case(&[0b10101010, 0b10101010], 0, 16)]
+#[case(&[0b10101010, 0b10101010], 3, 11)]
+#[case(&[0b11111111, 0b00000001], 1, 16)]
+#[case(&[], 0, 0)]
+#[case(&[0xff; 32], 5, 256)]
+fn test_path_key(#[case] input: &[u8], #[case] start: usize, #[case] end: usize) {
+    let bits = &BitSlice::from_slice(input)[start..end];
+    let path = Path(bits.to_bitvec());
+    assert_eq!(PathKey::node(bits).as_slice(), path.to_bytes().as_slice());
+    let leaf = PathKey::leaf(bits);
+    if bits.is_empty() {
+        assert!(leaf.is_empty());
+    } else {
+        assert_eq!(leaf.as_slice(), path.to_bytes().as_slice());
+    }
+}
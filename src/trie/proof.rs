@@ -1,20 +1,22 @@
 use super::{
-    merkle_node::{hash_binary_node, hash_edge_node, Direction},
+    merkle_node::{Direction, TrieHasher},
     path::Path,
     tree::MerkleTree,
 };
 use crate::{
+    format,
     id::Id,
     key_value_db::KeyValueDB,
     trie::{
-        iterator::NodeVisitor,
-        merkle_node::{Node, NodeHandle},
-        tree::NodeKey,
+        iterator::{NodeResolver, ResolvedNode},
+        merkle_node::Node,
     },
-    BitSlice, BitVec, BonsaiDatabase, BonsaiStorageError, HashMap, HashSet,
+    vec, BitSlice, BitVec, BonsaiDatabase, BonsaiStorageError, HashMap, HashSet, String, Vec,
 };
-use core::{marker::PhantomData, mem};
+use core::mem;
 use hashbrown::hash_set;
+use parity_scale_codec::{Decode, Encode, Input};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use starknet_types_core::{felt::Felt, hash::StarkHash};
 
 #[derive(Debug, thiserror::Error)]
@@ -42,23 +44,166 @@ pub enum ProofVerificationError {
     },
 }
 
+/// A trie node of a [`MultiProof`], with the hashes of its children.
+///
+/// It is serialized as the `MERKLE_NODE` of the `starknet_getStorageProof` method of the Starknet
+/// JSON-RPC 0.8 API: `{"left", "right"}` for a binary node, and `{"path", "length", "child"}` for
+/// an edge node, whose path is the integer of its bits, most significant first. The hashes and the
+/// path are hexadecimal strings.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ProofNode {
     Binary { left: Felt, right: Felt },
     Edge { child: Felt, path: Path },
 }
 
+/// Felt serialized as a `0x`-prefixed hexadecimal string, as in the Starknet JSON-RPC API.
+struct HexFelt(Felt);
+
+impl Serialize for HexFelt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:#x}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexFelt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if !hex.starts_with("0x") {
+            return Err(de::Error::custom(format!("{hex:?} is not 0x-prefixed")));
+        }
+        Felt::from_hex(&hex)
+            .map(HexFelt)
+            .map_err(|_| de::Error::custom(format!("{hex:?} is not a felt")))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RpcNode {
+    Binary {
+        left: HexFelt,
+        right: HexFelt,
+    },
+    Edge {
+        path: HexFelt,
+        length: u8,
+        child: HexFelt,
+    },
+}
+
+impl Serialize for ProofNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let node = match self {
+            ProofNode::Binary { left, right } => RpcNode::Binary {
+                left: HexFelt(*left),
+                right: HexFelt(*right),
+            },
+            ProofNode::Edge { child, path } => {
+                let mut bits = BitVec::repeat(false, 256 - path.len());
+                bits.extend_from_bitslice(&path.0);
+                RpcNode::Edge {
+                    path: HexFelt(Felt::from_bytes_be_slice(bits.as_raw_slice())),
+                    length: path.len() as u8,
+                    child: HexFelt(*child),
+                }
+            }
+        };
+        node.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProofNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match RpcNode::deserialize(deserializer)? {
+            RpcNode::Binary { left, right } => ProofNode::Binary {
+                left: left.0,
+                right: right.0,
+            },
+            RpcNode::Edge {
+                path,
+                length,
+                child,
+            } => {
+                let bits = path.0.to_bits_be();
+                let (high, low) = bits.split_at(256 - length as usize);
+                if length == 0 || length > 251 || high.contains(&true) {
+                    return Err(de::Error::custom(format!(
+                        "edge path {:#x} doesn't fit in {} bits",
+                        path.0, length
+                    )));
+                }
+                ProofNode::Edge {
+                    child: child.0,
+                    path: Path(low.iter().collect()),
+                }
+            }
+        })
+    }
+}
+
 impl ProofNode {
     pub fn hash<H: StarkHash>(&self) -> Felt {
+        // Only the edges of sparse tries depend on where they are.
+        self.hash_with(&TrieHasher::new::<H>(), 0, 0)
+    }
+
+    /// Hash of the node at `depth` in a trie of height `tree_height`.
+    pub(crate) fn hash_with(&self, hasher: &TrieHasher, depth: usize, tree_height: u8) -> Felt {
         match self {
-            ProofNode::Binary { left, right } => hash_binary_node::<H>(*left, *right),
-            ProofNode::Edge { child, path } => hash_edge_node::<H>(path, *child),
+            ProofNode::Binary { left, right } => hasher.hash_binary_node(*left, *right),
+            ProofNode::Edge { child, path } => {
+                hasher.hash_edge_node(path, *child, depth, tree_height)
+            }
         }
     }
 }
 
+/// Set in the tag of the edge nodes of [`MultiProof::encode_compact`].
+const COMPACT_EDGE: u8 = 1;
+/// Set in the tag of a binary node of [`MultiProof::encode_compact`] when its left child follows
+/// it, and in the tag of an edge node when its child does.
+const COMPACT_FIRST_CHILD: u8 = 2;
+/// Set in the tag of a binary node of [`MultiProof::encode_compact`] when its right child follows
+/// it.
+const COMPACT_SECOND_CHILD: u8 = 4;
+
+/// Trie nodes by hash, so that the nodes on the paths to several keys are stored once.
+///
+/// It is serialized as the `NODE_HASH_TO_NODE_MAPPING` of the Starknet JSON-RPC 0.8 API, a list of
+/// `{"node_hash", "node"}` sorted by hash, see [`ProofNode`]. The hashes are not checked when it
+/// is deserialized, [`MultiProof::verify_proof`] does it.
 #[derive(Debug, Clone)]
 pub struct MultiProof(pub HashMap<Felt, ProofNode>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RpcNodeMapping {
+    node_hash: HexFelt,
+    node: ProofNode,
+}
+
+impl Serialize for MultiProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut nodes: Vec<_> = self.0.iter().collect();
+        nodes.sort_by_key(|(hash, _)| **hash);
+        serializer.collect_seq(nodes.into_iter().map(|(hash, node)| RpcNodeMapping {
+            node_hash: HexFelt(*hash),
+            node: node.clone(),
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for MultiProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let nodes = Vec::<RpcNodeMapping>::deserialize(deserializer)?;
+        Ok(MultiProof(
+            nodes
+                .into_iter()
+                .map(|mapping| (mapping.node_hash.0, mapping.node))
+                .collect(),
+        ))
+    }
+}
 impl MultiProof {
     /// If the proof proves more than just the provided `key_values`, this function will not fail.
     /// Not the most optimized way of doing it, but we don't actually need to verify proofs in madara.
@@ -71,6 +216,19 @@ impl MultiProof {
         root: Felt,
         key_values: impl IntoIterator<Item = impl AsRef<BitSlice>> + 'a,
         tree_height: u8,
+    ) -> impl Iterator<Item = Result<Felt, ProofVerificationError>> + 'a {
+        self.verify_proof_with(TrieHasher::new::<H>(), root, key_values, tree_height)
+    }
+
+    /// [`MultiProof::verify_proof`] with a hasher chosen at runtime, which also verifies the
+    /// proofs of sparse tries. Their keys with the default value are not members of the trie, so
+    /// Felt::ZERO is returned for them as well.
+    pub fn verify_proof_with<'a, 'b: 'a>(
+        &'b self,
+        hasher: TrieHasher,
+        root: Felt,
+        key_values: impl IntoIterator<Item = impl AsRef<BitSlice>> + 'a,
+        tree_height: u8,
     ) -> impl Iterator<Item = Result<Felt, ProofVerificationError>> + 'a {
         let mut checked_cache: HashSet<Felt> = Default::default();
         let mut current_path = BitVec::with_capacity(251);
@@ -96,6 +254,10 @@ impl MultiProof {
                     log::trace!("End of traversal");
                     return Ok(current_felt);
                 }
+                if hasher.is_empty_subtrie(current_felt, current_path.len(), tree_height) {
+                    log::trace!("Empty subtrie");
+                    return Ok(Felt::ZERO);
+                }
                 if current_path.len() > k.len() {
                     // We overshot.
                     log::trace!("Overshot");
@@ -115,7 +277,7 @@ impl MultiProof {
 
                 // Check hash and save to verification cache.
                 if let hash_set::Entry::Vacant(entry) = checked_cache.entry(current_felt) {
-                    let computed_hash = node.hash::<H>();
+                    let computed_hash = node.hash_with(&hasher, current_path.len(), tree_height);
                     if computed_hash != current_felt {
                         // Hash mismatch.
                         log::trace!("Hash mismatch: {computed_hash:#x} {current_felt:#x}");
@@ -155,67 +317,291 @@ impl MultiProof {
             }
         })
     }
+
+    /// Compact encoding of the nodes of the proof on the paths from `root`, in a trie of height
+    /// `tree_height`, to be sent instead of the nodes themselves. The nodes are written depth
+    /// first, each with a tag telling which of its children follow it: only the hashes of the
+    /// other children are written, the ones of the nodes of the proof are computed again by
+    /// [`MultiProof::decode_compact`]. The nodes not on a path from `root` are left out.
+    pub fn encode_compact(&self, root: Felt, tree_height: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_compact_node(root, 0, tree_height, &mut out);
+        out
+    }
+
+    fn encode_compact_node(&self, hash: Felt, depth: usize, tree_height: u8, out: &mut Vec<u8>) {
+        // The leaves are values, even when one happens to be the hash of a node.
+        let follows =
+            |child: &Felt, depth: usize| depth < tree_height as usize && self.0.contains_key(child);
+        if !follows(&hash, depth) {
+            return;
+        }
+        match &self.0[&hash] {
+            ProofNode::Binary { left, right } => {
+                let (left_follows, right_follows) =
+                    (follows(left, depth + 1), follows(right, depth + 1));
+                let mut tag = 0;
+                if left_follows {
+                    tag |= COMPACT_FIRST_CHILD;
+                }
+                if right_follows {
+                    tag |= COMPACT_SECOND_CHILD;
+                }
+                out.push(tag);
+                for (child, child_follows) in [(left, left_follows), (right, right_follows)] {
+                    if !child_follows {
+                        child.encode_to(out);
+                    }
+                }
+                for (child, child_follows) in [(left, left_follows), (right, right_follows)] {
+                    if child_follows {
+                        self.encode_compact_node(*child, depth + 1, tree_height, out);
+                    }
+                }
+            }
+            ProofNode::Edge { child, path } => {
+                let child_depth = depth + path.len();
+                let child_follows = follows(child, child_depth);
+                out.push(if child_follows {
+                    COMPACT_EDGE | COMPACT_FIRST_CHILD
+                } else {
+                    COMPACT_EDGE
+                });
+                path.encode_to(out);
+                if child_follows {
+                    self.encode_compact_node(*child, child_depth, tree_height, out);
+                } else {
+                    child.encode_to(out);
+                }
+            }
+        }
+    }
+
+    /// Decode a proof encoded by [`MultiProof::encode_compact`]. It is checked against the root
+    /// hash by [`MultiProof::verify_proof`], as any other proof.
+    pub fn decode_compact<H: StarkHash>(
+        bytes: &[u8],
+        tree_height: u8,
+    ) -> Result<Self, parity_scale_codec::Error> {
+        Self::decode_compact_with(&TrieHasher::new::<H>(), bytes, tree_height)
+    }
+
+    /// [`MultiProof::decode_compact`] with a hasher chosen at runtime, which also decodes the
+    /// proofs of sparse tries.
+    pub fn decode_compact_with(
+        hasher: &TrieHasher,
+        mut bytes: &[u8],
+        tree_height: u8,
+    ) -> Result<Self, parity_scale_codec::Error> {
+        let mut proof = MultiProof(Default::default());
+        if !bytes.is_empty() {
+            proof.decode_compact_node(hasher, &mut bytes, 0, tree_height)?;
+        }
+        if !bytes.is_empty() {
+            return Err("Trailing bytes after the compact proof".into());
+        }
+        Ok(proof)
+    }
+
+    /// Decode the node at `depth` and its children that follow it, and return its hash.
+    fn decode_compact_node(
+        &mut self,
+        hasher: &TrieHasher,
+        input: &mut &[u8],
+        depth: usize,
+        tree_height: u8,
+    ) -> Result<Felt, parity_scale_codec::Error> {
+        if depth >= tree_height as usize {
+            return Err("Compact proof node below the leaves".into());
+        }
+        let tag = input.read_byte()?;
+        let node = if tag & COMPACT_EDGE != 0 {
+            if tag & !(COMPACT_EDGE | COMPACT_FIRST_CHILD) != 0 {
+                return Err("Invalid compact proof edge tag".into());
+            }
+            let path = Path::decode(input)?;
+            // Every node is deeper than its parent, which bounds the recursion.
+            if path.is_empty() || depth + path.len() > tree_height as usize {
+                return Err("Invalid compact proof edge path".into());
+            }
+            let child = if tag & COMPACT_FIRST_CHILD != 0 {
+                self.decode_compact_node(hasher, input, depth + path.len(), tree_height)?
+            } else {
+                Felt::decode(input)?
+            };
+            ProofNode::Edge { child, path }
+        } else {
+            if tag & !(COMPACT_FIRST_CHILD | COMPACT_SECOND_CHILD) != 0 {
+                return Err("Invalid compact proof binary tag".into());
+            }
+            let mut children = [None; 2];
+            for (child, flag) in children
+                .iter_mut()
+                .zip([COMPACT_FIRST_CHILD, COMPACT_SECOND_CHILD])
+            {
+                if tag & flag == 0 {
+                    *child = Some(Felt::decode(input)?);
+                }
+            }
+            let [left, right] = children;
+            let left = match left {
+                Some(left) => left,
+                None => self.decode_compact_node(hasher, input, depth + 1, tree_height)?,
+            };
+            let right = match right {
+                Some(right) => right,
+                None => self.decode_compact_node(hasher, input, depth + 1, tree_height)?,
+            };
+            ProofNode::Binary { left, right }
+        };
+        let hash = node.hash_with(hasher, depth, tree_height);
+        self.0.insert(hash, node);
+        Ok(hash)
+    }
+}
+
+/// A trie known only by its root hash and the proof nodes verified against it, see
+/// [`crate::BonsaiStorage::set_proven_root`].
+#[derive(Debug, Clone)]
+pub(crate) struct ProvenTrie {
+    root: Felt,
+    nodes: MultiProof,
+}
+
+impl ProvenTrie {
+    pub(crate) fn new(root: Felt) -> Self {
+        Self {
+            root,
+            nodes: MultiProof(Default::default()),
+        }
+    }
+
+    pub(crate) fn root(&self) -> Felt {
+        self.root
+    }
+
+    /// Keep the nodes of `proof` reachable from the root through the known nodes, and return how
+    /// many were added. The other nodes are ignored, as they may prove other tries.
+    pub(crate) fn insert(
+        &mut self,
+        hasher: &TrieHasher,
+        proof: MultiProof,
+        tree_height: u8,
+    ) -> Result<usize, ProofVerificationError> {
+        let mut added = 0;
+        let mut visited: HashSet<Felt> = Default::default();
+        let mut stack = vec![(self.root, BitVec::new())];
+        while let Some((hash, path)) = stack.pop() {
+            if path.len() >= tree_height as usize || !visited.insert(hash) {
+                continue;
+            }
+            if !self.nodes.0.contains_key(&hash) {
+                let Some(node) = proof.0.get(&hash) else {
+                    continue;
+                };
+                let computed_hash = node.hash_with(hasher, path.len(), tree_height);
+                if computed_hash != hash {
+                    return Err(ProofVerificationError::HashMismatch {
+                        path,
+                        expected: hash,
+                        got: computed_hash,
+                    });
+                }
+                self.nodes.0.insert(hash, node.clone());
+                added += 1;
+            }
+            match &self.nodes.0[&hash] {
+                ProofNode::Binary { left, right } => {
+                    for (child, direction) in [(*left, false), (*right, true)] {
+                        let mut child_path = path.clone();
+                        child_path.push(direction);
+                        stack.push((child, child_path));
+                    }
+                }
+                ProofNode::Edge { child, path: edge } => {
+                    let mut child_path = path;
+                    child_path.extend_from_bitslice(&edge.0);
+                    stack.push((*child, child_path));
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// Value of `key`, which fails with [`ProofVerificationError::MissingNode`] if it is not
+    /// proven by the known nodes.
+    pub(crate) fn get(
+        &self,
+        hasher: &TrieHasher,
+        key: &BitSlice,
+        tree_height: u8,
+    ) -> Result<Option<Felt>, ProofVerificationError> {
+        // An empty trie has no node to prove anything with.
+        if self.root == hasher.empty_root(tree_height) {
+            return Ok(None);
+        }
+        let value = self
+            .nodes
+            .verify_proof_with(hasher.clone(), self.root, [key], tree_height)
+            .next()
+            .expect("one value per key")?;
+        Ok((value != Felt::ZERO).then_some(value))
+    }
 }
 
 impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     /// This function is designed to be very efficient if the `keys` are sorted - this allows for
     /// the minimal amount of backtracking when switching from one key to the next.
+    ///
+    /// The tree is only read: the nodes loaded and the hashes of the uncommitted changes are kept
+    /// for the duration of the call, so proofs can be generated concurrently.
     pub fn get_multi_proof<DB: BonsaiDatabase, ID: Id>(
-        &mut self,
+        &self,
         db: &KeyValueDB<DB, ID>,
         keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
     ) -> Result<MultiProof, BonsaiStorageError<DB::DatabaseError>> {
-        let max_height = self.max_height;
-
-        struct ProofVisitor<H>(MultiProof, PhantomData<H>);
-        impl<H: StarkHash + Send + Sync> NodeVisitor<H> for ProofVisitor<H> {
-            fn visit_node<DB: BonsaiDatabase>(
-                &mut self,
-                tree: &mut MerkleTree<H>,
-                node_id: NodeKey,
-                _prev_height: usize,
-            ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-                let proof_node = match tree.get_node_mut::<DB>(node_id)? {
+        let mut proof = MultiProof(Default::default());
+        let mut visit =
+            |resolver: &mut NodeResolver<'_, H, DB, ID>, node: ResolvedNode, _: &BitSlice| {
+                let proof_node = match resolver.node(node)? {
                     Node::Binary(binary_node) => {
                         let (left, right) = (binary_node.left, binary_node.right);
                         ProofNode::Binary {
-                            left: tree.get_or_compute_node_hash::<DB>(left)?,
-                            right: tree.get_or_compute_node_hash::<DB>(right)?,
+                            left: resolver.hash(left)?,
+                            right: resolver.hash(right)?,
                         }
                     }
                     Node::Edge(edge_node) => {
                         let (child, path) = (edge_node.child, edge_node.path.clone());
                         ProofNode::Edge {
-                            child: tree.get_or_compute_node_hash::<DB>(child)?,
+                            child: resolver.hash(child)?,
                             path,
                         }
                     }
                 };
-                let hash = tree.get_or_compute_node_hash::<DB>(NodeHandle::InMemory(node_id))?;
-                self.0 .0.insert(hash, proof_node);
+                let hash = resolver.node_hash(node)?;
+                proof.0.insert(hash, proof_node);
                 Ok(())
-            }
-        }
-        let mut visitor = ProofVisitor::<H>(MultiProof(Default::default()), PhantomData);
+            };
 
-        let mut iter = self.iter(db);
+        let mut iter = self.cursor(db);
         for key in keys {
             let key = key.as_ref();
-            if key.len() != max_height as usize {
+            if key.len() != self.max_height as usize {
                 return Err(BonsaiStorageError::KeyLength {
                     expected: self.max_height as _,
                     got: key.len(),
                 });
             }
             log::debug!("go to = {key:b}");
-            iter.traverse_to(&mut visitor, key)?;
+            iter.traverse_to(&mut visit, key)?;
 
             log::debug!("iter = {iter:?}");
             // We should have found a leaf here. If we didn't, the value is not in the trie: return Felt::ZERO.
             // iter.leaf_hash.unwrap_or(Felt::ZERO) // no need to return a value, actually?
         }
 
-        Ok(visitor.0)
+        Ok(proof)
     }
 }
 
@@ -245,7 +631,8 @@ mod tests {
             RocksDB::<BasicId>::new(&db, RocksDBConfig::default()),
             BonsaiStorageConfig::default(),
             8,
-        );
+        )
+        .unwrap();
 
         let key_values = [
             (bits![u8, Msb0; 0,0,0,1,0,0,0,0], ONE),
@@ -278,7 +665,7 @@ mod tests {
         assert_eq!(
             proof
                 .verify_proof::<Pedersen>(
-                    tree.root_hash(&bonsai_storage.tries.db).unwrap(),
+                    tree.pending_root_hash(&bonsai_storage.tries.db).unwrap(),
                     key_values.iter().map(|(k, _v)| k),
                     8
                 )
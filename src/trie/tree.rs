@@ -7,15 +7,21 @@ use starknet_types_core::{felt::Felt, hash::StarkHash};
 use crate::trie::merkle_node::{hash_binary_node, hash_edge_node};
 use crate::BitVec;
 use crate::{
-    error::BonsaiStorageError, format, hash_map, id::Id, vec, BitSlice, BonsaiDatabase, ByteVec,
-    EncodeExt, HashMap, HashSet, KeyValueDB, ToString, Vec,
+    cancellation::CancellationToken,
+    error::{BonsaiStorageError, CorruptedNode},
+    format,
+    id::Id,
+    op_stats::OpCounters,
+    vec, BitSlice, BonsaiDatabase, Box, ByteVec, Cow, DBError, EncodeExt, HashMap, HashSet,
+    KeyValueDB, PendingStats, ToString, Vec,
 };
 
-use super::iterator::MerkleTreeIterator;
+use super::iterator::{MerkleTreeIterator, TrieCursor};
 use super::{
-    merkle_node::{BinaryNode, Direction, EdgeNode, Node, NodeHandle},
-    path::Path,
-    trie_db::TrieKeyType,
+    inline::InlineTrie,
+    merkle_node::{BinaryNode, Direction, EdgeNode, Height, Node, NodeHandle, TrieHasher},
+    path::{Path, PathKey},
+    trie_db::{MetaKeyType, TrieKeyType},
     TrieKey,
 };
 
@@ -48,6 +54,44 @@ pub(crate) enum RootHandle {
     Loaded(NodeKey),
 }
 
+/// Previous state of a part of a [`MerkleTree`], the undo log is replayed in reverse to roll back
+/// to a savepoint.
+#[derive(Debug, Clone)]
+pub(crate) enum UndoEntry {
+    /// A node was inserted at this key.
+    NodeInserted(NodeKey),
+    /// The node at this key was modified or removed, this is its previous value.
+    Node(NodeKey, Node),
+    Root(Option<RootHandle>),
+    /// The trie key was added to the death row.
+    DeathRow(TrieKey),
+    Leaf(ByteVec, Option<InsertOrRemove<Felt>>),
+    /// Previous raw payload of a modified leaf.
+    RawLeaf(ByteVec, Option<ByteVec>),
+}
+
+/// The changes made to a [`MerkleTree`] while a savepoint exists.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UndoLog {
+    entries: Vec<UndoEntry>,
+    /// A removed node is inserted back under a new key by a rollback, the older entries still
+    /// refer to it by its previous key.
+    moved: HashMap<NodeKey, NodeKey>,
+}
+
+impl UndoLog {
+    fn push(&mut self, entry: UndoEntry) {
+        self.entries.push(entry);
+    }
+}
+
+fn current_node_key(moved: &HashMap<NodeKey, NodeKey>, mut node_key: NodeKey) -> NodeKey {
+    while let Some(new_key) = moved.get(&node_key) {
+        node_key = *new_key;
+    }
+    node_key
+}
+
 /// A Starknet binary Merkle-Patricia tree with a specific root entry-point and storage.
 ///
 /// This is used to update, mutate and access global Starknet state as well as individual contract
@@ -65,9 +109,24 @@ pub struct MerkleTree<H: StarkHash> {
     pub(crate) death_row: HashSet<TrieKey>,
     /// The list of leaves that have been modified during the current commit.
     pub(crate) cache_leaf_modified: HashMap<ByteVec, InsertOrRemove<Felt>>,
+    /// Raw payloads of the leaves of `cache_leaf_modified` inserted with [`MerkleTree::set_raw`],
+    /// the other inserted leaves are stored without one.
+    pub(crate) cache_raw_modified: HashMap<ByteVec, ByteVec>,
     /// The maximum height of the tree. This is an u8 because we may rely on the fact that it's less than 256 in the future for optimizations.
     pub(crate) max_height: u8,
-    /// The hasher used to hash the nodes.
+    /// The changes made while a savepoint exists, `None` when there is no savepoint.
+    pub(crate) undo_log: Option<UndoLog>,
+    /// The hasher used to hash the nodes, `H` unless the tree was created with
+    /// [`MerkleTree::with_hasher`].
+    pub(crate) hasher: TrieHasher,
+    /// The nodes of the trie rebuilt from its record when it is stored inline, see
+    /// [`InlineTrie`]. Loaded along with the root node.
+    pub(crate) inline_nodes: Option<HashMap<TrieKey, ByteVec>>,
+    /// Stops the hashing of the nodes once cancelled, see [`MerkleTree::get_updates`].
+    pub(crate) cancel: Option<CancellationToken>,
+    /// Whether zero is stored as a value instead of removing the leaf, see
+    /// [`MerkleTree::store_zero_values`].
+    pub(crate) store_zero: bool,
     _hasher: PhantomData<H>,
 }
 
@@ -79,12 +138,14 @@ impl<H: StarkHash> fmt::Debug for MerkleTree<H> {
             .field("identifier", &self.identifier)
             .field("death_row", &self.death_row)
             .field("cache_leaf_modified", &self.cache_leaf_modified)
+            .field("cache_raw_modified", &self.cache_raw_modified)
+            .field("hasher", &self.hasher)
+            .field("inline_nodes", &self.inline_nodes)
             .finish()
     }
 }
 
 // NB: #[derive(Clone)] does not work because it expands to an impl block which forces H: Clone, which Pedersen/Poseidon aren't.
-#[cfg(feature = "bench")]
 impl<H: StarkHash> Clone for MerkleTree<H> {
     fn clone(&self) -> Self {
         Self {
@@ -94,6 +155,12 @@ impl<H: StarkHash> Clone for MerkleTree<H> {
             identifier: self.identifier.clone(),
             death_row: self.death_row.clone(),
             cache_leaf_modified: self.cache_leaf_modified.clone(),
+            cache_raw_modified: self.cache_raw_modified.clone(),
+            undo_log: self.undo_log.clone(),
+            hasher: self.hasher.clone(),
+            inline_nodes: self.inline_nodes.clone(),
+            cancel: self.cancel.clone(),
+            store_zero: self.store_zero,
             _hasher: PhantomData,
         }
     }
@@ -117,11 +184,83 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
             identifier,
             death_row: HashSet::new(),
             cache_leaf_modified: HashMap::new(),
+            cache_raw_modified: HashMap::new(),
             max_height,
+            undo_log: None,
+            hasher: TrieHasher::new::<H>(),
+            inline_nodes: None,
+            cancel: None,
+            store_zero: false,
             _hasher: PhantomData,
         }
     }
 
+    /// Tree whose nodes are hashed with `hasher` instead of `H`.
+    pub fn with_hasher(identifier: ByteVec, max_height: u8, hasher: TrieHasher) -> Self {
+        Self {
+            hasher,
+            ..Self::new(identifier, max_height)
+        }
+    }
+
+    /// Root hash of a tree without any leaf.
+    pub const fn empty_root() -> Felt {
+        Felt::ZERO
+    }
+
+    /// Root hash of a tree whose only leaf is `value` at `key`, the height of the tree being the
+    /// length of the key.
+    pub fn single_leaf_root(key: &BitSlice, value: Felt) -> Felt {
+        if value == Felt::ZERO {
+            return Self::empty_root();
+        }
+        Self::root_from_sorted_leaves(&[(key, value)])
+    }
+
+    /// Root hash of the tree containing `leaves`, computed in memory without a database.
+    ///
+    /// Leaves must be sorted by key without duplicates, their keys must all be as long as the
+    /// height of the tree and their values must not be zero.
+    pub fn root_from_sorted_leaves(leaves: &[(&BitSlice, Felt)]) -> Felt {
+        Self::subtree_root(leaves, 0)
+    }
+
+    /// Hash of the subtree containing `leaves`, which all share the same first `height` bits.
+    fn subtree_root(leaves: &[(&BitSlice, Felt)], height: usize) -> Felt {
+        let (Some((first, value)), Some((last, _))) = (leaves.first(), leaves.last()) else {
+            return Self::empty_root();
+        };
+        if height == first.len() {
+            return *value;
+        }
+
+        // As the leaves are sorted, the prefix shared by the first and last ones is shared by all.
+        let common_len = first[height..]
+            .iter()
+            .zip(last[height..].iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common_len > 0 {
+            let path = Path(first[height..height + common_len].to_bitvec());
+            let child_hash = Self::subtree_root(leaves, height + common_len);
+            return hash_edge_node::<H>(&path, child_hash);
+        }
+
+        let (left, right) = leaves.split_at(leaves.partition_point(|(key, _)| !key[height]));
+        #[cfg(feature = "std")]
+        if leaves.len() >= PARALLEL_ROOT_MIN_LEAVES {
+            let (left_hash, right_hash) = rayon::join(
+                || Self::subtree_root(left, height + 1),
+                || Self::subtree_root(right, height + 1),
+            );
+            return hash_binary_node::<H>(left_hash, right_hash);
+        }
+        hash_binary_node::<H>(
+            Self::subtree_root(left, height + 1),
+            Self::subtree_root(right, height + 1),
+        )
+    }
+
     /// Loads the root node or returns None if the tree is empty.
     pub(crate) fn load_root_node<DB: BonsaiDatabase, ID: Id>(
         &mut self,
@@ -133,16 +272,25 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
             Some(RootHandle::Empty) => Ok(None),
             None => {
                 // load the node
-                let id = self
-                    .load_db_node(db, &TrieKey::new(&self.identifier, TrieKeyType::Trie, &[0]))?;
+                let mut id = self.load_db_node(db, BitSlice::empty())?;
+                if id.is_none() {
+                    if let Some(record) = InlineTrie::read(db, &self.identifier)? {
+                        self.inline_nodes = Some(record.nodes::<H, _>(
+                            &self.identifier,
+                            self.max_height,
+                            &self.hasher,
+                        )?);
+                        id = self.load_db_node(db, BitSlice::empty())?;
+                    }
+                }
 
                 match id {
                     Some(id) => {
-                        self.root_node = Some(RootHandle::Loaded(id));
+                        self.set_root(Some(RootHandle::Loaded(id)));
                         Ok(Some(id))
                     }
                     None => {
-                        self.root_node = Some(RootHandle::Empty);
+                        self.set_root(Some(RootHandle::Empty));
                         Ok(None)
                     }
                 }
@@ -150,20 +298,25 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         }
     }
 
-    /// First step of two phase init.
+    /// First step of two phase init. The node is at `path`.
     pub(crate) fn load_db_node<DB: BonsaiDatabase, ID: Id>(
         &mut self,
         db: &KeyValueDB<DB, ID>,
-        key: &TrieKey,
+        path: &BitSlice,
     ) -> Result<Option<NodeKey>, BonsaiStorageError<DB::DatabaseError>> {
-        if self.death_row.contains(key) {
+        let key = TrieKey::new(&self.identifier, TrieKeyType::Trie, &PathKey::node(path));
+        if self.death_row.contains(&key) {
             return Ok(None);
         }
-        let node = db.get(key)?;
+        let node = match &self.inline_nodes {
+            Some(nodes) => nodes.get(&key).cloned(),
+            None => db.get(&key)?,
+        };
         let Some(node) = node else { return Ok(None) };
 
-        let node = Node::decode(&mut node.as_slice())?;
-        let key = self.nodes.insert(node);
+        let node = decode_node(&self.identifier, path, &key, &node, self.max_height)?;
+        db.stats.node_decoded();
+        let key = self.insert_node(node);
 
         Ok(Some(key))
     }
@@ -172,9 +325,151 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         &mut self,
         node_key: NodeKey,
     ) -> Result<&mut Node, BonsaiStorageError<DB::DatabaseError>> {
-        self.nodes.get_mut(node_key).ok_or_else(|| {
+        let node = self.nodes.get_mut(node_key).ok_or_else(|| {
             BonsaiStorageError::Trie(format!("Dangling in-memory node key: {node_key:?}"))
-        })
+        })?;
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(UndoEntry::Node(node_key, node.clone()));
+        }
+        Ok(node)
+    }
+
+    fn insert_node(&mut self, node: Node) -> NodeKey {
+        let node_key = self.nodes.insert(node);
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(UndoEntry::NodeInserted(node_key));
+        }
+        node_key
+    }
+
+    fn replace_node(&mut self, node_key: NodeKey, node: Node) {
+        let previous = mem::replace(&mut self.nodes[node_key], node);
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(UndoEntry::Node(node_key, previous));
+        }
+    }
+
+    fn remove_node(&mut self, node_key: NodeKey) {
+        if let (Some(node), Some(undo_log)) = (self.nodes.remove(node_key), &mut self.undo_log) {
+            undo_log.push(UndoEntry::Node(node_key, node));
+        }
+    }
+
+    fn set_root(&mut self, root_node: Option<RootHandle>) {
+        let previous = mem::replace(&mut self.root_node, root_node);
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(UndoEntry::Root(previous));
+        }
+    }
+
+    fn add_to_death_row(&mut self, key: TrieKey) {
+        match &mut self.undo_log {
+            Some(undo_log) if !self.death_row.contains(&key) => {
+                self.death_row.insert(key.clone());
+                undo_log.push(UndoEntry::DeathRow(key));
+            }
+            _ => {
+                self.death_row.insert(key);
+            }
+        }
+    }
+
+    fn modify_leaf(&mut self, key: &[u8], value: InsertOrRemove<Felt>) {
+        match &mut self.undo_log {
+            Some(undo_log) => {
+                let previous = self.cache_leaf_modified.insert(key.into(), value);
+                undo_log.push(UndoEntry::Leaf(key.into(), previous));
+            }
+            None => {
+                self.cache_leaf_modified.insert(key.into(), value);
+            }
+        }
+    }
+
+    fn modify_raw_leaf(&mut self, key: &[u8], raw: Option<ByteVec>) {
+        let previous = match raw {
+            Some(raw) => self.cache_raw_modified.insert(key.into(), raw),
+            None => self.cache_raw_modified.remove(key),
+        };
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(UndoEntry::RawLeaf(key.into(), previous));
+        }
+    }
+
+    /// Length of the undo log, journaling starts if it was not enabled.
+    pub(crate) fn undo_log_len(&mut self) -> usize {
+        self.undo_log
+            .get_or_insert_with(Default::default)
+            .entries
+            .len()
+    }
+
+    /// Undo the changes recorded after the undo log had `len` entries.
+    ///
+    /// A removed node is inserted back under a new key, the handles to its previous key in the
+    /// restored nodes and in the root are updated afterwards. The nodes which were not modified
+    /// cannot point to a removed node, as removing a node always modifies its parent.
+    pub(crate) fn undo_to(&mut self, len: usize) {
+        let Some(undo_log) = &mut self.undo_log else {
+            return;
+        };
+        let moved = &mut undo_log.moved;
+        let mut restored = Vec::new();
+        for entry in undo_log.entries.drain(len..).rev() {
+            match entry {
+                UndoEntry::NodeInserted(node_key) => {
+                    self.nodes.remove(current_node_key(moved, node_key));
+                }
+                UndoEntry::Node(node_key, node) => {
+                    let current = current_node_key(moved, node_key);
+                    if let Some(slot) = self.nodes.get_mut(current) {
+                        *slot = node;
+                        restored.push(current);
+                    } else {
+                        let new_key = self.nodes.insert(node);
+                        moved.insert(current, new_key);
+                        restored.push(new_key);
+                    }
+                }
+                UndoEntry::Root(root_node) => self.root_node = root_node,
+                UndoEntry::DeathRow(key) => {
+                    self.death_row.remove(&key);
+                }
+                UndoEntry::Leaf(key, Some(value)) => {
+                    self.cache_leaf_modified.insert(key, value);
+                }
+                UndoEntry::Leaf(key, None) => {
+                    self.cache_leaf_modified.remove(&key);
+                }
+                UndoEntry::RawLeaf(key, Some(raw)) => {
+                    self.cache_raw_modified.insert(key, raw);
+                }
+                UndoEntry::RawLeaf(key, None) => {
+                    self.cache_raw_modified.remove(&key);
+                }
+            }
+        }
+        if moved.is_empty() {
+            return;
+        }
+        let update_handle = |handle: &mut NodeHandle| {
+            if let NodeHandle::InMemory(node_key) = handle {
+                *node_key = current_node_key(moved, *node_key);
+            }
+        };
+        for node_key in restored {
+            match self.nodes.get_mut(node_key) {
+                Some(Node::Binary(binary)) => {
+                    update_handle(&mut binary.left);
+                    update_handle(&mut binary.right);
+                }
+                Some(Node::Edge(edge)) => update_handle(&mut edge.child),
+                None => {}
+            }
+        }
+        if let Some(RootHandle::Loaded(node_key)) = &mut self.root_node {
+            *node_key = current_node_key(moved, *node_key);
+        }
     }
 
     pub(crate) fn load_node_handle<DB: BonsaiDatabase, ID: Id>(
@@ -185,64 +480,26 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     ) -> Result<NodeKey, BonsaiStorageError<DB::DatabaseError>> {
         match handle {
             NodeHandle::Hash(_) => {
-                // TODO(perf): useless allocs everywhere here...
-                let path: ByteVec = path.clone().into();
-                log::trace!("Visiting db node {:?}", path);
-                let key = TrieKey::new(&self.identifier, TrieKeyType::Trie, &path);
-                let Some(node_key) = self.load_db_node(db, &key)? else {
+                log::trace!("Visiting db node {:b}", path.0);
+                let Some(node_key) = self.load_db_node(db, path)? else {
                     // Dangling node id in db
-                    return Err(BonsaiStorageError::Trie(
-                        "Could not get node from db".to_string(),
-                    ));
+                    return Err(BonsaiStorageError::NodeNotFound {
+                        identifier: self.identifier.clone(),
+                        path: path.clone(),
+                    });
                 };
                 Ok(node_key)
             }
-            NodeHandle::InMemory(node_key) => Ok(node_key),
-        }
-    }
-
-    /// Get or compute the hash of a node.
-    pub(crate) fn get_or_compute_node_hash<DB: BonsaiDatabase>(
-        &mut self,
-        node: NodeHandle,
-    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
-        match node {
-            NodeHandle::Hash(felt) => Ok(felt),
             NodeHandle::InMemory(node_key) => {
-                let computed_hash = match self.get_node_mut::<DB>(node_key)? {
-                    Node::Binary(binary_node) => {
-                        if let Some(hash) = binary_node.hash {
-                            return Ok(hash);
-                        }
-                        let (left, right) = (binary_node.left, binary_node.right);
-                        let left_hash = self.get_or_compute_node_hash::<DB>(left)?;
-                        let right_hash = self.get_or_compute_node_hash::<DB>(right)?;
-                        hash_binary_node::<H>(left_hash, right_hash)
-                    }
-                    Node::Edge(edge_node) => {
-                        if let Some(hash) = edge_node.hash {
-                            return Ok(hash);
-                        }
-                        let (path, child) = (edge_node.path.clone(), edge_node.child);
-                        // edge_node borrow ends here
-                        let child_hash = self.get_or_compute_node_hash::<DB>(child)?;
-                        hash_edge_node::<H>(&path, child_hash)
-                    }
-                };
-
-                // reborrow, for lifetime reasons (can't go into children if a borrow is alive)
-                match self.get_node_mut::<DB>(node_key)? {
-                    Node::Binary(binary_node) => binary_node.hash = Some(computed_hash),
-                    Node::Edge(edge_node) => edge_node.hash = Some(computed_hash),
-                }
-
-                Ok(computed_hash)
+                db.stats.cache_hit();
+                Ok(node_key)
             }
         }
     }
 
     /// Note: as iterators load nodes from the database, this takes an &mut self. However,
-    /// note that it will not modify anything in the database - hence the &db.
+    /// note that it will not modify anything in the database - hence the &db. Use
+    /// [`MerkleTree::cursor`] to only read the tree.
     pub fn iter<'a, DB: BonsaiDatabase, ID: Id>(
         &'a mut self,
         db: &'a KeyValueDB<DB, ID>,
@@ -250,6 +507,41 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         MerkleTreeIterator::new(self, db)
     }
 
+    /// Cursor reading the tree from a shared reference, see [`TrieCursor`].
+    pub fn cursor<'a, DB: BonsaiDatabase, ID: Id>(
+        &'a self,
+        db: &'a KeyValueDB<DB, ID>,
+    ) -> TrieCursor<'a, H, DB, ID> {
+        TrieCursor::new(Cow::Borrowed(self), db)
+    }
+
+    /// Load the nodes on the paths to `keys` into memory, so that modifying these keys does not
+    /// read from the database anymore. The nodes stay loaded until the next commit.
+    pub fn prefetch<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        if let Some(key) = keys
+            .iter()
+            .find(|key| key.as_ref().len() != self.max_height as usize)
+        {
+            return Err(BonsaiStorageError::KeyLength {
+                expected: self.max_height as _,
+                got: key.as_ref().len(),
+            });
+        }
+        // Sorted keys share the longest prefixes with the previous one, which the iterator reuses.
+        keys.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+
+        let mut iter = self.iter(db);
+        for key in keys {
+            iter.seek_to(key.as_ref())?;
+        }
+        Ok(())
+    }
+
     /// # Panics
     ///
     /// Calling this function when the tree has uncommited changes is invalid as the hashes need to be recomputed.
@@ -258,11 +550,14 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         db: &KeyValueDB<DB, ID>,
     ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
         match self.root_node {
-            Some(RootHandle::Empty) => Ok(Felt::ZERO),
+            Some(RootHandle::Empty) => Ok(self.hasher.empty_root(self.max_height)),
             Some(RootHandle::Loaded(node_id)) => {
-                let node = self.nodes.get(node_id).ok_or_else(|| {
-                    BonsaiStorageError::Trie("Could not fetch root node from storage".into())
-                })?;
+                let node =
+                    self.nodes
+                        .get(node_id)
+                        .ok_or_else(|| BonsaiStorageError::RootNotFound {
+                            identifier: self.identifier.clone(),
+                        })?;
                 node.get_hash().ok_or_else(|| {
                     BonsaiStorageError::Trie("The tree has uncommited changes".into())
                 })
@@ -271,11 +566,16 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                 let Some(node) = Self::get_trie_branch_in_db_from_path(
                     &self.death_row,
                     &self.identifier,
+                    self.inline_nodes.as_ref(),
                     db,
                     &Path::default(),
+                    self.max_height,
                 )?
                 else {
-                    return Ok(Felt::ZERO);
+                    if let Some(record) = InlineTrie::read(db, &self.identifier)? {
+                        return Ok(record.root_hash);
+                    }
+                    return Ok(self.hasher.empty_root(self.max_height));
                 };
                 Ok(node
                     .get_hash()
@@ -284,18 +584,66 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         }
     }
 
-    pub fn cache_leaf_modified(&self) -> &HashMap<ByteVec, InsertOrRemove<Felt>> {
+    /// Root hash of the tree once the uncommitted changes are committed, without committing them.
+    pub(crate) fn pending_root_hash<DB: BonsaiDatabase, ID: Id>(
+        &self,
+        db: &KeyValueDB<DB, ID>,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        match self.root_node {
+            Some(RootHandle::Loaded(_)) => {
+                let mut hashes = Vec::new();
+                let root_hash = self.compute_root_hash::<DB>(&mut hashes)?;
+                db.stats.nodes_hashed(hashes.len());
+                Ok(root_hash)
+            }
+            _ => self.root_hash(db),
+        }
+    }
+
+    pub(crate) fn cache_leaf_modified(&self) -> &HashMap<ByteVec, InsertOrRemove<Felt>> {
         &self.cache_leaf_modified
     }
 
-    /// Calculate all the new hashes and the root hash.
+    pub(crate) fn has_uncommitted_changes(&self) -> bool {
+        !self.cache_leaf_modified.is_empty() || !self.death_row.is_empty()
+    }
+
+    pub(crate) fn pending_stats(&self) -> PendingStats {
+        PendingStats {
+            nodes: self.nodes.len(),
+            death_row: self.death_row.len(),
+            modified_leaves: self.cache_leaf_modified.len(),
+        }
+    }
+
+    /// The modified leaves as they are stored in the database, see [`encode_leaf`].
+    pub(crate) fn stored_leaf_changes(&self) -> impl Iterator<Item = (&ByteVec, Option<ByteVec>)> {
+        self.cache_leaf_modified.iter().map(|(key, value)| {
+            let value = match value {
+                InsertOrRemove::Insert(value) => Some(encode_leaf(
+                    value,
+                    self.cache_raw_modified.get(key).map(|raw| raw.as_slice()),
+                )),
+                InsertOrRemove::Remove => None,
+            };
+            (key, value)
+        })
+    }
+
+    /// Calculate all the new hashes and the root hash, counting the hashed nodes in `stats`. Once
+    /// `cancel` is cancelled, stops while hashing and leaves the tree unusable, it is only given
+    /// for copies of the trees.
     #[allow(clippy::type_complexity)]
     pub(crate) fn get_updates<DB: BonsaiDatabase>(
         &mut self,
+        stats: &OpCounters,
+        cancel: Option<&CancellationToken>,
     ) -> Result<
         impl Iterator<Item = (TrieKey, InsertOrRemove<ByteVec>)>,
         BonsaiStorageError<DB::DatabaseError>,
     > {
+        self.undo_log = None;
+        self.cancel = cancel.cloned();
         let mut updates = HashMap::new();
         for node_key in mem::take(&mut self.death_row) {
             updates.insert(node_key, InsertOrRemove::Remove);
@@ -305,6 +653,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
             // compute hashes
             let mut hashes = vec![];
             self.compute_root_hash::<DB>(&mut hashes)?;
+            stats.nodes_hashed(hashes.len());
 
             // commit the tree
             self.commit_subtree::<DB>(
@@ -316,14 +665,20 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         }
 
         self.root_node = None; // unloaded
+        self.inline_nodes = None;
 
+        let mut raw_leaves = mem::take(&mut self.cache_raw_modified);
         for (key, value) in mem::take(&mut self.cache_leaf_modified) {
+            let value = match value {
+                InsertOrRemove::Insert(value) => {
+                    let raw = raw_leaves.remove(&key);
+                    InsertOrRemove::Insert(encode_leaf(&value, raw.as_deref()))
+                }
+                InsertOrRemove::Remove => InsertOrRemove::Remove,
+            };
             updates.insert(
                 TrieKey::new(&self.identifier, TrieKeyType::Flat, &key),
-                match value {
-                    InsertOrRemove::Insert(value) => InsertOrRemove::Insert(value.encode_bytevec()),
-                    InsertOrRemove::Remove => InsertOrRemove::Remove,
-                },
+                value,
             );
         }
         #[cfg(test)]
@@ -332,13 +687,30 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         Ok(updates.into_iter())
     }
 
+    /// Number of leaves added minus number of leaves removed by the uncommitted changes.
+    pub(crate) fn leaf_count_delta<DB: BonsaiDatabase, ID: Id>(
+        &self,
+        db: &KeyValueDB<DB, ID>,
+    ) -> Result<i64, BonsaiStorageError<DB::DatabaseError>> {
+        let mut delta = 0;
+        for (key, value) in &self.cache_leaf_modified {
+            let in_db = db.contains(&TrieKey::new(&self.identifier, TrieKeyType::Flat, key))?;
+            match value {
+                InsertOrRemove::Insert(_) if !in_db => delta += 1,
+                InsertOrRemove::Remove if in_db => delta -= 1,
+                _ => {}
+            }
+        }
+        Ok(delta)
+    }
+
     // Commit a single merkle tree
     #[cfg(test)]
     pub(crate) fn commit<DB: BonsaiDatabase, ID: Id>(
         &mut self,
         db: &mut KeyValueDB<DB, ID>,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        let db_changes = self.get_updates::<DB>()?;
+        let db_changes = self.get_updates::<DB>(&db.stats, None)?;
 
         let mut batch = db.create_batch();
         for (key, value) in db_changes {
@@ -365,10 +737,21 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         assert_eq!(self.nodes.iter().collect::<Vec<_>>(), vec![]);
     }
 
+    /// The child `node_handle` to hash, failing with [`BonsaiStorageError::Cancelled`] once
+    /// [`MerkleTree::cancel`] is cancelled. Checked here rather than in
+    /// [`MerkleTree::compute_hashes`] to keep its stack frames small, as it recurses down to the
+    /// leaves.
     fn get_node_or_felt<DB: BonsaiDatabase>(
         &self,
         node_handle: &NodeHandle,
     ) -> Result<NodeOrFelt, BonsaiStorageError<DB::DatabaseError>> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(BonsaiStorageError::Cancelled);
+        }
         let node_id = match node_handle {
             NodeHandle::Hash(hash) => return Ok(NodeOrFelt::Felt(*hash)),
             NodeHandle::InMemory(node_id) => *node_id,
@@ -385,7 +768,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
         let handle = match &self.root_node {
             Some(RootHandle::Loaded(node_id)) => *node_id,
-            Some(RootHandle::Empty) => return Ok(Felt::ZERO),
+            Some(RootHandle::Empty) => return Ok(self.hasher.empty_root(self.max_height)),
             None => {
                 return Err(BonsaiStorageError::Trie(
                     "Root node is not loaded".to_string(),
@@ -393,9 +776,9 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
             }
         };
         let Some(node) = self.nodes.get(handle) else {
-            return Err(BonsaiStorageError::Trie(
-                "Could not fetch root node from storage".to_string(),
-            ));
+            return Err(BonsaiStorageError::RootNotFound {
+                identifier: self.identifier.clone(),
+            });
         };
         self.compute_hashes::<DB>(node, Path::default(), hashes)
     }
@@ -455,7 +838,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                     }
                 };
 
-                let hash = hash_binary_node::<H>(left_hash, right_hash);
+                let hash = self.hasher.hash_binary_node(left_hash, right_hash);
 
                 hashes.push(hash);
                 Ok(hash)
@@ -471,7 +854,12 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                     }
                 };
 
-                let hash = hash_edge_node::<H>(&edge.path, child_hash);
+                let hash = self.hasher.hash_edge_node(
+                    &edge.path,
+                    child_hash,
+                    edge.height.get(),
+                    self.max_height,
+                );
                 hashes.push(hash);
 
                 Ok(hash)
@@ -528,10 +916,10 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                 binary.hash = Some(hash);
                 binary.left = NodeHandle::Hash(left_hash);
                 binary.right = NodeHandle::Hash(right_hash);
-                let key_bytes: ByteVec = path.into();
+                let key_bytes = PathKey::node(&path);
                 updates.insert(
                     TrieKey::new(&self.identifier, TrieKeyType::Trie, &key_bytes),
-                    InsertOrRemove::Insert(Node::Binary(binary).encode_bytevec()),
+                    InsertOrRemove::Insert(Node::Binary(binary).encode_versioned()),
                 );
                 Ok(hash)
             }
@@ -547,17 +935,18 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                 let hash = hashes.next().expect("mismatched hash state");
                 edge.hash = Some(hash);
                 edge.child = NodeHandle::Hash(child_hash);
-                let key_bytes: ByteVec = path.into();
+                let key_bytes = PathKey::node(&path);
                 updates.insert(
                     TrieKey::new(&self.identifier, TrieKeyType::Trie, &key_bytes),
-                    InsertOrRemove::Insert(Node::Edge(edge).encode_bytevec()),
+                    InsertOrRemove::Insert(Node::Edge(edge).encode_versioned()),
                 );
                 Ok(hash)
             }
         }
     }
 
-    /// Sets the value of a key. To delete a key, set the value to [Felt::ZERO].
+    /// Sets the value of a key. To delete a key, set the value to [Felt::ZERO], or to the default
+    /// value of a sparse tree.
     ///
     /// # Arguments
     ///
@@ -569,38 +958,90 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         key: &BitSlice,
         value: Felt,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        if value == Felt::ZERO {
+        if self.is_removal(value) {
             return self.delete_leaf(db, key);
         }
-        if key.len() != self.max_height as _ {
+        if key.len() != self.max_height as usize {
             return Err(BonsaiStorageError::KeyLength {
                 expected: self.max_height as _,
                 got: key.len(),
             });
         }
-        let key_bytes = bitslice_to_bytes(key);
+        let key_bytes = PathKey::leaf(key);
         log::trace!("key_bytes: {:?}", key_bytes);
+        // The leaf is stored without raw payload from now on.
+        if self.cache_raw_modified.contains_key(key_bytes.as_slice()) {
+            self.modify_raw_leaf(&key_bytes, None);
+        }
 
-        // TODO(perf): do not double lookup when changing the value later (borrow needs to be split for preload_nodes though)
-        let mut cache_leaf_entry = self.cache_leaf_modified.entry_ref(&key_bytes[..]);
-
-        if let hash_map::EntryRef::Occupied(entry) = &mut cache_leaf_entry {
-            if matches!(entry.get(), InsertOrRemove::Insert(_)) {
-                entry.insert(InsertOrRemove::Insert(value));
-                return Ok(());
+        // Nothing to do if the value is unchanged. The leaf in the trie nodes must be updated
+        // otherwise, even when it was already modified since the last commit.
+        match self.cache_leaf_modified.get(key_bytes.as_slice()) {
+            Some(InsertOrRemove::Insert(cached)) if *cached == value => return Ok(()),
+            Some(_) => {}
+            None => {
+                let flat_key = TrieKey::new(&self.identifier, TrieKeyType::Flat, &key_bytes);
+                if let Some(value_db) = db.get(&flat_key)? {
+                    let (value_db, raw) = decode_leaf(&value_db).map_err(|source| {
+                        BonsaiStorageError::DecodeError {
+                            key: flat_key.as_slice().into(),
+                            source,
+                        }
+                    })?;
+                    if value == value_db {
+                        // Only the raw payload is dropped, the trie does not change.
+                        if !raw.is_empty() {
+                            self.modify_leaf(&key_bytes, InsertOrRemove::Insert(value));
+                        }
+                        return Ok(());
+                    }
+                }
             }
         }
 
-        if let Some(value_db) = db.get(&TrieKey::new(
-            &self.identifier,
-            TrieKeyType::Flat,
-            &key_bytes,
-        ))? {
-            if value == Felt::decode(&mut value_db.as_slice()).unwrap() {
-                return Ok(());
-            }
+        self.insert_leaf(db, key, key_bytes, value)
+    }
+
+    /// Returns the value of `key`, after inserting the one returned by `f` if it has none. The leaf
+    /// is read once, and the trie only traversed to insert it, unlike [`MerkleTree::get`] followed
+    /// by [`MerkleTree::set`].
+    pub fn get_or_insert_with<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
+        f: impl FnOnce() -> Felt,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(value) = self.get(db, key)? {
+            return Ok(value);
+        }
+        let value = f();
+        if self.is_removal(value) {
+            return Ok(value);
+        }
+        if key.len() != self.max_height as usize {
+            return Err(BonsaiStorageError::KeyLength {
+                expected: self.max_height as _,
+                got: key.len(),
+            });
+        }
+        let key_bytes = PathKey::leaf(key);
+        // The payload of a leaf removed since the last commit.
+        if self.cache_raw_modified.contains_key(key_bytes.as_slice()) {
+            self.modify_raw_leaf(&key_bytes, None);
         }
+        self.insert_leaf(db, key, key_bytes, value)?;
+        Ok(value)
+    }
 
+    /// Set the leaf `key`, whose key in the database is `key_bytes`, to `value`, which is not the
+    /// value it has.
+    fn insert_leaf<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
+        key_bytes: PathKey,
+        value: Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         let mut iter = self.iter(db);
         iter.seek_to(key)?;
         log::trace!("Iter is {:?}", iter);
@@ -632,14 +1073,13 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                     Edge(edge) => {
                         let common = edge.common_path(key);
                         // Height of the binary node
-                        let branch_height = edge.height as usize + common.len();
+                        let branch_height = edge.height.get() + common.len();
                         if branch_height == key.len() {
                             edge.child = NodeHandle::Hash(value);
                             // The leaf already exists, we simply change its value.
                             log::trace!("change val: {:?} => {:#x}", key_bytes, value);
-                            self.cache_leaf_modified
-                                .insert(key_bytes, InsertOrRemove::Insert(value));
-                            self.nodes[*node_id] = node;
+                            self.modify_leaf(&key_bytes, InsertOrRemove::Insert(value));
+                            self.replace_node(*node_id, node);
                             return Ok(());
                         }
                         // Height of the binary node's children
@@ -657,15 +1097,17 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                             key_bytes,
                             value
                         );
-                        self.cache_leaf_modified
-                            .insert(key_bytes, InsertOrRemove::Insert(value));
+                        self.modify_leaf(&key_bytes, InsertOrRemove::Insert(value));
 
                         let new = if new_path.is_empty() {
                             NodeHandle::Hash(value)
                         } else {
-                            let edge_id = self.nodes.insert(Node::Edge(EdgeNode {
+                            let edge_id = self.insert_node(Node::Edge(EdgeNode {
                                 hash: None,
-                                height: child_height as u64,
+                                height: node_height::<DB::DatabaseError>(
+                                    child_height,
+                                    self.max_height,
+                                )?,
                                 path: Path(new_path),
                                 child: NodeHandle::Hash(value),
                             }));
@@ -676,9 +1118,12 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                         let old = if old_path.is_empty() {
                             edge.child
                         } else {
-                            let edge_id = self.nodes.insert(Node::Edge(EdgeNode {
+                            let edge_id = self.insert_node(Node::Edge(EdgeNode {
                                 hash: None,
-                                height: child_height as u64,
+                                height: node_height::<DB::DatabaseError>(
+                                    child_height,
+                                    self.max_height,
+                                )?,
                                 path: Path(old_path),
                                 child: edge.child,
                             }));
@@ -693,7 +1138,10 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
 
                         let branch = Node::Binary(BinaryNode {
                             hash: None,
-                            height: branch_height as u64,
+                            height: node_height::<DB::DatabaseError>(
+                                branch_height,
+                                self.max_height,
+                            )?,
                             left,
                             right,
                         });
@@ -702,7 +1150,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                         let new_node = if common.is_empty() {
                             branch
                         } else {
-                            let branch_id = self.nodes.insert(branch);
+                            let branch_id = self.insert_node(branch);
                             Node::Edge(EdgeNode {
                                 hash: None,
                                 height: edge.height,
@@ -710,28 +1158,31 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                                 child: NodeHandle::InMemory(branch_id),
                             })
                         };
-                        let key_bytes = bitslice_to_bytes(&key[..edge.height as usize]);
-                        log::trace!("2 death row add ({:?})", key_bytes);
-                        self.death_row.insert(TrieKey::Trie(key_bytes));
+                        let path = PathKey::node(&key[..edge.height.get()]);
+                        log::trace!("2 death row add ({:?})", path);
+                        self.add_to_death_row(TrieKey::new(
+                            &self.identifier,
+                            TrieKeyType::Trie,
+                            &path,
+                        ));
                         node = new_node;
                     }
                     Binary(binary) => {
-                        let child_height = binary.height + 1;
+                        let child_height = binary.height.get() + 1;
 
-                        if child_height as usize == key.len() {
-                            let direction = Direction::from(key[binary.height as usize]);
+                        if child_height == key.len() {
+                            let direction = Direction::from(key[binary.height.get()]);
                             match direction {
                                 Direction::Left => binary.left = NodeHandle::Hash(value),
                                 Direction::Right => binary.right = NodeHandle::Hash(value),
                             };
-                            self.cache_leaf_modified
-                                .insert(key_bytes, InsertOrRemove::Insert(value));
+                            self.modify_leaf(&key_bytes, InsertOrRemove::Insert(value));
                         }
                     }
                 };
 
                 // Update the node
-                self.nodes[*node_id] = node;
+                self.replace_node(*node_id, node);
                 Ok(())
             }
             None => {
@@ -741,25 +1192,66 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                 // an edge node connecting to the leaf.
                 let edge = Node::Edge(EdgeNode {
                     hash: None,
-                    height: 0,
+                    height: Height::default(),
                     path: Path(key.to_bitvec()),
                     child: NodeHandle::Hash(value),
                 });
-                let node_id = self.nodes.insert(edge);
-                self.root_node = Some(RootHandle::Loaded(node_id));
+                let node_id = self.insert_node(edge);
+                self.set_root(Some(RootHandle::Loaded(node_id)));
 
-                let key_bytes = bitslice_to_bytes(key);
-                self.cache_leaf_modified
-                    .insert(key_bytes, InsertOrRemove::Insert(value));
+                self.modify_leaf(&PathKey::leaf(key), InsertOrRemove::Insert(value));
                 Ok(())
             }
         }
     }
 
-    /// Deletes a leaf node from the tree.
-    ///
-    /// This is not an external facing API; the functionality is instead accessed by calling
-    /// [`MerkleTree::set`] with value set to [`Felt::ZERO`].
+    /// Sets the value of a key along with a raw payload, stored next to it but not hashed by the
+    /// trie: `value` is the commitment to the payload. Setting the value to [Felt::ZERO] deletes
+    /// the key and its payload.
+    pub fn set_raw<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
+        value: Felt,
+        raw: &[u8],
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.set(db, key, value)?;
+        if self.is_removal(value) {
+            return Ok(());
+        }
+        let key_bytes = PathKey::leaf(key);
+        // The leaf is written again for its payload even if its value did not change.
+        if !self.cache_leaf_modified.contains_key(key_bytes.as_slice()) {
+            self.modify_leaf(&key_bytes, InsertOrRemove::Insert(value));
+        }
+        self.modify_raw_leaf(&key_bytes, Some(raw.into()));
+        Ok(())
+    }
+
+    /// Store the leaves set to zero instead of removing them, so that they are removed only by
+    /// [`MerkleTree::remove`]. The default value of a sparse tree still removes them, as the tree
+    /// can't tell them from the missing leaves.
+    pub fn store_zero_values(mut self, store_zero: bool) -> Self {
+        self.store_zero = store_zero;
+        self
+    }
+
+    /// Whether setting a key to `value` removes it.
+    fn is_removal(&self, value: Felt) -> bool {
+        (value == Felt::ZERO && !self.store_zero) || Some(value) == self.hasher.default_value()
+    }
+
+    /// Removes a key, which does nothing if it is not in the tree. This is what setting it to
+    /// [`Felt::ZERO`] does, unless the tree stores zero values.
+    pub fn remove<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.delete_leaf(db, key)
+    }
+
+    /// Deletes a leaf node from the tree, see [`MerkleTree::remove`].
     ///
     /// # Arguments
     ///
@@ -769,7 +1261,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         db: &KeyValueDB<DB, ID>,
         key: &BitSlice,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        if key.len() != self.max_height as _ {
+        if key.len() != self.max_height as usize {
             return Err(BonsaiStorageError::KeyLength {
                 expected: self.max_height as _,
                 got: key.len(),
@@ -789,11 +1281,11 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         // and other remaining child node -- if they're also edges.
         //
         // Then we are done.
-        let key_bytes = bitslice_to_bytes(key);
-        let leaf_entry = self.cache_leaf_modified.entry(key_bytes.clone());
+        let key_bytes = PathKey::leaf(key);
 
-        let tree_has_value = if let hash_map::Entry::Occupied(entry) = &leaf_entry {
-            !matches!(entry.get(), InsertOrRemove::Remove)
+        let tree_has_value = if let Some(value) = self.cache_leaf_modified.get(key_bytes.as_slice())
+        {
+            !matches!(value, InsertOrRemove::Remove)
         } else {
             db.get(&TrieKey::new(
                 &self.identifier,
@@ -806,7 +1298,7 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
         if !tree_has_value {
             return Ok(());
         }
-        leaf_entry.insert(InsertOrRemove::Remove);
+        self.modify_leaf(&key_bytes, InsertOrRemove::Remove);
 
         let mut iter = self.iter(db);
         iter.seek_to(key)?;
@@ -829,20 +1321,37 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                         new_path.push(*i);
                     }
                     last_binary_path = new_path.clone();
-                    let path: ByteVec = (&last_binary_path).into();
+                    let path = PathKey::node(&last_binary_path);
                     log::trace!(
                         "iter leaf= edge={edge:?}, new_path={new_path:?}",
                         // TrieKey::new(self.identifier.clone(), TrieKeyType::Trie, &path)
                     );
 
-                    self.death_row
-                        .insert(TrieKey::new(&self.identifier, TrieKeyType::Trie, &path));
-                    self.nodes.remove(*node_key);
+                    let key = TrieKey::new(&self.identifier, TrieKeyType::Trie, &path);
+                    self.add_to_death_row(key);
+                    self.remove_node(*node_key);
                     path_nodes.pop();
                 }
             }
         }
 
+        self.remove_binary_child(db, path_nodes, last_binary_path)
+    }
+
+    /// Replace the closest binary node of `path_nodes`, the nodes on the path to a removed subtree,
+    /// with an edge to its other child, merging it with the edges around it. The tree becomes empty
+    /// when there is no binary node.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_nodes` - The nodes on the path to the removed subtree, the last one is a binary node.
+    /// * `last_binary_path` - The path to the removed child of that binary node.
+    fn remove_binary_child<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        path_nodes: Vec<(NodeKey, usize)>,
+        mut last_binary_path: Path,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         let mut node_iter = path_nodes.into_iter().rev().peekable();
 
         let branch_node = node_iter.next();
@@ -860,7 +1369,8 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                     let binary = node
                         .as_binary()
                         .expect("The node must be a binary node due to the iteration condition");
-                    let (direction, height) = { (binary.direction(key).invert(), binary.height) };
+                    let (direction, height) =
+                        { (binary.direction(&last_binary_path).invert(), binary.height) };
                     last_binary_path.pop();
                     last_binary_path.push(bool::from(direction));
                     // Create an edge node to replace the old binary node
@@ -893,18 +1403,15 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
 
                         let mut par_path = par_path;
                         par_path.pop();
-                        let path: ByteVec = par_path.into();
-                        self.death_row.insert(TrieKey::new(
-                            &self.identifier,
-                            TrieKeyType::Trie,
-                            &path,
-                        ));
-                        self.nodes.remove(node_id);
+                        let path = PathKey::node(&par_path);
+                        let key = TrieKey::new(&self.identifier, TrieKeyType::Trie, &path);
+                        self.add_to_death_row(key);
+                        self.remove_node(node_id);
                     } else {
-                        self.nodes[node_id] = Node::Edge(new_edge);
+                        self.replace_node(node_id, Node::Edge(new_edge));
                     }
                 } else {
-                    self.nodes[node_id] = Node::Edge(new_edge);
+                    self.replace_node(node_id, Node::Edge(new_edge));
                 }
             }
             None => {
@@ -913,17 +1420,134 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
 
                 log::trace!("empty {:?}", self.root_node);
                 if let Some(RootHandle::Loaded(node_id)) = self.root_node {
-                    self.nodes.remove(node_id);
+                    self.remove_node(node_id);
                 }
-                self.death_row
-                    .insert(TrieKey::new(&self.identifier, TrieKeyType::Trie, &[0]));
-                self.root_node = Some(RootHandle::Empty);
+                self.add_to_death_row(TrieKey::new(&self.identifier, TrieKeyType::Trie, &[0]));
+                self.set_root(Some(RootHandle::Empty));
                 return Ok(());
             }
         };
         Ok(())
     }
 
+    /// Deletes many leaves from the tree. The nodes on their paths are loaded in a single
+    /// traversal, see [`MerkleTree::prefetch`], before the keys are removed in order.
+    pub fn remove_batch<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        self.prefetch(db, &keys)?;
+        for key in &keys {
+            self.delete_leaf(db, key.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Deletes all the leaves whose key starts with `prefix`, returning their keys. The subtree
+    /// holding them is detached at once: its nodes go to the death row and its leaves are removed
+    /// without restructuring the tree for each of them.
+    pub fn remove_prefix<DB: BonsaiDatabase, ID: Id>(
+        &mut self,
+        db: &KeyValueDB<DB, ID>,
+        prefix: &BitSlice,
+    ) -> Result<Vec<BitVec>, BonsaiStorageError<DB::DatabaseError>> {
+        if prefix.len() > self.max_height as usize {
+            return Err(BonsaiStorageError::KeyLength {
+                expected: self.max_height as _,
+                got: prefix.len(),
+            });
+        }
+        // Find the root of the subtree, and the nodes on the path to it.
+        let (path_nodes, subtree, subtree_path) = if prefix.is_empty() {
+            let Some(root) = self.load_root_node(db)? else {
+                return Ok(Vec::new());
+            };
+            (Vec::new(), NodeHandle::InMemory(root), BitVec::new())
+        } else {
+            let mut iter = self.iter(db);
+            iter.seek_to(prefix)?;
+            let path_nodes = iter.current_nodes_heights;
+            let Some(&(node_id, height)) = path_nodes.last() else {
+                return Ok(Vec::new());
+            };
+            match self.get_node_mut::<DB>(node_id)? {
+                // The subtree is a child of the binary node.
+                Node::Binary(binary) => {
+                    let child = binary.get_child(binary.direction(prefix));
+                    (path_nodes, child, prefix.to_bitvec())
+                }
+                // The edge leads to the subtree, or goes past the prefix.
+                Node::Edge(edge) => {
+                    if !edge.path_matches(prefix, height) {
+                        return Ok(Vec::new());
+                    }
+                    let mut path_nodes = path_nodes;
+                    path_nodes.pop();
+                    (
+                        path_nodes,
+                        NodeHandle::InMemory(node_id),
+                        prefix[..height].to_bitvec(),
+                    )
+                }
+            }
+        };
+
+        let mut removed = Vec::new();
+        let mut stack = vec![(subtree, subtree_path.clone())];
+        while let Some((handle, path)) = stack.pop() {
+            let node = match handle {
+                NodeHandle::Hash(_) if path.len() == self.max_height as usize => {
+                    self.modify_leaf(&PathKey::leaf(&path), InsertOrRemove::Remove);
+                    removed.push(path);
+                    continue;
+                }
+                NodeHandle::Hash(_) => Self::get_trie_branch_in_db_from_path(
+                    &self.death_row,
+                    &self.identifier,
+                    self.inline_nodes.as_ref(),
+                    db,
+                    &Path(path.clone()),
+                    self.max_height,
+                )?
+                .ok_or_else(|| BonsaiStorageError::NodeNotFound {
+                    identifier: self.identifier.clone(),
+                    path: Path(path.clone()),
+                })?,
+                NodeHandle::InMemory(node_key) => {
+                    let node = self.get_node_mut::<DB>(node_key)?.clone();
+                    self.remove_node(node_key);
+                    node
+                }
+            };
+            self.add_to_death_row(TrieKey::new(
+                &self.identifier,
+                TrieKeyType::Trie,
+                &PathKey::node(&path),
+            ));
+            match node {
+                Node::Binary(binary) => {
+                    let mut right = path.clone();
+                    right.push(true);
+                    stack.push((binary.right, right));
+                    let mut left = path;
+                    left.push(false);
+                    stack.push((binary.left, left));
+                }
+                Node::Edge(edge) => {
+                    let mut child = path;
+                    child.extend_from_bitslice(&edge.path);
+                    stack.push((edge.child, child));
+                }
+            }
+        }
+
+        self.remove_binary_child(db, path_nodes, Path(subtree_path))?;
+        Ok(removed)
+    }
+
     /// Returns the value stored at key, or `None` if it does not exist.
     ///
     /// # Arguments
@@ -932,16 +1556,24 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     ///
     /// # Returns
     ///
-    /// The value of the key.
+    /// The value of the key, which is the default value of a sparse tree if the key is missing.
     pub fn get<DB: BonsaiDatabase, ID: Id>(
         &self,
         db: &KeyValueDB<DB, ID>,
         key: &BitSlice,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        Ok(self.get_stored(db, key)?.or(self.hasher.default_value()))
+    }
+
+    fn get_stored<DB: BonsaiDatabase, ID: Id>(
+        &self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
     ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
         log::trace!("get with key {:b}", key);
-        let key = bitslice_to_bytes(key);
+        let key = PathKey::leaf(key);
         log::trace!("get from cache with {:?}", key);
-        if let Some(value) = self.cache_leaf_modified.get(&key) {
+        if let Some(value) = self.cache_leaf_modified.get(key.as_slice()) {
             log::trace!("get has cache_leaf_modified {:?} {:?}", key, value);
             match value {
                 InsertOrRemove::Remove => return Ok(None),
@@ -956,24 +1588,96 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
             .map(|r| r.map(|opt| Felt::decode(&mut opt.as_slice()).unwrap()))
     }
 
+    /// Same as `get` for many keys, the leaves which are not modified in memory are read from the
+    /// database with a single batched read.
+    pub fn get_many<DB: BonsaiDatabase, ID: Id>(
+        &self,
+        db: &KeyValueDB<DB, ID>,
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<Vec<Option<Felt>>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut values = Vec::new();
+        let mut db_indices = Vec::new();
+        let mut db_keys = Vec::new();
+        for key in keys {
+            let key = PathKey::leaf(key.as_ref());
+            match self.cache_leaf_modified.get(key.as_slice()) {
+                Some(InsertOrRemove::Remove) => values.push(None),
+                Some(InsertOrRemove::Insert(value)) => values.push(Some(*value)),
+                None => {
+                    db_indices.push(values.len());
+                    db_keys.push(TrieKey::new(&self.identifier, TrieKeyType::Flat, &key));
+                    values.push(None);
+                }
+            }
+        }
+        for (i, value) in db_indices.into_iter().zip(db.get_many(&db_keys)?) {
+            values[i] = value.map(|value| Felt::decode(&mut value.as_slice()).unwrap());
+        }
+        if let Some(default) = self.hasher.default_value() {
+            for value in &mut values {
+                value.get_or_insert(default);
+            }
+        }
+        Ok(values)
+    }
+
+    /// The raw payload of the key, set with [`MerkleTree::set_raw`]. It is empty for the keys set
+    /// without one.
+    pub fn get_raw<DB: BonsaiDatabase, ID: Id>(
+        &self,
+        db: &KeyValueDB<DB, ID>,
+        key: &BitSlice,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = PathKey::leaf(key);
+        match self.cache_leaf_modified.get(key.as_slice()) {
+            Some(InsertOrRemove::Remove) => return Ok(None),
+            Some(InsertOrRemove::Insert(_)) => {
+                return Ok(Some(
+                    self.cache_raw_modified
+                        .get(key.as_slice())
+                        .cloned()
+                        .unwrap_or_default(),
+                ))
+            }
+            None => {}
+        }
+        let key = TrieKey::new(&self.identifier, TrieKeyType::Flat, &key);
+        let Some(value) = db.get(&key)? else {
+            return Ok(None);
+        };
+        let (_, raw) = decode_leaf(&value).map_err(|source| BonsaiStorageError::DecodeError {
+            key: key.as_slice().into(),
+            source,
+        })?;
+        Ok(Some(raw.into()))
+    }
+
     pub fn get_at<DB: BonsaiDatabase, ID: Id>(
         &self,
         db: &KeyValueDB<DB, ID>,
         key: &BitSlice,
         id: ID,
     ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
-        let key = bitslice_to_bytes(key);
-        db.get_at(&TrieKey::new(&self.identifier, TrieKeyType::Flat, &key), id)
-            .map(|r| r.map(|opt| Felt::decode(&mut opt.as_slice()).unwrap()))
+        let key = PathKey::leaf(key);
+        let value = db
+            .get_at(
+                &self.identifier,
+                &TrieKey::new(&self.identifier, TrieKeyType::Flat, &key),
+                id,
+            )?
+            .map(|value| Felt::decode(&mut value.as_slice()).unwrap());
+        Ok(value.or(self.hasher.default_value()))
     }
 
+    /// Whether the key is in the tree. The keys of a sparse tree which have the default value are
+    /// not.
     pub fn contains<DB: BonsaiDatabase, ID: Id>(
         &self,
         db: &KeyValueDB<DB, ID>,
         key: &BitSlice,
     ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
-        let key = bitslice_to_bytes(key);
-        if let Some(value) = self.cache_leaf_modified.get(&key) {
+        let key = PathKey::leaf(key);
+        if let Some(value) = self.cache_leaf_modified.get(key.as_slice()) {
             match value {
                 InsertOrRemove::Remove => return Ok(false),
                 InsertOrRemove::Insert(_) => return Ok(true),
@@ -986,26 +1690,29 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     fn get_trie_branch_in_db_from_path<DB: BonsaiDatabase, ID: Id>(
         death_row: &HashSet<TrieKey>,
         identifier: &[u8],
+        inline_nodes: Option<&HashMap<TrieKey, ByteVec>>,
         db: &KeyValueDB<DB, ID>,
         path: &Path,
+        max_height: u8,
     ) -> Result<Option<Node>, BonsaiStorageError<DB::DatabaseError>> {
         log::trace!("getting: {:b}", path.0);
 
-        let path: ByteVec = path.into();
-        let key = TrieKey::new(identifier, TrieKeyType::Trie, &path);
+        let key = TrieKey::new(identifier, TrieKeyType::Trie, &PathKey::node(path));
 
         if death_row.contains(&key) {
             return Ok(None);
         }
 
-        db.get(&key)?
-            .map(|node| {
-                log::trace!("got: {:?}", node);
-                Node::decode(&mut node.as_slice()).map_err(|err| {
-                    BonsaiStorageError::Trie(format!("Couldn't decode node: {}", err))
-                })
-            })
-            .map_or(Ok(None), |r| r.map(Some))
+        let node = match inline_nodes {
+            Some(nodes) => nodes.get(&key).cloned(),
+            None => db.get(&key)?,
+        };
+        node.map(|node| {
+            log::trace!("got: {:?}", node);
+            db.stats.node_decoded();
+            decode_node(identifier, path, &key, &node, max_height)
+        })
+        .map_or(Ok(None), |r| r.map(Some))
     }
 
     /// This is a convenience function which merges the edge node with its child __iff__ it is also
@@ -1030,18 +1737,19 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                 let node = Self::get_trie_branch_in_db_from_path(
                     &self.death_row,
                     &self.identifier,
+                    self.inline_nodes.as_ref(),
                     db,
                     path,
+                    self.max_height,
                 )?;
                 log::trace!("case: Hash {:?}", node);
                 if let Some(Node::Edge(child_edge)) = node {
                     parent.path.0.extend_from_bitslice(&child_edge.path.0);
                     parent.child = child_edge.child;
                     // remove node from db
-                    let path: ByteVec = path.into();
+                    let path = PathKey::node(path);
                     log::trace!("4 death row {:?}", path);
-                    self.death_row
-                        .insert(TrieKey::new(&self.identifier, TrieKeyType::Trie, &path));
+                    self.add_to_death_row(TrieKey::new(&self.identifier, TrieKeyType::Trie, &path));
                 }
             }
             NodeHandle::InMemory(child_id) => {
@@ -1052,12 +1760,11 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
                     parent.path.0.extend_from_bitslice(&child_edge.path.0);
                     parent.child = child_edge.child;
 
-                    self.nodes.remove(child_id);
+                    self.remove_node(child_id);
 
-                    let path: ByteVec = path.into();
+                    let path = PathKey::node(path);
                     log::trace!("3 death row {:?}", path);
-                    self.death_row
-                        .insert(TrieKey::new(&self.identifier, TrieKeyType::Trie, &path));
+                    self.add_to_death_row(TrieKey::new(&self.identifier, TrieKeyType::Trie, &path));
                 }
             }
         };
@@ -1118,16 +1825,188 @@ impl<H: StarkHash + Send + Sync> MerkleTree<H> {
     }
 }
 
+/// Below this number of leaves, hashing a subtree is cheaper than handing it to another thread.
+#[cfg(feature = "std")]
+const PARALLEL_ROOT_MIN_LEAVES: usize = 64;
+
+/// Root hash of the trie containing `leaves`, built purely in memory without a database or commit
+/// IDs. This is meant for one-shot commitments, such as the transaction, receipt and event tries of
+/// a block.
+///
+/// Leaves can be given in any order. When a key appears more than once, the last value wins, and
+/// zero values are treated as removals, like with [`crate::BonsaiStorage::insert`].
+///
+/// # Panics
+///
+/// Panics if the keys don't all have the same length, which is the height of the trie.
+pub fn compute_root<H: StarkHash + Send + Sync>(
+    leaves: impl IntoIterator<Item = (BitVec, Felt)>,
+) -> Felt {
+    let mut leaves: Vec<_> = leaves.into_iter().collect();
+    // The sort is stable, so the last value of a key is the last one of its run.
+    leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut sorted: Vec<(&BitSlice, Felt)> = Vec::with_capacity(leaves.len());
+    for (i, (key, value)) in leaves.iter().enumerate() {
+        if leaves.get(i + 1).is_some_and(|(next, _)| next == key) || *value == Felt::ZERO {
+            continue;
+        }
+        if let Some((first, _)) = sorted.first() {
+            assert_eq!(
+                first.len(),
+                key.len(),
+                "all the keys of a trie must have the same length"
+            );
+        }
+        sorted.push((key, *value));
+    }
+    MerkleTree::<H>::root_from_sorted_leaves(&sorted)
+}
+
+/// Root hash of the trie of height `max_height` containing `leaves`, whose nodes are hashed by
+/// `hasher`. Leaves must be sorted by key, without duplicates nor removed values.
+#[cfg(feature = "audit")]
+pub(crate) fn root_with_hasher(
+    hasher: &TrieHasher,
+    leaves: &[(&BitSlice, Felt)],
+    max_height: u8,
+) -> Felt {
+    /// Hash of the subtree containing `leaves`, which all share the same first `depth` bits.
+    fn subtree_root(
+        hasher: &TrieHasher,
+        leaves: &[(&BitSlice, Felt)],
+        depth: usize,
+        max_height: u8,
+    ) -> Felt {
+        let (Some((first, value)), Some((last, _))) = (leaves.first(), leaves.last()) else {
+            return hasher.empty_root(max_height);
+        };
+        if depth == first.len() {
+            return *value;
+        }
+        let common_len = first[depth..]
+            .iter()
+            .zip(last[depth..].iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common_len > 0 {
+            let path = Path(first[depth..depth + common_len].to_bitvec());
+            let child_hash = subtree_root(hasher, leaves, depth + common_len, max_height);
+            return hasher.hash_edge_node(&path, child_hash, depth, max_height);
+        }
+        let (left, right) = leaves.split_at(leaves.partition_point(|(key, _)| !key[depth]));
+        hasher.hash_binary_node(
+            subtree_root(hasher, left, depth + 1, max_height),
+            subtree_root(hasher, right, depth + 1, max_height),
+        )
+    }
+    subtree_root(hasher, leaves, 0, max_height)
+}
+
+/// `height` as the height of a node in a trie of height `max_height`.
+pub(crate) fn node_height<E: DBError>(
+    height: usize,
+    max_height: u8,
+) -> Result<Height, BonsaiStorageError<E>> {
+    Height::new(height, max_height).ok_or(BonsaiStorageError::HeightOverflow { height, max_height })
+}
+
+/// Decode the node at `path` of the trie `identifier`, stored at `key`, in a trie of height
+/// `max_height`.
+pub(crate) fn decode_node<E: DBError>(
+    identifier: &[u8],
+    path: &BitSlice,
+    key: &TrieKey,
+    bytes: &[u8],
+    max_height: u8,
+) -> Result<Node, BonsaiStorageError<E>> {
+    Node::decode_at(bytes, path.len(), max_height)
+        .map(|(node, _)| node)
+        .map_err(|source| corrupted_node(identifier, path, key, source))
+}
+
+/// Error for the node at `path` of the trie `identifier`, stored at `key`, which could not be
+/// decoded.
+pub(crate) fn corrupted_node<E: DBError>(
+    identifier: &[u8],
+    path: &BitSlice,
+    key: &TrieKey,
+    source: parity_scale_codec::Error,
+) -> BonsaiStorageError<E> {
+    BonsaiStorageError::CorruptedNode(Box::new(CorruptedNode {
+        identifier: identifier.into(),
+        path: Path(path.to_bitvec()),
+        key_bytes: key.as_slice().into(),
+        source,
+    }))
+}
+
+/// Key under which the undecodable node stored at `node_key` is moved, see
+/// [`crate::BonsaiStorageConfig::quarantine_corrupted_nodes`].
+pub(crate) fn quarantine_key(node_key: &[u8]) -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::Quarantine, node_key)
+}
+
 pub(crate) fn bitslice_to_bytes(bitslice: &BitSlice) -> ByteVec {
-    // TODO(perf): this should not copy to a bitvec :(
-    if bitslice.is_empty() {
-        return Default::default();
-    } // special case: tree root
-    iter::once(bitslice.len() as u8)
-        .chain(bitslice.to_bitvec().as_raw_slice().iter().copied())
-        .collect()
+    PathKey::leaf(bitslice).as_slice().into()
 }
 
 pub(crate) fn bytes_to_bitvec(bytes: &[u8]) -> BitVec {
-    BitSlice::from_slice(&bytes[1..]).to_bitvec()
+    let mut bitvec = BitSlice::from_slice(&bytes[1..]).to_bitvec();
+    bitvec.truncate(bytes[0] as usize);
+    bitvec
+}
+
+/// Trie node keys start with the bit length of their path, which is lower than the maximum height
+/// of the tree, so this can't collide with a node key.
+const LEAF_COUNT_KEY: u8 = u8::MAX;
+
+/// Key of the persisted number of leaves of the trie `identifier`. It is stored with the trie nodes
+/// so that its changes are recorded in the trie logs.
+pub(crate) fn leaf_count_key(identifier: &[u8]) -> TrieKey {
+    TrieKey::new(identifier, TrieKeyType::Trie, &[LEAF_COUNT_KEY])
+}
+
+/// Split a flat database key into the trie identifier and the leaf key. Leaf keys are always
+/// `max_height` bits long, which is how the two can be told apart.
+pub(crate) fn split_flat_key(key: &[u8], max_height: u8) -> (&[u8], BitVec) {
+    let leaf_key_len = 1 + (max_height as usize).div_ceil(8);
+    let (identifier, leaf_key) = key.split_at(key.len().saturating_sub(leaf_key_len));
+    (identifier, bytes_to_bitvec(leaf_key))
+}
+
+/// Key of the persisted disk usage of the trie `identifier`, see
+/// [`crate::BonsaiStorage::disk_usage`].
+pub(crate) fn disk_usage_key(identifier: &[u8]) -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::DiskUsage, identifier)
+}
+
+/// Key of the height the trie `identifier` is committed with.
+pub(crate) fn trie_height_key(identifier: &[u8]) -> TrieKey {
+    TrieKey::new_meta(MetaKeyType::TrieHeight, identifier)
+}
+
+/// Encoding of a leaf in the flat storage: its value, followed by its raw payload if it has one,
+/// see [`MerkleTree::set_raw`]. The value is read by decoding the leaf as a [`Felt`].
+pub(crate) fn encode_leaf(value: &Felt, raw: Option<&[u8]>) -> ByteVec {
+    let mut bytes = value.encode_bytevec();
+    if let Some(raw) = raw {
+        bytes.extend_from_slice(raw);
+    }
+    bytes
+}
+
+/// The value and the raw payload of a leaf stored in the flat storage, see [`encode_leaf`].
+pub(crate) fn decode_leaf(bytes: &[u8]) -> Result<(Felt, &[u8]), parity_scale_codec::Error> {
+    let mut raw = bytes;
+    let value = Felt::decode(&mut raw)?;
+    Ok((value, raw))
+}
+
+/// Whether `key`, from the trie node column, is the key of a node of the trie `identifier`: the
+/// path that follows the identifier starts with its bit length.
+pub(crate) fn is_node_key(key: &[u8], identifier: &[u8]) -> bool {
+    key.strip_prefix(identifier).is_some_and(|path| {
+        path.first()
+            .is_some_and(|len| path.len() == 1 + (*len as usize).div_ceil(8))
+    })
 }
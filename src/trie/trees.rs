@@ -1,15 +1,173 @@
-use super::{proof::MultiProof, tree::MerkleTree};
+use super::{
+    builder::IncrementalTrieBuilder,
+    inline::{build_nodes, inline_trie_key, inline_updates, InlineTrie},
+    iterator::TrieCursor,
+    leaves::CommittedLeaves,
+    merkle_node::{Node, TrieHasher, NODE_ENCODING_VERSION},
+    path::Path,
+    proof::{MultiProof, ProvenTrie},
+    tree::{
+        bitslice_to_bytes, bytes_to_bitvec, corrupted_node, decode_leaf, decode_node,
+        disk_usage_key, is_node_key, leaf_count_key, quarantine_key, split_flat_key,
+        trie_height_key, MerkleTree,
+    },
+    trie_db::TrieKeyType,
+    TrieKey,
+};
 use crate::{
-    id::Id, key_value_db::KeyValueDB, trie::tree::InsertOrRemove, BitSlice, BonsaiDatabase,
-    BonsaiStorageError, ByteVec, HashMap, Vec,
+    cancellation::CancellationToken,
+    changes::{trie_log_usage, Change},
+    databases::ForkDb,
+    format,
+    id::Id,
+    key_value_db::{KeyValueDB, KeyValueDBConfig},
+    trie::tree::InsertOrRemove,
+    BitSlice, BitVec, BonsaiDatabase, BonsaiStorageError, ByteVec, Cow, DBError, DatabaseKey,
+    DiskUsage, EncodeExt, HashMap, HashSet, LeafChange, PendingStats, ShardError, Vec,
 };
 use core::fmt;
+use parity_scale_codec::Decode;
 use starknet_types_core::{felt::Felt, hash::StarkHash};
 
+/// Hasher of the trie `identifier`, `H` unless another one is configured for it.
+pub(crate) fn trie_hasher<H: StarkHash>(
+    config: &KeyValueDBConfig,
+    identifier: &[u8],
+) -> TrieHasher {
+    config
+        .trie_hashers
+        .get(identifier)
+        .cloned()
+        .unwrap_or_else(TrieHasher::new::<H>)
+}
+
+/// Empty in-memory tree of the trie `identifier`, before any node is loaded.
+fn new_tree<H: StarkHash + Send + Sync>(
+    config: &KeyValueDBConfig,
+    identifier: &[u8],
+    max_height: u8,
+) -> MerkleTree<H> {
+    MerkleTree::with_hasher(
+        identifier.into(),
+        max_height,
+        trie_hasher::<H>(config, identifier),
+    )
+    .store_zero_values(config.store_zero_values)
+}
+
+/// Root hash of the trie `identifier` of height `max_height` whose root node is `node`, stored at
+/// `key`, `None` without one.
+fn stored_root_hash<E: DBError>(
+    identifier: &[u8],
+    key: &TrieKey,
+    node: &Option<ByteVec>,
+    max_height: u8,
+) -> Result<Option<Felt>, BonsaiStorageError<E>> {
+    let Some(node) = node else {
+        return Ok(None);
+    };
+    let node = decode_node(identifier, BitSlice::empty(), key, node, max_height)?;
+    Ok(Some(
+        node.get_hash()
+            .expect("The stored node has no computed hash"),
+    ))
+}
+
+/// Root hash of a trie stored inline whose record is `record`, stored at `key`, `None` without
+/// one.
+fn inline_root_hash<E: DBError>(
+    key: &TrieKey,
+    record: &Option<ByteVec>,
+) -> Result<Option<Felt>, BonsaiStorageError<E>> {
+    let Some(record) = record else {
+        return Ok(None);
+    };
+    let record = InlineTrie::decode(record).map_err(|source| BonsaiStorageError::DecodeError {
+        key: key.as_slice().into(),
+        source,
+    })?;
+    Ok(Some(record.root_hash))
+}
+
+/// Identifier of a savepoint of the uncommitted changes, see [`crate::BonsaiStorage::savepoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SavepointId(u64);
+
+/// Lengths of the undo logs when a savepoint was taken, the changes recorded after them are
+/// undone by a rollback.
+#[derive(Debug, Clone)]
+pub(crate) struct Savepoint {
+    id: SavepointId,
+    /// Undo log length of each tree, the trees created after the savepoint are missing.
+    trees: HashMap<ByteVec, usize>,
+    meta: usize,
+}
+
 pub(crate) struct MerkleTrees<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> {
     pub db: KeyValueDB<DB, CommitID>,
     pub trees: HashMap<ByteVec, MerkleTree<H>>,
     pub max_height: u8,
+    /// Uncommitted metadata writes, `None` marks a removed key.
+    pub meta: HashMap<ByteVec, Option<ByteVec>>,
+    /// Previous values of the `meta` entries written while a savepoint exists.
+    pub meta_undo_log: Vec<(ByteVec, Option<Option<ByteVec>>)>,
+    /// Open savepoints, oldest first.
+    pub savepoints: Vec<Savepoint>,
+    /// Never reused, so that a savepoint discarded by a commit cannot be mistaken for a new one.
+    pub next_savepoint_id: u64,
+    /// Incremented whenever `trees` changes, so that a [`PreparedCommit`] can tell whether it is
+    /// still up to date.
+    pub generation: u64,
+    /// Tries checked out by a [`crate::TrieShard`], which cannot be modified through the storage
+    /// until the shard is dropped.
+    pub sharded: HashSet<ByteVec>,
+    /// Tries known only by their root hash and proof nodes, instead of the database, see
+    /// [`crate::BonsaiStorage::set_proven_root`].
+    pub proven: HashMap<ByteVec, ProvenTrie>,
+    /// Tries written since the last [`MerkleTrees::audit`].
+    #[cfg(feature = "audit")]
+    pub audited: Vec<ByteVec>,
+}
+
+/// Number of leaves written by [`MerkleTrees::bulk_load`] between two batches.
+const BULK_LOAD_BATCH_LEAVES: usize = 100_000;
+
+/// Number of hashed trees waiting to be written during a commit, see
+/// [`MerkleTrees::write_trees_pipelined`].
+#[cfg(feature = "std")]
+const COMMIT_PIPELINE_DEPTH: usize = 4;
+
+/// Database updates of a trie or of the metadata.
+pub(crate) type Updates = Vec<(TrieKey, InsertOrRemove<ByteVec>)>;
+
+/// Bytes of the trie nodes and of the leaves of a trie, see [`crate::DiskUsage`].
+type TrieUsage = (u64, u64);
+
+/// The database updates of a commit, computed from `&self` so that the tries can still be read
+/// while the hashes are computed, see [`crate::BonsaiStorage::prepare_commit`].
+pub struct PreparedCommit {
+    generation: u64,
+    tree_updates: Vec<(ByteVec, Updates)>,
+    meta_updates: Updates,
+    leaf_counts: Vec<(ByteVec, u64)>,
+    disk_usages: Vec<(ByteVec, Option<TrieUsage>)>,
+}
+
+impl fmt::Debug for PreparedCommit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedCommit")
+            .field("generation", &self.generation)
+            .field(
+                "updates",
+                &(self
+                    .tree_updates
+                    .iter()
+                    .map(|(_, updates)| updates.len())
+                    .sum::<usize>()
+                    + self.meta_updates.len()),
+            )
+            .finish()
+    }
 }
 
 impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase + fmt::Debug, CommitID: Id> fmt::Debug
@@ -32,6 +190,15 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase + Clone, CommitID: Id> Clone
             db: self.db.clone(),
             trees: self.trees.clone(),
             max_height: self.max_height,
+            meta: self.meta.clone(),
+            meta_undo_log: self.meta_undo_log.clone(),
+            savepoints: self.savepoints.clone(),
+            next_savepoint_id: self.next_savepoint_id,
+            generation: self.generation,
+            sharded: self.sharded.clone(),
+            proven: self.proven.clone(),
+            #[cfg(feature = "audit")]
+            audited: Vec::new(),
         }
     }
 }
@@ -42,21 +209,122 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
             db,
             trees: HashMap::new(),
             max_height: tree_height,
+            meta: HashMap::new(),
+            meta_undo_log: Vec::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+            generation: 0,
+            sharded: HashSet::new(),
+            proven: HashMap::new(),
+            #[cfg(feature = "audit")]
+            audited: Vec::new(),
         }
     }
 
+    /// The trie `identifier` to write to, created if missing, with the database to read it from.
+    #[allow(clippy::type_complexity)]
+    fn tree_mut(
+        &mut self,
+        identifier: &[u8],
+    ) -> Result<
+        (&mut MerkleTree<H>, &KeyValueDB<DB, CommitID>),
+        BonsaiStorageError<DB::DatabaseError>,
+    > {
+        self.check_not_sharded(identifier)?;
+        self.check_not_proven(identifier)?;
+        if !self.trees.contains_key(identifier) {
+            self.check_trie_height(identifier)?;
+        }
+        let tree = self
+            .trees
+            .entry_ref(identifier)
+            .or_insert_with(|| new_tree(&self.db.config, identifier, self.max_height));
+
+        self.generation += 1;
+        Ok((tree, &self.db))
+    }
+
     pub(crate) fn set(
         &mut self,
         identifier: &[u8],
         key: &BitSlice,
         value: Felt,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        let tree = self
-            .trees
-            .entry_ref(identifier)
-            .or_insert_with(|| MerkleTree::new(identifier.into(), self.max_height));
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.set(db, key, value)
+    }
+
+    pub(crate) fn remove(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.remove(db, key)
+    }
+
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        f: impl FnOnce() -> Felt,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.get_or_insert_with(db, key, f)
+    }
+
+    pub(crate) fn remove_batch(
+        &mut self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.remove_batch(db, keys)
+    }
+
+    pub(crate) fn remove_prefix(
+        &mut self,
+        identifier: &[u8],
+        prefix: &BitSlice,
+    ) -> Result<Vec<BitVec>, BonsaiStorageError<DB::DatabaseError>> {
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.remove_prefix(db, prefix)
+    }
+
+    pub(crate) fn set_raw(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        value: Felt,
+        raw: &[u8],
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let (tree, db) = self.tree_mut(identifier)?;
+        tree.set_raw(db, key, value, raw)
+    }
 
-        tree.set(&self.db, key, value)
+    /// Set a leaf from its bytes in the flat storage, see [`super::tree::encode_leaf`], or remove it with
+    /// `None`.
+    pub(crate) fn set_stored(
+        &mut self,
+        identifier: &[u8],
+        key: &BitSlice,
+        stored: Option<&[u8]>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let Some(stored) = stored else {
+            return self.remove(identifier, key);
+        };
+        let (value, raw) =
+            decode_leaf(stored).map_err(|source| BonsaiStorageError::DecodeError {
+                key: TrieKey::new(identifier, TrieKeyType::Flat, &bitslice_to_bytes(key))
+                    .as_slice()
+                    .into(),
+                source,
+            })?;
+        if raw.is_empty() {
+            self.set(identifier, key, value)
+        } else {
+            self.set_raw(identifier, key, value, raw)
+        }
     }
 
     pub(crate) fn get(
@@ -64,10 +332,53 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
         identifier: &[u8],
         key: &BitSlice,
     ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(proven) = self.proven.get(identifier) {
+            let hasher = trie_hasher::<H>(&self.db.config, identifier);
+            return proven
+                .get(&hasher, key, self.max_height)
+                .map(|value| value.or(hasher.default_value()))
+                .map_err(|err| BonsaiStorageError::Proof(err.into()));
+        }
         if let Some(tree) = self.trees.get(identifier) {
             tree.get(&self.db, key)
         } else {
-            MerkleTree::<H>::new(identifier.into(), self.max_height).get(&self.db, key)
+            new_tree::<H>(&self.db.config, identifier, self.max_height).get(&self.db, key)
+        }
+    }
+
+    pub(crate) fn get_raw(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(tree) = self.trees.get(identifier) {
+            tree.get_raw(&self.db, key)
+        } else {
+            new_tree::<H>(&self.db.config, identifier, self.max_height).get_raw(&self.db, key)
+        }
+    }
+
+    pub(crate) fn get_many(
+        &self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<Vec<Option<Felt>>, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(proven) = self.proven.get(identifier) {
+            let hasher = trie_hasher::<H>(&self.db.config, identifier);
+            return keys
+                .into_iter()
+                .map(|key| {
+                    proven
+                        .get(&hasher, key.as_ref(), self.max_height)
+                        .map(|value| value.or(hasher.default_value()))
+                        .map_err(|err| BonsaiStorageError::Proof(err.into()))
+                })
+                .collect();
+        }
+        if let Some(tree) = self.trees.get(identifier) {
+            tree.get_many(&self.db, keys)
+        } else {
+            new_tree::<H>(&self.db.config, identifier, self.max_height).get_many(&self.db, keys)
         }
     }
 
@@ -80,7 +391,35 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
         if let Some(tree) = self.trees.get(identifier) {
             tree.get_at(&self.db, key, id)
         } else {
-            MerkleTree::<H>::new(identifier.into(), self.max_height).get_at(&self.db, key, id)
+            new_tree::<H>(&self.db.config, identifier, self.max_height).get_at(&self.db, key, id)
+        }
+    }
+
+    /// Set or, with `None`, remove the metadata stored at `key`. It is written on the next commit.
+    pub(crate) fn set_meta(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.generation += 1;
+        let previous = self.meta.insert(key.into(), value.map(ByteVec::from));
+        if !self.savepoints.is_empty() {
+            self.meta_undo_log.push((key.into(), previous));
+        }
+    }
+
+    /// Drop the uncommitted change of the metadata `key`, if any.
+    pub(crate) fn unset_meta(&mut self, key: &[u8]) {
+        self.generation += 1;
+        let previous = self.meta.remove(key);
+        if !self.savepoints.is_empty() {
+            self.meta_undo_log.push((key.into(), previous));
+        }
+    }
+
+    pub(crate) fn get_meta(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        match self.meta.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.db.get(&TrieKey::Meta(key.into())),
         }
     }
 
@@ -89,10 +428,188 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
         identifier: &[u8],
         key: &BitSlice,
     ) -> Result<bool, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(proven) = self.proven.get(identifier) {
+            let hasher = trie_hasher::<H>(&self.db.config, identifier);
+            return proven
+                .get(&hasher, key, self.max_height)
+                .map(|value| value.is_some())
+                .map_err(|err| BonsaiStorageError::Proof(err.into()));
+        }
         if let Some(tree) = self.trees.get(identifier) {
             tree.contains(&self.db, key)
         } else {
-            MerkleTree::<H>::new(identifier.into(), self.max_height).contains(&self.db, key)
+            new_tree::<H>(&self.db.config, identifier, self.max_height).contains(&self.db, key)
+        }
+    }
+
+    /// Copy-on-write copy of the tries, see [`crate::BonsaiStorage::fork`]. Savepoints are not
+    /// carried over.
+    pub(crate) fn fork(&self) -> MerkleTrees<H, ForkDb<'_, DB>, CommitID> {
+        MerkleTrees::<H, ForkDb<'_, DB>, CommitID> {
+            db: self.db.fork(),
+            trees: self
+                .trees
+                .iter()
+                .map(|(identifier, tree)| {
+                    let mut tree = tree.clone();
+                    tree.undo_log = None;
+                    (identifier.clone(), tree)
+                })
+                .collect(),
+            max_height: self.max_height,
+            meta: self.meta.clone(),
+            meta_undo_log: Vec::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+            generation: 0,
+            sharded: self.sharded.clone(),
+            proven: self.proven.clone(),
+            #[cfg(feature = "audit")]
+            audited: Vec::new(),
+        }
+    }
+
+    pub(crate) fn has_uncommitted_changes(&self) -> bool {
+        !self.meta.is_empty() || self.trees.values().any(MerkleTree::has_uncommitted_changes)
+    }
+
+    /// Identifiers of the tries with uncommitted changes.
+    pub(crate) fn changed_tries(&self) -> impl Iterator<Item = &ByteVec> {
+        self.trees
+            .iter()
+            .filter(|(_, tree)| tree.has_uncommitted_changes())
+            .map(|(identifier, _)| identifier)
+    }
+
+    pub(crate) fn pending_stats(&self) -> HashMap<ByteVec, PendingStats> {
+        self.trees
+            .iter()
+            .map(|(identifier, tree)| (identifier.clone(), tree.pending_stats()))
+            .filter(|(_, stats)| *stats != PendingStats::default())
+            .collect()
+    }
+
+    fn check_not_sharded(
+        &self,
+        identifier: &[u8],
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if self.sharded.contains(identifier) {
+            return Err(BonsaiStorageError::Shard(ShardError::CheckedOut {
+                identifier: identifier.into(),
+            }));
+        }
+        Ok(())
+    }
+
+    fn check_not_proven(
+        &self,
+        identifier: &[u8],
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if self.proven.contains_key(identifier) {
+            return Err(BonsaiStorageError::ProvenTrieReadOnly {
+                identifier: identifier.into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// See [`crate::BonsaiStorage::set_proven_root`].
+    pub(crate) fn set_proven_root(
+        &mut self,
+        identifier: &[u8],
+        root: Felt,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_not_sharded(identifier)?;
+        if self
+            .trees
+            .get(identifier)
+            .is_some_and(MerkleTree::has_uncommitted_changes)
+        {
+            return Err(BonsaiStorageError::TrieUncommittedChanges {
+                identifier: identifier.into(),
+            });
+        }
+        if self.proven.get(identifier).map(ProvenTrie::root) != Some(root) {
+            self.trees.remove(identifier);
+            self.generation += 1;
+            self.proven.insert(identifier.into(), ProvenTrie::new(root));
+        }
+        Ok(())
+    }
+
+    /// See [`crate::BonsaiStorage::insert_proof_nodes`].
+    pub(crate) fn insert_proof_nodes(
+        &mut self,
+        identifier: &[u8],
+        proof: MultiProof,
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        let hasher = trie_hasher::<H>(&self.db.config, identifier);
+        let Some(proven) = self.proven.get_mut(identifier) else {
+            return Err(BonsaiStorageError::NoProvenRoot {
+                identifier: identifier.into(),
+            });
+        };
+        proven
+            .insert(&hasher, proof, self.max_height)
+            .map_err(|err| BonsaiStorageError::Proof(err.into()))
+    }
+
+    /// Root hash of the trie `identifier` without any leaf.
+    pub(crate) fn empty_root(&self, identifier: &[u8]) -> Felt {
+        trie_hasher::<H>(&self.db.config, identifier).empty_root(self.max_height)
+    }
+
+    /// Fails while shards are open, for the operations which could rewrite their tries.
+    pub(crate) fn check_no_shards<E: DBError>(&self) -> Result<(), BonsaiStorageError<E>> {
+        if !self.sharded.is_empty() {
+            return Err(BonsaiStorageError::Shard(ShardError::OpenShards {
+                count: self.sharded.len(),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Check out the tries `identifiers` for a [`crate::TrieShard`], which gets them as empty trees
+    /// loading their nodes from the database. They must have no uncommitted changes.
+    #[cfg(feature = "std")]
+    pub(crate) fn open_shard(
+        &mut self,
+        identifiers: &[&[u8]],
+    ) -> Result<HashMap<ByteVec, MerkleTree<H>>, BonsaiStorageError<DB::DatabaseError>> {
+        for (i, identifier) in identifiers.iter().enumerate() {
+            self.check_not_sharded(identifier)?;
+            if identifiers[..i].contains(identifier) {
+                return Err(BonsaiStorageError::Shard(ShardError::DuplicateTrie {
+                    identifier: (*identifier).into(),
+                }));
+            }
+            if self
+                .trees
+                .get(*identifier)
+                .is_some_and(MerkleTree::has_uncommitted_changes)
+            {
+                return Err(BonsaiStorageError::Shard(ShardError::UncommittedChanges {
+                    identifier: (*identifier).into(),
+                }));
+            }
+        }
+        let mut trees = HashMap::new();
+        for identifier in identifiers {
+            self.trees.remove(*identifier);
+            self.sharded.insert((*identifier).into());
+            trees.insert(
+                (*identifier).into(),
+                new_tree(&self.db.config, identifier, self.max_height),
+            );
+        }
+        Ok(trees)
+    }
+
+    /// Give the tries of a dropped shard back to the storage.
+    #[cfg(feature = "std")]
+    pub(crate) fn close_shard<'a>(&mut self, identifiers: impl IntoIterator<Item = &'a ByteVec>) {
+        for identifier in identifiers {
+            self.sharded.remove(identifier);
         }
     }
 
@@ -100,10 +617,167 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
         &mut self.db
     }
 
-    pub(crate) fn _reset_to_last_commit(
+    /// Drop all the uncommitted changes of the tries.
+    pub(crate) fn reset_to_last_commit(&mut self) {
+        self.trees.clear(); // just clear the map
+        self.meta.clear();
+        self.meta_undo_log.clear();
+        self.savepoints.clear();
+        self.generation += 1;
+        self.db.changes_store.clear();
+    }
+
+    pub(crate) fn savepoint(&mut self) -> SavepointId {
+        let id = SavepointId(self.next_savepoint_id);
+        self.next_savepoint_id += 1;
+        let trees = self
+            .trees
+            .iter_mut()
+            .map(|(identifier, tree)| (identifier.clone(), tree.undo_log_len()))
+            .collect();
+        self.savepoints.push(Savepoint {
+            id,
+            trees,
+            meta: self.meta_undo_log.len(),
+        });
+        id
+    }
+
+    /// Undo the changes made since `savepoint` was taken. Savepoints taken after it are dropped.
+    pub(crate) fn rollback_to_savepoint(
         &mut self,
+        savepoint: SavepointId,
     ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
-        self.trees.clear(); // just clear the map
+        let Some(index) = self.savepoints.iter().position(|s| s.id == savepoint) else {
+            return Err(BonsaiStorageError::SavepointNotFound(savepoint));
+        };
+        self.savepoints.truncate(index + 1);
+        let savepoint = &self.savepoints[index];
+        self.trees
+            .retain(|identifier, tree| match savepoint.trees.get(identifier) {
+                Some(len) => {
+                    tree.undo_to(*len);
+                    true
+                }
+                None => false,
+            });
+        for (key, previous) in self.meta_undo_log.drain(savepoint.meta..).rev() {
+            match previous {
+                Some(value) => self.meta.insert(key, value),
+                None => self.meta.remove(&key),
+            };
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Number of leaves in the trie, including the uncommitted changes.
+    pub(crate) fn len(
+        &self,
+        identifier: &[u8],
+    ) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        let delta = match self.trees.get(identifier) {
+            Some(tree) => tree.leaf_count_delta(&self.db)?,
+            None => 0,
+        };
+        self.committed_len(identifier)?
+            .checked_add_signed(delta)
+            .ok_or_else(|| BonsaiStorageError::LeafCountUnderflow {
+                identifier: identifier.into(),
+            })
+    }
+
+    /// Number of committed leaves of the trie `identifier`.
+    fn committed_len(
+        &self,
+        identifier: &[u8],
+    ) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        match self.stored_len(identifier)? {
+            Some(count) => Ok(count),
+            None => self.count_leaves(identifier),
+        }
+    }
+
+    /// The stored leaf count of the trie `identifier`. It is missing when the trie is empty, and
+    /// in databases written before the leaf counts were stored.
+    fn stored_len(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Option<u64>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = leaf_count_key(identifier);
+        self.db
+            .get(&key)?
+            .map(|count| {
+                u64::decode(&mut count.as_slice()).map_err(|source| {
+                    BonsaiStorageError::DecodeError {
+                        key: key.as_slice().into(),
+                        source,
+                    }
+                })
+            })
+            .transpose()
+    }
+
+    /// Count the committed leaves of the trie `identifier` by walking them, for the tries that
+    /// have no stored leaf count.
+    fn count_leaves(
+        &self,
+        identifier: &[u8],
+    ) -> Result<u64, BonsaiStorageError<DB::DatabaseError>> {
+        let leaves = self
+            .db
+            .db
+            .get_by_prefix(&crate::DatabaseKey::Flat(identifier))?;
+        Ok(leaves
+            .iter()
+            .filter(|(key, _)| split_flat_key(key, self.max_height).0 == identifier)
+            .count() as u64)
+    }
+
+    /// Check that the root hash of each trie written since the last call, as read from the
+    /// database, is the one of its leaves in the flat storage, to catch commits writing other
+    /// nodes than the ones they hashed.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the first trie whose root hashes differ.
+    #[cfg(feature = "audit")]
+    pub(crate) fn audit(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut identifiers = core::mem::take(&mut self.audited);
+        identifiers.sort_unstable();
+        identifiers.dedup();
+        for identifier in identifiers {
+            let committed =
+                new_tree::<H>(&self.db.config, &identifier, self.max_height).root_hash(&self.db)?;
+            let mut leaves = Vec::new();
+            for (key, value) in self.db.db.get_by_prefix(&DatabaseKey::Flat(&identifier))? {
+                let (leaf_identifier, leaf_key) = split_flat_key(&key, self.max_height);
+                if leaf_identifier != identifier.as_slice() {
+                    continue;
+                }
+                let (value, _) =
+                    decode_leaf(&value).map_err(|source| BonsaiStorageError::DecodeError {
+                        key: key.clone(),
+                        source,
+                    })?;
+                leaves.push((leaf_key, value));
+            }
+            leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let leaves: Vec<_> = leaves
+                .iter()
+                .map(|(key, value)| (key.as_bitslice(), *value))
+                .collect();
+            let recomputed = super::tree::root_with_hasher(
+                &trie_hasher::<H>(&self.db.config, &identifier),
+                &leaves,
+                self.max_height,
+            );
+            assert_eq!(
+                committed, recomputed,
+                "The committed root hash of the trie {:?} is not the one of its leaves",
+                identifier
+            );
+        }
         Ok(())
     }
 
@@ -124,95 +798,1306 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
         &self,
         identifier: &[u8],
     ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        if let Some(proven) = self.proven.get(identifier) {
+            return Ok(proven.root());
+        }
         if let Some(tree) = self.trees.get(identifier) {
             Ok(tree.root_hash(&self.db)?)
         } else {
-            MerkleTree::<H>::new(identifier.into(), self.max_height).root_hash(&self.db)
+            self.check_trie_height(identifier)?;
+            new_tree::<H>(&self.db.config, identifier, self.max_height).root_hash(&self.db)
+        }
+    }
+
+    /// Fail with [`BonsaiStorageError::TrieHeightMismatch`] if the trie `identifier` was committed
+    /// with another height than the one of the storage, as it would be read wrongly.
+    fn check_trie_height(
+        &self,
+        identifier: &[u8],
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        match self.stored_trie_height(identifier)? {
+            Some(stored) if stored != self.max_height => {
+                Err(BonsaiStorageError::TrieHeightMismatch {
+                    identifier: identifier.into(),
+                    stored,
+                    requested: self.max_height,
+                })
+            }
+            _ => Ok(()),
         }
     }
 
-    pub(crate) fn get_keys(
+    /// Height the trie `identifier` was committed with. It is missing before its first commit,
+    /// and in databases written before it was stored.
+    fn stored_trie_height(
         &self,
         identifier: &[u8],
-    ) -> Result<Vec<Vec<u8>>, BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<Option<u8>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = trie_height_key(identifier);
         self.db
-            .db
-            .get_by_prefix(&crate::DatabaseKey::Flat(identifier))
-            .map(|key_value_pairs| {
-                // Remove the identifier from the key
-                key_value_pairs
-                    .into_iter()
-                    // FIXME: this does not filter out keys values correctly for `HashMapDb` due
-                    // to branches and leafs not being differenciated
-                    .filter_map(|(key, _value)| {
-                        if key.len() > identifier.len() {
-                            Some(key[identifier.len() + 1..].into())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
+            .get(&key)?
+            .map(|height| {
+                u8::decode(&mut height.as_slice()).map_err(|source| {
+                    BonsaiStorageError::DecodeError {
+                        key: key.as_slice().into(),
+                        source,
+                    }
+                })
             })
-            .map_err(|e| e.into())
+            .transpose()
+    }
+
+    /// The committed leaves of the trie `identifier` after `start_after`, in increasing key
+    /// order.
+    pub(crate) fn iter_leaves<'a>(
+        &'a self,
+        identifier: &'a [u8],
+        start_after: Option<&BitSlice>,
+    ) -> CommittedLeaves<'a, DB::DatabaseError> {
+        CommittedLeaves::new(&self.db, identifier, self.max_height, start_after)
     }
 
+    /// The committed nodes of the trie `identifier` with their paths and their hashes, in
+    /// increasing path order. The nodes of a trie stored inline are rebuilt from its record.
     #[allow(clippy::type_complexity)]
-    pub(crate) fn get_key_value_pairs(
+    pub(crate) fn committed_nodes(
         &self,
         identifier: &[u8],
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, BonsaiStorageError<DB::DatabaseError>> {
-        self.db
-            .db
-            .get_by_prefix(&crate::DatabaseKey::Flat(identifier))
-            .map(|key_value_pairs| {
-                key_value_pairs
-                    .into_iter()
-                    // FIXME: this does not filter out keys values correctly for `HashMapDb` due
-                    // to branches and leafs not being differenciated
-                    .filter_map(|(key, value)| {
-                        if key.len() > identifier.len() {
-                            Some((key[identifier.len() + 1..].into(), value.into_vec()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
-            })
-            .map_err(|e| e.into())
+    ) -> Result<Vec<(BitVec, Node, Felt)>, BonsaiStorageError<DB::DatabaseError>> {
+        let entries: Vec<(ByteVec, ByteVec)> = match InlineTrie::read(&self.db, identifier)? {
+            Some(record) => record
+                .nodes::<H, _>(
+                    identifier,
+                    self.max_height,
+                    &trie_hasher::<H>(&self.db.config, identifier),
+                )?
+                .into_iter()
+                .map(|(key, node)| (key.as_slice().into(), node))
+                .collect(),
+            None => self
+                .db
+                .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Trie, &[]))?,
+        };
+        let mut nodes = Vec::new();
+        for (key, value) in entries {
+            if !is_node_key(&key, identifier) {
+                continue;
+            }
+            let path = bytes_to_bitvec(&key[identifier.len()..]);
+            let node = decode_node(
+                identifier,
+                &path,
+                &TrieKey::Trie(key),
+                &value,
+                self.max_height,
+            )?;
+            let hash = node.get_hash().ok_or_else(|| {
+                BonsaiStorageError::Trie(format!(
+                    "Node {path:?} of trie {identifier:?} is stored without its hash"
+                ))
+            })?;
+            nodes.push((path, node, hash));
+        }
+        nodes.sort_unstable_by(|(a, ..), (b, ..)| a.cmp(b));
+        Ok(nodes)
+    }
+
+    /// The committed leaf `key` of the trie `identifier` as stored in the flat storage.
+    pub(crate) fn stored_leaf(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+    ) -> Result<Option<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        self.db.get(&TrieKey::new(
+            identifier,
+            TrieKeyType::Flat,
+            &bitslice_to_bytes(key),
+        ))
+    }
+
+    /// Raw payloads of all the leaves of the trie `identifier`, uncommitted changes included, in
+    /// no particular order.
+    #[cfg(feature = "eth")]
+    pub(crate) fn raw_leaves(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Vec<ByteVec>, BonsaiStorageError<DB::DatabaseError>> {
+        let leaf_key_len = 1 + (self.max_height as usize).div_ceil(8);
+        let tree = self.trees.get(identifier);
+        let mut leaves = Vec::new();
+        for (key, value) in
+            self.db
+                .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Flat, &[]))?
+        {
+            // Skip the leaves of the tries whose identifier starts with this one.
+            if key.len() != identifier.len() + leaf_key_len {
+                continue;
+            }
+            let leaf_key = &key[identifier.len()..];
+            if tree.is_some_and(|tree| tree.cache_leaf_modified.contains_key(leaf_key)) {
+                continue;
+            }
+            let (_, raw) = decode_leaf(&value)
+                .map_err(|source| BonsaiStorageError::DecodeError { key, source })?;
+            leaves.push(raw.into());
+        }
+        for (key, change) in tree.into_iter().flat_map(|tree| &tree.cache_leaf_modified) {
+            if let InsertOrRemove::Insert(_) = change {
+                let raw = tree.and_then(|tree| tree.cache_raw_modified.get(key));
+                leaves.push(raw.cloned().unwrap_or_default());
+            }
+        }
+        Ok(leaves)
     }
 
     pub(crate) fn commit(&mut self) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let idle: Vec<_> = match self.db.config.max_cached_tries {
+            Some(max) if self.trees.len() > max => self
+                .trees
+                .iter()
+                .filter(|(_, tree)| !tree.has_uncommitted_changes())
+                .map(|(identifier, _)| identifier.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut batch = self.db.create_batch();
+        self.write_commit(&mut batch, Some(&mut 0))?;
+        self.db.write_batch(batch)?;
+        self.evict_trees(idle);
+        Ok(())
+    }
+
+    /// Drop the committed trees past [`crate::BonsaiStorageConfig::max_cached_tries`], the `idle`
+    /// ones, which the commit didn't change, first.
+    fn evict_trees(&mut self, idle: Vec<ByteVec>) {
+        let Some(max) = self.db.config.max_cached_tries else {
+            return;
+        };
+        let mut excess = self.trees.len().saturating_sub(max);
+        for identifier in idle.into_iter().take(excess) {
+            self.trees.remove(&identifier);
+            excess -= 1;
+        }
+        if excess > 0 {
+            let evicted: Vec<_> = self.trees.keys().take(excess).cloned().collect();
+            for identifier in evicted {
+                self.trees.remove(&identifier);
+            }
+        }
+    }
+
+    /// Same as `commit` but the changes are written to `batch` instead of being applied directly.
+    pub(crate) fn commit_to_batch(
+        &mut self,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.write_commit(batch, None)
+    }
+
+    /// Write the changes to `batch`. With `batch_bytes`, the bytes written to `batch` so far, it is
+    /// written early once it is full, see [`KeyValueDB::flush_full_batch`].
+    fn write_commit(
+        &mut self,
+        batch: &mut DB::Batch,
+        mut batch_bytes: Option<&mut usize>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
         #[cfg(feature = "std")]
         use rayon::prelude::*;
 
+        self.savepoints.clear();
+        self.meta_undo_log.clear();
+        self.generation += 1;
+        // Must be computed before the leaves are written to the database.
+        #[cfg(feature = "key_checks")]
+        crate::bonsai_database::debug_assert_prefix_free(self.trees.keys().map(ByteVec::as_slice));
+        let leaf_counts = self.leaf_counts(&self.trees)?;
+        let disk_usages = self.disk_usages(&self.trees)?;
+
+        let mut usage_deltas = HashMap::new();
+        #[cfg(feature = "std")]
+        if rayon::current_thread_index().is_none() {
+            self.write_trees_pipelined(batch, batch_bytes.as_deref_mut(), &mut usage_deltas)?;
+        } else {
+            // On a worker of the pool, waiting for the hashing to send the updates could keep it
+            // from running: every tree is hashed before writing.
+            let stats = &self.db.stats;
+            let db_changes = self
+                .trees
+                .par_iter_mut()
+                .map(|(identifier, tree)| (identifier.clone(), tree.get_updates::<DB>(stats, None)))
+                .collect_vec_list()
+                .into_iter()
+                .flatten();
+            for (identifier, changes) in db_changes {
+                let delta = self.write_tree_updates(
+                    &identifier,
+                    changes?,
+                    batch,
+                    batch_bytes.as_deref_mut(),
+                )?;
+                usage_deltas.insert(identifier, delta);
+            }
+        }
         #[cfg(not(feature = "std"))]
-        let db_changes = self
-            .trees
-            .iter_mut()
-            .map(|(_, tree)| tree.get_updates::<DB>());
+        {
+            let stats = &self.db.stats;
+            let db_changes = self
+                .trees
+                .iter_mut()
+                .map(|(identifier, tree)| (identifier.clone(), tree.get_updates::<DB>(stats, None)))
+                .collect::<Vec<_>>();
+            for (identifier, changes) in db_changes {
+                let delta = self.write_tree_updates(
+                    &identifier,
+                    changes?,
+                    batch,
+                    batch_bytes.as_deref_mut(),
+                )?;
+                usage_deltas.insert(identifier, delta);
+            }
+        }
+        let meta_updates = self.meta_updates();
+        self.meta.clear();
+        self.write_updates(meta_updates, batch)?;
+        self.write_leaf_counts(leaf_counts, batch)?;
+        self.write_disk_usages(disk_usages, usage_deltas, batch)
+    }
+
+    /// Write the updates of every tree to `batch`, adding their disk usage deltas to
+    /// `usage_deltas`. The trees are hashed on the rayon pool and their updates are written as
+    /// soon as they are computed, so that writing the updates of a tree overlaps with hashing the
+    /// next ones. At most [`COMMIT_PIPELINE_DEPTH`] trees wait to be written.
+    #[cfg(feature = "std")]
+    fn write_trees_pipelined(
+        &mut self,
+        batch: &mut DB::Batch,
+        mut batch_bytes: Option<&mut usize>,
+        usage_deltas: &mut HashMap<ByteVec, (i64, i64)>,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        use rayon::prelude::*;
+        use std::sync::mpsc;
+
+        // Taken out so that the trees are hashed while the database is written.
+        let mut trees = core::mem::take(&mut self.trees);
+        let stats = crate::op_stats::OpCounters::default();
+        let result = rayon::in_place_scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel(COMMIT_PIPELINE_DEPTH);
+            let hashed = &mut trees;
+            let stats = &stats;
+            scope.spawn(move |_| {
+                hashed
+                    .par_iter_mut()
+                    .for_each_with(sender, |sender, (identifier, tree)| {
+                        // Only fails once the writes stopped on an error.
+                        let _ =
+                            sender.send((identifier.clone(), tree.get_updates::<DB>(stats, None)));
+                    });
+            });
+            for (identifier, changes) in receiver {
+                let delta = self.write_tree_updates(
+                    &identifier,
+                    changes?,
+                    batch,
+                    batch_bytes.as_deref_mut(),
+                )?;
+                usage_deltas.insert(identifier, delta);
+            }
+            Ok(())
+        });
+        self.trees = trees;
+        self.db.stats.add(stats.take());
+        result
+    }
+
+    /// Build the empty trie `identifier` from `leaves` and commit it as `id`, see
+    /// [`crate::BonsaiStorage::bulk_load`].
+    pub(crate) fn bulk_load(
+        &mut self,
+        identifier: &[u8],
+        leaves: impl IntoIterator<Item = (BitVec, Felt)>,
+        id: CommitID,
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_trie_height(identifier)?;
+        let hasher = trie_hasher::<H>(&self.db.config, identifier);
+        let empty_root = hasher.empty_root(self.max_height);
+        let mut builder =
+            IncrementalTrieBuilder::<H>::with_hasher(identifier, self.max_height, hasher)
+                .store_zero_values(self.db.config.store_zero_values);
+        let db = &mut self.db.db;
+        let mut batch = db.create_batch();
+        for (pushed, (key, value)) in leaves.into_iter().enumerate() {
+            builder.push(db, &mut batch, &key, value)?;
+            if (pushed + 1) % BULK_LOAD_BATCH_LEAVES == 0 {
+                let full = core::mem::replace(&mut batch, db.create_batch());
+                db.write_batch(full)?;
+            }
+        }
+        // The root is written last, along with the commit.
+        let root_hash = builder.finish(db, &mut batch)?;
+        db.insert(
+            &DatabaseKey::from(&trie_height_key(identifier)),
+            &self.max_height.encode_bytevec(),
+            Some(&mut batch),
+        )?;
+        self.db.commit_bulk_load(
+            id,
+            [(identifier.into(), (empty_root, root_hash))],
+            &mut batch,
+        )?;
+        self.db.write_batch(batch)?;
+        self.reset_to_last_commit();
+        Ok(root_hash)
+    }
+
+    /// Rebuild the tries from the leaves of `reader` and commit them as `id` if their root hashes
+    /// are the ones of `expected_roots`, see [`crate::BonsaiStorage::import_snapshot`]. The staged
+    /// entries are removed otherwise.
+    #[cfg(feature = "std")]
+    pub(crate) fn import_snapshot<R: BonsaiDatabase>(
+        &mut self,
+        reader: &R,
+        expected_roots: &HashMap<ByteVec, Felt>,
+        id: CommitID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>>
+    where
+        R::DatabaseError: 'static,
+    {
+        let import_error = |err: R::DatabaseError| BonsaiStorageError::ImportSource(Box::new(err));
+        let key = crate::stored_config::stored_config_key();
+        let stored = reader
+            .get(&DatabaseKey::from(&key))
+            .map_err(import_error)?
+            .map(|stored| crate::StoredConfig::decode(&mut stored.as_slice()))
+            .transpose()
+            .map_err(|source| BonsaiStorageError::DecodeError {
+                key: key.as_slice().into(),
+                source,
+            })?;
+        if let Some(stored) = stored.filter(|stored| stored.max_height != self.max_height) {
+            return Err(BonsaiStorageError::SnapshotHeightMismatch {
+                stored: stored.max_height,
+                requested: self.max_height,
+            });
+        }
+        let mut tries: crate::BTreeMap<ByteVec, Vec<(BitVec, Felt)>> = expected_roots
+            .keys()
+            .map(|identifier| (identifier.clone(), Vec::new()))
+            .collect();
+        let leaves = reader
+            .get_by_prefix(&DatabaseKey::Flat(&[]))
+            .map_err(import_error)?;
+        for (key, value) in leaves {
+            let (identifier, leaf_key) = split_flat_key(&key, self.max_height);
+            let Some(leaves) = tries.get_mut(identifier) else {
+                return Err(BonsaiStorageError::UntrustedTrie {
+                    identifier: identifier.into(),
+                });
+            };
+            let (value, _) =
+                decode_leaf(&value).map_err(|source| BonsaiStorageError::DecodeError {
+                    key: key.clone(),
+                    source,
+                })?;
+            leaves.push((leaf_key, value));
+        }
+
+        let mut commit_batch = self.db.create_batch();
+        let staged = self.stage_imported_tries(tries, expected_roots, &mut commit_batch);
+        let result = staged.and_then(|root_hashes| {
+            self.db
+                .commit_bulk_load(id, root_hashes, &mut commit_batch)?;
+            self.db.write_batch(commit_batch)
+        });
+        if result.is_err() {
+            // The storage was empty: every node and leaf in the database was staged by the import.
+            let db = &mut self.db.db;
+            db.remove_by_prefix(&DatabaseKey::Trie(&[]))?;
+            db.remove_by_prefix(&DatabaseKey::Flat(&[]))?;
+        }
+        self.reset_to_last_commit();
+        result
+    }
+
+    /// Write the leaves and the nodes of the imported `tries` to the database in batches of
+    /// [`BULK_LOAD_BATCH_LEAVES`] leaves, and the roots to `commit_batch`, so that the tries are
+    /// only reachable once it is written. Stops at the first trie without its trusted root hash.
+    #[cfg(feature = "std")]
+    #[allow(clippy::type_complexity)]
+    fn stage_imported_tries(
+        &mut self,
+        tries: crate::BTreeMap<ByteVec, Vec<(BitVec, Felt)>>,
+        expected_roots: &HashMap<ByteVec, Felt>,
+        commit_batch: &mut DB::Batch,
+    ) -> Result<Vec<(ByteVec, (Felt, Felt))>, BonsaiStorageError<DB::DatabaseError>> {
+        let db = &mut self.db.db;
+        let mut root_hashes = Vec::new();
+        for (identifier, mut leaves) in tries {
+            let hasher = trie_hasher::<H>(&self.db.config, &identifier);
+            let empty_root = hasher.empty_root(self.max_height);
+            let root_hash = if leaves.is_empty() {
+                empty_root
+            } else {
+                leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+                // The leaves are the stored ones, zero values included.
+                let mut builder =
+                    IncrementalTrieBuilder::<H>::with_hasher(&identifier, self.max_height, hasher)
+                        .store_zero_values(true);
+                let mut batch = db.create_batch();
+                for (pushed, (key, value)) in leaves.into_iter().enumerate() {
+                    builder.push(db, &mut batch, &key, value)?;
+                    if (pushed + 1) % BULK_LOAD_BATCH_LEAVES == 0 {
+                        let full = core::mem::replace(&mut batch, db.create_batch());
+                        db.write_batch(full)?;
+                    }
+                }
+                db.write_batch(batch)?;
+                let root_hash = builder.finish(db, commit_batch)?;
+                db.insert(
+                    &DatabaseKey::from(&trie_height_key(&identifier)),
+                    &self.max_height.encode_bytevec(),
+                    Some(commit_batch),
+                )?;
+                root_hash
+            };
+            if root_hash != expected_roots[&identifier] {
+                return Err(BonsaiStorageError::ImportRootMismatch(Box::new(
+                    crate::ImportRootMismatch {
+                        expected: expected_roots[&identifier],
+                        got: root_hash,
+                        identifier,
+                    },
+                )));
+            }
+            root_hashes.push((identifier, (empty_root, root_hash)));
+        }
+        Ok(root_hashes)
+    }
+
+    /// Compute the database updates of a commit without modifying the tries, the trees with
+    /// uncommitted changes are cloned instead. Stops once `cancel` is cancelled.
+    pub(crate) fn prepare_commit(
+        &self,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<PreparedCommit, BonsaiStorageError<DB::DatabaseError>> {
+        #[cfg(feature = "std")]
+        use rayon::prelude::*;
+
+        let leaf_counts = self.leaf_counts(&self.trees)?;
+        let disk_usages = self.disk_usages(&self.trees)?;
+
+        let stats = &self.db.stats;
+        #[cfg(not(feature = "std"))]
+        let db_changes = self.trees.iter().map(|(identifier, tree)| {
+            (
+                identifier.clone(),
+                tree.clone()
+                    .get_updates::<DB>(stats, cancel)
+                    .map(Iterator::collect),
+            )
+        });
         #[cfg(feature = "std")]
         let db_changes = self
             .trees
+            .par_iter()
+            .map(|(identifier, tree)| {
+                (
+                    identifier.clone(),
+                    tree.clone()
+                        .get_updates::<DB>(stats, cancel)
+                        .map(Iterator::collect),
+                )
+            })
+            .collect_vec_list()
+            .into_iter()
+            .flatten();
+
+        let mut tree_updates = Vec::new();
+        for (identifier, changes) in db_changes {
+            tree_updates.push((identifier, changes?));
+        }
+        Ok(PreparedCommit {
+            generation: self.generation,
+            tree_updates,
+            meta_updates: self.meta_updates(),
+            leaf_counts,
+            disk_usages,
+        })
+    }
+
+    /// Write the updates of a commit prepared with [`MerkleTrees::prepare_commit`], which must
+    /// have been prepared from the current uncommitted changes.
+    pub(crate) fn commit_prepared_to_batch(
+        &mut self,
+        prepared: PreparedCommit,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_prepared(&prepared)?;
+        // Same state as after `commit_to_batch`: nothing is left in memory.
+        self.trees.clear();
+        self.meta.clear();
+        self.meta_undo_log.clear();
+        self.savepoints.clear();
+        self.generation += 1;
+        self.write_prepared(prepared, batch)
+    }
+
+    /// Compute the database updates of the trees of a shard, committing them in memory. Only
+    /// `&self` is needed so that shards can compute them at the same time.
+    #[cfg(feature = "std")]
+    pub(crate) fn prepare_shard_commit(
+        &self,
+        trees: &mut HashMap<ByteVec, MerkleTree<H>>,
+    ) -> Result<PreparedCommit, BonsaiStorageError<DB::DatabaseError>> {
+        use rayon::prelude::*;
+
+        let leaf_counts = self.leaf_counts(trees)?;
+        let disk_usages = self.disk_usages(trees)?;
+        let stats = &self.db.stats;
+        let db_changes = trees
             .par_iter_mut()
-            .map(|(_, tree)| tree.get_updates::<DB>())
+            .map(|(identifier, tree)| {
+                (
+                    identifier.clone(),
+                    tree.get_updates::<DB>(stats, None).map(Iterator::collect),
+                )
+            })
             .collect_vec_list()
             .into_iter()
             .flatten();
 
+        let mut tree_updates = Vec::new();
+        for (identifier, changes) in db_changes {
+            tree_updates.push((identifier, changes?));
+        }
+        Ok(PreparedCommit {
+            generation: self.generation,
+            tree_updates,
+            meta_updates: Updates::new(),
+            leaf_counts,
+            disk_usages,
+        })
+    }
+
+    /// Write the updates of a prepared commit, without checking what they were prepared from.
+    pub(crate) fn write_prepared(
+        &mut self,
+        prepared: PreparedCommit,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        let mut usage_deltas = HashMap::new();
+        for (identifier, updates) in prepared.tree_updates {
+            let delta = self.write_tree_updates(&identifier, updates, batch, None)?;
+            usage_deltas.insert(identifier, delta);
+        }
+        self.write_updates(prepared.meta_updates, batch)?;
+        self.write_leaf_counts(prepared.leaf_counts, batch)?;
+        self.write_disk_usages(prepared.disk_usages, usage_deltas, batch)
+    }
+
+    /// Root hash of the trie `identifier` once its uncommitted changes are committed.
+    pub(crate) fn pending_root_hash(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Felt, BonsaiStorageError<DB::DatabaseError>> {
+        match self.trees.get(identifier) {
+            Some(tree) if tree.has_uncommitted_changes() => tree.pending_root_hash(&self.db),
+            _ => self.root_hash(identifier),
+        }
+    }
+
+    /// Root hashes of the tries with uncommitted changes once they are committed, along with the
+    /// number of leaves they change.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn pending_root_hashes(
+        &self,
+    ) -> Result<(HashMap<ByteVec, Felt>, usize), BonsaiStorageError<DB::DatabaseError>> {
+        let mut root_hashes = HashMap::new();
+        let mut changes = 0;
+        for (identifier, tree) in &self.trees {
+            if tree.has_uncommitted_changes() {
+                root_hashes.insert(identifier.clone(), tree.pending_root_hash(&self.db)?);
+                changes += tree.cache_leaf_modified().len();
+            }
+        }
+        Ok((root_hashes, changes))
+    }
+
+    /// The leaves changed by the uncommitted changes, along with their committed value.
+    pub(crate) fn leaf_changes(
+        &self,
+    ) -> Result<Vec<LeafChange>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut changes = Vec::new();
+        for (identifier, tree) in &self.trees {
+            let modified: Vec<_> = tree.cache_leaf_modified().iter().collect();
+            let keys: Vec<_> = modified
+                .iter()
+                .map(|(key, _)| TrieKey::new(identifier, TrieKeyType::Flat, key))
+                .collect();
+            let old_values = self.db.get_many(&keys)?;
+            for (((key, op), db_key), old_value) in modified.into_iter().zip(&keys).zip(old_values)
+            {
+                let old_value = old_value
+                    .map(|value| {
+                        decode_leaf(&value).map(|(v, _)| v).map_err(|source| {
+                            BonsaiStorageError::DecodeError {
+                                key: db_key.as_slice().into(),
+                                source,
+                            }
+                        })
+                    })
+                    .transpose()?;
+                let new_value = match op {
+                    InsertOrRemove::Insert(value) => Some(*value),
+                    InsertOrRemove::Remove => None,
+                };
+                if old_value != new_value {
+                    changes.push(LeafChange {
+                        identifier: identifier.clone(),
+                        key: bytes_to_bitvec(key),
+                        old_value,
+                        new_value,
+                    });
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Check that `prepared` was prepared from the current uncommitted changes.
+    pub(crate) fn check_prepared(
+        &self,
+        prepared: &PreparedCommit,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        if prepared.generation != self.generation {
+            return Err(BonsaiStorageError::PreparedCommitStale);
+        }
+        Ok(())
+    }
+
+    /// Number of leaves of each trie once the uncommitted changes are committed, for the tries
+    /// where it changes.
+    fn leaf_counts(
+        &self,
+        trees: &HashMap<ByteVec, MerkleTree<H>>,
+    ) -> Result<Vec<(ByteVec, u64)>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut leaf_counts = Vec::new();
+        for (identifier, tree) in trees {
+            let delta = tree.leaf_count_delta(&self.db)?;
+            let stored = self.stored_len(identifier)?;
+            if delta == 0 && stored.is_some() {
+                continue;
+            }
+            let committed = match stored {
+                Some(count) => count,
+                None => self.count_leaves(identifier)?,
+            };
+            let count = committed.checked_add_signed(delta).ok_or_else(|| {
+                BonsaiStorageError::LeafCountUnderflow {
+                    identifier: identifier.clone(),
+                }
+            })?;
+            // A missing count is stored as soon as the trie is modified.
+            if delta != 0 || count != 0 {
+                leaf_counts.push((identifier.clone(), count));
+            }
+        }
+        Ok(leaf_counts)
+    }
+
+    /// Disk usage of each trie with uncommitted changes, `None` for the tries where it is stored.
+    /// The others are measured, so this must be called before the changes are written.
+    #[allow(clippy::type_complexity)]
+    fn disk_usages(
+        &self,
+        trees: &HashMap<ByteVec, MerkleTree<H>>,
+    ) -> Result<Vec<(ByteVec, Option<TrieUsage>)>, BonsaiStorageError<DB::DatabaseError>> {
+        let mut disk_usages = Vec::new();
+        for identifier in trees.keys() {
+            let usage = match self.stored_disk_usage(identifier)? {
+                Some(_) => None,
+                None => Some(self.measure_disk_usage(identifier)?),
+            };
+            disk_usages.push((identifier.clone(), usage));
+        }
+        Ok(disk_usages)
+    }
+
+    /// Apply the `deltas` written by [`MerkleTrees::write_tree_updates`] to the stored disk usages,
+    /// or to the measured ones of `disk_usages`.
+    fn write_disk_usages(
+        &mut self,
+        disk_usages: Vec<(ByteVec, Option<TrieUsage>)>,
+        deltas: HashMap<ByteVec, (i64, i64)>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (identifier, measured) in disk_usages {
+            let (trie_delta, flat_delta) = deltas.get(&identifier).copied().unwrap_or_default();
+            if trie_delta == 0 && flat_delta == 0 && measured.is_none() {
+                continue;
+            }
+            let (trie, flat) = match measured {
+                Some(usage) => usage,
+                None => self.stored_disk_usage(&identifier)?.unwrap_or_default(),
+            };
+            // The usage is approximate, don't fail the commit if it drifted.
+            let usage = (
+                trie.saturating_add_signed(trie_delta),
+                flat.saturating_add_signed(flat_delta),
+            );
+            let key = disk_usage_key(&identifier);
+            if usage == (0, 0) {
+                self.db.remove_in_trie(&identifier, &key, Some(batch))?;
+            } else {
+                self.db
+                    .insert_in_trie(&identifier, &key, &usage.encode_bytevec(), Some(batch))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The stored disk usage of the trie `identifier`, in bytes of trie nodes and of leaves. It is
+    /// missing when the trie is empty, and in databases written before it was stored.
+    fn stored_disk_usage(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Option<TrieUsage>, BonsaiStorageError<DB::DatabaseError>> {
+        let key = disk_usage_key(identifier);
+        self.db
+            .get(&key)?
+            .map(|usage| {
+                TrieUsage::decode(&mut usage.as_slice()).map_err(|source| {
+                    BonsaiStorageError::DecodeError {
+                        key: key.as_slice().into(),
+                        source,
+                    }
+                })
+            })
+            .transpose()
+    }
+
+    /// Measure the disk usage of the trie `identifier` by reading its nodes and leaves, for the
+    /// tries that have no stored disk usage.
+    fn measure_disk_usage(
+        &self,
+        identifier: &[u8],
+    ) -> Result<TrieUsage, BonsaiStorageError<DB::DatabaseError>> {
+        let size = |entries: Vec<(ByteVec, ByteVec)>, is_in_trie: &dyn Fn(&[u8]) -> bool| {
+            entries
+                .iter()
+                .filter(|(key, _)| is_in_trie(key))
+                .map(|(key, value)| (key.len() + value.len()) as u64)
+                .sum::<u64>()
+        };
+        let nodes = self
+            .db
+            .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Trie, &[]))?;
+        let leaves = self
+            .db
+            .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Flat, &[]))?;
+        let inline_key = inline_trie_key(identifier);
+        Ok((
+            size(nodes, &|key| {
+                is_node_key(key, identifier) || key == inline_key.as_slice()
+            }),
+            size(leaves, &|key| {
+                split_flat_key(key, self.max_height).0 == identifier
+            }),
+        ))
+    }
+
+    /// Approximate bytes used in the database by the trie `identifier`, see
+    /// [`crate::BonsaiStorage::disk_usage`].
+    pub(crate) fn disk_usage(
+        &self,
+        identifier: &[u8],
+    ) -> Result<DiskUsage, BonsaiStorageError<DB::DatabaseError>> {
+        let (trie, flat) = match self.stored_disk_usage(identifier)? {
+            Some(usage) => usage,
+            None => self.measure_disk_usage(identifier)?,
+        };
+        Ok(DiskUsage {
+            trie,
+            flat,
+            trie_logs: self.db.trie_log_usage(identifier)?,
+        })
+    }
+
+    /// Set the leaves of the trie `identifier` changed since commit `id` back to the values they
+    /// had then, see [`crate::BonsaiStorage::revert_identifier_to`].
+    pub(crate) fn revert_trie_to(
+        &mut self,
+        identifier: &[u8],
+        id: CommitID,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_not_sharded(identifier)?;
+        if self
+            .trees
+            .get(identifier)
+            .is_some_and(MerkleTree::has_uncommitted_changes)
+        {
+            return Err(BonsaiStorageError::TrieUncommittedChanges {
+                identifier: identifier.into(),
+            });
+        }
+        for (key, stored) in self.db.trie_leaves_at(identifier, id)? {
+            let (key_identifier, key) = split_flat_key(&key, self.max_height);
+            // The trie logs written before they were framed by trie have the other tries.
+            if key_identifier == identifier {
+                self.set_stored(identifier, &key, stored.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite the nodes of the trie `identifier` written with an older version of the node
+    /// encoding, see [`crate::BonsaiStorage::migrate_nodes`].
+    pub(crate) fn migrate_nodes(
+        &mut self,
+        identifier: &[u8],
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        let nodes = self
+            .db
+            .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Trie, &[]))?;
         let mut batch = self.db.create_batch();
-        for changes in db_changes {
-            for (key, value) in changes? {
+        let mut migrated = 0;
+        let mut delta = 0i64;
+        for (key, value) in nodes {
+            if !is_node_key(&key, identifier) {
+                continue;
+            }
+            let path = bytes_to_bitvec(&key[identifier.len()..]);
+            let key = TrieKey::Trie(key);
+            let (node, version) = match Node::decode_at(&value, path.len(), self.max_height) {
+                Ok(decoded) => decoded,
+                Err(_) if self.db.config.quarantine_corrupted_nodes => {
+                    self.db.insert_untracked(
+                        &quarantine_key(key.as_slice()),
+                        &value,
+                        &mut batch,
+                    )?;
+                    self.db.remove_untracked(&key, &mut batch)?;
+                    delta -= (key.as_slice().len() + value.len()) as i64;
+                    continue;
+                }
+                Err(source) => return Err(corrupted_node(identifier, &path, &key, source)),
+            };
+            if version == NODE_ENCODING_VERSION {
+                continue;
+            }
+            let encoded = node.encode_versioned();
+            delta += encoded.len() as i64 - value.len() as i64;
+            // The content of the trie is the same, reverting doesn't need to restore the old
+            // encoding.
+            self.db.insert_untracked(&key, &encoded, &mut batch)?;
+            migrated += 1;
+        }
+        if delta != 0 {
+            if let Some((trie, flat)) = self.stored_disk_usage(identifier)? {
+                let usage = (trie.saturating_add_signed(delta), flat);
+                let key = disk_usage_key(identifier);
+                self.db
+                    .insert_untracked(&key, &usage.encode_bytevec(), &mut batch)?;
+            }
+        }
+        self.db.write_batch(batch)?;
+        Ok(migrated)
+    }
+
+    /// Nodes of the trie `identifier` moved aside by [`MerkleTrees::migrate_nodes`], see
+    /// [`crate::BonsaiStorage::quarantined_nodes`].
+    pub(crate) fn quarantined_nodes(
+        &self,
+        identifier: &[u8],
+    ) -> Result<Vec<(Path, ByteVec)>, BonsaiStorageError<DB::DatabaseError>> {
+        let prefix = quarantine_key(&[]);
+        Ok(self
+            .db
+            .get_by_prefix(&quarantine_key(identifier))?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let node_key = &key[prefix.as_slice().len()..];
+                // Nodes of the tries whose identifier starts with this one.
+                is_node_key(node_key, identifier)
+                    .then(|| (Path(bytes_to_bitvec(&node_key[identifier.len()..])), value))
+            })
+            .collect())
+    }
+
+    /// Rebuild the nodes of the trie `identifier` from its committed leaves, see
+    /// [`crate::BonsaiStorage::rebuild_from_leaves`]. Returns the number of nodes written or
+    /// removed.
+    pub(crate) fn rebuild_from_leaves(
+        &mut self,
+        identifier: &[u8],
+    ) -> Result<usize, BonsaiStorageError<DB::DatabaseError>> {
+        self.check_not_sharded(identifier)?;
+        self.check_not_proven(identifier)?;
+        self.check_trie_height(identifier)?;
+        if self
+            .trees
+            .get(identifier)
+            .is_some_and(MerkleTree::has_uncommitted_changes)
+        {
+            return Err(BonsaiStorageError::TrieUncommittedChanges {
+                identifier: identifier.into(),
+            });
+        }
+        let leaf_key_len = 1 + (self.max_height as usize).div_ceil(8);
+        let mut leaves = Vec::new();
+        for (key, value) in
+            self.db
+                .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Flat, &[]))?
+        {
+            // Skip the leaves of the tries whose identifier starts with this one.
+            if key.len() != identifier.len() + leaf_key_len {
+                continue;
+            }
+            let (value, _) =
+                decode_leaf(&value).map_err(|source| BonsaiStorageError::DecodeError {
+                    key: key.clone(),
+                    source,
+                })?;
+            leaves.push((ByteVec::from(&key[identifier.len()..]), value));
+        }
+        leaves.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let hasher = trie_hasher::<H>(&self.db.config, identifier);
+        let mut nodes = build_nodes::<H, _>(identifier, self.max_height, &hasher, &leaves)?;
+
+        let mut batch = self.db.create_batch();
+        let mut rewritten = 0;
+        let mut delta = 0i64;
+        let record_key = inline_trie_key(identifier);
+        let record = match self.db.config.max_inline_leaves {
+            Some(_) => self.db.get(&record_key)?,
+            None => None,
+        };
+        // The nodes of an inline trie are not stored, only its record, which may not decode.
+        if let Some(record) = record {
+            let root_key = TrieKey::new(identifier, TrieKeyType::Trie, &[0]);
+            let root_hash = match nodes.get(&root_key) {
+                Some(root) => decode_node(
+                    identifier,
+                    BitSlice::empty(),
+                    &root_key,
+                    root,
+                    self.max_height,
+                )?
+                .get_hash()
+                .expect("The built node has no computed hash"),
+                None => hasher.empty_root(self.max_height),
+            };
+            nodes.clear();
+            let encoded = InlineTrie { root_hash, leaves }.encode();
+            if encoded != record {
+                delta += encoded.len() as i64 - record.len() as i64;
+                self.db
+                    .insert_untracked(&record_key, &encoded, &mut batch)?;
+                rewritten += 1;
+            }
+        }
+        for (key, value) in
+            self.db
+                .get_by_prefix(&TrieKey::new(identifier, TrieKeyType::Trie, &[]))?
+        {
+            if !is_node_key(&key, identifier) {
+                continue;
+            }
+            let key = TrieKey::Trie(key);
+            match nodes.remove(&key) {
+                Some(node) if node == value => continue,
+                Some(node) => {
+                    delta += node.len() as i64 - value.len() as i64;
+                    self.db.insert_untracked(&key, &node, &mut batch)?;
+                }
+                None => {
+                    delta -= (key.as_slice().len() + value.len()) as i64;
+                    self.db.remove_untracked(&key, &mut batch)?;
+                }
+            }
+            rewritten += 1;
+        }
+        for (key, node) in nodes {
+            delta += (key.as_slice().len() + node.len()) as i64;
+            self.db.insert_untracked(&key, &node, &mut batch)?;
+            rewritten += 1;
+        }
+        if delta != 0 {
+            if let Some((trie, flat)) = self.stored_disk_usage(identifier)? {
+                let usage = (trie.saturating_add_signed(delta), flat);
+                let key = disk_usage_key(identifier);
+                self.db
+                    .insert_untracked(&key, &usage.encode_bytevec(), &mut batch)?;
+            }
+        }
+        self.db.write_batch(batch)?;
+        // The nodes loaded in memory may be the corrupted ones.
+        self.trees.remove(identifier);
+        Ok(rewritten)
+    }
+
+    fn meta_updates(&self) -> Updates {
+        self.meta
+            .iter()
+            .map(|(key, value)| {
+                let key = TrieKey::Meta(key.clone());
                 match value {
-                    InsertOrRemove::Insert(value) => {
-                        self.db.insert(&key, &value, Some(&mut batch))?;
+                    Some(value) => (key, InsertOrRemove::Insert(value.clone())),
+                    None => (key, InsertOrRemove::Remove),
+                }
+            })
+            .collect()
+    }
+
+    fn write_updates(
+        &mut self,
+        updates: impl IntoIterator<Item = (TrieKey, InsertOrRemove<ByteVec>)>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (key, value) in updates {
+            match value {
+                InsertOrRemove::Insert(value) => {
+                    self.db.insert(&key, &value, Some(batch))?;
+                }
+                InsertOrRemove::Remove => {
+                    self.db.remove(&key, Some(batch))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the updates of the trie `identifier`. Returns by how many bytes its nodes and its
+    /// leaves grow, and records the size of their trie log for [`KeyValueDB::commit_to_batch`].
+    ///
+    /// With `batch_bytes`, `batch` is written early once it is full, the root node being written
+    /// last so that it doesn't reach the database before the nodes it references.
+    ///
+    /// With [`KeyValueDBConfig::max_inline_leaves`], the node updates of the small tries are
+    /// replaced by the update of their record, see [`inline_updates`].
+    fn write_tree_updates(
+        &mut self,
+        identifier: &[u8],
+        updates: impl IntoIterator<Item = (TrieKey, InsertOrRemove<ByteVec>)>,
+        batch: &mut DB::Batch,
+        mut batch_bytes: Option<&mut usize>,
+    ) -> Result<(i64, i64), BonsaiStorageError<DB::DatabaseError>> {
+        #[cfg(feature = "audit")]
+        self.audited.push(identifier.into());
+        let (mut trie_delta, mut flat_delta) = (0i64, 0i64);
+        let (mut log_entries, mut log_bytes) = (0, 0);
+        let root_path: ByteVec = Path::default().into();
+        let root_key = TrieKey::new(identifier, TrieKeyType::Trie, &root_path);
+        let inline_key = inline_trie_key(identifier);
+        let hasher = trie_hasher::<H>(&self.db.config, identifier);
+        let empty_root = hasher.empty_root(self.max_height);
+        let updates: Vec<_> = updates.into_iter().collect();
+        if !updates.is_empty() && self.stored_trie_height(identifier)?.is_none() {
+            let key = trie_height_key(identifier);
+            self.db.insert_in_trie(
+                identifier,
+                &key,
+                &self.max_height.encode_bytevec(),
+                Some(batch),
+            )?;
+        }
+        let updates = match self.db.config.max_inline_leaves {
+            Some(max_leaves) => inline_updates::<H, _, _>(
+                &self.db,
+                identifier,
+                self.max_height,
+                &hasher,
+                max_leaves,
+                updates,
+            )?,
+            None => updates,
+        };
+        let (mut root_change, mut inline_change) = (None, None);
+        let (root_update, updates): (Vec<_>, Vec<_>) = updates
+            .into_iter()
+            .partition(|(key, _)| *key == root_key || *key == inline_key);
+        let mut changes = Vec::with_capacity(updates.len() + root_update.len());
+        let mut inserts = Vec::new();
+        for (key, value) in updates {
+            match value {
+                InsertOrRemove::Insert(value) => inserts.push((key, value)),
+                InsertOrRemove::Remove => {
+                    let written = key.as_slice().len();
+                    let old_value = self.db.remove_in_trie(identifier, &key, Some(batch))?;
+                    changes.push((
+                        key,
+                        Change {
+                            old_value,
+                            new_value: None,
+                        },
+                    ));
+                    if let Some(batch_bytes) = batch_bytes.as_deref_mut() {
+                        self.db.flush_full_batch(batch, batch_bytes, written)?;
                     }
-                    InsertOrRemove::Remove => {
-                        self.db.remove(&key, Some(&mut batch))?;
+                }
+            }
+        }
+        // The insertions are written with one `insert_many` per batch, cut where the batch gets
+        // full so that it is flushed as often as when writing key by key.
+        let mut inserts = inserts.into_iter().peekable();
+        while inserts.peek().is_some() {
+            let room = match (batch_bytes.as_deref(), self.db.config.max_batch_bytes) {
+                (Some(batch_bytes), Some(max_batch_bytes)) => {
+                    max_batch_bytes.saturating_sub(*batch_bytes)
+                }
+                _ => usize::MAX,
+            };
+            let (mut chunk, mut written) = (Vec::new(), 0);
+            while let Some((key, value)) = inserts.next_if(|_| chunk.is_empty() || written < room) {
+                written += key.as_slice().len() + value.len();
+                chunk.push((key, value));
+            }
+            let old_values = self
+                .db
+                .insert_many_in_trie(identifier, &chunk, Some(batch))?;
+            changes.extend(
+                chunk
+                    .into_iter()
+                    .zip(old_values)
+                    .map(|((key, value), old_value)| {
+                        (
+                            key,
+                            Change {
+                                old_value,
+                                new_value: Some(value),
+                            },
+                        )
+                    }),
+            );
+            if let Some(batch_bytes) = batch_bytes.as_deref_mut() {
+                self.db.flush_full_batch(batch, batch_bytes, written)?;
+            }
+        }
+        // The root, or the record of an inline trie, is written last.
+        for (key, value) in root_update {
+            let written = match &value {
+                InsertOrRemove::Insert(value) => key.as_slice().len() + value.len(),
+                InsertOrRemove::Remove => key.as_slice().len(),
+            };
+            let change = match value {
+                InsertOrRemove::Insert(value) => {
+                    let old_value =
+                        self.db
+                            .insert_in_trie(identifier, &key, &value, Some(batch))?;
+                    Change {
+                        old_value,
+                        new_value: Some(value),
                     }
                 }
+                InsertOrRemove::Remove => Change {
+                    old_value: self.db.remove_in_trie(identifier, &key, Some(batch))?,
+                    new_value: None,
+                },
+            };
+            changes.push((key, change));
+            if let Some(batch_bytes) = batch_bytes.as_deref_mut() {
+                self.db.flush_full_batch(batch, batch_bytes, written)?;
+            }
+        }
+        for (key, change) in changes {
+            let size = |value: &Option<ByteVec>| {
+                value
+                    .as_ref()
+                    .map_or(0, |value| (key.as_slice().len() + value.len()) as i64)
+            };
+            let delta = size(&change.new_value) - size(&change.old_value);
+            match key {
+                TrieKey::Trie(_) => trie_delta += delta,
+                TrieKey::Flat(_) => flat_delta += delta,
+                TrieKey::Meta(_) => {}
+            }
+            let (entries, bytes) = trie_log_usage(identifier, &key, &change);
+            log_entries += entries;
+            log_bytes += bytes;
+            if key == root_key {
+                root_change = Some(change);
+            } else if key == inline_key {
+                inline_change = Some(change);
+            }
+        }
+        // A trie has either a root node or a record, both change when it stops being inline.
+        if root_change.is_some() || inline_change.is_some() {
+            let mut root_hashes = (None, None);
+            if let Some(change) = &root_change {
+                root_hashes.0 =
+                    stored_root_hash(identifier, &root_key, &change.old_value, self.max_height)?;
+                root_hashes.1 =
+                    stored_root_hash(identifier, &root_key, &change.new_value, self.max_height)?;
+            }
+            if let Some(change) = &inline_change {
+                root_hashes.0 = root_hashes
+                    .0
+                    .or(inline_root_hash(&inline_key, &change.old_value)?);
+                root_hashes.1 = root_hashes
+                    .1
+                    .or(inline_root_hash(&inline_key, &change.new_value)?);
+            }
+            self.db.changes_store.root_hashes.insert(
+                identifier.into(),
+                (
+                    root_hashes.0.unwrap_or(empty_root),
+                    root_hashes.1.unwrap_or(empty_root),
+                ),
+            );
+        }
+        if log_entries != 0 {
+            let log_usage = self
+                .db
+                .changes_store
+                .log_usage
+                .entry(identifier.into())
+                .or_default();
+            log_usage.0 += log_entries;
+            log_usage.1 += log_bytes;
+        }
+        Ok((trie_delta, flat_delta))
+    }
+
+    /// Root hash of the trie `identifier` at commit `id`, see
+    /// [`crate::BonsaiStorage::root_hash_at`].
+    pub(crate) fn root_hash_at(
+        &self,
+        identifier: &[u8],
+        id: CommitID,
+    ) -> Result<Option<Felt>, BonsaiStorageError<DB::DatabaseError>> {
+        let Some(since) = self.db.root_hashes_since()? else {
+            return Ok(None);
+        };
+        if id < since || !(self.db.get_latest_id() == Some(id) || self.db.has_trie_log(id)) {
+            return Ok(None);
+        }
+        let root_hashes = self.db.root_hashes(identifier, id)?;
+        if let Some((_, root_hash)) = root_hashes.iter().rev().find(|(root_id, _)| *root_id <= id) {
+            return Ok(Some(*root_hash));
+        }
+        // The trie did not change between `since` and its first recorded root hash, or did not
+        // change at all since then.
+        match root_hashes.first() {
+            Some((_, root_hash)) => Ok(Some(*root_hash)),
+            None => new_tree::<H>(&self.db.config, identifier, self.max_height)
+                .root_hash(&self.db)
+                .map(Some),
+        }
+    }
+
+    fn write_leaf_counts(
+        &mut self,
+        leaf_counts: Vec<(ByteVec, u64)>,
+        batch: &mut DB::Batch,
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        for (identifier, count) in leaf_counts {
+            let key = leaf_count_key(&identifier);
+            if count == 0 {
+                self.db.remove_in_trie(&identifier, &key, Some(batch))?;
+            } else {
+                self.db
+                    .insert_in_trie(&identifier, &key, &count.encode_bytevec(), Some(batch))?;
             }
         }
-        self.db.write_batch(batch)?;
         Ok(())
     }
 
@@ -228,16 +2113,56 @@ impl<H: StarkHash + Send + Sync, DB: BonsaiDatabase, CommitID: Id> MerkleTrees<H
     //     }
     // }
 
-    pub fn get_multi_proof(
+    pub(crate) fn prefetch(
         &mut self,
         identifier: &[u8],
         keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
-    ) -> Result<MultiProof, BonsaiStorageError<DB::DatabaseError>> {
+    ) -> Result<(), BonsaiStorageError<DB::DatabaseError>> {
+        self.check_not_sharded(identifier)?;
         let tree = self
             .trees
             .entry_ref(identifier)
-            .or_insert_with(|| MerkleTree::new(identifier.into(), self.max_height));
+            .or_insert_with(|| new_tree(&self.db.config, identifier, self.max_height));
 
-        tree.get_multi_proof(&self.db, keys)
+        tree.prefetch(&self.db, keys)
+    }
+
+    pub(crate) fn cursor(&self, identifier: &[u8]) -> TrieCursor<'_, H, DB, CommitID> {
+        match self.trees.get(identifier) {
+            Some(tree) => tree.cursor(&self.db),
+            None => TrieCursor::new(
+                Cow::Owned(new_tree(&self.db.config, identifier, self.max_height)),
+                &self.db,
+            ),
+        }
+    }
+
+    /// The proof covers the uncommitted changes, whose hashes are computed without storing them
+    /// in the tree.
+    pub fn get_multi_proof(
+        &self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<MultiProof, BonsaiStorageError<DB::DatabaseError>> {
+        match self.trees.get(identifier) {
+            Some(tree) => tree.get_multi_proof(&self.db, keys),
+            None => new_tree::<H>(&self.db.config, identifier, self.max_height)
+                .get_multi_proof(&self.db, keys),
+        }
+    }
+
+    /// Root hash and proof of `keys` of the trie `identifier` at the last commit, ignoring the
+    /// uncommitted changes.
+    #[cfg(feature = "std")]
+    pub(crate) fn committed_multi_proof(
+        &self,
+        identifier: &[u8],
+        keys: impl IntoIterator<Item = impl AsRef<BitSlice>>,
+    ) -> Result<(Felt, MultiProof), BonsaiStorageError<DB::DatabaseError>> {
+        let tree = new_tree::<H>(&self.db.config, identifier, self.max_height);
+        let proof = tree.get_multi_proof(&self.db, keys)?;
+        let root =
+            new_tree::<H>(&self.db.config, identifier, self.max_height).root_hash(&self.db)?;
+        Ok((root, proof))
     }
 }
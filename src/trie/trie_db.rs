@@ -6,11 +6,83 @@ use crate::{bonsai_database::DatabaseKey, ByteVec};
 pub(crate) enum TrieKey {
     Trie(ByteVec),
     Flat(ByteVec),
+    Meta(ByteVec),
 }
 
 pub(crate) enum TrieKeyType {
     Trie = 0,
     Flat = 1,
+    Meta = 2,
+}
+
+/// Kind of the entries of the [`TrieKey::Meta`] column, which prefixes their key.
+pub(crate) enum MetaKeyType {
+    /// Set with [`crate::BonsaiStorage::put_meta`].
+    User = 0,
+    /// Commit ID of a tag, see [`crate::BonsaiStorage::commit_with_tag`].
+    Tag = 1,
+    /// Tag of a commit ID, to remove it when the trie log of the commit is pruned.
+    CommitTag = 2,
+    /// Id of the latest commit, so that it is known when the database is reopened.
+    LatestId = 3,
+    /// Bytes used by the nodes and leaves of a trie, by identifier, see
+    /// [`crate::BonsaiStorage::disk_usage`].
+    DiskUsage = 4,
+    /// Bytes of the trie log of a commit used by a trie, by commit ID then identifier. Removed
+    /// along with the trie log.
+    LogUsage = 5,
+    /// Compression dictionary of a [`crate::databases::CompressedDb`] column, by dictionary id.
+    /// Written by the database itself.
+    #[cfg(feature = "zstd")]
+    CompressionDictionary = 6,
+    /// Version of the layout of the database, see [`crate::migrations`].
+    SchemaVersion = 7,
+    /// Root hash of a trie after a commit, by identifier then commit ID, see
+    /// [`crate::BonsaiStorage::root_hash_at`]. The entry without identifier and commit ID is the
+    /// first commit with known root hashes.
+    RootHash = 8,
+    /// Id of the latest commit made by [`crate::BonsaiStorage::bulk_load`].
+    BulkLoad = 9,
+    /// Trie node stored once for all the tries which have it, by height then hash, with its
+    /// reference count. Written by a [`crate::databases::DedupDb`] itself.
+    SharedNode = 10,
+    /// Trie or flat key removed from a [`crate::databases::RemoteDb`], by key type then key, so
+    /// that it is not fetched again. Written by the database itself.
+    #[cfg(feature = "std")]
+    RemoteRemoved = 11,
+    /// Trie node which could not be decoded, by its key in the trie column, see
+    /// [`crate::BonsaiStorageConfig::quarantine_corrupted_nodes`].
+    Quarantine = 12,
+    /// Parameters the database must be reopened with, see [`crate::StoredConfig`].
+    Config = 13,
+    /// Height of a trie, by identifier, stored by its first commit, see
+    /// [`crate::BonsaiStorageError::TrieHeightMismatch`].
+    TrieHeight = 14,
+    /// Latest commit whose trie log was kept by a revert, see
+    /// [`crate::BonsaiStorageConfig::keep_reverted_trie_logs`].
+    RevertedFrom = 15,
+    /// Oldest commit whose trie log was kept past `max_saved_trie_logs` by a pinned commit, see
+    /// [`crate::BonsaiStorage::pin_commit`].
+    DeferredPruning = 16,
+    /// Metadata of a commit, by commit ID, see [`crate::BonsaiStorage::commit_with_meta`].
+    /// Removed along with the trie log.
+    CommitMeta = 17,
+}
+
+impl MetaKeyType {
+    /// Whether the metadata at `key` is maintained by the commits themselves, so that it must not
+    /// be copied over when merging the changes of another storage.
+    pub(crate) fn is_maintained_by_commit(key: &[u8]) -> bool {
+        [
+            Self::LatestId,
+            Self::DiskUsage,
+            Self::LogUsage,
+            Self::RootHash,
+            Self::TrieHeight,
+        ]
+        .into_iter()
+        .any(|key_type| key.first() == Some(&(key_type as u8)))
+    }
 }
 
 impl From<TrieKey> for u8 {
@@ -18,6 +90,7 @@ impl From<TrieKey> for u8 {
         match value {
             TrieKey::Trie(_) => TrieKeyType::Trie as u8,
             TrieKey::Flat(_) => TrieKeyType::Flat as u8,
+            TrieKey::Meta(_) => TrieKeyType::Meta as u8,
         }
     }
 }
@@ -27,32 +100,33 @@ impl From<&TrieKey> for u8 {
         match value {
             TrieKey::Trie(_) => TrieKeyType::Trie as u8,
             TrieKey::Flat(_) => TrieKeyType::Flat as u8,
+            TrieKey::Meta(_) => TrieKeyType::Meta as u8,
         }
     }
 }
 
 impl TrieKey {
     pub fn new(identifier: &[u8], key_type: TrieKeyType, key: &[u8]) -> Self {
-        let mut final_key = ByteVec::from(identifier);
+        // Allocated once: most keys don't fit in the inline capacity of a `ByteVec`.
+        let mut final_key = ByteVec::with_capacity(identifier.len() + key.len());
+        final_key.extend_from_slice(identifier);
         final_key.extend_from_slice(key);
         match key_type {
             TrieKeyType::Trie => TrieKey::Trie(final_key),
             TrieKeyType::Flat => TrieKey::Flat(final_key),
+            TrieKeyType::Meta => TrieKey::Meta(final_key),
         }
     }
 
-    pub fn from_variant_and_bytes(variant: u8, bytes: ByteVec) -> Self {
-        match variant {
-            x if x == TrieKeyType::Trie as u8 => TrieKey::Trie(bytes),
-            x if x == TrieKeyType::Flat as u8 => TrieKey::Flat(bytes),
-            _ => panic!("Invalid trie key type"),
-        }
+    pub fn new_meta(key_type: MetaKeyType, key: &[u8]) -> Self {
+        TrieKey::new(&[key_type as u8], TrieKeyType::Meta, key)
     }
 
     pub fn as_slice(&self) -> &[u8] {
         match self {
             TrieKey::Trie(slice) => slice,
             TrieKey::Flat(slice) => slice,
+            TrieKey::Meta(slice) => slice,
         }
     }
 }
@@ -63,6 +137,7 @@ impl<'a> From<&'a TrieKey> for DatabaseKey<'a> {
         match key {
             TrieKey::Trie(_) => DatabaseKey::Trie(key_slice),
             TrieKey::Flat(_) => DatabaseKey::Flat(key_slice),
+            TrieKey::Meta(_) => DatabaseKey::Meta(key_slice),
         }
     }
 }
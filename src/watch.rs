@@ -0,0 +1,149 @@
+//! Leaves watched through a storage, see [`crate::BonsaiStorage::watch`].
+
+use crate::{
+    changes::ChangeBatch,
+    id::Id,
+    trie::{path::PathKey, tree::decode_leaf, trie_db::TrieKeyType, TrieKey},
+    Arc, BTreeMap, BitSlice, BitVec, ByteVec, LeafChange, Vec,
+};
+use starknet_types_core::felt::Felt;
+use std::sync::{Mutex, PoisonError};
+
+/// Called with the id of a commit and the change it makes to a watched leaf.
+pub(crate) type WatchCallback<ID> = dyn Fn(ID, &LeafChange) + Send + Sync;
+
+struct Watch<ID> {
+    identifier: ByteVec,
+    key: BitVec,
+    /// Key of the leaf in the flat storage, as in the trie logs.
+    flat_key: TrieKey,
+    callback: Arc<WatchCallback<ID>>,
+}
+
+struct Registry<ID> {
+    next_id: u64,
+    watches: BTreeMap<u64, Watch<ID>>,
+}
+
+/// Leaves watched through a storage, shared with their handles, which stop watching them when
+/// dropped.
+pub(crate) struct Watches<ID> {
+    registry: Arc<Mutex<Registry<ID>>>,
+    /// Changes to watched leaves made by the commits being written, given to the callbacks once
+    /// they are.
+    pending: Vec<(Arc<WatchCallback<ID>>, ID, LeafChange)>,
+}
+
+impl<ID> Default for Watches<ID> {
+    fn default() -> Self {
+        Self {
+            registry: Arc::new(Mutex::new(Registry {
+                next_id: 0,
+                watches: BTreeMap::new(),
+            })),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<ID> core::fmt::Debug for Watches<ID> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Watches")
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl<ID: Id> Watches<ID> {
+    /// Call `callback` with the changes to the leaf `key` of the trie `identifier`.
+    pub(crate) fn watch(
+        &self,
+        identifier: &[u8],
+        key: &BitSlice,
+        callback: Arc<WatchCallback<ID>>,
+    ) -> WatchHandle<ID> {
+        let mut registry = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.watches.insert(
+            id,
+            Watch {
+                identifier: identifier.into(),
+                key: key.to_bitvec(),
+                flat_key: TrieKey::new(identifier, TrieKeyType::Flat, &PathKey::leaf(key)),
+                callback,
+            },
+        );
+        WatchHandle {
+            id,
+            registry: self.registry.clone(),
+        }
+    }
+
+    /// Record the changes to watched leaves made by commit `id`, whose changes are `changes`, to
+    /// give them to the callbacks with [`Watches::notify`] once the commit is written.
+    pub(crate) fn collect(&mut self, id: ID, changes: &ChangeBatch) {
+        let registry = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        for watch in registry.watches.values() {
+            let Some(change) = changes.0.get(&watch.flat_key) else {
+                continue;
+            };
+            let value = |value: &Option<ByteVec>| -> Option<Felt> {
+                value
+                    .as_deref()
+                    .and_then(|value| decode_leaf(value).ok())
+                    .map(|(value, _)| value)
+            };
+            let (old_value, new_value) = (value(&change.old_value), value(&change.new_value));
+            if old_value == new_value {
+                continue;
+            }
+            let change = LeafChange {
+                identifier: watch.identifier.clone(),
+                key: watch.key.clone(),
+                old_value,
+                new_value,
+            };
+            self.pending.push((watch.callback.clone(), id, change));
+        }
+    }
+
+    /// Give the changes recorded since the last call to their callbacks, in commit order.
+    pub(crate) fn notify(&mut self) {
+        for (callback, id, change) in core::mem::take(&mut self.pending) {
+            callback(id, &change);
+        }
+    }
+
+    /// Drop the changes recorded by commits which were not written.
+    pub(crate) fn discard(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Keeps a leaf watched while it is alive, created with [`crate::BonsaiStorage::watch`].
+#[must_use = "the leaf is not watched anymore once the handle is dropped"]
+pub struct WatchHandle<ID> {
+    id: u64,
+    registry: Arc<Mutex<Registry<ID>>>,
+}
+
+impl<ID> core::fmt::Debug for WatchHandle<ID> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WatchHandle").field("id", &self.id).finish()
+    }
+}
+
+impl<ID> Drop for WatchHandle<ID> {
+    fn drop(&mut self) {
+        let mut registry = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        registry.watches.remove(&self.id);
+    }
+}
+
+#[cfg(feature = "bench")]
+impl<ID> Clone for Watches<ID> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,63 @@
+#[cfg(feature = "std")]
+use crate::HashSet;
+use crate::{BitSlice, BitVec, ByteVec, HashMap, MultiProof, Vec};
+use starknet_types_core::felt::Felt;
+
+/// The trie nodes needed to replay the reads and writes recorded since
+/// [`crate::BonsaiStorage::record_witness`], for instance to build the witness of a proof of a
+/// block execution.
+#[derive(Debug, Clone, Default)]
+pub struct Witness {
+    pub tries: HashMap<ByteVec, TrieWitness>,
+}
+
+/// The part of a [`Witness`] for one trie.
+#[derive(Debug, Clone)]
+pub struct TrieWitness {
+    /// Root hash of the trie at the last commit, which the nodes of `proof` lead to.
+    pub root: Felt,
+    /// The keys read or written, in increasing order.
+    pub keys: Vec<BitVec>,
+    /// The nodes on the paths to `keys`, each of them once.
+    pub proof: MultiProof,
+}
+
+/// Keys accessed through a storage while a witness is recorded. Reads only take `&self`, so the
+/// keys are behind a lock, and nothing is recorded without `std`.
+#[derive(Debug, Default)]
+pub(crate) struct WitnessRecorder {
+    #[cfg(feature = "std")]
+    keys: std::sync::Mutex<Option<HashMap<ByteVec, HashSet<BitVec>>>>,
+}
+
+impl WitnessRecorder {
+    #[cfg(feature = "std")]
+    pub(crate) fn start(&mut self) {
+        *self.keys.get_mut().unwrap() = Some(HashMap::new());
+    }
+
+    /// The keys recorded since `start`, by trie, which stops recording.
+    #[cfg(feature = "std")]
+    pub(crate) fn finish(&mut self) -> Option<HashMap<ByteVec, HashSet<BitVec>>> {
+        self.keys.get_mut().unwrap().take()
+    }
+
+    pub(crate) fn record(&self, identifier: &[u8], key: &BitSlice) {
+        #[cfg(feature = "std")]
+        if let Some(keys) = self.keys.lock().unwrap().as_mut() {
+            keys.entry_ref(identifier).or_default().insert(key.into());
+        }
+        #[cfg(not(feature = "std"))]
+        let _ = (identifier, key);
+    }
+}
+
+#[cfg(feature = "bench")]
+impl Clone for WitnessRecorder {
+    fn clone(&self) -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            keys: std::sync::Mutex::new(self.keys.lock().unwrap().clone()),
+        }
+    }
+}